@@ -0,0 +1,55 @@
+//! Cooperative cancellation of long-running work
+//!
+//! Gap-limit scans and large address ranges can take seconds on slow
+//! phones. The host's UI thread trips a `CancelToken` while a worker
+//! thread runs the scan; the scan checks the token between indices and
+//! returns `CoreError::Cancelled`, dropping everything it allocated.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Flag a worker polls to learn it should stop
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask work checking this token to stop; there is no way to reset it
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// `Err(CoreError::Cancelled)` once the token is tripped
+    pub fn check(&self) -> CoreResult<()> {
+        if self.is_cancelled() {
+            return Err(CoreError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_is_seen_by_other_threads() {
+        let token = CancelToken::new();
+        assert!(token.check().is_ok());
+        std::thread::scope(|scope| {
+            scope.spawn(|| token.cancel());
+        });
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(CoreError::Cancelled)));
+    }
+}
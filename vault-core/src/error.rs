@@ -12,6 +12,9 @@ pub enum CoreError {
     #[error("Invalid network: expected {expected}, got {actual}")]
     NetworkMismatch { expected: String, actual: String },
 
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(MnemonicError),
+
     #[error("PSBT building failed: {0}")]
     PsbtError(String),
 
@@ -21,9 +24,19 @@ pub enum CoreError {
     #[error("Invalid metadata encoding: {0}")]
     MetadataError(String),
 
+    #[error("Script construction failed: {0}")]
+    ScriptError(String),
+
     #[error("Insufficient funds: need {needed} sats, have {available} sats")]
     InsufficientFunds { needed: u64, available: u64 },
 
+    /// `input_index` is 0 when no single input is at fault
+    #[error("Signing input {input_index} failed: {reason}")]
+    SigningError { input_index: usize, reason: String },
+
+    #[error("Timelock not expired: need {required} blocks, have {current}")]
+    TimelockNotExpired { required: u32, current: u32 },
+
     #[error("Policy violation: {0}")]
     PolicyViolation(String),
 
@@ -32,6 +45,20 @@ pub enum CoreError {
 
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+
+    /// `offset` is the index of the first byte that isn't valid UTF-8
+    #[error("Invalid UTF-8 at byte {offset}")]
+    InvalidUtf8 { offset: usize },
+
+    #[error("No network given and vault_init() has not been called")]
+    NotInitialized,
+
+    /// The caller tripped the operation's `cancel::CancelToken`
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    #[error("Internal error: {0}")]
+    Internal(String),
 }
 
 impl CoreError {
@@ -41,15 +68,77 @@ impl CoreError {
             CoreError::InvalidXpub(_) => 1001,
             CoreError::InvalidAddress(_) => 1002,
             CoreError::NetworkMismatch { .. } => 1003,
+            CoreError::InvalidMnemonic(_) => 1004,
             CoreError::PsbtError(_) => 2001,
             CoreError::InsufficientFunds { .. } => 2002,
             CoreError::PolicyViolation(_) => 2003,
+            CoreError::SigningError { .. } => 2004,
+            CoreError::TimelockNotExpired { .. } => 2005,
             CoreError::DerivationError(_) => 3001,
             CoreError::MetadataError(_) => 3002,
+            CoreError::ScriptError(_) => 3003,
             CoreError::SerializationError(_) => 4001,
             CoreError::InvalidInput(_) => 4002,
+            CoreError::NotInitialized => 4003,
+            CoreError::InvalidUtf8 { .. } => 4004,
+            CoreError::Cancelled => 4005,
+            CoreError::Internal(_) => 5000,
         }
     }
+
+    /// Fields of structured variants, as a JSON object for FFI responses
+    ///
+    /// Empty for variants that only carry a message.
+    pub fn details(&self) -> serde_json::Value {
+        match self {
+            CoreError::NetworkMismatch { expected, actual } => serde_json::json!({
+                "expected": expected,
+                "actual": actual,
+            }),
+            CoreError::InvalidMnemonic(MnemonicError::UnknownWord { word_index }) => serde_json::json!({
+                "reason": "unknown_word",
+                "word_index": word_index,
+            }),
+            CoreError::InvalidMnemonic(MnemonicError::BadChecksum) => serde_json::json!({
+                "reason": "bad_checksum",
+            }),
+            CoreError::InvalidMnemonic(MnemonicError::BadWordCount { word_count }) => serde_json::json!({
+                "reason": "bad_word_count",
+                "word_count": word_count,
+            }),
+            CoreError::InsufficientFunds { needed, available } => serde_json::json!({
+                "needed": needed,
+                "available": available,
+            }),
+            CoreError::SigningError { input_index, reason } => serde_json::json!({
+                "input_index": input_index,
+                "reason": reason,
+            }),
+            CoreError::TimelockNotExpired { required, current } => serde_json::json!({
+                "required": required,
+                "current": current,
+            }),
+            CoreError::InvalidUtf8 { offset } => serde_json::json!({
+                "offset": offset,
+            }),
+            _ => serde_json::json!({}),
+        }
+    }
+}
+
+/// Why a BIP39 mnemonic was rejected
+///
+/// Messages name the position of a bad word, never the word itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MnemonicError {
+    #[error("word {} is not in the English wordlist", word_index + 1)]
+    UnknownWord { word_index: usize },
+
+    #[error("checksum does not match; a word may be misspelled as another valid word or out of order")]
+    BadChecksum,
+
+    #[error("{word_count} words; expected 12, 15, 18, 21 or 24")]
+    BadWordCount { word_count: usize },
 }
 
 /// Result type for core operations
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::cancel::CancelToken;
+use crate::error::CoreError;
+use crate::taproot::VaultTree;
+use crate::vault::ur::UrDecoder;
+use crate::vault::{Vault, VaultConfig};
+
+use super::{set_last_error, FfiReturn};
+
+/// Tag of a live handle ("VAULTHDL")
+const HANDLE_MAGIC: u64 = 0x5641_554c_5448_444c;
+/// Tag of a live UR decoder handle ("URDECHDL")
+const UR_DECODER_MAGIC: u64 = 0x5552_4445_4348_444c;
+/// Tag of a live cancel token handle ("CANCLTOK")
+const CANCEL_TOKEN_MAGIC: u64 = 0x4341_4e43_4c54_4f4b;
+/// Tag written into a handle as it is freed
+const FREED_MAGIC: u64 = 0xdead_dead_dead_dead;
+
+/// Opaque handle to a parsed vault, owned by the FFI caller
+///
+/// Holds the vault and a cache of the trees derived through it, so
+/// repeated calls skip key parsing and derivation. Safe to share
+/// between threads.
+pub struct VaultHandle {
+    magic: AtomicU64,
+    vault: Vault,
+    trees: Mutex<HashMap<u32, VaultTree>>,
+}
+
+impl VaultHandle {
+    pub fn new(config: &VaultConfig) -> Result<Self, CoreError> {
+        Ok(VaultHandle {
+            magic: AtomicU64::new(HANDLE_MAGIC),
+            vault: Vault::from_config(config)?,
+            trees: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Move the handle to the heap and hand ownership to the caller
+    pub fn into_raw(self) -> *mut VaultHandle {
+        Box::into_raw(Box::new(self))
+    }
+
+    /// Borrow a caller-held handle
+    ///
+    /// Null pointers and pointers whose magic number doesn't match (most
+    /// double frees and uses after free) are rejected.
+    pub fn from_ptr<'a>(ptr: *const VaultHandle) -> Result<&'a VaultHandle, CoreError> {
+        if ptr.is_null() {
+            return Err(CoreError::InvalidInput("null vault handle".to_string()));
+        }
+        let handle = unsafe { &*ptr };
+        if handle.magic.load(Ordering::Acquire) != HANDLE_MAGIC {
+            return Err(CoreError::InvalidInput(
+                "invalid or freed vault handle".to_string(),
+            ));
+        }
+        Ok(handle)
+    }
+
+    /// Release a handle created by `into_raw()`
+    pub fn free(ptr: *mut VaultHandle) -> Result<(), CoreError> {
+        let handle = Self::from_ptr(ptr)?;
+        if handle
+            .magic
+            .compare_exchange(HANDLE_MAGIC, FREED_MAGIC, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(CoreError::InvalidInput("vault handle already freed".to_string()));
+        }
+        drop(unsafe { Box::from_raw(ptr) });
+        Ok(())
+    }
+
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
+    /// Script tree at `vault_index`, derived once and cached
+    pub fn tree(&self, vault_index: u32) -> Result<VaultTree, CoreError> {
+        let mut trees = self
+            .trees
+            .lock()
+            .map_err(|_| CoreError::Internal("vault handle cache poisoned".to_string()))?;
+        if let Some(tree) = trees.get(&vault_index) {
+            return Ok(tree.clone());
+        }
+        let tree = self.vault.tree_at(vault_index)?;
+        trees.insert(vault_index, tree.clone());
+        Ok(tree)
+    }
+}
+
+impl FfiReturn for *mut VaultHandle {
+    fn from_error(error: CoreError) -> Self {
+        set_last_error(error);
+        std::ptr::null_mut()
+    }
+}
+
+/// Opaque handle to a UR decoder collecting scanned QR parts
+///
+/// The decoder sits behind a mutex, so a scanner thread can feed parts
+/// while the UI thread polls progress.
+pub struct UrDecoderHandle {
+    magic: AtomicU64,
+    decoder: Mutex<UrDecoder>,
+}
+
+impl UrDecoderHandle {
+    pub fn new() -> Self {
+        UrDecoderHandle {
+            magic: AtomicU64::new(UR_DECODER_MAGIC),
+            decoder: Mutex::new(UrDecoder::new()),
+        }
+    }
+
+    /// Move the handle to the heap and hand ownership to the caller
+    pub fn into_raw(self) -> *mut UrDecoderHandle {
+        Box::into_raw(Box::new(self))
+    }
+
+    /// Borrow a caller-held handle, rejecting null and freed pointers as
+    /// `VaultHandle::from_ptr()` does
+    pub fn from_ptr<'a>(ptr: *const UrDecoderHandle) -> Result<&'a UrDecoderHandle, CoreError> {
+        if ptr.is_null() {
+            return Err(CoreError::InvalidInput("null UR decoder handle".to_string()));
+        }
+        let handle = unsafe { &*ptr };
+        if handle.magic.load(Ordering::Acquire) != UR_DECODER_MAGIC {
+            return Err(CoreError::InvalidInput(
+                "invalid or freed UR decoder handle".to_string(),
+            ));
+        }
+        Ok(handle)
+    }
+
+    /// Release a handle created by `into_raw()`
+    pub fn free(ptr: *mut UrDecoderHandle) -> Result<(), CoreError> {
+        let handle = Self::from_ptr(ptr)?;
+        if handle
+            .magic
+            .compare_exchange(UR_DECODER_MAGIC, FREED_MAGIC, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(CoreError::InvalidInput("UR decoder handle already freed".to_string()));
+        }
+        drop(unsafe { Box::from_raw(ptr) });
+        Ok(())
+    }
+
+    /// Run `f` with the decoder locked
+    pub fn with_decoder<T>(
+        &self,
+        f: impl FnOnce(&mut UrDecoder) -> Result<T, CoreError>,
+    ) -> Result<T, CoreError> {
+        let mut decoder = self
+            .decoder
+            .lock()
+            .map_err(|_| CoreError::Internal("UR decoder handle poisoned".to_string()))?;
+        f(&mut decoder)
+    }
+}
+
+impl Default for UrDecoderHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FfiReturn for *mut UrDecoderHandle {
+    fn from_error(error: CoreError) -> Self {
+        set_last_error(error);
+        std::ptr::null_mut()
+    }
+}
+
+/// Opaque handle to a cancel token, tripped from one thread to stop a
+/// scan running on another
+pub struct CancelTokenHandle {
+    magic: AtomicU64,
+    token: CancelToken,
+}
+
+impl CancelTokenHandle {
+    pub fn new() -> Self {
+        CancelTokenHandle {
+            magic: AtomicU64::new(CANCEL_TOKEN_MAGIC),
+            token: CancelToken::new(),
+        }
+    }
+
+    /// Move the handle to the heap and hand ownership to the caller
+    pub fn into_raw(self) -> *mut CancelTokenHandle {
+        Box::into_raw(Box::new(self))
+    }
+
+    /// Borrow a caller-held handle, rejecting null and freed pointers as
+    /// `VaultHandle::from_ptr()` does
+    pub fn from_ptr<'a>(ptr: *const CancelTokenHandle) -> Result<&'a CancelTokenHandle, CoreError> {
+        if ptr.is_null() {
+            return Err(CoreError::InvalidInput("null cancel token".to_string()));
+        }
+        let handle = unsafe { &*ptr };
+        if handle.magic.load(Ordering::Acquire) != CANCEL_TOKEN_MAGIC {
+            return Err(CoreError::InvalidInput(
+                "invalid or freed cancel token".to_string(),
+            ));
+        }
+        Ok(handle)
+    }
+
+    /// Release a handle created by `into_raw()`
+    pub fn free(ptr: *mut CancelTokenHandle) -> Result<(), CoreError> {
+        let handle = Self::from_ptr(ptr)?;
+        if handle
+            .magic
+            .compare_exchange(CANCEL_TOKEN_MAGIC, FREED_MAGIC, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(CoreError::InvalidInput("cancel token already freed".to_string()));
+        }
+        drop(unsafe { Box::from_raw(ptr) });
+        Ok(())
+    }
+
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl Default for CancelTokenHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FfiReturn for *mut CancelTokenHandle {
+    fn from_error(error: CoreError) -> Self {
+        set_last_error(error);
+        std::ptr::null_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::regtest_config;
+
+    #[test]
+    fn test_tree_is_cached() {
+        let handle = VaultHandle::new(&regtest_config()).unwrap();
+        let tree = handle.tree(7).unwrap();
+        assert_eq!(handle.trees.lock().unwrap().len(), 1);
+        assert_eq!(handle.tree(7).unwrap().script_pubkey(), tree.script_pubkey());
+        assert_eq!(handle.trees.lock().unwrap().len(), 1);
+        assert_eq!(tree.script_pubkey(), handle.vault().tree_at(7).unwrap().script_pubkey());
+    }
+
+    #[test]
+    fn test_freed_magic_rejected() {
+        let ptr = VaultHandle::new(&regtest_config()).unwrap().into_raw();
+        assert!(VaultHandle::from_ptr(ptr).is_ok());
+
+        // Simulate a freed handle whose memory hasn't been reused yet
+        unsafe { &*ptr }.magic.store(FREED_MAGIC, Ordering::Release);
+        assert!(VaultHandle::from_ptr(ptr).is_err());
+        assert!(VaultHandle::free(ptr).is_err());
+
+        unsafe { &*ptr }.magic.store(HANDLE_MAGIC, Ordering::Release);
+        VaultHandle::free(ptr).unwrap();
+        assert!(VaultHandle::free(std::ptr::null_mut()).is_err());
+    }
+}
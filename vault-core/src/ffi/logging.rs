@@ -0,0 +1,104 @@
+//! Forwarding `log` records to a host callback
+//!
+//! The host registers a C function with `vault_set_log_callback()`; every
+//! record at or above its minimum level is formatted and passed to it,
+//! e.g. for Android's logcat. The callback may be invoked from any thread
+//! the library runs on, including several at once.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::error::CoreError;
+
+/// Host function receiving a level (see `level_code()`) and a message
+///
+/// The message is only valid until the callback returns.
+pub type LogCallback = extern "C" fn(level: i32, msg: *const c_char);
+
+/// The registered callback, null when none is
+static CALLBACK: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Least severe level forwarded, as a `level_code()`
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Whether `LOGGER` is the process's logger, decided on first registration
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+static LOGGER: CallbackLogger = CallbackLogger;
+
+struct CallbackLogger;
+
+impl Log for CallbackLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        level_code(metadata.level()) as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let ptr = CALLBACK.load(Ordering::Acquire);
+        if ptr.is_null() {
+            return;
+        }
+        // Only ever stored from a `LogCallback` in `set_log_callback()`
+        let callback: LogCallback = unsafe { std::mem::transmute::<*mut (), LogCallback>(ptr) };
+
+        let message = format!("{}: {}", record.target(), record.args()).replace('\0', "\\0");
+        let message = CString::new(message).expect("NUL bytes were escaped");
+        callback(level_code(record.level()), message.as_ptr());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Level as passed to a `LogCallback`: 0=trace, 1=debug, 2=info, 3=warn, 4=error
+pub fn level_code(level: Level) -> i32 {
+    match level {
+        Level::Trace => 0,
+        Level::Debug => 1,
+        Level::Info => 2,
+        Level::Warn => 3,
+        Level::Error => 4,
+    }
+}
+
+fn level_from_code(code: i32) -> Result<Level, CoreError> {
+    match code {
+        0 => Ok(Level::Trace),
+        1 => Ok(Level::Debug),
+        2 => Ok(Level::Info),
+        3 => Ok(Level::Warn),
+        4 => Ok(Level::Error),
+        _ => Err(CoreError::InvalidInput(format!("Invalid log level: {}", code))),
+    }
+}
+
+/// Forward records at `min_level` (see `level_code()`) and above to `callback`
+///
+/// Replaces any earlier callback. Fails with `Internal` if the process
+/// already has a logger other than this library's.
+pub fn set_log_callback(callback: LogCallback, min_level: i32) -> Result<(), CoreError> {
+    let level = level_from_code(min_level)?;
+    if !*INSTALLED.get_or_init(|| log::set_logger(&LOGGER).is_ok()) {
+        return Err(CoreError::Internal("Another logger is already installed".to_string()));
+    }
+
+    MIN_LEVEL.store(min_level as u8, Ordering::Relaxed);
+    CALLBACK.store(callback as *mut (), Ordering::Release);
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
+
+/// Stop forwarding records
+///
+/// A record already being logged on another thread may still reach the
+/// old callback.
+pub fn clear_log_callback() {
+    log::set_max_level(LevelFilter::Off);
+    CALLBACK.store(std::ptr::null_mut(), Ordering::Release);
+}
@@ -1,13 +1,267 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, OnceLock};
+
+use serde::de::DeserializeOwned;
+
 use crate::error::CoreError;
+use crate::keys::musig::{PublicNonce, SecretNonce};
+use crate::vault::Network;
+
+mod handle;
+mod logging;
+
+pub use handle::{CancelTokenHandle, UrDecoderHandle, VaultHandle};
+pub use logging::{clear_log_callback, level_code, set_log_callback, LogCallback};
+
+/// Define a C export whose body runs inside `guard()`
+///
+/// Expands to a `#[no_mangle] pub extern "C" fn` with the same
+/// signature, so a panic in the body becomes an error return instead of
+/// unwinding into the caller. Early `return`s in the body work as usual.
+#[macro_export]
+macro_rules! ffi_export {
+    ($(#[$meta:meta])* fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block) => {
+        $(#[$meta])*
+        #[no_mangle]
+        pub extern "C" fn $name($($arg: $ty),*) -> $ret {
+            $crate::ffi::guard(stringify!($name), move || -> $ret { $body })
+        }
+    };
+    ($(#[$meta:meta])* fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $body:block) => {
+        $crate::ffi_export! {
+            $(#[$meta])*
+            fn $name($($arg: $ty),*) -> () $body
+        }
+    };
+}
+
+thread_local! {
+    /// Most recent error from an integer-returning export on this thread
+    static LAST_ERROR: RefCell<Option<CoreError>> = const { RefCell::new(None) };
+}
+
+/// Record `error` as this thread's last error
+pub fn set_last_error(error: CoreError) {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = Some(error));
+}
+
+/// Clear this thread's last error
+pub fn clear_last_error() {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = None);
+}
+
+/// Run `f` against this thread's last error, if any
+pub fn with_last_error<T>(f: impl FnOnce(Option<&CoreError>) -> T) -> T {
+    LAST_ERROR.with(|slot| f(slot.borrow().as_ref()))
+}
+
+/// Network selected by `vault_init()`, shared by every thread
+static NETWORK: NetworkContext = NetworkContext::new();
+
+/// A network that can be selected once per process
+struct NetworkContext(OnceLock<Network>);
+
+impl NetworkContext {
+    const fn new() -> Self {
+        NetworkContext(OnceLock::new())
+    }
+
+    /// Select `network`
+    ///
+    /// Selecting the current network again is a no-op; any other network
+    /// is rejected with `NetworkMismatch`. When threads race, the first
+    /// to select wins.
+    fn init(&self, network: Network) -> Result<(), CoreError> {
+        let selected = *self.0.get_or_init(|| network);
+        if selected != network {
+            return Err(CoreError::NetworkMismatch {
+                expected: selected.name().to_string(),
+                actual: network.name().to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Option<Network> {
+        self.0.get().copied()
+    }
+
+    /// `network`, or the selected network if `None`
+    fn resolve(&self, network: Option<Network>) -> Result<Network, CoreError> {
+        network.or_else(|| self.get()).ok_or(CoreError::NotInitialized)
+    }
+}
+
+/// Select the process-wide network (see `vault_init()`)
+pub fn init_network(network: Network) -> Result<(), CoreError> {
+    NETWORK.init(network)
+}
+
+/// The network selected by `vault_init()`, if any
+pub fn network() -> Option<Network> {
+    NETWORK.get()
+}
+
+/// Network for an export's `network: i32` argument
+///
+/// `-1` stands for the network selected by `vault_init()`.
+pub fn network_arg(network: i32) -> Result<Network, CoreError> {
+    let explicit = match network {
+        -1 => None,
+        n => Some(Network::try_from(n)?),
+    };
+    NETWORK.resolve(explicit)
+}
+
+/// MuSig2 secret nonces from `vault_musig_nonce()`, waiting for
+/// `vault_musig_partial_sign()`
+///
+/// Hosts only ever see the public nonce, so they can't hand the same
+/// secret nonce to two signing calls.
+static MUSIG_NONCES: Mutex<Vec<SecretNonce>> = Mutex::new(Vec::new());
+
+/// Secret nonces kept before the oldest is dropped, so sessions that
+/// never reach the second round don't pile up
+const MAX_PENDING_MUSIG_NONCES: usize = 256;
+
+/// Keep `nonce` for `take_musig_nonce()`
+pub fn store_musig_nonce(nonce: SecretNonce) {
+    let mut nonces = MUSIG_NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    if nonces.len() == MAX_PENDING_MUSIG_NONCES {
+        nonces.remove(0);
+    }
+    nonces.push(nonce);
+}
+
+/// Remove and return the stored nonce of `public_key` whose public half
+/// is among `public_nonces`
+pub fn take_musig_nonce(public_key: &secp256k1::PublicKey, public_nonces: &[PublicNonce]) -> Option<SecretNonce> {
+    let mut nonces = MUSIG_NONCES.lock().unwrap_or_else(|e| e.into_inner());
+    let position = nonces
+        .iter()
+        .position(|nonce| nonce.public_key() == *public_key && public_nonces.contains(&nonce.public_nonce()))?;
+    Some(nonces.remove(position))
+}
+
+/// Parse request JSON, taking `"network"` from `vault_init()` if absent
+///
+/// Malformed JSON is reported through `json_error`, so each export
+/// keeps its own error code and message.
+pub fn parse_request<T: DeserializeOwned>(
+    json: &str,
+    json_error: impl Fn(serde_json::Error) -> CoreError,
+) -> Result<T, CoreError> {
+    let mut request: serde_json::Value = serde_json::from_str(json).map_err(&json_error)?;
+    if let serde_json::Value::Object(fields) = &mut request {
+        if !fields.contains_key("network") {
+            let network = NETWORK.resolve(None)?;
+            fields.insert("network".to_string(), serde_json::json!(network));
+        }
+    }
+    serde_json::from_value(request).map_err(json_error)
+}
+
+/// Convert a result into a status code for integer-returning exports
+///
+/// `Ok` clears the last error and returns 0; `Err` stores the error for
+/// `vault_last_error_message()` / `vault_last_error_code()` and returns -1.
+pub fn status(result: Result<(), CoreError>) -> i32 {
+    match result {
+        Ok(()) => {
+            clear_last_error();
+            0
+        }
+        Err(error) => {
+            log::warn!("Returning error {}: {}", error.code(), error);
+            set_last_error(error);
+            -1
+        }
+    }
+}
+
+/// Return types an export can produce when its body fails
+pub trait FfiReturn {
+    /// Value handed back to the caller for `error`
+    fn from_error(error: CoreError) -> Self;
+}
+
+impl FfiReturn for *mut c_char {
+    fn from_error(error: CoreError) -> Self {
+        error_response(error)
+    }
+}
+
+impl FfiReturn for i32 {
+    fn from_error(error: CoreError) -> Self {
+        status(Err(error))
+    }
+}
+
+impl FfiReturn for u32 {
+    fn from_error(_error: CoreError) -> Self {
+        0
+    }
+}
+
+impl FfiReturn for ByteBuffer {
+    fn from_error(error: CoreError) -> Self {
+        set_last_error(error);
+        ByteBuffer::null()
+    }
+}
+
+impl FfiReturn for () {
+    fn from_error(_error: CoreError) -> Self {}
+}
+
+/// Run the body of export `name`, converting a panic into `CoreError::Internal`
+///
+/// Unwinding across an `extern "C"` boundary is undefined behavior, so
+/// every export goes through here (see `ffi_export!`). Entry and exit
+/// are logged at debug level.
+pub fn guard<R: FfiReturn>(name: &str, body: impl FnOnce() -> R) -> R {
+    log::debug!("{} called", name);
+    let result = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            log::warn!("{} failed with {}", name, message);
+            R::from_error(CoreError::Internal(message))
+        }
+    };
+    log::debug!("{} returned", name);
+    result
+}
+
+/// Best-effort text of a panic payload
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        format!("panic: {}", msg)
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        format!("panic: {}", msg)
+    } else {
+        "panic with non-string payload".to_string()
+    }
+}
+
+/// Cap on the JSON arguments of exports, in bytes including the NUL
+pub const MAX_JSON_INPUT_LEN: usize = 10 * 1024 * 1024;
 
 /// Convert Rust string to C string pointer
+///
+/// Interior NULs would end the string early on the C side, so each is
+/// replaced with U+FFFD.
 pub fn to_c_string(s: &str) -> *mut c_char {
-    match CString::new(s) {
-        Ok(cs) => cs.into_raw(),
-        Err(_) => CString::new("error: invalid string").unwrap().into_raw(),
-    }
+    let cs = if s.contains('\0') {
+        CString::new(s.replace('\0', "\u{FFFD}"))
+    } else {
+        CString::new(s)
+    };
+    cs.expect("no interior NULs remain").into_raw()
 }
 
 /// Convert C string pointer to Rust string
@@ -15,28 +269,414 @@ pub fn from_c_string(ptr: *const c_char) -> Result<String, CoreError> {
     if ptr.is_null() {
         return Err(CoreError::InvalidInput("null pointer".to_string()));
     }
-    unsafe {
-        CStr::from_ptr(ptr)
-            .to_str()
-            .map(|s| s.to_string())
-            .map_err(|e| CoreError::InvalidInput(e.to_string()))
+    utf8_string(unsafe { CStr::from_ptr(ptr) })
+}
+
+/// Convert C string pointer to Rust string, reading at most `max_len` bytes
+///
+/// Bytes are read one at a time up to the NUL, so a caller that forgot
+/// the terminator gets `InvalidInput` instead of a read running off the
+/// end of its buffer, and a well-formed string is never read past.
+pub fn from_c_string_bounded(ptr: *const c_char, max_len: usize) -> Result<String, CoreError> {
+    if ptr.is_null() {
+        return Err(CoreError::InvalidInput("null pointer".to_string()));
     }
+    let len = (0..max_len)
+        .find(|&i| unsafe { *ptr.add(i) } == 0)
+        .ok_or_else(|| CoreError::InvalidInput(format!("missing NUL within {} bytes", max_len)))?;
+    let bytes = unsafe { std::slice::from_raw_parts(ptr.cast::<u8>(), len + 1) };
+    let cstr = CStr::from_bytes_until_nul(bytes).map_err(|e| CoreError::InvalidInput(e.to_string()))?;
+    utf8_string(cstr)
+}
+
+fn utf8_string(cstr: &CStr) -> Result<String, CoreError> {
+    cstr.to_str()
+        .map(|s| s.to_string())
+        .map_err(|e| CoreError::InvalidUtf8 { offset: e.valid_up_to() })
 }
 
 /// Create JSON error response
+///
+/// `details` holds the fields of structured errors, and is an empty
+/// object for errors that only carry a message.
 pub fn error_response(error: CoreError) -> *mut c_char {
+    log::warn!("Returning error {}: {}", error.code(), error);
     let response = serde_json::json!({
         "error": true,
         "code": error.code(),
         "message": error.to_string(),
+        "details": error.details(),
     });
     to_c_string(&response.to_string())
 }
 
-/// Create JSON success response
+/// Version of the success envelope, sent as `"ffi_schema"`
+///
+/// Version 1 returned payloads bare; version 2 wraps them in
+/// `{"error": false, "ffi_schema": 2, "data": ...}`.
+pub const FFI_SCHEMA_VERSION: u32 = 2;
+
+/// Create JSON success response: `{"error":false,"ffi_schema":2,"data":<payload>}`
+///
+/// With `error_response()`, hosts tell the outcome from the boolean
+/// `error` alone. Every export returning JSON uses this. Exports whose
+/// result is text return it bare: `vault_version`,
+/// `vault_last_error_message`, `ffi_get_derivation_path`,
+/// `vault_export_wallet` (a wallet file), `vault_bip21_uri` and
+/// `ffi_blocks_to_time_estimate`.
 pub fn success_response<T: serde::Serialize>(data: T) -> *mut c_char {
+    #[derive(serde::Serialize)]
+    struct Envelope<T> {
+        error: bool,
+        ffi_schema: u32,
+        data: T,
+    }
+
+    success_response_legacy(Envelope {
+        error: false,
+        ffi_schema: FFI_SCHEMA_VERSION,
+        data,
+    })
+}
+
+/// Create a schema 1 success response: the payload, bare
+///
+/// For exports that have to keep the old shape while their hosts
+/// migrate to `success_response()`. No export uses it at schema 2.
+pub fn success_response_legacy<T: serde::Serialize>(data: T) -> *mut c_char {
     match serde_json::to_string(&data) {
         Ok(json) => to_c_string(&json),
         Err(e) => error_response(CoreError::SerializationError(e.to_string())),
     }
 }
+
+/// `VaultVersion::feature_flags` bit: anti-fee-sniping locktimes are
+/// randomized (the `rand` cargo feature)
+pub const FEATURE_RAND: u32 = 1 << 0;
+
+/// `VaultVersion::feature_flags` bit: MuSig2 internal keys are supported
+pub const FEATURE_MUSIG: u32 = 1 << 1;
+
+/// `VaultVersion::feature_flags` bit: UR QR encoding of PSBTs and
+/// descriptors is supported
+pub const FEATURE_UR: u32 = 1 << 2;
+
+/// `VaultVersion::feature_flags` bit: address ranges and index scans run
+/// on all cores (the `parallel` cargo feature)
+pub const FEATURE_PARALLEL: u32 = 1 << 3;
+
+/// `VaultVersion::feature_flags` bit: `vault_generate_test_vectors()` is
+/// exported (the `test-vectors` cargo feature)
+pub const FEATURE_TEST_VECTORS: u32 = 1 << 4;
+
+/// Library version and compiled-in capabilities, returned by value
+///
+/// Lets hosts test for a feature with `feature_flags` instead of parsing
+/// the version string. All fields are 0 on error.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VaultVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+    /// `FEATURE_*` bits of the capabilities in this build
+    pub feature_flags: u32,
+}
+
+impl VaultVersion {
+    /// Version of this build
+    pub fn current() -> Self {
+        VaultVersion {
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+            patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0),
+            feature_flags: feature_flags(),
+        }
+    }
+}
+
+impl FfiReturn for VaultVersion {
+    fn from_error(error: CoreError) -> Self {
+        set_last_error(error);
+        VaultVersion::default()
+    }
+}
+
+/// `FEATURE_*` bits of the capabilities compiled into this build
+pub fn feature_flags() -> u32 {
+    let mut flags = FEATURE_MUSIG | FEATURE_UR;
+    if cfg!(feature = "rand") {
+        flags |= FEATURE_RAND;
+    }
+    if cfg!(feature = "parallel") {
+        flags |= FEATURE_PARALLEL;
+    }
+    if cfg!(feature = "test-vectors") {
+        flags |= FEATURE_TEST_VECTORS;
+    }
+    flags
+}
+
+/// Owned byte buffer handed across the FFI boundary
+///
+/// Produced by `to_byte_buffer()` and released with `free_rust_bytes()`.
+/// An empty buffer has a null `ptr` and zero `len` and `cap`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ByteBuffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub cap: usize,
+}
+
+impl ByteBuffer {
+    /// Empty buffer, also returned on error
+    pub fn null() -> Self {
+        ByteBuffer {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+}
+
+/// Hand ownership of `bytes` to the caller as a `ByteBuffer`
+pub fn to_byte_buffer(bytes: Vec<u8>) -> ByteBuffer {
+    if bytes.is_empty() {
+        return ByteBuffer::null();
+    }
+    let mut bytes = std::mem::ManuallyDrop::new(bytes);
+    ByteBuffer {
+        ptr: bytes.as_mut_ptr(),
+        len: bytes.len(),
+        cap: bytes.capacity(),
+    }
+}
+
+/// Take back ownership of a buffer created by `to_byte_buffer()`
+///
+/// Null buffers yield an empty vector.
+pub fn from_byte_buffer(buffer: ByteBuffer) -> Vec<u8> {
+    if buffer.ptr.is_null() {
+        return Vec::new();
+    }
+    unsafe { Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.cap) }
+}
+
+/// Copy caller-owned bytes into a vector
+///
+/// A null pointer is accepted only with a zero length.
+pub fn from_raw_bytes(ptr: *const u8, len: usize) -> Result<Vec<u8>, CoreError> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if ptr.is_null() {
+        return Err(CoreError::InvalidInput("null pointer".to_string()));
+    }
+    unsafe { Ok(std::slice::from_raw_parts(ptr, len).to_vec()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(error: CoreError) -> serde_json::Value {
+        let ptr = error_response(error);
+        let json = unsafe { CString::from_raw(ptr) };
+        serde_json::from_str(json.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_network_context_init_once() {
+        let context = NetworkContext::new();
+        assert_eq!(context.get(), None);
+        assert!(matches!(context.resolve(None), Err(CoreError::NotInitialized)));
+        assert_eq!(context.resolve(Some(Network::Signet)).unwrap(), Network::Signet);
+
+        context.init(Network::Regtest).unwrap();
+        context.init(Network::Regtest).unwrap();
+        match context.init(Network::Mainnet) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "regtest");
+                assert_eq!(actual, "mainnet");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+        assert_eq!(context.get(), Some(Network::Regtest));
+        assert_eq!(context.resolve(None).unwrap(), Network::Regtest);
+        // An explicit network still wins
+        assert_eq!(context.resolve(Some(Network::Testnet)).unwrap(), Network::Testnet);
+    }
+
+    #[test]
+    fn test_network_context_testnets_differ() {
+        let context = NetworkContext::new();
+        context.init(Network::Testnet4).unwrap();
+        match context.init(Network::Testnet) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "testnet4");
+                assert_eq!(actual, "testnet");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_network_context_init_race() {
+        let networks = [Network::Mainnet, Network::Testnet, Network::Signet, Network::Regtest, Network::Testnet4];
+        for _ in 0..20 {
+            let context = NetworkContext::new();
+            let barrier = std::sync::Barrier::new(8);
+            let results: Vec<(Network, bool)> = std::thread::scope(|scope| {
+                let handles: Vec<_> = (0..8)
+                    .map(|i| {
+                        let network = networks[i % networks.len()];
+                        let (context, barrier) = (&context, &barrier);
+                        scope.spawn(move || {
+                            barrier.wait();
+                            (network, context.init(network).is_ok())
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+            });
+
+            // Exactly the threads that asked for the winning network succeed
+            let winner = context.get().unwrap();
+            for (network, ok) in results {
+                assert_eq!(ok, network == winner);
+            }
+        }
+    }
+
+    #[test]
+    fn test_signing_error_json() {
+        let error = CoreError::SigningError {
+            input_index: 2,
+            reason: "Sighash failed".to_string(),
+        };
+        assert_eq!(
+            response(error),
+            json!({
+                "error": true,
+                "code": 2004,
+                "message": "Signing input 2 failed: Sighash failed",
+                "details": {"input_index": 2, "reason": "Sighash failed"},
+            })
+        );
+    }
+
+    #[test]
+    fn test_timelock_not_expired_json() {
+        let error = CoreError::TimelockNotExpired {
+            required: 144,
+            current: 12,
+        };
+        assert_eq!(
+            response(error),
+            json!({
+                "error": true,
+                "code": 2005,
+                "message": "Timelock not expired: need 144 blocks, have 12",
+                "details": {"required": 144, "current": 12},
+            })
+        );
+    }
+
+    #[test]
+    fn test_script_error_json() {
+        let error = CoreError::ScriptError("Failed to finalize Taproot tree".to_string());
+        assert_eq!(
+            response(error),
+            json!({
+                "error": true,
+                "code": 3003,
+                "message": "Script construction failed: Failed to finalize Taproot tree",
+                "details": {},
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_c_string_bounded_embedded_nul() {
+        // Everything after the first NUL is ignored
+        let bytes = b"{\"a\":1}\0{\"b\":2}\0";
+        let read = from_c_string_bounded(bytes.as_ptr().cast(), bytes.len()).unwrap();
+        assert_eq!(read, "{\"a\":1}");
+        assert_eq!(from_c_string(bytes.as_ptr().cast()).unwrap(), read);
+    }
+
+    #[test]
+    fn test_from_c_string_bounded_length_cap() {
+        let bytes = b"abcd\0";
+        assert_eq!(from_c_string_bounded(bytes.as_ptr().cast(), 5).unwrap(), "abcd");
+        match from_c_string_bounded(bytes.as_ptr().cast(), 4) {
+            Err(CoreError::InvalidInput(message)) => assert_eq!(message, "missing NUL within 4 bytes"),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+
+        // An unterminated buffer is read up to the cap and no further
+        let unterminated = [b'x'; 16];
+        assert!(matches!(
+            from_c_string_bounded(unterminated.as_ptr().cast(), unterminated.len()),
+            Err(CoreError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            from_c_string_bounded(std::ptr::null(), MAX_JSON_INPUT_LEN),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_c_string_invalid_utf8() {
+        let bytes = b"ok \xe2\x82 then\0";
+        for result in [
+            from_c_string(bytes.as_ptr().cast()),
+            from_c_string_bounded(bytes.as_ptr().cast(), bytes.len()),
+        ] {
+            assert!(matches!(result, Err(CoreError::InvalidUtf8 { offset: 3 })));
+        }
+        assert_eq!(
+            response(CoreError::InvalidUtf8 { offset: 3 }),
+            json!({
+                "error": true,
+                "code": 4004,
+                "message": "Invalid UTF-8 at byte 3",
+                "details": {"offset": 3},
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_c_string_interior_nul() {
+        let ptr = to_c_string("label\0suffix");
+        let returned = unsafe { CString::from_raw(ptr) };
+        assert_eq!(returned.to_str().unwrap(), "label\u{FFFD}suffix");
+    }
+
+    fn take(ptr: *mut c_char) -> String {
+        unsafe { CString::from_raw(ptr) }.into_string().unwrap()
+    }
+
+    #[test]
+    fn test_success_response_envelope() {
+        let payload = json!({"address": "bcrt1p...", "vault_index": 3});
+        assert_eq!(
+            take(success_response(&payload)),
+            r#"{"error":false,"ffi_schema":2,"data":{"address":"bcrt1p...","vault_index":3}}"#
+        );
+        assert_eq!(take(success_response_legacy(&payload)), payload.to_string());
+
+        // `error` is a boolean both ways, never the string "false"
+        let success: serde_json::Value = serde_json::from_str(&take(success_response(()))).unwrap();
+        assert_eq!(success["error"], json!(false));
+        assert_eq!(success["data"], json!(null));
+        let failure = response(CoreError::InvalidInput("bad".to_string()));
+        assert_eq!(failure["error"], json!(true));
+    }
+}
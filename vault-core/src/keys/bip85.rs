@@ -0,0 +1,198 @@
+//! BIP85 child mnemonics, for a recovery key backed up by the main seed
+//!
+//! The recovery mnemonic is derived from the owner's master key, so one
+//! backup restores both keys, while the device holding the recovery key
+//! never needs the owner's seed and the online device never holds the
+//! recovery key.
+
+use bip39::{Language, Mnemonic};
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+use bitcoin::hashes::{hmac, sha512, Hash, HashEngine};
+use bitcoin::secp256k1::Secp256k1;
+
+use crate::error::{CoreError, CoreResult};
+use crate::vault::Network;
+
+use super::mnemonic::{account_xpriv, from_mnemonic, TAPROOT_PURPOSE};
+use super::secret::{wipe, wipe_string, SecretMaterial};
+use super::{derive_secret, require_key_network};
+
+/// BIP85 purpose, "bip" on a phone keypad
+const BIP85_PURPOSE: u32 = 83696968;
+
+/// BIP85 application number of BIP39 mnemonics
+const BIP39_APPLICATION: u32 = 39;
+
+/// BIP85 language code of the English wordlist
+const ENGLISH: u32 = 0;
+
+/// HMAC-SHA512 key turning a derived private key into entropy
+const ENTROPY_KEY: &[u8] = b"bip-entropy-from-k";
+
+/// English mnemonic `index` of `word_count` words derived from a master
+/// key, at `m/83696968'/39'/0'/{word_count}'/{index}'`
+///
+/// Fails with `InvalidInput` for a word count other than 12, 18 or 24,
+/// an index that can't be hardened, or a key that isn't a master key.
+/// The caller owns the words and should wipe them with `wipe_string`.
+pub fn bip85_mnemonic(master: &SecretMaterial, word_count: u32, index: u32) -> CoreResult<String> {
+    let entropy_len = match word_count {
+        12 => 16,
+        18 => 24,
+        24 => 32,
+        _ => {
+            return Err(CoreError::InvalidInput(format!(
+                "Unsupported BIP85 word count {}; expected 12, 18 or 24",
+                word_count
+            )))
+        }
+    };
+    let index = ChildNumber::from_hardened_idx(index)
+        .map_err(|_| CoreError::InvalidInput(format!("BIP85 index {} can't be hardened; it must be below 2^31", index)))?;
+
+    let depth = master.xpriv()?.depth;
+    if depth != 0 {
+        return Err(CoreError::InvalidInput(format!("Expected a master key, got a key at depth {}", depth)));
+    }
+
+    let path = DerivationPath::from(vec![
+        ChildNumber::Hardened { index: BIP85_PURPOSE },
+        ChildNumber::Hardened { index: BIP39_APPLICATION },
+        ChildNumber::Hardened { index: ENGLISH },
+        ChildNumber::Hardened { index: word_count },
+        index,
+    ]);
+    let mut entropy = derive_entropy(master, &path)?;
+    let mnemonic = Mnemonic::from_entropy_in(Language::English, &entropy[..entropy_len]);
+    wipe(&mut entropy);
+
+    mnemonic
+        .map(|mnemonic| mnemonic.to_string())
+        .map_err(|e| CoreError::Internal(format!("BIP85 entropy rejected: {}", e)))
+}
+
+/// Recovery xpub of a vault whose recovery key is BIP85 child mnemonic
+/// `index` of `master`: the BIP86 account 0 xpub of that mnemonic,
+/// without a passphrase
+///
+/// This is the key a signer set up from the child mnemonic reports, so
+/// it can be passed to `VaultBuilder::recovery_xpub` without the child
+/// words ever leaving this call.
+pub fn bip85_recovery_xpub(
+    master: &SecretMaterial,
+    word_count: u32,
+    index: u32,
+    network: Network,
+) -> CoreResult<ExtendedPubKey> {
+    require_key_network(master.xpriv()?.network == bitcoin::Network::Bitcoin, network)?;
+
+    let mut words = bip85_mnemonic(master, word_count, index)?;
+    let child_master = from_mnemonic(&words, "", network);
+    wipe_string(&mut words);
+
+    let account = account_xpriv(&child_master?, TAPROOT_PURPOSE, 0, network)?;
+    Ok(ExtendedPubKey::from_priv(&Secp256k1::new(), &account.xpriv()?))
+}
+
+/// BIP85 entropy: HMAC-SHA512 of the private key at `path`
+fn derive_entropy(master: &SecretMaterial, path: &DerivationPath) -> CoreResult<[u8; 64]> {
+    let child = derive_secret(&Secp256k1::new(), master, path)?;
+    let mut key = child.secret_key().secret_bytes();
+
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(ENTROPY_KEY);
+    engine.input(&key);
+    wipe(&mut key);
+    Ok(hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::OWNER_TPUB;
+    use std::str::FromStr;
+
+    use crate::vault::{VaultBuilder, VaultTemplate};
+
+    /// Master key of the BIP85 test vectors
+    const MASTER: &str = "xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb";
+
+    fn master() -> SecretMaterial {
+        SecretMaterial::from_str(MASTER).unwrap()
+    }
+
+    #[test]
+    fn test_bip85_entropy_vector() {
+        let path = DerivationPath::from_str("m/83696968'/0'/0'").unwrap();
+        let entropy = derive_entropy(&master(), &path).unwrap();
+        assert_eq!(
+            hex::encode(entropy),
+            "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7"
+        );
+    }
+
+    #[test]
+    fn test_bip85_mnemonic_vectors() {
+        let vectors = [
+            (12, "girl mad pet galaxy egg matter matrix prison refuse sense ordinary nose"),
+            (
+                18,
+                "near account window bike charge season chef number sketch tomorrow excuse sniff circle vital hockey outdoor supply token",
+            ),
+            (
+                24,
+                "puppy ocean match cereal symbol another shed magic wrap hammer bulb intact gadget divorce twin tonight reason outdoor destroy simple truth cigar social volcano",
+            ),
+        ];
+        for (word_count, expected) in vectors {
+            assert_eq!(bip85_mnemonic(&master(), word_count, 0).unwrap(), expected);
+        }
+
+        // Each index is an unrelated mnemonic
+        assert_ne!(bip85_mnemonic(&master(), 12, 1).unwrap(), vectors[0].1);
+    }
+
+    #[test]
+    fn test_bip85_mnemonic_errors() {
+        for word_count in [0, 15, 21, 25] {
+            assert!(matches!(bip85_mnemonic(&master(), word_count, 0), Err(CoreError::InvalidInput(_))));
+        }
+        assert!(matches!(bip85_mnemonic(&master(), 12, 1 << 31), Err(CoreError::InvalidInput(_))));
+        assert!(bip85_mnemonic(&master(), 12, (1 << 31) - 1).is_ok());
+
+        let account = account_xpriv(&master(), TAPROOT_PURPOSE, 0, Network::Mainnet).unwrap();
+        assert!(matches!(bip85_mnemonic(&account, 12, 0), Err(CoreError::InvalidInput(_))));
+        let single = SecretMaterial::from_secret_key(&master().secret_key());
+        assert!(matches!(bip85_mnemonic(&single, 12, 0), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_bip85_recovery_xpub() {
+        let words = bip85_mnemonic(&master(), 12, 0).unwrap();
+        let child = from_mnemonic(&words, "", Network::Mainnet).unwrap();
+        let account = account_xpriv(&child, TAPROOT_PURPOSE, 0, Network::Mainnet).unwrap();
+        let expected = ExtendedPubKey::from_priv(&Secp256k1::new(), &account.xpriv().unwrap());
+
+        let xpub = bip85_recovery_xpub(&master(), 12, 0, Network::Mainnet).unwrap();
+        assert_eq!(xpub, expected);
+        assert_ne!(xpub.parent_fingerprint, master().fingerprint());
+        assert!(matches!(
+            bip85_recovery_xpub(&master(), 12, 0, Network::Regtest),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bip85_recovery_xpub_builds_vault() {
+        let master = SecretMaterial::from_seed(&[7; 32], bitcoin::Network::Regtest).unwrap();
+        let recovery = bip85_recovery_xpub(&master, 24, 0, Network::Regtest).unwrap();
+
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(recovery.to_string())
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+        assert_eq!(vault.recovery_xpub(), &recovery);
+    }
+}
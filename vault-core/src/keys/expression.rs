@@ -0,0 +1,363 @@
+//! Descriptor-style key expressions: `[fingerprint/path]xpub/path/*`
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
+
+use crate::error::{CoreError, CoreResult};
+use crate::vault::Network;
+
+use super::{normalize_extended_key, require_key_network};
+
+/// How a key expression ends after its unhardened path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Wildcard {
+    /// A single key
+    None,
+    /// `/*`, every unhardened child
+    Single,
+    /// `/<a;b;...>/*`, one range of children per alternative step (BIP389)
+    Multipath(Vec<u32>),
+}
+
+/// A key as written in a descriptor
+///
+/// `[73c5da0a/86'/0'/0']xpub.../<0;1>/*` has the origin
+/// `(73c5da0a, m/86'/0'/0')`, an empty path and a multipath wildcard.
+/// `Display` writes hardened steps with `'`, so parsing and displaying
+/// an expression written that way gives back the same string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyExpression {
+    /// Master key fingerprint and path to `xpub`, if given
+    pub origin: Option<KeySource>,
+    pub xpub: ExtendedPubKey,
+    /// Unhardened steps from `xpub`, before any wildcard
+    pub path: DerivationPath,
+    pub wildcard: Wildcard,
+}
+
+impl KeyExpression {
+    /// Origin of the xpub: as given, or the xpub's own fingerprint and an
+    /// empty path
+    pub fn key_source(&self) -> KeySource {
+        self.origin
+            .clone()
+            .unwrap_or_else(|| (self.xpub.fingerprint(), DerivationPath::master()))
+    }
+
+    /// The account xpub and its origin, for a vault key on `network`
+    ///
+    /// Vault keys are the `0/index` children of an account xpub, so only
+    /// the account xpub itself, `xpub/0/*` and `xpub/<0;1>/*` describe
+    /// one; other expressions fail with `InvalidXpub`.
+    pub fn vault_account(&self, network: Network) -> CoreResult<(ExtendedPubKey, KeySource)> {
+        require_key_network(self.xpub.network == bitcoin::Network::Bitcoin, network)?;
+
+        let receive = [ChildNumber::Normal { index: 0 }];
+        let account = match &self.wildcard {
+            Wildcard::None => self.path.is_empty(),
+            Wildcard::Single => self.path.as_ref() == receive,
+            Wildcard::Multipath(steps) => self.path.is_empty() && steps[..] == [0, 1],
+        };
+        if !account {
+            return Err(CoreError::InvalidXpub(format!(
+                "Key expression ending '{}' doesn't describe vault keys, which derive at xpub/0/*",
+                self.ending()
+            )));
+        }
+        Ok((self.xpub, self.key_source()))
+    }
+
+    /// Everything after the xpub, e.g. `/0/*`
+    fn ending(&self) -> String {
+        let mut ending = self.path.to_string()[1..].to_string();
+        match &self.wildcard {
+            Wildcard::None => {}
+            Wildcard::Single => ending.push_str("/*"),
+            Wildcard::Multipath(steps) => {
+                let steps: Vec<String> = steps.iter().map(|step| step.to_string()).collect();
+                ending.push_str(&format!("/<{}>/*", steps.join(";")));
+            }
+        }
+        ending
+    }
+}
+
+impl fmt::Display for KeyExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((fingerprint, path)) = &self.origin {
+            write!(f, "[{}{}]", fingerprint, &path.to_string()[1..])?;
+        }
+        write!(f, "{}{}", self.xpub, self.ending())
+    }
+}
+
+impl FromStr for KeyExpression {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key_expression(s)
+    }
+}
+
+/// Builders take keys as strings, which a key expression writes itself to
+impl From<KeyExpression> for String {
+    fn from(expression: KeyExpression) -> Self {
+        expression.to_string()
+    }
+}
+
+/// Parse a key expression: an optional `[fingerprint/path]` origin, an
+/// extended public key, unhardened steps and an optional `/*` or
+/// `/<a;b;...>/*` ending
+///
+/// Hardened steps may be written `h` or `'` in the origin, and can't
+/// follow the xpub, which can only derive unhardened children. The
+/// origin must agree with the xpub: its path is as long as the xpub's
+/// depth and ends at its child number, and an empty path gives the
+/// xpub's own fingerprint. SLIP-132 prefixes are accepted (see
+/// `normalize_extended_key()`). Errors are `InvalidXpub`, naming the
+/// segment at fault. The network is checked by whoever uses the key, as
+/// in `vault_account()`.
+pub fn parse_key_expression(s: &str) -> CoreResult<KeyExpression> {
+    let (origin, key) = match s.strip_prefix('[') {
+        Some(rest) => {
+            let (origin, key) = rest
+                .split_once(']')
+                .ok_or_else(|| CoreError::InvalidXpub("Key origin is missing its closing ']'".to_string()))?;
+            (Some(parse_origin(origin)?), key)
+        }
+        None => (None, s),
+    };
+
+    let mut segments = key.split('/');
+    let xpub_str = segments.next().unwrap_or_default();
+    let (xpub, _) = normalize_extended_key(xpub_str)?;
+    if let Some((fingerprint, path)) = &origin {
+        check_origin(fingerprint, path, &xpub)?;
+    }
+
+    let segments: Vec<&str> = segments.collect();
+    let mut path = Vec::new();
+    let mut wildcard = Wildcard::None;
+    for (i, segment) in segments.iter().enumerate() {
+        let step = i + 1;
+        let last = i + 1 == segments.len();
+        let bad = |reason: &str| CoreError::InvalidXpub(format!("Step {} ('{}') after the xpub {}", step, segment, reason));
+
+        if *segment == "*" {
+            if !last {
+                return Err(bad("is a wildcard, which must come last"));
+            }
+            if wildcard == Wildcard::None {
+                wildcard = Wildcard::Single;
+            }
+        } else if let Some(alternatives) = segment.strip_prefix('<').and_then(|rest| rest.strip_suffix('>')) {
+            if segments.get(i + 1) != Some(&"*") || i + 2 != segments.len() {
+                return Err(bad("is a multipath step, which must be followed by a final /*"));
+            }
+            let steps = alternatives
+                .split(';')
+                .map(|alternative| match ChildNumber::from_str(alternative) {
+                    Ok(ChildNumber::Normal { index }) => Ok(index),
+                    _ => Err(bad(&format!("has '{}', which isn't an unhardened index", alternative))),
+                })
+                .collect::<CoreResult<Vec<u32>>>()?;
+            if steps.len() < 2 {
+                return Err(bad("needs at least two alternatives"));
+            }
+            if (1..steps.len()).any(|j| steps[..j].contains(&steps[j])) {
+                return Err(bad("repeats an alternative"));
+            }
+            wildcard = Wildcard::Multipath(steps);
+        } else {
+            match ChildNumber::from_str(segment) {
+                Ok(child @ ChildNumber::Normal { .. }) => path.push(child),
+                Ok(ChildNumber::Hardened { .. }) => {
+                    return Err(bad("is hardened; an xpub can only derive unhardened children"))
+                }
+                Err(_) => return Err(bad("isn't a child index")),
+            }
+        }
+    }
+
+    Ok(KeyExpression {
+        origin,
+        xpub,
+        path: DerivationPath::from(path),
+        wildcard,
+    })
+}
+
+/// `fingerprint/path` inside an origin's brackets
+fn parse_origin(origin: &str) -> CoreResult<KeySource> {
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    if fingerprint.len() != 8 {
+        return Err(CoreError::InvalidXpub(format!(
+            "Key origin fingerprint '{}' must be 8 hex characters",
+            fingerprint
+        )));
+    }
+    let fingerprint = Fingerprint::from_str(fingerprint)
+        .map_err(|_| CoreError::InvalidXpub(format!("Key origin fingerprint '{}' is not hex", fingerprint)))?;
+
+    let steps = match path {
+        "" => vec![],
+        path => path
+            .split('/')
+            .enumerate()
+            .map(|(i, step)| {
+                ChildNumber::from_str(step).map_err(|_| {
+                    CoreError::InvalidXpub(format!(
+                        "Invalid key origin path '{}': step {} ('{}') isn't a child index",
+                        path,
+                        i + 1,
+                        step
+                    ))
+                })
+            })
+            .collect::<CoreResult<Vec<_>>>()?,
+    };
+    Ok((fingerprint, DerivationPath::from(steps)))
+}
+
+/// Check that an origin can lead to `xpub`
+fn check_origin(fingerprint: &Fingerprint, path: &DerivationPath, xpub: &ExtendedPubKey) -> CoreResult<()> {
+    if path.len() != xpub.depth as usize {
+        return Err(CoreError::InvalidXpub(format!(
+            "Key origin path {} has {} steps, but the xpub is at depth {}",
+            path,
+            path.len(),
+            xpub.depth
+        )));
+    }
+    let consistent = match path.as_ref().last() {
+        Some(child) => *child == xpub.child_number,
+        None => *fingerprint == xpub.fingerprint(),
+    };
+    if !consistent {
+        return Err(CoreError::InvalidXpub(format!(
+            "Key origin [{}{}] doesn't lead to this xpub",
+            fingerprint,
+            &path.to_string()[1..]
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP86 test vector: account 0 of "abandon ... about", master fingerprint 73c5da0a
+    const ACCOUNT_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+    const ORIGIN: &str = "[73c5da0a/86'/0'/0']";
+
+    fn expression(s: &str) -> KeyExpression {
+        parse_key_expression(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_valid_forms() {
+        let xpub = ExtendedPubKey::from_str(ACCOUNT_XPUB).unwrap();
+        let origin = (Fingerprint::from([0x73, 0xc5, 0xda, 0x0a]), DerivationPath::from_str("m/86'/0'/0'").unwrap());
+
+        let bare = expression(ACCOUNT_XPUB);
+        assert_eq!(bare.origin, None);
+        assert_eq!(bare.xpub, xpub);
+        assert!(bare.path.is_empty());
+        assert_eq!(bare.wildcard, Wildcard::None);
+        assert_eq!(bare.key_source(), (xpub.fingerprint(), DerivationPath::master()));
+
+        let ranged = expression(&format!("[73c5da0a/86h/0h/0h]{}/0/*", ACCOUNT_XPUB));
+        assert_eq!(ranged.origin, Some(origin.clone()));
+        assert_eq!(ranged.key_source(), origin);
+        assert_eq!(ranged.path, DerivationPath::from_str("m/0").unwrap());
+        assert_eq!(ranged.wildcard, Wildcard::Single);
+
+        let multipath = expression(&format!("{}{}/<0;1>/*", ORIGIN, ACCOUNT_XPUB));
+        assert!(multipath.path.is_empty());
+        assert_eq!(multipath.wildcard, Wildcard::Multipath(vec![0, 1]));
+
+        let fixed = expression(&format!("{}/1/7", ACCOUNT_XPUB));
+        assert_eq!(fixed.path, DerivationPath::from_str("m/1/7").unwrap());
+        assert_eq!(fixed.wildcard, Wildcard::None);
+
+        let deep = expression(&format!("{}/2/<4;5;6>/*", ACCOUNT_XPUB));
+        assert_eq!(deep.wildcard, Wildcard::Multipath(vec![4, 5, 6]));
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        for ending in ["", "/0/*", "/<0;1>/*", "/1/7", "/2/<4;5;6>/*", "/*"] {
+            for origin in ["", ORIGIN] {
+                let s = format!("{}{}{}", origin, ACCOUNT_XPUB, ending);
+                assert_eq!(expression(&s).to_string(), s);
+                assert_eq!(String::from(expression(&s)), s);
+            }
+        }
+
+        // `h` is written back as `'`
+        let s = format!("[73c5da0a/86h/0h/0h]{}/<0;1>/*", ACCOUNT_XPUB);
+        assert_eq!(expression(&s).to_string(), format!("{}{}/<0;1>/*", ORIGIN, ACCOUNT_XPUB));
+    }
+
+    #[test]
+    fn test_hardened_suffix_rejected() {
+        for (ending, segment) in [("/0h/*", "Step 1 ('0h')"), ("/0/1'", "Step 2 ('1'')"), ("/<0;1h>/*", "has '1h'")] {
+            match parse_key_expression(&format!("{}{}", ACCOUNT_XPUB, ending)) {
+                Err(CoreError::InvalidXpub(msg)) => assert!(msg.contains(segment), "{}: {}", ending, msg),
+                other => panic!("{}: expected InvalidXpub, got {:?}", ending, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_expressions_name_the_segment() {
+        let cases = [
+            (format!("[73c5da0/86'/0'/0']{}", ACCOUNT_XPUB), "'73c5da0' must be 8 hex characters"),
+            (format!("[73c5da0z/86'/0'/0']{}", ACCOUNT_XPUB), "'73c5da0z' is not hex"),
+            (format!("[/86'/0'/0']{}", ACCOUNT_XPUB), "'' must be 8 hex characters"),
+            (format!("[73c5da0a/86'/x/0']{}", ACCOUNT_XPUB), "step 2 ('x')"),
+            (format!("73c5da0a/86'/0'/0']{}", ACCOUNT_XPUB), "Failed to parse xpub"),
+            (format!("[73c5da0a/86'/0'/0'{}", ACCOUNT_XPUB), "closing ']'"),
+            (format!("{}/0/*/1", ACCOUNT_XPUB), "Step 2 ('*')"),
+            (format!("{}/<0;1>", ACCOUNT_XPUB), "Step 1 ('<0;1>')"),
+            (format!("{}/<0>/*", ACCOUNT_XPUB), "at least two"),
+            (format!("{}/<1;1>/*", ACCOUNT_XPUB), "repeats"),
+            (format!("{}/0//*", ACCOUNT_XPUB), "Step 2 ('')"),
+        ];
+        for (s, expected) in cases {
+            match parse_key_expression(&s) {
+                Err(CoreError::InvalidXpub(msg)) => assert!(msg.contains(expected), "{}: {}", s, msg),
+                other => panic!("{}: expected InvalidXpub, got {:?}", s, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_vault_account() {
+        let xpub = ExtendedPubKey::from_str(ACCOUNT_XPUB).unwrap();
+        for ending in ["", "/0/*", "/<0;1>/*"] {
+            let (account, (fingerprint, _)) =
+                expression(&format!("{}{}{}", ORIGIN, ACCOUNT_XPUB, ending)).vault_account(Network::Mainnet).unwrap();
+            assert_eq!(account, xpub);
+            assert_eq!(fingerprint.to_string(), "73c5da0a");
+        }
+        for ending in ["/1/*", "/*", "/0", "/0/0/*", "/<1;0>/*", "/<0;1;2>/*"] {
+            assert!(
+                matches!(
+                    expression(&format!("{}{}", ACCOUNT_XPUB, ending)).vault_account(Network::Mainnet),
+                    Err(CoreError::InvalidXpub(_))
+                ),
+                "{}",
+                ending
+            );
+        }
+        assert!(matches!(
+            expression(ACCOUNT_XPUB).vault_account(Network::Regtest),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+//! BIP39 mnemonics to BIP32 master and account keys
+
+use bip39::{Language, Mnemonic};
+use bitcoin::bip32::{ChildNumber, DerivationPath};
+use bitcoin::secp256k1::Secp256k1;
+
+use crate::error::{CoreError, CoreResult, MnemonicError};
+use crate::vault::Network;
+
+use super::secret::{wipe, SecretMaterial};
+use super::{coin_type, derive_secret, require_key_network};
+
+/// BIP86 purpose, of the single-key Taproot accounts vault keys come from
+pub const TAPROOT_PURPOSE: u32 = 86;
+
+/// BIP32 master key of an English BIP39 mnemonic and passphrase
+///
+/// Words are separated by any whitespace and normalized (NFKD) along
+/// with the passphrase, which is empty for none. A word missing from
+/// the wordlist, a checksum mismatch and a bad word count are told apart
+/// by `CoreError::InvalidMnemonic`.
+pub fn from_mnemonic(words: &str, passphrase: &str, network: Network) -> CoreResult<SecretMaterial> {
+    let mnemonic = Mnemonic::parse_in(Language::English, words).map_err(|e| match e {
+        bip39::Error::UnknownWord(word_index) => CoreError::InvalidMnemonic(MnemonicError::UnknownWord { word_index }),
+        bip39::Error::InvalidChecksum => CoreError::InvalidMnemonic(MnemonicError::BadChecksum),
+        bip39::Error::BadWordCount(word_count) => CoreError::InvalidMnemonic(MnemonicError::BadWordCount { word_count }),
+        // Only entropy conversions and language detection report the others
+        e => CoreError::Internal(format!("Unexpected BIP39 error: {}", e)),
+    })?;
+
+    let mut seed = mnemonic.to_seed(passphrase);
+    let master = SecretMaterial::from_seed(&seed, network.into());
+    wipe(&mut seed);
+    master
+}
+
+/// Account key at `account_path()` of a master key
+///
+/// With `TAPROOT_PURPOSE` this is the account whose xpub a vault takes as
+/// owner or recovery key. Fails with `InvalidInput` for a key that
+/// isn't a master key or an index that can't be hardened, and with
+/// `NetworkMismatch` for a master key of the other network type.
+pub fn account_xpriv(
+    master: &SecretMaterial,
+    purpose: u32,
+    account: u32,
+    network: Network,
+) -> CoreResult<SecretMaterial> {
+    let master_key = master.xpriv()?;
+    require_key_network(master_key.network == bitcoin::Network::Bitcoin, network)?;
+    if master_key.depth != 0 {
+        return Err(CoreError::InvalidInput(format!(
+            "Expected a master key, got a key at depth {}",
+            master_key.depth
+        )));
+    }
+
+    let path = account_path(purpose, account, network)?;
+
+    derive_secret(&Secp256k1::new(), master, &path)
+}
+
+/// `m/purpose'/coin'/account'`, with coin type 0 on mainnet and 1 elsewhere
+///
+/// Fails with `InvalidInput` for an index that can't be hardened.
+pub fn account_path(purpose: u32, account: u32, network: Network) -> CoreResult<DerivationPath> {
+    let hardened = |what: &str, index: u32| {
+        ChildNumber::from_hardened_idx(index)
+            .map_err(|_| CoreError::InvalidInput(format!("{} {} can't be hardened; it must be below 2^31", what, index)))
+    };
+    Ok(DerivationPath::from(vec![
+        hardened("Purpose", purpose)?,
+        ChildNumber::Hardened { index: coin_type(network) },
+        hardened("Account", account)?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bip32::ExtendedPubKey;
+
+    /// BIP39 reference vectors (passphrase "TREZOR"): mnemonic, seed, master xprv
+    const VECTORS: [(&str, &str, &str); 6] = [
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF",
+        ),
+        (
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+            "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq",
+        ),
+        (
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+            "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+            "xprv9s21ZrQH143K2V4oox4M8Zmhi2Fjx5XK4Lf7GKRvPSgydU3mjZuKGCTg7UPiBUD7ydVPvSLtg9hjp7MQTYsW67rZHAXeccqYqrsx8LcXnyd",
+        ),
+        (
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+            "xprv9s21ZrQH143K32qBagUJAMU2LsHg3ka7jqMcV98Y7gVeVyNStwYS3U7yVVoDZ4btbRNf4h6ibWpY22iRmXq35qgLs79f312g2kj5539ebPM",
+        ),
+        (
+            "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+            "c0c519bd0e91a2ed54357d9d1ebef6f5af218a153624cf4f2da911a0ed8f7a09e2ef61af0aca007096df430022f7a2b6fb91661a9589097069720d015e4e982f",
+            "xprv9s21ZrQH143K3CSnQNYC3MqAAqHwxeTLhDbhF43A4ss4ciWNmCY9zQGvAKUSqVUf2vPHBTSE1rB2pg4avopqSiLVzXEU8KziNnVPauTqLRo",
+        ),
+        (
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+            "xprv9s21ZrQH143K2WFF16X85T2QCpndrGwx6GueB72Zf3AHwHJaknRXNF37ZmDrtHrrLSHvbuRejXcnYxoZKvRquTPyp2JiNG3XcjQyzSEgqCB",
+        ),
+    ];
+
+    const ABANDON_ABOUT: &str = VECTORS[0].0;
+
+    #[test]
+    fn test_bip39_vectors() {
+        for (words, seed, xprv) in VECTORS {
+            let master = from_mnemonic(words, "TREZOR", Network::Mainnet).unwrap();
+            assert_eq!(master.xpriv().unwrap().to_string(), xprv, "{}", words);
+
+            let expected = SecretMaterial::from_seed(&hex::decode(seed).unwrap(), bitcoin::Network::Bitcoin).unwrap();
+            assert_eq!(master.xpriv().unwrap(), expected.xpriv().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_passphrase_changes_master_key() {
+        let without = from_mnemonic(ABANDON_ABOUT, "", Network::Mainnet).unwrap();
+        assert_eq!(
+            without.xpriv().unwrap().to_string(),
+            "xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu"
+        );
+        let with = from_mnemonic(ABANDON_ABOUT, "TREZOR", Network::Mainnet).unwrap();
+        assert_ne!(with.fingerprint(), without.fingerprint());
+
+        // Extra whitespace between words doesn't change the seed
+        let spaced = from_mnemonic(&format!("  {}\n", ABANDON_ABOUT.replace(' ', "\t ")), "", Network::Mainnet).unwrap();
+        assert_eq!(spaced.fingerprint(), without.fingerprint());
+    }
+
+    #[test]
+    fn test_mnemonic_errors() {
+        let cases = [
+            (ABANDON_ABOUT.replace("about", "abuot"), MnemonicError::UnknownWord { word_index: 11 }),
+            ("abandon ".repeat(12), MnemonicError::BadChecksum),
+            (ABANDON_ABOUT.replacen("abandon ", "", 1), MnemonicError::BadWordCount { word_count: 11 }),
+        ];
+        for (words, expected) in cases {
+            match from_mnemonic(&words, "", Network::Mainnet) {
+                Err(CoreError::InvalidMnemonic(e)) => assert_eq!(e, expected),
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        }
+
+        let err = from_mnemonic(&ABANDON_ABOUT.replace("about", "abuot"), "", Network::Mainnet).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid mnemonic: word 12 is not in the English wordlist");
+        assert_eq!(err.code(), 1004);
+        assert_eq!(err.details(), serde_json::json!({"reason": "unknown_word", "word_index": 11}));
+    }
+
+    #[test]
+    fn test_account_xpriv_bip86_vector() {
+        // BIP86 test vector: account 0 of "abandon ... about"
+        let master = from_mnemonic(ABANDON_ABOUT, "", Network::Mainnet).unwrap();
+        let account = account_xpriv(&master, TAPROOT_PURPOSE, 0, Network::Mainnet).unwrap();
+        let secp = Secp256k1::new();
+        let xpub = ExtendedPubKey::from_priv(&secp, &account.xpriv().unwrap());
+        assert_eq!(
+            account.xpriv().unwrap().to_string(),
+            "xprv9xgqHN7yz9MwCkxsBPN5qetuNdQSUttZNKw1dcYTV4mkaAFiBVGQziHs3NRSWMkCzvgjEe3n9xV8oYywvM8at9yRqyaZVz6TYYhX98VjsUk"
+        );
+        assert_eq!(
+            xpub.to_string(),
+            "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ"
+        );
+        assert_eq!(xpub.depth, 3);
+    }
+
+    #[test]
+    fn test_account_xpriv_errors() {
+        let master = from_mnemonic(ABANDON_ABOUT, "", Network::Mainnet).unwrap();
+
+        assert!(matches!(
+            account_xpriv(&master, TAPROOT_PURPOSE, 0, Network::Regtest),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+        assert!(matches!(
+            account_xpriv(&master, TAPROOT_PURPOSE, 1 << 31, Network::Mainnet),
+            Err(CoreError::InvalidInput(_))
+        ));
+        let account = account_xpriv(&master, TAPROOT_PURPOSE, 0, Network::Mainnet).unwrap();
+        assert!(matches!(
+            account_xpriv(&account, TAPROOT_PURPOSE, 0, Network::Mainnet),
+            Err(CoreError::InvalidInput(_))
+        ));
+        let single = SecretMaterial::from_secret_key(&master.secret_key());
+        assert!(matches!(
+            account_xpriv(&single, TAPROOT_PURPOSE, 0, Network::Mainnet),
+            Err(CoreError::InvalidInput(_))
+        ));
+
+        // Test networks share coin type 1
+        let testnet = from_mnemonic(ABANDON_ABOUT, "", Network::Testnet4).unwrap();
+        let account = account_xpriv(&testnet, TAPROOT_PURPOSE, 0, Network::Testnet4).unwrap();
+        assert!(account.xpriv().unwrap().to_string().starts_with("tprv"));
+    }
+}
@@ -1,10 +1,27 @@
-use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
-use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::base58;
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
+use bitcoin::key::TapTweak;
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{schnorr, Message, Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::taproot::TapNodeHash;
+use bitcoin::{taproot, TxOut};
 use serde::{Deserialize, Serialize};
 
-use crate::error::CoreError;
+use crate::error::{CoreError, CoreResult};
 use crate::vault::Network;
 
+mod bip85;
+mod expression;
+mod mnemonic;
+pub mod musig;
+mod secret;
+
+pub use bip85::{bip85_mnemonic, bip85_recovery_xpub};
+pub use expression::{parse_key_expression, KeyExpression, Wildcard};
+pub use mnemonic::{account_path, account_xpriv, from_mnemonic, TAPROOT_PURPOSE};
+pub use secret::{wipe_string, SecretMaterial};
+
 /// Validated xpub information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct XpubInfo {
@@ -18,32 +35,144 @@ pub struct XpubInfo {
     pub supports_taproot: bool,
 }
 
-/// Validate an xpub string and extract info
-pub fn validate_xpub(xpub_str: &str, network: Network) -> Result<XpubInfo, CoreError> {
-    let xpub = xpub_str.parse::<ExtendedPubKey>()
+/// Structural details of a parsed xpub, for display by host apps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XpubDetails {
+    /// The original xpub string
+    pub xpub: String,
+    /// Network this xpub was validated against
+    pub network: String,
+    /// Fingerprint of this key (first 4 bytes of hash160)
+    pub fingerprint: String,
+    /// Fingerprint of the parent key
+    pub parent_fingerprint: String,
+    /// Depth in the BIP32 tree (0 = master)
+    pub depth: u8,
+    /// Child index this key was derived at (without the hardened bit)
+    pub child_number: u32,
+    /// Whether the child index is hardened
+    pub hardened: bool,
+}
+
+/// SLIP-132 version bytes and the canonical BIP32 version they map to
+///
+/// (version, canonical version, network of the key)
+const SLIP132_VERSIONS: &[([u8; 4], [u8; 4], Network)] = &[
+    // xpub / ypub / zpub / Ypub / Zpub
+    ([0x04, 0x88, 0xb2, 0x1e], [0x04, 0x88, 0xb2, 0x1e], Network::Mainnet),
+    ([0x04, 0x9d, 0x7c, 0xb2], [0x04, 0x88, 0xb2, 0x1e], Network::Mainnet),
+    ([0x04, 0xb2, 0x47, 0x46], [0x04, 0x88, 0xb2, 0x1e], Network::Mainnet),
+    ([0x02, 0x95, 0xb4, 0x3f], [0x04, 0x88, 0xb2, 0x1e], Network::Mainnet),
+    ([0x02, 0xaa, 0x7e, 0xd3], [0x04, 0x88, 0xb2, 0x1e], Network::Mainnet),
+    // tpub / upub / vpub / Upub / Vpub
+    ([0x04, 0x35, 0x87, 0xcf], [0x04, 0x35, 0x87, 0xcf], Network::Testnet),
+    ([0x04, 0x4a, 0x52, 0x62], [0x04, 0x35, 0x87, 0xcf], Network::Testnet),
+    ([0x04, 0x5f, 0x1c, 0xf6], [0x04, 0x35, 0x87, 0xcf], Network::Testnet),
+    ([0x02, 0x42, 0x89, 0xef], [0x04, 0x35, 0x87, 0xcf], Network::Testnet),
+    ([0x02, 0x57, 0x54, 0x83], [0x04, 0x35, 0x87, 0xcf], Network::Testnet),
+];
+
+/// Parse an extended public key with any SLIP-132 prefix
+///
+/// Accepts xpub/ypub/zpub/Ypub/Zpub (mainnet) and tpub/upub/vpub/Upub/Vpub
+/// (testnet), rewrites the version bytes to canonical xpub/tpub, and
+/// returns the key together with the network its prefix belongs to.
+/// Testnet prefixes are shared by signet and regtest, so they are
+/// reported as `Network::Testnet`.
+pub fn normalize_extended_key(key_str: &str) -> Result<(ExtendedPubKey, Network), CoreError> {
+    let mut data = base58::decode_check(key_str)
+        .map_err(|e| CoreError::InvalidXpub(format!("Failed to parse xpub: {}", e)))?;
+
+    if data.len() != 78 {
+        return Err(CoreError::InvalidXpub(format!(
+            "Invalid extended key length: {} bytes",
+            data.len()
+        )));
+    }
+
+    let (canonical, network) = SLIP132_VERSIONS
+        .iter()
+        .find(|(version, _, _)| data[0..4] == version[..])
+        .map(|(_, canonical, network)| (*canonical, *network))
+        .ok_or_else(|| {
+            CoreError::InvalidXpub(format!(
+                "Unknown extended key version bytes: {}",
+                hex::encode(&data[0..4])
+            ))
+        })?;
+    data[0..4].copy_from_slice(&canonical);
+
+    let xpub = ExtendedPubKey::decode(&data)
         .map_err(|e| CoreError::InvalidXpub(format!("Failed to parse xpub: {}", e)))?;
 
-    let btc_network: bitcoin::Network = network.into();
+    Ok((xpub, network))
+}
+
+/// Parse an xpub string and check its version bytes against the network
+///
+/// Mainnet expects `xpub` version bytes; testnet, signet and regtest
+/// all share the `tpub` version bytes. SLIP-132 prefixes are accepted
+/// and normalized (see [`normalize_extended_key`]). A key origin prefix
+/// is checked and dropped (see [`parse_xpub_with_origin`]).
+pub fn parse_xpub(xpub_str: &str, network: Network) -> Result<ExtendedPubKey, CoreError> {
+    parse_xpub_with_origin(xpub_str, network).map(|(xpub, _)| xpub)
+}
+
+/// Parse an account xpub with an optional `[fingerprint/path]` key
+/// origin, as in `[73c5da0a/86h/1h/0h]tpub...`
+///
+/// Any key expression describing vault keys is accepted: the account
+/// xpub, `xpub/0/*` or `xpub/<0;1>/*` (see [`KeyExpression::vault_account`]).
+/// Without an origin, the origin is the xpub itself (its fingerprint,
+/// empty path), and vault PSBTs list keys relative to the account xpub.
+pub fn parse_xpub_with_origin(key_str: &str, network: Network) -> Result<(ExtendedPubKey, KeySource), CoreError> {
+    parse_key_expression(key_str)?.vault_account(network)
+}
 
-    // Check network prefix matches
-    // tpub = testnet/signet/regtest, xpub = mainnet
-    let is_testnet_key = xpub_str.starts_with("tpub");
-    let is_mainnet_key = xpub_str.starts_with("xpub");
+/// Check that a key's version bytes (mainnet or not) fit the network
+fn require_key_network(is_mainnet_key: bool, network: Network) -> Result<(), CoreError> {
+    let expected_mainnet = matches!(network, Network::Mainnet);
 
-    let expected_mainnet = matches!(btc_network, bitcoin::Network::Bitcoin);
     if expected_mainnet && !is_mainnet_key {
         return Err(CoreError::NetworkMismatch {
             expected: "mainnet (xpub)".to_string(),
             actual: "testnet key (tpub)".to_string(),
         });
     }
-    if !expected_mainnet && !is_testnet_key {
+    if !expected_mainnet && is_mainnet_key {
         return Err(CoreError::NetworkMismatch {
             expected: "testnet (tpub)".to_string(),
             actual: "mainnet key (xpub)".to_string(),
         });
     }
 
+    Ok(())
+}
+
+/// Parse an xpub for the network and extract its structural details
+pub fn xpub_details(xpub_str: &str, network: Network) -> Result<XpubDetails, CoreError> {
+    let xpub = parse_xpub(xpub_str, network)?;
+
+    let (child_number, hardened) = match xpub.child_number {
+        ChildNumber::Normal { index } => (index, false),
+        ChildNumber::Hardened { index } => (index, true),
+    };
+
+    Ok(XpubDetails {
+        xpub: xpub_str.to_string(),
+        network: format!("{:?}", network),
+        fingerprint: hex::encode(xpub.fingerprint().as_bytes()),
+        parent_fingerprint: hex::encode(xpub.parent_fingerprint.as_bytes()),
+        depth: xpub.depth,
+        child_number,
+        hardened,
+    })
+}
+
+/// Validate an xpub string and extract info
+pub fn validate_xpub(xpub_str: &str, network: Network) -> Result<XpubInfo, CoreError> {
+    let xpub = parse_xpub(xpub_str, network)?;
+
     let fingerprint = hex::encode(xpub.fingerprint().as_bytes());
 
     Ok(XpubInfo {
@@ -60,12 +189,16 @@ pub fn validate_xpub(xpub_str: &str, network: Network) -> Result<XpubInfo, CoreE
 /// Since we receive account-level xpub from hardware wallet,
 /// we derive relative path: m/0/{vault_index}
 pub fn get_derivation_path(vault_index: u32, network: Network) -> String {
-    let coin = match network {
+    // Full absolute path (for display)
+    format!("m/86'/{}'/0'/0/{}", coin_type(network), vault_index)
+}
+
+/// BIP44 coin type: 0 for mainnet, 1 for every test network
+fn coin_type(network: Network) -> u32 {
+    match network {
         Network::Mainnet => 0,
         _ => 1,
-    };
-    // Full absolute path (for display)
-    format!("m/86'/{}'/0'/0/{}", coin, vault_index)
+    }
 }
 
 /// Derive a child x-only public key from an account xpub
@@ -79,8 +212,7 @@ pub fn derive_child_pubkey(
 ) -> Result<XOnlyPublicKey, CoreError> {
     let secp = Secp256k1::new();
 
-    let xpub = xpub_str.parse::<ExtendedPubKey>()
-        .map_err(|e| CoreError::InvalidXpub(format!("Failed to parse xpub: {}", e)))?;
+    let (xpub, _) = normalize_extended_key(xpub_str)?;
 
     // Derive: /0/{vault_index} (non-hardened, relative from account xpub)
     let path = DerivationPath::from(vec![
@@ -95,6 +227,129 @@ pub fn derive_child_pubkey(
     Ok(child_xpub.to_x_only_pub())
 }
 
+/// A vault key derived from an account xpub
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DerivedKey {
+    /// X-only public key used in the vault scripts
+    pub public_key: XOnlyPublicKey,
+    /// Full BIP86 derivation path (m/86'/coin'/0'/0/index)
+    pub path: DerivationPath,
+    /// Fingerprint of the parent of the derived key
+    pub parent_fingerprint: Fingerprint,
+}
+
+/// Derive the key for `vault_index` from an account-level xpub
+///
+/// Derives xpub/0/{vault_index}. Only public derivation is possible
+/// from an xpub, so hardened indices (>= 2^31) are rejected.
+pub fn derive_vault_key(
+    xpub: &ExtendedPubKey,
+    vault_index: u32,
+    network: Network,
+) -> Result<DerivedKey, CoreError> {
+    let secp = Secp256k1::verification_only();
+
+    require_key_network(xpub.network == bitcoin::Network::Bitcoin, network)?;
+
+    let index = ChildNumber::from_normal_idx(vault_index).map_err(|_| {
+        CoreError::DerivationError(format!(
+            "Vault index {} is hardened; only unhardened indices can be derived from an xpub",
+            vault_index
+        ))
+    })?;
+
+    let child_xpub = xpub
+        .derive_pub(&secp, &[ChildNumber::Normal { index: 0 }, index])
+        .map_err(|e| CoreError::DerivationError(format!("Child derivation failed: {}", e)))?;
+    log::debug!("Derived vault key {} at index {}", child_xpub.to_x_only_pub(), vault_index);
+
+    Ok(DerivedKey {
+        public_key: child_xpub.to_x_only_pub(),
+        path: vault_derivation_path(vault_index, network),
+        parent_fingerprint: child_xpub.parent_fingerprint,
+    })
+}
+
+/// Path of a vault key relative to its account xpub (0/index)
+pub fn vault_key_relative_path(vault_index: u32) -> DerivationPath {
+    DerivationPath::from(vec![
+        ChildNumber::Normal { index: 0 },
+        ChildNumber::Normal { index: vault_index },
+    ])
+}
+
+/// Receive branch (`xpub/0`) of an account xpub, derived once
+///
+/// Deriving many vault keys from the branch costs one child derivation
+/// per index instead of two.
+#[derive(Debug, Clone)]
+pub struct ReceiveBranch {
+    /// Origin of the account xpub, the xpub itself unless set by `with_origin()`
+    origin: KeySource,
+    branch: ExtendedPubKey,
+}
+
+impl ReceiveBranch {
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        xpub: &ExtendedPubKey,
+        network: Network,
+    ) -> Result<Self, CoreError> {
+        require_key_network(xpub.network == bitcoin::Network::Bitcoin, network)?;
+
+        let branch = xpub
+            .ckd_pub(secp, ChildNumber::Normal { index: 0 })
+            .map_err(|e| CoreError::DerivationError(format!("Child derivation failed: {}", e)))?;
+
+        Ok(ReceiveBranch {
+            origin: (xpub.fingerprint(), DerivationPath::master()),
+            branch,
+        })
+    }
+
+    /// Report key origins from the account xpub's own origin, as given by
+    /// `parse_xpub_with_origin()`
+    pub fn with_origin(mut self, origin: KeySource) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Vault key at `vault_index`, same as `derive_vault_key()`
+    pub fn derive<C: Verification>(&self, secp: &Secp256k1<C>, vault_index: u32) -> Result<XOnlyPublicKey, CoreError> {
+        let index = ChildNumber::from_normal_idx(vault_index).map_err(|_| {
+            CoreError::DerivationError(format!(
+                "Vault index {} is hardened; only unhardened indices can be derived from an xpub",
+                vault_index
+            ))
+        })?;
+
+        let child_xpub = self
+            .branch
+            .ckd_pub(secp, index)
+            .map_err(|e| CoreError::DerivationError(format!("Child derivation failed: {}", e)))?;
+
+        Ok(child_xpub.to_x_only_pub())
+    }
+
+    /// Origin of the key at `vault_index`: the account xpub's origin
+    /// followed by `0/index`
+    pub fn key_origin(&self, vault_index: u32) -> KeySource {
+        let (fingerprint, path) = &self.origin;
+        (*fingerprint, path.extend(vault_key_relative_path(vault_index)))
+    }
+}
+
+/// Full BIP86 derivation path for a vault index (account 0, receive chain)
+fn vault_derivation_path(vault_index: u32, network: Network) -> DerivationPath {
+    DerivationPath::from(vec![
+        ChildNumber::Hardened { index: 86 },
+        ChildNumber::Hardened { index: coin_type(network) },
+        ChildNumber::Hardened { index: 0 },
+        ChildNumber::Normal { index: 0 },
+        ChildNumber::Normal { index: vault_index },
+    ])
+}
+
 /// Create a provably unspendable internal key (NUMS point)
 ///
 /// Used when no emergency device is configured.
@@ -128,19 +383,168 @@ pub fn unspendable_internal_key() -> XOnlyPublicKey {
     }
 }
 
+/// Extended key over the BIP341 NUMS point H, for per-vault unspendable keys
+///
+/// The public key is H (even y) and the chain code is SHA256(H), so
+/// nothing is hidden in either. Children are H + t·G for a public tweak
+/// t, whose discrete log is as unknown as H's; every vault index gets a
+/// different unspendable key, and the key is expressible in a ranged
+/// descriptor as `xpub/0/*`.
+pub fn nums_xpub(network: Network) -> ExtendedPubKey {
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1::Parity;
+
+    let h = unspendable_internal_key();
+    ExtendedPubKey {
+        network: network.into(),
+        depth: 0,
+        parent_fingerprint: Fingerprint::default(),
+        child_number: ChildNumber::Normal { index: 0 },
+        public_key: h.public_key(Parity::Even),
+        chain_code: sha256::Hash::hash(&h.serialize()).to_byte_array().into(),
+    }
+}
+
+/// Key pair for a BIP341 key-path spend: the child of `xpriv` at `path`,
+/// tweaked by `merkle_root`
+///
+/// The public key of the result is the output key of a P2TR output with
+/// the child key as internal key and `merkle_root` as script tree root
+/// (`None` for an output without scripts).
+pub fn tweaked_keypair(
+    xpriv: &SecretMaterial,
+    path: &DerivationPath,
+    merkle_root: Option<TapNodeHash>,
+) -> Result<SecretMaterial, CoreError> {
+    let secp = Secp256k1::new();
+    let child = derive_secret(&secp, xpriv, path)?;
+    let tweaked = child.keypair().tap_tweak(&secp, merkle_root).to_inner();
+    Ok(SecretMaterial::from_secret_key(&tweaked.secret_key()))
+}
+
+/// Child of `xpriv` at `path`
+fn derive_secret<C: bitcoin::secp256k1::Signing>(
+    secp: &Secp256k1<C>,
+    xpriv: &SecretMaterial,
+    path: &DerivationPath,
+) -> Result<SecretMaterial, CoreError> {
+    let child = xpriv
+        .xpriv()?
+        .derive_priv(secp, path)
+        .map_err(|e| CoreError::DerivationError(format!("Child derivation failed: {}", e)))?;
+    Ok(SecretMaterial::from_xpriv(&child))
+}
+
+/// Sign the vault inputs of a PSBT with an extended private key
+///
+/// For every input, each `tap_key_origins` entry whose fingerprint
+/// matches `xpriv` is derived along its path. If the derived key is the
+/// one recorded in the PSBT, a BIP341 script-path signature is added for
+/// each leaf hash the key is listed under. Inputs without matching
+/// origins are skipped.
+///
+/// The inputs' `witness_utxo` data is taken as given; use
+/// `vault::psbt::sign_verified()` to check it against the vault first.
+///
+/// Returns the number of signatures added. Errors with `SigningError`
+/// if `xpriv` does not sign for any input.
+pub fn sign_psbt(psbt: &mut Psbt, xpriv: &SecretMaterial, network: Network) -> Result<usize, CoreError> {
+    let secp = Secp256k1::new();
+
+    require_key_network(xpriv.xpriv()?.network == bitcoin::Network::Bitcoin, network)?;
+
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                CoreError::PsbtError(format!("Input {} is missing its witness UTXO", i))
+            })
+        })
+        .collect::<Result<Vec<TxOut>, CoreError>>()?;
+    let prevouts = Prevouts::All(&prevouts);
+
+    let fingerprint = xpriv.fingerprint();
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut signed = 0;
+
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        let hash_ty = input
+            .taproot_hash_ty()
+            .map_err(|e| CoreError::PsbtError(format!("Input {} has an invalid sighash type: {}", i, e)))?;
+
+        for (key, (leaf_hashes, (key_fingerprint, path))) in &input.tap_key_origins {
+            if *key_fingerprint != fingerprint {
+                continue;
+            }
+
+            let keypair = derive_secret(&secp, xpriv, path)?.keypair();
+            if keypair.x_only_public_key().0 != *key {
+                log::warn!(
+                    "Input {} lists key {} under fingerprint {} at {}, but it derives another key",
+                    i, key, fingerprint, path
+                );
+                continue;
+            }
+
+            for leaf_hash in leaf_hashes {
+                let sighash = cache
+                    .taproot_script_spend_signature_hash(i, &prevouts, *leaf_hash, hash_ty)
+                    .map_err(|e| CoreError::SigningError {
+                        input_index: i,
+                        reason: format!("Sighash failed: {}", e),
+                    })?;
+                let msg = Message::from_slice(sighash.as_ref())
+                    .map_err(|e| CoreError::SigningError {
+                        input_index: i,
+                        reason: format!("Invalid sighash: {}", e),
+                    })?;
+                let sig = secp.sign_schnorr(&msg, &keypair);
+
+                input
+                    .tap_script_sigs
+                    .insert((*key, *leaf_hash), taproot::Signature { sig, hash_ty });
+                signed += 1;
+            }
+        }
+    }
+
+    if signed == 0 {
+        return Err(CoreError::SigningError {
+            input_index: 0,
+            reason: format!("Key {} does not sign for any input", fingerprint),
+        });
+    }
+
+    log::debug!("Added {} signature(s) with key {}", signed, fingerprint);
+    Ok(signed)
+}
+
+/// Check a BIP340 signature over a 32-byte message
+///
+/// Returns `Ok(false)` for a signature that doesn't verify, including
+/// one whose `r` or `s` is out of range.
+pub fn verify_schnorr(msg32: &[u8; 32], sig: &[u8; 64], pubkey: &XOnlyPublicKey) -> CoreResult<bool> {
+    let msg = Message::from_slice(msg32)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid message: {}", e)))?;
+    let sig = schnorr::Signature::from_slice(sig)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid signature: {}", e)))?;
+    Ok(Secp256k1::verification_only().verify_schnorr(&sig, &msg, pubkey).is_ok())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    // BIP32 test vector 1: master public key (mainnet)
-    const TEST_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    use crate::test_common::{OWNER_TPUB, OWNER_XPUB};
+    use std::str::FromStr;
 
     #[test]
     fn test_validate_xpub_success() {
-        let result = validate_xpub(TEST_XPUB, Network::Mainnet);
+        let result = validate_xpub(OWNER_XPUB, Network::Mainnet);
         assert!(result.is_ok());
         let info = result.unwrap();
-        assert_eq!(info.xpub, TEST_XPUB);
+        assert_eq!(info.xpub, OWNER_XPUB);
         assert_eq!(info.fingerprint, "3442193e");
         assert!(info.supports_taproot);
     }
@@ -148,7 +552,7 @@ mod tests {
     #[test]
     fn test_validate_xpub_network_mismatch() {
         // xpub (mainnet key) on testnet should fail
-        let result = validate_xpub(TEST_XPUB, Network::Testnet);
+        let result = validate_xpub(OWNER_XPUB, Network::Testnet);
         assert!(result.is_err());
         match result.unwrap_err() {
             CoreError::NetworkMismatch { .. } => {}
@@ -162,6 +566,169 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_xpub_testnet_networks() {
+        // tpub version bytes are shared by testnet, signet and regtest
+        assert!(parse_xpub(OWNER_TPUB, Network::Testnet).is_ok());
+        assert!(parse_xpub(OWNER_TPUB, Network::Signet).is_ok());
+        assert!(parse_xpub(OWNER_TPUB, Network::Regtest).is_ok());
+        match parse_xpub(OWNER_TPUB, Network::Mainnet).unwrap_err() {
+            CoreError::NetworkMismatch { .. } => {}
+            other => panic!("Expected NetworkMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xpub_details_master_key() {
+        let details = xpub_details(OWNER_XPUB, Network::Mainnet).unwrap();
+        assert_eq!(details.fingerprint, "3442193e");
+        assert_eq!(details.parent_fingerprint, "00000000");
+        assert_eq!(details.depth, 0);
+        assert_eq!(details.child_number, 0);
+        assert!(!details.hardened);
+    }
+
+    /// Re-encode a canonical key string with different SLIP-132 version bytes
+    fn with_version(key: &str, version: [u8; 4]) -> String {
+        let mut data = base58::decode_check(key).unwrap();
+        data[0..4].copy_from_slice(&version);
+        base58::encode_check(&data)
+    }
+
+    #[test]
+    fn test_normalize_slip132_mainnet_prefixes() {
+        for (version, prefix) in [
+            ([0x04, 0x9d, 0x7c, 0xb2], "ypub"),
+            ([0x04, 0xb2, 0x47, 0x46], "zpub"),
+            ([0x02, 0x95, 0xb4, 0x3f], "Ypub"),
+            ([0x02, 0xaa, 0x7e, 0xd3], "Zpub"),
+        ] {
+            let slip132 = with_version(OWNER_XPUB, version);
+            assert!(slip132.starts_with(prefix), "{} -> {}", prefix, slip132);
+
+            let (xpub, network) = normalize_extended_key(&slip132).unwrap();
+            assert_eq!(xpub.to_string(), OWNER_XPUB);
+            assert!(matches!(network, Network::Mainnet));
+        }
+    }
+
+    #[test]
+    fn test_normalize_slip132_testnet_prefixes() {
+        for (version, prefix) in [
+            ([0x04, 0x4a, 0x52, 0x62], "upub"),
+            ([0x04, 0x5f, 0x1c, 0xf6], "vpub"),
+            ([0x02, 0x42, 0x89, 0xef], "Upub"),
+            ([0x02, 0x57, 0x54, 0x83], "Vpub"),
+        ] {
+            let slip132 = with_version(OWNER_TPUB, version);
+            assert!(slip132.starts_with(prefix), "{} -> {}", prefix, slip132);
+
+            let (xpub, network) = normalize_extended_key(&slip132).unwrap();
+            assert_eq!(xpub.to_string(), OWNER_TPUB);
+            assert!(matches!(network, Network::Testnet));
+        }
+    }
+
+    #[test]
+    fn test_normalize_canonical_keys_unchanged() {
+        let (xpub, network) = normalize_extended_key(OWNER_XPUB).unwrap();
+        assert_eq!(xpub.to_string(), OWNER_XPUB);
+        assert!(matches!(network, Network::Mainnet));
+
+        let (tpub, network) = normalize_extended_key(OWNER_TPUB).unwrap();
+        assert_eq!(tpub.to_string(), OWNER_TPUB);
+        assert!(matches!(network, Network::Testnet));
+    }
+
+    #[test]
+    fn test_normalize_unknown_version_bytes() {
+        let unknown = with_version(OWNER_XPUB, [0xde, 0xad, 0xbe, 0xef]);
+        match normalize_extended_key(&unknown).unwrap_err() {
+            CoreError::InvalidXpub(msg) => assert!(msg.contains("deadbeef"), "{}", msg),
+            other => panic!("Expected InvalidXpub, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slip132_keys_accepted_by_vault_paths() {
+        let zpub = with_version(OWNER_XPUB, [0x04, 0xb2, 0x47, 0x46]);
+        assert!(parse_xpub(&zpub, Network::Mainnet).is_ok());
+        assert_eq!(
+            derive_child_pubkey(&zpub, 3, Network::Mainnet).unwrap(),
+            derive_child_pubkey(OWNER_XPUB, 3, Network::Mainnet).unwrap()
+        );
+
+        let vpub = with_version(OWNER_TPUB, [0x04, 0x5f, 0x1c, 0xf6]);
+        assert!(parse_xpub(&vpub, Network::Signet).is_ok());
+        assert!(parse_xpub(&vpub, Network::Mainnet).is_err());
+    }
+
+    // BIP86 test vector: account 0 of "abandon ... about", master fingerprint 73c5da0a
+    const BIP86_ACCOUNT_XPUB: &str = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+
+    #[test]
+    fn test_parse_xpub_with_origin() {
+        let account = parse_xpub(BIP86_ACCOUNT_XPUB, Network::Mainnet).unwrap();
+        for origin in ["[73c5da0a/86h/0h/0h]", "[73c5da0a/86'/0'/0']", "[73C5DA0A/86h/0'/0h]"] {
+            let (xpub, (fingerprint, path)) =
+                parse_xpub_with_origin(&format!("{}{}", origin, BIP86_ACCOUNT_XPUB), Network::Mainnet).unwrap();
+            assert_eq!(xpub, account);
+            assert_eq!(fingerprint.to_string(), "73c5da0a");
+            assert_eq!(path.to_string(), "m/86'/0'/0'");
+        }
+
+        // Without an origin the xpub is its own origin
+        let (_, origin) = parse_xpub_with_origin(BIP86_ACCOUNT_XPUB, Network::Mainnet).unwrap();
+        assert_eq!(origin, (account.fingerprint(), DerivationPath::master()));
+        let (_, origin) = parse_xpub_with_origin(&format!("[3442193e]{}", OWNER_XPUB), Network::Mainnet).unwrap();
+        assert_eq!(origin, (Fingerprint::from([0x34, 0x42, 0x19, 0x3e]), DerivationPath::master()));
+
+        // The origin doesn't change what `parse_xpub` returns
+        let with_origin = format!("[73c5da0a/86h/0h/0h]{}", BIP86_ACCOUNT_XPUB);
+        assert_eq!(parse_xpub(&with_origin, Network::Mainnet).unwrap(), account);
+        assert!(matches!(parse_xpub(&with_origin, Network::Testnet), Err(CoreError::NetworkMismatch { .. })));
+    }
+
+    #[test]
+    fn test_parse_xpub_with_origin_errors() {
+        let cases = [
+            ("[73c5da0a/86h/0h/0h", "closing ']'"),
+            ("[73c5da0/86h/0h/0h]", "'73c5da0' must be 8 hex characters"),
+            ("[73c5da0g/86h/0h/0h]", "'73c5da0g' is not hex"),
+            ("[73c5da0a/86h/0x/0h]", "Invalid key origin path '86h/0x/0h'"),
+            ("[73c5da0a/86h/0h]", "has 2 steps, but the xpub is at depth 3"),
+            ("[73c5da0a/86h/0h/1h]", "doesn't lead to this xpub"),
+            ("[73c5da0a/86h/0h/0]", "doesn't lead to this xpub"),
+        ];
+        for (origin, expected) in cases {
+            match parse_xpub_with_origin(&format!("{}{}", origin, BIP86_ACCOUNT_XPUB), Network::Mainnet) {
+                Err(CoreError::InvalidXpub(msg)) => assert!(msg.contains(expected), "{}: {}", origin, msg),
+                other => panic!("{}: expected InvalidXpub, got {:?}", origin, other),
+            }
+        }
+
+        // A master key's origin must be its own fingerprint
+        assert!(matches!(
+            parse_xpub_with_origin(&format!("[73c5da0a]{}", OWNER_XPUB), Network::Mainnet),
+            Err(CoreError::InvalidXpub(_))
+        ));
+    }
+
+    #[test]
+    fn test_receive_branch_key_origin() {
+        let secp = Secp256k1::verification_only();
+        let (xpub, origin) =
+            parse_xpub_with_origin(&format!("[73c5da0a/86h/0h/0h]{}", BIP86_ACCOUNT_XPUB), Network::Mainnet).unwrap();
+
+        let branch = ReceiveBranch::new(&secp, &xpub, Network::Mainnet).unwrap();
+        assert_eq!(branch.key_origin(4), (xpub.fingerprint(), DerivationPath::from_str("m/0/4").unwrap()));
+
+        let branch = branch.with_origin(origin);
+        let (fingerprint, path) = branch.key_origin(4);
+        assert_eq!(fingerprint.to_string(), "73c5da0a");
+        assert_eq!(path.to_string(), "m/86'/0'/0'/0/4");
+    }
+
     #[test]
     fn test_get_derivation_path() {
         assert_eq!(get_derivation_path(0, Network::Mainnet), "m/86'/0'/0'/0/0");
@@ -171,7 +738,7 @@ mod tests {
 
     #[test]
     fn test_derive_child_pubkey() {
-        let result = derive_child_pubkey(TEST_XPUB, 0, Network::Mainnet);
+        let result = derive_child_pubkey(OWNER_XPUB, 0, Network::Mainnet);
         assert!(result.is_ok());
         let key = result.unwrap();
         assert_eq!(key.serialize().len(), 32);
@@ -179,15 +746,201 @@ mod tests {
 
     #[test]
     fn test_derive_child_pubkey_deterministic() {
-        let key1 = derive_child_pubkey(TEST_XPUB, 0, Network::Mainnet).unwrap();
-        let key2 = derive_child_pubkey(TEST_XPUB, 0, Network::Mainnet).unwrap();
+        let key1 = derive_child_pubkey(OWNER_XPUB, 0, Network::Mainnet).unwrap();
+        let key2 = derive_child_pubkey(OWNER_XPUB, 0, Network::Mainnet).unwrap();
         assert_eq!(key1, key2);
 
         // Different index should give different key
-        let key3 = derive_child_pubkey(TEST_XPUB, 1, Network::Mainnet).unwrap();
+        let key3 = derive_child_pubkey(OWNER_XPUB, 1, Network::Mainnet).unwrap();
         assert_ne!(key1, key3);
     }
 
+    #[test]
+    fn test_derive_vault_key_vectors() {
+        let xpub = parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+
+        let key0 = derive_vault_key(&xpub, 0, Network::Mainnet).unwrap();
+        assert_eq!(hex::encode(key0.public_key.serialize()), "756de182c5dd4b717ea87e693006da62dbb3cddaa4a5cad2ed1f5bbab755f0f5");
+        assert_eq!(key0.path.to_string(), "m/86'/0'/0'/0/0");
+        assert_eq!(hex::encode(key0.parent_fingerprint.as_bytes()), "9cc81b61");
+
+        let key7 = derive_vault_key(&xpub, 7, Network::Mainnet).unwrap();
+        assert_eq!(hex::encode(key7.public_key.serialize()), "26abaa1f0174d87f16d63879e70b1425fc220f9d57ab4531c2328953c04260e5");
+        assert_eq!(key7.path.to_string(), "m/86'/0'/0'/0/7");
+        assert_eq!(key7.parent_fingerprint, key0.parent_fingerprint);
+
+        let tpub = parse_xpub(OWNER_TPUB, Network::Signet).unwrap();
+        let tkey = derive_vault_key(&tpub, 0, Network::Signet).unwrap();
+        assert_eq!(tkey.path.to_string(), "m/86'/1'/0'/0/0");
+        // Same key material behind both encodings
+        assert_eq!(tkey.public_key, key0.public_key);
+    }
+
+    #[test]
+    fn test_derive_vault_key_matches_derive_child_pubkey() {
+        let xpub = parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+        for index in [0, 1, 42, 1000] {
+            let derived = derive_vault_key(&xpub, index, Network::Mainnet).unwrap();
+            let legacy = derive_child_pubkey(OWNER_XPUB, index, Network::Mainnet).unwrap();
+            assert_eq!(derived.public_key, legacy);
+        }
+    }
+
+    #[test]
+    fn test_derive_vault_key_rejects_hardened_index() {
+        let xpub = parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+        match derive_vault_key(&xpub, 1 << 31, Network::Mainnet).unwrap_err() {
+            CoreError::DerivationError(_) => {}
+            other => panic!("Expected DerivationError, got {:?}", other),
+        }
+        assert!(derive_vault_key(&xpub, (1 << 31) - 1, Network::Mainnet).is_ok());
+    }
+
+    #[test]
+    fn test_derive_vault_key_network_mismatch() {
+        let xpub = parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+        assert!(matches!(
+            derive_vault_key(&xpub, 0, Network::Testnet),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_tweaked_keypair_matches_output_key() {
+        let secp = Secp256k1::new();
+        let xpriv = bitcoin::bip32::ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[7; 32]).unwrap();
+        let path = vault_key_relative_path(3);
+        let internal_key = ExtendedPubKey::from_priv(&secp, &xpriv)
+            .derive_pub(&secp, &path)
+            .unwrap()
+            .to_x_only_pub();
+
+        for merkle_root in [None, Some(TapNodeHash::assume_hidden([9; 32]))] {
+            let keypair = tweaked_keypair(&xpriv.into(), &path, merkle_root).unwrap().keypair();
+            let (output_key, _) = internal_key.tap_tweak(&secp, merkle_root);
+            assert_eq!(keypair.x_only_public_key().0, output_key.to_inner());
+        }
+    }
+
+    // BIP340 test vectors 0-4 and 6-13: (pubkey, message, signature, valid)
+    const BIP340_VECTORS: [(&str, &str, &str, bool); 13] = [
+        // 0
+        (
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+            true,
+        ),
+        // 1
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A",
+            true,
+        ),
+        // 2
+        (
+            "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+            "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7",
+            true,
+        ),
+        // 3
+        (
+            "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3",
+            true,
+        ),
+        // 4
+        (
+            "D69C3509BB99E412E68B0FE8544E72837DFA30746D8BE2AA65975F29D22DC7B9",
+            "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+            "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C6376AFB1548AF603B3EB45C9F8207DEE1060CB71C04E80F593060B07D28308D7F4",
+            true,
+        ),
+        // 6
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A14602975563CC27944640AC607CD107AE10923D9EF7A73C643E166BE5EBEAFA34B1AC553E2",
+            false,
+        ),
+        // 7
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "1FA62E331EDBC21C394792D2AB1100A7B432B013DF3F6FF4F99FCB33E0E1515F28890B3EDB6E7189B630448B515CE4F8622A954CFE545735AAEA5134FCCDB2BD",
+            false,
+        ),
+        // 8
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769961764B3AA9B2FFCB6EF947B6887A226E8D7C93E00C5ED0C1834FF0D0C2E6DA6",
+            false,
+        ),
+        // 9
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "0000000000000000000000000000000000000000000000000000000000000000123DDA8328AF9C23A94C1FEECFD123BA4FB73476F0D594DCB65C6425BD186051",
+            false,
+        ),
+        // 10
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "00000000000000000000000000000000000000000000000000000000000000017615FBAF5AE28864013C099742DEADB4DBA87F11AC6754F93780D5A1837CF197",
+            false,
+        ),
+        // 11
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "4A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false,
+        ),
+        // 12
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F69E89B4C5564D00349106B8497785DD7D1D713A8AE82B32FA79D5F7FC407D39B",
+            false,
+        ),
+        // 13
+        (
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6CFF5C3BA86C69EA4B7376F31A9BCB4F74C1976089B2D9963DA2E5543E177769FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
+            false,
+        ),
+    ];
+
+    fn hex_array<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_verify_schnorr_bip340_vectors() {
+        for (pubkey, msg, sig, valid) in BIP340_VECTORS {
+            let pubkey = XOnlyPublicKey::from_slice(&hex_array::<32>(pubkey)).unwrap();
+            let result = verify_schnorr(&hex_array(msg), &hex_array(sig), &pubkey).unwrap();
+            assert_eq!(result, valid, "signature {}", sig);
+        }
+    }
+
+    #[test]
+    fn test_bip340_invalid_pubkeys_rejected() {
+        // Vector 5: not on the curve; vector 14: exceeds the field size
+        for pubkey in [
+            "EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC30",
+        ] {
+            assert!(XOnlyPublicKey::from_slice(&hex_array::<32>(pubkey)).is_err());
+        }
+    }
+
     #[test]
     fn test_unspendable_internal_key() {
         let key = unspendable_internal_key();
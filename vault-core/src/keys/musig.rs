@@ -0,0 +1,706 @@
+//! MuSig2 (BIP327) two-round multi-signatures for an aggregated internal key
+//!
+//! Lets n signers, such as the owner and a cosigner, share one taproot
+//! internal key and spend through the key path with a single Schnorr
+//! signature. secp256k1 0.27 has no MuSig2 module, so the scheme is
+//! built here on its point and scalar operations, following BIP327 with
+//! participant keys as 33-byte compressed points. The tests run the
+//! BIP327 key aggregation and signing vectors.
+//!
+//! Signing takes two rounds: each signer publishes a `PublicNonce` from
+//! `generate_nonce()`, then a `PartialSignature` from `partial_sign()`
+//! once it has everyone's nonce; anyone holding all of them can
+//! `aggregate_partial_sigs()`. Two signatures with the same nonce reveal
+//! the secret key, so a `SecretNonce` can't be copied or serialized, is
+//! consumed by signing, and only signs the message and aggregate key it
+//! was generated for.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use bitcoin::secp256k1::{schnorr, Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::taproot::{TapNodeHash, TapTweakHash};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Participant keys combined into one key, with any tweaks applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregatedKey {
+    /// Participant keys, in the order they were aggregated
+    keys: Vec<PublicKey>,
+    /// Aggregate point Q, tweaks included
+    point: PublicKey,
+    /// Sign flips of Q from x-only tweaks (BIP327's gacc)
+    gacc: ModN,
+    /// Accumulated tweak (BIP327's tacc)
+    tacc: ModN,
+}
+
+/// Aggregate `keys` into one key, as BIP327 KeyAgg
+///
+/// The order of `keys` matters: every signer must aggregate the same list.
+/// Fails with `InvalidInput` for an empty list.
+pub fn aggregate_keys(keys: &[PublicKey]) -> CoreResult<AggregatedKey> {
+    if keys.is_empty() {
+        return Err(CoreError::InvalidInput("MuSig2 needs at least one key".to_string()));
+    }
+
+    let points = keys
+        .iter()
+        .map(|key| mul_point(*key, key_agg_coeff(keys, key)))
+        .collect::<CoreResult<Vec<_>>>()?;
+    let point = PublicKey::combine_keys(&points.iter().collect::<Vec<_>>())
+        .map_err(|_| CoreError::InvalidInput("MuSig2 keys aggregate to the point at infinity".to_string()))?;
+
+    Ok(AggregatedKey {
+        keys: keys.to_vec(),
+        point,
+        gacc: ModN::one(),
+        tacc: ModN::ZERO,
+    })
+}
+
+impl AggregatedKey {
+    /// The aggregate key as used in taproot: an internal key before
+    /// `with_taproot_tweak()`, the output key after
+    pub fn x_only_public_key(&self) -> XOnlyPublicKey {
+        self.point.x_only_public_key().0
+    }
+
+    /// Participant keys, in aggregation order
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// The key tweaked into the output key of a P2TR output with this
+    /// key as internal key and `merkle_root` as script tree root
+    pub fn with_taproot_tweak(&self, merkle_root: Option<TapNodeHash>) -> CoreResult<AggregatedKey> {
+        let tweak = TapTweakHash::from_key_and_tweak(self.x_only_public_key(), merkle_root).to_byte_array();
+        self.apply_xonly_tweak(ModN::reduce(tweak))
+    }
+
+    /// BIP327 ApplyTweak with `is_xonly_t`
+    fn apply_xonly_tweak(&self, tweak: ModN) -> CoreResult<AggregatedKey> {
+        let secp = Secp256k1::new();
+        let g = if has_even_y(&self.point) { ModN::one() } else { ModN::one().neg() };
+        let flipped = if has_even_y(&self.point) { self.point } else { self.point.negate(&secp) };
+        let point = match tweak.secret_key() {
+            Some(t) => flipped
+                .combine(&PublicKey::from_secret_key(&secp, &t))
+                .map_err(|_| CoreError::InvalidInput("Tweaked MuSig2 key is the point at infinity".to_string()))?,
+            None => flipped,
+        };
+        Ok(AggregatedKey {
+            keys: self.keys.clone(),
+            point,
+            gacc: g.mul(self.gacc),
+            tacc: tweak.add(g.mul(self.tacc)),
+        })
+    }
+}
+
+/// A signer's first-round nonce, kept private until `partial_sign()`
+///
+/// Neither `Clone` nor serializable, so it can only be used once: hosts
+/// behind the FFI never see it (see `vault_musig_nonce()`).
+pub struct SecretNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+    /// Signer's key
+    public_key: PublicKey,
+    /// Aggregate key and message the nonce may sign
+    agg_key: XOnlyPublicKey,
+    msg: [u8; 32],
+}
+
+/// A signer's first-round nonce as shared with the other signers
+///
+/// Serialized as 66 hex-encoded bytes: two compressed points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicNonce {
+    r1: PublicKey,
+    r2: PublicKey,
+}
+
+/// A signer's second-round signature share, serialized as 32 hex-encoded bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialSignature([u8; 32]);
+
+/// Generate a nonce pair for signing `msg` with `secret_key` under `agg_key`
+///
+/// Fresh randomness is mixed with the key, aggregate key and message as
+/// in BIP327 NonceGen, so the nonce is unique even if the random source
+/// repeats. The secret nonce can't sign any other message or key.
+pub fn generate_nonce(
+    secret_key: &SecretKey,
+    agg_key: &AggregatedKey,
+    msg: &[u8; 32],
+) -> CoreResult<(SecretNonce, PublicNonce)> {
+    let secp = Secp256k1::new();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key);
+
+    let mut rand = [0u8; 32];
+    thread_rng().fill_bytes(&mut rand);
+    let aux = tagged_hash("MuSig/aux", &[&rand]);
+    for (byte, (key_byte, aux_byte)) in rand.iter_mut().zip(secret_key.secret_bytes().iter().zip(aux)) {
+        *byte = key_byte ^ aux_byte;
+    }
+
+    let pk = cbytes(&public_key);
+    let aggpk = agg_key.x_only_public_key().serialize();
+    let nonce = |i: u8| -> CoreResult<SecretKey> {
+        let hash = tagged_hash(
+            "MuSig/nonce",
+            &[
+                &rand,
+                &[pk.len() as u8],
+                &pk,
+                &[aggpk.len() as u8],
+                &aggpk,
+                &[1],
+                &(msg.len() as u64).to_be_bytes(),
+                msg,
+                &0u32.to_be_bytes(),
+                &[i],
+            ],
+        );
+        ModN::reduce(hash)
+            .secret_key()
+            .ok_or_else(|| CoreError::Internal("MuSig2 nonce is zero".to_string()))
+    };
+    let (k1, k2) = (nonce(0)?, nonce(1)?);
+
+    let secret_nonce = SecretNonce {
+        k1,
+        k2,
+        public_key,
+        agg_key: agg_key.x_only_public_key(),
+        msg: *msg,
+    };
+    let public_nonce = secret_nonce.public_nonce();
+    Ok((secret_nonce, public_nonce))
+}
+
+/// Second round: sign `msg` under `agg_key` with everyone's `pub_nonces`
+///
+/// `secret_nonce` is consumed; it must come from `generate_nonce()` for
+/// the same key, aggregate key and message, and its public half must be
+/// among `pub_nonces`. Fails with `InvalidInput` if `secret_key` isn't
+/// one of the aggregated keys or the nonce was generated for another
+/// signing session.
+pub fn partial_sign(
+    secret_nonce: SecretNonce,
+    secret_key: &SecretKey,
+    agg_key: &AggregatedKey,
+    pub_nonces: &[PublicNonce],
+    msg: &[u8; 32],
+) -> CoreResult<PartialSignature> {
+    let secp = Secp256k1::new();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key);
+    if public_key != secret_nonce.public_key {
+        return Err(CoreError::InvalidInput("Secret nonce was generated for another key".to_string()));
+    }
+    if secret_nonce.agg_key != agg_key.x_only_public_key() || secret_nonce.msg != *msg {
+        return Err(CoreError::InvalidInput(
+            "Secret nonce was generated for another aggregate key or message".to_string(),
+        ));
+    }
+    if !agg_key.keys.contains(&public_key) {
+        return Err(CoreError::InvalidInput(format!("Key {} is not part of the MuSig2 key", public_key)));
+    }
+    if !pub_nonces.contains(&secret_nonce.public_nonce()) {
+        return Err(CoreError::InvalidInput("Public nonces do not include this signer's nonce".to_string()));
+    }
+
+    let session = Session::new(agg_key, pub_nonces, msg)?;
+    let (k1, k2) = if has_even_y(&session.r) {
+        (secret_nonce.k1, secret_nonce.k2)
+    } else {
+        (secret_nonce.k1.negate(), secret_nonce.k2.negate())
+    };
+    let g = if has_even_y(&agg_key.point) { ModN::one() } else { ModN::one().neg() };
+    let d = g.mul(agg_key.gacc).mul(ModN::from(*secret_key));
+    let a = key_agg_coeff(&agg_key.keys, &public_key);
+
+    let s = ModN::from(k1)
+        .add(session.b.mul(ModN::from(k2)))
+        .add(session.e.mul(a).mul(d));
+    Ok(PartialSignature(s.to_bytes()))
+}
+
+/// Combine every signer's partial signature into a BIP340 signature
+///
+/// The result is checked against the aggregate key, so a missing or
+/// bad share fails with `InvalidInput` rather than producing an invalid
+/// signature.
+pub fn aggregate_partial_sigs(
+    agg_key: &AggregatedKey,
+    pub_nonces: &[PublicNonce],
+    msg: &[u8; 32],
+    partial_sigs: &[PartialSignature],
+) -> CoreResult<schnorr::Signature> {
+    let session = Session::new(agg_key, pub_nonces, msg)?;
+    let g = if has_even_y(&agg_key.point) { ModN::one() } else { ModN::one().neg() };
+    let mut s = session.e.mul(g).mul(agg_key.tacc);
+    for partial in partial_sigs {
+        let share = Scalar::from_be_bytes(partial.0)
+            .map_err(|_| CoreError::InvalidInput("Partial signature is out of range".to_string()))?;
+        s = s.add(ModN::reduce(share.to_be_bytes()));
+    }
+
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&session.r.x_only_public_key().0.serialize());
+    bytes[32..].copy_from_slice(&s.to_bytes());
+    let signature = schnorr::Signature::from_slice(&bytes)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid aggregate signature: {}", e)))?;
+
+    let secp = Secp256k1::verification_only();
+    let message = bitcoin::secp256k1::Message::from_slice(msg)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid message: {}", e)))?;
+    secp.verify_schnorr(&signature, &message, &agg_key.x_only_public_key())
+        .map_err(|_| CoreError::InvalidInput("Partial signatures do not combine to a valid signature".to_string()))?;
+    Ok(signature)
+}
+
+/// Values every signer derives from the aggregate nonce (BIP327 GetSessionValues)
+struct Session {
+    /// Final nonce point R
+    r: PublicKey,
+    /// Nonce coefficient
+    b: ModN,
+    /// BIP340 challenge
+    e: ModN,
+}
+
+impl Session {
+    fn new(agg_key: &AggregatedKey, pub_nonces: &[PublicNonce], msg: &[u8; 32]) -> CoreResult<Session> {
+        if pub_nonces.is_empty() {
+            return Err(CoreError::InvalidInput("MuSig2 needs at least one public nonce".to_string()));
+        }
+        let infinity = || CoreError::InvalidInput("MuSig2 nonces aggregate to the point at infinity".to_string());
+        let r1 = PublicKey::combine_keys(&pub_nonces.iter().map(|nonce| &nonce.r1).collect::<Vec<_>>())
+            .map_err(|_| infinity())?;
+        let r2 = PublicKey::combine_keys(&pub_nonces.iter().map(|nonce| &nonce.r2).collect::<Vec<_>>())
+            .map_err(|_| infinity())?;
+
+        let q = agg_key.x_only_public_key().serialize();
+        let b = ModN::reduce(tagged_hash("MuSig/noncecoef", &[&cbytes(&r1), &cbytes(&r2), &q, msg]));
+        let r = r1.combine(&mul_point(r2, b)?).map_err(|_| infinity())?;
+        let e = ModN::reduce(tagged_hash(
+            "BIP0340/challenge",
+            &[&r.x_only_public_key().0.serialize(), &q, msg],
+        ));
+        Ok(Session { r, b, e })
+    }
+}
+
+/// BIP327 KeyAggCoeff: 1 for the second distinct key, a hash otherwise
+fn key_agg_coeff(keys: &[PublicKey], key: &PublicKey) -> ModN {
+    if keys.iter().find(|other| *other != &keys[0]) == Some(key) {
+        return ModN::one();
+    }
+    let list: Vec<u8> = keys.iter().flat_map(cbytes).collect();
+    let list_hash = tagged_hash("KeyAgg list", &[&list]);
+    ModN::reduce(tagged_hash("KeyAgg coefficient", &[&list_hash, &cbytes(key)]))
+}
+
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_ref());
+    engine.input(tag_hash.as_ref());
+    for part in parts {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn cbytes(point: &PublicKey) -> [u8; 33] {
+    point.serialize()
+}
+
+fn has_even_y(point: &PublicKey) -> bool {
+    point.x_only_public_key().1 == Parity::Even
+}
+
+fn mul_point(point: PublicKey, k: ModN) -> CoreResult<PublicKey> {
+    if k == ModN::one() {
+        return Ok(point);
+    }
+    let scalar = Scalar::from_be_bytes(k.to_bytes()).expect("reduced scalars are below the curve order");
+    point
+        .mul_tweak(&Secp256k1::verification_only(), &scalar)
+        .map_err(|_| CoreError::Internal("MuSig2 coefficient is zero".to_string()))
+}
+
+/// An integer modulo the curve order
+///
+/// `SecretKey` does the arithmetic but can't hold zero, which accumulated
+/// tweaks and partial signatures may be, so zero is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModN(Option<SecretKey>);
+
+impl ModN {
+    const ZERO: ModN = ModN(None);
+
+    /// Curve order n
+    const ORDER: [u8; 32] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+        0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+    ];
+
+    fn one() -> ModN {
+        ModN(Some(SecretKey::from_slice(&bitcoin::secp256k1::constants::ONE).expect("1 is a valid secret key")))
+    }
+
+    /// A 256-bit big-endian integer mod n
+    fn reduce(mut bytes: [u8; 32]) -> ModN {
+        if Scalar::from_be_bytes(bytes).is_err() {
+            // Below 2^256 < 2n, so one subtraction reduces it
+            let mut borrow = 0i16;
+            for i in (0..32).rev() {
+                let diff = bytes[i] as i16 - Self::ORDER[i] as i16 - borrow;
+                borrow = (diff < 0) as i16;
+                bytes[i] = diff.rem_euclid(256) as u8;
+            }
+        }
+        ModN(SecretKey::from_slice(&bytes).ok())
+    }
+
+    fn secret_key(self) -> Option<SecretKey> {
+        self.0
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        self.0.map_or([0; 32], |key| key.secret_bytes())
+    }
+
+    fn add(self, other: ModN) -> ModN {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => ModN(a.add_tweak(&Scalar::from(b)).ok()),
+            (a, None) => ModN(a),
+            (None, b) => ModN(b),
+        }
+    }
+
+    fn mul(self, other: ModN) -> ModN {
+        match (self.0, other.0) {
+            (Some(a), Some(b)) => ModN(a.mul_tweak(&Scalar::from(b)).ok()),
+            _ => ModN::ZERO,
+        }
+    }
+
+    fn neg(self) -> ModN {
+        ModN(self.0.map(SecretKey::negate))
+    }
+}
+
+impl From<SecretKey> for ModN {
+    fn from(key: SecretKey) -> Self {
+        ModN(Some(key))
+    }
+}
+
+impl SecretNonce {
+    /// The public half, as `generate_nonce()` returned it
+    pub fn public_nonce(&self) -> PublicNonce {
+        let secp = Secp256k1::new();
+        PublicNonce {
+            r1: PublicKey::from_secret_key(&secp, &self.k1),
+            r2: PublicKey::from_secret_key(&secp, &self.k2),
+        }
+    }
+
+    /// Key of the signer the nonce belongs to
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+}
+
+impl fmt::Debug for SecretNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretNonce").field("public_key", &self.public_key).finish_non_exhaustive()
+    }
+}
+
+impl PublicNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut bytes = [0u8; 66];
+        bytes[..33].copy_from_slice(&cbytes(&self.r1));
+        bytes[33..].copy_from_slice(&cbytes(&self.r2));
+        bytes
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> CoreResult<Self> {
+        let invalid = || CoreError::InvalidInput("Invalid MuSig2 public nonce".to_string());
+        if bytes.len() != 66 {
+            return Err(invalid());
+        }
+        Ok(PublicNonce {
+            r1: PublicKey::from_slice(&bytes[..33]).map_err(|_| invalid())?,
+            r2: PublicKey::from_slice(&bytes[33..]).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl PartialSignature {
+    pub fn serialize(&self) -> [u8; 32] {
+        self.0
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> CoreResult<Self> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| CoreError::InvalidInput("Invalid MuSig2 partial signature".to_string()))?;
+        Scalar::from_be_bytes(bytes)
+            .map_err(|_| CoreError::InvalidInput("MuSig2 partial signature is out of range".to_string()))?;
+        Ok(PartialSignature(bytes))
+    }
+}
+
+/// Hex string forms, as the types cross the FFI boundary
+macro_rules! hex_serde {
+    ($($ty:ident),*) => {$(
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&hex::encode(self.serialize()))
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = CoreError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let bytes = hex::decode(s)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid {} hex: {}", stringify!($ty), e)))?;
+                $ty::from_slice(&bytes)
+            }
+        }
+
+        impl Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    )*};
+}
+
+hex_serde!(PublicNonce, PartialSignature);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::TapTweak;
+
+    fn secret(seed: u8) -> SecretKey {
+        SecretKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn public(key: &SecretKey) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), key)
+    }
+
+    fn bytes<const N: usize>(hex_str: &str) -> [u8; N] {
+        hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    /// Run both rounds for `signers` over `msg`
+    fn sign(signers: &[SecretKey], agg_key: &AggregatedKey, msg: &[u8; 32]) -> CoreResult<schnorr::Signature> {
+        let nonces = signers
+            .iter()
+            .map(|key| generate_nonce(key, agg_key, msg).unwrap())
+            .collect::<Vec<_>>();
+        let pub_nonces: Vec<PublicNonce> = nonces.iter().map(|(_, public)| *public).collect();
+        let partials = signers
+            .iter()
+            .zip(nonces)
+            .map(|(key, (secret_nonce, _))| partial_sign(secret_nonce, key, agg_key, &pub_nonces, msg))
+            .collect::<CoreResult<Vec<_>>>()?;
+        aggregate_partial_sigs(agg_key, &pub_nonces, msg, &partials)
+    }
+
+    #[test]
+    fn test_two_party_signature_verifies() {
+        // Seeds chosen so both parities of participant keys are covered
+        for (a, b) in [(1, 2), (3, 4), (5, 6)] {
+            let signers = [secret(a), secret(b)];
+            let agg_key = aggregate_keys(&[public(&signers[0]), public(&signers[1])]).unwrap();
+            let msg = [0x42; 32];
+            sign(&signers, &agg_key, &msg).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_taproot_tweak_matches_output_key() {
+        let signers = [secret(7), secret(8)];
+        let agg_key = aggregate_keys(&[public(&signers[0]), public(&signers[1])]).unwrap();
+        let merkle_root = Some(TapNodeHash::assume_hidden([9; 32]));
+
+        let tweaked = agg_key.with_taproot_tweak(merkle_root).unwrap();
+        let (output_key, _) = agg_key.x_only_public_key().tap_tweak(&Secp256k1::new(), merkle_root);
+        assert_eq!(tweaked.x_only_public_key(), output_key.to_inner());
+
+        // Signatures under the tweaked key verify against the output key
+        sign(&signers, &tweaked, &[0x17; 32]).unwrap();
+    }
+
+    #[test]
+    fn test_key_order_matters() {
+        let (a, b) = (public(&secret(1)), public(&secret(2)));
+        assert_ne!(
+            aggregate_keys(&[a, b]).unwrap().x_only_public_key(),
+            aggregate_keys(&[b, a]).unwrap().x_only_public_key()
+        );
+        assert!(matches!(aggregate_keys(&[]), Err(CoreError::InvalidInput(_))));
+    }
+
+    /// BIP327 key_agg_vectors.json, valid cases
+    #[test]
+    fn test_bip327_key_agg_vectors() {
+        let pubkeys: Vec<PublicKey> = [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        ]
+        .iter()
+        .map(|key| key.parse().unwrap())
+        .collect();
+        let cases: [(&[usize], &str); 4] = [
+            (&[0, 1, 2], "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C"),
+            (&[2, 1, 0], "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B"),
+            (&[0, 0, 0], "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935"),
+            (&[0, 0, 1, 1], "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E"),
+        ];
+        for (indices, expected) in cases {
+            let keys: Vec<PublicKey> = indices.iter().map(|&i| pubkeys[i]).collect();
+            let agg_key = aggregate_keys(&keys).unwrap();
+            assert_eq!(agg_key.x_only_public_key().serialize(), bytes::<32>(expected), "{:?}", indices);
+        }
+    }
+
+    /// BIP327 sign_verify_vectors.json, valid cases without tweaks
+    #[test]
+    fn test_bip327_sign_vectors() {
+        let secret_key = SecretKey::from_slice(&bytes::<32>(
+            "7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671",
+        ))
+        .unwrap();
+        let pubkeys: Vec<PublicKey> = [
+            "03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9",
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661",
+        ]
+        .iter()
+        .map(|key| key.parse().unwrap())
+        .collect();
+        let pnonces: Vec<PublicNonce> = [
+            "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+        ]
+        .iter()
+        .map(|nonce| nonce.to_lowercase().parse().unwrap())
+        .collect();
+        let secnonce = bytes::<64>(
+            "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7",
+        );
+        let msg = bytes::<32>("F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF");
+        assert_eq!(public(&secret_key), pubkeys[0]);
+
+        let cases: [(&[usize], &str); 3] = [
+            (&[0, 1, 2], "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB"),
+            (&[1, 0, 2], "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52"),
+            (&[1, 2, 0], "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900"),
+        ];
+        for (indices, expected) in cases {
+            let agg_key = aggregate_keys(&indices.iter().map(|&i| pubkeys[i]).collect::<Vec<_>>()).unwrap();
+            let nonces: Vec<PublicNonce> = indices.iter().map(|&i| pnonces[i]).collect();
+            let secret_nonce = SecretNonce {
+                k1: SecretKey::from_slice(&secnonce[..32]).unwrap(),
+                k2: SecretKey::from_slice(&secnonce[32..]).unwrap(),
+                public_key: pubkeys[0],
+                agg_key: agg_key.x_only_public_key(),
+                msg,
+            };
+            assert_eq!(secret_nonce.public_nonce(), pnonces[0]);
+            let partial = partial_sign(secret_nonce, &secret_key, &agg_key, &nonces, &msg).unwrap();
+            assert_eq!(partial.serialize(), bytes::<32>(expected), "{:?}", indices);
+        }
+    }
+
+    #[test]
+    fn test_partial_sign_rejects_outsider_and_missing_share() {
+        let signers = [secret(1), secret(2)];
+        let agg_key = aggregate_keys(&[public(&signers[0]), public(&signers[1])]).unwrap();
+        let msg = [1; 32];
+
+        let outsider = secret(3);
+        let (secret_nonce, public_nonce) = generate_nonce(&outsider, &agg_key, &msg).unwrap();
+        assert!(matches!(
+            partial_sign(secret_nonce, &outsider, &agg_key, &[public_nonce], &msg),
+            Err(CoreError::InvalidInput(_))
+        ));
+
+        // One share short of the full signature
+        let nonces: Vec<_> = signers.iter().map(|key| generate_nonce(key, &agg_key, &msg).unwrap()).collect();
+        let pub_nonces: Vec<_> = nonces.iter().map(|(_, public)| *public).collect();
+        let (secret_nonce, _) = nonces.into_iter().next().unwrap();
+        let partial = partial_sign(secret_nonce, &signers[0], &agg_key, &pub_nonces, &msg).unwrap();
+        assert!(matches!(
+            aggregate_partial_sigs(&agg_key, &pub_nonces, &msg, &[partial]),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_secret_nonce_is_bound_to_session() {
+        let signers = [secret(1), secret(2)];
+        let agg_key = aggregate_keys(&[public(&signers[0]), public(&signers[1])]).unwrap();
+        let other_key = aggregate_keys(&[public(&signers[1]), public(&signers[0])]).unwrap();
+        let msg = [1; 32];
+        let rejected = |result: CoreResult<PartialSignature>| {
+            matches!(result, Err(CoreError::InvalidInput(msg)) if msg.contains("another aggregate key or message"))
+        };
+
+        let (secret_nonce, public_nonce) = generate_nonce(&signers[0], &agg_key, &msg).unwrap();
+        assert!(rejected(partial_sign(secret_nonce, &signers[0], &agg_key, &[public_nonce], &[2; 32])));
+        let (secret_nonce, public_nonce) = generate_nonce(&signers[0], &agg_key, &msg).unwrap();
+        assert!(rejected(partial_sign(secret_nonce, &signers[0], &other_key, &[public_nonce], &msg)));
+    }
+
+    #[test]
+    fn test_nonce_and_signature_hex_roundtrip() {
+        let key = secret(1);
+        let agg_key = aggregate_keys(&[public(&key)]).unwrap();
+        let (secret_nonce, public_nonce) = generate_nonce(&key, &agg_key, &[0; 32]).unwrap();
+        assert_eq!(secret_nonce.public_nonce(), public_nonce);
+        assert!(!format!("{:?}", secret_nonce).contains(&hex::encode(secret_nonce.k1.secret_bytes())));
+
+        let json = serde_json::to_value(public_nonce).unwrap();
+        assert_eq!(serde_json::from_value::<PublicNonce>(json).unwrap(), public_nonce);
+
+        let partial = partial_sign(secret_nonce, &key, &agg_key, &[public_nonce], &[0; 32]).unwrap();
+        assert_eq!(partial.to_string().parse::<PartialSignature>().unwrap(), partial);
+        assert!(PartialSignature::from_slice(&ModN::ORDER).is_err());
+    }
+
+    #[test]
+    fn test_mod_n_reduction() {
+        assert_eq!(ModN::reduce(ModN::ORDER), ModN::ZERO);
+        let mut above = ModN::ORDER;
+        above[31] += 5;
+        let mut five = [0; 32];
+        five[31] = 5;
+        assert_eq!(ModN::reduce(above).to_bytes(), five);
+        assert_eq!(ModN::one().neg().add(ModN::one()), ModN::ZERO);
+    }
+}
@@ -0,0 +1,270 @@
+//! Private key material that is wiped from memory when dropped
+//!
+//! `ExtendedPrivKey` and `SecretKey` are `Copy` and leave their bytes
+//! behind wherever they were held. `SecretMaterial` keeps the encoded
+//! key in a buffer of its own and overwrites it with volatile writes on
+//! drop, and its `Debug` and `Display` show the key's fingerprint only.
+//! Keys decoded from it for a single derivation or signature are
+//! short-lived copies on the stack.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::bip32::{ExtendedPrivKey, Fingerprint};
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey};
+
+use crate::error::{CoreError, CoreResult};
+
+/// Length of a BIP32 serialized extended key
+const XPRIV_LEN: usize = 78;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SecretKind {
+    /// `bytes` holds `ExtendedPrivKey::encode()`
+    Xpriv,
+    /// The first 32 bytes of `bytes` hold a secret key
+    Key,
+}
+
+/// An extended private key or a single secret key, zeroed on drop
+pub struct SecretMaterial {
+    kind: SecretKind,
+    bytes: [u8; XPRIV_LEN],
+}
+
+impl SecretMaterial {
+    pub fn from_xpriv(xpriv: &ExtendedPrivKey) -> Self {
+        let mut encoded = xpriv.encode();
+        let secret = SecretMaterial { kind: SecretKind::Xpriv, bytes: encoded };
+        wipe(&mut encoded);
+        secret
+    }
+
+    pub fn from_secret_key(key: &SecretKey) -> Self {
+        let mut key_bytes = key.secret_bytes();
+        let mut bytes = [0; XPRIV_LEN];
+        bytes[..32].copy_from_slice(&key_bytes);
+        wipe(&mut key_bytes);
+        SecretMaterial { kind: SecretKind::Key, bytes }
+    }
+
+    /// BIP32 master key of `seed`, such as a BIP39 seed
+    ///
+    /// The caller still owns (and should wipe) `seed`.
+    pub fn from_seed(seed: &[u8], network: bitcoin::Network) -> CoreResult<Self> {
+        let master = ExtendedPrivKey::new_master(network, seed)
+            .map_err(|e| CoreError::DerivationError(format!("Invalid seed: {}", e)))?;
+        Ok(Self::from_xpriv(&master))
+    }
+
+    /// The extended key, or `InvalidInput` for a single secret key
+    pub fn xpriv(&self) -> CoreResult<ExtendedPrivKey> {
+        match self.kind {
+            SecretKind::Xpriv => ExtendedPrivKey::decode(&self.bytes)
+                .map_err(|e| CoreError::Internal(format!("Corrupt extended key: {}", e))),
+            SecretKind::Key => Err(CoreError::InvalidInput(
+                "An extended private key is required, not a single secret key".to_string(),
+            )),
+        }
+    }
+
+    /// The secret key itself, the private key of an extended key
+    pub fn secret_key(&self) -> SecretKey {
+        let start = match self.kind {
+            SecretKind::Xpriv => XPRIV_LEN - 32,
+            SecretKind::Key => 0,
+        };
+        SecretKey::from_slice(&self.bytes[start..start + 32]).expect("buffer holds a valid secret key")
+    }
+
+    pub fn keypair(&self) -> KeyPair {
+        KeyPair::from_secret_key(&Secp256k1::signing_only(), &self.secret_key())
+    }
+
+    /// BIP32 fingerprint of the key: the first 4 bytes of the hash160 of
+    /// its compressed public key
+    pub fn fingerprint(&self) -> Fingerprint {
+        let public_key = self.keypair().public_key();
+        let hash = hash160::Hash::hash(&public_key.serialize()).to_byte_array();
+        Fingerprint::from([hash[0], hash[1], hash[2], hash[3]])
+    }
+}
+
+impl From<ExtendedPrivKey> for SecretMaterial {
+    fn from(xpriv: ExtendedPrivKey) -> Self {
+        Self::from_xpriv(&xpriv)
+    }
+}
+
+impl From<SecretKey> for SecretMaterial {
+    fn from(key: SecretKey) -> Self {
+        Self::from_secret_key(&key)
+    }
+}
+
+impl Clone for SecretMaterial {
+    fn clone(&self) -> Self {
+        SecretMaterial { kind: self.kind, bytes: self.bytes }
+    }
+}
+
+impl Drop for SecretMaterial {
+    fn drop(&mut self) {
+        wipe(&mut self.bytes);
+    }
+}
+
+impl FromStr for SecretMaterial {
+    type Err = CoreError;
+
+    /// An xprv/tprv, or a secret key as 64 hex characters
+    ///
+    /// The error never repeats the input.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(xpriv) = ExtendedPrivKey::from_str(s) {
+            return Ok(Self::from_xpriv(&xpriv));
+        }
+        SecretKey::from_str(s)
+            .map(|key| Self::from_secret_key(&key))
+            .map_err(|_| CoreError::InvalidInput("Invalid private key: expected an xprv or 32 hex-encoded bytes".to_string()))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SecretMaterial {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = SecretMaterial;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an xprv or a hex-encoded secret key")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<SecretMaterial, E> {
+                s.parse().map_err(|e: CoreError| E::custom(e))
+            }
+
+            fn visit_string<E: serde::de::Error>(self, mut s: String) -> Result<SecretMaterial, E> {
+                let secret = self.visit_str(&s);
+                wipe_string(&mut s);
+                secret
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+impl fmt::Debug for SecretMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretMaterial({})", self.fingerprint())
+    }
+}
+
+impl fmt::Display for SecretMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[secret {}]", self.fingerprint())
+    }
+}
+
+/// Overwrite `bytes` with zeros the compiler can't elide
+pub(super) fn wipe(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Zero a string's buffer in place, e.g. a request that held a private key
+///
+/// Only the current buffer is wiped; copies left by earlier reallocations
+/// are out of reach.
+pub fn wipe_string(s: &mut String) {
+    // SAFETY: all zeros is valid UTF-8
+    wipe(unsafe { s.as_bytes_mut() });
+    s.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::ManuallyDrop;
+
+    const XPRV: &str = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+
+    #[test]
+    fn test_debug_and_display_show_fingerprint_only() {
+        let xpriv = ExtendedPrivKey::from_str(XPRV).unwrap();
+        let secret = SecretMaterial::from_xpriv(&xpriv);
+        let fingerprint = xpriv.fingerprint(&Secp256k1::new()).to_string();
+
+        for shown in [format!("{:?}", secret), secret.to_string()] {
+            assert!(shown.contains(&fingerprint), "{}", shown);
+            assert!(!shown.contains(&XPRV[4..20]), "{}", shown);
+            assert!(!shown.contains(&xpriv.private_key.display_secret().to_string()), "{}", shown);
+        }
+        assert_eq!(format!("{:?}", secret), format!("SecretMaterial({})", fingerprint));
+
+        // A single key's fingerprint is that of an xpriv holding it
+        let key = SecretMaterial::from_secret_key(&xpriv.private_key);
+        assert_eq!(key.fingerprint(), secret.fingerprint());
+    }
+
+    #[test]
+    fn test_roundtrips() {
+        let xpriv = ExtendedPrivKey::from_str(XPRV).unwrap();
+        let secret: SecretMaterial = XPRV.parse().unwrap();
+        assert_eq!(secret.xpriv().unwrap(), xpriv);
+        assert_eq!(secret.secret_key(), xpriv.private_key);
+        assert_eq!(secret.clone().xpriv().unwrap(), xpriv);
+
+        let hex = xpriv.private_key.display_secret().to_string();
+        let key: SecretMaterial = serde_json::from_value(serde_json::json!(hex)).unwrap();
+        assert_eq!(key.secret_key(), xpriv.private_key);
+        assert!(matches!(key.xpriv(), Err(CoreError::InvalidInput(_))));
+
+        let seeded = SecretMaterial::from_seed(&[7; 32], bitcoin::Network::Regtest).unwrap();
+        // Regtest keys share testnet's version bytes, so decode as testnet
+        let master = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[7; 32]).unwrap();
+        assert_eq!(seeded.xpriv().unwrap().encode(), master.encode());
+    }
+
+    #[test]
+    fn test_parse_error_hides_input() {
+        let almost = &XPRV[..XPRV.len() - 1];
+        let err = almost.parse::<SecretMaterial>().unwrap_err().to_string();
+        assert!(!err.contains(almost), "{}", err);
+
+        let err = serde_json::from_value::<SecretMaterial>(serde_json::json!(almost)).unwrap_err().to_string();
+        assert!(!err.contains(almost), "{}", err);
+    }
+
+    #[test]
+    fn test_buffer_zeroed_on_drop() {
+        let mut secret = ManuallyDrop::new(SecretMaterial::from_str(XPRV).unwrap());
+        let ptr = secret.bytes.as_ptr();
+        assert!(secret.bytes.iter().any(|byte| *byte != 0));
+
+        // SAFETY: dropped in place, so the buffer stays allocated in the
+        // `ManuallyDrop` and is read but never used as a key again
+        unsafe {
+            ManuallyDrop::drop(&mut secret);
+            let bytes = std::slice::from_raw_parts(ptr, XPRIV_LEN);
+            assert!(bytes.iter().all(|byte| *byte == 0));
+        }
+    }
+
+    #[test]
+    fn test_wipe_string() {
+        let mut request = format!("{{\"xpriv\":\"{}\"}}", XPRV);
+        let (ptr, len) = (request.as_ptr(), request.len());
+        wipe_string(&mut request);
+        assert!(request.is_empty());
+        // SAFETY: clear() keeps the allocation, and `request` is still alive
+        let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+        assert!(bytes.iter().all(|byte| *byte == 0));
+    }
+}
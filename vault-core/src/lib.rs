@@ -1,65 +1,232 @@
+// FFI exports receive raw pointers from the host by design; safety
+// requirements are documented on each export.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use std::ffi::CString;
 use std::os::raw::c_char;
 
 // Module declarations
+pub mod cancel;
 pub mod error;
 pub mod ffi;
 pub mod keys;
+mod parallel;
 pub mod taproot;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
 pub mod transaction;
 pub mod vault;
 
 // Re-exports for convenience
-pub use error::{CoreError, CoreResult};
-pub use vault::{Network, VaultTemplate, VaultMetadata, RecoveryType};
+pub use error::{CoreError, CoreResult, MnemonicError};
+pub use vault::{AbsoluteLockUnit, DelayUnit, HashlockRecovery, HeirSet, MetadataMode, MultisigRecovery, Network, VaultTemplate, VaultMetadata, VaultMetadataRef, RecoveryType};
 
 // ═══════════════════════════════════════════════════════════════════
 //                      INITIALIZATION FFI
 // ═══════════════════════════════════════════════════════════════════
 
-/// Get library version
-///
-/// Returns the semantic version of the vault-core library.
-/// The returned string must be freed using `free_rust_string()`.
-///
-/// # Safety
-/// This function is safe to call from any context.
-#[no_mangle]
-pub extern "C" fn vault_version() -> *mut c_char {
-    ffi::to_c_string(env!("CARGO_PKG_VERSION"))
-}
-
-/// Initialize library with network
-///
-/// # Arguments
-/// * `network` - Network selection (0=mainnet, 1=testnet, 2=signet, 3=regtest)
-///
-/// # Returns
-/// * `0` on success
-/// * `-1` on invalid network
-///
-/// # Safety
-/// This function is safe to call from any context.
-#[no_mangle]
-pub extern "C" fn vault_init(network: i32) -> i32 {
-    match Network::try_from(network) {
-        Ok(_) => 0,
-        Err(_) => -1,
-    }
-}
-
-/// Free a string allocated by Rust
-///
-/// # Safety
-/// - `ptr` must be a valid pointer returned from a Rust FFI function, or null
-/// - `ptr` must not be used after calling this function
-#[no_mangle]
-pub extern "C" fn free_rust_string(ptr: *mut c_char) {
-    if ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let _ = CString::from_raw(ptr);
+ffi_export! {
+    /// Get library version
+    ///
+    /// Returns the semantic version of the vault-core library.
+    /// The returned string must be freed using `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_version() -> *mut c_char {
+        ffi::to_c_string(env!("CARGO_PKG_VERSION"))
+    }
+}
+
+ffi_export! {
+    /// Get library version as numbers, with the capabilities compiled in
+    ///
+    /// `feature_flags` holds the `FEATURE_*` bits (1=randomized anti-fee-sniping
+    /// locktimes, 2=MuSig2, 4=UR QR codes, 8=parallel address scans, 16=test vectors) of this build. Use `vault_version()`
+    /// for display.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_version_info() -> ffi::VaultVersion {
+        ffi::VaultVersion::current()
+    }
+}
+
+ffi_export! {
+    /// Check whether this build has a capability
+    ///
+    /// # Arguments
+    /// * `flag` - One or more `FEATURE_*` bits, as in `vault_version_info()`
+    ///
+    /// # Returns
+    /// 1 if every bit of `flag` is set, 0 otherwise (also for `flag = 0`).
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_has_feature(flag: u32) -> i32 {
+        i32::from(flag != 0 && ffi::feature_flags() & flag == flag)
+    }
+}
+
+ffi_export! {
+    /// Initialize library with network
+    ///
+    /// The network is kept for the life of the process. Exports that take
+    /// a config or request without `"network"`, or `network = -1`, use it.
+    /// Calling again with the same network is a no-op; a different
+    /// network is rejected with code 1003 and the first one stays.
+    ///
+    /// # Arguments
+    /// * `network` - Network selection (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// * `0` on success
+    /// * `-1` on invalid network or a conflicting earlier call (details via
+    ///   `vault_last_error_message()`)
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_init(network: i32) -> i32 {
+        ffi::status(Network::try_from(network).and_then(ffi::init_network))
+    }
+}
+
+ffi_export! {
+    /// Network selected by `vault_init()`
+    ///
+    /// # Returns
+    /// The network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4), or `-1`
+    /// if `vault_init()` has not succeeded yet.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_get_network() -> i32 {
+        ffi::network().map_or(-1, |network| network as i32)
+    }
+}
+
+ffi_export! {
+    /// Network code for a network name
+    ///
+    /// # Arguments
+    /// * `name` - `"mainnet"`, `"testnet"`, `"signet"`, `"regtest"` or `"testnet4"`, in any case
+    ///
+    /// # Returns
+    /// The network's code for `vault_init()` and the `network` arguments, or
+    /// `-1` for an unknown name (details via `vault_last_error_message()`).
+    ///
+    /// # Safety
+    /// `name` must be a valid null-terminated C string.
+    fn vault_parse_network(name: *const c_char) -> i32 {
+        match ffi::from_c_string(name).and_then(|name| name.parse::<Network>()) {
+            Ok(network) => {
+                ffi::clear_last_error();
+                network as i32
+            }
+            Err(e) => ffi::FfiReturn::from_error(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Message of the last error raised by an integer-returning export
+    ///
+    /// Errors are tracked per thread, so this reports the last failure on
+    /// the calling thread only. A successful call clears it.
+    ///
+    /// # Returns
+    /// Error message string, or null if there is no error.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_last_error_message() -> *mut c_char {
+        ffi::with_last_error(|error| match error {
+            Some(error) => ffi::to_c_string(&error.to_string()),
+            None => std::ptr::null_mut(),
+        })
+    }
+}
+
+ffi_export! {
+    /// Code of the last error raised by an integer-returning export
+    ///
+    /// # Returns
+    /// Error code (see `CoreError::code`), or 0 if there is no error.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_last_error_code() -> i32 {
+        ffi::with_last_error(|error| error.map_or(0, CoreError::code))
+    }
+}
+
+ffi_export! {
+    /// Forward the library's log messages to `callback`
+    ///
+    /// Messages are `"<module>: <text>"`, e.g. `"vault_core::ffi: vault_init called"`.
+    /// The callback may be invoked from several threads at once and must
+    /// not call back into the library. Replaces any earlier callback.
+    ///
+    /// # Arguments
+    /// * `callback` - `void (*)(int32_t level, const char *msg)`; `msg` is only
+    ///   valid until the callback returns
+    /// * `min_level` - Least severe level forwarded (0=trace, 1=debug, 2=info,
+    ///   3=warn, 4=error), also the `level` values passed to the callback
+    ///
+    /// # Returns
+    /// * `0` on success
+    /// * `-1` on a null callback, invalid level, or if the host process
+    ///   installed its own Rust logger (details via `vault_last_error_message()`)
+    ///
+    /// # Safety
+    /// `callback` must be null or a function safe to call from any thread
+    /// until `vault_clear_log_callback()` returns.
+    fn vault_set_log_callback(callback: Option<ffi::LogCallback>, min_level: i32) -> i32 {
+        let result = callback
+            .ok_or_else(|| CoreError::InvalidInput("null log callback".to_string()))
+            .and_then(|callback| ffi::set_log_callback(callback, min_level));
+        ffi::status(result)
+    }
+}
+
+ffi_export! {
+    /// Stop forwarding log messages to the callback set with `vault_set_log_callback()`
+    ///
+    /// A message being logged on another thread at the time may still be
+    /// delivered.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_clear_log_callback() {
+        ffi::clear_log_callback();
+    }
+}
+
+ffi_export! {
+    /// Free a string allocated by Rust
+    ///
+    /// # Safety
+    /// - `ptr` must be a valid pointer returned from a Rust FFI function, or null
+    /// - `ptr` must not be used after calling this function
+    fn free_rust_string(ptr: *mut c_char) {
+        if ptr.is_null() {
+            return;
+        }
+        unsafe {
+            let _ = CString::from_raw(ptr);
+        }
+    }
+}
+
+ffi_export! {
+    /// Free a byte buffer allocated by Rust
+    ///
+    /// # Safety
+    /// - `buffer` must have been returned from a Rust FFI function, or be null
+    /// - `buffer` must not be used after calling this function
+    fn free_rust_bytes(buffer: ffi::ByteBuffer) {
+        drop(ffi::from_byte_buffer(buffer));
     }
 }
 
@@ -67,509 +234,5051 @@ pub extern "C" fn free_rust_string(ptr: *mut c_char) {
 //                       KEY DERIVATION FFI
 // ═══════════════════════════════════════════════════════════════════
 
-/// Validate an xpub string
-///
-/// # Arguments
-/// * `xpub` - Extended public key string (xpub... or tpub...)
-/// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest)
-///
-/// # Returns
-/// JSON string: `{"xpub":"...","fingerprint":"...","network":"...","supports_taproot":true}`
-/// or error JSON: `{"error":true,"code":...,"message":"..."}`
-///
-/// # Safety
-/// `xpub` must be a valid null-terminated C string.
-#[no_mangle]
-pub extern "C" fn ffi_validate_xpub(xpub: *const c_char, network: i32) -> *mut c_char {
-    let xpub_str = match ffi::from_c_string(xpub) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-    let net = match Network::try_from(network) {
-        Ok(n) => n,
-        Err(e) => return ffi::error_response(e),
-    };
+ffi_export! {
+    /// Validate an xpub string
+    ///
+    /// # Arguments
+    /// * `xpub` - Extended public key string (xpub... or tpub...)
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// JSON string: `{"xpub":"...","fingerprint":"...","network":"...","supports_taproot":true}`
+    /// or error JSON: `{"error":true,"code":...,"message":"..."}`
+    ///
+    /// # Safety
+    /// `xpub` must be a valid null-terminated C string.
+    fn ffi_validate_xpub(xpub: *const c_char, network: i32) -> *mut c_char {
+        let xpub_str = match ffi::from_c_string(xpub) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match Network::try_from(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
 
-    match keys::validate_xpub(&xpub_str, net) {
-        Ok(info) => ffi::success_response(info),
-        Err(e) => ffi::error_response(e),
+        match keys::validate_xpub(&xpub_str, net) {
+            Ok(info) => ffi::success_response(info),
+            Err(e) => ffi::error_response(e),
+        }
     }
 }
 
-/// Get BIP86 derivation path for a vault index
-///
-/// # Arguments
-/// * `vault_index` - Vault derivation index
-/// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest)
-///
-/// # Returns
-/// Path string like "m/86'/0'/0'/0/0". Must be freed with `free_rust_string()`.
-///
-/// # Safety
-/// This function is safe to call from any context.
-#[no_mangle]
-pub extern "C" fn ffi_get_derivation_path(vault_index: u32, network: i32) -> *mut c_char {
-    let net = match Network::try_from(network) {
-        Ok(n) => n,
-        Err(e) => return ffi::error_response(e),
-    };
-    ffi::to_c_string(&keys::get_derivation_path(vault_index, net))
+ffi_export! {
+    /// Validate an xpub against a network and return its structural details
+    ///
+    /// The version bytes must match the network: `xpub` for mainnet,
+    /// `tpub` for testnet/signet/regtest.
+    ///
+    /// # Arguments
+    /// * `xpub` - Extended public key string (xpub... or tpub...)
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// JSON: `{"xpub":"...","network":"...","fingerprint":"...","parent_fingerprint":"...","depth":3,"child_number":0,"hardened":true}`
+    /// or error JSON (1001 for unparseable keys, 1003 for network mismatch).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `xpub` must be a valid null-terminated C string.
+    fn vault_validate_xpub(xpub: *const c_char, network: i32) -> *mut c_char {
+        let xpub_str = match ffi::from_c_string(xpub) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match Network::try_from(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match keys::xpub_details(&xpub_str, net) {
+            Ok(details) => ffi::success_response(details),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Derive the vault key at `vault_index` from an account xpub
+    ///
+    /// The network is taken from the key's version bytes (SLIP-132
+    /// prefixes are accepted).
+    ///
+    /// # Arguments
+    /// * `xpub` - Account-level extended public key string
+    /// * `vault_index` - Unhardened vault derivation index (< 2^31)
+    ///
+    /// # Returns
+    /// JSON: `{"public_key":"...","path":"m/86'/0'/0'/0/0","parent_fingerprint":"..."}`
+    /// or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `xpub` must be a valid null-terminated C string.
+    fn vault_derive_key(xpub: *const c_char, vault_index: u32) -> *mut c_char {
+        let xpub_str = match ffi::from_c_string(xpub) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let (xpub, net) = match keys::normalize_extended_key(&xpub_str) {
+            Ok(k) => k,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match keys::derive_vault_key(&xpub, vault_index, net) {
+            Ok(derived) => ffi::success_response(derived),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Account xpub of a BIP39 mnemonic, for use as a vault owner or recovery key
+    ///
+    /// Derives `m/86'/coin'/account'` on the network selected by
+    /// `vault_init()`. Only public data leaves the library; the
+    /// intermediate key material and the Rust copies of both arguments
+    /// are wiped before returning. See `keys::from_mnemonic()`.
+    ///
+    /// # Arguments
+    /// * `words` - English BIP39 mnemonic, words separated by whitespace
+    /// * `passphrase` - BIP39 passphrase, empty for none
+    /// * `account` - Account index (< 2^31)
+    ///
+    /// # Returns
+    /// JSON: `{"xpub":"xpub...","master_fingerprint":"...","path":"m/86'/0'/0'"}` or
+    /// error JSON (1004 for an unknown word, bad checksum or word count, with the
+    /// reason in `details`; 4003 before `vault_init()`).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `words` and `passphrase` must be valid null-terminated C strings.
+    fn vault_mnemonic_to_xpub(words: *const c_char, passphrase: *const c_char, account: u32) -> *mut c_char {
+        let mut words = match ffi::from_c_string(words) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let mut passphrase = match ffi::from_c_string(passphrase) {
+            Ok(s) => s,
+            Err(e) => {
+                keys::wipe_string(&mut words);
+                return ffi::error_response(e);
+            }
+        };
+
+        let result = ffi::network_arg(-1).and_then(|network| {
+            let master = keys::from_mnemonic(&words, &passphrase, network)?;
+            let account_key = keys::account_xpriv(&master, keys::TAPROOT_PURPOSE, account, network)?;
+            let secp = bitcoin::secp256k1::Secp256k1::new();
+            let xpub = bitcoin::bip32::ExtendedPubKey::from_priv(&secp, &account_key.xpriv()?);
+            Ok(serde_json::json!({
+                "xpub": xpub.to_string(),
+                "master_fingerprint": master.fingerprint().to_string(),
+                "path": keys::account_path(keys::TAPROOT_PURPOSE, account, network)?.to_string(),
+            }))
+        });
+        keys::wipe_string(&mut words);
+        keys::wipe_string(&mut passphrase);
+
+        match result {
+            Ok(response) => ffi::success_response(response),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Get BIP86 derivation path for a vault index
+    ///
+    /// # Arguments
+    /// * `vault_index` - Vault derivation index
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// Path string like "m/86'/0'/0'/0/0". Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn ffi_get_derivation_path(vault_index: u32, network: i32) -> *mut c_char {
+        let net = match Network::try_from(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+        ffi::to_c_string(&keys::get_derivation_path(vault_index, net))
+    }
 }
 
 // ═══════════════════════════════════════════════════════════════════
 //                     VAULT ADDRESS FFI
 // ═══════════════════════════════════════════════════════════════════
 
-/// Generate a Taproot vault address with embedded metadata
-///
-/// # Arguments
-/// * `params_json` - JSON: `{"primary_xpub":"...","emergency_xpub":"...","template":{...},"vault_index":0}`
-/// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest)
-///
-/// # Returns
-/// JSON with address, internal_key, scripts, metadata. Must be freed with `free_rust_string()`.
-///
-/// # Safety
-/// `params_json` must be a valid null-terminated C string.
-#[no_mangle]
-pub extern "C" fn ffi_generate_vault_address(
-    params_json: *const c_char,
-    network: i32,
-) -> *mut c_char {
-    let params_str = match ffi::from_c_string(params_json) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-    let net = match Network::try_from(network) {
-        Ok(n) => n,
-        Err(e) => return ffi::error_response(e),
-    };
+ffi_export! {
+    /// Generate a Taproot vault address with embedded metadata
+    ///
+    /// # Arguments
+    /// * `params_json` - JSON: `{"primary_xpub":"...","emergency_xpub":"...","template":{...},"vault_index":0,
+    ///   "metadata_mode":"full"}`. `"metadata_mode"` is `"full"` (the default) to embed the
+    ///   metadata in its leaf or `"commitment"` to embed only its hash.
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// JSON with address, internal_key, scripts, metadata_mode, metadata_commitment and
+    /// metadata. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `params_json` must be a valid null-terminated C string.
+    fn ffi_generate_vault_address(
+        params_json: *const c_char,
+        network: i32,
+    ) -> *mut c_char {
+        let params_str = match ffi::from_c_string_bounded(params_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match Network::try_from(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
 
-    #[derive(serde::Deserialize)]
-    struct Params {
-        primary_xpub: String,
-        emergency_xpub: Option<String>,
-        template: VaultTemplate,
-        vault_index: u32,
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            primary_xpub: String,
+            emergency_xpub: Option<String>,
+            template: VaultTemplate,
+            vault_index: u32,
+            #[serde(default)]
+            metadata_mode: vault::MetadataMode,
+        }
+
+        let params: Params = match serde_json::from_str(&params_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid params JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        match taproot::generate_vault_address(
+            &params.primary_xpub,
+            params.emergency_xpub.as_deref(),
+            &params.template,
+            params.vault_index,
+            net,
+            params.metadata_mode,
+        ) {
+            Ok(result) => ffi::success_response(result),
+            Err(e) => ffi::error_response(e),
+        }
     }
+}
 
-    let params: Params = match serde_json::from_str(&params_str) {
-        Ok(p) => p,
-        Err(e) => {
-            return ffi::error_response(CoreError::InvalidInput(format!(
-                "Invalid params JSON: {}",
-                e
-            )))
+ffi_export! {
+    /// Get the deposit address for a vault index
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    /// * `vault_index` - Vault derivation index
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"address":"bc1p...","script_pubkey":"5120...","merkle_root":"...","vault_index":0}`
+    /// or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_get_address(
+        config_json: *const c_char,
+        vault_index: u32,
+        network: i32,
+    ) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            template: VaultTemplate,
+            owner_xpub: String,
+            recovery_xpub: String,
+            #[serde(default)]
+            tree_version: taproot::TreeVersion,
         }
-    };
 
-    match taproot::generate_vault_address(
-        &params.primary_xpub,
-        params.emergency_xpub.as_deref(),
-        &params.template,
-        params.vault_index,
-        net,
-    ) {
-        Ok(result) => ffi::success_response(result),
-        Err(e) => ffi::error_response(e),
+        let params: Params = match serde_json::from_str(&config_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid config JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let result = keys::parse_xpub(&params.owner_xpub, net).and_then(|owner| {
+            let recovery = keys::parse_xpub(&params.recovery_xpub, net)?;
+            taproot::vault_tree_versioned(&params.template, &owner, &recovery, vault_index, net, params.tree_version)
+        });
+
+        match result {
+            Ok(tree) => {
+                ffi::success_response(serde_json::json!({
+                    "address": tree.address(net).to_string(),
+                    "script_pubkey": hex::encode(tree.script_pubkey().as_bytes()),
+                    "merkle_root": tree.merkle_root().map(|root| root.to_string()),
+                    "vault_index": vault_index,
+                }))
+            }
+            Err(e) => ffi::error_response(e),
+        }
     }
 }
 
-/// Validate a Bitcoin address for a given network
-///
-/// # Arguments
-/// * `address` - Bitcoin address string
-/// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest)
-///
-/// # Returns
-/// JSON: `{"valid":true}` or error JSON
-///
-/// # Safety
-/// `address` must be a valid null-terminated C string.
-#[no_mangle]
-pub extern "C" fn ffi_validate_address(address: *const c_char, network: i32) -> *mut c_char {
-    let addr_str = match ffi::from_c_string(address) {
+/// Response of `vault_derive_addresses` and its cancellable variant
+fn derive_addresses_response(config_json: *const c_char, start: u32, count: u32, cancel: &cancel::CancelToken) -> *mut c_char {
+    let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
         Ok(s) => s,
         Err(e) => return ffi::error_response(e),
     };
-    let net = match Network::try_from(network) {
-        Ok(n) => n,
+
+    let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+        CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+    }) {
+        Ok(c) => c,
         Err(e) => return ffi::error_response(e),
     };
 
-    match taproot::validate_address(&addr_str, net) {
-        Ok(valid) => ffi::success_response(serde_json::json!({ "valid": valid })),
+    match taproot::derive_address_range_cancellable(&config, start, count, cancel) {
+        Ok(addresses) => ffi::success_response(addresses),
         Err(e) => ffi::error_response(e),
     }
 }
 
-/// Decode metadata from a Taproot metadata script leaf
-///
-/// # Arguments
-/// * `script_hex` - Hex-encoded metadata script
-///
-/// # Returns
-/// JSON VaultMetadata or error JSON
-///
-/// # Safety
-/// `script_hex` must be a valid null-terminated C string.
-#[no_mangle]
-pub extern "C" fn ffi_decode_metadata_leaf(script_hex: *const c_char) -> *mut c_char {
-    let hex_str = match ffi::from_c_string(script_hex) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-
-    match taproot::decode_metadata_from_script(&hex_str) {
-        Ok(metadata) => ffi::success_response(metadata),
-        Err(e) => ffi::error_response(e),
+ffi_export! {
+    /// Derive a run of deposit addresses, e.g. to scan for funds on restore
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `start` - First vault index
+    /// * `count` - Number of addresses, at most 10000
+    ///
+    /// # Returns
+    /// JSON: `[{"index":0,"address":"bc1p...","script_pubkey":"5120..."},...]`
+    /// or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_derive_addresses(config_json: *const c_char, start: u32, count: u32) -> *mut c_char {
+        derive_addresses_response(config_json, start, count, &cancel::CancelToken::new())
     }
 }
 
-// ═══════════════════════════════════════════════════════════════════
-//                    TRANSACTION BUILDING FFI
-// ═══════════════════════════════════════════════════════════════════
+ffi_export! {
+    /// `vault_derive_addresses()`, stopped early by `vault_cancel(token)`
+    ///
+    /// # Returns
+    /// As `vault_derive_addresses()`, or error JSON with code 4005 once
+    /// `token` is tripped.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string; `token` must
+    /// come from `vault_cancel_token_new()` and stay alive until this returns.
+    fn vault_derive_addresses_cancellable(
+        config_json: *const c_char,
+        start: u32,
+        count: u32,
+        token: *const ffi::CancelTokenHandle,
+    ) -> *mut c_char {
+        match ffi::CancelTokenHandle::from_ptr(token) {
+            Ok(token) => derive_addresses_response(config_json, start, count, token.token()),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
 
-/// Build PSBT for delayed spend (script-path with CSV timelock)
-///
-/// # Arguments
-/// * `intent_json` - JSON SpendIntent: `{"destination":"...","fee_rate":5.0}`
-/// * `utxos_json` - JSON array of Utxo: `[{"txid":"...","vout":0,"amount_sats":100000}]`
-/// * `vault_json` - JSON VaultConfig
-///
-/// # Returns
-/// JSON PsbtResult with base64 PSBT and summary. Must be freed with `free_rust_string()`.
-///
-/// # Safety
-/// All pointer arguments must be valid null-terminated C strings.
-#[no_mangle]
-pub extern "C" fn ffi_build_delayed_spend_psbt(
-    intent_json: *const c_char,
-    utxos_json: *const c_char,
-    vault_json: *const c_char,
-) -> *mut c_char {
-    let intent_str = match ffi::from_c_string(intent_json) {
+/// Response of `vault_find_address_index` and its cancellable variant
+fn find_address_index_response(config_json: *const c_char, address: *const c_char, gap_limit: u32, cancel: &cancel::CancelToken) -> *mut c_char {
+    let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
         Ok(s) => s,
         Err(e) => return ffi::error_response(e),
     };
-    let utxos_str = match ffi::from_c_string(utxos_json) {
+    let address = match ffi::from_c_string(address) {
         Ok(s) => s,
         Err(e) => return ffi::error_response(e),
     };
-    let vault_str = match ffi::from_c_string(vault_json) {
-        Ok(s) => s,
+
+    let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+        CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+    }) {
+        Ok(c) => c,
         Err(e) => return ffi::error_response(e),
     };
 
-    let intent: transaction::SpendIntent = match serde_json::from_str(&intent_str) {
-        Ok(i) => i,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid intent: {}", e))),
-    };
-    let utxos: Vec<transaction::Utxo> = match serde_json::from_str(&utxos_str) {
-        Ok(u) => u,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid utxos: {}", e))),
-    };
-    let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
-        Ok(v) => v,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
-    };
+    let result = vault::Vault::from_config(&config).and_then(|vault| {
+        let address = vault::policy::validate_address(&address, vault.network())?;
+        match gap_limit.checked_sub(1) {
+            Some(max_index) => vault::verify_address_cancellable(&vault, &address, max_index, cancel),
+            None => Ok(None),
+        }
+    });
 
-    match transaction::build_delayed_spend_psbt(&intent, &utxos, &vault) {
-        Ok(result) => ffi::success_response(result),
+    match result {
+        Ok(index) => ffi::success_response(serde_json::json!({
+            "found": index.is_some(),
+            "index": index,
+        })),
         Err(e) => ffi::error_response(e),
     }
 }
 
-/// Build PSBT for emergency key-path spend (no delay)
-///
-/// # Arguments
-/// * `params_json` - JSON: `{"destination":"...","fee_rate":5.0}`
-/// * `utxos_json` - JSON array of Utxo
-/// * `vault_json` - JSON VaultConfig
-///
-/// # Returns
-/// JSON PsbtResult. Must be freed with `free_rust_string()`.
-///
-/// # Safety
-/// All pointer arguments must be valid null-terminated C strings.
-#[no_mangle]
-pub extern "C" fn ffi_build_emergency_psbt(
-    params_json: *const c_char,
-    utxos_json: *const c_char,
-    vault_json: *const c_char,
-) -> *mut c_char {
-    let params_str = match ffi::from_c_string(params_json) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-    let utxos_str = match ffi::from_c_string(utxos_json) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-    let vault_str = match ffi::from_c_string(vault_json) {
-        Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
-    };
-
-    #[derive(serde::Deserialize)]
-    struct Params {
-        destination: String,
-        fee_rate: f64,
+ffi_export! {
+    /// Find the vault index an address belongs to
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `address` - Address to look for
+    /// * `gap_limit` - Number of indices to scan from 0, at most 10000
+    ///
+    /// # Returns
+    /// JSON: `{"found":true,"index":42}`, or `{"found":false,"index":null}` if no
+    /// index below `gap_limit` matches, or error JSON. An address for
+    /// another network fails with code 1003. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` and `address` must be valid null-terminated C strings.
+    fn vault_find_address_index(config_json: *const c_char, address: *const c_char, gap_limit: u32) -> *mut c_char {
+        find_address_index_response(config_json, address, gap_limit, &cancel::CancelToken::new())
     }
+}
 
-    let params: Params = match serde_json::from_str(&params_str) {
-        Ok(p) => p,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid params: {}", e))),
-    };
-    let utxos: Vec<transaction::Utxo> = match serde_json::from_str(&utxos_str) {
-        Ok(u) => u,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid utxos: {}", e))),
-    };
-    let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
-        Ok(v) => v,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
-    };
-
-    match transaction::build_emergency_psbt(&params.destination, params.fee_rate, &utxos, &vault) {
-        Ok(result) => ffi::success_response(result),
-        Err(e) => ffi::error_response(e),
+ffi_export! {
+    /// `vault_find_address_index()`, stopped early by `vault_cancel(token)`
+    ///
+    /// # Returns
+    /// As `vault_find_address_index()`, or error JSON with code 4005 once
+    /// `token` is tripped.
+    ///
+    /// # Safety
+    /// `config_json` and `address` must be valid null-terminated C strings; `token` must
+    /// come from `vault_cancel_token_new()` and stay alive until this returns.
+    fn vault_find_address_index_cancellable(
+        config_json: *const c_char,
+        address: *const c_char,
+        gap_limit: u32,
+        token: *const ffi::CancelTokenHandle,
+    ) -> *mut c_char {
+        match ffi::CancelTokenHandle::from_ptr(token) {
+            Ok(token) => find_address_index_response(config_json, address, gap_limit, token.token()),
+            Err(e) => ffi::error_response(e),
+        }
     }
 }
 
-/// Verify PSBT matches vault policy
-///
-/// # Arguments
-/// * `psbt_base64` - Base64-encoded PSBT
-/// * `vault_json` - JSON VaultConfig
-///
-/// # Returns
-/// JSON PolicyCheck: `{"valid":true,"warnings":[],"errors":[]}`
-///
-/// # Safety
-/// All pointer arguments must be valid null-terminated C strings.
-#[no_mangle]
-pub extern "C" fn ffi_verify_psbt_policy(
-    psbt_base64: *const c_char,
-    vault_json: *const c_char,
-) -> *mut c_char {
-    let psbt_str = match ffi::from_c_string(psbt_base64) {
+/// Response of `vault_scan_indices` and its cancellable variant
+fn scan_indices_response(config_json: *const c_char, spks_json: *const c_char, gap_limit: u32, cancel: &cancel::CancelToken) -> *mut c_char {
+    let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
         Ok(s) => s,
         Err(e) => return ffi::error_response(e),
     };
-    let vault_str = match ffi::from_c_string(vault_json) {
+    let spks_str = match ffi::from_c_string_bounded(spks_json, ffi::MAX_JSON_INPUT_LEN) {
         Ok(s) => s,
         Err(e) => return ffi::error_response(e),
     };
 
-    let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
-        Ok(v) => v,
-        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
+    let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+        CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+    }) {
+        Ok(c) => c,
+        Err(e) => return ffi::error_response(e),
     };
-
-    match transaction::verify_psbt_policy(&psbt_str, &vault) {
-        Ok(check) => ffi::success_response(check),
-        Err(e) => ffi::error_response(e),
-    }
-}
-
-/// Finalize a signed PSBT and extract raw transaction
-///
-/// # Arguments
-/// * `signed_psbt_base64` - Base64-encoded signed PSBT
-///
-/// # Returns
-/// JSON: `{"tx_hex":"...","txid":"...","vsize":...}`
-///
-/// # Safety
-/// `signed_psbt_base64` must be a valid null-terminated C string.
-#[no_mangle]
-pub extern "C" fn ffi_finalize_psbt(signed_psbt_base64: *const c_char) -> *mut c_char {
-    let psbt_str = match ffi::from_c_string(signed_psbt_base64) {
+    let encoded: Vec<String> = match serde_json::from_str(&spks_str) {
         Ok(s) => s,
-        Err(e) => return ffi::error_response(e),
+        Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid scriptPubKey list JSON: {}", e))),
     };
 
-    match transaction::finalize_psbt(&psbt_str) {
-        Ok(result) => ffi::success_response(result),
+    let result = encoded
+        .iter()
+        .map(|spk| {
+            hex::decode(spk)
+                .map(bitcoin::ScriptBuf::from)
+                .map_err(|e| CoreError::InvalidInput(format!("Invalid scriptPubKey hex '{}': {}", spk, e)))
+        })
+        .collect::<CoreResult<std::collections::HashSet<_>>>()
+        .and_then(|spks| {
+            let vault = vault::Vault::from_config(&config)?;
+            vault::scan::discover_indices_cancellable(&vault, &spks, gap_limit, cancel)
+        });
+
+    match result {
+        Ok(scan) => ffi::success_response(scan),
         Err(e) => ffi::error_response(e),
     }
 }
 
-// ═══════════════════════════════════════════════════════════════════
-//                         UTILITIES FFI
-// ═══════════════════════════════════════════════════════════════════
-
-/// Convert block count to estimated time string
-///
-/// # Arguments
-/// * `blocks` - Number of blocks
-///
-/// # Returns
-/// Human-readable time estimate (e.g., "~7 days"). Must be freed with `free_rust_string()`.
-///
-/// # Safety
-/// This function is safe to call from any context.
-#[no_mangle]
-pub extern "C" fn ffi_blocks_to_time_estimate(blocks: u32) -> *mut c_char {
-    let minutes = blocks as u64 * 10;
-    let estimate = if minutes < 60 {
-        format!("~{} minutes", minutes)
-    } else if minutes < 1440 {
-        let hours = minutes / 60;
-        format!("~{} hour{}", hours, if hours == 1 { "" } else { "s" })
-    } else {
-        let days = minutes / 1440;
-        format!("~{} day{}", days, if days == 1 { "" } else { "s" })
-    };
-    ffi::to_c_string(&estimate)
+ffi_export! {
+    /// Find the vault indices in use, for a restored wallet
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `spks_json` - JSON array of hex scriptPubKeys known to be funded;
+    ///   ones that belong to no vault index are ignored
+    /// * `gap_limit` - Consecutive unused indices that end the scan, 1 to 10000
+    ///
+    /// # Returns
+    /// JSON: `{"used_indices":[0,3],"highest_used":3,"next_index":4}`, with
+    /// `"highest_used":null` and `"next_index":0` if no index is used, or error
+    /// JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` and `spks_json` must be valid null-terminated C strings.
+    fn vault_scan_indices(config_json: *const c_char, spks_json: *const c_char, gap_limit: u32) -> *mut c_char {
+        scan_indices_response(config_json, spks_json, gap_limit, &cancel::CancelToken::new())
+    }
 }
 
-/// Calculate the absolute block height when a CSV timelock unlocks
-///
-/// # Arguments
-/// * `current_height` - Current blockchain height
-/// * `delay_blocks` - CSV delay in blocks
-///
-/// # Returns
-/// The block height at which spending becomes possible.
-#[no_mangle]
-pub extern "C" fn ffi_calculate_unlock_height(current_height: u32, delay_blocks: u32) -> u32 {
-    current_height.saturating_add(delay_blocks)
+ffi_export! {
+    /// `vault_scan_indices()`, stopped early by `vault_cancel(token)`
+    ///
+    /// # Returns
+    /// As `vault_scan_indices()`, or error JSON with code 4005 once
+    /// `token` is tripped.
+    ///
+    /// # Safety
+    /// `config_json` and `spks_json` must be valid null-terminated C strings; `token` must
+    /// come from `vault_cancel_token_new()` and stay alive until this returns.
+    fn vault_scan_indices_cancellable(
+        config_json: *const c_char,
+        spks_json: *const c_char,
+        gap_limit: u32,
+        token: *const ffi::CancelTokenHandle,
+    ) -> *mut c_char {
+        match ffi::CancelTokenHandle::from_ptr(token) {
+            Ok(token) => scan_indices_response(config_json, spks_json, gap_limit, token.token()),
+            Err(e) => ffi::error_response(e),
+        }
+    }
 }
 
-// ═══════════════════════════════════════════════════════════════════
-//                         UNIT TESTS
-// ═══════════════════════════════════════════════════════════════════
+ffi_export! {
+    /// Create a vault: everything a host needs to receive funds at one index
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...",
+    ///   "recovery_xpub":"...","vault_index":0,"used_indices":[1,2],"tree_version":2}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    ///   `"tree_version"` (optional, default `TreeVersion::LATEST`) selects
+    ///   the leaf scripts, see `taproot::TreeVersion`; older versions are
+    ///   for recreating vaults made with them.
+    ///   `"used_indices"` (optional) lists the indices already used for
+    ///   these xpubs (see `vault::registry::IndexLedger`); `"vault_index"`
+    ///   may then be omitted to take the lowest free one.
+    ///
+    /// # Returns
+    /// JSON: `{"network":"mainnet","vault_index":0,"address":"bc1p...","script_pubkey":"5120...",
+    /// "internal_key":"...","merkle_root":"...","metadata_hex":"...","metadata_commitment":"...",
+    /// "descriptor":"tr(...)#...","used_indices":[0,1,2]}`, where `"vault_index"` is the index
+    /// assigned and `"used_indices"`, present only when given, now includes it for the host to persist.
+    /// `"descriptor"` is null for a `"tree_version"` 1 vault, which has none;
+    /// or error JSON. The config is checked by `vault::VaultBuilder`:
+    /// malformed JSON fails with code 4001, bad xpubs with 1001, xpubs for
+    /// another network with 1003, an invalid template, a key used twice
+    /// or an index in `"used_indices"` with 2003, a hardened index with
+    /// 4002 and a missing network before `vault_init()` with 4003.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_create(config_json: *const c_char) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::ffi::CStr;
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            #[serde(flatten)]
+            config: vault::VaultConfig,
+            vault_index: Option<u32>,
+            #[serde(default)]
+            used_indices: Option<vault::registry::IndexLedger>,
+        }
 
-    #[test]
-    fn test_vault_version() {
-        let version_ptr = vault_version();
-        assert!(!version_ptr.is_null());
+        let params: Params = match ffi::parse_request(&config_str, |e| {
+            CoreError::SerializationError(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(p) => p,
+            Err(e) => return ffi::error_response(e),
+        };
 
-        unsafe {
-            let version_cstr = CStr::from_ptr(version_ptr);
-            let version = version_cstr.to_str().unwrap();
+        let mut builder = vault::VaultBuilder::from_config(&params.config);
+        match (params.vault_index, &params.used_indices) {
+            (Some(index), _) => builder = builder.index(index),
+            (None, None) => {
+                return ffi::error_response(CoreError::SerializationError(
+                    "Invalid config JSON: missing field `vault_index`".to_string(),
+                ))
+            }
+            (None, Some(_)) => {}
+        }
+        if let Some(ledger) = &params.used_indices {
+            builder = builder.ledger(ledger);
+        }
 
-            assert!(version.contains('.'));
-            assert_eq!(version, env!("CARGO_PKG_VERSION"));
+        let result = builder
+            .build()
+            .and_then(|vault| {
+                let tree = vault.tree();
+                let mut response = serde_json::json!({
+                    "network": vault.network(),
+                    "vault_index": vault.index(),
+                    "address": vault.address().to_string(),
+                    "script_pubkey": hex::encode(vault.script_pubkey().as_bytes()),
+                    "internal_key": tree.internal_key().to_string(),
+                    "merkle_root": tree.merkle_root().map(|root| root.to_string()),
+                    "metadata_hex": hex::encode(vault.metadata().to_bytes()),
+                    "metadata_commitment": hex::encode(vault.metadata().commitment()),
+                    "descriptor": vault.tree_version().has_descriptor().then(|| vault.descriptor()).transpose()?,
+                });
+                if let Some(ledger) = vault.ledger() {
+                    response["used_indices"] = serde_json::json!(ledger);
+                }
+                Ok(response)
+            });
 
-            free_rust_string(version_ptr);
+        match result {
+            Ok(response) => ffi::success_response(response),
+            Err(e) => ffi::error_response(e),
         }
     }
+}
 
-    #[test]
-    fn test_vault_init_valid_networks() {
-        assert_eq!(vault_init(0), 0);
-        assert_eq!(vault_init(1), 0);
-        assert_eq!(vault_init(2), 0);
-        assert_eq!(vault_init(3), 0);
+ffi_export! {
+    /// Export a watch-only wallet file for other wallet software
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `format` - Wallet format (0=Sparrow/Specter JSON, 1=Ledger wallet policy JSON,
+    ///   2=Coldcard import file, see `vault::export::WalletFormat`)
+    ///
+    /// # Returns
+    /// The wallet file's contents, or error JSON. An unknown format fails
+    /// with code 4002. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_export_wallet(config_json: *const c_char, format: i32) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = vault::export::WalletFormat::try_from(format).and_then(|format| {
+            let vault = vault::Vault::from_config(&config)?;
+            vault::export::export_wallet(&vault, format)
+        });
+
+        match result {
+            Ok(file) => ffi::to_c_string(&file),
+            Err(e) => ffi::error_response(e),
+        }
     }
+}
 
-    #[test]
-    fn test_vault_init_invalid_network() {
-        assert_eq!(vault_init(4), -1);
-        assert_eq!(vault_init(-1), -1);
-        assert_eq!(vault_init(999), -1);
+ffi_export! {
+    /// BIP21 payment URI for a deposit QR code
+    ///
+    /// See `vault::export::bip21_uri()`. The address must belong to the
+    /// network selected by `vault_init()`.
+    ///
+    /// # Arguments
+    /// * `address` - Deposit address
+    /// * `amount_sats` - Requested amount in satoshis, or 0 for none
+    /// * `label` - Label for the payer's wallet; null or empty for none
+    ///
+    /// # Returns
+    /// The URI, e.g. `bitcoin:bc1p...?amount=0.5&label=Savings`, or error JSON.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `address` must be a valid null-terminated C string; `label` must be
+    /// one or null.
+    fn vault_bip21_uri(address: *const c_char, amount_sats: u64, label: *const c_char) -> *mut c_char {
+        let address = match ffi::from_c_string(address) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let label = if label.is_null() {
+            None
+        } else {
+            match ffi::from_c_string(label) {
+                Ok(s) => Some(s),
+                Err(e) => return ffi::error_response(e),
+            }
+        };
+
+        let result = ffi::network_arg(-1)
+            .and_then(|network| vault::policy::validate_address(&address, network));
+
+        match result {
+            Ok(address) => {
+                let amount_sats = Some(amount_sats).filter(|&amount| amount > 0);
+                ffi::to_c_string(&vault::export::bip21_uri(&address, amount_sats, label.as_deref(), None))
+            }
+            Err(e) => ffi::error_response(e),
+        }
     }
+}
 
-    #[test]
-    fn test_free_rust_string_null_is_safe() {
-        free_rust_string(std::ptr::null_mut());
+ffi_export! {
+    /// Where an unvault stands at `current_height`
+    ///
+    /// # Arguments
+    /// * `state_json` - JSON: `{"trigger_txid":"...","broadcast_height":800000,
+    ///   "confirmation_height":800001,"delay_blocks":1008}`; `"confirmation_height"`
+    ///   is null or omitted while the trigger is unconfirmed
+    /// * `current_height` - Height of the chain tip
+    ///
+    /// # Returns
+    /// JSON: `{"status":"pending"}`, `{"status":"waiting","blocks_left":12}` or
+    /// `{"status":"spendable"}`, or error JSON. `"spendable"` means the completing
+    /// spend is valid in the next block (see `vault::UnvaultState::blocks_remaining()`).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `state_json` must be a valid null-terminated C string.
+    fn vault_unvault_status(state_json: *const c_char, current_height: u32) -> *mut c_char {
+        let state_str = match ffi::from_c_string_bounded(state_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let request: UnvaultStatusRequest = match serde_json::from_str(&state_str) {
+            Ok(r) => r,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid state JSON: {}", e))),
+        };
+
+        ffi::success_response(request.state.status(request.delay_blocks, current_height))
     }
+}
 
-    #[test]
-    fn test_blocks_to_time_estimate() {
-        unsafe {
-            // 6 blocks = ~60 minutes = ~1 hour
-            let ptr = ffi_blocks_to_time_estimate(6);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "~1 hour");
-            free_rust_string(ptr);
+ffi_export! {
+    /// Find spends of watched vault outputs in a raw transaction
+    ///
+    /// # Arguments
+    /// * `tx_hex` - Raw transaction, hex-encoded
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `outpoints_json` - JSON array of watched outpoints: `["<txid>:<vout>",...]`
+    ///
+    /// # Returns
+    /// JSON array with one entry per input spending a watched outpoint:
+    /// `[{"input_index":0,"outpoint":"<txid>:<vout>","vault_index":3,"path":"timelock",
+    /// "destinations":[{"address":"bc1p...","script_pubkey":"5120...","amount_sats":90000}]}]`,
+    /// or error JSON. `"path"` is one of `"timelock"`, `"whitelist_timelock"`,
+    /// `"emergency"`, `"multisig"`, `"inheritance"`, `"key_path"` or `"unknown"`;
+    /// see `vault::watch::classify_spend()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// All arguments must be valid null-terminated C strings.
+    fn vault_classify_tx(tx_hex: *const c_char, config_json: *const c_char, outpoints_json: *const c_char) -> *mut c_char {
+        let tx_hex = match ffi::from_c_string(tx_hex) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let outpoints_str = match ffi::from_c_string_bounded(outpoints_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
 
-            // 144 blocks = ~1 day
-            let ptr = ffi_blocks_to_time_estimate(144);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "~1 day");
-            free_rust_string(ptr);
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+        let outpoints: Vec<bitcoin::OutPoint> = match serde_json::from_str(&outpoints_str) {
+            Ok(o) => o,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid outpoints JSON: {}", e))),
+        };
 
-            // 1008 blocks = ~7 days
-            let ptr = ffi_blocks_to_time_estimate(1008);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "~7 days");
-            free_rust_string(ptr);
+        let result = vault::Vault::from_config(&config)
+            .and_then(|vault| vault::watch::classify_spend(&tx_hex, &vault, &outpoints));
 
-            // 3 blocks = ~30 minutes
-            let ptr = ffi_blocks_to_time_estimate(3);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "~30 minutes");
-            free_rust_string(ptr);
+        match result {
+            Ok(events) => ffi::success_response(events),
+            Err(e) => ffi::error_response(e),
         }
     }
+}
 
-    #[test]
-    fn test_calculate_unlock_height() {
-        assert_eq!(ffi_calculate_unlock_height(800_000, 1008), 801_008);
-        assert_eq!(ffi_calculate_unlock_height(800_000, 144), 800_144);
-        assert_eq!(ffi_calculate_unlock_height(u32::MAX, 1), u32::MAX); // saturating
-    }
-
-    #[test]
-    fn test_ffi_validate_xpub() {
-        let xpub = std::ffi::CString::new(
-            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
-        ).unwrap();
+ffi_export! {
+    /// Electrum-protocol script hashes of a run of vault addresses, for a
+    /// watchtower subscribing with `blockchain.scripthash.subscribe`
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `start` - First vault index
+    /// * `count` - Number of indices, at most 10000
+    ///
+    /// # Returns
+    /// JSON: `[{"index":0,"script_hash":"<hex>"},...]`, each hash the reversed
+    /// SHA256 of the index's scriptPubKey (see `vault::watch::electrum_script_hashes()`),
+    /// or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_electrum_hashes(config_json: *const c_char, start: u32, count: u32) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
 
-        unsafe {
-            let result_ptr = ffi_validate_xpub(xpub.as_ptr(), 0);
-            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
-            let result: serde_json::Value = serde_json::from_str(result_str).unwrap();
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+        let Some(end) = start.checked_add(count) else {
+            return ffi::error_response(CoreError::InvalidInput(format!(
+                "Index range {}+{} overflows",
+                start, count
+            )));
+        };
 
-            assert!(result.get("error").is_none());
-            assert_eq!(result["fingerprint"], "3442193e");
-            assert_eq!(result["supports_taproot"], true);
+        let result = vault::Vault::from_config(&config)
+            .and_then(|vault| vault::watch::electrum_script_hashes(&vault, start..end));
 
-            free_rust_string(result_ptr);
+        match result {
+            Ok(hashes) => ffi::success_response(
+                hashes
+                    .into_iter()
+                    .map(|(index, script_hash)| serde_json::json!({"index": index, "script_hash": script_hash}))
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => ffi::error_response(e),
         }
     }
+}
 
-    #[test]
-    fn test_ffi_get_derivation_path() {
-        unsafe {
-            let ptr = ffi_get_derivation_path(0, 0);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "m/86'/0'/0'/0/0");
-            free_rust_string(ptr);
+ffi_export! {
+    /// First MuSig2 round: generate this signer's nonce pair
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"keys":["<33-byte compressed hex>",...],"merkle_root":"<hex>",
+    ///   "message":"<sighash hex>","secret_key":"<hex>"}`. The BIP327 aggregate of
+    ///   `"keys"` is tweaked into the P2TR output key for `"merkle_root"`, which may
+    ///   be omitted for an output without scripts.
+    ///
+    /// # Returns
+    /// JSON: `{"public_nonce":"<hex>"}`, or error JSON. The public nonce goes to
+    /// every cosigner. The secret nonce stays in the library, bound to this key,
+    /// aggregate key and message, until `vault_musig_partial_sign()` uses it;
+    /// only the 256 most recent are kept. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_musig_nonce(request_json: *const c_char) -> *mut c_char {
+        let request: MusigNonceRequest = match parse_musig_request(request_json) {
+            Ok(r) => r,
+            Err(e) => return ffi::error_response(e),
+        };
 
-            let ptr = ffi_get_derivation_path(5, 1);
-            let s = CStr::from_ptr(ptr).to_str().unwrap();
-            assert_eq!(s, "m/86'/1'/0'/0/5");
-            free_rust_string(ptr);
+        let result = request.session.agg_key().and_then(|agg_key| {
+            keys::musig::generate_nonce(&request.secret_key.secret_key(), &agg_key, &request.session.message()?)
+        });
+
+        match result {
+            Ok((secret_nonce, public_nonce)) => {
+                ffi::store_musig_nonce(secret_nonce);
+                ffi::success_response(serde_json::json!({"public_nonce": public_nonce}))
+            }
+            Err(e) => ffi::error_response(e),
         }
     }
+}
 
-    #[test]
-    fn test_ffi_generate_vault_address() {
-        let params = serde_json::json!({
-            "primary_xpub": "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
-            "template": {"type": "savings"},
-            "vault_index": 0
+ffi_export! {
+    /// Second MuSig2 round: sign with this signer's key once every nonce is in
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: the `vault_musig_nonce()` request plus
+    ///   `"public_nonces":["<hex>",...]` from every signer, this one included
+    ///
+    /// # Returns
+    /// JSON: `{"partial_signature":"<hex>"}`, or error JSON (4002 if the key isn't
+    /// part of the session or no stored nonce matches it). The nonce is used up
+    /// even when signing fails, so a retry starts again from `vault_musig_nonce()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_musig_partial_sign(request_json: *const c_char) -> *mut c_char {
+        let request: MusigSignRequest = match parse_musig_request(request_json) {
+            Ok(r) => r,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let secret_key = request.secret_key.secret_key();
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let public_key = bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &secret_key);
+        let Some(secret_nonce) = ffi::take_musig_nonce(&public_key, &request.public_nonces) else {
+            return ffi::error_response(CoreError::InvalidInput(
+                "No unused MuSig2 nonce from vault_musig_nonce() for this key".to_string(),
+            ));
+        };
+
+        let result = request.session.agg_key().and_then(|agg_key| {
+            keys::musig::partial_sign(
+                secret_nonce,
+                &secret_key,
+                &agg_key,
+                &request.public_nonces,
+                &request.session.message()?,
+            )
         });
-        let params_cstr = std::ffi::CString::new(params.to_string()).unwrap();
 
-        unsafe {
-            let result_ptr = ffi_generate_vault_address(params_cstr.as_ptr(), 0);
-            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
-            let result: serde_json::Value = serde_json::from_str(result_str).unwrap();
+        match result {
+            Ok(partial_signature) => ffi::success_response(serde_json::json!({
+                "partial_signature": partial_signature,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
 
-            assert!(result.get("error").is_none(), "Got error: {}", result_str);
-            assert!(result["address"].as_str().unwrap().starts_with("bc1p"));
+ffi_export! {
+    /// Combine every signer's partial signature into a BIP340 signature
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"keys":[...],"merkle_root":"<hex>","message":"<hex>",
+    ///   "public_nonces":["<hex>",...],"partial_signatures":["<hex>",...]}`
+    ///
+    /// # Returns
+    /// JSON: `{"signature":"<64-byte hex>"}`, valid for the tweaked aggregate key,
+    /// or error JSON (4002 if the shares don't combine to a valid signature).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_musig_aggregate(request_json: *const c_char) -> *mut c_char {
+        let request: MusigAggregateRequest = match parse_musig_request(request_json) {
+            Ok(r) => r,
+            Err(e) => return ffi::error_response(e),
+        };
 
-            free_rust_string(result_ptr);
+        let result = request.session.agg_key().and_then(|agg_key| {
+            keys::musig::aggregate_partial_sigs(
+                &agg_key,
+                &request.public_nonces,
+                &request.session.message()?,
+                &request.partial_signatures,
+            )
+        });
+
+        match result {
+            Ok(signature) => ffi::success_response(serde_json::json!({
+                "signature": hex::encode(signature.as_ref()),
+            })),
+            Err(e) => ffi::error_response(e),
         }
     }
 }
+
+/// `vault_unvault_status()` input: the unvault and the delay it waits out
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnvaultStatusRequest {
+    #[serde(flatten)]
+    state: vault::UnvaultState,
+    delay_blocks: u32,
+}
+
+/// MuSig2 signing session fields shared by the `vault_musig_*` exports
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MusigSession {
+    keys: Vec<bitcoin::secp256k1::PublicKey>,
+    #[serde(default)]
+    merkle_root: Option<String>,
+    message: String,
+}
+
+impl MusigSession {
+    /// Aggregate of `keys`, tweaked into the P2TR output key
+    fn agg_key(&self) -> CoreResult<keys::musig::AggregatedKey> {
+        let merkle_root = self
+            .merkle_root
+            .as_deref()
+            .map(|root| parse_hex_array(root, "merkle root").map(<bitcoin::taproot::TapNodeHash as bitcoin::hashes::Hash>::from_byte_array))
+            .transpose()?;
+        keys::musig::aggregate_keys(&self.keys)?.with_taproot_tweak(merkle_root)
+    }
+
+    fn message(&self) -> CoreResult<[u8; 32]> {
+        parse_hex_array(&self.message, "message")
+    }
+}
+
+fn parse_hex_array<const N: usize>(hex_str: &str, what: &str) -> CoreResult<[u8; N]> {
+    hex::decode(hex_str)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| CoreError::InvalidInput(format!("Invalid {}: expected {} hex-encoded bytes", what, N)))
+}
+
+fn parse_musig_request<T: serde::de::DeserializeOwned>(request_json: *const c_char) -> CoreResult<T> {
+    let mut request_str = ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN)?;
+    let request = serde_json::from_str(&request_str)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid MuSig2 request JSON: {}", e)));
+    keys::wipe_string(&mut request_str);
+    request
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MusigNonceRequest {
+    #[serde(flatten)]
+    session: MusigSession,
+    secret_key: keys::SecretMaterial,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MusigSignRequest {
+    #[serde(flatten)]
+    session: MusigSession,
+    secret_key: keys::SecretMaterial,
+    public_nonces: Vec<keys::musig::PublicNonce>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MusigAggregateRequest {
+    #[serde(flatten)]
+    session: MusigSession,
+    public_nonces: Vec<keys::musig::PublicNonce>,
+    partial_signatures: Vec<keys::musig::PartialSignature>,
+}
+
+ffi_export! {
+    /// Restore a watch-only vault from its descriptor and metadata backup
+    ///
+    /// Uses the network selected by `vault_init()`. See `vault::restore()`.
+    ///
+    /// # Arguments
+    /// * `descriptor` - Descriptor from `vault_create()` or `vault_export_descriptor()`
+    /// * `metadata_hex` - Metadata bytes, as `metadata_hex` from `vault_create()`
+    ///
+    /// # Returns
+    /// JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"...",
+    /// "vault_index":0,"address":"bc1p...","script_pubkey":"5120..."}`, usable as the
+    /// vault config of other exports, or error JSON. A metadata delay that
+    /// disagrees with the descriptor fails with code 3002, a call before
+    /// `vault_init()` with 4003. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `descriptor` and `metadata_hex` must be valid null-terminated C strings.
+    fn vault_restore(descriptor: *const c_char, metadata_hex: *const c_char) -> *mut c_char {
+        let descriptor = match ffi::from_c_string(descriptor) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let metadata_hex = match ffi::from_c_string(metadata_hex) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = ffi::network_arg(-1)
+            .and_then(|network| vault::restore(&descriptor, &metadata_hex, network));
+
+        match result {
+            Ok(vault) => ffi::success_response(serde_json::json!({
+                "network": vault.network(),
+                "template": vault.template(),
+                "owner_xpub": vault.owner_xpub().to_string(),
+                "recovery_xpub": vault.recovery_xpub().to_string(),
+                "vault_index": vault.index(),
+                "address": vault.address().to_string(),
+                "script_pubkey": hex::encode(vault.script_pubkey().as_bytes()),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Plain-language summary of a vault's rules, to confirm before funding it
+    ///
+    /// See `vault::describe()`. Every spend path and warning carries the
+    /// structured facts behind its English text, for hosts to localize.
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON VaultConfig
+    ///
+    /// # Returns
+    /// JSON: `{"template_id":"savings_v1","spend_paths":[{"leaf":"timelock","signers":["3442193e"],
+    /// "threshold":1,"delay":1008,"delay_unit":"blocks","duration":"~7 days","description":
+    /// "funds can be spent by owner key 3442193e after 1008 blocks (~7 days)"},...],
+    /// "recovery":"...","whitelist_size":null,"warnings":[{"kind":"no_recovery_path"}]}`
+    /// or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_describe(config_json: *const c_char) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match vault::Vault::from_config(&config) {
+            Ok(vault) => ffi::success_response(vault::describe(&vault)),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Every leaf script of a vault, disassembled for audit display
+    ///
+    /// See `Vault::leaf_listing()` and `taproot::disassemble()`.
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON VaultConfig
+    ///
+    /// # Returns
+    /// JSON: `[{"purpose":"timelock","leaf_hash":"...","depth":1,"control_block_size":65,"script":"02f003b26920...ac",
+    /// "tokens":["f003[2]","OP_CSV","OP_VERIFY","79be...[32]","OP_CHECKSIG"],
+    /// "asm":"f003[2] OP_CSV OP_VERIFY 79be...[32] OP_CHECKSIG"},...]` or error JSON.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_list_leaves(config_json: *const c_char) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match vault::Vault::from_config(&config).and_then(|vault| vault.leaf_listing()) {
+            Ok(leaves) => ffi::success_response(leaves),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// List the vault templates, for template pickers
+    ///
+    /// # Returns
+    /// JSON: `[{"template_id":"savings_v1","type":"savings","name":"Savings","delay_blocks":1008,
+    /// "recovery_types":["emergency_key"],"parameters":[{"name":"delay_blocks","required":false,
+    /// "kind":"integer","min":1,"max":65535,"default":1008}]},...]`, generated by
+    /// `VaultTemplate::catalog()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn vault_list_templates() -> *mut c_char {
+        ffi::success_response(VaultTemplate::catalog())
+    }
+}
+
+ffi_export! {
+    /// Fixed test vectors for the bindings' test suites
+    ///
+    /// Addresses at indexes 0-4 on every network, encoded metadata, a
+    /// descriptor and an unsigned unvault PSBT with its sighashes, all
+    /// derived from hard-coded xpubs (see `test_vectors::generate()`).
+    /// The document is identical on every call. Only exported with the
+    /// `test-vectors` feature (`FEATURE_TEST_VECTORS`).
+    ///
+    /// # Returns
+    /// JSON: `{"template":{...},"networks":[{"network":"mainnet","owner_xpub":"xpub...",
+    /// "recovery_xpub":"xpub...","addresses":["bc1p...",...]},...],"metadata":[{"template":{...},
+    /// "metadata_hex":"..."},...],"descriptor":"tr(...)#...","unvault":{"outpoint":"...:0",
+    /// "amount_sats":100000,"destination":"bcrt1q...","fee_rate":2,"psbt_base64":"...",
+    /// "sighashes":[...]}}` or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    #[cfg(feature = "test-vectors")]
+    fn vault_generate_test_vectors() -> *mut c_char {
+        match test_vectors::generate() {
+            Ok(vectors) => ffi::success_response(vectors),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Export the vault as a descriptor for Bitcoin Core's `importdescriptors`
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"template":{...},"owner_xpub":"...","recovery_xpub":"..."}`,
+    ///   optionally with `"range_end"` (last index to watch, default 999) and
+    ///   `"timestamp"` (rescan start as a UNIX time, default `"now"`)
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `[{"desc":"tr(...)#checksum","active":true,"range":[0,999],"timestamp":"now"}]`,
+    /// ready to pass to `importdescriptors`, or error JSON.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_export_descriptor(config_json: *const c_char, network: i32) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            template: VaultTemplate,
+            owner_xpub: String,
+            recovery_xpub: String,
+            #[serde(default = "default_range_end")]
+            range_end: u32,
+            timestamp: Option<u64>,
+            #[serde(default)]
+            tree_version: taproot::TreeVersion,
+        }
+
+        fn default_range_end() -> u32 {
+            999
+        }
+
+        let params: Params = match serde_json::from_str(&config_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid config JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let result = keys::parse_xpub(&params.owner_xpub, net).and_then(|owner| {
+            let recovery = keys::parse_xpub(&params.recovery_xpub, net)?;
+            vault::descriptor::to_core_descriptor(&params.template, &owner, &recovery, net, params.tree_version)
+        });
+
+        match result {
+            Ok(desc) => ffi::success_response(serde_json::json!([{
+                "desc": desc,
+                "active": true,
+                "range": [0, params.range_end],
+                "timestamp": params.timestamp.map_or(serde_json::json!("now"), serde_json::Value::from),
+            }])),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Validate a Bitcoin address for a given network
+    ///
+    /// # Arguments
+    /// * `address` - Bitcoin address string
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4)
+    ///
+    /// # Returns
+    /// JSON: `{"valid":true}` or error JSON
+    ///
+    /// # Safety
+    /// `address` must be a valid null-terminated C string.
+    fn ffi_validate_address(address: *const c_char, network: i32) -> *mut c_char {
+        let addr_str = match ffi::from_c_string(address) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match Network::try_from(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match taproot::validate_address(&addr_str, net) {
+            Ok(valid) => ffi::success_response(serde_json::json!({ "valid": valid })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Decode metadata from a Taproot metadata script leaf
+    ///
+    /// # Arguments
+    /// * `script_hex` - Hex-encoded metadata script
+    ///
+    /// # Returns
+    /// JSON VaultMetadata or error JSON
+    ///
+    /// # Safety
+    /// `script_hex` must be a valid null-terminated C string.
+    fn ffi_decode_metadata_leaf(script_hex: *const c_char) -> *mut c_char {
+        let hex_str = match ffi::from_c_string(script_hex) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match taproot::decode_metadata_from_script(&hex_str) {
+            Ok(metadata) => ffi::success_response(metadata),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Encode vault metadata to its binary leaf format
+    ///
+    /// # Arguments
+    /// * `metadata_json` - JSON VaultMetadata
+    ///
+    /// # Returns
+    /// Byte buffer with the encoded metadata, or a null buffer on error
+    /// (details via `vault_last_error_message()`). Must be freed with `free_rust_bytes()`.
+    ///
+    /// # Safety
+    /// `metadata_json` must be a valid null-terminated C string.
+    fn vault_metadata_encode(metadata_json: *const c_char) -> ffi::ByteBuffer {
+        let result = ffi::from_c_string_bounded(metadata_json, ffi::MAX_JSON_INPUT_LEN).and_then(|json| {
+            serde_json::from_str::<VaultMetadata>(&json)
+                .map_err(|e| CoreError::InvalidInput(format!("Invalid metadata JSON: {}", e)))
+        });
+
+        match result {
+            Ok(metadata) => {
+                ffi::clear_last_error();
+                ffi::to_byte_buffer(metadata.to_bytes())
+            }
+            Err(e) => {
+                ffi::set_last_error(e);
+                ffi::ByteBuffer::null()
+            }
+        }
+    }
+}
+
+ffi_export! {
+    /// Decode vault metadata from its binary leaf format
+    ///
+    /// # Arguments
+    /// * `data` - Pointer to the encoded bytes (may be null only if `len` is 0)
+    /// * `len` - Number of bytes at `data`
+    ///
+    /// # Returns
+    /// JSON VaultMetadata or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `data` must point to at least `len` readable bytes.
+    fn vault_metadata_decode(data: *const u8, len: usize) -> *mut c_char {
+        match ffi::from_raw_bytes(data, len).and_then(|bytes| VaultMetadata::from_bytes(&bytes)) {
+            Ok(metadata) => ffi::success_response(metadata),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Convert hex-encoded metadata to its canonical JSON form
+    ///
+    /// See `VaultMetadata::to_json()`. The canonical JSON is returned as a
+    /// string, so hosts can store it verbatim; `vault_metadata_from_json()`
+    /// turns it back into the same bytes.
+    ///
+    /// # Arguments
+    /// * `bytes_hex` - Metadata bytes, as `metadata_hex` from `vault_create()`
+    ///
+    /// # Returns
+    /// JSON: `{"metadata_json":"{...}"}` or error JSON. Must be freed with
+    /// `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `bytes_hex` must be a valid null-terminated C string.
+    fn vault_metadata_to_json(bytes_hex: *const c_char) -> *mut c_char {
+        let result = ffi::from_c_string(bytes_hex).and_then(|bytes_hex| {
+            let bytes = hex::decode(bytes_hex.trim())
+                .map_err(|e| CoreError::MetadataError(format!("Invalid metadata hex: {}", e)))?;
+            VaultMetadata::from_bytes(&bytes)?.to_json()
+        });
+
+        match result {
+            Ok(json) => ffi::success_response(serde_json::json!({ "metadata_json": json })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Convert canonical JSON metadata back to hex-encoded bytes
+    ///
+    /// See `VaultMetadata::from_json()`. The bytes use the layout named
+    /// by `version`, so JSON from `vault_metadata_to_json()` yields the
+    /// bytes it was made from.
+    ///
+    /// # Arguments
+    /// * `metadata_json` - JSON VaultMetadata
+    ///
+    /// # Returns
+    /// JSON: `{"metadata_hex":"01..."}` or error JSON. Unknown fields
+    /// fail with code 4001 naming the field, and JSON the binary form
+    /// can't represent exactly with 4001 or 3002. Must be freed with
+    /// `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `metadata_json` must be a valid null-terminated C string.
+    fn vault_metadata_from_json(metadata_json: *const c_char) -> *mut c_char {
+        let result = ffi::from_c_string_bounded(metadata_json, ffi::MAX_JSON_INPUT_LEN)
+            .and_then(|json| VaultMetadata::from_json(&json))
+            .and_then(|metadata| metadata.to_bytes_as_version());
+
+        match result {
+            Ok(bytes) => ffi::success_response(serde_json::json!({ "metadata_hex": hex::encode(bytes) })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                    TRANSACTION BUILDING FFI
+// ═══════════════════════════════════════════════════════════════════
+
+ffi_export! {
+    /// Build PSBT for delayed spend (script-path with CSV timelock)
+    ///
+    /// # Arguments
+    /// * `intent_json` - JSON SpendIntent: `{"destination":"...","fee_rate":5.0}`
+    /// * `utxos_json` - JSON array of Utxo: `[{"txid":"...","vout":0,"amount_sats":100000}]`
+    /// * `vault_json` - JSON VaultConfig
+    ///
+    /// # Returns
+    /// JSON PsbtResult with base64 PSBT and summary. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// All pointer arguments must be valid null-terminated C strings.
+    fn ffi_build_delayed_spend_psbt(
+        intent_json: *const c_char,
+        utxos_json: *const c_char,
+        vault_json: *const c_char,
+    ) -> *mut c_char {
+        let intent_str = match ffi::from_c_string_bounded(intent_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let utxos_str = match ffi::from_c_string_bounded(utxos_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let vault_str = match ffi::from_c_string_bounded(vault_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let intent: transaction::SpendIntent = match serde_json::from_str(&intent_str) {
+            Ok(i) => i,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid intent: {}", e))),
+        };
+        let utxos: Vec<transaction::Utxo> = match serde_json::from_str(&utxos_str) {
+            Ok(u) => u,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid utxos: {}", e))),
+        };
+        let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
+            Ok(v) => v,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
+        };
+
+        match transaction::build_delayed_spend_psbt(&intent, &utxos, &vault) {
+            Ok(result) => ffi::success_response(result),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build PSBT for emergency key-path spend (no delay)
+    ///
+    /// # Arguments
+    /// * `params_json` - JSON: `{"destination":"...","fee_rate":5.0}`
+    /// * `utxos_json` - JSON array of Utxo
+    /// * `vault_json` - JSON VaultConfig
+    ///
+    /// # Returns
+    /// JSON PsbtResult. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// All pointer arguments must be valid null-terminated C strings.
+    fn ffi_build_emergency_psbt(
+        params_json: *const c_char,
+        utxos_json: *const c_char,
+        vault_json: *const c_char,
+    ) -> *mut c_char {
+        let params_str = match ffi::from_c_string_bounded(params_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let utxos_str = match ffi::from_c_string_bounded(utxos_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let vault_str = match ffi::from_c_string_bounded(vault_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            destination: String,
+            fee_rate: f64,
+        }
+
+        let params: Params = match serde_json::from_str(&params_str) {
+            Ok(p) => p,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid params: {}", e))),
+        };
+        let utxos: Vec<transaction::Utxo> = match serde_json::from_str(&utxos_str) {
+            Ok(u) => u,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid utxos: {}", e))),
+        };
+        let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
+            Ok(v) => v,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
+        };
+
+        match transaction::build_emergency_psbt(&params.destination, params.fee_rate, &utxos, &vault) {
+            Ok(result) => ffi::success_response(result),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Verify PSBT matches vault policy
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT
+    /// * `vault_json` - JSON VaultConfig
+    ///
+    /// # Returns
+    /// JSON PolicyCheck: `{"valid":true,"warnings":[],"errors":[]}`
+    ///
+    /// # Safety
+    /// All pointer arguments must be valid null-terminated C strings.
+    fn ffi_verify_psbt_policy(
+        psbt_base64: *const c_char,
+        vault_json: *const c_char,
+    ) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let vault_str = match ffi::from_c_string_bounded(vault_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let vault: transaction::VaultConfig = match serde_json::from_str(&vault_str) {
+            Ok(v) => v,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid vault: {}", e))),
+        };
+
+        match transaction::verify_psbt_policy(&psbt_str, &vault) {
+            Ok(check) => ffi::success_response(check),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Finalize a signed PSBT and extract raw transaction
+    ///
+    /// # Arguments
+    /// * `signed_psbt_base64` - Base64-encoded signed PSBT
+    ///
+    /// # Returns
+    /// JSON: `{"tx_hex":"...","txid":"...","vsize":...,"warnings":[...]}`, with
+    /// `"warnings"` as in `vault_finalize_psbt()`
+    ///
+    /// # Safety
+    /// `signed_psbt_base64` must be a valid null-terminated C string.
+    fn ffi_finalize_psbt(signed_psbt_base64: *const c_char) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(signed_psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match transaction::finalize_psbt(&psbt_str) {
+            Ok(result) => ffi::success_response(result),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build the unvault PSBT spending a vault UTXO through the timelock leaf
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"template":{...},"owner_xpub":"...","recovery_xpub":"...",
+    ///   "utxo":{"txid":"...","vout":0,"amount_sats":100000,"vault_index":0},
+    ///   "destination":"...","fee_rate":2,"metadata":{...}}`. An optional
+    ///   `"amount_sats"` sends only that amount and returns change to the vault
+    ///   address at `"change_index"`, which is then required; change below the
+    ///   optional `"dust_threshold"` (at least the 330-sat P2TR dust limit) goes
+    ///   to the fee. An optional `"dust_limit_sats"` raises the smallest output,
+    ///   destination or change, above the relay dust limit; a smaller
+    ///   `"amount_sats"` fails with code 2003. With `"psbt_vault_info":true` the
+    ///   PSBT records the metadata and the timelock spend path under the
+    ///   `"vaultmgr"` proprietary prefix (see `vault_read_psbt_vault_info()`).
+    ///   If the metadata has `destination_indices`, `"approved_destinations"`
+    ///   (`{"network":"...","destinations":[{"label":"...","address":"..."}]}`)
+    ///   must list the destination at one of them. An optional
+    ///   `"current_block_height"` sets an anti-fee-sniping nLockTime, and an
+    ///   optional hex `"memo"` of up to 80 bytes adds a last OP_RETURN output.
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"...","change":{"kind":"output","vault_index":5,"vout":1,
+    /// "amount_sats":59000}}` or error JSON. `"kind"` is `"output"`, `"added_to_fee"`
+    /// (with `"amount_sats"` and the `"dust_limit_sats"` it fell short of) or
+    /// `"none"`; the host should mark a change index as used. Must be freed with
+    /// `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_build_unvault_psbt(request_json: *const c_char, network: i32) -> *mut c_char {
+        let request_str = match ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            #[serde(default)]
+            tree_version: taproot::TreeVersion,
+            template: VaultTemplate,
+            owner_xpub: String,
+            recovery_xpub: String,
+            #[serde(flatten)]
+            request: UnvaultRequest,
+        }
+
+        let params: Params = match serde_json::from_str(&request_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid request JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let config = vault::VaultConfig {
+            dust_limit_sats: params.request.dust_limit_sats,
+            tree_version: params.tree_version,
+            ..vault::VaultConfig::new(net, params.template, params.owner_xpub, params.recovery_xpub)
+        };
+        let result = vault::Vault::from_config(&config)
+            .and_then(|vault| params.request.build(&vault, |index| vault.tree_at(index)));
+
+        match result {
+            Ok(bundle) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&bundle.psbt),
+                "change": bundle.change,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build the recovery PSBT sweeping vault UTXOs through the emergency leaf
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"template":{...},"owner_xpub":"...","recovery_xpub":"...",
+    ///   "utxos":[{"txid":"...","vout":0,"amount_sats":100000,"vault_index":0}],
+    ///   "cold_address":"...","fee_rate":5}`. UTXOs may come from different vault indices.
+    ///   An optional `"current_block_height"` sets an anti-fee-sniping nLockTime, an
+    ///   optional hex `"memo"` of up to 80 bytes adds a last OP_RETURN output, and an
+    ///   optional `"dust_limit_sats"` raises the smallest output above the relay dust limit.
+    ///   With `"psbt_vault_info":true` the PSBT records the vault's metadata and the
+    ///   spend path of the leaf it uses under the `"vaultmgr"` proprietary prefix. An optional
+    ///   x-only hex `"anchor_key"` adds a 330-sat CPFP anchor output paying that key
+    ///   after the cold output. For a degrading template, `"stage":n` spends every
+    ///   UTXO through stage `n`'s leaf instead, with that stage's delay as nSequence.
+    ///   `"leaf"` (`"emergency"`, `"hashlock"` or `"absolute_lock"`) picks the recovery
+    ///   leaf every UTXO is spent through; by default each uses the first of those it has.
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"..."}` or error JSON. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_build_recovery_psbt(request_json: *const c_char, network: i32) -> *mut c_char {
+        let request_str = match ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            #[serde(default)]
+            tree_version: taproot::TreeVersion,
+            template: VaultTemplate,
+            owner_xpub: String,
+            recovery_xpub: String,
+            utxos: Vec<FfiVaultUtxo>,
+            cold_address: String,
+            fee_rate: u64,
+            #[serde(default)]
+            current_block_height: Option<u32>,
+            #[serde(default)]
+            memo: Option<String>,
+            #[serde(default)]
+            dust_limit_sats: Option<u64>,
+            #[serde(default)]
+            psbt_vault_info: bool,
+            #[serde(default)]
+            anchor_key: Option<bitcoin::secp256k1::XOnlyPublicKey>,
+            #[serde(default)]
+            stage: Option<usize>,
+            #[serde(default)]
+            leaf: Option<taproot::LeafPurpose>,
+        }
+
+        let params: Params = match serde_json::from_str(&request_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid request JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let config = vault::VaultConfig {
+            dust_limit_sats: params.dust_limit_sats,
+            psbt_vault_info: params.psbt_vault_info,
+            tree_version: params.tree_version,
+            ..vault::VaultConfig::new(net, params.template, params.owner_xpub, params.recovery_xpub)
+        };
+        let result = vault::Vault::from_config(&config).and_then(|vault| {
+            let utxos = params
+                .utxos
+                .iter()
+                .map(|utxo| utxo.resolve(vault.tree_at(utxo.vault_index)?))
+                .collect::<CoreResult<Vec<_>>>()?;
+            let cold_address = vault::policy::validate_address(&params.cold_address, net)?;
+            let options = vault::psbt::SpendOptions {
+                current_block_height: params.current_block_height,
+                memo: parse_memo(params.memo.as_deref())?,
+                dust: vault.dust_policy(),
+                anchor: params.anchor_key,
+                ..Default::default()
+            };
+            let mut psbt = match params.stage {
+                Some(stage) => vault::psbt::build_stage_spend(
+                    &utxos,
+                    stage,
+                    cold_address,
+                    params.fee_rate,
+                    options,
+                )?,
+                None => vault::psbt::build_recovery(
+                    &utxos,
+                    params.leaf,
+                    cold_address,
+                    params.fee_rate,
+                    options,
+                )?,
+            };
+            if vault.psbt_vault_info() {
+                let spend_path = vault::psbt::built_spend_path(&psbt)?;
+                vault::psbt::attach_vault_info(&mut psbt, &vault.metadata(), spend_path)?;
+            }
+            Ok(psbt)
+        });
+
+        match result {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build an unsigned PSBT depositing into a vault from the user's wallet
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"inputs":[{"txid":"...","vout":0,"amount_sats":100000,
+    ///   "script_pubkey":"<hex>"}],"vault_address":"...","amount_sats":90000,
+    ///   "change_address":"...","fee_rate":2}`. Inputs must be P2WPKH or P2TR and
+    ///   are all spent. Each may add `"pubkey"` (compressed hex: the P2WPKH key or
+    ///   P2TR internal key), `"fingerprint"` and `"path"` so the wallet can find
+    ///   its signing key. Change below the dust limit goes to the fee.
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"...","fee_sats":300}` or error JSON. The PSBT
+    /// is for the wallet (Sparrow, Bitcoin Core) to sign and broadcast.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_build_deposit_psbt(request_json: *const c_char, network: i32) -> *mut c_char {
+        let request_str = match ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            inputs: Vec<FfiExternalUtxo>,
+            vault_address: String,
+            amount_sats: u64,
+            change_address: String,
+            fee_rate: u64,
+        }
+
+        let params: Params = match serde_json::from_str(&request_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid request JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let result = params
+            .inputs
+            .iter()
+            .map(FfiExternalUtxo::resolve)
+            .collect::<CoreResult<Vec<_>>>()
+            .and_then(|inputs| {
+                let vault_address = vault::policy::validate_address(&params.vault_address, net)?;
+                let change_address = vault::policy::validate_address(&params.change_address, net)?;
+                let psbt = vault::psbt::build_deposit(
+                    &inputs,
+                    vault_address,
+                    params.amount_sats,
+                    change_address,
+                    params.fee_rate,
+                )?;
+                let input_sats: u64 = inputs.iter().map(|utxo| utxo.amount_sats).sum();
+                let output_sats: u64 = psbt.unsigned_tx.output.iter().map(|txout| txout.value).sum();
+                Ok((psbt, input_sats - output_sats))
+            });
+
+        match result {
+            Ok((psbt, fee_sats)) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+                "fee_sats": fee_sats,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build a consolidation PSBT sweeping vault UTXOs into one output at a
+    /// fresh vault index
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"template":{...},"owner_xpub":"...","recovery_xpub":"...",
+    ///   "utxos":[{"txid":"...","vout":0,"amount_sats":100000,"vault_index":0}],
+    ///   "target_index":12,"fee_rate":2}`. Inputs spend the key path if the template
+    ///   enables it, otherwise the timelock leaf. An optional `"fee_warning_percent"`
+    ///   (default 5) sets the share of the swept value above which the fee is flagged,
+    ///   and an optional `"dust_limit_sats"` the smallest output to leave.
+    ///   `"psbt_vault_info":true` records the vault and spend path in the PSBT.
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"...","vault_index":12,"total_input_sats":100000,
+    /// "fee_sats":900,"fee_warning":null}` or error JSON. `"fee_warning"` is a
+    /// message when the fee is over the warning share; the host should mark
+    /// `"vault_index"` as used. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_build_consolidation_psbt(request_json: *const c_char, network: i32) -> *mut c_char {
+        let request_str = match ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let net = match ffi::network_arg(network) {
+            Ok(n) => n,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        #[derive(serde::Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Params {
+            #[serde(default)]
+            tree_version: taproot::TreeVersion,
+            template: VaultTemplate,
+            owner_xpub: String,
+            recovery_xpub: String,
+            utxos: Vec<FfiVaultUtxo>,
+            target_index: u32,
+            fee_rate: u64,
+            #[serde(default)]
+            fee_warning_percent: Option<u64>,
+            #[serde(default)]
+            dust_limit_sats: Option<u64>,
+            #[serde(default)]
+            psbt_vault_info: bool,
+        }
+
+        let params: Params = match serde_json::from_str(&request_str) {
+            Ok(p) => p,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid request JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        let config = vault::VaultConfig {
+            dust_limit_sats: params.dust_limit_sats,
+            psbt_vault_info: params.psbt_vault_info,
+            tree_version: params.tree_version,
+            ..vault::VaultConfig::new(net, params.template, params.owner_xpub, params.recovery_xpub)
+        };
+        let result = vault::Vault::from_config(&config).and_then(|vault| {
+            let utxos = params
+                .utxos
+                .iter()
+                .map(|utxo| utxo.resolve(vault.tree_at(utxo.vault_index)?))
+                .collect::<CoreResult<Vec<_>>>()?;
+            vault::psbt::build_consolidation_with_fee_warning(
+                &utxos,
+                params.target_index,
+                params.fee_rate,
+                &vault,
+                params
+                    .fee_warning_percent
+                    .unwrap_or(vault::psbt::DEFAULT_CONSOLIDATION_FEE_WARNING_PERCENT),
+            )
+        });
+
+        match result {
+            Ok(consolidation) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&consolidation.psbt),
+                "vault_index": consolidation.vault_index,
+                "total_input_sats": consolidation.total_input_sats,
+                "fee_sats": consolidation.fee_sats,
+                "fee_warning": consolidation.fee_warning,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Check a PSBT against the vault's rules before signing it
+    ///
+    /// Inputs must spend vault scripts, timelock-leaf inputs must wait
+    /// out the delay, outputs must pay approved destinations or back to
+    /// the vault, and the fee must be within the ceiling.
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...",
+    ///   "recovery_xpub":"..."}`, optionally with `"approved_destinations"`
+    ///   (`{"network":"...","destinations":[{"label":"...","address":"..."}]}`)
+    ///   `"max_fee_sats"` and `"require_non_witness_utxo"`. A `"velocity_limit"`
+    ///   (`{"max_sats":50000000,"window_blocks":1008}`) also needs
+    ///   `"current_block_height"` and takes earlier spends from `"spend_history"`
+    ///   (`[{"txid":"...","height":800000,"amount_sats":10000}]`). `"network"` may be
+    ///   omitted once `vault_init()` has selected one.
+    ///
+    /// # Returns
+    /// JSON: `{"checks":[{"rule":"vault_input","index":0,"passed":true,"detail":"..."},...],
+    /// "passed":true}` or error JSON. A PSBT that breaks the rules still
+    /// returns a report, with `"passed":false`; one whose `non_witness_utxo`
+    /// contradicts its `witness_utxo`, or lacks a required `non_witness_utxo`,
+    /// fails with code 2001.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// All pointer arguments must be valid null-terminated C strings.
+    fn vault_check_psbt(psbt_base64: *const c_char, config_json: *const c_char) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = vault::psbt::parse_any(&psbt_str)
+            .and_then(|psbt| vault::policy::check_psbt(&psbt, &config));
+
+        match result {
+            Ok(report) => ffi::success_response(report),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Signing progress of a vault PSBT, for coordinating cosigners
+    ///
+    /// Reports, for each input, the spend path its leaf data implies, the
+    /// keys expected to sign, which of them have, and whether the input
+    /// can be finalized. Signatures are counted, not verified.
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT
+    /// * `config_json` - Vault config JSON, as for `vault_check_psbt()`
+    ///
+    /// # Returns
+    /// JSON: `{"inputs":[{"input_index":0,"vault_index":2,"spend_path":{"type":"multisig_leaf",
+    /// "threshold":2,"total":3},"signers":[{"pubkey":"...","fingerprint":"...","signed":true},...],
+    /// "required":2,"signed":1,"finalized":false,"finalizable":false}],"ready":false,
+    /// "estimated_vsize":190,"fee_sats":1000}` or error JSON. Fails with code
+    /// 2001 if an input lacks its witness UTXO.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// All pointer arguments must be valid null-terminated C strings.
+    fn vault_psbt_status(psbt_base64: *const c_char, config_json: *const c_char) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = vault::psbt::parse_any(&psbt_str)
+            .and_then(|psbt| vault::psbt::status(&psbt, &config));
+
+        match result {
+            Ok(status) => ffi::success_response(status),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Rebuild an unsigned or signed vault PSBT at a higher fee rate (RBF)
+    ///
+    /// The extra fee comes out of change, or out of the destination when
+    /// there is no change; change below the relay dust limit is dropped to
+    /// fees. Signatures are
+    /// removed, so the result must be signed again.
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT from one of the vault builders
+    /// * `fee_rate` - New fee rate in sat/vB
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"...","fee_sats":...}` or error JSON (2003 if the
+    /// rate doesn't beat the original by the incremental relay fee).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `psbt_base64` must be a valid null-terminated C string.
+    fn vault_bump_psbt_fee(psbt_base64: *const c_char, fee_rate: u64) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = vault::psbt::parse_any(&psbt_str)
+            .and_then(|psbt| vault::psbt::bump_fee(&psbt, fee_rate, vault::fees::DustPolicy::Relay));
+
+        match result {
+            Ok(psbt) => {
+                let inputs: u64 = psbt.inputs.iter().filter_map(|i| i.witness_utxo.as_ref()).map(|o| o.value).sum();
+                let outputs: u64 = psbt.unsigned_tx.output.iter().map(|o| o.value).sum();
+                ffi::success_response(serde_json::json!({
+                    "psbt_base64": vault::psbt::to_base64(&psbt),
+                    "fee_sats": inputs - outputs,
+                }))
+            }
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Merge cosigners' signed copies of one PSBT
+    ///
+    /// # Arguments
+    /// * `psbts_json` - JSON array of base64-encoded PSBTs: `["cHNidP8B...",...]`
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"..."}` with every copy's signatures, or error JSON
+    /// naming the first field in which the unsigned transactions differ. See
+    /// `vault::psbt::combine()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `psbts_json` must be a valid null-terminated C string.
+    fn vault_combine_psbts(psbts_json: *const c_char) -> *mut c_char {
+        let psbts_str = match ffi::from_c_string_bounded(psbts_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let encoded: Vec<String> = match serde_json::from_str(&psbts_str) {
+            Ok(p) => p,
+            Err(e) => return ffi::error_response(CoreError::InvalidInput(format!("Invalid PSBT list JSON: {}", e))),
+        };
+
+        let result = encoded
+            .iter()
+            .map(|psbt| vault::psbt::parse_any(psbt))
+            .collect::<CoreResult<Vec<_>>>()
+            .and_then(|psbts| vault::psbt::combine(&psbts));
+
+        match result {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Read the vault info records a builder attached to a PSBT
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64 or hex PSBT
+    ///
+    /// # Returns
+    /// JSON: `{"vault_info":{"metadata":{...},"spend_path":{"type":"timelock_leaf"},
+    /// "library_version":"0.1.0"}}`, with `"vault_info":null` for a PSBT without
+    /// records, or error JSON (2001 for incomplete records). See
+    /// `vault::psbt::read_vault_info()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `psbt_base64` must be a valid null-terminated C string.
+    fn vault_read_psbt_vault_info(psbt_base64: *const c_char) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        match vault::psbt::parse_any(&psbt_str).and_then(|psbt| vault::psbt::read_vault_info(&psbt)) {
+            Ok(info) => ffi::success_response(serde_json::json!({ "vault_info": info })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SighashRequest {
+    psbt: String,
+    spend_path: vault::fees::SpendPath,
+}
+
+ffi_export! {
+    /// BIP341 sighashes of a PSBT's inputs, for signers that sign raw digests
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"psbt":"cHNidP8B...","spend_path":{"type":"timelock_leaf"}}`.
+    ///   `"type"` is `"key_path"`, `"timelock_leaf"`, `"emergency_leaf"` or
+    ///   `"multisig_leaf"` (with `"threshold"` and `"total"`).
+    ///
+    /// # Returns
+    /// JSON: `{"sighashes":[{"input_index":0,"message":"<32-byte hex>","leaf_hash":"...",
+    /// "sighash_type":0,"pubkey":"<x-only hex>"}]}` or error JSON. `"leaf_hash"` is
+    /// null for the key path. See `vault::psbt::sighashes()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_psbt_sighashes(request_json: *const c_char) -> *mut c_char {
+        let result = ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN)
+            .and_then(|json| {
+                serde_json::from_str::<SighashRequest>(&json)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid sighash request JSON: {}", e)))
+            })
+            .and_then(|request| {
+                let psbt = vault::psbt::parse_any(&request.psbt)?;
+                vault::psbt::sighashes(&psbt, request.spend_path)
+            });
+
+        match result {
+            Ok(sighashes) => ffi::success_response(serde_json::json!({ "sighashes": sighashes })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ApplySignatureRequest {
+    psbt: String,
+    input_index: usize,
+    pubkey: bitcoin::secp256k1::XOnlyPublicKey,
+    signature: String,
+}
+
+ffi_export! {
+    /// Add a signature over one of `vault_psbt_sighashes()`'s digests to a PSBT
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"psbt":"cHNidP8B...","input_index":0,"pubkey":"<x-only hex>",
+    ///   "signature":"<64-byte hex, or 65 with a sighash type byte>"}`
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"..."}` or error JSON (2004 if the signature
+    /// doesn't verify against the input's sighash, or the key doesn't sign
+    /// for the input). See `vault::psbt::apply_signature()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_psbt_apply_signature(request_json: *const c_char) -> *mut c_char {
+        let result = ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN)
+            .and_then(|json| {
+                serde_json::from_str::<ApplySignatureRequest>(&json)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid signature request JSON: {}", e)))
+            })
+            .and_then(|request| {
+                let signature = hex::decode(&request.signature)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid signature hex: {}", e)))
+                    .and_then(|bytes| {
+                        bitcoin::taproot::Signature::from_slice(&bytes)
+                            .map_err(|e| CoreError::InvalidInput(format!("Invalid signature: {}", e)))
+                    })?;
+                let mut psbt = vault::psbt::parse_any(&request.psbt)?;
+                vault::psbt::apply_signature(&mut psbt, request.input_index, request.pubkey, signature)?;
+                Ok(psbt)
+            });
+
+        match result {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttachPreimageRequest {
+    psbt: String,
+    input_index: usize,
+    preimage: String,
+}
+
+ffi_export! {
+    /// Record the preimage that opens an input's hashlock leaf in a PSBT
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON: `{"psbt":"cHNidP8B...","input_index":0,"preimage":"<32-byte hex>"}`
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"..."}` or error JSON (4002 for a preimage
+    /// that isn't 32 bytes, 2003 if no hashlock leaf of the input matches
+    /// it). See `vault::psbt::attach_preimage()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_psbt_attach_preimage(request_json: *const c_char) -> *mut c_char {
+        let result = ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN)
+            .and_then(|json| {
+                serde_json::from_str::<AttachPreimageRequest>(&json)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid preimage request JSON: {}", e)))
+            })
+            .and_then(|request| {
+                let preimage = hex::decode(&request.preimage)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid preimage hex: {}", e)))?;
+                let mut psbt = vault::psbt::parse_any(&request.psbt)?;
+                vault::psbt::attach_preimage(&mut psbt, request.input_index, &preimage)?;
+                Ok(psbt)
+            });
+
+        match result {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Check a BIP340 signature over a 32-byte message
+    ///
+    /// # Arguments
+    /// * `msg_hex` - 32-byte message, e.g. a sighash from `vault_psbt_sighashes()`
+    /// * `sig_hex` - 64-byte signature
+    /// * `pubkey_hex` - 32-byte x-only public key
+    ///
+    /// # Returns
+    /// JSON: `{"valid":true}` or error JSON. A signature that doesn't verify
+    /// gives `{"valid":false}`; an argument of the wrong length, or a
+    /// pubkey that isn't on the curve, fails with 4002 naming the argument.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `msg_hex`, `sig_hex` and `pubkey_hex` must be valid null-terminated C strings.
+    fn vault_verify_signature(
+        msg_hex: *const c_char,
+        sig_hex: *const c_char,
+        pubkey_hex: *const c_char,
+    ) -> *mut c_char {
+        let msg = match ffi::from_c_string(msg_hex).and_then(|s| parse_hex_array::<32>(&s, "message")) {
+            Ok(msg) => msg,
+            Err(e) => return ffi::error_response(e),
+        };
+        let sig = match ffi::from_c_string(sig_hex).and_then(|s| parse_hex_array::<64>(&s, "signature")) {
+            Ok(sig) => sig,
+            Err(e) => return ffi::error_response(e),
+        };
+        let pubkey = ffi::from_c_string(pubkey_hex)
+            .and_then(|s| parse_hex_array::<32>(&s, "pubkey"))
+            .and_then(|bytes| {
+                bitcoin::secp256k1::XOnlyPublicKey::from_slice(&bytes)
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid pubkey: {}", e)))
+            });
+
+        let result = pubkey.and_then(|pubkey| keys::verify_schnorr(&msg, &sig, &pubkey));
+
+        match result {
+            Ok(valid) => ffi::success_response(serde_json::json!({ "valid": valid })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignMessageRequest {
+    vault_index: u32,
+    xpriv: keys::SecretMaterial,
+    message: String,
+}
+
+ffi_export! {
+    /// Prove control of a vault address with a BIP322 message signature
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"mainnet","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one.
+    /// * `request_json` - JSON: `{"vault_index":0,"xpriv":"xprv...","message":"..."}`.
+    ///   `"xpriv"` is the owner account key for vaults with key-path spends,
+    ///   or the recovery account key for the emergency leaf.
+    ///
+    /// # Returns
+    /// JSON: `{"address":"bc1p...","proof":"<base64>"}` or error JSON (2004 if
+    /// the key can't sign for the address). `"proof"` is BIP322's simple
+    /// encoding; see `vault::proof::sign_message()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `config_json` and `request_json` must be valid null-terminated C strings.
+    fn vault_sign_message(config_json: *const c_char, request_json: *const c_char) -> *mut c_char {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let config: vault::VaultConfig = match ffi::parse_request(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        }) {
+            Ok(c) => c,
+            Err(e) => return ffi::error_response(e),
+        };
+        let request = ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN).and_then(|mut json| {
+            let request = serde_json::from_str::<SignMessageRequest>(&json)
+                .map_err(|e| CoreError::InvalidInput(format!("Invalid sign message request JSON: {}", e)));
+            keys::wipe_string(&mut json);
+            request
+        });
+
+        let result = request.and_then(|request| {
+            let vault = vault::Vault::from_config(&config)?;
+            let proof = vault::proof::sign_message(&vault, request.vault_index, &request.xpriv, &request.message)?;
+            let address = vault.tree_at(request.vault_index)?.address(vault.network());
+            Ok((address, proof))
+        });
+
+        match result {
+            Ok((address, proof)) => ffi::success_response(serde_json::json!({
+                "address": address.to_string(),
+                "proof": proof,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Check a BIP322 message signature for a taproot address
+    ///
+    /// # Arguments
+    /// * `address` - Taproot address the proof is for
+    /// * `message` - Signed message
+    /// * `proof` - Base64 proof, in BIP322's simple or full encoding
+    /// * `network` - Network (0=mainnet, 1=testnet, 2=signet, 3=regtest, 4=testnet4, -1=as set by `vault_init()`)
+    ///
+    /// # Returns
+    /// JSON: `{"valid":true}` or error JSON. A proof that doesn't satisfy
+    /// the address gives `{"valid":false}`; an undecodable proof, a
+    /// non-taproot address or a leaf script that can't be checked fails
+    /// with 4002. See `vault::proof::verify_message()`.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `address`, `message` and `proof` must be valid null-terminated C strings.
+    fn vault_verify_message(
+        address: *const c_char,
+        message: *const c_char,
+        proof: *const c_char,
+        network: i32,
+    ) -> *mut c_char {
+        let address = match ffi::from_c_string(address) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let message = match ffi::from_c_string(message) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+        let proof = match ffi::from_c_string(proof) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = ffi::network_arg(network)
+            .and_then(|net| taproot::parse_address(&address, net))
+            .and_then(|address| vault::proof::verify_message(&address, &message, &proof));
+
+        match result {
+            Ok(valid) => ffi::success_response(serde_json::json!({ "valid": valid })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Finalize a signed vault PSBT into a raw transaction
+    ///
+    /// Assembles the script-path witness for every input and extracts the
+    /// transaction.
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT with all required `tap_script_sigs`
+    ///
+    /// # Returns
+    /// JSON: `{"tx_hex":"...","txid":"...","vsize":...,"warnings":[...]}` or
+    /// error JSON (2001 naming the input and missing signature count).
+    /// `"warnings"` lists the relay policy rules the transaction breaks, e.g.
+    /// `{"kind":"tx_weight","weight":400100,"max":400000}` (see
+    /// `vault::fees::check_standardness()`); it is empty for a standard transaction.
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `psbt_base64` must be a valid null-terminated C string.
+    fn vault_finalize_psbt(psbt_base64: *const c_char) -> *mut c_char {
+        let psbt_str = match ffi::from_c_string(psbt_base64) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let result = vault::psbt::parse_any(&psbt_str)
+            .and_then(|mut psbt| vault::psbt::finalize(&mut psbt))
+            .and_then(|tx| {
+                let warnings = vault::fees::check_standardness(&tx)?.violations;
+                Ok(transaction::FinalizedTx {
+                    tx_hex: bitcoin::consensus::encode::serialize_hex(&tx),
+                    txid: tx.txid().to_string(),
+                    vsize: tx.vsize() as u64,
+                    warnings,
+                })
+            });
+
+        match result {
+            Ok(finalized) => ffi::success_response(finalized),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Encode a PSBT as base64, e.g. for a file or clipboard
+    ///
+    /// # Arguments
+    /// * `data` - Pointer to the PSBT: raw bytes, or base64 or hex text
+    ///   (see `vault::psbt::parse_any_bytes()`)
+    /// * `len` - Number of bytes at `data`
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_base64":"..."}` or error JSON (2001 for malformed input).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `data` must point to at least `len` readable bytes.
+    fn vault_psbt_to_base64(data: *const u8, len: usize) -> *mut c_char {
+        match ffi::from_raw_bytes(data, len).and_then(|bytes| vault::psbt::parse_any_bytes(&bytes)) {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Encode a PSBT as lowercase hex, e.g. for a QR code
+    ///
+    /// # Arguments
+    /// * `data` - Pointer to the PSBT: raw bytes, or base64 or hex text
+    /// * `len` - Number of bytes at `data`
+    ///
+    /// # Returns
+    /// JSON: `{"psbt_hex":"..."}` or error JSON (2001 for malformed input).
+    /// Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `data` must point to at least `len` readable bytes.
+    fn vault_psbt_to_hex(data: *const u8, len: usize) -> *mut c_char {
+        match ffi::from_raw_bytes(data, len).and_then(|bytes| vault::psbt::parse_any_bytes(&bytes)) {
+            Ok(psbt) => ffi::success_response(serde_json::json!({
+                "psbt_hex": vault::psbt::to_hex(&psbt),
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Decode a base64 PSBT to its raw bytes
+    ///
+    /// # Arguments
+    /// * `psbt_base64` - Base64-encoded PSBT; hex is accepted too
+    ///
+    /// # Returns
+    /// Byte buffer with the serialized PSBT, or a null buffer on error
+    /// (details via `vault_last_error_message()`). Must be freed with `free_rust_bytes()`.
+    ///
+    /// # Safety
+    /// `psbt_base64` must be a valid null-terminated C string.
+    fn vault_psbt_from_base64(psbt_base64: *const c_char) -> ffi::ByteBuffer {
+        psbt_bytes_response(ffi::from_c_string(psbt_base64).and_then(|s| vault::psbt::parse_any(&s)))
+    }
+}
+
+ffi_export! {
+    /// Decode a hex PSBT to its raw bytes
+    ///
+    /// # Arguments
+    /// * `psbt_hex` - Hex-encoded PSBT; base64 is accepted too
+    ///
+    /// # Returns
+    /// Byte buffer with the serialized PSBT, or a null buffer on error
+    /// (details via `vault_last_error_message()`). Must be freed with `free_rust_bytes()`.
+    ///
+    /// # Safety
+    /// `psbt_hex` must be a valid null-terminated C string.
+    fn vault_psbt_from_hex(psbt_hex: *const c_char) -> ffi::ByteBuffer {
+        psbt_bytes_response(ffi::from_c_string(psbt_hex).and_then(|s| vault::psbt::parse_any(&s)))
+    }
+}
+
+/// Serialize a decoded PSBT into a byte buffer, recording any error
+fn psbt_bytes_response(result: CoreResult<bitcoin::psbt::Psbt>) -> ffi::ByteBuffer {
+    match result {
+        Ok(psbt) => {
+            ffi::clear_last_error();
+            ffi::to_byte_buffer(psbt.serialize())
+        }
+        Err(e) => {
+            ffi::set_last_error(e);
+            ffi::ByteBuffer::null()
+        }
+    }
+}
+
+/// A vault UTXO as passed over FFI, before its tree is derived
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FfiVaultUtxo {
+    txid: String,
+    vout: u32,
+    amount_sats: u64,
+    vault_index: u32,
+}
+
+impl FfiVaultUtxo {
+    /// Attach the UTXO's vault tree, derived at its `vault_index`
+    fn resolve(&self, tree: taproot::VaultTree) -> CoreResult<vault::psbt::VaultUtxo> {
+        let txid = self
+            .txid
+            .parse::<bitcoin::Txid>()
+            .map_err(|e| CoreError::InvalidInput(format!("Invalid txid: {}", e)))?;
+
+        Ok(vault::psbt::VaultUtxo::new(
+            bitcoin::OutPoint::new(txid, self.vout),
+            self.amount_sats,
+            tree,
+        ))
+    }
+}
+
+/// A wallet UTXO funding a deposit, as passed over FFI
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FfiExternalUtxo {
+    txid: String,
+    vout: u32,
+    amount_sats: u64,
+    script_pubkey: String,
+    #[serde(default)]
+    pubkey: Option<String>,
+    #[serde(default)]
+    fingerprint: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+impl FfiExternalUtxo {
+    fn resolve(&self) -> CoreResult<vault::psbt::ExternalUtxo> {
+        let txid = self
+            .txid
+            .parse::<bitcoin::Txid>()
+            .map_err(|e| CoreError::InvalidInput(format!("Invalid txid: {}", e)))?;
+        let script_pubkey = hex::decode(&self.script_pubkey)
+            .map_err(|e| CoreError::InvalidInput(format!("Invalid script_pubkey hex: {}", e)))?;
+
+        let key_origin = match (&self.pubkey, &self.fingerprint, &self.path) {
+            (None, None, None) => None,
+            (Some(pubkey), Some(fingerprint), Some(path)) => {
+                let pubkey = pubkey
+                    .parse::<bitcoin::secp256k1::PublicKey>()
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid pubkey: {}", e)))?;
+                let fingerprint = fingerprint
+                    .parse::<bitcoin::bip32::Fingerprint>()
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid fingerprint: {}", e)))?;
+                let path = path
+                    .parse::<bitcoin::bip32::DerivationPath>()
+                    .map_err(|e| CoreError::InvalidInput(format!("Invalid derivation path: {}", e)))?;
+                Some((pubkey, (fingerprint, path)))
+            }
+            _ => {
+                return Err(CoreError::InvalidInput(
+                    "A UTXO's pubkey, fingerprint and path must be given together".to_string(),
+                ))
+            }
+        };
+
+        Ok(vault::psbt::ExternalUtxo {
+            outpoint: bitcoin::OutPoint::new(txid, self.vout),
+            amount_sats: self.amount_sats,
+            script_pubkey: bitcoin::ScriptBuf::from_bytes(script_pubkey),
+            key_origin,
+        })
+    }
+}
+
+/// Memo bytes from a request's hex `"memo"`
+fn parse_memo(memo_hex: Option<&str>) -> CoreResult<Option<Vec<u8>>> {
+    memo_hex
+        .map(|memo| hex::decode(memo).map_err(|e| CoreError::InvalidInput(format!("Invalid memo hex: {}", e))))
+        .transpose()
+}
+
+/// Unvault parameters shared by the stateless and handle-based exports
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnvaultRequest {
+    utxo: FfiVaultUtxo,
+    destination: String,
+    fee_rate: u64,
+    amount_sats: Option<u64>,
+    metadata: VaultMetadata,
+    #[serde(default)]
+    approved_destinations: Option<vault::policy::ApprovedDestinations>,
+    #[serde(default)]
+    current_block_height: Option<u32>,
+    #[serde(default)]
+    change_index: Option<u32>,
+    #[serde(default)]
+    dust_threshold: Option<u64>,
+    /// Smallest output to create, overriding the vault config's
+    #[serde(default)]
+    dust_limit_sats: Option<u64>,
+    /// Hex bytes for an OP_RETURN output
+    #[serde(default)]
+    memo: Option<String>,
+    /// Attach vault info records, whatever the vault config says
+    #[serde(default)]
+    psbt_vault_info: bool,
+}
+
+impl UnvaultRequest {
+    /// Build the unvault PSBT for `vault`, looking up the UTXO's and change
+    /// trees through `tree`
+    ///
+    /// The vault's dust policy and vault info setting apply unless the
+    /// request sets its own.
+    fn build(
+        &self,
+        vault: &vault::Vault,
+        tree: impl Fn(u32) -> CoreResult<taproot::VaultTree>,
+    ) -> CoreResult<vault::psbt::PsbtBundle> {
+        let network = vault.network();
+        let dust = self.dust_limit_sats.map_or(vault.dust_policy(), vault::fees::DustPolicy::Floor);
+        let utxo = self.utxo.resolve(tree(self.utxo.vault_index)?)?;
+        let destination = vault::policy::validate_address(&self.destination, network)?;
+        let approved = self.approved_destinations.as_ref();
+        if approved.is_some_and(|approved| approved.network() != network) {
+            return Err(CoreError::InvalidInput(
+                "Approved destinations are for a different network".to_string(),
+            ));
+        }
+        let options = vault::psbt::SpendOptions {
+            approved,
+            current_block_height: self.current_block_height,
+            memo: parse_memo(self.memo.as_deref())?,
+            dust,
+            ..Default::default()
+        };
+        let mut bundle = match self.amount_sats {
+            Some(amount) => {
+                let change_index = self.change_index.ok_or_else(|| {
+                    CoreError::InvalidInput("\"change_index\" is required with \"amount_sats\"".to_string())
+                })?;
+                let change = vault::psbt::ChangeTarget::new(change_index, tree(change_index)?)
+                    .with_dust_threshold(self.dust_threshold.unwrap_or(0));
+                vault::psbt::build_partial_unvault(
+                    utxo,
+                    destination,
+                    amount,
+                    &change,
+                    self.fee_rate,
+                    &self.metadata,
+                    options,
+                )
+            }
+            None => vault::psbt::build_unvault(
+                utxo,
+                destination,
+                self.fee_rate,
+                &self.metadata,
+                options,
+            )
+            .map(|psbt| vault::psbt::PsbtBundle {
+                psbt,
+                change: vault::psbt::ChangeOutcome::None,
+            }),
+        }?;
+        if self.psbt_vault_info || vault.psbt_vault_info() {
+            let spend_path = vault::psbt::built_spend_path(&bundle.psbt)?;
+            vault::psbt::attach_vault_info(&mut bundle.psbt, &self.metadata, spend_path)?;
+        }
+        Ok(bundle)
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                         VAULT HANDLE FFI
+// ═══════════════════════════════════════════════════════════════════
+
+ffi_export! {
+    /// Parse a vault config once and return an opaque handle to it
+    ///
+    /// # Arguments
+    /// * `config_json` - JSON: `{"network":"regtest","template":{...},"owner_xpub":"...","recovery_xpub":"..."}`
+    ///   `"network"` may be omitted once `vault_init()` has selected one. An
+    ///   optional `"dust_limit_sats"` is the smallest output the handle's
+    ///   builders create, and `"psbt_vault_info":true` makes them record the
+    ///   vault and spend path in their PSBTs.
+    ///
+    /// # Returns
+    /// Handle for the other `vault_handle_*` calls, or null with the error
+    /// available from `vault_last_error_message()`. Must be released with
+    /// `vault_handle_free()`. Handles may be shared between threads.
+    ///
+    /// # Safety
+    /// `config_json` must be a valid null-terminated C string.
+    fn vault_handle_create(config_json: *const c_char) -> *mut ffi::VaultHandle {
+        let config_str = match ffi::from_c_string_bounded(config_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::FfiReturn::from_error(e),
+        };
+
+        let handle = ffi::parse_request::<vault::VaultConfig>(&config_str, |e| {
+            CoreError::InvalidInput(format!("Invalid config JSON: {}", e))
+        })
+        .and_then(|config| ffi::VaultHandle::new(&config));
+
+        match handle {
+            Ok(handle) => {
+                ffi::clear_last_error();
+                handle.into_raw()
+            }
+            Err(e) => ffi::FfiReturn::from_error(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Release a handle from `vault_handle_create()`
+    ///
+    /// # Returns
+    /// 0 on success, -1 for a null, freed or foreign pointer (see
+    /// `vault_last_error_message()`).
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_handle_create()`. Freeing twice is
+    /// detected on a best-effort basis only.
+    fn vault_handle_free(handle: *mut ffi::VaultHandle) -> i32 {
+        ffi::status(ffi::VaultHandle::free(handle))
+    }
+}
+
+ffi_export! {
+    /// Derive the vault address at `vault_index` through a handle
+    ///
+    /// # Returns
+    /// JSON as for `vault_get_address()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_handle_create()` and not have been freed.
+    fn vault_handle_get_address(handle: *const ffi::VaultHandle, vault_index: u32) -> *mut c_char {
+        let result = ffi::VaultHandle::from_ptr(handle)
+            .and_then(|handle| Ok((handle.vault().network(), handle.tree(vault_index)?)));
+
+        match result {
+            Ok((net, tree)) => ffi::success_response(serde_json::json!({
+                "address": tree.address(net).to_string(),
+                "script_pubkey": hex::encode(tree.script_pubkey().as_bytes()),
+                "merkle_root": tree.merkle_root().map(|root| root.to_string()),
+                "vault_index": vault_index,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Build an unvault PSBT for a vault UTXO through a handle
+    ///
+    /// # Arguments
+    /// * `request_json` - JSON as for `vault_build_unvault_psbt()`, without the
+    ///   template and xpubs.
+    ///
+    /// # Returns
+    /// JSON as for `vault_build_unvault_psbt()`, or error JSON. Must be freed
+    /// with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_handle_create()` and not have been freed;
+    /// `request_json` must be a valid null-terminated C string.
+    fn vault_handle_build_unvault(handle: *const ffi::VaultHandle, request_json: *const c_char) -> *mut c_char {
+        let handle = match ffi::VaultHandle::from_ptr(handle) {
+            Ok(h) => h,
+            Err(e) => return ffi::error_response(e),
+        };
+        let request_str = match ffi::from_c_string_bounded(request_json, ffi::MAX_JSON_INPUT_LEN) {
+            Ok(s) => s,
+            Err(e) => return ffi::error_response(e),
+        };
+
+        let request: UnvaultRequest = match serde_json::from_str(&request_str) {
+            Ok(r) => r,
+            Err(e) => {
+                return ffi::error_response(CoreError::InvalidInput(format!(
+                    "Invalid request JSON: {}",
+                    e
+                )))
+            }
+        };
+
+        match request.build(handle.vault(), |index| handle.tree(index)) {
+            Ok(bundle) => ffi::success_response(serde_json::json!({
+                "psbt_base64": vault::psbt::to_base64(&bundle.psbt),
+                "change": bundle.change,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                         UR (ANIMATED QR) FFI
+// ═══════════════════════════════════════════════════════════════════
+
+/// `{"parts":[...]}` response for the `vault_ur_encode_*` exports
+fn ur_parts_response(parts: CoreResult<Vec<String>>) -> *mut c_char {
+    match parts {
+        Ok(parts) => ffi::success_response(serde_json::json!({ "parts": parts })),
+        Err(e) => ffi::error_response(e),
+    }
+}
+
+ffi_export! {
+    /// Split a PSBT into `ur:crypto-psbt` QR parts
+    ///
+    /// # Arguments
+    /// * `psbt` - PSBT as base64 or hex
+    /// * `max_fragment_len` - Most PSBT bytes per part (at least 10)
+    ///
+    /// # Returns
+    /// JSON: `{"parts":["ur:crypto-psbt/1-9/...",...]}`, a single part when the
+    /// PSBT fits, for the host to show as a looping animation. Must be freed
+    /// with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `psbt` must be a valid null-terminated C string.
+    fn vault_ur_encode_psbt(psbt: *const c_char, max_fragment_len: u32) -> *mut c_char {
+        ur_parts_response(
+            ffi::from_c_string(psbt)
+                .and_then(|s| vault::psbt::parse_any(&s))
+                .map(|psbt| vault::ur::encode_psbt(&psbt, max_fragment_len as usize)),
+        )
+    }
+}
+
+ffi_export! {
+    /// Split a descriptor into `ur:output-descriptor` QR parts
+    ///
+    /// # Returns
+    /// JSON as for `vault_ur_encode_psbt()`. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `descriptor` must be a valid null-terminated C string.
+    fn vault_ur_encode_descriptor(descriptor: *const c_char, max_fragment_len: u32) -> *mut c_char {
+        ur_parts_response(
+            ffi::from_c_string(descriptor)
+                .map(|descriptor| vault::ur::encode_descriptor(&descriptor, max_fragment_len as usize)),
+        )
+    }
+}
+
+ffi_export! {
+    /// Start decoding a UR scanned from QR codes
+    ///
+    /// # Returns
+    /// Handle for the other `vault_ur_decoder_*` calls. Must be released
+    /// with `vault_ur_decoder_free()`. Handles may be shared between threads.
+    fn vault_ur_decoder_new() -> *mut ffi::UrDecoderHandle {
+        ffi::clear_last_error();
+        ffi::UrDecoderHandle::new().into_raw()
+    }
+}
+
+ffi_export! {
+    /// Feed one scanned QR part to a decoder
+    ///
+    /// Parts may arrive in any order and repeat; parts received after the
+    /// UR is complete are ignored.
+    ///
+    /// # Returns
+    /// 0 on success, -1 for a malformed part or a part of another UR (see
+    /// `vault_last_error_message()`). The decoder is unchanged by a rejected
+    /// part, so the host can keep scanning.
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_ur_decoder_new()` and not have been freed;
+    /// `part` must be a valid null-terminated C string.
+    fn vault_ur_decoder_receive_part(handle: *const ffi::UrDecoderHandle, part: *const c_char) -> i32 {
+        ffi::status(ffi::UrDecoderHandle::from_ptr(handle).and_then(|handle| {
+            let part = ffi::from_c_string(part)?;
+            handle.with_decoder(|decoder| decoder.receive_part(&part))
+        }))
+    }
+}
+
+ffi_export! {
+    /// `vault_ur_decoder_receive_part()`, stopped early by `vault_cancel(token)`
+    ///
+    /// # Returns
+    /// As `vault_ur_decoder_receive_part()`; a cancelled part returns -1
+    /// with error code 4005 and leaves the decoder unchanged.
+    ///
+    /// # Safety
+    /// As `vault_ur_decoder_receive_part()`; `token` must come from
+    /// `vault_cancel_token_new()` and stay alive until this returns.
+    fn vault_ur_decoder_receive_part_cancellable(
+        handle: *const ffi::UrDecoderHandle,
+        part: *const c_char,
+        token: *const ffi::CancelTokenHandle,
+    ) -> i32 {
+        ffi::status(ffi::UrDecoderHandle::from_ptr(handle).and_then(|handle| {
+            let token = ffi::CancelTokenHandle::from_ptr(token)?;
+            let part = ffi::from_c_string(part)?;
+            handle.with_decoder(|decoder| decoder.receive_part_cancellable(&part, token.token()))
+        }))
+    }
+}
+
+ffi_export! {
+    /// Report how much of a UR has been scanned
+    ///
+    /// # Returns
+    /// JSON: `{"complete":false,"progress":0.4,"fragments_received":4,"fragments_total":10}`,
+    /// with `progress` from 0.0 to 1.0 for a progress bar. Must be freed with
+    /// `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_ur_decoder_new()` and not have been freed.
+    fn vault_ur_decoder_progress(handle: *const ffi::UrDecoderHandle) -> *mut c_char {
+        let result = ffi::UrDecoderHandle::from_ptr(handle).and_then(|handle| {
+            handle.with_decoder(|decoder| {
+                let (received, total) = decoder.fragments_received();
+                Ok(serde_json::json!({
+                    "complete": decoder.is_complete(),
+                    "progress": decoder.progress(),
+                    "fragments_received": received,
+                    "fragments_total": total,
+                }))
+            })
+        });
+
+        match result {
+            Ok(json) => ffi::success_response(json),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Get the decoded content of a complete UR
+    ///
+    /// # Returns
+    /// JSON: `{"type":"crypto-psbt","psbt_base64":"..."}` or
+    /// `{"type":"output-descriptor","descriptor":"tr(...)"}`, or error JSON
+    /// while parts are missing. Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_ur_decoder_new()` and not have been freed.
+    fn vault_ur_decoder_result(handle: *const ffi::UrDecoderHandle) -> *mut c_char {
+        let result = ffi::UrDecoderHandle::from_ptr(handle)
+            .and_then(|handle| handle.with_decoder(|decoder| Ok((decoder.ur_type().map(str::to_string), decoder.result()?))));
+
+        match result {
+            Ok((ur_type, vault::ur::UrPayload::Psbt(psbt))) => ffi::success_response(serde_json::json!({
+                "type": ur_type,
+                "psbt_base64": vault::psbt::to_base64(&psbt),
+            })),
+            Ok((ur_type, vault::ur::UrPayload::Descriptor(descriptor))) => ffi::success_response(serde_json::json!({
+                "type": ur_type,
+                "descriptor": descriptor,
+            })),
+            Err(e) => ffi::error_response(e),
+        }
+    }
+}
+
+ffi_export! {
+    /// Release a handle from `vault_ur_decoder_new()`
+    ///
+    /// # Returns
+    /// 0 on success, -1 for a null, freed or foreign pointer (see
+    /// `vault_last_error_message()`).
+    ///
+    /// # Safety
+    /// `handle` must come from `vault_ur_decoder_new()`. Freeing twice is
+    /// detected on a best-effort basis only.
+    fn vault_ur_decoder_free(handle: *mut ffi::UrDecoderHandle) -> i32 {
+        ffi::status(ffi::UrDecoderHandle::free(handle))
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                         CANCELLATION FFI
+// ═══════════════════════════════════════════════════════════════════
+
+ffi_export! {
+    /// Create a token for stopping a long-running call from another thread
+    ///
+    /// Pass it to a `*_cancellable` export and call `vault_cancel()` from
+    /// any thread to make that export return error code 4005. A token
+    /// stays tripped; use a new one per operation.
+    ///
+    /// # Returns
+    /// Token handle. Must be released with `vault_cancel_token_free()` once
+    /// no call using it is running.
+    fn vault_cancel_token_new() -> *mut ffi::CancelTokenHandle {
+        ffi::clear_last_error();
+        ffi::CancelTokenHandle::new().into_raw()
+    }
+}
+
+ffi_export! {
+    /// Trip a token, stopping the calls using it at their next check
+    ///
+    /// # Returns
+    /// 0 on success, -1 for a null, freed or foreign pointer (see
+    /// `vault_last_error_message()`).
+    ///
+    /// # Safety
+    /// `token` must come from `vault_cancel_token_new()`.
+    fn vault_cancel(token: *const ffi::CancelTokenHandle) -> i32 {
+        ffi::status(ffi::CancelTokenHandle::from_ptr(token).map(|token| token.token().cancel()))
+    }
+}
+
+ffi_export! {
+    /// Release a token from `vault_cancel_token_new()`
+    ///
+    /// # Returns
+    /// 0 on success, -1 for a null, freed or foreign pointer (see
+    /// `vault_last_error_message()`).
+    ///
+    /// # Safety
+    /// `token` must come from `vault_cancel_token_new()`, and no call using
+    /// it may still be running. Freeing twice is detected on a best-effort
+    /// basis only.
+    fn vault_cancel_token_free(token: *mut ffi::CancelTokenHandle) -> i32 {
+        ffi::status(ffi::CancelTokenHandle::free(token))
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                         UTILITIES FFI
+// ═══════════════════════════════════════════════════════════════════
+
+ffi_export! {
+    /// Convert block count to estimated time string
+    ///
+    /// # Arguments
+    /// * `blocks` - Number of blocks
+    ///
+    /// # Returns
+    /// Human-readable time estimate (e.g., "~7 days"). Must be freed with `free_rust_string()`.
+    ///
+    /// # Safety
+    /// This function is safe to call from any context.
+    fn ffi_blocks_to_time_estimate(blocks: u32) -> *mut c_char {
+        ffi::to_c_string(&vault::summary::delay_to_duration(blocks))
+    }
+}
+
+ffi_export! {
+    /// Calculate the absolute block height when a CSV timelock unlocks
+    ///
+    /// # Arguments
+    /// * `current_height` - Current blockchain height
+    /// * `delay_blocks` - CSV delay in blocks
+    ///
+    /// # Returns
+    /// The block height at which spending becomes possible.
+    fn ffi_calculate_unlock_height(current_height: u32, delay_blocks: u32) -> u32 {
+        current_height.saturating_add(delay_blocks)
+    }
+}
+
+// ═══════════════════════════════════════════════════════════════════
+//                         UNIT TESTS
+// ═══════════════════════════════════════════════════════════════════
+
+// Lets the shared test fixtures name the crate as the integration tests do
+#[cfg(test)]
+extern crate self as vault_core;
+
+#[cfg(test)]
+mod test_common;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    use crate::test_common::{payload, OWNER_TPUB, OWNER_XPUB, RECOVERY_TPUB, RECOVERY_XPUB};
+
+    #[test]
+    fn test_vault_version_info() {
+        let version = vault_version_info();
+        assert_eq!(
+            format!("{}.{}.{}", version.major, version.minor, version.patch),
+            env!("CARGO_PKG_VERSION")
+        );
+
+        // Flags follow the cargo features of this build
+        assert_eq!(vault_has_feature(ffi::FEATURE_RAND), i32::from(cfg!(feature = "rand")));
+        assert_eq!(version.feature_flags & ffi::FEATURE_RAND != 0, cfg!(feature = "rand"));
+        assert_eq!(vault_has_feature(ffi::FEATURE_PARALLEL), i32::from(cfg!(feature = "parallel")));
+        assert_eq!(vault_has_feature(ffi::FEATURE_TEST_VECTORS), i32::from(cfg!(feature = "test-vectors")));
+        assert_eq!(vault_has_feature(ffi::FEATURE_MUSIG | ffi::FEATURE_UR), 1);
+        assert_eq!(version.feature_flags, ffi::feature_flags());
+
+        // Unknown and empty flags are never set
+        assert_eq!(vault_has_feature(1 << 31), 0);
+        assert_eq!(vault_has_feature(ffi::FEATURE_MUSIG | 1 << 31), 0);
+        assert_eq!(vault_has_feature(0), 0);
+    }
+
+    #[test]
+    fn test_vault_version() {
+        let version_ptr = vault_version();
+        assert!(!version_ptr.is_null());
+
+        unsafe {
+            let version_cstr = CStr::from_ptr(version_ptr);
+            let version = version_cstr.to_str().unwrap();
+
+            assert!(version.contains('.'));
+            assert_eq!(version, env!("CARGO_PKG_VERSION"));
+
+            free_rust_string(version_ptr);
+        }
+    }
+
+    // The network selected by `vault_init()` is process-wide, so every
+    // test here selects regtest. Fresh-process behavior is covered by
+    // tests/network_context.rs.
+
+    #[cfg(feature = "test-vectors")]
+    #[test]
+    fn test_vault_generate_test_vectors() {
+        let read = || {
+            let ptr = vault_generate_test_vectors();
+            let json = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string();
+            free_rust_string(ptr);
+            json
+        };
+        let json = read();
+        assert_eq!(json, read());
+
+        let vectors: serde_json::Value = payload(&json);
+        assert_eq!(vectors["networks"].as_array().unwrap().len(), 5);
+        assert_eq!(vectors["networks"][0]["network"], "mainnet");
+        assert_eq!(vectors["tree_version"], 3);
+        assert!(vectors["unvault"]["psbt_base64"].as_str().unwrap().starts_with("cHNidP8"));
+    }
+
+    #[test]
+    fn test_vault_list_templates() {
+        let result_ptr = vault_list_templates();
+        let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+        free_rust_string(result_ptr);
+
+        let templates: serde_json::Value = payload(&result);
+        let ids: Vec<&str> = templates
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|template| template["template_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["savings_v1", "spending_v1", "custom_v1", "inheritance_v1", "dual_delay_v1", "degrading_v1"]);
+    }
+
+    #[test]
+    fn test_vault_init_keeps_first_network() {
+        assert_eq!(vault_init(3), 0);
+        assert_eq!(vault_init(3), 0);
+        assert_eq!(vault_get_network(), 3);
+
+        assert_eq!(vault_init(0), -1);
+        assert_eq!(vault_last_error_code(), 1003);
+        assert_eq!(vault_get_network(), 3);
+    }
+
+    #[test]
+    fn test_vault_parse_network() {
+        for network in Network::ALL {
+            let name = CString::new(network.to_string().to_uppercase()).unwrap();
+            assert_eq!(vault_parse_network(name.as_ptr()), network as i32);
+            assert_eq!(Network::try_from(network as i32).unwrap(), network);
+        }
+
+        let unknown = CString::new("bitcoin").unwrap();
+        assert_eq!(vault_parse_network(unknown.as_ptr()), -1);
+        assert_eq!(vault_last_error_code(), 4002);
+        assert_eq!(vault_parse_network(std::ptr::null()), -1);
+    }
+
+    #[test]
+    fn test_vault_init_invalid_network() {
+        assert_eq!(vault_init(5), -1);
+        assert_eq!(vault_init(-1), -1);
+        assert_eq!(vault_init(999), -1);
+    }
+
+    #[test]
+    fn test_free_rust_string_null_is_safe() {
+        free_rust_string(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn test_blocks_to_time_estimate() {
+        unsafe {
+            // 6 blocks = ~60 minutes = ~1 hour
+            let ptr = ffi_blocks_to_time_estimate(6);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "~1 hour");
+            free_rust_string(ptr);
+
+            // 144 blocks = ~1 day
+            let ptr = ffi_blocks_to_time_estimate(144);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "~1 day");
+            free_rust_string(ptr);
+
+            // 1008 blocks = ~7 days
+            let ptr = ffi_blocks_to_time_estimate(1008);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "~7 days");
+            free_rust_string(ptr);
+
+            // 3 blocks = ~30 minutes
+            let ptr = ffi_blocks_to_time_estimate(3);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "~30 minutes");
+            free_rust_string(ptr);
+        }
+    }
+
+    #[test]
+    fn test_calculate_unlock_height() {
+        assert_eq!(ffi_calculate_unlock_height(800_000, 1008), 801_008);
+        assert_eq!(ffi_calculate_unlock_height(800_000, 144), 800_144);
+        assert_eq!(ffi_calculate_unlock_height(u32::MAX, 1), u32::MAX); // saturating
+    }
+
+    #[test]
+    fn test_ffi_validate_xpub() {
+        let xpub = std::ffi::CString::new(OWNER_XPUB).unwrap();
+
+        unsafe {
+            let result_ptr = ffi_validate_xpub(xpub.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none());
+            assert_eq!(result["fingerprint"], "3442193e");
+            assert_eq!(result["supports_taproot"], true);
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_validate_xpub() {
+        let xpub = std::ffi::CString::new(OWNER_XPUB).unwrap();
+
+        unsafe {
+            let result_ptr = vault_validate_xpub(xpub.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            assert_eq!(result["fingerprint"], "3442193e");
+            assert_eq!(result["depth"], 0);
+            assert_eq!(result["child_number"], 0);
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_validate_xpub_network_mismatch() {
+        let tpub = std::ffi::CString::new(OWNER_TPUB).unwrap();
+
+        unsafe {
+            let result_ptr = vault_validate_xpub(tpub.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert_eq!(result["error"], true);
+            assert_eq!(result["code"], 1003);
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_validate_xpub_garbage() {
+        let garbage = std::ffi::CString::new("definitely-not-an-xpub").unwrap();
+
+        unsafe {
+            let result_ptr = vault_validate_xpub(garbage.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert_eq!(result["error"], true);
+            assert_eq!(result["code"], 1001);
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_derive_key() {
+        let xpub = std::ffi::CString::new(OWNER_XPUB).unwrap();
+
+        unsafe {
+            let result_ptr = vault_derive_key(xpub.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            assert_eq!(
+                result["public_key"],
+                "756de182c5dd4b717ea87e693006da62dbb3cddaa4a5cad2ed1f5bbab755f0f5"
+            );
+            assert_eq!(result["path"], "m/86'/0'/0'/0/0");
+            assert_eq!(result["parent_fingerprint"], "9cc81b61");
+            free_rust_string(result_ptr);
+
+            let result_ptr = vault_derive_key(xpub.as_ptr(), 0x8000_0000);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert_eq!(result["code"], 3001);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_ffi_get_derivation_path() {
+        unsafe {
+            let ptr = ffi_get_derivation_path(0, 0);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "m/86'/0'/0'/0/0");
+            free_rust_string(ptr);
+
+            let ptr = ffi_get_derivation_path(5, 1);
+            let s = CStr::from_ptr(ptr).to_str().unwrap();
+            assert_eq!(s, "m/86'/1'/0'/0/5");
+            free_rust_string(ptr);
+        }
+    }
+
+    #[test]
+    fn test_ffi_generate_vault_address() {
+        let params = serde_json::json!({
+            "primary_xpub": OWNER_XPUB,
+            "template": {"type": "savings"},
+            "vault_index": 0
+        });
+        let params_cstr = std::ffi::CString::new(params.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = ffi_generate_vault_address(params_cstr.as_ptr(), 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            assert!(result["address"].as_str().unwrap().starts_with("bc1p"));
+            assert_eq!(result["metadata_mode"], "full");
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_get_address() {
+        let config = serde_json::json!({
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_get_address(config_cstr.as_ptr(), 0, 0);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            assert_eq!(
+                result["address"],
+                "bc1ppwlpr72ejugtz4v6x3aujy0qkyzmkuwhu2npg0qfkz4r33l89acsuwkqqh"
+            );
+            let spk = result["script_pubkey"].as_str().unwrap();
+            assert!(spk.starts_with("5120"));
+            assert_eq!(spk.len(), 68);
+            assert_eq!(result["merkle_root"].as_str().unwrap().len(), 64);
+
+            free_rust_string(result_ptr);
+
+            // Mainnet keys on signet are rejected
+            let result_ptr = vault_get_address(config_cstr.as_ptr(), 0, 2);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert_eq!(result["code"], 1003);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    const DESTINATION: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    /// Template and account xpubs of the spending vault the request tests use
+    fn vault_config() -> serde_json::Value {
+        serde_json::json!({
+            "template": {"type": "spending"},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB
+        })
+    }
+
+    fn unvault_request(amount_sats: u64) -> serde_json::Value {
+        let mut request = vault_config();
+        request["utxo"] = serde_json::json!(
+            {"txid": "ab".repeat(32), "vout": 1, "amount_sats": amount_sats, "vault_index": 0}
+        );
+        request["destination"] = serde_json::json!(DESTINATION);
+        request["fee_rate"] = serde_json::json!(2);
+        request["metadata"] = serde_json::json!({
+            "version": 1,
+            "template_id": "spending_v1",
+            "delay_blocks": 144,
+            "destination_indices": [],
+            "recovery_type": "emergency_key",
+            "created_at_block": 0,
+            "vault_index": 0
+        });
+        request
+    }
+
+    #[test]
+    fn test_vault_build_unvault_psbt() {
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+            assert_eq!(psbt.unsigned_tx.input[0].sequence, bitcoin::Sequence::from_height(144));
+            assert_eq!(psbt.inputs[0].tap_scripts.len(), 1);
+            assert_eq!(result["change"], serde_json::json!({"kind": "none"}));
+
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_requests_reject_unknown_fields() {
+        let call = |ptr: *mut c_char| unsafe {
+            let result: serde_json::Value = payload(CStr::from_ptr(ptr).to_str().unwrap());
+            free_rust_string(ptr);
+            result
+        };
+
+        // A recovery field on an unvault request
+        let mut request = unvault_request(100_000);
+        request["cold_address"] = serde_json::json!(DESTINATION);
+        let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+        let result = call(vault_build_unvault_psbt(request_cstr.as_ptr(), 3));
+        assert_eq!(result["code"], 4002);
+        assert!(result["message"].as_str().unwrap().contains("unknown field `cold_address`"));
+
+        // Also past a flattened config
+        let mut config = vault_config();
+        config["vault_index"] = serde_json::json!(0);
+        config["network"] = serde_json::json!("regtest");
+        config["vault_idx"] = serde_json::json!(0);
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+        let result = call(vault_create(config_cstr.as_ptr()));
+        assert_eq!(result["code"], 4001);
+        assert!(result["message"].as_str().unwrap().contains("unknown field `vault_idx`"));
+    }
+
+    #[test]
+    fn test_vault_read_psbt_vault_info() {
+        let call = |ptr: *mut c_char| unsafe {
+            let result: serde_json::Value = payload(CStr::from_ptr(ptr).to_str().unwrap());
+            free_rust_string(ptr);
+            result
+        };
+        let read = |psbt_base64: &serde_json::Value| {
+            let psbt_cstr = std::ffi::CString::new(psbt_base64.as_str().unwrap()).unwrap();
+            call(vault_read_psbt_vault_info(psbt_cstr.as_ptr()))
+        };
+        let build = |request: &serde_json::Value| {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            call(vault_build_unvault_psbt(request_cstr.as_ptr(), 3))
+        };
+
+        let mut request = unvault_request(100_000);
+        let result = build(&request);
+        assert_eq!(read(&result["psbt_base64"]), serde_json::json!({"vault_info": null}));
+
+        request["psbt_vault_info"] = serde_json::json!(true);
+        let result = build(&request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        let info = read(&result["psbt_base64"]);
+        assert_eq!(info["vault_info"]["spend_path"], serde_json::json!({"type": "timelock_leaf"}));
+        assert_eq!(info["vault_info"]["metadata"]["delay_blocks"], request["metadata"]["delay_blocks"]);
+        assert_eq!(info["vault_info"]["library_version"], env!("CARGO_PKG_VERSION"));
+
+        assert_eq!(read(&serde_json::json!("not a psbt"))["code"], 2001);
+    }
+
+    #[test]
+    fn test_vault_build_psbt_memo() {
+        let build = |request: &serde_json::Value, recovery: bool| unsafe {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = if recovery {
+                vault_build_recovery_psbt(request_cstr.as_ptr(), 3)
+            } else {
+                vault_build_unvault_psbt(request_cstr.as_ptr(), 3)
+            };
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+            result
+        };
+        let memo_output = |result: &serde_json::Value| {
+            let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+            hex::encode(psbt.unsigned_tx.output.last().unwrap().script_pubkey.as_bytes())
+        };
+
+        let mut request = unvault_request(100_000);
+        request["memo"] = serde_json::json!("deadbeef");
+        assert_eq!(memo_output(&build(&request, false)), "6a04deadbeef");
+
+        let mut request = vault_config();
+        request["utxos"] = serde_json::json!([unvault_request(100_000)["utxo"]]);
+        request["cold_address"] = serde_json::json!(DESTINATION);
+        request["fee_rate"] = serde_json::json!(2);
+        request["memo"] = serde_json::json!("deadbeef");
+        assert_eq!(memo_output(&build(&request, true)), "6a04deadbeef");
+
+        request["memo"] = serde_json::json!("xyz");
+        assert_eq!(build(&request, false)["code"], 4002);
+        request["memo"] = serde_json::json!("00".repeat(81));
+        assert_eq!(build(&request, true)["code"], 2003);
+    }
+
+    #[test]
+    fn test_vault_build_unvault_psbt_change() {
+        let build = |request: &serde_json::Value| unsafe {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+            result
+        };
+
+        let mut request = unvault_request(100_000);
+        request["amount_sats"] = serde_json::json!(40_000);
+        let result = build(&request);
+        assert_eq!(result["code"], 4002);
+        assert!(result["message"].as_str().unwrap().contains("change_index"));
+
+        request["change_index"] = serde_json::json!(5);
+        let result = build(&request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        assert_eq!(result["change"]["kind"], "output");
+        assert_eq!(result["change"]["vault_index"], 5);
+        assert_eq!(result["change"]["vout"], 1);
+        let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        assert_eq!(psbt.unsigned_tx.output[1].value, result["change"]["amount_sats"].as_u64().unwrap());
+
+        request["dust_threshold"] = serde_json::json!(100_000);
+        let result = build(&request);
+        assert_eq!(result["change"]["kind"], "added_to_fee");
+        let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_vault_build_unvault_psbt_dust_limit() {
+        let build = |request: &serde_json::Value| unsafe {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+            result
+        };
+
+        let mut request = unvault_request(100_000);
+        request["amount_sats"] = serde_json::json!(70_000);
+        request["change_index"] = serde_json::json!(5);
+        assert_eq!(build(&request)["change"]["kind"], "output");
+
+        // About 29,700 sats of change falls short of a 40,000-sat limit
+        request["dust_limit_sats"] = serde_json::json!(40_000);
+        let result = build(&request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        assert_eq!(result["change"]["kind"], "added_to_fee");
+        assert_eq!(result["change"]["dust_limit_sats"], 40_000);
+
+        request["amount_sats"] = serde_json::json!(30_000);
+        let result = build(&request);
+        assert_eq!(result["code"], 2003);
+        assert_eq!(
+            result["message"].as_str().unwrap(),
+            "Policy violation: Output 0 pays 30000 sats, below its dust limit of 40000 sats"
+        );
+    }
+
+    #[test]
+    fn test_vault_build_unvault_psbt_insufficient_funds() {
+        let request_cstr = std::ffi::CString::new(unvault_request(200).to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert_eq!(result["code"], 2002);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_build_recovery_psbt() {
+        let mut request = vault_config();
+        request["utxos"] = serde_json::json!([
+            {"txid": "ab".repeat(32), "vout": 0, "amount_sats": 60_000, "vault_index": 0},
+            {"txid": "cd".repeat(32), "vout": 1, "amount_sats": 40_000, "vault_index": 9}
+        ]);
+        request["cold_address"] = serde_json::json!(DESTINATION);
+        request["fee_rate"] = serde_json::json!(2);
+        let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_build_recovery_psbt(request_cstr.as_ptr(), 3);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+            assert_eq!(psbt.unsigned_tx.input.len(), 2);
+            assert_eq!(psbt.unsigned_tx.output.len(), 1);
+            free_rust_string(result_ptr);
+
+            // An empty UTXO list is rejected
+            request["utxos"] = serde_json::json!([]);
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = vault_build_recovery_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 4002);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_build_consolidation_psbt() {
+        let mut request = vault_config();
+        request["utxos"] = serde_json::json!([
+            {"txid": "ab".repeat(32), "vout": 0, "amount_sats": 60_000, "vault_index": 0},
+            {"txid": "cd".repeat(32), "vout": 1, "amount_sats": 40_000, "vault_index": 9}
+        ]);
+        request["target_index"] = serde_json::json!(10);
+        request["fee_rate"] = serde_json::json!(2);
+        let build = |request: &serde_json::Value| -> serde_json::Value {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = vault_build_consolidation_psbt(request_cstr.as_ptr(), 3);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        let result = build(&request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(result["vault_index"], 10);
+        assert_eq!(result["total_input_sats"], 100_000);
+        assert_eq!(
+            psbt.unsigned_tx.output[0].value + result["fee_sats"].as_u64().unwrap(),
+            100_000
+        );
+        assert_eq!(result["fee_warning"], serde_json::Value::Null);
+
+        request["fee_warning_percent"] = serde_json::json!(0);
+        assert!(build(&request)["fee_warning"].is_string());
+
+        request["utxos"] = serde_json::json!([]);
+        assert_eq!(build(&request)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_build_deposit_psbt() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let key = bitcoin::secp256k1::SecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp);
+        let wpkh = bitcoin::PublicKey::new(key).wpubkey_hash().unwrap();
+        let vault_address = bitcoin::Address::p2tr(&secp, key.x_only_public_key().0, None, bitcoin::Network::Regtest);
+        let mut request = serde_json::json!({
+            "inputs": [{
+                "txid": "ab".repeat(32),
+                "vout": 0,
+                "amount_sats": 100_000,
+                "script_pubkey": hex::encode(bitcoin::ScriptBuf::new_v0_p2wpkh(&wpkh).as_bytes()),
+                "pubkey": key.to_string(),
+                "fingerprint": "d34db33f",
+                "path": "m/84h/1h/0h/0/0"
+            }],
+            "vault_address": vault_address.to_string(),
+            "amount_sats": 60_000,
+            "change_address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            "fee_rate": 2
+        });
+        let build = |request: &serde_json::Value| -> serde_json::Value {
+            let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = vault_build_deposit_psbt(request_cstr.as_ptr(), 3);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        let result = build(&request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        assert_eq!(psbt.unsigned_tx.output[0].value, 60_000);
+        assert_eq!(psbt.unsigned_tx.output[0].script_pubkey, vault_address.script_pubkey());
+        assert_eq!(
+            psbt.unsigned_tx.output[1].value + result["fee_sats"].as_u64().unwrap(),
+            40_000
+        );
+        let (fingerprint, path) = &psbt.inputs[0].bip32_derivation[&key];
+        assert_eq!(fingerprint.to_string(), "d34db33f");
+        assert_eq!(path.to_string(), "m/84'/1'/0'/0/0");
+
+        // A partial key origin is rejected
+        request["inputs"][0]["path"] = serde_json::Value::Null;
+        assert_eq!(build(&request)["code"], 4002);
+
+        // So is a mainnet vault address on regtest
+        request["inputs"][0]["path"] = serde_json::json!("m/84h/1h/0h/0/0");
+        request["vault_address"] =
+            serde_json::json!(bitcoin::Address::p2tr(&secp, key.x_only_public_key().0, None, bitcoin::Network::Bitcoin).to_string());
+        assert!(build(&request)["error"].as_bool().unwrap());
+    }
+
+    #[test]
+    fn test_vault_finalize_psbt() {
+        let (psbt_base64, xpriv) = {
+            let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap());
+            free_rust_string(result_ptr);
+
+            // BIP32 test vector 1 master, the private half of the owner tpub
+            let mut xpriv: bitcoin::bip32::ExtendedPrivKey = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi".parse().unwrap();
+            xpriv.network = bitcoin::Network::Regtest;
+            (result["psbt_base64"].as_str().unwrap().to_string(), xpriv)
+        };
+
+        unsafe {
+            // Unsigned PSBTs report the missing signature
+            let unsigned_cstr = std::ffi::CString::new(psbt_base64.clone()).unwrap();
+            let result_ptr = vault_finalize_psbt(unsigned_cstr.as_ptr());
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 2001);
+            assert!(result["message"].as_str().unwrap().contains("Input 0"));
+            free_rust_string(result_ptr);
+
+            let mut psbt = vault::psbt::from_base64(&psbt_base64).unwrap();
+            keys::sign_psbt(&mut psbt, &xpriv.into(), Network::Regtest).unwrap();
+            let signed_cstr = std::ffi::CString::new(vault::psbt::to_base64(&psbt)).unwrap();
+            let result_ptr = vault_finalize_psbt(signed_cstr.as_ptr());
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            let tx_bytes = hex::decode(result["tx_hex"].as_str().unwrap()).unwrap();
+            let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&tx_bytes).unwrap();
+            assert_eq!(tx.input[0].witness.len(), 3);
+            assert_eq!(result["txid"], tx.txid().to_string());
+            assert_eq!(result["warnings"], serde_json::json!([]));
+            free_rust_string(result_ptr);
+        }
+    }
+
+    ffi_export! {
+        /// Test-only export that always panics
+        fn vault_test_panic(message: *const c_char) -> *mut c_char {
+            let message = ffi::from_c_string(message).unwrap();
+            panic!("{}", message);
+        }
+    }
+
+    ffi_export! {
+        /// Test-only integer export that always panics
+        fn vault_test_panic_status() -> i32 {
+            let metadata = VaultMetadata::from_bytes(&[1]);
+            metadata.unwrap().version as i32
+        }
+    }
+
+    #[test]
+    fn test_panic_is_caught_at_ffi_boundary() {
+        let message = std::ffi::CString::new("deliberate").unwrap();
+
+        unsafe {
+            let result_ptr = vault_test_panic(message.as_ptr());
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            assert_eq!(result["error"], true);
+            assert_eq!(result["code"], 5000);
+            assert!(result["message"].as_str().unwrap().contains("deliberate"));
+            free_rust_string(result_ptr);
+        }
+
+        assert_eq!(vault_test_panic_status(), -1);
+
+        // The process is still healthy after the caught panics
+        assert_eq!(vault_init(3), 0);
+    }
+
+    #[test]
+    fn test_vault_mnemonic_to_xpub() {
+        assert_eq!(vault_init(3), 0);
+        let words = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let call = |words: &str, account: u32| -> serde_json::Value {
+            let words = std::ffi::CString::new(words).unwrap();
+            let passphrase = std::ffi::CString::new("").unwrap();
+            let result_ptr = vault_mnemonic_to_xpub(words.as_ptr(), passphrase.as_ptr(), account);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        let result = call(words, 1);
+        let master = keys::from_mnemonic(words, "", Network::Regtest).unwrap();
+        let account = keys::account_xpriv(&master, keys::TAPROOT_PURPOSE, 1, Network::Regtest).unwrap();
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let xpub = bitcoin::bip32::ExtendedPubKey::from_priv(&secp, &account.xpriv().unwrap());
+        assert_eq!(result["xpub"], xpub.to_string());
+        assert_eq!(result["master_fingerprint"], "73c5da0a");
+        assert_eq!(result["path"], "m/86'/1'/1'");
+        assert!(!result.to_string().contains("prv"), "{}", result);
+
+        let result = call(&words.replace("about", "abandon"), 0);
+        assert_eq!(result["code"], 1004);
+        assert_eq!(result["details"]["reason"], "bad_checksum");
+    }
+
+    #[test]
+    fn test_last_error_after_failed_init() {
+        assert_eq!(vault_init(7), -1);
+        assert_eq!(vault_last_error_code(), 4002);
+
+        let message_ptr = vault_last_error_message();
+        assert!(!message_ptr.is_null());
+        let message = unsafe { CStr::from_ptr(message_ptr) }.to_str().unwrap().to_string();
+        assert!(message.contains('7'), "{}", message);
+        free_rust_string(message_ptr);
+
+        // A successful call clears it
+        assert_eq!(vault_init(3), 0);
+        assert_eq!(vault_last_error_code(), 0);
+        assert!(vault_last_error_message().is_null());
+    }
+
+    #[test]
+    fn test_last_error_is_thread_local() {
+        assert_eq!(vault_init(42), -1);
+        assert_eq!(vault_last_error_code(), 4002);
+
+        std::thread::spawn(|| {
+            // Nothing leaks in from the other thread
+            assert_eq!(vault_last_error_code(), 0);
+            assert!(vault_last_error_message().is_null());
+
+            assert_eq!(vault_init(-5), -1);
+            assert_eq!(vault_last_error_code(), 4002);
+        })
+        .join()
+        .unwrap();
+
+        // The other thread's error doesn't overwrite this one
+        let message_ptr = vault_last_error_message();
+        let message = unsafe { CStr::from_ptr(message_ptr) }.to_str().unwrap().to_string();
+        assert!(message.contains("42"), "{}", message);
+        assert!(!message.contains("-5"), "{}", message);
+        free_rust_string(message_ptr);
+    }
+
+    static LOGGED: std::sync::Mutex<Vec<(i32, String)>> = std::sync::Mutex::new(Vec::new());
+
+    extern "C" fn collect_log(level: i32, msg: *const c_char) {
+        let msg = unsafe { CStr::from_ptr(msg) }.to_str().unwrap().to_string();
+        LOGGED.lock().unwrap().push((level, msg));
+    }
+
+    /// Messages containing `needle` logged so far, with their levels
+    fn logged(needle: &str) -> Vec<(i32, String)> {
+        LOGGED.lock().unwrap().iter().filter(|(_, msg)| msg.contains(needle)).cloned().collect()
+    }
+
+    #[test]
+    fn test_vault_log_callback() {
+        assert_eq!(vault_set_log_callback(None, 1), -1);
+        assert_eq!(vault_last_error_code(), 4002);
+        assert_eq!(vault_set_log_callback(Some(collect_log), 5), -1);
+
+        assert_eq!(vault_set_log_callback(Some(collect_log), 1), 0);
+        vault_get_network();
+        let entries = logged("vault_get_network called");
+        assert!(entries.iter().any(|(level, msg)| *level == 1 && msg.starts_with("vault_core::ffi: ")), "{:?}", entries);
+
+        // Errors are logged as warnings, from every calling thread
+        let threads: Vec<_> = (0..4)
+            .map(|n| std::thread::spawn(move || assert_eq!(vault_init(100 + n), -1)))
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for n in 100..104 {
+            let entries = logged(&format!("Invalid network value: {}", n));
+            assert!(entries.iter().any(|(level, _)| *level == 3), "{}: {:?}", n, entries);
+        }
+
+        // Below the minimum level, nothing is forwarded
+        assert_eq!(vault_set_log_callback(Some(collect_log), 3), 0);
+        log::debug!("log callback marker 1");
+        log::warn!("log callback marker 2");
+        assert!(logged("log callback marker 1").is_empty());
+        assert_eq!(logged("log callback marker 2"), vec![(3, "vault_core::tests: log callback marker 2".to_string())]);
+
+        vault_clear_log_callback();
+        log::error!("log callback marker 3");
+        assert!(logged("log callback marker 3").is_empty());
+    }
+
+    #[test]
+    fn test_last_error_set_by_caught_panic() {
+        assert_eq!(vault_test_panic_status(), -1);
+        assert_eq!(vault_last_error_code(), 5000);
+    }
+
+    fn test_metadata() -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![0, 2],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 0,
+            vault_index: 0,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_vault_metadata_encode_decode_roundtrip() {
+        let metadata = test_metadata();
+        let json = std::ffi::CString::new(serde_json::to_string(&metadata).unwrap()).unwrap();
+
+        let buffer = vault_metadata_encode(json.as_ptr());
+        assert!(!buffer.is_null());
+        let encoded = unsafe { std::slice::from_raw_parts(buffer.ptr, buffer.len) }.to_vec();
+        assert_eq!(encoded, metadata.to_bytes());
+        // Zero bytes survive the trip, unlike with C strings
+        assert!(encoded.contains(&0));
+
+        unsafe {
+            let result_ptr = vault_metadata_decode(buffer.ptr, buffer.len);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let decoded: VaultMetadata = serde_json::from_value(payload(result_str)).unwrap();
+            assert_eq!(decoded.template_id, metadata.template_id);
+            assert_eq!(decoded.destination_indices, metadata.destination_indices);
+            assert_eq!(decoded.delay_blocks, metadata.delay_blocks);
+            free_rust_string(result_ptr);
+        }
+
+        free_rust_bytes(buffer);
+    }
+
+    #[test]
+    fn test_vault_metadata_to_json_envelope() {
+        let metadata = test_metadata();
+        let bytes_hex = std::ffi::CString::new(hex::encode(metadata.to_bytes())).unwrap();
+
+        let result_ptr = vault_metadata_to_json(bytes_hex.as_ptr());
+        let response: serde_json::Value =
+            serde_json::from_str(unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap()).unwrap();
+        free_rust_string(result_ptr);
+        assert_eq!(response["error"], false);
+        assert_eq!(response["ffi_schema"], ffi::FFI_SCHEMA_VERSION);
+        assert_eq!(response["data"]["metadata_json"], metadata.to_json().unwrap());
+    }
+
+    #[test]
+    fn test_vault_metadata_encode_invalid_json() {
+        let json = std::ffi::CString::new("{\"version\": 1}").unwrap();
+        let buffer = vault_metadata_encode(json.as_ptr());
+        assert!(buffer.is_null());
+        assert_eq!(buffer.len, 0);
+        assert_eq!(vault_last_error_code(), 4002);
+
+        assert!(vault_metadata_encode(std::ptr::null()).is_null());
+
+        // Freeing a null buffer is a no-op
+        free_rust_bytes(buffer);
+        free_rust_bytes(ffi::ByteBuffer::null());
+    }
+
+    #[test]
+    fn test_vault_metadata_decode_null_and_empty() {
+        unsafe {
+            for (ptr, len, code) in [(std::ptr::null(), 0, 3002), (std::ptr::null(), 16, 4002)] {
+                let result_ptr = vault_metadata_decode(ptr, len);
+                let result: serde_json::Value =
+                    payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+                assert_eq!(result["code"], code);
+                free_rust_string(result_ptr);
+            }
+
+            let truncated = [1u8, 10, b's'];
+            let result_ptr = vault_metadata_decode(truncated.as_ptr(), truncated.len());
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 3002);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    fn handle_config() -> std::ffi::CString {
+        let mut config = vault_config();
+        config["network"] = serde_json::json!("regtest");
+        std::ffi::CString::new(config.to_string()).unwrap()
+    }
+
+    fn handle_address(handle: *const ffi::VaultHandle, vault_index: u32) -> serde_json::Value {
+        unsafe {
+            let result_ptr = vault_handle_get_address(handle, vault_index);
+            let result = payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+            result
+        }
+    }
+
+    #[test]
+    fn test_vault_export_descriptor() {
+        let mut config = vault_config();
+        config["range_end"] = serde_json::json!(49);
+        config["tree_version"] = serde_json::json!(2);
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_export_descriptor(config_cstr.as_ptr(), 3);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+
+            let request = &result.as_array().expect("importdescriptors takes an array")[0];
+            assert!(request["desc"].as_str().unwrap().starts_with("tr("));
+            assert_eq!(request["active"], true);
+            assert_eq!(request["range"], serde_json::json!([0, 49]));
+            assert_eq!(request["timestamp"], "now");
+            free_rust_string(result_ptr);
+
+            config["timestamp"] = serde_json::json!(1_700_000_000u64);
+            let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+            let result_ptr = vault_export_descriptor(config_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result[0]["timestamp"], 1_700_000_000u64);
+            free_rust_string(result_ptr);
+
+            // Testnet keys on mainnet
+            let result_ptr = vault_export_descriptor(config_cstr.as_ptr(), 0);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert!(result.get("error").is_some());
+            free_rust_string(result_ptr);
+
+            // Version 1 trees have no descriptor
+            config["tree_version"] = serde_json::json!(1);
+            let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+            let result_ptr = vault_export_descriptor(config_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 4002);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_check_psbt() {
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+        let config = serde_json::json!({
+            "network": "regtest",
+            "template": {"type": "spending"},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB,
+            "max_fee_sats": 100
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            let psbt_cstr = std::ffi::CString::new(result["psbt_base64"].as_str().unwrap()).unwrap();
+            free_rust_string(result_ptr);
+
+            let result_ptr = vault_check_psbt(psbt_cstr.as_ptr(), config_cstr.as_ptr());
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            assert_eq!(result["passed"], false);
+            let checks = result["checks"].as_array().unwrap();
+            assert_eq!(checks[0]["rule"], "vault_input");
+            assert_eq!(checks[0]["passed"], true);
+            assert_eq!(checks[3]["rule"], "fee_ceiling");
+            assert_eq!(checks[3]["passed"], false);
+            free_rust_string(result_ptr);
+
+            // The unvault sends its whole 100,000 sats out, over a 0.5 BTC
+            // window that already holds 49,950,000
+            let mut velocity_config = config.clone();
+            velocity_config["velocity_limit"] = serde_json::json!({"max_sats": 50_000_000, "window_blocks": 1008});
+            velocity_config["spend_history"] =
+                serde_json::json!([{"txid": "ab".repeat(32), "height": 990, "amount_sats": 49_950_000}]);
+            velocity_config["current_block_height"] = serde_json::json!(1000);
+            let velocity_cstr = std::ffi::CString::new(velocity_config.to_string()).unwrap();
+            let result_ptr = vault_check_psbt(psbt_cstr.as_ptr(), velocity_cstr.as_ptr());
+            let result: serde_json::Value = payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            let checks = result["checks"].as_array().unwrap();
+            assert_eq!(checks[4]["rule"], "velocity");
+            assert_eq!(checks[4]["passed"], false);
+            free_rust_string(result_ptr);
+
+            let garbage = std::ffi::CString::new("not a psbt").unwrap();
+            let result_ptr = vault_check_psbt(garbage.as_ptr(), config_cstr.as_ptr());
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 2001);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_describe() {
+        let call = |json: &str| unsafe {
+            let json = std::ffi::CString::new(json).unwrap();
+            let ptr = vault_describe(json.as_ptr());
+            let result: serde_json::Value = payload(CStr::from_ptr(ptr).to_str().unwrap());
+            free_rust_string(ptr);
+            result
+        };
+        let config = serde_json::json!({
+            "network": "regtest",
+            "template": {"type": "custom", "delay_blocks": 288, "recovery_type": "timelock_only"},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB
+        });
+
+        let result = call(&config.to_string());
+        assert_eq!(result["template_id"], "custom_v1");
+        let path = &result["spend_paths"][0];
+        assert_eq!(path["leaf"], "timelock");
+        assert_eq!(path["delay"], 288);
+        assert_eq!(path["delay_unit"], "blocks");
+        assert_eq!(path["duration"], "~2 days");
+        assert_eq!(result["spend_paths"].as_array().unwrap().len(), 1);
+        assert_eq!(result["whitelist_size"], serde_json::Value::Null);
+        assert_eq!(result["warnings"], serde_json::json!([{"kind": "no_recovery_path"}]));
+
+        assert_eq!(call(r#"{"network":"regtest"}"#)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_list_leaves() {
+        let config = serde_json::json!({
+            "network": "regtest",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_list_leaves(config_cstr.as_ptr());
+            let leaves: serde_json::Value = payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+
+            let purposes: Vec<_> = leaves.as_array().unwrap().iter().map(|leaf| leaf["purpose"].as_str().unwrap()).collect();
+            assert_eq!(purposes, ["timelock", "emergency"]);
+            let timelock = &leaves[0];
+            assert!(timelock["asm"].as_str().unwrap().starts_with("f003[2] OP_CSV OP_VERIFY "));
+            assert_eq!(timelock["tokens"][1], "OP_CSV");
+            assert!(timelock["script"].as_str().unwrap().starts_with("02f003b269"));
+            assert_eq!(timelock["leaf_hash"].as_str().unwrap().len(), 64);
+            assert_eq!(leaves[1]["depth"], 1);
+            assert!(leaves[1]["asm"].as_str().unwrap().ends_with("[32] OP_CHECKSIG"));
+        }
+    }
+
+    #[test]
+    fn test_vault_psbt_status() {
+        let call = |ptr: *mut c_char| unsafe {
+            let result: serde_json::Value = payload(CStr::from_ptr(ptr).to_str().unwrap());
+            free_rust_string(ptr);
+            result
+        };
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+        let config = serde_json::json!({
+            "network": "regtest",
+            "template": {"type": "spending"},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        let built = call(vault_build_unvault_psbt(request_cstr.as_ptr(), 3));
+        let psbt_cstr = std::ffi::CString::new(built["psbt_base64"].as_str().unwrap()).unwrap();
+        let result = call(vault_psbt_status(psbt_cstr.as_ptr(), config_cstr.as_ptr()));
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        assert_eq!(result["ready"], false);
+        assert!(result["fee_sats"].as_u64().unwrap() > 0);
+        assert!(result["estimated_vsize"].as_u64().unwrap() > 0);
+        let input = &result["inputs"][0];
+        assert_eq!(input["spend_path"], serde_json::json!({"type": "timelock_leaf"}));
+        assert_eq!((input["required"].as_u64(), input["signed"].as_u64()), (Some(1), Some(0)));
+        assert_eq!(input["signers"].as_array().unwrap().len(), 1);
+        assert_eq!(input["signers"][0]["signed"], false);
+        assert_eq!(input["finalizable"], false);
+
+        let garbage = std::ffi::CString::new("not a psbt").unwrap();
+        let result = call(vault_psbt_status(garbage.as_ptr(), config_cstr.as_ptr()));
+        assert_eq!(result["code"], 2001);
+    }
+
+    #[test]
+    fn test_json_argument_invalid_utf8() {
+        let psbt_cstr = std::ffi::CString::new("cHNidP8=").unwrap();
+        let config = std::ffi::CString::new(b"{\"network\":\"\xffregtest\"}".to_vec()).unwrap();
+        let result_ptr = vault_check_psbt(psbt_cstr.as_ptr(), config.as_ptr());
+        let result: serde_json::Value =
+            payload(unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap());
+        free_rust_string(result_ptr);
+        assert_eq!(result["code"], 4004);
+        assert_eq!(result["details"], serde_json::json!({"offset": 12}));
+    }
+
+    #[test]
+    fn test_vault_bump_psbt_fee() {
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            let psbt_cstr = std::ffi::CString::new(result["psbt_base64"].as_str().unwrap()).unwrap();
+            free_rust_string(result_ptr);
+
+            let result_ptr = vault_bump_psbt_fee(psbt_cstr.as_ptr(), 10);
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+            let psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+            assert_eq!(result["fee_sats"], 100_000 - psbt.unsigned_tx.output[0].value);
+            free_rust_string(result_ptr);
+
+            // Not enough over the original 2 sat/vB
+            let result_ptr = vault_bump_psbt_fee(psbt_cstr.as_ptr(), 2);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 2003);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_combine_psbts() {
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+        let combine = |psbts: serde_json::Value| -> serde_json::Value {
+            let psbts = std::ffi::CString::new(psbts.to_string()).unwrap();
+            let result_ptr = vault_combine_psbts(psbts.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+        let result: serde_json::Value =
+            payload(unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap());
+        free_rust_string(result_ptr);
+        let psbt = result["psbt_base64"].as_str().unwrap().to_string();
+
+        let result = combine(serde_json::json!([psbt, psbt]));
+        assert_eq!(result["psbt_base64"], psbt);
+
+        let mut other = vault::psbt::from_base64(&psbt).unwrap();
+        other.unsigned_tx.output[0].value -= 1;
+        let result = combine(serde_json::json!([psbt, vault::psbt::to_base64(&other)]));
+        assert_eq!(result["code"], 2001);
+        assert!(result["message"].as_str().unwrap().contains("output 0 value"), "{}", result);
+
+        assert_eq!(combine(serde_json::json!([]))["code"], 2001);
+        assert_eq!(combine(serde_json::json!({}))["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_psbt_external_signing() {
+        fn call(export: impl Fn(*const c_char) -> *mut c_char, request: serde_json::Value) -> serde_json::Value {
+            let request = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = export(request.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        }
+        let built = call(
+            |request| vault_build_unvault_psbt(request, 3),
+            unvault_request(100_000),
+        );
+        let psbt = built["psbt_base64"].as_str().unwrap().to_string();
+
+        let result = call(
+            |r| vault_psbt_sighashes(r),
+            serde_json::json!({"psbt": psbt, "spend_path": {"type": "timelock_leaf"}}),
+        );
+        let info = &result["sighashes"][0];
+        assert_eq!(result["sighashes"].as_array().unwrap().len(), 1);
+        assert_eq!(info["input_index"], 0);
+        assert_eq!(info["sighash_type"], 0);
+        assert!(info["leaf_hash"].is_string());
+        let bad_path = serde_json::json!({"psbt": psbt, "spend_path": {"type": "sideways"}});
+        assert_eq!(call(|r| vault_psbt_sighashes(r), bad_path)["code"], 4002);
+
+        // Sign the digest with the owner key, as an HSM holding it would
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let pubkey: bitcoin::secp256k1::XOnlyPublicKey = info["pubkey"].as_str().unwrap().parse().unwrap();
+        let parsed = vault::psbt::from_base64(&psbt).unwrap();
+        let (_, (_, path)) = &parsed.inputs[0].tap_key_origins[&pubkey];
+        let mut xpriv: bitcoin::bip32::ExtendedPrivKey = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi".parse().unwrap();
+        xpriv.network = bitcoin::Network::Regtest;
+        let keypair = xpriv.derive_priv(&secp, path).unwrap().private_key.keypair(&secp);
+        let message = hex::decode(info["message"].as_str().unwrap()).unwrap();
+        let sig = secp.sign_schnorr(&bitcoin::secp256k1::Message::from_slice(&message).unwrap(), &keypair);
+
+        let mut request = serde_json::json!({
+            "psbt": psbt,
+            "input_index": 0,
+            "pubkey": pubkey.to_string(),
+            "signature": "00".repeat(64),
+        });
+        let result = call(|r| vault_psbt_apply_signature(r), request.clone());
+        assert_eq!(result["code"], 2004);
+
+        request["signature"] = serde_json::json!(hex::encode(sig.as_ref()));
+        let result = call(|r| vault_psbt_apply_signature(r), request);
+        assert!(result.get("error").is_none(), "Got error: {}", result);
+        let mut signed = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        assert_eq!(signed.inputs[0].tap_script_sigs.len(), 1);
+        assert!(vault::psbt::finalize(&mut signed).is_ok());
+    }
+
+    #[test]
+    fn test_vault_verify_signature() {
+        let verify = |msg: &str, sig: &str, pubkey: &str| {
+            let (msg, sig, pubkey) = (CString::new(msg).unwrap(), CString::new(sig).unwrap(), CString::new(pubkey).unwrap());
+            let result_ptr = vault_verify_signature(msg.as_ptr(), sig.as_ptr(), pubkey.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        // BIP340 test vector 1, and vector 6 (negated message)
+        let pubkey = "dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659";
+        let msg = "243f6a8885a308d313198a2e03707344a4093822299f31d0082efa98ec4e6c89";
+        let sig = "6896bd60eeae296db48a229ff71dfe071bde413e6d43f917dc8dcf8c78de33418906d11ac976abccb20b091292bff4ea897efcb639ea871cfa95f6de339e4b0a";
+        let bad_sig = "fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a14602975563cc27944640ac607cd107ae10923d9ef7a73c643e166be5ebeafa34b1ac553e2";
+        assert_eq!(verify(msg, sig, pubkey), serde_json::json!({"valid": true}));
+        assert_eq!(verify(msg, bad_sig, pubkey), serde_json::json!({"valid": false}));
+
+        for (msg, sig, pubkey, argument) in [
+            (&msg[2..], sig, pubkey, "message"),
+            (msg, &sig[..126], pubkey, "signature"),
+            (msg, "zz", pubkey, "signature"),
+            (msg, sig, &pubkey[..62], "pubkey"),
+            // BIP340 test vector 5: not on the curve
+            (msg, sig, "eefdea4cdb677750a420fee807eacf21eb9898ae79b9768766e4faa04a2d4a34", "pubkey"),
+        ] {
+            let result = verify(msg, sig, pubkey);
+            assert_eq!(result["code"], 4002);
+            assert!(
+                result["message"].as_str().unwrap().contains(&format!("Invalid {}", argument)),
+                "Got: {}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_vault_sign_and_verify_message() {
+        let config = serde_json::json!({
+            "network": "regtest",
+            "template": {"type": "custom", "delay_blocks": 144, "recovery_type": "emergency_key", "key_path_enabled": true},
+            "owner_xpub": OWNER_TPUB,
+            "recovery_xpub": RECOVERY_TPUB,
+        });
+        let sign = |request: serde_json::Value| {
+            let (config, request) = (CString::new(config.to_string()).unwrap(), CString::new(request.to_string()).unwrap());
+            let result_ptr = vault_sign_message(config.as_ptr(), request.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let verify = |address: &str, message: &str, proof: &str| {
+            let (address, message, proof) = (CString::new(address).unwrap(), CString::new(message).unwrap(), CString::new(proof).unwrap());
+            let result_ptr = vault_verify_message(address.as_ptr(), message.as_ptr(), proof.as_ptr(), 3);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        // The owner key, BIP32 test vector 1, holds the internal key
+        let owner_xprv = "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m";
+        let signed = sign(serde_json::json!({"vault_index": 2, "xpriv": owner_xprv, "message": "proof of reserves"}));
+        assert!(signed.get("error").is_none(), "Got error: {}", signed);
+        let address = signed["address"].as_str().unwrap();
+        let proof = signed["proof"].as_str().unwrap();
+        assert_eq!(verify(address, "proof of reserves", proof), serde_json::json!({"valid": true}));
+        assert_eq!(verify(address, "proof of nothing", proof), serde_json::json!({"valid": false}));
+        assert_eq!(verify(address, "proof of reserves", "%%%")["code"], 4002);
+        assert_eq!(verify("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", "proof of reserves", proof)["code"], 4002);
+
+        let stranger = "tprv8ZgxMBicQKsPd7Uf69XL1XwhmjHopUGep8GuEiJDZmbQz6o58LninorQAfcKZWARbtRtfnLcJ5MQ2AtHcQJCCRUcMRvmDUjyEmNUWwx8UbK";
+        let unsigned = sign(serde_json::json!({"vault_index": 2, "xpriv": stranger, "message": "proof of reserves"}));
+        assert_eq!(unsigned["code"], 2004);
+        assert_eq!(sign(serde_json::json!({"vault_index": 2}))["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_psbt_encoding_roundtrip() {
+        let request_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+        let result_ptr = vault_build_unvault_psbt(request_cstr.as_ptr(), 3);
+        let result: serde_json::Value =
+            payload(unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap());
+        free_rust_string(result_ptr);
+        let mut psbt = vault::psbt::from_base64(result["psbt_base64"].as_str().unwrap()).unwrap();
+        psbt.inputs[0]
+            .unknown
+            .insert(bitcoin::psbt::raw::Key { type_value: 0xf0, key: vec![0x00, 0xff] }, (0..=255).collect());
+        let base64 = vault::psbt::to_base64(&psbt);
+
+        let encode = |export: extern "C" fn(*const u8, usize) -> *mut c_char, bytes: &[u8]| {
+            let result_ptr = export(bytes.as_ptr(), bytes.len());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let decode = |export: extern "C" fn(*const c_char) -> ffi::ByteBuffer, text: &str| {
+            let text = std::ffi::CString::new(text).unwrap();
+            ffi::from_byte_buffer(export(text.as_ptr()))
+        };
+
+        // base64 -> bytes -> base64, with NUL and high bytes in the PSBT
+        let bytes = decode(vault_psbt_from_base64, &base64);
+        assert_eq!(bytes, psbt.serialize());
+        assert!(bytes.contains(&0) && std::str::from_utf8(&bytes).is_err());
+        assert_eq!(encode(vault_psbt_to_base64, &bytes)["psbt_base64"], base64);
+
+        let hex = encode(vault_psbt_to_hex, &bytes)["psbt_hex"].as_str().unwrap().to_string();
+        assert_eq!(hex, hex::encode(&bytes));
+        assert_eq!(decode(vault_psbt_from_hex, &hex), bytes);
+        assert_eq!(encode(vault_psbt_to_base64, hex.as_bytes())["psbt_base64"], base64);
+
+        // Odd-length hex
+        assert!(decode(vault_psbt_from_hex, &hex[..hex.len() - 1]).is_empty());
+        assert_eq!(vault_last_error_code(), 2001);
+        assert_eq!(encode(vault_psbt_to_hex, &bytes[..bytes.len() - 1])["code"], 2001);
+        assert_eq!(encode(vault_psbt_to_hex, &[])["code"], 2001);
+    }
+
+    #[test]
+    fn test_vault_derive_addresses() {
+        let config = serde_json::json!({
+            "network": "mainnet",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+
+        unsafe {
+            let started = std::time::Instant::now();
+            let result_ptr = vault_derive_addresses(config_cstr.as_ptr(), 0, 1000);
+            let elapsed = started.elapsed();
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            free_rust_string(result_ptr);
+
+            let addresses = result.as_array().unwrap();
+            assert_eq!(addresses.len(), 1000);
+            assert_eq!(addresses[0]["index"], 0);
+            assert_eq!(
+                addresses[0]["address"],
+                "bc1ppwlpr72ejugtz4v6x3aujy0qkyzmkuwhu2npg0qfkz4r33l89acsuwkqqh"
+            );
+            assert_eq!(addresses[999]["index"], 999);
+            assert!(addresses[999]["script_pubkey"].as_str().unwrap().starts_with("5120"));
+            // Generous bound so unoptimized test builds on slow CI still pass
+            assert!(elapsed < std::time::Duration::from_secs(5), "took {:?}", elapsed);
+
+            let result_ptr = vault_derive_addresses(config_cstr.as_ptr(), 0, 10_001);
+            let result: serde_json::Value =
+                payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+            assert_eq!(result["code"], 4002);
+            free_rust_string(result_ptr);
+        }
+    }
+
+    #[test]
+    fn test_vault_find_address_index() {
+        let config = serde_json::json!({
+            "network": "mainnet",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+        let find = |address: &str, gap_limit: u32| -> serde_json::Value {
+            let address = std::ffi::CString::new(address).unwrap();
+            let result_ptr = vault_find_address_index(config_cstr.as_ptr(), address.as_ptr(), gap_limit);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let first = "bc1ppwlpr72ejugtz4v6x3aujy0qkyzmkuwhu2npg0qfkz4r33l89acsuwkqqh";
+
+        assert_eq!(find(first, 20), serde_json::json!({"found": true, "index": 0}));
+        assert_eq!(find(first, 0), serde_json::json!({"found": false, "index": null}));
+        assert_eq!(
+            find("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", 20),
+            serde_json::json!({"found": false, "index": null})
+        );
+        assert_eq!(find("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", 20)["code"], 1003);
+        assert_eq!(find(first, 10_001)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_scan_indices() {
+        let config = serde_json::json!({
+            "network": "mainnet",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+        let scan = |spks: serde_json::Value, gap_limit: u32| -> serde_json::Value {
+            let spks = std::ffi::CString::new(spks.to_string()).unwrap();
+            let result_ptr = vault_scan_indices(config_cstr.as_ptr(), spks.as_ptr(), gap_limit);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let vault_config: vault::VaultConfig = serde_json::from_value(config.clone()).unwrap();
+        let vault = vault::Vault::from_config(&vault_config).unwrap();
+        let spk = |index: u32| hex::encode(vault.tree_at(index).unwrap().script_pubkey().as_bytes());
+
+        assert_eq!(
+            scan(serde_json::json!([spk(0), spk(7), "0014751e76e8199196d454941c45d1b3a323f1433bd6"]), 20),
+            serde_json::json!({"used_indices": [0, 7], "highest_used": 7, "next_index": 8})
+        );
+        assert_eq!(
+            scan(serde_json::json!([]), 20),
+            serde_json::json!({"used_indices": [], "highest_used": null, "next_index": 0})
+        );
+        assert_eq!(scan(serde_json::json!(["zz"]), 20)["code"], 4002);
+        assert_eq!(scan(serde_json::json!({"spk": spk(0)}), 20)["code"], 4002);
+        assert_eq!(scan(serde_json::json!([spk(0)]), 0)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_unvault_status() {
+        let status = |state: serde_json::Value, current_height: u32| -> serde_json::Value {
+            let state = std::ffi::CString::new(state.to_string()).unwrap();
+            let result_ptr = vault_unvault_status(state.as_ptr(), current_height);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let txid = "ab".repeat(32);
+        let confirmed = serde_json::json!({
+            "trigger_txid": txid,
+            "broadcast_height": 799_999,
+            "confirmation_height": 800_000,
+            "delay_blocks": 144,
+        });
+
+        assert_eq!(status(confirmed.clone(), 800_142), serde_json::json!({"status": "waiting", "blocks_left": 1}));
+        assert_eq!(status(confirmed.clone(), 800_143), serde_json::json!({"status": "spendable"}));
+        assert_eq!(
+            status(
+                serde_json::json!({"trigger_txid": txid, "broadcast_height": 799_999, "delay_blocks": 144}),
+                900_000
+            ),
+            serde_json::json!({"status": "pending"})
+        );
+        assert_eq!(status(serde_json::json!({"trigger_txid": txid}), 0)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_classify_tx() {
+        let config = serde_json::json!({
+            "network": "mainnet",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let vault: vault::VaultConfig = serde_json::from_value(config.clone()).unwrap();
+        let tree = vault::Vault::from_config(&vault).unwrap().tree_at(2).unwrap();
+        let control_block = taproot::control_block(&tree, taproot::LeafPurpose::Timelock).unwrap();
+        let mut witness = bitcoin::Witness::new();
+        witness.push([1u8; 64]);
+        witness.push(tree.leaf(taproot::LeafPurpose::Timelock).unwrap().script.as_bytes());
+        witness.push(control_block.serialize());
+        let outpoint = format!("{}:0", "ab".repeat(32));
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                previous_output: outpoint.parse().unwrap(),
+                script_sig: bitcoin::ScriptBuf::new(),
+                sequence: bitcoin::Sequence::from_height(1008),
+                witness,
+            }],
+            output: vec![bitcoin::TxOut { value: 90_000, script_pubkey: tree.script_pubkey() }],
+        };
+
+        let classify = |tx_hex: &str, outpoints: serde_json::Value| -> serde_json::Value {
+            let tx_hex = std::ffi::CString::new(tx_hex).unwrap();
+            let config = std::ffi::CString::new(config.to_string()).unwrap();
+            let outpoints = std::ffi::CString::new(outpoints.to_string()).unwrap();
+            let result_ptr = vault_classify_tx(tx_hex.as_ptr(), config.as_ptr(), outpoints.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let tx_hex = hex::encode(bitcoin::consensus::serialize(&tx));
+
+        let events = classify(&tx_hex, serde_json::json!([outpoint]));
+        assert_eq!(events[0]["input_index"], 0);
+        assert_eq!(events[0]["outpoint"], outpoint);
+        assert_eq!(events[0]["vault_index"], 2);
+        assert_eq!(events[0]["path"], "timelock");
+        assert_eq!(events[0]["destinations"][0]["address"], tree.address(vault::Network::Mainnet).to_string());
+        assert_eq!(events[0]["destinations"][0]["amount_sats"], 90_000);
+
+        assert_eq!(classify(&tx_hex, serde_json::json!([])), serde_json::json!([]));
+        assert_eq!(classify(&tx_hex, serde_json::json!(["ab:0"]))["code"], 4002);
+        assert_eq!(classify("00", serde_json::json!([outpoint]))["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_electrum_hashes() {
+        let config = serde_json::json!({
+            "network": "mainnet",
+            "template": {"type": "savings"},
+            "owner_xpub": OWNER_XPUB,
+            "recovery_xpub": RECOVERY_XPUB
+        });
+        let hashes = |start: u32, count: u32| -> serde_json::Value {
+            let config = std::ffi::CString::new(config.to_string()).unwrap();
+            let result_ptr = vault_electrum_hashes(config.as_ptr(), start, count);
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+
+        let vault: vault::VaultConfig = serde_json::from_value(config.clone()).unwrap();
+        let tree = vault::Vault::from_config(&vault).unwrap().tree_at(5).unwrap();
+        let result = hashes(4, 3);
+        assert_eq!(result.as_array().unwrap().len(), 3);
+        assert_eq!(result[1]["index"], 5);
+        assert_eq!(result[1]["script_hash"], vault::watch::electrum_script_hash(&tree.script_pubkey()));
+
+        assert_eq!(hashes(0, 10_001)["code"], 4002);
+        assert_eq!(hashes(u32::MAX, 2)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_musig_rounds() {
+        let call = |export: extern "C" fn(*const c_char) -> *mut c_char, request: serde_json::Value| {
+            let request = std::ffi::CString::new(request.to_string()).unwrap();
+            let result_ptr = export(request.as_ptr());
+            let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+            free_rust_string(result_ptr);
+            payload(&result)
+        };
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let alice = "11".repeat(32);
+        let bob = "22".repeat(32);
+        let public = |secret: &str| {
+            bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &secret.parse().unwrap()).to_string()
+        };
+        let session = serde_json::json!({
+            "keys": [public(&alice), public(&bob)],
+            "merkle_root": "ab".repeat(32),
+            "message": "cd".repeat(32),
+        });
+        let with = |fields: serde_json::Value| {
+            let mut request = session.clone();
+            request.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+            request
+        };
+
+        let alice_nonce = call(vault_musig_nonce, with(serde_json::json!({"secret_key": alice})));
+        let bob_nonce = call(vault_musig_nonce, with(serde_json::json!({"secret_key": bob})));
+        // The secret nonce never leaves the library
+        assert_eq!(alice_nonce.as_object().unwrap().len(), 1, "{}", alice_nonce);
+        let public_nonces = [alice_nonce["public_nonce"].clone(), bob_nonce["public_nonce"].clone()];
+        let sign = |secret: &str| {
+            call(
+                vault_musig_partial_sign,
+                with(serde_json::json!({"secret_key": secret, "public_nonces": public_nonces})),
+            )
+        };
+        let partial_signatures = [sign(&alice)["partial_signature"].clone(), sign(&bob)["partial_signature"].clone()];
+        let result = call(
+            vault_musig_aggregate,
+            with(serde_json::json!({"public_nonces": public_nonces, "partial_signatures": partial_signatures})),
+        );
+
+        let signature =
+            result["signature"].as_str().unwrap().parse::<bitcoin::secp256k1::schnorr::Signature>().unwrap();
+        let output_key = keys::musig::aggregate_keys(&[public(&alice).parse().unwrap(), public(&bob).parse().unwrap()])
+        .unwrap()
+        .with_taproot_tweak(Some(bitcoin::taproot::TapNodeHash::assume_hidden([0xab; 32])))
+        .unwrap()
+        .x_only_public_key();
+        let message = bitcoin::secp256k1::Message::from_slice(&[0xcd; 32]).unwrap();
+        secp.verify_schnorr(&signature, &message, &output_key).unwrap();
+
+        // Each nonce signs once
+        assert_eq!(sign(&alice)["code"], 4002);
+        let carol = "33".repeat(32);
+        assert_eq!(sign(&carol)["code"], 4002);
+        let result = call(
+            vault_musig_aggregate,
+            with(serde_json::json!({"public_nonces": public_nonces, "partial_signatures": [partial_signatures[0]]})),
+        );
+        assert_eq!(result["code"], 4002);
+        assert_eq!(call(vault_musig_nonce, serde_json::json!({"keys": [], "message": "00"}))["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ffi::VaultHandle>();
+    }
+
+    #[test]
+    fn test_vault_handle_derives_1000_addresses() {
+        let handle = vault_handle_create(handle_config().as_ptr());
+        assert!(!handle.is_null());
+
+        let mut addresses = std::collections::HashSet::new();
+        for index in 0..1000 {
+            let result = handle_address(handle, index);
+            assert!(result.get("error").is_none(), "Got error: {}", result);
+            assert_eq!(result["vault_index"], index);
+            assert!(addresses.insert(result["address"].as_str().unwrap().to_string()));
+        }
+
+        // Handle results match the stateless export, cached or not
+        let config = vault_config().to_string();
+        let config_cstr = std::ffi::CString::new(config).unwrap();
+        for index in [0, 1, 500, 999] {
+            unsafe {
+                let result_ptr = vault_get_address(config_cstr.as_ptr(), index, 3);
+                let expected: serde_json::Value =
+                    payload(CStr::from_ptr(result_ptr).to_str().unwrap());
+                free_rust_string(result_ptr);
+                assert_eq!(handle_address(handle, index), expected);
+            }
+        }
+
+        assert_eq!(vault_handle_free(handle), 0);
+    }
+
+    #[test]
+    fn test_vault_handle_build_unvault() {
+        let handle = vault_handle_create(handle_config().as_ptr());
+        let mut request = unvault_request(100_000);
+        for key in ["template", "owner_xpub", "recovery_xpub"] {
+            request.as_object_mut().unwrap().remove(key);
+        }
+        let request_cstr = std::ffi::CString::new(request.to_string()).unwrap();
+        let stateless_cstr = std::ffi::CString::new(unvault_request(100_000).to_string()).unwrap();
+
+        unsafe {
+            let result_ptr = vault_handle_build_unvault(handle, request_cstr.as_ptr());
+            let result_str = CStr::from_ptr(result_ptr).to_str().unwrap();
+            let result: serde_json::Value = payload(result_str);
+            assert!(result.get("error").is_none(), "Got error: {}", result_str);
+
+            let stateless_ptr = vault_build_unvault_psbt(stateless_cstr.as_ptr(), 3);
+            let stateless: serde_json::Value =
+                payload(CStr::from_ptr(stateless_ptr).to_str().unwrap());
+            assert_eq!(result, stateless);
+
+            free_rust_string(result_ptr);
+            free_rust_string(stateless_ptr);
+        }
+
+        assert_eq!(vault_handle_free(handle), 0);
+    }
+
+    #[test]
+    fn test_vault_handle_shared_between_threads() {
+        let handle = vault_handle_create(handle_config().as_ptr());
+        let expected = handle_address(handle, 42);
+
+        // Raw pointers aren't Send; pass the address as an integer like a C caller would
+        let addr = handle as usize;
+        let threads: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(move || handle_address(addr as *const ffi::VaultHandle, 42)))
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), expected);
+        }
+
+        assert_eq!(vault_handle_free(handle), 0);
+    }
+
+    #[test]
+    fn test_vault_handle_invalid() {
+        let bad_config = std::ffi::CString::new("{\"network\":\"mainnet\"}").unwrap();
+        assert!(vault_handle_create(bad_config.as_ptr()).is_null());
+        assert_eq!(vault_last_error_code(), 4002);
+
+        // Testnet keys are rejected for a mainnet vault
+        let mut config = vault_config();
+        config["network"] = serde_json::json!("mainnet");
+        let config_cstr = std::ffi::CString::new(config.to_string()).unwrap();
+        assert!(vault_handle_create(config_cstr.as_ptr()).is_null());
+
+        assert_eq!(vault_handle_free(std::ptr::null_mut()), -1);
+        assert_eq!(vault_last_error_code(), 4002);
+        assert_eq!(handle_address(std::ptr::null(), 0)["code"], 4002);
+    }
+
+    #[test]
+    fn test_vault_ur_psbt_roundtrip() {
+        let json = |ptr: *mut c_char| {
+            let result = unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string();
+            free_rust_string(ptr);
+            payload(&result)
+        };
+
+        let request_cstr = CString::new(unvault_request(100_000).to_string()).unwrap();
+        let psbt_base64 = json(vault_build_unvault_psbt(request_cstr.as_ptr(), 3))["psbt_base64"].clone();
+        let psbt_cstr = CString::new(psbt_base64.as_str().unwrap()).unwrap();
+        let encoded = json(vault_ur_encode_psbt(psbt_cstr.as_ptr(), 50));
+        let parts: Vec<CString> = encoded["parts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|part| CString::new(part.as_str().unwrap()).unwrap())
+            .collect();
+        assert!(parts.len() > 2);
+
+        let decoder = vault_ur_decoder_new();
+        assert_eq!(json(vault_ur_decoder_progress(decoder))["complete"], false);
+        assert_eq!(json(vault_ur_decoder_result(decoder))["code"], 4002);
+
+        for part in parts.iter().rev() {
+            assert_eq!(vault_ur_decoder_receive_part(decoder, part.as_ptr()), 0);
+        }
+        let progress = json(vault_ur_decoder_progress(decoder));
+        assert_eq!(progress["complete"], true);
+        assert_eq!(progress["fragments_received"], parts.len());
+        assert_eq!(
+            json(vault_ur_decoder_result(decoder)),
+            serde_json::json!({"type": "crypto-psbt", "psbt_base64": psbt_base64})
+        );
+
+        let garbage = CString::new("ur:crypto-psbt/1-2/zzzz").unwrap();
+        assert_eq!(vault_ur_decoder_receive_part(std::ptr::null(), parts[0].as_ptr()), -1);
+        assert_eq!(vault_ur_decoder_free(decoder), 0);
+
+        let decoder = vault_ur_decoder_new();
+        assert_eq!(vault_ur_decoder_receive_part(decoder, garbage.as_ptr()), -1);
+        assert_eq!(vault_last_error_code(), 4001);
+        assert_eq!(vault_ur_decoder_free(decoder), 0);
+    }
+}
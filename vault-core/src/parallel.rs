@@ -0,0 +1,66 @@
+//! Index-range work spread over rayon's thread pool with the `parallel`
+//! feature
+//!
+//! Results come back in index order with or without the feature, so
+//! callers behave the same either way; only wall-clock time differs.
+//! Closures share their captures (keys, secp contexts) immutably across
+//! threads, hence the `Sync` bounds.
+
+use std::ops::RangeInclusive;
+
+/// `f` applied to every index in `indices`, in index order
+pub(crate) fn map_indices<T, F>(indices: RangeInclusive<u32>, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(u32) -> T + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        indices.into_par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        indices.map(f).collect()
+    }
+}
+
+/// The result of `f` for the lowest index in `indices` where it is `Some`
+///
+/// Serially the search stops at that index; in parallel, higher indices
+/// may already have been tried, but a lower match always wins.
+pub(crate) fn find_map_first<T, F>(indices: RangeInclusive<u32>, f: F) -> Option<T>
+where
+    T: Send,
+    F: Fn(u32) -> Option<T> + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        indices.into_par_iter().find_map_first(f)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        indices.into_iter().find_map(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_indices_keeps_order() {
+        let serial: Vec<u64> = (5..=5_000u32).map(|i| u64::from(i) * 7).collect();
+        assert_eq!(map_indices(5..=5_000, |i| u64::from(i) * 7), serial);
+        assert_eq!(map_indices(3..=3, |i| i), [3]);
+    }
+
+    #[test]
+    fn test_find_map_first_lowest_match() {
+        // Every multiple of 97 matches; the first one must be reported
+        let found = find_map_first(1..=10_000, |i| (i % 97 == 0).then_some(i));
+        assert_eq!(found, Some(97));
+        assert_eq!(find_map_first(1..=96, |i| (i % 97 == 0).then_some(i)), None);
+    }
+}
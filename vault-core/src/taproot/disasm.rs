@@ -0,0 +1,174 @@
+//! Readable rendering of leaf scripts, for audit display
+//!
+//! Unlike `Script::to_asm_string()`, every token stands alone (pushes
+//! carry their length) and bytes that aren't valid tapscript opcodes are
+//! flagged instead of rendered as `OP_SUCCESSx` lookalikes.
+
+use std::fmt;
+
+use bitcoin::blockdata::opcodes::all::OP_INVALIDOPCODE;
+use bitcoin::blockdata::opcodes::{All, Class, ClassifyContext};
+use bitcoin::blockdata::script::{Instruction, Script, ScriptBuf};
+use bitcoin::taproot::TapLeafHash;
+use serde::{Serialize, Serializer};
+
+use super::LeafPurpose;
+
+/// One element of a disassembled script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptToken {
+    /// Opcode, shown by name (`OP_CHECKSIG`)
+    Op(All),
+    /// Data push, shown as hex with its length (`f003[2]`); an empty
+    /// push is shown as `OP_0`
+    Push(Vec<u8>),
+    /// A byte that is no valid tapscript opcode, or a push running past
+    /// the end of the script (`INVALID(0xbb)`)
+    Invalid(u8),
+}
+
+impl fmt::Display for ScriptToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptToken::Op(op) => write!(f, "{}", op),
+            ScriptToken::Push(data) if data.is_empty() => write!(f, "OP_0"),
+            ScriptToken::Push(data) => write!(f, "{}[{}]", hex::encode(data), data.len()),
+            ScriptToken::Invalid(byte) => write!(f, "INVALID(0x{:02x})", byte),
+        }
+    }
+}
+
+/// Serialized as its display string
+impl Serialize for ScriptToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A vault tree leaf, disassembled
+#[derive(Debug, Clone, Serialize)]
+pub struct LeafListing {
+    pub purpose: LeafPurpose,
+    pub leaf_hash: TapLeafHash,
+    /// Depth of the leaf in the tree (length of its merkle branch)
+    pub depth: usize,
+    /// Serialized size of the leaf's control block, 33 + 32 * `depth` bytes
+    pub control_block_size: usize,
+    pub script: ScriptBuf,
+    pub tokens: Vec<ScriptToken>,
+    /// `tokens` joined by spaces
+    pub asm: String,
+}
+
+/// Disassemble a tapscript into tokens
+///
+/// Never fails: a byte that can't start an instruction becomes an
+/// `Invalid` token and disassembly resumes after it.
+pub fn disassemble(script: &Script) -> Vec<ScriptToken> {
+    let bytes = script.as_bytes();
+    let mut tokens = Vec::new();
+    let mut instructions = script.instructions();
+    loop {
+        let pos = bytes.len() - instructions.as_script().len();
+        match instructions.next() {
+            None => break,
+            Some(Ok(Instruction::PushBytes(data))) => tokens.push(ScriptToken::Push(data.as_bytes().to_vec())),
+            Some(Ok(Instruction::Op(op))) => tokens.push(match op.classify(ClassifyContext::TapScript) {
+                Class::IllegalOp | Class::SuccessOp => ScriptToken::Invalid(op.to_u8()),
+                _ if op == OP_INVALIDOPCODE => ScriptToken::Invalid(op.to_u8()),
+                _ => ScriptToken::Op(op),
+            }),
+            Some(Err(_)) => {
+                // The iterator stops at an error; go on after the bad byte
+                tokens.push(ScriptToken::Invalid(bytes[pos]));
+                instructions = Script::from_bytes(&bytes[pos + 1..]).instructions();
+            }
+        }
+    }
+    tokens
+}
+
+/// `disassemble()` joined by spaces
+pub fn disassemble_to_string(script: &Script) -> String {
+    disassemble(script).iter().map(ScriptToken::to_string).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taproot::{multisig_leaf, timelock_leaf, TreeVersion};
+    use crate::vault::DelayUnit;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey, XOnlyPublicKey};
+
+    // Generator point x-coordinate, a convenient fixed x-only key
+    const KEY_HEX: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn test_key() -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&hex::decode(KEY_HEX).unwrap()).unwrap()
+    }
+
+    fn asm(script_hex: &str) -> String {
+        disassemble_to_string(Script::from_bytes(&hex::decode(script_hex).unwrap()))
+    }
+
+    #[test]
+    fn test_disassemble_timelock_leaf() {
+        let leaf = timelock_leaf(&test_key(), 1008, DelayUnit::Blocks, TreeVersion::V1).unwrap();
+        assert_eq!(
+            disassemble_to_string(&leaf.script),
+            format!("f003[2] OP_CSV OP_DROP {}[32] OP_CHECKSIG", KEY_HEX)
+        );
+
+        let leaf = timelock_leaf(&test_key(), 1008, DelayUnit::Blocks, TreeVersion::V2).unwrap();
+        assert_eq!(
+            disassemble_to_string(&leaf.script),
+            format!("f003[2] OP_CSV OP_VERIFY {}[32] OP_CHECKSIG", KEY_HEX)
+        );
+        assert_eq!(
+            disassemble(&leaf.script)[..3],
+            [
+                ScriptToken::Push(vec![0xf0, 0x03]),
+                ScriptToken::Op(bitcoin::blockdata::opcodes::all::OP_CSV),
+                ScriptToken::Op(bitcoin::blockdata::opcodes::all::OP_VERIFY),
+            ]
+        );
+
+        let leaf = timelock_leaf(&test_key(), 1, DelayUnit::Blocks, TreeVersion::V2).unwrap();
+        assert!(disassemble_to_string(&leaf.script).starts_with("OP_PUSHNUM_1 OP_CSV OP_VERIFY"));
+    }
+
+    #[test]
+    fn test_disassemble_multisig_leaf() {
+        let secp = Secp256k1::new();
+        let mut keys: Vec<_> = [[1u8; 32], [2; 32], [3; 32]]
+            .iter()
+            .map(|bytes| SecretKey::from_slice(bytes).unwrap().x_only_public_key(&secp).0)
+            .collect();
+        let script = multisig_leaf(&keys, 2).unwrap();
+        keys.sort_by_key(|key| key.serialize());
+        let [a, b, c] = [0, 1, 2].map(|i| hex::encode(keys[i].serialize()));
+
+        assert_eq!(
+            disassemble_to_string(&script),
+            format!("{}[32] OP_CHECKSIG {}[32] OP_CHECKSIGADD {}[32] OP_CHECKSIGADD OP_PUSHNUM_2 OP_NUMEQUAL", a, b, c)
+        );
+    }
+
+    #[test]
+    fn test_disassemble_invalid_bytes() {
+        // OP_SUCCESS187, OP_RESERVED and OP_INVALIDOPCODE between valid opcodes
+        assert_eq!(asm("acbb50ff69"), "OP_CHECKSIG INVALID(0xbb) INVALID(0x50) INVALID(0xff) OP_VERIFY");
+        // A push running past the end: the push opcode is flagged, the rest read on
+        assert_eq!(asm("0301ac"), "INVALID(0x03) ac[1]");
+        assert_eq!(asm("4c"), "INVALID(0x4c)");
+        assert_eq!(asm("00"), "OP_0");
+        assert_eq!(asm(""), "");
+        assert!(disassemble(Script::from_bytes(&[0x4d, 0xff])).iter().all(|token| matches!(token, ScriptToken::Invalid(_))));
+    }
+
+    #[test]
+    fn test_script_token_serializes_as_display() {
+        let tokens = [ScriptToken::Op(bitcoin::blockdata::opcodes::all::OP_CHECKSIG), ScriptToken::Push(vec![0xab]), ScriptToken::Invalid(0xfe)];
+        assert_eq!(serde_json::to_string(&tokens).unwrap(), r#"["OP_CHECKSIG","ab[1]","INVALID(0xfe)"]"#);
+    }
+}
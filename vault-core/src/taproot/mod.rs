@@ -1,14 +1,35 @@
+use std::collections::BTreeMap;
+
 use bitcoin::address::Address;
-use bitcoin::blockdata::opcodes::all::{OP_CHECKSIGVERIFY, OP_RETURN};
-use bitcoin::blockdata::script::{Builder, PushBytesBuf, ScriptBuf};
-use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::bip32::{ExtendedPubKey, KeySource};
+use bitcoin::blockdata::opcodes::all::{OP_CHECKSIGVERIFY, OP_CSV};
+use bitcoin::blockdata::script::{Builder, Script, ScriptBuf};
+use bitcoin::secp256k1::{Secp256k1, Verification, XOnlyPublicKey};
 use bitcoin::taproot::TaprootBuilder;
 use bitcoin::Sequence;
 use serde::{Deserialize, Serialize};
 
+use crate::cancel::CancelToken;
 use crate::error::CoreError;
 use crate::keys;
-use crate::vault::{Network, VaultMetadata, VaultTemplate, RecoveryType};
+use crate::vault::{MetadataMode, Network, VaultConfig, VaultMetadata, VaultTemplate, RecoveryType};
+
+mod disasm;
+mod script;
+mod tree;
+
+pub use script::{
+    assemble_multisig_witness, cltv_leaf, cltv_leaf_at, commitment_leaf, emergency_leaf, extract_commitment, extract_metadata, hashlock_leaf,
+    inheritance_leaf, leaf_cltv_lock, leaf_csv_delay, leaf_hashlock, leaf_scripts, leaf_signers, metadata_leaf,
+    multisig_leaf, timelock_leaf, LeafKeys, LeafPurpose, LeafSigners, TimelockLeaf, VaultLeaf,
+    HASHLOCK_PREIMAGE_LEN, MAX_CSV_DELAY_BLOCKS, MAX_MULTISIG_KEYS,
+};
+pub use disasm::{disassemble, disassemble_to_string, LeafListing, ScriptToken};
+pub use tree::{
+    build_tree, control_block, huffman_builder, verify_control_block, LeafId, LeafSpec, TreeVersion, VaultTree,
+    DEFAULT_LEAF_WEIGHT, TIMELOCK_LEAF_WEIGHT,
+};
+pub(crate) use tree::leaf_weight;
 
 /// Result of generating a vault Taproot address
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +42,10 @@ pub struct VaultAddressResult {
     pub spending_script_hex: String,
     /// Metadata script (hex)
     pub metadata_script_hex: String,
+    /// Whether the metadata script holds `metadata` or its commitment
+    pub metadata_mode: MetadataMode,
+    /// `metadata.commitment()` (hex)
+    pub metadata_commitment: String,
     /// Vault metadata that was encoded
     pub metadata: VaultMetadata,
 }
@@ -30,14 +55,24 @@ pub struct VaultAddressResult {
 /// Script tree structure:
 ///   Internal Key = emergency key (or NUMS if no emergency device)
 ///   Leaf 0 (depth 1): Spending script = <primary_key> OP_CHECKSIGVERIFY <delay> OP_CSV
-///   Leaf 1 (depth 1): Metadata script = OP_RETURN <metadata_bytes>
+///   Leaf 1 (depth 1): Metadata script = OP_RETURN <metadata_bytes>, or
+///                     OP_RETURN <commitment> in `MetadataMode::Commitment`
 pub fn generate_vault_address(
     primary_xpub: &str,
     emergency_xpub: Option<&str>,
     template: &VaultTemplate,
     vault_index: u32,
     network: Network,
+    metadata_mode: MetadataMode,
 ) -> Result<VaultAddressResult, CoreError> {
+    // This two-leaf layout can't express heir or whitelist leaves
+    if template.heir_set().is_some() || template.whitelist_delay().is_some() {
+        return Err(CoreError::PolicyViolation(format!(
+            "Template {} is only supported by vault_tree()",
+            template.template_id()
+        )));
+    }
+
     let secp = Secp256k1::new();
     let btc_network: bitcoin::Network = network.into();
 
@@ -52,7 +87,7 @@ pub fn generate_vault_address(
 
     // 3. Build spending script: <primary_key> OP_CHECKSIGVERIFY <delay> OP_CSV
     let delay_blocks = template.delay_blocks();
-    let spending_script = build_spending_script(&primary_key, delay_blocks);
+    let spending_script = build_spending_script(&primary_key, template.sequence()?);
 
     // 4. Build metadata
     let recovery_type = match template {
@@ -70,25 +105,30 @@ pub fn generate_vault_address(
         version: 1,
         template_id: template.template_id().to_string(),
         delay_blocks,
+        delay_unit: template.delay_unit(),
         destination_indices: vec![],
         recovery_type,
         created_at_block: 0, // Filled by caller with actual block height
         vault_index,
+        key_path_enabled: false,
+        heirs: None,
+        whitelist_delay: None,
+        tlv_records: Default::default(),
     };
 
-    // 5. Build metadata script: OP_RETURN <metadata_bytes>
-    let metadata_script = build_metadata_script(&metadata);
+    // 5. Build metadata script: OP_RETURN <metadata_bytes> or OP_RETURN <commitment>
+    let metadata_script = mode_leaf(&metadata, metadata_mode);
 
     // 6. Build Taproot script tree with two leaves at depth 1
     let builder = TaprootBuilder::new()
         .add_leaf(1, spending_script.clone())
-        .map_err(|e| CoreError::DerivationError(format!("Failed to add spending leaf: {:?}", e)))?
+        .map_err(|e| CoreError::ScriptError(format!("Failed to add spending leaf: {:?}", e)))?
         .add_leaf(1, metadata_script.clone())
-        .map_err(|e| CoreError::DerivationError(format!("Failed to add metadata leaf: {:?}", e)))?;
+        .map_err(|e| CoreError::ScriptError(format!("Failed to add metadata leaf: {:?}", e)))?;
 
     let spend_info = builder
         .finalize(&secp, internal_key)
-        .map_err(|_| CoreError::DerivationError("Failed to finalize Taproot tree".to_string()))?;
+        .map_err(|_| CoreError::ScriptError("Failed to finalize Taproot tree".to_string()))?;
 
     // 7. Generate address
     let address = Address::p2tr(
@@ -103,6 +143,8 @@ pub fn generate_vault_address(
         internal_key: hex::encode(internal_key.serialize()),
         spending_script_hex: hex::encode(spending_script.as_bytes()),
         metadata_script_hex: hex::encode(metadata_script.as_bytes()),
+        metadata_mode,
+        metadata_commitment: hex::encode(metadata.commitment()),
         metadata,
     })
 }
@@ -111,88 +153,409 @@ pub fn generate_vault_address(
 ///
 /// This script enforces:
 /// 1. A valid Schnorr signature from the primary device key
-/// 2. A minimum relative timelock of `delay_blocks` blocks
-fn build_spending_script(primary_key: &XOnlyPublicKey, delay_blocks: u32) -> ScriptBuf {
+/// 2. A minimum relative timelock given by `sequence`
+fn build_spending_script(primary_key: &XOnlyPublicKey, sequence: Sequence) -> ScriptBuf {
     Builder::new()
         .push_x_only_key(primary_key)
         .push_opcode(OP_CHECKSIGVERIFY)
-        .push_sequence(Sequence::from_height(delay_blocks as u16))
-        .push_opcode(bitcoin::blockdata::opcodes::all::OP_CSV)
+        .push_sequence(sequence)
+        .push_opcode(OP_CSV)
         .into_script()
 }
 
-/// Build the metadata script: OP_RETURN <metadata_bytes>
+/// Build the script tree for a vault at `vault_index`, in the
+/// `TreeVersion::LATEST` layout
 ///
-/// This leaf is provably unspendable (OP_RETURN always fails).
-/// It commits vault configuration to the blockchain for recovery.
-fn build_metadata_script(metadata: &VaultMetadata) -> ScriptBuf {
-    let metadata_bytes = metadata.to_bytes();
-    let push_bytes = PushBytesBuf::try_from(metadata_bytes)
-        .expect("metadata bytes should be valid push data (< 4294967296 bytes)");
-    Builder::new()
-        .push_opcode(OP_RETURN)
-        .push_slice(&push_bytes)
-        .into_script()
+/// Script tree structure:
+///   Internal Key = `keys::unspendable_internal_key()` (script-path only;
+///                  `nums_internal_key(vault_index)` from `TreeVersion::V3`),
+///                  or the owner key if the template enables key-path spends
+///   Leaves = `leaf_scripts()` for the template (timelock + optional recovery)
+pub fn vault_tree(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    vault_index: u32,
+    network: Network,
+) -> Result<VaultTree, CoreError> {
+    vault_tree_versioned(template, owner_xpub, recovery_xpub, vault_index, network, TreeVersion::LATEST)
 }
 
-/// Validate a Bitcoin address string for the given network
-pub fn validate_address(address_str: &str, network: Network) -> Result<bool, CoreError> {
-    let btc_network: bitcoin::Network = network.into();
-    let address = address_str
-        .parse::<Address<bitcoin::address::NetworkUnchecked>>()
-        .map_err(|e| CoreError::InvalidAddress(format!("Failed to parse address: {}", e)))?;
+/// `vault_tree()` in the layout of `version`
+pub fn vault_tree_versioned(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    vault_index: u32,
+    network: Network,
+    version: TreeVersion,
+) -> Result<VaultTree, CoreError> {
+    let secp = Secp256k1::verification_only();
+    VaultKeys::new(&secp, template, owner_xpub, recovery_xpub, network, version)?.tree(&secp, vault_index, None)
+}
 
-    let checked = address
-        .require_network(btc_network)
-        .map_err(|e| CoreError::InvalidAddress(format!("Network mismatch: {}", e)))?;
+/// Build the script tree for a vault at `vault_index` with a metadata leaf
+///
+/// Same leaves as `vault_tree()` plus `metadata_leaf(metadata)`, or
+/// `commitment_leaf()` of its commitment in `MetadataMode::Commitment`,
+/// so the address differs from the plain tree's. The layout is
+/// `metadata.tree_version()`, and `metadata.vault_index` must match
+/// `vault_index`.
+pub fn vault_tree_with_metadata(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    vault_index: u32,
+    network: Network,
+    metadata: &VaultMetadata,
+    mode: MetadataMode,
+) -> Result<VaultTree, CoreError> {
+    if metadata.vault_index != vault_index {
+        return Err(CoreError::InvalidInput(format!(
+            "Metadata is for vault index {}, not {}",
+            metadata.vault_index, vault_index
+        )));
+    }
 
-    Ok(checked.is_spend_standard())
+    let secp = Secp256k1::verification_only();
+    let metadata_script = mode_leaf(metadata, mode);
+    VaultKeys::new(&secp, template, owner_xpub, recovery_xpub, network, metadata.tree_version()?)?
+        .tree(&secp, vault_index, Some(metadata_script))
 }
 
-/// Decode metadata from a script leaf hex string
-pub fn decode_metadata_from_script(script_hex: &str) -> Result<VaultMetadata, CoreError> {
-    let script_bytes = hex::decode(script_hex)
-        .map_err(|e| CoreError::MetadataError(format!("Invalid hex: {}", e)))?;
+/// Same tree as `vault_tree_versioned()`, with the owner and recovery
+/// keys' origins given by the account xpubs' own origins (see
+/// `keys::parse_xpub_with_origin()`)
+pub(crate) fn vault_tree_with_origins(
+    template: &VaultTemplate,
+    owner: (&ExtendedPubKey, &KeySource),
+    recovery: (&ExtendedPubKey, &KeySource),
+    vault_index: u32,
+    network: Network,
+    version: TreeVersion,
+) -> Result<VaultTree, CoreError> {
+    let secp = Secp256k1::verification_only();
+    VaultKeys::new(&secp, template, owner.0, recovery.0, network, version)?
+        .with_origins(owner.1, recovery.1)
+        .tree(&secp, vault_index, None)
+}
+
+fn mode_leaf(metadata: &VaultMetadata, mode: MetadataMode) -> ScriptBuf {
+    match mode {
+        MetadataMode::Full => metadata_leaf(metadata),
+        MetadataMode::Commitment => commitment_leaf(&metadata.commitment()),
+    }
+}
+
+/// Provably unspendable internal key for the vault at `vault_index`, in
+/// `TreeVersion::V3` and later trees, and so in new vaults by default
+///
+/// The BIP341 NUMS point H tweaked by a per-index hash: the key at
+/// `keys::nums_xpub()/0/vault_index`. Anyone can recompute the tweak to
+/// verify that nobody knows the private key, while each vault gets a
+/// distinct internal key. Only unhardened indices are accepted. Earlier
+/// versions use H itself at every index.
+pub fn nums_internal_key(vault_index: u32) -> Result<XOnlyPublicKey, CoreError> {
+    let secp = Secp256k1::verification_only();
+    keys::ReceiveBranch::new(&secp, &keys::nums_xpub(Network::Mainnet), Network::Mainnet)?
+        .derive(&secp, vault_index)
+}
+
+/// Receive branches of every account xpub a vault's tree uses
+pub(crate) struct VaultKeys<'a> {
+    template: &'a VaultTemplate,
+    owner: keys::ReceiveBranch,
+    recovery: keys::ReceiveBranch,
+    cosigners: Vec<keys::ReceiveBranch>,
+    /// Recovery service branch for the hashlock leaf
+    service: Option<keys::ReceiveBranch>,
+    internal_key: InternalKey,
+    version: TreeVersion,
+}
+
+/// Internal key of a vault's trees
+enum InternalKey {
+    /// The owner key, for templates with key-path spends
+    Owner,
+    /// `keys::unspendable_internal_key()` at every index
+    Unspendable,
+    /// Branch of `keys::nums_xpub()`, a different key at each index
+    PerIndexNums(keys::ReceiveBranch),
+}
 
-    // The script is: OP_RETURN <push> <metadata_bytes>
-    // OP_RETURN = 0x6a, then a push of the metadata
-    if script_bytes.is_empty() || script_bytes[0] != 0x6a {
-        return Err(CoreError::MetadataError("Script does not start with OP_RETURN".to_string()));
+impl<'a> VaultKeys<'a> {
+    pub(crate) fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        template: &'a VaultTemplate,
+        owner_xpub: &ExtendedPubKey,
+        recovery_xpub: &ExtendedPubKey,
+        network: Network,
+        version: TreeVersion,
+    ) -> Result<Self, CoreError> {
+        let cosigner_xpubs: &[String] = match template {
+            VaultTemplate::Custom { multisig: Some(multisig), .. } => &multisig.cosigners,
+            VaultTemplate::Inheritance { heirs, .. } => heirs,
+            VaultTemplate::Degrading { cosigners, .. } => cosigners,
+            _ => &[],
+        };
+        let branch = |xpub_str: &String| -> Result<keys::ReceiveBranch, CoreError> {
+            let (xpub, origin) = keys::parse_xpub_with_origin(xpub_str, network)?;
+            Ok(keys::ReceiveBranch::new(secp, &xpub, network)?.with_origin(origin))
+        };
+        let cosigners = cosigner_xpubs.iter().map(branch).collect::<Result<Vec<_>, CoreError>>()?;
+        let service = match template {
+            VaultTemplate::Custom { hashlock: Some(hashlock), .. } => Some(branch(&hashlock.service_xpub)?),
+            _ => None,
+        };
+        let internal_key = if template.key_path_enabled() {
+            InternalKey::Owner
+        } else if version.per_index_nums() {
+            InternalKey::PerIndexNums(keys::ReceiveBranch::new(secp, &keys::nums_xpub(network), network)?)
+        } else {
+            InternalKey::Unspendable
+        };
+
+        Ok(VaultKeys {
+            template,
+            owner: keys::ReceiveBranch::new(secp, owner_xpub, network)?,
+            recovery: keys::ReceiveBranch::new(secp, recovery_xpub, network)?,
+            cosigners,
+            service,
+            internal_key,
+            version,
+        })
     }
 
-    if script_bytes.len() < 3 {
-        return Err(CoreError::MetadataError("Script too short".to_string()));
+    /// Origins of the owner and recovery account xpubs, in place of the
+    /// xpubs themselves
+    fn with_origins(mut self, owner: &KeySource, recovery: &KeySource) -> Self {
+        self.owner = self.owner.with_origin(owner.clone());
+        self.recovery = self.recovery.with_origin(recovery.clone());
+        self
     }
 
-    // Parse the push opcode
-    let (data_start, data_len) = if script_bytes[1] < 0x4c {
-        (2usize, script_bytes[1] as usize)
-    } else if script_bytes[1] == 0x4c {
-        if script_bytes.len() < 4 {
-            return Err(CoreError::MetadataError("Script too short for PUSHDATA1".to_string()));
+    /// Tree at `vault_index`, with `metadata_script` as a metadata leaf if given
+    ///
+    /// Leaf keys carry their origin from their account xpub's origin, by
+    /// default relative to the account xpub itself, as (account
+    /// fingerprint, `0/index`).
+    pub(crate) fn tree<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        vault_index: u32,
+        metadata_script: Option<ScriptBuf>,
+    ) -> Result<VaultTree, CoreError> {
+        let mut key_origins = BTreeMap::new();
+        let mut derive = |branch: &keys::ReceiveBranch| -> Result<XOnlyPublicKey, CoreError> {
+            let key = branch.derive(secp, vault_index)?;
+            key_origins.insert(key, branch.key_origin(vault_index));
+            Ok(key)
+        };
+
+        let cosigners = self
+            .cosigners
+            .iter()
+            .map(&mut derive)
+            .collect::<Result<Vec<_>, CoreError>>()?;
+        let service = self.service.as_ref().map(&mut derive).transpose()?;
+        let leaf_keys = LeafKeys {
+            owner: derive(&self.owner)?,
+            recovery: derive(&self.recovery)?,
+            cosigners,
+            service,
+        };
+
+        let mut leaves = leaf_scripts(self.template, &leaf_keys, self.version)?;
+        if let Some(script) = metadata_script {
+            leaves.push(VaultLeaf {
+                purpose: LeafPurpose::Metadata,
+                script,
+                version: bitcoin::taproot::LeafVersion::TapScript,
+            });
         }
-        (3usize, script_bytes[2] as usize)
-    } else {
-        return Err(CoreError::MetadataError("Unexpected push opcode".to_string()));
-    };
 
-    if data_start + data_len > script_bytes.len() {
-        return Err(CoreError::MetadataError("Data extends past script end".to_string()));
+        let internal_key = match &self.internal_key {
+            InternalKey::Owner => leaf_keys.owner,
+            InternalKey::Unspendable => keys::unspendable_internal_key(),
+            InternalKey::PerIndexNums(nums) => nums.derive(secp, vault_index)?,
+        };
+
+        Ok(tree::build_tree_with(secp, leaves, internal_key, self.version)?.with_key_origins(key_origins))
+    }
+}
+
+/// Most addresses `derive_address_range()` derives in one call
+pub const MAX_ADDRESS_RANGE: u32 = 10_000;
+
+/// A vault deposit address at one derivation index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressInfo {
+    /// Vault derivation index
+    pub index: u32,
+    /// Taproot address
+    pub address: String,
+    /// scriptPubKey (hex)
+    pub script_pubkey: String,
+}
+
+/// Derive deposit addresses for vault indices `start..start + count`
+///
+/// Keys are parsed and their receive branches derived once for the whole
+/// range, so each address costs one child derivation per key. With the
+/// `parallel` feature the indices are split across threads; addresses
+/// are returned in index order either way. `count` is capped at
+/// `MAX_ADDRESS_RANGE`.
+pub fn derive_address_range(config: &VaultConfig, start: u32, count: u32) -> Result<Vec<AddressInfo>, CoreError> {
+    derive_address_range_cancellable(config, start, count, &CancelToken::new())
+}
+
+/// `derive_address_range()`, giving up with `CoreError::Cancelled` once
+/// `cancel` is tripped
+pub fn derive_address_range_cancellable(
+    config: &VaultConfig,
+    start: u32,
+    count: u32,
+    cancel: &CancelToken,
+) -> Result<Vec<AddressInfo>, CoreError> {
+    if count > MAX_ADDRESS_RANGE {
+        return Err(CoreError::InvalidInput(format!(
+            "Cannot derive {} addresses at once (maximum {})",
+            count, MAX_ADDRESS_RANGE
+        )));
+    }
+    let end = start
+        .checked_add(count)
+        .filter(|end| *end <= 1 << 31)
+        .ok_or_else(|| CoreError::InvalidInput(format!("Address range {}+{} passes the last unhardened index", start, count)))?;
+
+    let secp = Secp256k1::verification_only();
+    let owner = keys::parse_xpub(&config.owner_xpub, config.network)?;
+    let recovery = keys::parse_xpub(&config.recovery_xpub, config.network)?;
+    let vault_keys = VaultKeys::new(&secp, &config.template, &owner, &recovery, config.network, config.tree_version)?;
+
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    crate::parallel::map_indices(start..=end - 1, |index| {
+        cancel.check()?;
+        let tree = vault_keys.tree(&secp, index, None)?;
+        Ok(AddressInfo {
+            index,
+            address: tree.address(config.network).to_string(),
+            script_pubkey: hex::encode(tree.script_pubkey().as_bytes()),
+        })
+    })
+    .into_iter()
+    .collect()
+}
+
+/// First vault index in `0..=max_index` whose output is `script_pubkey`,
+/// for trees in the layout of `version`
+///
+/// Keys and receive branches are derived once, as in
+/// `derive_address_range()`, and the scan stops at the first match (with
+/// the `parallel` feature, at the first match in index order; a derivation
+/// error at a lower index is reported instead). At most
+/// `MAX_ADDRESS_RANGE` indices are scanned per call.
+pub fn find_vault_index(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+    script_pubkey: &Script,
+    max_index: u32,
+) -> Result<Option<u32>, CoreError> {
+    find_vault_index_cancellable(
+        template,
+        owner_xpub,
+        recovery_xpub,
+        network,
+        version,
+        script_pubkey,
+        max_index,
+        &CancelToken::new(),
+    )
+}
+
+/// `find_vault_index()`, giving up with `CoreError::Cancelled` once
+/// `cancel` is tripped
+#[allow(clippy::too_many_arguments)]
+pub fn find_vault_index_cancellable(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+    script_pubkey: &Script,
+    max_index: u32,
+    cancel: &CancelToken,
+) -> Result<Option<u32>, CoreError> {
+    if max_index >= MAX_ADDRESS_RANGE {
+        return Err(CoreError::InvalidInput(format!(
+            "Cannot scan to index {} (at most {} indices)",
+            max_index, MAX_ADDRESS_RANGE
+        )));
     }
 
-    VaultMetadata::from_bytes(&script_bytes[data_start..data_start + data_len])
+    let secp = Secp256k1::verification_only();
+    let vault_keys = VaultKeys::new(&secp, template, owner_xpub, recovery_xpub, network, version)?;
+    crate::parallel::find_map_first(0..=max_index, |index| {
+        if let Err(e) = cancel.check() {
+            return Some(Err(e));
+        }
+        match vault_keys.tree(&secp, index, None) {
+            Ok(tree) => (tree.script_pubkey().as_script() == script_pubkey).then_some(Ok(index)),
+            Err(e) => Some(Err(e)),
+        }
+    })
+    .transpose()
+}
+
+/// Derive the bech32m deposit address for a vault at `vault_index`, in
+/// the `TreeVersion::LATEST` layout
+///
+/// The same template, keys, index and network always yield the same address.
+pub fn vault_address(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    vault_index: u32,
+    network: Network,
+) -> Result<Address, CoreError> {
+    Ok(vault_tree(template, owner_xpub, recovery_xpub, vault_index, network)?.address(network))
+}
+
+/// Validate a Bitcoin address string for the given network
+pub fn validate_address(address_str: &str, network: Network) -> Result<bool, CoreError> {
+    Ok(parse_address(address_str, network)?.is_spend_standard())
+}
+
+/// Parse a Bitcoin address string, requiring it to belong to `network`
+///
+/// See `vault::policy::validate_address()`.
+pub fn parse_address(address_str: &str, network: Network) -> Result<Address, CoreError> {
+    crate::vault::policy::validate_address(address_str, network)
+}
+
+/// Decode metadata from a script leaf hex string
+pub fn decode_metadata_from_script(script_hex: &str) -> Result<VaultMetadata, CoreError> {
+    let script_bytes = hex::decode(script_hex)
+        .map_err(|e| CoreError::MetadataError(format!("Invalid hex: {}", e)))?;
+
+    extract_metadata(bitcoin::Script::from_bytes(&script_bytes))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    const TEST_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    use crate::test_common::{custom_template, mainnet_config, CustomFields, OWNER_TPUB, OWNER_XPUB, RECOVERY_TPUB, RECOVERY_XPUB, THIRD_XPUB};
+    use crate::vault::DelayUnit;
 
     #[test]
     fn test_generate_vault_address_savings() {
         let result = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         );
         assert!(result.is_ok(), "Failed: {:?}", result.err());
         let addr = result.unwrap();
@@ -205,7 +568,7 @@ mod tests {
     #[test]
     fn test_generate_vault_address_spending() {
         let result = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::spending(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::spending(), 0, Network::Mainnet, MetadataMode::Full,
         );
         assert!(result.is_ok());
         let addr = result.unwrap();
@@ -216,7 +579,7 @@ mod tests {
     #[test]
     fn test_generate_vault_address_with_emergency() {
         let result = generate_vault_address(
-            TEST_XPUB, Some(TEST_XPUB), &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, Some(OWNER_XPUB), &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         );
         assert!(result.is_ok());
     }
@@ -224,15 +587,15 @@ mod tests {
     #[test]
     fn test_generate_vault_address_deterministic() {
         let a1 = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
         let a2 = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
         assert_eq!(a1.address, a2.address);
 
         let a3 = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 1, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 1, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
         assert_ne!(a1.address, a3.address);
     }
@@ -240,7 +603,7 @@ mod tests {
     #[test]
     fn test_metadata_roundtrip_via_script() {
         let addr = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 42, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 42, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
 
         let decoded = decode_metadata_from_script(&addr.metadata_script_hex).unwrap();
@@ -250,10 +613,29 @@ mod tests {
         assert_eq!(decoded.vault_index, 42);
     }
 
+    #[test]
+    fn test_generate_vault_address_commitment_mode() {
+        let full = generate_vault_address(
+            OWNER_XPUB, None, &VaultTemplate::savings(), 42, Network::Mainnet, MetadataMode::Full,
+        ).unwrap();
+        let committed = generate_vault_address(
+            OWNER_XPUB, None, &VaultTemplate::savings(), 42, Network::Mainnet, MetadataMode::Commitment,
+        ).unwrap();
+        assert_eq!(committed.metadata_mode, MetadataMode::Commitment);
+        assert_eq!(committed.metadata_commitment, full.metadata_commitment);
+        assert_eq!(committed.metadata_script_hex, format!("6a20{}", committed.metadata_commitment));
+        assert_ne!(committed.address, full.address);
+
+        // The commitment leaf reveals no metadata
+        assert!(decode_metadata_from_script(&committed.metadata_script_hex).is_err());
+        let json = serde_json::to_value(&committed).unwrap();
+        assert_eq!(json["metadata_mode"], "commitment");
+    }
+
     #[test]
     fn test_validate_address_valid() {
         let addr = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
         assert!(validate_address(&addr.address, Network::Mainnet).unwrap());
     }
@@ -261,15 +643,349 @@ mod tests {
     #[test]
     fn test_validate_address_wrong_network() {
         let addr = generate_vault_address(
-            TEST_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet,
+            OWNER_XPUB, None, &VaultTemplate::savings(), 0, Network::Mainnet, MetadataMode::Full,
         ).unwrap();
         assert!(validate_address(&addr.address, Network::Testnet).is_err());
     }
 
+    fn xpubs(network: Network) -> (ExtendedPubKey, ExtendedPubKey) {
+        let (owner, recovery) = match network {
+            Network::Mainnet => (OWNER_XPUB, RECOVERY_XPUB),
+            _ => (OWNER_TPUB, RECOVERY_TPUB),
+        };
+        (
+            keys::parse_xpub(owner, network).unwrap(),
+            keys::parse_xpub(recovery, network).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_vault_address_mainnet_vectors() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = VaultTemplate::savings();
+
+        let addr0 = vault_address(&template, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        assert_eq!(addr0.to_string(), "bc1ppwlpr72ejugtz4v6x3aujy0qkyzmkuwhu2npg0qfkz4r33l89acsuwkqqh");
+
+        let addr1 = vault_address(&template, &owner, &recovery, 1, Network::Mainnet).unwrap();
+        assert_eq!(addr1.to_string(), "bc1pewx9thw4ydtvgppnkhzmnh8harkkh7lfmpupqwyc48mhukq7yqdqn22ped");
+    }
+
+    #[test]
+    fn test_vault_address_signet_vectors() {
+        let (owner, recovery) = xpubs(Network::Signet);
+        let template = VaultTemplate::spending();
+
+        let addr0 = vault_address(&template, &owner, &recovery, 0, Network::Signet).unwrap();
+        assert_eq!(addr0.to_string(), "tb1pwh9ktspkxxzlv0cqjeq4g77len7yt9c5xryndwrhtshwwazj0y8srrj5p9");
+
+        let v1 = vault_tree_versioned(&template, &owner, &recovery, 0, Network::Signet, TreeVersion::V1).unwrap();
+        assert_eq!(v1.address(Network::Signet).to_string(), "tb1p0rvpwqz5y42km98e4qxrsuja53kmcnxe0llwygu78p4lrf60v8gs4jfcln");
+    }
+
+    #[test]
+    fn test_vault_address_versioned_vectors() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = VaultTemplate::savings();
+        let address = |index, version| {
+            vault_tree_versioned(&template, &owner, &recovery, index, Network::Mainnet, version)
+                .unwrap()
+                .address(Network::Mainnet)
+                .to_string()
+        };
+
+        // V1, the layout of vaults created before versions were recorded
+        assert_eq!(address(0, TreeVersion::V1), "bc1pxss4uus4xg2slncafuja8efxa9z7n3shypgmsj5k2nw6evaaqr3qjp936n");
+        assert_eq!(address(1, TreeVersion::V1), "bc1panyqsr56kjrv32at270dksassjg54qn554zwxjqcc3dx8eq48qsqa6ffjj");
+        // V2 changes the timelock leaf
+        assert_eq!(address(0, TreeVersion::V2), "bc1pmkn83fs04w0dzdzcxk2wy2hkughesw086gt0axa3w5vql0czv7ysrr2jdc");
+        assert_eq!(address(1, TreeVersion::V2), "bc1prmgua4tz6rma9zefrcy2tcp0knm44gyd6a9qkchys7dh25ayg8tsl0m4xd");
+        // V3 then changes the internal key
+        assert_eq!(address(0, TreeVersion::V3), "bc1ppwlpr72ejugtz4v6x3aujy0qkyzmkuwhu2npg0qfkz4r33l89acsuwkqqh");
+        assert_eq!(address(1, TreeVersion::V3), "bc1pewx9thw4ydtvgppnkhzmnh8harkkh7lfmpupqwyc48mhukq7yqdqn22ped");
+    }
+
+    #[test]
+    fn test_vault_address_deterministic() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = VaultTemplate::savings();
+
+        let a1 = vault_address(&template, &owner, &recovery, 5, Network::Mainnet).unwrap();
+        let a2 = vault_address(&template, &owner, &recovery, 5, Network::Mainnet).unwrap();
+        assert_eq!(a1, a2);
+        assert_eq!(
+            a1.address_type(),
+            Some(bitcoin::address::AddressType::P2tr)
+        );
+
+        // Swapping roles must change the address
+        let swapped = vault_address(&template, &recovery, &owner, 5, Network::Mainnet).unwrap();
+        assert_ne!(a1, swapped);
+    }
+
+    #[test]
+    fn test_derive_address_range_matches_vault_address() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let config = VaultConfig { tree_version: TreeVersion::V1, ..mainnet_config() };
+
+        let range = derive_address_range(&config, 0, 3).unwrap();
+        assert_eq!(range.len(), 3);
+        assert_eq!(range[0].address, "bc1pxss4uus4xg2slncafuja8efxa9z7n3shypgmsj5k2nw6evaaqr3qjp936n");
+        assert_eq!(range[1].address, "bc1panyqsr56kjrv32at270dksassjg54qn554zwxjqcc3dx8eq48qsqa6ffjj");
+
+        for info in derive_address_range(&config, 40, 5).unwrap() {
+            let tree = vault_tree_versioned(&config.template, &owner, &recovery, info.index, Network::Mainnet, config.tree_version)
+                .unwrap();
+            assert_eq!(info.address, tree.address(Network::Mainnet).to_string());
+            assert_eq!(info.script_pubkey, hex::encode(tree.script_pubkey().as_bytes()));
+        }
+        assert_eq!(derive_address_range(&config, 40, 5).unwrap()[0].index, 40);
+        assert!(derive_address_range(&config, 7, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_derive_address_range_limits() {
+        let config = mainnet_config();
+
+        assert!(derive_address_range(&config, 0, MAX_ADDRESS_RANGE + 1).is_err());
+        assert!(derive_address_range(&config, (1 << 31) - 2, 2).is_ok());
+        assert!(matches!(
+            derive_address_range(&config, (1 << 31) - 2, 3),
+            Err(CoreError::InvalidInput(_))
+        ));
+        assert!(derive_address_range(&config, u32::MAX, 2).is_err());
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            derive_address_range_cancellable(&config, 0, 100, &cancel),
+            Err(CoreError::Cancelled)
+        ));
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let spk = vault_tree(&config.template, &owner, &recovery, 5, Network::Mainnet).unwrap().script_pubkey();
+        assert!(matches!(
+            find_vault_index_cancellable(&config.template, &owner, &recovery, Network::Mainnet, TreeVersion::V1, &spk, 100, &cancel),
+            Err(CoreError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_vault_tree_timelock_only_has_single_leaf() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = custom_template(CustomFields::default());
+
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        assert_eq!(tree.spend_info().as_script_map().len(), 1);
+        assert_eq!(tree.leaves().len(), 1);
+        assert_eq!(tree.internal_key(), nums_internal_key(0).unwrap());
+    }
+
+    #[test]
+    fn test_nums_internal_key_per_index() {
+        let h = keys::unspendable_internal_key();
+        let k0 = nums_internal_key(0).unwrap();
+        let k1 = nums_internal_key(1).unwrap();
+
+        assert_eq!(k0, nums_internal_key(0).unwrap());
+        assert_ne!(k0, k1);
+        assert_ne!(k0, h);
+        assert_ne!(k1, h);
+        assert!(nums_internal_key(0x8000_0000).is_err());
+
+        // Same key on every network: only the xpub encoding differs.
+        let secp = Secp256k1::verification_only();
+        let branch = keys::ReceiveBranch::new(&secp, &keys::nums_xpub(Network::Regtest), Network::Regtest).unwrap();
+        assert_eq!(branch.derive(&secp, 1).unwrap(), k1);
+    }
+
+    #[test]
+    fn test_key_path_enabled_uses_owner_internal_key() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = custom_template(CustomFields { key_path_enabled: true, ..Default::default() });
+
+        let tree = vault_tree(&template, &owner, &recovery, 2, Network::Mainnet).unwrap();
+        let secp = Secp256k1::verification_only();
+        let owner_key = keys::ReceiveBranch::new(&secp, &owner, Network::Mainnet)
+            .unwrap()
+            .derive(&secp, 2)
+            .unwrap();
+        assert_eq!(tree.internal_key(), owner_key);
+
+        let disabled = custom_template(CustomFields::default());
+        let nums_tree = vault_tree(&disabled, &owner, &recovery, 2, Network::Mainnet).unwrap();
+        assert_eq!(nums_tree.internal_key(), nums_internal_key(2).unwrap());
+        assert_ne!(tree.address(Network::Mainnet), nums_tree.address(Network::Mainnet));
+        let h_tree = vault_tree_versioned(&disabled, &owner, &recovery, 2, Network::Mainnet, TreeVersion::V2).unwrap();
+        assert_eq!(h_tree.internal_key(), keys::unspendable_internal_key());
+        // The owner key is the internal key in every version
+        let v1_tree = vault_tree_versioned(&template, &owner, &recovery, 2, Network::Mainnet, TreeVersion::V1).unwrap();
+        assert_eq!(v1_tree.internal_key(), owner_key);
+    }
+
+    #[test]
+    fn test_recovery_type_changes_address() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let emergency = custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::EmergencyKey,
+            ..Default::default()
+        });
+        let timelock_only = custom_template(CustomFields { delay_blocks: 1008, ..Default::default() });
+
+        let a = vault_address(&emergency, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        let b = vault_address(&timelock_only, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_multisig_vault_address() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let cosigners = vec![
+            RECOVERY_XPUB.to_string(),
+            OWNER_XPUB.to_string(),
+            THIRD_XPUB.to_string(),
+        ];
+        let template = custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(crate::vault::MultisigRecovery { threshold: 2, cosigners: cosigners.clone() }),
+            ..Default::default()
+        });
+        let addr = vault_address(&template, &owner, &recovery, 0, Network::Mainnet).unwrap();
+
+        // Cosigner order must not affect the address
+        let mut reversed = cosigners;
+        reversed.reverse();
+        let reordered = custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(crate::vault::MultisigRecovery { threshold: 2, cosigners: reversed }),
+            ..Default::default()
+        });
+        let addr2 = vault_address(&reordered, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        assert_eq!(addr, addr2);
+
+        let emergency = custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::EmergencyKey,
+            ..Default::default()
+        });
+        let addr3 = vault_address(&emergency, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        assert_ne!(addr, addr3);
+    }
+
+    fn inheritance(heirs: &[&str], inactivity_blocks: u32) -> VaultTemplate {
+        VaultTemplate::Inheritance {
+            heir_threshold: 2,
+            heir_count: heirs.len() as u8,
+            inactivity_blocks,
+            heirs: heirs.iter().map(|xpub| xpub.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_inheritance_vault_tree() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = inheritance(&[RECOVERY_XPUB, THIRD_XPUB, OWNER_XPUB], 26_000);
+
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        let purposes: Vec<_> = tree.leaves().iter().map(|leaf| leaf.purpose).collect();
+        assert_eq!(purposes, vec![LeafPurpose::Inheritance]);
+
+        // The owner spends through the key path
+        let secp = Secp256k1::verification_only();
+        let owner_key = keys::ReceiveBranch::new(&secp, &owner, Network::Mainnet)
+            .unwrap()
+            .derive(&secp, 0)
+            .unwrap();
+        assert_eq!(tree.internal_key(), owner_key);
+
+        let script = &tree.leaf(LeafPurpose::Inheritance).unwrap().script;
+        assert_eq!(leaf_csv_delay(script), Some(26_000));
+        let signers = leaf_signers(script).unwrap();
+        assert_eq!(signers.threshold, 2);
+        assert_eq!(signers.keys.len(), 3);
+        assert_eq!(signers.keys[2], owner_key);
+    }
+
+    #[test]
+    fn test_inheritance_address_vectors() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = inheritance(&[RECOVERY_XPUB, THIRD_XPUB, OWNER_XPUB], 26_000);
+
+        let cases = [
+            (0, "bc1plc63w352qxgs7h32qkwl8l3yjlxpsvf8z8h48sj88mqa5d9p2pqsed4cn2"),
+            (1, "bc1p7nmeuxta3fqcgrl58fsfxlpddx0wzpvmrx4jlus4vhyhgwtxrkusn243hj"),
+        ];
+        for (index, expected) in cases {
+            let addr = vault_address(&template, &owner, &recovery, index, Network::Mainnet).unwrap();
+            assert_eq!(addr.to_string(), expected, "index {}", index);
+        }
+
+        // Heir order, heir set and delay are all part of the address
+        let addr = vault_address(&template, &owner, &recovery, 0, Network::Mainnet).unwrap();
+        for other in [
+            inheritance(&[THIRD_XPUB, RECOVERY_XPUB, OWNER_XPUB], 26_000),
+            inheritance(&[RECOVERY_XPUB, THIRD_XPUB], 26_000),
+            inheritance(&[RECOVERY_XPUB, THIRD_XPUB, OWNER_XPUB], 26_001),
+        ] {
+            assert_ne!(vault_address(&other, &owner, &recovery, 0, Network::Mainnet).unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn test_vault_tree_with_metadata() {
+        let (owner, recovery) = xpubs(Network::Mainnet);
+        let template = VaultTemplate::savings();
+        let metadata = VaultMetadata {
+            version: 1,
+            template_id: template.template_id().to_string(),
+            delay_blocks: template.delay_blocks(),
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 800_000,
+            vault_index: 3,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        };
+
+        let plain = vault_tree(&template, &owner, &recovery, 3, Network::Mainnet).unwrap();
+        let tree =
+            vault_tree_with_metadata(&template, &owner, &recovery, 3, Network::Mainnet, &metadata, MetadataMode::Full).unwrap();
+        assert_eq!(tree.leaves().len(), plain.leaves().len() + 1);
+        assert_ne!(tree.merkle_root(), plain.merkle_root());
+        assert_ne!(tree.address(Network::Mainnet), plain.address(Network::Mainnet));
+
+        // Spending leaves still prove membership under the new output key
+        for purpose in [LeafPurpose::Timelock, LeafPurpose::Emergency] {
+            let leaf = tree.leaf(purpose).unwrap();
+            let cb = control_block(&tree, purpose).unwrap();
+            assert!(verify_control_block(&cb, &leaf.script, &tree.output_key().to_inner()));
+            assert!(!verify_control_block(&cb, &leaf.script, &plain.output_key().to_inner()));
+        }
+
+        let leaf = tree.leaf(LeafPurpose::Metadata).unwrap();
+        assert_eq!(extract_metadata(&leaf.script).unwrap().to_bytes(), metadata.to_bytes());
+
+        let committed = vault_tree_with_metadata(
+            &template, &owner, &recovery, 3, Network::Mainnet, &metadata, MetadataMode::Commitment,
+        )
+        .unwrap();
+        let leaf = committed.leaf(LeafPurpose::Metadata).unwrap();
+        assert_eq!(extract_commitment(&leaf.script).unwrap(), metadata.commitment());
+        assert_ne!(committed.merkle_root(), tree.merkle_root());
+
+        let err = vault_tree_with_metadata(&template, &owner, &recovery, 4, Network::Mainnet, &metadata, MetadataMode::Full);
+        assert!(matches!(err, Err(CoreError::InvalidInput(_))));
+    }
+
     #[test]
     fn test_spending_script_structure() {
-        let key = keys::derive_child_pubkey(TEST_XPUB, 0, Network::Mainnet).unwrap();
-        let script = build_spending_script(&key, 1008);
+        let key = keys::derive_child_pubkey(OWNER_XPUB, 0, Network::Mainnet).unwrap();
+        let script = build_spending_script(&key, Sequence::from_height(1008));
         let bytes = script.as_bytes();
         assert!(!bytes.is_empty());
         assert!(bytes.contains(&0xad), "Missing OP_CHECKSIGVERIFY");
@@ -0,0 +1,1208 @@
+use std::collections::BTreeMap;
+
+use bitcoin::absolute::{self, LockTime};
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKSIG, OP_CHECKSIGADD, OP_CLTV, OP_CSV, OP_DROP, OP_EQUALVERIFY, OP_NUMEQUAL,
+    OP_PUSHNUM_1, OP_PUSHNUM_16, OP_SHA256, OP_SIZE, OP_VERIFY, OP_RETURN,
+};
+use bitcoin::blockdata::script::{
+    read_scriptint, Builder, Instruction, PushBytesBuf, Script, ScriptBuf,
+};
+use bitcoin::hashes::Hash as _;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::taproot::{LeafVersion, Signature, TapLeafHash};
+use bitcoin::Sequence;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::CoreError;
+use super::tree::TreeVersion;
+use crate::vault::{DelayUnit, RecoveryType, VaultMetadata, VaultTemplate};
+
+/// Largest delay encodable in a CSV sequence (16 bits), in blocks or 512-second units
+pub const MAX_CSV_DELAY_BLOCKS: u32 = 65_535;
+
+/// Most keys a CHECKSIGADD leaf can hold within the tapscript stack limit
+pub const MAX_MULTISIG_KEYS: usize = 999;
+
+/// Length of the secret that opens a hashlock leaf
+pub const HASHLOCK_PREIMAGE_LEN: usize = 32;
+
+/// A CSV timelock leaf with the data needed to build control blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelockLeaf {
+    /// Leaf script: <delay> OP_CSV OP_DROP|OP_VERIFY <key> OP_CHECKSIG
+    pub script: ScriptBuf,
+    /// Tapleaf hash of the script
+    pub leaf_hash: TapLeafHash,
+    /// Tapscript leaf version
+    pub version: LeafVersion,
+    /// Relative delay in `delay_unit`s
+    pub delay_blocks: u32,
+    /// Unit of `delay_blocks`
+    pub delay_unit: DelayUnit,
+}
+
+/// Build the delayed spend leaf: <delay> OP_CSV OP_DROP <key> OP_CHECKSIG,
+/// or <delay> OP_CSV OP_VERIFY <key> OP_CHECKSIG from `TreeVersion::V2`
+///
+/// The delay is pushed with minimal encoding, as the nSequence value it
+/// requires: time-based delays carry the BIP68 type flag (bit 22).
+/// Delays of 0 or above `MAX_CSV_DELAY_BLOCKS` are rejected since they
+/// either disable the timelock or cannot be expressed in a CSV sequence.
+pub fn timelock_leaf(
+    spend_key: &XOnlyPublicKey,
+    delay_blocks: u32,
+    delay_unit: DelayUnit,
+    version: TreeVersion,
+) -> Result<TimelockLeaf, CoreError> {
+    let sequence = csv_sequence(delay_blocks, delay_unit)?;
+
+    // OP_CSV leaves the delay on the stack; either opcode clears it
+    let clear_delay = if version.has_descriptor() { OP_VERIFY } else { OP_DROP };
+    let script = Builder::new()
+        .push_int(sequence.to_consensus_u32() as i64)
+        .push_opcode(OP_CSV)
+        .push_opcode(clear_delay)
+        .push_x_only_key(spend_key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script();
+    let version = LeafVersion::TapScript;
+
+    Ok(TimelockLeaf {
+        leaf_hash: TapLeafHash::from_script(&script, version),
+        script,
+        version,
+        delay_blocks,
+        delay_unit,
+    })
+}
+
+/// nSequence for a CSV push of `delay_blocks` `delay_unit`s, rejecting 0
+/// and anything above `MAX_CSV_DELAY_BLOCKS`
+fn csv_sequence(delay_blocks: u32, delay_unit: DelayUnit) -> Result<Sequence, CoreError> {
+    if delay_blocks == 0 {
+        return Err(CoreError::PolicyViolation(format!(
+            "Timelock delay must be at least 1 ({})",
+            delay_unit.name()
+        )));
+    }
+    if delay_blocks > MAX_CSV_DELAY_BLOCKS {
+        return Err(CoreError::PolicyViolation(format!(
+            "Timelock delay of {} {} exceeds the CSV limit of {}",
+            delay_blocks,
+            delay_unit.name(),
+            MAX_CSV_DELAY_BLOCKS
+        )));
+    }
+    delay_unit.sequence(delay_blocks)
+}
+
+/// Build the emergency recovery leaf: <key> OP_CHECKSIG
+///
+/// Lets the recovery key sweep vault funds immediately, with no timelock.
+/// Only included in trees for `RecoveryType::EmergencyKey`.
+pub fn emergency_leaf(recovery_key: &XOnlyPublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_x_only_key(recovery_key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Build the absolute lock leaf: <height> OP_CHECKLOCKTIMEVERIFY OP_DROP <key> OP_CHECKSIG
+///
+/// The key can spend once the chain reaches `lock_height`, however
+/// recently the output moved. Heights of `absolute::LOCK_TIME_THRESHOLD`
+/// (500,000,000) and up would be read as Unix timestamps and 0 doesn't
+/// lock at all, so both are a `PolicyViolation`; use `cltv_leaf_at()`
+/// with `LockTime::Seconds` for a date.
+pub fn cltv_leaf(key: &XOnlyPublicKey, lock_height: u32) -> Result<ScriptBuf, CoreError> {
+    let height = absolute::Height::from_consensus(lock_height).map_err(|_| {
+        CoreError::PolicyViolation(format!(
+            "Absolute lock height {} is at or above {}, which would be read as a timestamp",
+            lock_height,
+            absolute::LOCK_TIME_THRESHOLD
+        ))
+    })?;
+    cltv_leaf_at(key, LockTime::Blocks(height), TreeVersion::V1)
+}
+
+/// Build the absolute lock leaf for a block height or a Unix timestamp,
+/// in the form `version` uses
+///
+/// Like `cltv_leaf()`, but `LockTime::Seconds` locks until the median
+/// time past reaches the timestamp, and from `TreeVersion::V4` the lock
+/// is cleared with OP_VERIFY, the miniscript `and_v(v:after(n),pk(K))`.
+/// A lock of 0 is rejected.
+pub fn cltv_leaf_at(key: &XOnlyPublicKey, lock: LockTime, version: TreeVersion) -> Result<ScriptBuf, CoreError> {
+    if lock == LockTime::ZERO {
+        return Err(CoreError::PolicyViolation(
+            "Absolute lock must be above height 0".to_string(),
+        ));
+    }
+    // OP_CLTV leaves the lock on the stack; either opcode clears it
+    let clear_lock = if version.has_lock_descriptors() { OP_VERIFY } else { OP_DROP };
+    Ok(Builder::new()
+        .push_int(lock.to_consensus_u32() as i64)
+        .push_opcode(OP_CLTV)
+        .push_opcode(clear_lock)
+        .push_x_only_key(key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script())
+}
+
+/// Build the hashlock leaf: OP_SHA256 <hash> OP_EQUALVERIFY <key> OP_CHECKSIG
+///
+/// The key can only spend by also revealing a preimage of `hash`. The
+/// preimage length isn't checked in script; `psbt::attach_preimage()`
+/// only accepts `HASHLOCK_PREIMAGE_LEN` bytes.
+pub fn hashlock_leaf(hash: [u8; 32], key: &XOnlyPublicKey) -> ScriptBuf {
+    hashlock_leaf_at(hash, key, TreeVersion::V1)
+}
+
+/// Build the hashlock leaf in the form `version` uses
+///
+/// From `TreeVersion::V4` the leaf is the miniscript
+/// `and_v(v:sha256(H),pk(K))`, which prefixes `hashlock_leaf()` with
+/// `OP_SIZE 32 OP_EQUALVERIFY` so the script itself checks the preimage
+/// length.
+pub fn hashlock_leaf_at(hash: [u8; 32], key: &XOnlyPublicKey, version: TreeVersion) -> ScriptBuf {
+    let mut builder = Builder::new();
+    if version.has_lock_descriptors() {
+        builder = builder
+            .push_opcode(OP_SIZE)
+            .push_int(HASHLOCK_PREIMAGE_LEN as i64)
+            .push_opcode(OP_EQUALVERIFY);
+    }
+    builder
+        .push_opcode(OP_SHA256)
+        .push_slice(hash)
+        .push_opcode(OP_EQUALVERIFY)
+        .push_x_only_key(key)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Build the metadata leaf: OP_RETURN <metadata_bytes>
+///
+/// OP_RETURN fails unconditionally, so the leaf is provably unspendable;
+/// it only commits the vault's configuration to the output for
+/// watch-only restores.
+pub fn metadata_leaf(metadata: &VaultMetadata) -> ScriptBuf {
+    let push_bytes = PushBytesBuf::try_from(metadata.to_bytes())
+        .expect("metadata bytes should be valid push data (< 4294967296 bytes)");
+    Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(&push_bytes)
+        .into_script()
+}
+
+/// Recover vault metadata from a revealed metadata leaf
+///
+/// The script must be exactly `OP_RETURN <push>`. The push may use any
+/// push opcode, minimal or not, since leaves built by other software
+/// may not encode it minimally.
+pub fn extract_metadata(leaf_script: &Script) -> Result<VaultMetadata, CoreError> {
+    VaultMetadata::from_bytes(op_return_payload(leaf_script, "metadata")?)
+}
+
+/// Build the metadata commitment leaf: OP_RETURN <commitment>
+///
+/// Takes `VaultMetadata::commitment()`. Unspendable like
+/// `metadata_leaf()`, but reveals nothing about the vault's
+/// configuration; the metadata itself stays in the backup.
+pub fn commitment_leaf(commitment: &[u8; 32]) -> ScriptBuf {
+    Builder::new()
+        .push_opcode(OP_RETURN)
+        .push_slice(commitment)
+        .into_script()
+}
+
+/// Recover the metadata commitment from a commitment leaf
+///
+/// Accepts the same script shapes as `extract_metadata()`, with a push
+/// of exactly 32 bytes.
+pub fn extract_commitment(leaf_script: &Script) -> Result<[u8; 32], CoreError> {
+    let data = op_return_payload(leaf_script, "commitment")?;
+    data.try_into().map_err(|_| {
+        CoreError::MetadataError(format!("Commitment leaf pushes {} bytes, not 32", data.len()))
+    })
+}
+
+/// The single push of an `OP_RETURN <push>` script
+fn op_return_payload<'a>(leaf_script: &'a Script, kind: &str) -> Result<&'a [u8], CoreError> {
+    let mut instructions = leaf_script.instructions();
+    let not_leaf = || CoreError::MetadataError(format!("Script is not an OP_RETURN {} leaf", kind));
+
+    match instructions.next() {
+        Some(Ok(Instruction::Op(OP_RETURN))) => {}
+        _ => return Err(not_leaf()),
+    }
+    let data = match instructions.next() {
+        Some(Ok(Instruction::PushBytes(bytes))) => bytes,
+        Some(Err(e)) => return Err(CoreError::MetadataError(format!("Malformed {} push: {}", kind, e))),
+        _ => return Err(not_leaf()),
+    };
+    if instructions.next().is_some() {
+        return Err(not_leaf());
+    }
+
+    Ok(data.as_bytes())
+}
+
+/// What a leaf in the vault script tree is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeafPurpose {
+    /// Delayed spend by the owner key
+    Timelock,
+    /// Shorter delayed spend by the owner key, for approved destinations
+    WhitelistTimelock,
+    /// Immediate sweep by the recovery key
+    Emergency,
+    /// Immediate sweep by k-of-n cosigners
+    Multisig,
+    /// Sweep by k-of-n heirs after the inactivity delay
+    Inheritance,
+    /// Spend by the recovery key once the chain reaches an absolute lock
+    AbsoluteLock,
+    /// Immediate sweep by the recovery service key, given the hash preimage
+    Hashlock,
+    /// Spend by k-of-n of a degrading template's signers, at the given
+    /// stage of its table
+    DegradingStage(u8),
+    /// Unspendable OP_RETURN leaf committing to the vault's metadata
+    Metadata,
+}
+
+/// Keys derived at a vault index, as used by the tree's leaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafKeys {
+    /// Owner key for the timelock leaf
+    pub owner: XOnlyPublicKey,
+    /// Recovery key for the emergency and absolute lock leaves
+    pub recovery: XOnlyPublicKey,
+    /// Cosigner keys for the multisig leaf (empty unless `RecoveryType::MultiSig`),
+    /// heir keys for the inheritance leaf, or the signers after owner and
+    /// recovery in the degrading leaves
+    pub cosigners: Vec<XOnlyPublicKey>,
+    /// Recovery service key for the hashlock leaf, if the template has one
+    pub service: Option<XOnlyPublicKey>,
+}
+
+/// A script leaf of the vault tree, labeled with its purpose
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultLeaf {
+    /// What the leaf is for
+    pub purpose: LeafPurpose,
+    /// Leaf script
+    pub script: ScriptBuf,
+    /// Tapscript leaf version
+    pub version: LeafVersion,
+}
+
+/// Build every script leaf of a vault tree for the given template
+///
+/// The timelock leaf is always present. Savings, spending and dual-delay
+/// templates add the emergency leaf; custom templates add the emergency
+/// leaf for `RecoveryType::EmergencyKey`, the multisig leaf for
+/// `MultiSig`, and nothing for `TimelockOnly`, then the absolute lock
+/// leaf if they set `absolute_lock` and the hashlock leaf if they set
+/// `hashlock`. Dual-delay templates also add the
+/// whitelist timelock leaf. Inheritance templates have the inheritance
+/// leaf alone, since the owner spends through the key path. Degrading
+/// templates have one leaf per stage, over owner, recovery and cosigner
+/// keys in that order, earliest stage first. `version` picks the
+/// timelock leaves' form (see `timelock_leaf()`).
+pub fn leaf_scripts(
+    template: &VaultTemplate,
+    keys: &LeafKeys,
+    version: TreeVersion,
+) -> Result<Vec<VaultLeaf>, CoreError> {
+    if let VaultTemplate::Degrading { stages, .. } = template {
+        let signers: Vec<XOnlyPublicKey> =
+            [keys.owner, keys.recovery].into_iter().chain(keys.cosigners.iter().copied()).collect();
+        return stages
+            .iter()
+            .enumerate()
+            .map(|(stage, &(delay, threshold))| {
+                Ok(VaultLeaf {
+                    purpose: LeafPurpose::DegradingStage(stage as u8),
+                    script: degrading_leaf(&signers, threshold, delay)?,
+                    version: LeafVersion::TapScript,
+                })
+            })
+            .collect();
+    }
+
+    if let VaultTemplate::Inheritance { heir_threshold, inactivity_blocks, .. } = template {
+        return Ok(vec![VaultLeaf {
+            purpose: LeafPurpose::Inheritance,
+            script: inheritance_leaf(&keys.cosigners, *heir_threshold, *inactivity_blocks)?,
+            version: LeafVersion::TapScript,
+        }]);
+    }
+
+    let timelock = timelock_leaf(&keys.owner, template.delay_blocks(), template.delay_unit(), version)?;
+    let mut leaves = vec![VaultLeaf {
+        purpose: LeafPurpose::Timelock,
+        script: timelock.script,
+        version: timelock.version,
+    }];
+    if let Some(delay) = template.whitelist_delay() {
+        let whitelist = timelock_leaf(&keys.owner, delay, DelayUnit::Blocks, version)?;
+        leaves.push(VaultLeaf {
+            purpose: LeafPurpose::WhitelistTimelock,
+            script: whitelist.script,
+            version: whitelist.version,
+        });
+    }
+
+    let (recovery_type, multisig) = match template {
+        VaultTemplate::Custom { recovery_type, multisig, .. } => (*recovery_type, multisig.as_ref()),
+        _ => (RecoveryType::EmergencyKey, None),
+    };
+
+    match recovery_type {
+        RecoveryType::EmergencyKey => leaves.push(VaultLeaf {
+            purpose: LeafPurpose::Emergency,
+            script: emergency_leaf(&keys.recovery),
+            version: LeafVersion::TapScript,
+        }),
+        RecoveryType::TimelockOnly => {}
+        RecoveryType::MultiSig => {
+            let multisig = multisig.ok_or_else(|| {
+                CoreError::PolicyViolation(
+                    "MultiSig recovery requires a cosigner set".to_string(),
+                )
+            })?;
+            leaves.push(VaultLeaf {
+                purpose: LeafPurpose::Multisig,
+                script: multisig_leaf(&keys.cosigners, multisig.threshold)?,
+                version: LeafVersion::TapScript,
+            });
+        }
+    }
+
+    if let Some(lock) = template.absolute_lock()? {
+        leaves.push(VaultLeaf {
+            purpose: LeafPurpose::AbsoluteLock,
+            script: cltv_leaf_at(&keys.recovery, lock, version)?,
+            version: LeafVersion::TapScript,
+        });
+    }
+
+    if let VaultTemplate::Custom { hashlock: Some(hashlock), .. } = template {
+        let service = keys.service.ok_or_else(|| {
+            CoreError::PolicyViolation("Hashlock recovery requires a service key".to_string())
+        })?;
+        leaves.push(VaultLeaf {
+            purpose: LeafPurpose::Hashlock,
+            script: hashlock_leaf_at(hashlock.hash.to_byte_array(), &service, version),
+            version: LeafVersion::TapScript,
+        });
+    }
+
+    Ok(leaves)
+}
+
+/// Build a k-of-n recovery leaf using the BIP342 OP_CHECKSIGADD pattern:
+/// <key_1> OP_CHECKSIG <key_2> OP_CHECKSIGADD ... <key_n> OP_CHECKSIGADD <k> OP_NUMEQUAL
+///
+/// Keys are sorted by their serialized bytes so every cosigner derives the
+/// same script regardless of the order they were supplied in.
+pub fn multisig_leaf(keys: &[XOnlyPublicKey], threshold: u8) -> Result<ScriptBuf, CoreError> {
+    let mut sorted = keys.to_vec();
+    sorted.sort_by_key(|key| key.serialize());
+    checksigadd_script(&sorted, threshold)
+}
+
+/// <key_1> OP_CHECKSIG <key_2> OP_CHECKSIGADD ... <k> OP_NUMEQUAL, keys in the order given
+fn checksigadd_script(keys: &[XOnlyPublicKey], threshold: u8) -> Result<ScriptBuf, CoreError> {
+    if keys.is_empty() {
+        return Err(CoreError::PolicyViolation(
+            "Multisig leaf requires at least one key".to_string(),
+        ));
+    }
+    if keys.len() > MAX_MULTISIG_KEYS {
+        return Err(CoreError::PolicyViolation(format!(
+            "Multisig leaf has {} keys, tapscript limit is {}",
+            keys.len(),
+            MAX_MULTISIG_KEYS
+        )));
+    }
+    if threshold == 0 || threshold as usize > keys.len() {
+        return Err(CoreError::PolicyViolation(format!(
+            "Multisig threshold {} is invalid for {} keys",
+            threshold,
+            keys.len()
+        )));
+    }
+
+    let mut sorted = keys.to_vec();
+    sorted.sort_by_key(|key| key.serialize());
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(CoreError::PolicyViolation(format!(
+            "Duplicate multisig key: {}",
+            pair[0]
+        )));
+    }
+
+    let mut builder = Builder::new();
+    for (i, key) in keys.iter().enumerate() {
+        builder = builder.push_x_only_key(key);
+        builder = if i == 0 {
+            builder.push_opcode(OP_CHECKSIG)
+        } else {
+            builder.push_opcode(OP_CHECKSIGADD)
+        };
+    }
+
+    Ok(builder
+        .push_int(threshold as i64)
+        .push_opcode(OP_NUMEQUAL)
+        .into_script())
+}
+
+/// Build the inheritance leaf: a k-of-n OP_CHECKSIGADD check behind a CSV,
+/// <delay> OP_CSV OP_VERIFY <key_1> OP_CHECKSIG <key_2> OP_CHECKSIGADD ... <k> OP_NUMEQUAL
+///
+/// Unlike `multisig_leaf()`, keys stay in the order given, so the leaf
+/// is the miniscript `and_v(v:older(delay),multi_a(k,...))` over the
+/// template's heir list.
+pub fn inheritance_leaf(
+    heir_keys: &[XOnlyPublicKey],
+    threshold: u8,
+    inactivity_blocks: u32,
+) -> Result<ScriptBuf, CoreError> {
+    let sequence = csv_sequence(inactivity_blocks, DelayUnit::Blocks)?;
+    let multisig = checksigadd_script(heir_keys, threshold)?;
+
+    let mut bytes = Builder::new()
+        .push_int(sequence.to_consensus_u32() as i64)
+        .push_opcode(OP_CSV)
+        .push_opcode(OP_VERIFY)
+        .into_script()
+        .into_bytes();
+    bytes.extend_from_slice(multisig.as_bytes());
+    Ok(ScriptBuf::from(bytes))
+}
+
+/// Build one stage of a degrading tree: `inheritance_leaf()` for a
+/// delayed stage, or the bare OP_CHECKSIGADD check for a delay of 0
+///
+/// Keys stay in the order given either way, so the stage is the
+/// miniscript `multi_a(k,...)` or `and_v(v:older(delay),multi_a(k,...))`.
+pub fn degrading_leaf(keys: &[XOnlyPublicKey], threshold: u8, delay_blocks: u32) -> Result<ScriptBuf, CoreError> {
+    if delay_blocks == 0 {
+        checksigadd_script(keys, threshold)
+    } else {
+        inheritance_leaf(keys, threshold, delay_blocks)
+    }
+}
+
+/// Keys that sign for a leaf, in script order, and how many must sign
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafSigners {
+    /// Keys in the order the script checks them
+    pub keys: Vec<XOnlyPublicKey>,
+    /// Number of signatures the script requires
+    pub threshold: usize,
+}
+
+/// Parse the signers of a vault leaf script
+///
+/// Recognizes the single-key leaves (`<key> OP_CHECKSIG`, optionally
+/// behind a `<delay> OP_CSV OP_VERIFY` (or `OP_DROP`), `<lock> OP_CLTV OP_VERIFY`
+/// (or `OP_DROP`) or `OP_SHA256 <hash> OP_EQUALVERIFY` prefix) and the OP_CHECKSIGADD multisig leaf. Returns `None` for any
+/// other script.
+pub fn leaf_signers(script: &Script) -> Option<LeafSigners> {
+    let instructions = script
+        .instructions_minimal()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let mut rest = &instructions[..];
+    if leaf_csv_delay(script).is_some() {
+        match rest {
+            [_, _, Instruction::Op(OP_VERIFY | OP_DROP), tail @ ..] => rest = tail,
+            _ => return None,
+        }
+    } else if leaf_cltv_lock(script).is_some() {
+        match rest {
+            [_, _, Instruction::Op(OP_VERIFY | OP_DROP), tail @ ..] => rest = tail,
+            _ => return None,
+        }
+    } else if leaf_hashlock(script).is_some() {
+        rest = match rest {
+            [Instruction::Op(OP_SIZE), _, _, _, _, _, tail @ ..] => tail,
+            [_, _, _, tail @ ..] => tail,
+            _ => return None,
+        };
+    }
+
+    let mut keys = Vec::new();
+    match rest {
+        [Instruction::PushBytes(key), Instruction::Op(OP_CHECKSIG), tail @ ..] => {
+            keys.push(XOnlyPublicKey::from_slice(key.as_bytes()).ok()?);
+            rest = tail;
+        }
+        _ => return None,
+    }
+    if rest.is_empty() {
+        return Some(LeafSigners { keys, threshold: 1 });
+    }
+
+    while let [Instruction::PushBytes(key), Instruction::Op(OP_CHECKSIGADD), tail @ ..] = rest {
+        keys.push(XOnlyPublicKey::from_slice(key.as_bytes()).ok()?);
+        rest = tail;
+    }
+    match rest {
+        [threshold, Instruction::Op(OP_NUMEQUAL)] => {
+            let threshold = usize::try_from(instruction_int(threshold)?).ok()?;
+            (1..=keys.len())
+                .contains(&threshold)
+                .then_some(LeafSigners { keys, threshold })
+        }
+        _ => None,
+    }
+}
+
+/// Signature elements of the witness spending a CHECKSIGADD leaf
+///
+/// `leaf_keys` are in script order (see `leaf_signers()`). The script
+/// pops one element per key, first key first, so the elements come out
+/// in reverse key order, with an empty element for each key that didn't
+/// sign. The elements go below the script and control block.
+///
+/// Errors with `InvalidInput` unless `sigs` holds exactly `threshold`
+/// signatures, all by leaf keys; too few names the keys without one.
+pub fn assemble_multisig_witness(
+    leaf_keys: &[XOnlyPublicKey],
+    sigs: &BTreeMap<XOnlyPublicKey, Signature>,
+    threshold: u8,
+) -> Result<Vec<Vec<u8>>, CoreError> {
+    if let Some(key) = sigs.keys().find(|key| !leaf_keys.contains(key)) {
+        return Err(CoreError::InvalidInput(format!("Key {} is not in the leaf", key)));
+    }
+    let threshold = threshold as usize;
+    if sigs.len() < threshold {
+        let missing: Vec<String> = leaf_keys
+            .iter()
+            .filter(|key| !sigs.contains_key(key))
+            .map(|key| key.to_string())
+            .collect();
+        return Err(CoreError::InvalidInput(format!(
+            "Leaf needs {} signature(s), has {}; unsigned keys: {}",
+            threshold,
+            sigs.len(),
+            missing.join(", ")
+        )));
+    }
+    if sigs.len() > threshold {
+        return Err(CoreError::InvalidInput(format!(
+            "Leaf needs exactly {} signature(s), has {}",
+            threshold,
+            sigs.len()
+        )));
+    }
+
+    Ok(leaf_keys
+        .iter()
+        .rev()
+        .map(|key| sigs.get(key).map(|sig| sig.to_vec()).unwrap_or_default())
+        .collect())
+}
+
+/// CSV delay of a timelock leaf: the number pushed before OP_CSV
+///
+/// This is the raw nSequence value, with the BIP68 type flag set for
+/// time-based delays. Returns `None` if the script does not start with
+/// `<delay> OP_CSV`.
+pub fn leaf_csv_delay(script: &Script) -> Option<u32> {
+    let mut instructions = script.instructions_minimal();
+    let delay = instruction_int(&instructions.next()?.ok()?)?;
+    match instructions.next()?.ok()? {
+        Instruction::Op(OP_CSV) => u32::try_from(delay).ok(),
+        _ => None,
+    }
+}
+
+/// Absolute lock of a CLTV leaf: the number pushed before OP_CLTV
+///
+/// Values from `absolute::LOCK_TIME_THRESHOLD` up are timestamps.
+/// Returns `None` if the script does not start with `<lock> OP_CLTV`.
+pub fn leaf_cltv_lock(script: &Script) -> Option<LockTime> {
+    let mut instructions = script.instructions_minimal();
+    let lock = instruction_int(&instructions.next()?.ok()?)?;
+    match instructions.next()?.ok()? {
+        Instruction::Op(OP_CLTV) => u32::try_from(lock).ok().map(LockTime::from_consensus),
+        _ => None,
+    }
+}
+
+/// Hash a hashlock leaf's preimage must match: the push between OP_SHA256 and OP_EQUALVERIFY
+///
+/// Returns `None` if the script does not start with
+/// `OP_SHA256 <32-byte hash> OP_EQUALVERIFY`, optionally after the
+/// `OP_SIZE 32 OP_EQUALVERIFY` length check of `TreeVersion::V4` leaves.
+pub fn leaf_hashlock(script: &Script) -> Option<[u8; 32]> {
+    let instructions = script
+        .instructions_minimal()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let rest = match &instructions[..] {
+        [Instruction::Op(OP_SIZE), size, Instruction::Op(OP_EQUALVERIFY), rest @ ..]
+            if instruction_int(size) == Some(HASHLOCK_PREIMAGE_LEN as i64) =>
+        {
+            rest
+        }
+        rest => rest,
+    };
+    match rest {
+        [Instruction::Op(OP_SHA256), Instruction::PushBytes(hash), Instruction::Op(OP_EQUALVERIFY), ..] => {
+            hash.as_bytes().try_into().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Value of a number push, including the OP_1..OP_16 opcodes
+fn instruction_int(instruction: &Instruction) -> Option<i64> {
+    match instruction {
+        Instruction::PushBytes(bytes) => read_scriptint(bytes.as_bytes()).ok(),
+        Instruction::Op(op) => {
+            let n = op.to_u8();
+            (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8())
+                .contains(&n)
+                .then(|| (n - OP_PUSHNUM_1.to_u8() + 1) as i64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, CustomFields};
+    use crate::vault::{HashlockRecovery, MultisigRecovery};
+    use bitcoin::hashes::sha256;
+
+    // Generator point x-coordinate, a convenient fixed x-only key
+    const KEY_HEX: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    fn test_key() -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&hex::decode(KEY_HEX).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_timelock_leaf_vectors() {
+        // <delay push> b2 (CSV) 75 (DROP) 20 <key> ac (CHECKSIG)
+        let cases = [
+            (1, "51"),           // OP_1
+            (144, "029000"),     // 0x0090 little-endian
+            (1008, "02f003"),    // 0x03f0 little-endian
+            (65535, "03ffff00"), // sign byte needed for 0xffff
+        ];
+
+        for (delay, push_hex) in cases {
+            let leaf = timelock_leaf(&test_key(), delay, DelayUnit::Blocks, TreeVersion::V1).unwrap();
+            let expected = format!("{}b27520{}ac", push_hex, KEY_HEX);
+            assert_eq!(hex::encode(leaf.script.as_bytes()), expected, "delay {}", delay);
+            assert_eq!(leaf.delay_blocks, delay);
+            assert_eq!(leaf.version, LeafVersion::TapScript);
+            assert_eq!(leaf.leaf_hash, TapLeafHash::from_script(&leaf.script, LeafVersion::TapScript));
+
+            // Version 2: b2 (CSV) 69 (VERIFY), the miniscript form
+            let leaf = timelock_leaf(&test_key(), delay, DelayUnit::Blocks, TreeVersion::V2).unwrap();
+            let expected = format!("{}b26920{}ac", push_hex, KEY_HEX);
+            assert_eq!(hex::encode(leaf.script.as_bytes()), expected, "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn test_timelock_leaf_time_based_encoding() {
+        // The pushed value is the nSequence with the type flag (bit 22) set
+        let cases = [
+            (1, "03010040"),      // 0x400001
+            (144, "03900040"),    // 0x400090
+            (65535, "03ffff40"),  // 0x40ffff
+        ];
+
+        for (delay, push_hex) in cases {
+            let leaf = timelock_leaf(&test_key(), delay, DelayUnit::TimeUnits512s, TreeVersion::V2).unwrap();
+            let expected = format!("{}b26920{}ac", push_hex, KEY_HEX);
+            assert_eq!(hex::encode(leaf.script.as_bytes()), expected, "delay {}", delay);
+            assert_eq!(leaf.delay_unit, DelayUnit::TimeUnits512s);
+            assert_eq!(leaf_csv_delay(&leaf.script), Some(0x40_0000 | delay));
+        }
+
+        assert!(timelock_leaf(&test_key(), 65_536, DelayUnit::TimeUnits512s, TreeVersion::V2).is_err());
+    }
+
+    #[test]
+    fn test_timelock_leaf_rejects_zero() {
+        match timelock_leaf(&test_key(), 0, DelayUnit::Blocks, TreeVersion::V1).unwrap_err() {
+            CoreError::PolicyViolation(_) => {}
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_timelock_leaf_rejects_above_csv_limit() {
+        match timelock_leaf(&test_key(), 65_536, DelayUnit::Blocks, TreeVersion::V1).unwrap_err() {
+            CoreError::PolicyViolation(msg) => assert!(msg.contains("65536"), "{}", msg),
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_emergency_leaf() {
+        let script = emergency_leaf(&test_key());
+        assert_eq!(hex::encode(script.as_bytes()), format!("20{}ac", KEY_HEX));
+    }
+
+    #[test]
+    fn test_cltv_leaf_height_and_timestamp() {
+        // <lock push> b1 (CLTV) 75 (DROP), or 69 (VERIFY) from V4, 20 <key> ac (CHECKSIG)
+        let cases = [
+            (LockTime::from_height(1).unwrap(), "51"),
+            (LockTime::from_height(1_000_000).unwrap(), "0340420f"), // 0x0f4240 little-endian
+            (LockTime::from_height(499_999_999).unwrap(), "04ff64cd1d"),
+            (LockTime::from_time(500_000_000).unwrap(), "040065cd1d"),
+            (LockTime::from_time(1_700_000_000).unwrap(), "0400f15365"),
+        ];
+        for (lock, push_hex) in cases {
+            for (version, clear) in [(TreeVersion::V3, "75"), (TreeVersion::V4, "69")] {
+                let script = cltv_leaf_at(&test_key(), lock, version).unwrap();
+                assert_eq!(hex::encode(script.as_bytes()), format!("{}b1{}20{}ac", push_hex, clear, KEY_HEX), "{}", lock);
+                assert_eq!(leaf_cltv_lock(&script), Some(lock));
+                assert_eq!(leaf_csv_delay(&script), None);
+                let signers = leaf_signers(&script).unwrap();
+                assert_eq!(signers.keys, vec![test_key()]);
+                assert_eq!(signers.threshold, 1);
+            }
+        }
+
+        assert_eq!(
+            cltv_leaf(&test_key(), 1_000_000).unwrap(),
+            cltv_leaf_at(&test_key(), cases[1].0, TreeVersion::V1).unwrap()
+        );
+        // A height this large would be read as a timestamp
+        for height in [0, 500_000_000, u32::MAX] {
+            assert!(matches!(cltv_leaf(&test_key(), height), Err(CoreError::PolicyViolation(_))), "{}", height);
+        }
+        assert!(cltv_leaf_at(&test_key(), LockTime::ZERO, TreeVersion::LATEST).is_err());
+    }
+
+    #[test]
+    fn test_hashlock_leaf() {
+        let hash = sha256::Hash::hash(&[7u8; HASHLOCK_PREIMAGE_LEN]).to_byte_array();
+        let script = hashlock_leaf(hash, &test_key());
+        // a8 (SHA256) 20 <hash> 88 (EQUALVERIFY) 20 <key> ac (CHECKSIG)
+        assert_eq!(
+            hex::encode(script.as_bytes()),
+            format!("a820{}8820{}ac", hex::encode(hash), KEY_HEX)
+        );
+        assert_eq!(leaf_hashlock(&script), Some(hash));
+        assert_eq!(leaf_cltv_lock(&script), None);
+        let signers = leaf_signers(&script).unwrap();
+        assert_eq!(signers.keys, vec![test_key()]);
+        assert_eq!(signers.threshold, 1);
+
+        assert_eq!(leaf_hashlock(&emergency_leaf(&test_key())), None);
+    }
+
+    #[test]
+    fn test_hashlock_leaf_v4() {
+        let hash = sha256::Hash::hash(&[7u8; HASHLOCK_PREIMAGE_LEN]).to_byte_array();
+        assert_eq!(hashlock_leaf_at(hash, &test_key(), TreeVersion::V3), hashlock_leaf(hash, &test_key()));
+
+        let script = hashlock_leaf_at(hash, &test_key(), TreeVersion::V4);
+        // 82 (SIZE) 01 20 88 (EQUALVERIFY) then the V1 leaf
+        assert_eq!(
+            hex::encode(script.as_bytes()),
+            format!("82012088a820{}8820{}ac", hex::encode(hash), KEY_HEX)
+        );
+        assert_eq!(leaf_hashlock(&script), Some(hash));
+        let signers = leaf_signers(&script).unwrap();
+        assert_eq!(signers.keys, vec![test_key()]);
+        assert_eq!(signers.threshold, 1);
+    }
+
+    // x-coordinates of 2G, 3G and 4G
+    const KEY2_HEX: &str = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+    const KEY3_HEX: &str = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+    const KEY4_HEX: &str = "e493dbf1c10d80f3581e4904930b1404cc6c13900ee0758474fa94abe8c4cd13";
+
+    fn key(hex_str: &str) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_slice(&hex::decode(hex_str).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_multisig_leaf_2_of_3() {
+        let keys = [key(KEY3_HEX), key(KEY_HEX), key(KEY2_HEX)];
+        let script = multisig_leaf(&keys, 2).unwrap();
+
+        // Sorted: 79be.. < c604.. < f930..
+        let expected = format!(
+            "20{}ac20{}ba20{}ba529c",
+            KEY_HEX, KEY2_HEX, KEY3_HEX
+        );
+        assert_eq!(hex::encode(script.as_bytes()), expected);
+    }
+
+    fn dummy_sig(byte: u8) -> Signature {
+        Signature {
+            sig: bitcoin::secp256k1::schnorr::Signature::from_slice(&[byte; 64]).unwrap(),
+            hash_ty: bitcoin::sighash::TapSighashType::Default,
+        }
+    }
+
+    #[test]
+    fn test_assemble_multisig_witness_2_of_3() {
+        let keys = [key(KEY_HEX), key(KEY2_HEX), key(KEY3_HEX)];
+        for (a, b) in [(0, 1), (0, 2), (1, 2)] {
+            let sigs = BTreeMap::from([(keys[a], dummy_sig(a as u8 + 1)), (keys[b], dummy_sig(b as u8 + 1))]);
+            let witness = assemble_multisig_witness(&keys, &sigs, 2).unwrap();
+
+            // Last key's element first, an empty one for the key that didn't sign
+            let expected: Vec<Vec<u8>> = (0..3)
+                .rev()
+                .map(|i| if i == a || i == b { dummy_sig(i as u8 + 1).to_vec() } else { vec![] })
+                .collect();
+            assert_eq!(witness, expected, "signers {} and {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_assemble_multisig_witness_errors() {
+        let keys = [key(KEY_HEX), key(KEY2_HEX), key(KEY3_HEX)];
+
+        let one = BTreeMap::from([(keys[1], dummy_sig(1))]);
+        match assemble_multisig_witness(&keys, &one, 2).unwrap_err() {
+            CoreError::InvalidInput(msg) => {
+                assert!(msg.contains(KEY_HEX) && msg.contains(KEY3_HEX), "{}", msg);
+                assert!(!msg.contains(KEY2_HEX), "{}", msg);
+            }
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
+
+        let all = keys.iter().map(|key| (*key, dummy_sig(1))).collect();
+        assert!(matches!(assemble_multisig_witness(&keys, &all, 2), Err(CoreError::InvalidInput(_))));
+
+        let stranger = BTreeMap::from([(keys[0], dummy_sig(1)), (key(KEY4_HEX), dummy_sig(2))]);
+        assert!(matches!(assemble_multisig_witness(&keys[..2], &stranger, 2), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_inheritance_leaf_keeps_key_order() {
+        let keys = [key(KEY3_HEX), key(KEY_HEX), key(KEY2_HEX)];
+        let script = inheritance_leaf(&keys, 2, 26_000).unwrap();
+
+        // 26000 = 0x6590, then the CHECKSIGADD check over the unsorted keys
+        let expected = format!(
+            "029065b26920{}ac20{}ba20{}ba529c",
+            KEY3_HEX, KEY_HEX, KEY2_HEX
+        );
+        assert_eq!(hex::encode(script.as_bytes()), expected);
+
+        let signers = leaf_signers(&script).unwrap();
+        assert_eq!(signers.keys, keys);
+        assert_eq!(signers.threshold, 2);
+
+        assert!(matches!(inheritance_leaf(&keys, 2, 0), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(inheritance_leaf(&keys, 4, 26_000), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(
+            inheritance_leaf(&[key(KEY_HEX), key(KEY_HEX)], 1, 26_000),
+            Err(CoreError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_multisig_leaf_order_independent() {
+        let a = multisig_leaf(&[key(KEY_HEX), key(KEY2_HEX), key(KEY4_HEX)], 2).unwrap();
+        let b = multisig_leaf(&[key(KEY4_HEX), key(KEY2_HEX), key(KEY_HEX)], 2).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_multisig_leaf_rejects_bad_threshold() {
+        let keys = [key(KEY_HEX), key(KEY2_HEX)];
+        assert!(matches!(multisig_leaf(&keys, 0), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(multisig_leaf(&keys, 3), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(multisig_leaf(&[], 1), Err(CoreError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_multisig_leaf_rejects_duplicate_keys() {
+        let keys = [key(KEY_HEX), key(KEY2_HEX), key(KEY_HEX)];
+        match multisig_leaf(&keys, 2).unwrap_err() {
+            CoreError::PolicyViolation(msg) => assert!(msg.contains("Duplicate"), "{}", msg),
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_multisig_leaf_rejects_too_many_keys() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let keys: Vec<XOnlyPublicKey> = (1..=(MAX_MULTISIG_KEYS as u32 + 1))
+            .map(|i| {
+                let mut bytes = [0u8; 32];
+                bytes[28..].copy_from_slice(&i.to_be_bytes());
+                let sk = bitcoin::secp256k1::SecretKey::from_slice(&bytes).unwrap();
+                sk.x_only_public_key(&secp).0
+            })
+            .collect();
+        assert!(matches!(multisig_leaf(&keys, 2), Err(CoreError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_leaf_scripts_by_recovery_type() {
+        let owner = test_key();
+        let recovery = key(KEY2_HEX);
+        let keys = LeafKeys { owner, recovery, cosigners: vec![], service: None };
+
+        let savings = leaf_scripts(&VaultTemplate::savings(), &keys, TreeVersion::V1).unwrap();
+        let purposes: Vec<_> = savings.iter().map(|l| l.purpose).collect();
+        assert_eq!(purposes, vec![LeafPurpose::Timelock, LeafPurpose::Emergency]);
+        assert_eq!(savings[1].script, emergency_leaf(&recovery));
+
+        let timelock_only = custom_template(CustomFields { delay_blocks: 1008, ..Default::default() });
+        let leaves = leaf_scripts(&timelock_only, &keys, TreeVersion::V1).unwrap();
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].purpose, LeafPurpose::Timelock);
+        assert_eq!(leaves[0].script, timelock_leaf(&owner, 1008, DelayUnit::Blocks, TreeVersion::V1).unwrap().script);
+
+        let dual = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        let leaves = leaf_scripts(&dual, &keys, TreeVersion::V1).unwrap();
+        let purposes: Vec<_> = leaves.iter().map(|l| l.purpose).collect();
+        assert_eq!(
+            purposes,
+            vec![LeafPurpose::Timelock, LeafPurpose::WhitelistTimelock, LeafPurpose::Emergency]
+        );
+        assert_eq!(leaves[0].script, timelock_leaf(&owner, 1008, DelayUnit::Blocks, TreeVersion::V1).unwrap().script);
+        assert_eq!(leaves[1].script, timelock_leaf(&owner, 144, DelayUnit::Blocks, TreeVersion::V1).unwrap().script);
+    }
+
+    #[test]
+    fn test_leaf_scripts_absolute_lock() {
+        let keys = LeafKeys { owner: test_key(), recovery: key(KEY2_HEX), cosigners: vec![], service: None };
+        let template = custom_template(CustomFields { absolute_lock: Some(1_000_000), ..Default::default() });
+
+        let leaves = leaf_scripts(&template, &keys, TreeVersion::V1).unwrap();
+        let purposes: Vec<_> = leaves.iter().map(|l| l.purpose).collect();
+        assert_eq!(purposes, vec![LeafPurpose::Timelock, LeafPurpose::AbsoluteLock]);
+        assert_eq!(leaves[1].script, cltv_leaf(&keys.recovery, 1_000_000).unwrap());
+    }
+
+    #[test]
+    fn test_leaf_scripts_hashlock() {
+        let hash = sha256::Hash::hash(b"recovery secret");
+        let mut keys = LeafKeys { owner: test_key(), recovery: key(KEY2_HEX), cosigners: vec![], service: None };
+        let template = custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            hashlock: Some(HashlockRecovery { hash, service_xpub: String::new() }),
+            ..Default::default()
+        });
+        assert!(matches!(leaf_scripts(&template, &keys, TreeVersion::V1), Err(CoreError::PolicyViolation(_))));
+
+        keys.service = Some(key(KEY3_HEX));
+        let leaves = leaf_scripts(&template, &keys, TreeVersion::V1).unwrap();
+        let purposes: Vec<_> = leaves.iter().map(|l| l.purpose).collect();
+        assert_eq!(purposes, vec![LeafPurpose::Timelock, LeafPurpose::Emergency, LeafPurpose::Hashlock]);
+        assert_eq!(leaves[2].script, hashlock_leaf(hash.to_byte_array(), &key(KEY3_HEX)));
+    }
+
+    #[test]
+    fn test_leaf_scripts_multisig() {
+        let keys = LeafKeys {
+            owner: test_key(),
+            recovery: key(KEY2_HEX),
+            cosigners: vec![key(KEY2_HEX), key(KEY3_HEX), key(KEY4_HEX)],
+            service: None,
+        };
+        let template = custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery { threshold: 2, cosigners: vec![] }),
+            ..Default::default()
+        });
+
+        let leaves = leaf_scripts(&template, &keys, TreeVersion::V1).unwrap();
+        assert_eq!(leaves.len(), 2);
+        assert_eq!(leaves[1].purpose, LeafPurpose::Multisig);
+        assert_eq!(leaves[1].script, multisig_leaf(&keys.cosigners, 2).unwrap());
+
+        let missing = custom_template(CustomFields { recovery_type: RecoveryType::MultiSig, ..Default::default() });
+        assert!(matches!(leaf_scripts(&missing, &keys, TreeVersion::V1), Err(CoreError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_leaf_scripts_degrading() {
+        let keys = LeafKeys {
+            owner: test_key(),
+            recovery: key(KEY2_HEX),
+            cosigners: vec![key(KEY3_HEX)],
+            service: None,
+        };
+        let template = VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+            cosigners: vec![],
+        };
+        let leaves = leaf_scripts(&template, &keys, TreeVersion::V1).unwrap();
+        let purposes: Vec<_> = leaves.iter().map(|l| l.purpose).collect();
+        assert_eq!(
+            purposes,
+            vec![LeafPurpose::DegradingStage(0), LeafPurpose::DegradingStage(1), LeafPurpose::DegradingStage(2)]
+        );
+
+        let signers = vec![test_key(), key(KEY2_HEX), key(KEY3_HEX)];
+        for (leaf, (delay, threshold)) in leaves.iter().zip([(None, 3), (Some(4032), 2), (Some(26_208), 1)]) {
+            assert_eq!(leaf_csv_delay(&leaf.script), delay);
+            let parsed = leaf_signers(&leaf.script).unwrap();
+            // Template order, not sorted like `multisig_leaf()`
+            assert_eq!(parsed.keys, signers);
+            assert_eq!(parsed.threshold, threshold);
+        }
+        assert_eq!(leaves[0].script, degrading_leaf(&signers, 3, 0).unwrap());
+        assert_eq!(leaves[1].script, inheritance_leaf(&signers, 2, 4032).unwrap());
+    }
+
+    #[test]
+    fn test_leaf_signers_single_key_leaves() {
+        for version in [TreeVersion::V1, TreeVersion::V2] {
+            for delay in [1, 16, 17, 144, 65_535] {
+                let leaf = timelock_leaf(&test_key(), delay, DelayUnit::Blocks, version).unwrap();
+                assert_eq!(leaf_csv_delay(&leaf.script), Some(delay));
+                let signers = leaf_signers(&leaf.script).unwrap();
+                assert_eq!(signers.keys, vec![test_key()]);
+                assert_eq!(signers.threshold, 1);
+            }
+        }
+
+        let emergency = emergency_leaf(&key(KEY2_HEX));
+        assert_eq!(leaf_csv_delay(&emergency), None);
+        let signers = leaf_signers(&emergency).unwrap();
+        assert_eq!(signers.keys, vec![key(KEY2_HEX)]);
+        assert_eq!(signers.threshold, 1);
+    }
+
+    #[test]
+    fn test_leaf_signers_multisig() {
+        let keys = [key(KEY3_HEX), key(KEY_HEX), key(KEY2_HEX)];
+        for threshold in 1..=3 {
+            let script = multisig_leaf(&keys, threshold).unwrap();
+            let signers = leaf_signers(&script).unwrap();
+            assert_eq!(signers.keys, vec![key(KEY_HEX), key(KEY2_HEX), key(KEY3_HEX)]);
+            assert_eq!(signers.threshold, threshold as usize);
+        }
+    }
+
+    #[test]
+    fn test_leaf_signers_rejects_unknown_scripts() {
+        let metadata = Builder::new()
+            .push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN)
+            .push_slice([1u8, 2, 3])
+            .into_script();
+        assert_eq!(leaf_signers(&metadata), None);
+
+        // Threshold above the key count
+        let script = Builder::new()
+            .push_x_only_key(&test_key())
+            .push_opcode(OP_CHECKSIG)
+            .push_int(2)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+        assert_eq!(leaf_signers(&script), None);
+    }
+
+    fn test_metadata() -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![0, 2],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 800_000,
+            vault_index: 7,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_extract_metadata_roundtrip() {
+        let metadata = test_metadata();
+        let script = metadata_leaf(&metadata);
+        assert_eq!(script.as_bytes()[0], OP_RETURN.to_u8());
+
+        let decoded = extract_metadata(&script).unwrap();
+        assert_eq!(decoded.to_bytes(), metadata.to_bytes());
+    }
+
+    #[test]
+    fn test_extract_metadata_non_minimal_push() {
+        let data = test_metadata().to_bytes();
+        assert!(data.len() < 0x4c);
+
+        // Same data via OP_PUSHDATA1 and OP_PUSHDATA2 instead of a direct push
+        let mut pushdata1 = vec![OP_RETURN.to_u8(), 0x4c, data.len() as u8];
+        pushdata1.extend_from_slice(&data);
+        let mut pushdata2 = vec![OP_RETURN.to_u8(), 0x4d, data.len() as u8, 0];
+        pushdata2.extend_from_slice(&data);
+
+        for bytes in [pushdata1, pushdata2] {
+            let decoded = extract_metadata(Script::from_bytes(&bytes)).unwrap();
+            assert_eq!(decoded.vault_index, 7);
+        }
+    }
+
+    #[test]
+    fn test_extract_metadata_rejects_other_scripts() {
+        let data = test_metadata().to_bytes();
+        let push = PushBytesBuf::try_from(data.clone()).unwrap();
+
+        let rejected = [
+            // Spendable leaves
+            emergency_leaf(&test_key()),
+            timelock_leaf(&test_key(), 144, DelayUnit::Blocks, TreeVersion::V1).unwrap().script,
+            // Push without OP_RETURN
+            Builder::new().push_slice(&push).into_script(),
+            // OP_RETURN alone
+            Builder::new().push_opcode(OP_RETURN).into_script(),
+            // Anything after the push
+            Builder::new().push_opcode(OP_RETURN).push_slice(&push).push_opcode(OP_VERIFY).into_script(),
+            Builder::new().push_opcode(OP_RETURN).push_slice(&push).push_slice(&push).into_script(),
+            // OP_RETURN then a non-push opcode
+            Builder::new().push_opcode(OP_RETURN).push_opcode(OP_CHECKSIG).into_script(),
+        ];
+        for script in &rejected {
+            assert!(
+                matches!(extract_metadata(script), Err(CoreError::MetadataError(_))),
+                "accepted {:?}",
+                script
+            );
+        }
+
+        // Push length running past the end of the script
+        let mut truncated = vec![OP_RETURN.to_u8(), 0x4c, data.len() as u8 + 1];
+        truncated.extend_from_slice(&data);
+        assert!(extract_metadata(Script::from_bytes(&truncated)).is_err());
+    }
+
+    #[test]
+    fn test_commitment_leaf_roundtrip() {
+        let commitment = test_metadata().commitment();
+        let script = commitment_leaf(&commitment);
+        assert_eq!(script.len(), 34);
+        assert_eq!(script.as_bytes()[..2], [OP_RETURN.to_u8(), 32]);
+        assert_eq!(extract_commitment(&script).unwrap(), commitment);
+
+        // A full metadata leaf or a short push isn't a commitment
+        for script in [metadata_leaf(&test_metadata()), commitment_leaf(&commitment)[..33].into()] {
+            assert!(matches!(extract_commitment(&script), Err(CoreError::MetadataError(_))));
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use bitcoin::address::Address;
+use bitcoin::bip32::KeySource;
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::secp256k1::{Parity, Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::hashes::Hash;
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash, TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{Script, ScriptBuf};
+use serde::{Deserialize, Serialize};
+
+use super::script::{LeafPurpose, VaultLeaf};
+use crate::error::CoreError;
+use crate::vault::Network;
+
+/// Identifies a leaf within a vault tree
+///
+/// Every tree holds at most one leaf per purpose, so the purpose doubles
+/// as the leaf identifier.
+pub type LeafId = LeafPurpose;
+
+/// A finalized vault script tree
+///
+/// Retains the internal key, output key parity and the merkle branch of
+/// every leaf, so control blocks can be produced for any leaf later.
+#[derive(Debug, Clone)]
+pub struct VaultTree {
+    spend_info: TaprootSpendInfo,
+    leaves: Vec<VaultLeaf>,
+    key_origins: BTreeMap<XOnlyPublicKey, KeySource>,
+}
+
+impl VaultTree {
+    /// Attach BIP32 origins for the keys used in the leaves
+    pub fn with_key_origins(mut self, key_origins: BTreeMap<XOnlyPublicKey, KeySource>) -> Self {
+        self.key_origins = key_origins;
+        self
+    }
+
+    /// BIP32 origins of leaf keys, where known
+    pub fn key_origins(&self) -> &BTreeMap<XOnlyPublicKey, KeySource> {
+        &self.key_origins
+    }
+
+    /// Untweaked internal key
+    pub fn internal_key(&self) -> XOnlyPublicKey {
+        self.spend_info.internal_key()
+    }
+
+    /// Tweaked output key committed to by the scriptPubKey
+    pub fn output_key(&self) -> TweakedPublicKey {
+        self.spend_info.output_key()
+    }
+
+    /// Parity of the tweaked output key
+    pub fn output_key_parity(&self) -> Parity {
+        self.spend_info.output_key_parity()
+    }
+
+    /// Merkle root of the script tree
+    pub fn merkle_root(&self) -> Option<TapNodeHash> {
+        self.spend_info.merkle_root()
+    }
+
+    /// Underlying spend info from the `bitcoin` crate
+    pub fn spend_info(&self) -> &TaprootSpendInfo {
+        &self.spend_info
+    }
+
+    /// All leaves, in the order they were supplied to the builder
+    pub fn leaves(&self) -> &[VaultLeaf] {
+        &self.leaves
+    }
+
+    /// Look up a leaf by id
+    pub fn leaf(&self, id: LeafId) -> Option<&VaultLeaf> {
+        self.leaves.iter().find(|leaf| leaf.purpose == id)
+    }
+
+    /// Tapleaf hash of a leaf
+    pub fn leaf_hash(&self, id: LeafId) -> Option<TapLeafHash> {
+        self.leaf(id)
+            .map(|leaf| TapLeafHash::from_script(&leaf.script, leaf.version))
+    }
+
+    /// P2TR scriptPubKey paying to this tree
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        ScriptBuf::new_v1_p2tr_tweaked(self.output_key())
+    }
+
+    /// Bech32m address paying to this tree
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr_tweaked(self.output_key(), network.into())
+    }
+}
+
+/// Layout of a vault's script tree
+///
+/// Each version after the first changes the leaves of existing
+/// templates, and so every address they derive. A vault keeps the
+/// version it was created with, recorded in its metadata (see
+/// `VaultMetadata::tree_version()`), and only vaults created with a
+/// later version get its layout. Vaults created before versions were
+/// recorded are `V1`; new vaults default to `LATEST`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum TreeVersion {
+    /// Timelock leaf `<delay> OP_CSV OP_DROP <key> OP_CHECKSIG`
+    ///
+    /// No miniscript fragment compiles to it, so these trees have no
+    /// descriptor.
+    V1 = 1,
+    /// Timelock leaf in the miniscript form of `and_v(v:older(n),pk(K))`,
+    /// `<delay> OP_CSV OP_VERIFY <key> OP_CHECKSIG`
+    V2 = 2,
+    /// `V2` with a distinct NUMS internal key per vault index,
+    /// `taproot::nums_internal_key()`, instead of the point H at every
+    /// index
+    V3 = 3,
+    /// `V3` with the absolute lock and hashlock leaves in their miniscript
+    /// forms: `and_v(v:after(n),pk(K))`, `<lock> OP_CLTV OP_VERIFY <key>
+    /// OP_CHECKSIG`, and `and_v(v:sha256(H),pk(K))`, which also checks the
+    /// preimage is 32 bytes with `OP_SIZE 32 OP_EQUALVERIFY`; leaves are
+    /// placed by `huffman_builder()` with the timelock leaves weighted
+    /// `TIMELOCK_LEAF_WEIGHT`
+    #[default]
+    V4 = 4,
+}
+
+impl TreeVersion {
+    /// Version for new vaults that want every layout change
+    pub const LATEST: TreeVersion = TreeVersion::V4;
+
+    /// Whether the timelock leaves have a miniscript form, so the tree
+    /// can be written as a descriptor
+    pub fn has_descriptor(self) -> bool {
+        self >= TreeVersion::V2
+    }
+
+    /// Whether script-path-only trees take `taproot::nums_internal_key()`
+    /// at their index rather than `keys::unspendable_internal_key()`
+    pub fn per_index_nums(self) -> bool {
+        self >= TreeVersion::V3
+    }
+
+    /// Whether absolute lock and hashlock leaves have a miniscript form,
+    /// so trees with one can be written as a descriptor
+    pub fn has_lock_descriptors(self) -> bool {
+        self >= TreeVersion::V4
+    }
+
+    /// Whether leaves are placed by `huffman_builder()` with the timelock
+    /// leaves weighted, rather than by rust-bitcoin's Huffman builder with
+    /// only degrading stages weighted
+    pub fn weighted_leaves(self) -> bool {
+        self >= TreeVersion::V4
+    }
+}
+
+impl From<TreeVersion> for u8 {
+    fn from(version: TreeVersion) -> u8 {
+        version as u8
+    }
+}
+
+impl TryFrom<u8> for TreeVersion {
+    type Error = CoreError;
+
+    fn try_from(version: u8) -> Result<Self, CoreError> {
+        match version {
+            1 => Ok(TreeVersion::V1),
+            2 => Ok(TreeVersion::V2),
+            3 => Ok(TreeVersion::V3),
+            4 => Ok(TreeVersion::V4),
+            v => Err(CoreError::InvalidInput(format!(
+                "Unsupported tree version {} (latest is {})",
+                v,
+                TreeVersion::LATEST as u8
+            ))),
+        }
+    }
+}
+
+/// Weight of the timelock leaves, which every unvault spends
+pub const TIMELOCK_LEAF_WEIGHT: u32 = 10;
+
+/// Weight of every other leaf: recovery paths, which are rarely spent,
+/// and the unspendable metadata leaf
+pub const DEFAULT_LEAF_WEIGHT: u32 = 1;
+
+/// A tapscript leaf with its expected spend frequency
+///
+/// Weights are relative: a leaf spent ten times as often as another
+/// should weigh ten times as much.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafSpec {
+    pub script: ScriptBuf,
+    pub weight: u32,
+}
+
+/// Place `leaves` in a Huffman tree, heavier leaves nearer the root
+///
+/// The two lightest nodes are paired until one is left, so a leaf's
+/// control block grows with how rarely it is spent. Nodes of equal
+/// weight are taken in order of their tapleaf or branch hash, so the
+/// same leaves always give the same tree, whatever order they come in.
+/// An empty list is a `ScriptError`.
+pub fn huffman_builder(leaves: &[LeafSpec]) -> Result<TaprootBuilder, CoreError> {
+    enum Node {
+        Leaf(usize),
+        Branch(usize, usize),
+    }
+
+    let mut nodes = Vec::with_capacity(2 * leaves.len());
+    let mut heap = BinaryHeap::with_capacity(leaves.len());
+    for (i, leaf) in leaves.iter().enumerate() {
+        let hash = TapNodeHash::from(TapLeafHash::from_script(&leaf.script, LeafVersion::TapScript));
+        heap.push(Reverse((leaf.weight as u64, hash.to_byte_array(), nodes.len())));
+        nodes.push((Node::Leaf(i), hash));
+    }
+    while heap.len() > 1 {
+        let (Some(Reverse((a_weight, _, a))), Some(Reverse((b_weight, _, b)))) = (heap.pop(), heap.pop()) else {
+            unreachable!("heap holds at least two nodes")
+        };
+        let hash = TapNodeHash::from_node_hashes(nodes[a].1, nodes[b].1);
+        heap.push(Reverse((a_weight + b_weight, hash.to_byte_array(), nodes.len())));
+        nodes.push((Node::Branch(a, b), hash));
+    }
+    let Reverse((_, _, root)) = heap
+        .pop()
+        .ok_or_else(|| CoreError::ScriptError("Vault tree needs at least one leaf".to_string()))?;
+
+    // TaprootBuilder takes leaves depth-first, left to right
+    let mut builder = TaprootBuilder::new();
+    let mut stack = vec![(root, 0u8)];
+    while let Some((node, depth)) = stack.pop() {
+        match nodes[node].0 {
+            Node::Leaf(i) => {
+                builder = builder
+                    .add_leaf(depth, leaves[i].script.clone())
+                    .map_err(|e| CoreError::ScriptError(format!("Failed to add vault leaf: {:?}", e)))?;
+            }
+            Node::Branch(left, right) => {
+                let depth = depth
+                    .checked_add(1)
+                    .ok_or_else(|| CoreError::ScriptError("Vault tree is too deep".to_string()))?;
+                stack.push((right, depth));
+                stack.push((left, depth));
+            }
+        }
+    }
+    Ok(builder)
+}
+
+/// Default weight of a leaf in a tree holding `stages` degrading stages
+///
+/// Timelock leaves weigh `TIMELOCK_LEAF_WEIGHT` from `TreeVersion::V4`
+/// and the rest `DEFAULT_LEAF_WEIGHT`, except degrading stages: each
+/// weighs twice the next and the last 2, so earlier stages sit shallower
+/// and none ties with the metadata leaf.
+pub(crate) fn leaf_weight(purpose: LeafPurpose, stages: u32, version: TreeVersion) -> u32 {
+    match purpose {
+        LeafPurpose::Timelock | LeafPurpose::WhitelistTimelock if version.weighted_leaves() => TIMELOCK_LEAF_WEIGHT,
+        LeafPurpose::DegradingStage(stage) => 2u32 << (stages - 1 - stage as u32),
+        _ => DEFAULT_LEAF_WEIGHT,
+    }
+}
+
+/// Build a vault tree from labeled leaves over the given internal key
+///
+/// Leaves are weighted by `leaf_weight()`. From `TreeVersion::V4` they are
+/// placed by `huffman_builder()`, so the timelock leaf gets the shortest
+/// control block; earlier versions keep the placement of rust-bitcoin's
+/// `TaprootBuilder::with_huffman_tree()`, which breaks ties differently,
+/// so their addresses don't change. Leaf purposes must be unique.
+pub fn build_tree(leaves: Vec<VaultLeaf>, internal_key: XOnlyPublicKey, version: TreeVersion) -> Result<VaultTree, CoreError> {
+    build_tree_with(&Secp256k1::verification_only(), leaves, internal_key, version)
+}
+
+/// `build_tree()` with a caller-provided context, for building many trees
+pub(crate) fn build_tree_with<C: Verification>(
+    secp: &Secp256k1<C>,
+    leaves: Vec<VaultLeaf>,
+    internal_key: XOnlyPublicKey,
+    version: TreeVersion,
+) -> Result<VaultTree, CoreError> {
+    for (i, leaf) in leaves.iter().enumerate() {
+        if leaves[..i].iter().any(|other| other.purpose == leaf.purpose) {
+            return Err(CoreError::ScriptError(format!(
+                "Duplicate {:?} leaf in vault tree",
+                leaf.purpose
+            )));
+        }
+    }
+
+    let stages = leaves
+        .iter()
+        .filter(|leaf| matches!(leaf.purpose, LeafPurpose::DegradingStage(_)))
+        .count() as u32;
+    let specs: Vec<LeafSpec> = leaves
+        .iter()
+        .map(|leaf| LeafSpec { script: leaf.script.clone(), weight: leaf_weight(leaf.purpose, stages, version) })
+        .collect();
+    let builder = if version.weighted_leaves() {
+        huffman_builder(&specs)?
+    } else {
+        TaprootBuilder::with_huffman_tree(specs.into_iter().map(|spec| (spec.weight, spec.script)))
+            .map_err(|e| CoreError::ScriptError(format!("Failed to add vault leaf: {:?}", e)))?
+    };
+    let spend_info = builder
+        .finalize(secp, internal_key)
+        .map_err(|_| CoreError::ScriptError("Failed to finalize Taproot tree".to_string()))?;
+
+    Ok(VaultTree {
+        spend_info,
+        leaves,
+        key_origins: BTreeMap::new(),
+    })
+}
+
+/// Produce the BIP341 control block for spending `leaf` via the script path
+pub fn control_block(tree: &VaultTree, leaf: LeafId) -> Result<ControlBlock, CoreError> {
+    let vault_leaf = tree
+        .leaf(leaf)
+        .ok_or_else(|| CoreError::InvalidInput(format!("Vault tree has no {:?} leaf", leaf)))?;
+
+    tree.spend_info
+        .control_block(&(vault_leaf.script.clone(), vault_leaf.version))
+        .ok_or_else(|| {
+            CoreError::ScriptError(format!("No merkle branch for {:?} leaf", leaf))
+        })
+}
+
+/// Check that a control block proves `leaf_script` is committed to by `output_key`
+pub fn verify_control_block(cb: &ControlBlock, leaf_script: &Script, output_key: &XOnlyPublicKey) -> bool {
+    let secp = Secp256k1::verification_only();
+    cb.verify_taproot_commitment(&secp, *output_key, leaf_script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taproot::{cltv_leaf, commitment_leaf, emergency_leaf, leaf_scripts, timelock_leaf, LeafKeys};
+    use crate::vault::VaultTemplate;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::bip32::{ChildNumber, ExtendedPrivKey};
+    use bitcoin::secp256k1::{schnorr, KeyPair, Message, SecretKey};
+    use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+    use bitcoin::{OutPoint, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+    use std::str::FromStr;
+
+    // BIP32 test vector 1 master key
+    const OWNER_XPRV: &str = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+
+    fn owner_keypair(vault_index: u32) -> KeyPair {
+        let secp = Secp256k1::new();
+        let xprv = ExtendedPrivKey::from_str(OWNER_XPRV).unwrap();
+        let path = [
+            ChildNumber::from_normal_idx(0).unwrap(),
+            ChildNumber::from_normal_idx(vault_index).unwrap(),
+        ];
+        let child = xprv.derive_priv(&secp, &path).unwrap();
+        KeyPair::from_secret_key(&secp, &child.private_key)
+    }
+
+    fn recovery_key() -> XOnlyPublicKey {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        KeyPair::from_secret_key(&secp, &secret).x_only_public_key().0
+    }
+
+    fn savings_tree(owner: XOnlyPublicKey, delay_blocks: u32) -> VaultTree {
+        let keys = LeafKeys {
+            owner,
+            recovery: recovery_key(),
+            service: None,
+            cosigners: Vec::new(),
+        };
+        let leaves = leaf_scripts(&VaultTemplate::Savings { delay_blocks }, &keys, TreeVersion::V1).unwrap();
+        build_tree(leaves, crate::keys::unspendable_internal_key(), TreeVersion::V1).unwrap()
+    }
+
+    #[test]
+    fn test_control_block_verifies_for_every_leaf() {
+        let tree = savings_tree(owner_keypair(0).x_only_public_key().0, 144);
+        let output_key = tree.output_key().to_inner();
+
+        for leaf in tree.leaves() {
+            let cb = control_block(&tree, leaf.purpose).unwrap();
+            assert_eq!(cb.internal_key, tree.internal_key());
+            assert_eq!(cb.output_key_parity, tree.output_key_parity());
+            assert_eq!(cb.merkle_branch.as_inner().len(), 1);
+            assert!(verify_control_block(&cb, &leaf.script, &output_key));
+        }
+    }
+
+    #[test]
+    fn test_control_block_rejects_foreign_script() {
+        let tree = savings_tree(owner_keypair(0).x_only_public_key().0, 144);
+        let other = savings_tree(owner_keypair(1).x_only_public_key().0, 144);
+
+        let cb = control_block(&tree, LeafPurpose::Timelock).unwrap();
+        let foreign = &other.leaf(LeafPurpose::Timelock).unwrap().script;
+        assert!(!verify_control_block(&cb, foreign, &tree.output_key().to_inner()));
+        assert!(!verify_control_block(
+            &cb,
+            &tree.leaf(LeafPurpose::Timelock).unwrap().script,
+            &other.output_key().to_inner(),
+        ));
+    }
+
+    #[test]
+    fn test_control_block_missing_leaf() {
+        let tree = savings_tree(owner_keypair(0).x_only_public_key().0, 144);
+        let err = control_block(&tree, LeafPurpose::Multisig).unwrap_err();
+        assert_eq!(err.code(), CoreError::InvalidInput(String::new()).code());
+    }
+
+    #[test]
+    fn test_build_tree_rejects_duplicate_purpose() {
+        let tree = savings_tree(owner_keypair(0).x_only_public_key().0, 144);
+        let mut leaves = tree.leaves().to_vec();
+        leaves.push(leaves[0].clone());
+        assert!(build_tree(leaves, tree.internal_key(), TreeVersion::V1).is_err());
+    }
+
+    fn four_leaves() -> Vec<VaultLeaf> {
+        let owner = owner_keypair(0).x_only_public_key().0;
+        let recovery = recovery_key();
+        let timelock = timelock_leaf(&owner, 144, crate::vault::DelayUnit::Blocks, TreeVersion::V2).unwrap();
+        [
+            (LeafPurpose::Timelock, timelock.script),
+            (LeafPurpose::Emergency, emergency_leaf(&recovery)),
+            (LeafPurpose::AbsoluteLock, cltv_leaf(&recovery, 900_000).unwrap()),
+            (LeafPurpose::Metadata, commitment_leaf(&[0x11; 32])),
+        ]
+        .into_iter()
+        .map(|(purpose, script)| VaultLeaf { purpose, script, version: LeafVersion::TapScript })
+        .collect()
+    }
+
+    fn control_block_sizes(spend_info: &TaprootSpendInfo, leaves: &[VaultLeaf]) -> Vec<usize> {
+        leaves
+            .iter()
+            .map(|leaf| spend_info.control_block(&(leaf.script.clone(), leaf.version)).unwrap().size())
+            .collect()
+    }
+
+    #[test]
+    fn test_weighted_tree_shortens_timelock_control_block() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = crate::keys::unspendable_internal_key();
+        let leaves = four_leaves();
+
+        let balanced: Vec<LeafSpec> = leaves
+            .iter()
+            .map(|leaf| LeafSpec { script: leaf.script.clone(), weight: DEFAULT_LEAF_WEIGHT })
+            .collect();
+        let balanced = huffman_builder(&balanced).unwrap().finalize(&secp, internal_key).unwrap();
+        assert_eq!(control_block_sizes(&balanced, &leaves), vec![97, 97, 97, 97]);
+
+        let tree = build_tree(leaves.clone(), internal_key, TreeVersion::V4).unwrap();
+        let sizes = control_block_sizes(tree.spend_info(), &leaves);
+        assert_eq!(sizes[0], 65);
+        let mut rest = sizes[1..].to_vec();
+        rest.sort();
+        assert_eq!(rest, vec![97, 129, 129]);
+        for leaf in tree.leaves() {
+            let cb = control_block(&tree, leaf.purpose).unwrap();
+            assert!(verify_control_block(&cb, &leaf.script, &tree.output_key().to_inner()));
+        }
+    }
+
+    #[test]
+    fn test_trees_before_v4_keep_their_placement() {
+        let secp = Secp256k1::verification_only();
+        let internal_key = crate::keys::unspendable_internal_key();
+        let leaves = four_leaves();
+
+        // The placement of every tree built before leaves were weighted
+        let unweighted = TaprootBuilder::with_huffman_tree(leaves.iter().map(|leaf| (1, leaf.script.clone())))
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+        for version in [TreeVersion::V1, TreeVersion::V2, TreeVersion::V3] {
+            let tree = build_tree(leaves.clone(), internal_key, version).unwrap();
+            assert_eq!(tree.merkle_root(), unweighted.merkle_root(), "{:?}", version);
+        }
+        let weighted = build_tree(leaves, internal_key, TreeVersion::V4).unwrap();
+        assert_ne!(weighted.merkle_root(), unweighted.merkle_root());
+    }
+
+    #[test]
+    fn test_weighted_tree_ignores_leaf_order() {
+        let internal_key = crate::keys::unspendable_internal_key();
+        let leaves = four_leaves();
+        let root = build_tree(leaves.clone(), internal_key, TreeVersion::V4).unwrap().merkle_root();
+
+        let mut reversed = leaves.clone();
+        reversed.reverse();
+        assert_eq!(build_tree(reversed, internal_key, TreeVersion::V4).unwrap().merkle_root(), root);
+        let rotated = [&leaves[2..], &leaves[..2]].concat();
+        assert_eq!(build_tree(rotated, internal_key, TreeVersion::V4).unwrap().merkle_root(), root);
+    }
+
+    #[test]
+    fn test_huffman_builder_rejects_empty_tree() {
+        let err = huffman_builder(&[]).unwrap_err();
+        assert_eq!(err.code(), CoreError::ScriptError(String::new()).code());
+    }
+
+    #[test]
+    fn test_regtest_timelock_script_path_spend() {
+        let secp = Secp256k1::new();
+        let delay_blocks = 10;
+        let owner = owner_keypair(3);
+        let tree = savings_tree(owner.x_only_public_key().0, delay_blocks);
+        assert!(tree.address(Network::Regtest).to_string().starts_with("bcrt1p"));
+
+        let prevout = TxOut {
+            value: 100_000,
+            script_pubkey: tree.script_pubkey(),
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_str(&"11".repeat(32)).unwrap(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_height(delay_blocks as u16),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: tree.script_pubkey(),
+            }],
+        };
+
+        let leaf = tree.leaf(LeafPurpose::Timelock).unwrap().clone();
+        let leaf_hash = tree.leaf_hash(LeafPurpose::Timelock).unwrap();
+        let sighash = SighashCache::new(&tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(std::slice::from_ref(&prevout)),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .unwrap();
+        let msg = Message::from_slice(sighash.as_ref()).unwrap();
+        let sig = secp.sign_schnorr(&msg, &owner);
+
+        let cb = control_block(&tree, LeafPurpose::Timelock).unwrap();
+        let mut witness = Witness::new();
+        witness.push(sig.as_ref());
+        witness.push(leaf.script.as_bytes());
+        witness.push(cb.serialize());
+        tx.input[0].witness = witness;
+
+        // Consensus checks for a BIP341 script-path spend of the timelock leaf
+        let witness = &tx.input[0].witness;
+        let script = ScriptBuf::from_bytes(witness.nth(1).unwrap().to_vec());
+        let parsed_cb = ControlBlock::decode(witness.nth(2).unwrap()).unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..]).unwrap();
+        assert!(verify_control_block(&parsed_cb, &script, &output_key));
+
+        // Leaf: <delay> CSV DROP <key> CHECKSIG
+        let mut ops = script.instructions();
+        let _delay = ops.next();
+        let _csv = ops.next();
+        let _drop = ops.next();
+        let key_push = ops.next().unwrap().unwrap();
+        let key = XOnlyPublicKey::from_slice(key_push.push_bytes().unwrap().as_bytes()).unwrap();
+        let parsed_sig = schnorr::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        assert!(secp.verify_schnorr(&parsed_sig, &msg, &key).is_ok());
+
+        let relative = tx.input[0].sequence.to_relative_lock_time().unwrap();
+        let height = |h: u16| bitcoin::relative::Height::from(h);
+        assert!(relative.is_satisfied_by_height(height(delay_blocks as u16)).unwrap());
+        assert!(!relative.is_satisfied_by_height(height(delay_blocks as u16 - 1)).unwrap());
+    }
+}
@@ -0,0 +1,89 @@
+//! Fixtures shared by the crate's unit tests and the integration tests,
+//! which include this file as their `common` module
+
+#![allow(dead_code)]
+
+use serde_json::Value;
+use vault_core::vault::{
+    AbsoluteLockUnit, DelayUnit, HashlockRecovery, MultisigRecovery, Network, RecoveryType, Vault, VaultBuilder, VaultConfig,
+    VaultTemplate,
+};
+
+/// Owner account xpub on mainnet, the BIP32 test vector 1 master key
+pub const OWNER_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+/// Recovery account xpub on mainnet, the BIP32 test vector 2 master key
+pub const RECOVERY_XPUB: &str = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+/// A third mainnet xpub, for cosigner and heir keys
+pub const THIRD_XPUB: &str = "xpub661MyMwAqRbcEZVB4dScxMAdx6d4nFc9nvyvH3v4gJL378CSRZiYmhRoP7mBy6gSPSCYk6SzXPTf3ND1cZAceL7SfJ1Z3GC8vBgp2epUt13";
+/// Owner account xpub for the test networks
+pub const OWNER_TPUB: &str = "tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp";
+/// Recovery account xpub for the test networks
+pub const RECOVERY_TPUB: &str = "tpubD6NzVbkrYhZ4XJDrzRvuxHEyQaPd1mwwdDofEJwekX18tAdsqeKfxss79AJzg1431FybXg5rfpTrJF4iAhyR7RubberdzEQXiRmXGADH2eA";
+
+/// Mainnet savings vault over `OWNER_XPUB` and `RECOVERY_XPUB`, with no
+/// policy options and the default tree version
+pub fn mainnet_config() -> VaultConfig {
+    VaultConfig::new(Network::Mainnet, VaultTemplate::savings(), OWNER_XPUB, RECOVERY_XPUB)
+}
+
+/// Regtest spending vault over `OWNER_TPUB` and `RECOVERY_TPUB`, with no
+/// policy options and the default tree version
+pub fn regtest_config() -> VaultConfig {
+    VaultConfig::new(Network::Regtest, VaultTemplate::spending(), OWNER_TPUB, RECOVERY_TPUB)
+}
+
+/// Builder for a `regtest_config()` vault of `template`
+pub fn regtest_builder(template: VaultTemplate) -> VaultBuilder {
+    VaultBuilder::from_config(&VaultConfig { template, ..regtest_config() })
+}
+
+/// `regtest_builder()` vault at `index`
+pub fn regtest_vault(template: VaultTemplate, index: u32) -> Vault {
+    regtest_builder(template).index(index).build().unwrap()
+}
+
+/// Fields of a `VaultTemplate::Custom`, defaulting to a 144 block
+/// timelock-only template so tests spell out only what they exercise
+#[derive(Debug, Clone)]
+pub struct CustomFields {
+    pub delay_blocks: u32,
+    pub delay_unit: DelayUnit,
+    pub recovery_type: RecoveryType,
+    pub multisig: Option<MultisigRecovery>,
+    pub key_path_enabled: bool,
+    pub absolute_lock: Option<u32>,
+    pub absolute_lock_unit: AbsoluteLockUnit,
+    pub hashlock: Option<HashlockRecovery>,
+}
+
+impl Default for CustomFields {
+    fn default() -> Self {
+        CustomFields {
+            delay_blocks: 144,
+            delay_unit: DelayUnit::Blocks,
+            recovery_type: RecoveryType::TimelockOnly,
+            multisig: None,
+            key_path_enabled: false,
+            absolute_lock: None,
+            absolute_lock_unit: AbsoluteLockUnit::Height,
+            hashlock: None,
+        }
+    }
+}
+
+/// `VaultTemplate::Custom` of `fields`
+pub fn custom_template(fields: CustomFields) -> VaultTemplate {
+    let CustomFields { delay_blocks, delay_unit, recovery_type, multisig, key_path_enabled, absolute_lock, absolute_lock_unit, hashlock } =
+        fields;
+    VaultTemplate::Custom { delay_blocks, delay_unit, recovery_type, multisig, key_path_enabled, absolute_lock, absolute_lock_unit, hashlock }
+}
+
+/// `data` of a success envelope, or the error object of a failure
+pub fn payload(json: &str) -> Value {
+    let mut response: Value = serde_json::from_str(json).unwrap();
+    if response["error"] == false {
+        assert!(response["ffi_schema"].is_u64(), "{}", response);
+        return response["data"].take();
+    }
+    response
+}
@@ -0,0 +1,225 @@
+//! Fixed vectors for the bindings' test suites
+//!
+//! `generate()` derives addresses, metadata, a descriptor and an unvault
+//! PSBT from hard-coded keys, outpoints and amounts, so the Kotlin and
+//! Swift suites can check their own results against one document instead
+//! of hand-rolled fixtures. Nothing here reads a clock or an RNG: the
+//! output is the same on every run. Built with the `test-vectors` feature.
+
+use std::str::FromStr;
+
+use bitcoin::{Address, OutPoint, Txid};
+use serde::Serialize;
+
+use crate::error::CoreResult;
+use crate::keys;
+use crate::taproot::TreeVersion;
+use crate::vault::{fees, psbt, Network, Vault, VaultBuilder, VaultTemplate};
+
+/// Owner account xpub: the BIP32 test vector 1 master
+pub const OWNER_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+/// Recovery account xpub: the BIP32 test vector 2 master
+pub const RECOVERY_XPUB: &str = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+
+/// Tree version of every vector: the first with both a descriptor and a
+/// NUMS internal key per vault index
+///
+/// Pinned rather than `TreeVersion::LATEST`, so a later layout gets new
+/// vectors on purpose instead of moving these.
+const TREE_VERSION: TreeVersion = TreeVersion::V3;
+
+/// Vault indexes with an address in every network's vectors
+const ADDRESS_INDICES: u32 = 5;
+
+/// Outpoint, value and fee rate of the unvault vector's input
+const UNVAULT_TXID: &str = "abababababababababababababababababababababababababababababababab";
+const UNVAULT_AMOUNT_SATS: u64 = 100_000;
+const UNVAULT_FEE_RATE: u64 = 2;
+
+/// Destination of the unvault vector: the BIP173 P2WPKH example
+const UNVAULT_DESTINATION: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+/// Every vector, as returned by `vault_generate_test_vectors()`
+#[derive(Debug, Clone, Serialize)]
+pub struct TestVectors {
+    /// Template of the address, descriptor and unvault vectors
+    pub template: VaultTemplate,
+    /// Tree version of every vault in the vectors
+    pub tree_version: TreeVersion,
+    pub networks: Vec<NetworkVectors>,
+    pub metadata: Vec<MetadataVector>,
+    /// Regtest descriptor of the vault at index 0
+    pub descriptor: String,
+    pub unvault: UnvaultVector,
+}
+
+/// Keys and deposit addresses of the vault on one network
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkVectors {
+    pub network: Network,
+    /// `OWNER_XPUB` with this network's version bytes
+    pub owner_xpub: String,
+    /// `RECOVERY_XPUB` with this network's version bytes
+    pub recovery_xpub: String,
+    /// Addresses at vault indexes `0..ADDRESS_INDICES`
+    pub addresses: Vec<String>,
+}
+
+/// Encoded metadata of a regtest vault at index 0
+#[derive(Debug, Clone, Serialize)]
+pub struct MetadataVector {
+    pub template: VaultTemplate,
+    pub metadata_hex: String,
+}
+
+/// Unsigned regtest unvault of the vault at index 0, with its digests
+#[derive(Debug, Clone, Serialize)]
+pub struct UnvaultVector {
+    pub outpoint: String,
+    pub amount_sats: u64,
+    pub destination: String,
+    pub fee_rate: u64,
+    pub psbt_base64: String,
+    /// BIP341 digests of the timelock leaf, one per key
+    pub sighashes: Vec<psbt::SighashInfo>,
+}
+
+/// Generate every vector
+pub fn generate() -> CoreResult<TestVectors> {
+    let template = VaultTemplate::Savings { delay_blocks: 144 };
+
+    let networks = Network::ALL
+        .iter()
+        .map(|&network| network_vectors(&template, network))
+        .collect::<CoreResult<Vec<_>>>()?;
+
+    let metadata = [
+        VaultTemplate::Savings { delay_blocks: 144 },
+        VaultTemplate::spending(),
+        VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 },
+    ]
+    .into_iter()
+    .map(|template| {
+        let vault = vault(template.clone(), Network::Regtest, 0)?;
+        Ok(MetadataVector {
+            template,
+            metadata_hex: hex::encode(vault.metadata().to_bytes()),
+        })
+    })
+    .collect::<CoreResult<Vec<_>>>()?;
+
+    let regtest = vault(template.clone(), Network::Regtest, 0)?;
+    Ok(TestVectors {
+        template,
+        tree_version: TREE_VERSION,
+        networks,
+        metadata,
+        descriptor: regtest.descriptor()?,
+        unvault: unvault_vector(&regtest)?,
+    })
+}
+
+/// `xpub` with the version bytes of `network`
+fn xpub_for(xpub: &str, network: Network) -> CoreResult<String> {
+    let mut xpub = keys::parse_xpub(xpub, Network::Mainnet)?;
+    xpub.network = network.into();
+    Ok(xpub.to_string())
+}
+
+fn vault(template: VaultTemplate, network: Network, index: u32) -> CoreResult<Vault> {
+    VaultBuilder::new()
+        .template(template)
+        .owner_xpub(xpub_for(OWNER_XPUB, network)?)
+        .recovery_xpub(xpub_for(RECOVERY_XPUB, network)?)
+        .network(network)
+        .index(index)
+        .tree_version(TREE_VERSION)
+        .build()
+}
+
+fn network_vectors(template: &VaultTemplate, network: Network) -> CoreResult<NetworkVectors> {
+    let vault = vault(template.clone(), network, 0)?;
+    let addresses = (0..ADDRESS_INDICES)
+        .map(|index| Ok(vault.tree_at(index)?.address(network).to_string()))
+        .collect::<CoreResult<Vec<_>>>()?;
+
+    Ok(NetworkVectors {
+        network,
+        owner_xpub: xpub_for(OWNER_XPUB, network)?,
+        recovery_xpub: xpub_for(RECOVERY_XPUB, network)?,
+        addresses,
+    })
+}
+
+fn unvault_vector(vault: &Vault) -> CoreResult<UnvaultVector> {
+    let txid = Txid::from_str(UNVAULT_TXID).expect("valid txid");
+    let outpoint = OutPoint::new(txid, 0);
+    let destination = Address::from_str(UNVAULT_DESTINATION)
+        .expect("valid address")
+        .require_network(bitcoin::Network::Regtest)
+        .expect("regtest address");
+
+    // No block height, so nLockTime is 0 rather than randomized
+    let psbt = psbt::build_unvault(
+        vault.utxo(outpoint, UNVAULT_AMOUNT_SATS),
+        destination,
+        UNVAULT_FEE_RATE,
+        &vault.metadata(),
+        psbt::SpendOptions {
+            approved: vault.destinations(),
+            dust: vault.dust_policy(),
+            ..Default::default()
+        },
+    )?;
+    let sighashes = psbt::sighashes(&psbt, fees::SpendPath::TimelockLeaf)?;
+
+    Ok(UnvaultVector {
+        outpoint: outpoint.to_string(),
+        amount_sats: UNVAULT_AMOUNT_SATS,
+        destination: UNVAULT_DESTINATION.to_string(),
+        fee_rate: UNVAULT_FEE_RATE,
+        psbt_base64: psbt::to_base64(&psbt),
+        sighashes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors_are_deterministic() {
+        let first = serde_json::to_string(&generate().unwrap()).unwrap();
+        let second = serde_json::to_string(&generate().unwrap()).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_vectors_contents() {
+        let vectors = generate().unwrap();
+        assert_eq!(vectors.networks.len(), Network::ALL.len());
+        for network in &vectors.networks {
+            assert_eq!(network.addresses.len(), ADDRESS_INDICES as usize);
+        }
+        assert_eq!(vectors.networks[0].owner_xpub, OWNER_XPUB);
+        assert!(vectors.networks[0].addresses[0].starts_with("bc1p"));
+        assert!(vectors.networks[3].owner_xpub.starts_with("tpub"));
+        assert!(vectors.networks[3].addresses[0].starts_with("bcrt1p"));
+        assert!(vectors.descriptor.starts_with("tr("));
+
+        let psbt = psbt::parse_any(&vectors.unvault.psbt_base64).unwrap();
+        assert_eq!(psbt.unsigned_tx.lock_time, bitcoin::absolute::LockTime::ZERO);
+        assert_eq!(vectors.unvault.sighashes.len(), 1);
+
+        // Pinned, so a change that moves the vectors fails here before the bindings
+        assert_eq!(
+            vectors.networks[0].addresses[0],
+            "bc1pwh9ktspkxxzlv0cqjeq4g77len7yt9c5xryndwrhtshwwazj0y8s5tymm2"
+        );
+        assert_eq!(
+            vectors.unvault.sighashes[0].message,
+            "0d3119be1b97fb41b108a166a9731231d31a94adc1c50309f5f009eda075113e"
+        );
+    }
+}
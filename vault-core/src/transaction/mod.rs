@@ -11,6 +11,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::CoreError;
 use crate::keys;
+use crate::vault::{fees, policy};
 use crate::vault::{Network, RecoveryType, VaultMetadata, VaultTemplate};
 
 /// Spend path type
@@ -103,6 +104,9 @@ pub struct FinalizedTx {
     pub txid: String,
     /// Transaction virtual size in vbytes
     pub vsize: u64,
+    /// Relay policy rules the transaction breaks, so that it may not propagate
+    #[serde(default)]
+    pub warnings: Vec<fees::StandardnessViolation>,
 }
 
 // ═══════════════════════════════════════════════════════════════════
@@ -129,6 +133,7 @@ pub fn build_delayed_spend_psbt(
     let secp = Secp256k1::new();
     let btc_network: bitcoin::Network = vault.network.into();
     let delay_blocks = vault.template.delay_blocks();
+    let sequence = vault.template.sequence()?;
 
     // Derive keys
     let primary_key = keys::derive_child_pubkey(&vault.primary_xpub, vault.vault_index, vault.network)?;
@@ -138,7 +143,7 @@ pub fn build_delayed_spend_psbt(
     };
 
     // Build the spending script (same as used in address generation)
-    let spending_script = build_spending_script(&primary_key, delay_blocks);
+    let spending_script = build_spending_script(&primary_key, sequence);
 
     // Build the metadata script (needed for the full script tree)
     let recovery_type = match &vault.template {
@@ -156,10 +161,15 @@ pub fn build_delayed_spend_psbt(
         version: 1,
         template_id: vault.template.template_id().to_string(),
         delay_blocks,
+        delay_unit: vault.template.delay_unit(),
         destination_indices: vec![],
         recovery_type,
         created_at_block: 0,
         vault_index: vault.vault_index,
+        key_path_enabled: false,
+        heirs: None,
+        whitelist_delay: None,
+        tlv_records: Default::default(),
     };
     let metadata_script = build_metadata_script(&metadata);
 
@@ -189,12 +199,7 @@ pub fn build_delayed_spend_psbt(
     let script_pubkey = vault_address.script_pubkey();
 
     // Parse destination address
-    let dest_address = intent
-        .destination
-        .parse::<Address<bitcoin::address::NetworkUnchecked>>()
-        .map_err(|e| CoreError::InvalidAddress(format!("Invalid destination: {}", e)))?
-        .require_network(btc_network)
-        .map_err(|e| CoreError::InvalidAddress(format!("Network mismatch: {}", e)))?;
+    let dest_address = policy::validate_address(&intent.destination, vault.network)?;
 
     // Build transaction inputs
     let total_input_sats: u64 = utxos.iter().map(|u| u.amount_sats).sum();
@@ -209,7 +214,7 @@ pub fn build_delayed_spend_psbt(
             TxIn {
                 previous_output: OutPoint::new(txid, utxo.vout),
                 script_sig: ScriptBuf::new(),
-                sequence: Sequence::from_height(delay_blocks as u16),
+                sequence,
                 witness: Witness::default(),
             }
         })
@@ -265,7 +270,7 @@ pub fn build_delayed_spend_psbt(
     }
 
     // Serialize to base64
-    let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(&psbt.serialize());
+    let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(psbt.serialize());
 
     Ok(PsbtResult {
         psbt_base64,
@@ -310,6 +315,7 @@ pub fn build_emergency_psbt(
     let secp = Secp256k1::new();
     let btc_network: bitcoin::Network = vault.network.into();
     let delay_blocks = vault.template.delay_blocks();
+    let sequence = vault.template.sequence()?;
 
     // Derive keys
     let primary_key = keys::derive_child_pubkey(&vault.primary_xpub, vault.vault_index, vault.network)?;
@@ -320,7 +326,7 @@ pub fn build_emergency_psbt(
     )?;
 
     // Build script tree (same as address generation) to get merkle root
-    let spending_script = build_spending_script(&primary_key, delay_blocks);
+    let spending_script = build_spending_script(&primary_key, sequence);
     let recovery_type = match &vault.template {
         VaultTemplate::Custom { recovery_type, .. } => *recovery_type,
         _ => RecoveryType::EmergencyKey,
@@ -329,10 +335,15 @@ pub fn build_emergency_psbt(
         version: 1,
         template_id: vault.template.template_id().to_string(),
         delay_blocks,
+        delay_unit: vault.template.delay_unit(),
         destination_indices: vec![],
         recovery_type,
         created_at_block: 0,
         vault_index: vault.vault_index,
+        key_path_enabled: false,
+        heirs: None,
+        whitelist_delay: None,
+        tlv_records: Default::default(),
     };
     let metadata_script = build_metadata_script(&metadata);
 
@@ -356,11 +367,7 @@ pub fn build_emergency_psbt(
     let script_pubkey = vault_address.script_pubkey();
 
     // Parse destination
-    let dest_address = destination
-        .parse::<Address<bitcoin::address::NetworkUnchecked>>()
-        .map_err(|e| CoreError::InvalidAddress(format!("Invalid destination: {}", e)))?
-        .require_network(btc_network)
-        .map_err(|e| CoreError::InvalidAddress(format!("Network mismatch: {}", e)))?;
+    let dest_address = policy::validate_address(destination, vault.network)?;
 
     // Build inputs (no sequence restriction for key-path spend)
     let total_input_sats: u64 = utxos.iter().map(|u| u.amount_sats).sum();
@@ -417,7 +424,7 @@ pub fn build_emergency_psbt(
         };
     }
 
-    let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(&psbt.serialize());
+    let psbt_base64 = base64::engine::general_purpose::STANDARD.encode(psbt.serialize());
 
     Ok(PsbtResult {
         psbt_base64,
@@ -455,7 +462,7 @@ pub fn verify_psbt_policy(
     let mut warnings = Vec::new();
     let mut errors = Vec::new();
 
-    let delay_blocks = vault.template.delay_blocks();
+    let expected_delayed_seq = vault.template.sequence()?;
 
     // Check transaction version
     if psbt.unsigned_tx.version != 2 {
@@ -467,7 +474,6 @@ pub fn verify_psbt_policy(
         let seq = input.sequence;
 
         // For delayed spend, check CSV sequence
-        let expected_delayed_seq = Sequence::from_height(delay_blocks as u16);
         let is_delayed = seq == expected_delayed_seq;
         let is_emergency = seq == Sequence::ENABLE_RBF_NO_LOCKTIME;
 
@@ -539,11 +545,13 @@ pub fn finalize_psbt(signed_psbt_b64: &str) -> Result<FinalizedTx, CoreError> {
     let tx_bytes = bitcoin::consensus::serialize(&tx);
     let tx_hex = hex::encode(&tx_bytes);
     let vsize = tx.vsize() as u64;
+    let warnings = fees::check_standardness(&tx)?.violations;
 
     Ok(FinalizedTx {
         tx_hex,
         txid,
         vsize,
+        warnings,
     })
 }
 
@@ -552,11 +560,11 @@ pub fn finalize_psbt(signed_psbt_b64: &str) -> Result<FinalizedTx, CoreError> {
 // ═══════════════════════════════════════════════════════════════════
 
 /// Build spending script (same as taproot module, duplicated for self-containment)
-fn build_spending_script(primary_key: &XOnlyPublicKey, delay_blocks: u32) -> ScriptBuf {
+fn build_spending_script(primary_key: &XOnlyPublicKey, sequence: Sequence) -> ScriptBuf {
     Builder::new()
         .push_x_only_key(primary_key)
         .push_opcode(OP_CHECKSIGVERIFY)
-        .push_sequence(Sequence::from_height(delay_blocks as u16))
+        .push_sequence(sequence)
         .push_opcode(bitcoin::blockdata::opcodes::all::OP_CSV)
         .into_script()
 }
@@ -579,14 +587,13 @@ fn build_metadata_script(metadata: &VaultMetadata) -> ScriptBuf {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    const TEST_XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+    use crate::test_common::OWNER_XPUB;
 
     fn test_vault_config(emergency: bool) -> VaultConfig {
         VaultConfig {
-            primary_xpub: TEST_XPUB.to_string(),
+            primary_xpub: OWNER_XPUB.to_string(),
             emergency_xpub: if emergency {
-                Some(TEST_XPUB.to_string())
+                Some(OWNER_XPUB.to_string())
             } else {
                 None
             },
@@ -782,6 +789,7 @@ mod tests {
             &vault.template,
             vault.vault_index + 1, // Different index to avoid self-send
             vault.network,
+            crate::vault::MetadataMode::Full,
         )
         .unwrap()
         .address
@@ -0,0 +1,348 @@
+//! Description of every vault template, for UI pickers
+//!
+//! Generated from `VaultTemplate` itself: each entry comes from a
+//! template instance holding its default parameters, so ids, delays and
+//! recovery paths can't drift from the code that builds the trees.
+
+use bitcoin::absolute::LOCK_TIME_THRESHOLD;
+use serde::Serialize;
+
+use crate::taproot::{MAX_CSV_DELAY_BLOCKS, MAX_MULTISIG_KEYS};
+
+use super::{AbsoluteLockUnit, DelayUnit, RecoveryType, VaultTemplate, MAX_DEGRADING_KEYS, MAX_HEIRS};
+
+/// Default heir inactivity delay, about six months of blocks
+pub const DEFAULT_INACTIVITY_BLOCKS: u32 = 26_280;
+
+/// Default degrading stages: all three signers at once, two after about
+/// four weeks, any one after about six months
+pub const DEFAULT_DEGRADING_STAGES: [(u32, u8); 3] = [(0, 3), (4_032, 2), (26_208, 1)];
+
+/// One template as listed by `VaultTemplate::catalog()`
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateInfo {
+    /// `VaultTemplate::template_id()` of the template
+    pub template_id: &'static str,
+    /// Value of the `"type"` tag selecting the template in JSON
+    #[serde(rename = "type")]
+    pub type_tag: &'static str,
+    /// Human-readable name
+    pub name: &'static str,
+    /// Delay used when `delay_blocks` (or its equivalent) is not given
+    pub delay_blocks: u32,
+    /// Recovery paths the template can produce
+    pub recovery_types: Vec<RecoveryType>,
+    /// JSON fields the user may set, besides `"type"`
+    pub parameters: Vec<TemplateParameter>,
+}
+
+/// A user-configurable field of a template's JSON form
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateParameter {
+    /// JSON field name
+    pub name: &'static str,
+    /// Whether the field must be present
+    pub required: bool,
+    #[serde(flatten)]
+    pub kind: ParameterKind,
+}
+
+/// Value type and bounds of a `TemplateParameter`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ParameterKind {
+    /// Integer within `min..=max`
+    Integer { min: u32, max: u32, default: u32 },
+    Boolean { default: bool },
+    /// One of `options`, as its JSON string
+    Choice { options: Vec<&'static str>, default: &'static str },
+    /// List of `min..=max` account xpubs
+    XpubList { min: usize, max: usize },
+    /// `{"threshold":k,"cosigners":[...]}` with up to `max_cosigners` xpubs
+    Multisig { max_cosigners: usize },
+    /// Block height below `threshold`, or a Unix timestamp from it on;
+    /// absent by default
+    LockTime { threshold: u32 },
+    /// `{"hash":"<sha256 hex>","service_xpub":"..."}`; absent by default
+    Hashlock,
+    /// `[[delay, threshold], ...]` with delays up to `max_delay` and
+    /// thresholds up to `max_keys`
+    Stages { max_delay: u32, max_keys: u8, default: Vec<(u32, u8)> },
+}
+
+impl VaultTemplate {
+    /// Every template with its defaults and configurable parameters
+    pub fn catalog() -> Vec<TemplateInfo> {
+        Self::defaults().iter().map(VaultTemplate::info).collect()
+    }
+
+    /// One instance of each template, holding its default parameters
+    ///
+    /// Key lists are left empty; they have no default.
+    pub(crate) fn defaults() -> Vec<VaultTemplate> {
+        vec![
+            VaultTemplate::savings(),
+            VaultTemplate::spending(),
+            VaultTemplate::Custom {
+                delay_blocks: super::default_savings_delay(),
+                delay_unit: DelayUnit::Blocks,
+                recovery_type: RecoveryType::EmergencyKey,
+                multisig: None,
+                key_path_enabled: false,
+                absolute_lock: None,
+                absolute_lock_unit: AbsoluteLockUnit::Height,
+                hashlock: None,
+            },
+            VaultTemplate::Inheritance {
+                heir_threshold: 1,
+                heir_count: 1,
+                inactivity_blocks: DEFAULT_INACTIVITY_BLOCKS,
+                heirs: Vec::new(),
+            },
+            VaultTemplate::DualDelay {
+                whitelist_delay: super::default_spending_delay(),
+                open_delay: super::default_savings_delay(),
+            },
+            VaultTemplate::Degrading {
+                keys: 3,
+                stages: DEFAULT_DEGRADING_STAGES.to_vec(),
+                cosigners: Vec::new(),
+            },
+        ]
+    }
+
+    fn info(&self) -> TemplateInfo {
+        let (type_tag, name, recovery_types, parameters) = match self {
+            VaultTemplate::Savings { delay_blocks } => (
+                "savings",
+                "Savings",
+                vec![RecoveryType::EmergencyKey],
+                vec![delay("delay_blocks", false, *delay_blocks)],
+            ),
+            VaultTemplate::Spending { delay_blocks } => (
+                "spending",
+                "Spending",
+                vec![RecoveryType::EmergencyKey],
+                vec![delay("delay_blocks", false, *delay_blocks)],
+            ),
+            VaultTemplate::Custom { delay_blocks, key_path_enabled, .. } => (
+                "custom",
+                "Custom",
+                vec![RecoveryType::EmergencyKey, RecoveryType::TimelockOnly, RecoveryType::MultiSig],
+                vec![
+                    delay("delay_blocks", true, *delay_blocks),
+                    TemplateParameter {
+                        name: "delay_unit",
+                        required: false,
+                        kind: ParameterKind::Choice {
+                            options: vec!["blocks", "time_units_512s"],
+                            default: "blocks",
+                        },
+                    },
+                    TemplateParameter {
+                        name: "recovery_type",
+                        required: true,
+                        kind: ParameterKind::Choice {
+                            options: RecoveryType::ALL.map(RecoveryType::name).to_vec(),
+                            default: RecoveryType::EmergencyKey.name(),
+                        },
+                    },
+                    TemplateParameter {
+                        name: "multisig",
+                        required: false,
+                        kind: ParameterKind::Multisig { max_cosigners: MAX_MULTISIG_KEYS },
+                    },
+                    TemplateParameter {
+                        name: "key_path_enabled",
+                        required: false,
+                        kind: ParameterKind::Boolean { default: *key_path_enabled },
+                    },
+                    TemplateParameter {
+                        name: "absolute_lock",
+                        required: false,
+                        kind: ParameterKind::LockTime { threshold: LOCK_TIME_THRESHOLD },
+                    },
+                    TemplateParameter {
+                        name: "absolute_lock_unit",
+                        required: false,
+                        kind: ParameterKind::Choice {
+                            options: vec!["height", "seconds"],
+                            default: "height",
+                        },
+                    },
+                    TemplateParameter {
+                        name: "hashlock",
+                        required: false,
+                        kind: ParameterKind::Hashlock,
+                    },
+                ],
+            ),
+            VaultTemplate::Inheritance { heir_threshold, heir_count, inactivity_blocks, .. } => (
+                "inheritance",
+                "Inheritance",
+                vec![RecoveryType::TimelockOnly],
+                vec![
+                    heir_bound("heir_threshold", *heir_threshold),
+                    heir_bound("heir_count", *heir_count),
+                    delay("inactivity_blocks", true, *inactivity_blocks),
+                    TemplateParameter {
+                        name: "heirs",
+                        required: true,
+                        kind: ParameterKind::XpubList { min: 1, max: MAX_HEIRS as usize },
+                    },
+                ],
+            ),
+            VaultTemplate::DualDelay { whitelist_delay, open_delay } => (
+                "dual_delay",
+                "Dual delay",
+                vec![RecoveryType::EmergencyKey],
+                vec![
+                    delay("whitelist_delay", true, *whitelist_delay),
+                    delay("open_delay", true, *open_delay),
+                ],
+            ),
+            VaultTemplate::Degrading { keys, stages, .. } => (
+                "degrading",
+                "Degrading multisig",
+                vec![RecoveryType::MultiSig],
+                vec![
+                    TemplateParameter {
+                        name: "keys",
+                        required: true,
+                        kind: ParameterKind::Integer {
+                            min: 3,
+                            max: MAX_DEGRADING_KEYS as u32,
+                            default: *keys as u32,
+                        },
+                    },
+                    TemplateParameter {
+                        name: "stages",
+                        required: true,
+                        kind: ParameterKind::Stages {
+                            max_delay: MAX_CSV_DELAY_BLOCKS,
+                            max_keys: MAX_DEGRADING_KEYS,
+                            default: stages.clone(),
+                        },
+                    },
+                    TemplateParameter {
+                        name: "cosigners",
+                        required: true,
+                        kind: ParameterKind::XpubList { min: 1, max: MAX_DEGRADING_KEYS as usize - 2 },
+                    },
+                ],
+            ),
+        };
+
+        TemplateInfo {
+            template_id: self.template_id(),
+            type_tag,
+            name,
+            delay_blocks: self.delay_blocks(),
+            recovery_types,
+            parameters,
+        }
+    }
+}
+
+fn delay(name: &'static str, required: bool, default: u32) -> TemplateParameter {
+    TemplateParameter {
+        name,
+        required,
+        kind: ParameterKind::Integer { min: 1, max: MAX_CSV_DELAY_BLOCKS, default },
+    }
+}
+
+fn heir_bound(name: &'static str, default: u8) -> TemplateParameter {
+    TemplateParameter {
+        name,
+        required: true,
+        kind: ParameterKind::Integer { min: 1, max: MAX_HEIRS as u32, default: default as u32 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position of the template's variant; a new variant fails to compile
+    /// here until it is counted
+    fn variant(template: &VaultTemplate) -> usize {
+        match template {
+            VaultTemplate::Savings { .. } => 0,
+            VaultTemplate::Spending { .. } => 1,
+            VaultTemplate::Custom { .. } => 2,
+            VaultTemplate::Inheritance { .. } => 3,
+            VaultTemplate::DualDelay { .. } => 4,
+            VaultTemplate::Degrading { .. } => 5,
+        }
+    }
+    const VARIANTS: usize = 6;
+
+    /// Template JSON with every parameter at its default
+    fn default_json(info: &TemplateInfo) -> serde_json::Value {
+        let mut fields = serde_json::Map::new();
+        fields.insert("type".to_string(), info.type_tag.into());
+        for parameter in &info.parameters {
+            let value = match &parameter.kind {
+                ParameterKind::Integer { default, .. } => serde_json::json!(default),
+                ParameterKind::Boolean { default } => serde_json::json!(default),
+                ParameterKind::Choice { default, .. } => serde_json::json!(default),
+                ParameterKind::XpubList { min, .. } => serde_json::json!(vec!["xpub"; *min]),
+                ParameterKind::Stages { default, .. } => serde_json::json!(default),
+                ParameterKind::Multisig { .. }
+                | ParameterKind::LockTime { .. }
+                | ParameterKind::Hashlock => continue,
+            };
+            fields.insert(parameter.name.to_string(), value);
+        }
+        serde_json::Value::Object(fields)
+    }
+
+    #[test]
+    fn test_catalog_covers_every_template() {
+        let catalog = VaultTemplate::catalog();
+        let mut variants: Vec<usize> = VaultTemplate::defaults().iter().map(variant).collect();
+        variants.sort_unstable();
+        assert_eq!(variants, (0..VARIANTS).collect::<Vec<_>>());
+
+        let mut ids: Vec<&str> = catalog.iter().map(|info| info.template_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), catalog.len());
+    }
+
+    #[test]
+    fn test_catalog_entries_deserialize() {
+        for info in VaultTemplate::catalog() {
+            let json = default_json(&info);
+            let template: VaultTemplate = serde_json::from_value(json.clone())
+                .unwrap_or_else(|e| panic!("{} does not deserialize: {}", json, e));
+            assert_eq!(template.template_id(), info.template_id);
+            assert_eq!(template.delay_blocks(), info.delay_blocks);
+            assert!(info.recovery_types.contains(&template.recovery_type()), "{}", info.template_id);
+        }
+    }
+
+    #[test]
+    fn test_catalog_json_shape() {
+        let json = serde_json::to_value(VaultTemplate::catalog()).unwrap();
+        assert_eq!(json[0]["template_id"], "savings_v1");
+        assert_eq!(json[0]["type"], "savings");
+        assert_eq!(json[0]["delay_blocks"], 1008);
+        assert_eq!(json[0]["recovery_types"], serde_json::json!(["emergency_key"]));
+        assert_eq!(
+            json[0]["parameters"][0],
+            serde_json::json!({
+                "name": "delay_blocks",
+                "required": false,
+                "kind": "integer",
+                "min": 1,
+                "max": 65535,
+                "default": 1008,
+            })
+        );
+        assert_eq!(json[2]["parameters"][2]["options"], json[2]["recovery_types"]);
+        assert_eq!(json[3]["parameters"][3]["kind"], "xpub_list");
+        assert_eq!(json[3]["parameters"][3]["max"], 15);
+    }
+}
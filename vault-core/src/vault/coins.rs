@@ -0,0 +1,414 @@
+//! Coin selection over vault UTXOs for unvault transactions
+
+use bitcoin::OutPoint;
+
+use crate::error::CoreError;
+use crate::taproot::LeafPurpose;
+use crate::vault::fees::{self, DustPolicy, P2TR_SCRIPT_PUBKEY_LEN};
+use crate::vault::psbt::VaultUtxo;
+
+/// Search budget for `BranchAndBound`, in visited nodes
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Size of a P2TR output: value (8) + script length (1) + scriptPubKey (34)
+const P2TR_OUTPUT_VSIZE: u64 = 8 + 1 + P2TR_SCRIPT_PUBKEY_LEN as u64;
+
+/// How `select` picks UTXOs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionStrategy {
+    /// Take UTXOs by descending effective value until the target is met
+    LargestFirst,
+    /// Search for a changeless combination, falling back to `LargestFirst`
+    BranchAndBound,
+}
+
+/// UTXOs chosen to fund an unvault, and how their value is split
+#[derive(Debug, Clone)]
+pub struct Selection {
+    /// Chosen UTXOs, in input order
+    pub utxos: Vec<VaultUtxo>,
+    /// Amount paid to the destination
+    pub target_sats: u64,
+    /// Sum of the chosen UTXO values
+    pub total_input_sats: u64,
+    /// Fee, including any change too small to keep
+    pub fee_sats: u64,
+    /// Change returned to the vault, 0 for none
+    pub change_sats: u64,
+}
+
+impl Selection {
+    /// Outpoints of the chosen UTXOs
+    pub fn outpoints(&self) -> Vec<OutPoint> {
+        self.utxos.iter().map(|utxo| utxo.outpoint).collect()
+    }
+}
+
+/// A UTXO with the cost of spending it through its timelock leaf
+struct Candidate<'a> {
+    utxo: &'a VaultUtxo,
+    weight: usize,
+    /// Value minus the fee its input adds, which may be negative
+    effective_value: i64,
+}
+
+/// Choose vault UTXOs to pay `target_sats` at `fee_rate` sat/vB
+///
+/// Inputs are costed at their exact timelock-leaf witness weight, the
+/// path an unvault spends. The destination and change are assumed to be
+/// P2TR outputs, so narrower destinations pay slightly over the rate.
+/// Change below the `dust` limit is left to the fee.
+///
+/// If the UTXOs can't cover the target, fails with `InsufficientFunds`,
+/// where `needed` is the target plus the fee for spending every UTXO
+/// without change and `available` is their total value.
+pub fn select(
+    utxos: &[VaultUtxo],
+    target_sats: u64,
+    fee_rate: u64,
+    strategy: SelectionStrategy,
+    dust: DustPolicy,
+) -> Result<Selection, CoreError> {
+    fees::estimate_fee(0, fee_rate)?;
+    if target_sats == 0 {
+        return Err(CoreError::InvalidInput(
+            "Selection target must be above zero".to_string(),
+        ));
+    }
+
+    let mut candidates = utxos
+        .iter()
+        .map(|utxo| {
+            let weight = fees::leaf_input_weight(&utxo.tree, LeafPurpose::Timelock)?;
+            let input_fee = fees::estimate_fee(fees::weight_to_vsize(weight), fee_rate)?;
+            Ok(Candidate {
+                utxo,
+                weight,
+                effective_value: utxo.amount_sats as i64 - input_fee as i64,
+            })
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.effective_value));
+
+    let available: u64 = utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    let all: Vec<&Candidate> = candidates.iter().collect();
+    let needed = target_sats + fee(&all, 1, fee_rate)?;
+    if available < needed {
+        return Err(CoreError::InsufficientFunds { needed, available });
+    }
+
+    if strategy == SelectionStrategy::BranchAndBound {
+        if let Some(selection) = branch_and_bound(&candidates, target_sats, fee_rate)? {
+            return Ok(selection);
+        }
+    }
+    largest_first(&candidates, target_sats, fee_rate, dust)?
+        .ok_or(CoreError::InsufficientFunds { needed, available })
+}
+
+fn largest_first(
+    candidates: &[Candidate],
+    target_sats: u64,
+    fee_rate: u64,
+    dust: DustPolicy,
+) -> Result<Option<Selection>, CoreError> {
+    let mut chosen = Vec::new();
+    for candidate in candidates {
+        chosen.push(candidate);
+        if let Some(selection) = finalize(&chosen, target_sats, fee_rate, dust)? {
+            return Ok(Some(selection));
+        }
+    }
+    Ok(None)
+}
+
+/// Depth-first search for a subset that pays the target without change
+///
+/// A subset matches when its excess over target and fee is at most the
+/// cost of creating and later spending a change output; the match
+/// with the least excess wins.
+fn branch_and_bound(
+    candidates: &[Candidate],
+    target_sats: u64,
+    fee_rate: u64,
+) -> Result<Option<Selection>, CoreError> {
+    let positive: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|candidate| candidate.effective_value > 0)
+        .collect();
+    let Some(first) = positive.first() else {
+        return Ok(None);
+    };
+
+    let base_fee = fee(&[], 1, fee_rate)?;
+    let cost_of_change =
+        fees::estimate_fee(P2TR_OUTPUT_VSIZE + fees::weight_to_vsize(first.weight), fee_rate)?;
+    let mut search = BnbSearch {
+        candidates: &positive,
+        target_sats,
+        fee_rate,
+        lower: (target_sats + base_fee) as i64,
+        upper: (target_sats + base_fee + cost_of_change) as i64,
+        tries: BNB_MAX_TRIES,
+        path: Vec::new(),
+        best: None,
+    };
+    let remaining = positive.iter().map(|c| c.effective_value).sum();
+    search.run(0, 0, remaining)?;
+
+    Ok(search.best.map(|(_, selection)| selection))
+}
+
+struct BnbSearch<'a, 'b> {
+    candidates: &'b [&'b Candidate<'a>],
+    target_sats: u64,
+    fee_rate: u64,
+    lower: i64,
+    upper: i64,
+    tries: usize,
+    path: Vec<&'b Candidate<'a>>,
+    best: Option<(u64, Selection)>,
+}
+
+impl BnbSearch<'_, '_> {
+    fn run(&mut self, index: usize, value: i64, remaining: i64) -> Result<(), CoreError> {
+        if self.tries == 0 || value > self.upper {
+            return Ok(());
+        }
+        self.tries -= 1;
+
+        if value >= self.lower {
+            // Per-input rounding can push the exact fee past the window
+            if let Some(selection) = changeless(&self.path, self.target_sats, self.fee_rate)? {
+                let excess = selection.fee_sats;
+                if self.best.as_ref().map_or(true, |(best, _)| excess < *best) {
+                    self.best = Some((excess, selection));
+                }
+            }
+            return Ok(());
+        }
+        if index == self.candidates.len() || value + remaining < self.lower {
+            return Ok(());
+        }
+
+        let candidate = self.candidates[index];
+        let remaining = remaining - candidate.effective_value;
+        self.path.push(candidate);
+        self.run(index + 1, value + candidate.effective_value, remaining)?;
+        self.path.pop();
+        self.run(index + 1, value, remaining)
+    }
+}
+
+/// Fee for spending `chosen` to `n_outputs` P2TR outputs
+fn fee(chosen: &[&Candidate], n_outputs: usize, fee_rate: u64) -> Result<u64, CoreError> {
+    let input_weights: Vec<usize> = chosen.iter().map(|c| c.weight).collect();
+    let weight = fees::tx_weight(&input_weights, &vec![P2TR_SCRIPT_PUBKEY_LEN; n_outputs]);
+    fees::estimate_fee(fees::weight_to_vsize(weight), fee_rate)
+}
+
+/// Selection spending `chosen`, with change if it clears the `dust` limit
+fn finalize(
+    chosen: &[&Candidate],
+    target_sats: u64,
+    fee_rate: u64,
+    dust: DustPolicy,
+) -> Result<Option<Selection>, CoreError> {
+    let Some(mut selection) = changeless(chosen, target_sats, fee_rate)? else {
+        return Ok(None);
+    };
+
+    let change_fee = fee(chosen, 2, fee_rate)?;
+    let dust = dust.limit(&chosen[0].utxo.tree.script_pubkey());
+    let total = selection.total_input_sats;
+    if total >= target_sats + change_fee + dust {
+        selection.fee_sats = change_fee;
+        selection.change_sats = total - target_sats - change_fee;
+    }
+    Ok(Some(selection))
+}
+
+/// Selection spending `chosen` with everything above the target as fee
+fn changeless(
+    chosen: &[&Candidate],
+    target_sats: u64,
+    fee_rate: u64,
+) -> Result<Option<Selection>, CoreError> {
+    let total: u64 = chosen.iter().map(|c| c.utxo.amount_sats).sum();
+    if chosen.is_empty() || total < target_sats + fee(chosen, 1, fee_rate)? {
+        return Ok(None);
+    }
+
+    Ok(Some(Selection {
+        utxos: chosen.iter().map(|c| c.utxo.clone()).collect(),
+        target_sats,
+        total_input_sats: total,
+        fee_sats: total - target_sats,
+        change_sats: 0,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{OWNER_TPUB, RECOVERY_TPUB};
+    use crate::keys;
+    use crate::taproot::vault_tree;
+    use crate::vault::{Network, VaultTemplate};
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    fn utxos(amounts: &[u64]) -> Vec<VaultUtxo> {
+        let owner = keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap();
+        let recovery = keys::parse_xpub(RECOVERY_TPUB, Network::Regtest).unwrap();
+        let tree = vault_tree(&VaultTemplate::spending(), &owner, &recovery, 0, Network::Regtest).unwrap();
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(vout, amount)| {
+                let outpoint = OutPoint::new(Txid::from_str(&"cd".repeat(32)).unwrap(), vout as u32);
+                VaultUtxo::new(outpoint, *amount, tree.clone())
+            })
+            .collect()
+    }
+
+    fn input_fee(fee_rate: u64) -> u64 {
+        let utxo = &utxos(&[0])[0];
+        let weight = fees::leaf_input_weight(&utxo.tree, LeafPurpose::Timelock).unwrap();
+        fees::weight_to_vsize(weight) * fee_rate
+    }
+
+    fn assert_balanced(selection: &Selection) {
+        let total: u64 = selection.utxos.iter().map(|u| u.amount_sats).sum();
+        assert_eq!(total, selection.total_input_sats);
+        assert_eq!(
+            selection.target_sats + selection.fee_sats + selection.change_sats,
+            total
+        );
+    }
+
+    #[test]
+    fn test_largest_first_takes_biggest_utxos() {
+        let utxos = utxos(&[10_000, 80_000, 50_000, 30_000]);
+        let selection = select(&utxos, 100_000, 2, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+
+        let values: Vec<u64> = selection.utxos.iter().map(|u| u.amount_sats).collect();
+        assert_eq!(values, vec![80_000, 50_000]);
+        assert_eq!(selection.outpoints(), vec![utxos[1].outpoint, utxos[2].outpoint]);
+        assert!(selection.change_sats > 0);
+        assert_balanced(&selection);
+    }
+
+    #[test]
+    fn test_fee_matches_exact_vsize() {
+        let utxos = utxos(&[60_000, 60_000]);
+        let selection = select(&utxos, 100_000, 3, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+
+        let weights: Vec<usize> = selection
+            .utxos
+            .iter()
+            .map(|u| fees::leaf_input_weight(&u.tree, LeafPurpose::Timelock).unwrap())
+            .collect();
+        let vsize = fees::weight_to_vsize(fees::tx_weight(&weights, &[34, 34]));
+        assert_eq!(selection.fee_sats, vsize * 3);
+        assert_balanced(&selection);
+    }
+
+    #[test]
+    fn test_branch_and_bound_finds_changeless_match() {
+        let fee_rate = 2;
+        // 40k + 25k covers 65k plus fee exactly, with a few sats to spare
+        let base = fee(&[], 1, fee_rate).unwrap();
+        let target = 65_000 - 2 * input_fee(fee_rate) - base - 5;
+        let utxos = utxos(&[70_000, 40_000, 25_000, 90_000]);
+
+        let selection = select(&utxos, target, fee_rate, SelectionStrategy::BranchAndBound, DustPolicy::Relay).unwrap();
+        let mut values: Vec<u64> = selection.utxos.iter().map(|u| u.amount_sats).collect();
+        values.sort();
+        assert_eq!(values, vec![25_000, 40_000]);
+        assert_eq!(selection.change_sats, 0);
+        assert!(selection.fee_sats - (2 * input_fee(fee_rate) + base) <= 5);
+        assert_balanced(&selection);
+
+        // Largest-first overshoots and makes change instead
+        let largest = select(&utxos, target, fee_rate, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        assert_eq!(largest.utxos.len(), 1);
+        assert!(largest.change_sats > 0);
+    }
+
+    #[test]
+    fn test_branch_and_bound_falls_back_to_largest_first() {
+        let utxos = utxos(&[100_000, 100_000]);
+        let bnb = select(&utxos, 50_000, 1, SelectionStrategy::BranchAndBound, DustPolicy::Relay).unwrap();
+        let largest = select(&utxos, 50_000, 1, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        assert_eq!(bnb.outpoints(), largest.outpoints());
+        assert_eq!(bnb.change_sats, largest.change_sats);
+    }
+
+    #[test]
+    fn test_dust_change_goes_to_fee() {
+        let fee_rate = 1;
+        let utxos = utxos(&[50_000]);
+        let sweep_fee = fee_rate * fees::weight_to_vsize(fees::tx_weight(
+            &[fees::leaf_input_weight(&utxos[0].tree, LeafPurpose::Timelock).unwrap()],
+            &[34],
+        ));
+        let target = 50_000 - sweep_fee - 100;
+
+        let selection = select(&utxos, target, fee_rate, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        assert_eq!(selection.change_sats, 0);
+        assert_eq!(selection.fee_sats, sweep_fee + 100);
+    }
+
+    #[test]
+    fn test_dust_floor_folds_change() {
+        let fee_rate = 1;
+        let utxos = utxos(&[50_000]);
+        let input = fees::leaf_input_weight(&utxos[0].tree, LeafPurpose::Timelock).unwrap();
+        let change_fee = fee_rate * fees::weight_to_vsize(fees::tx_weight(&[input], &[34, 34]));
+        let target = 50_000 - change_fee - 800;
+
+        let relay = select(&utxos, target, fee_rate, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        assert_eq!(relay.change_sats, 800);
+        let strict = select(&utxos, target, fee_rate, SelectionStrategy::LargestFirst, DustPolicy::Floor(1_000)).unwrap();
+        assert_eq!(strict.change_sats, 0);
+        assert_eq!(strict.fee_sats, 50_000 - target);
+    }
+
+    #[test]
+    fn test_insufficient_funds_reports_exact_amounts() {
+        let utxos = utxos(&[30_000, 20_000]);
+        let weights: Vec<usize> = utxos
+            .iter()
+            .map(|u| fees::leaf_input_weight(&u.tree, LeafPurpose::Timelock).unwrap())
+            .collect();
+        let all_in_fee = 5 * fees::weight_to_vsize(fees::tx_weight(&weights, &[34]));
+
+        for strategy in [SelectionStrategy::LargestFirst, SelectionStrategy::BranchAndBound] {
+            match select(&utxos, 50_000, 5, strategy, DustPolicy::Relay) {
+                Err(CoreError::InsufficientFunds { needed, available }) => {
+                    assert_eq!(needed, 50_000 + all_in_fee);
+                    assert_eq!(available, 50_000);
+                }
+                other => panic!("expected InsufficientFunds, got {:?}", other),
+            }
+        }
+        assert!(matches!(
+            select(&[], 1_000, 1, SelectionStrategy::LargestFirst, DustPolicy::Relay),
+            Err(CoreError::InsufficientFunds { available: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_bad_parameters() {
+        let utxos = utxos(&[10_000]);
+        assert!(matches!(
+            select(&utxos, 1_000, 0, SelectionStrategy::LargestFirst, DustPolicy::Relay),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        assert!(matches!(
+            select(&utxos, 0, 1, SelectionStrategy::LargestFirst, DustPolicy::Relay),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,841 @@
+//! Output descriptors describing vault trees for other wallets
+
+use std::cell::{Cell, RefCell};
+
+use bitcoin::bip32::{DerivationPath, ExtendedPubKey, KeySource};
+use bitcoin::hashes::sha256;
+
+use crate::error::CoreError;
+use crate::keys;
+use crate::taproot::{self, LeafPurpose, TreeVersion};
+use crate::vault::{Network, VaultTemplate};
+
+/// Build a `tr()` descriptor covering every vault index of an account pair
+///
+/// Keys are ranged as `xpub/0/*`, matching `keys::derive_vault_key()`.
+/// The internal key is the owner key when the template enables key-path
+/// spends, so the descriptor records which mode the vault uses, and
+/// otherwise `keys::nums_xpub()/0/*` in `TreeVersion::V3` and later, or
+/// the fixed `keys::unspendable_internal_key()` in hex before. Leaves use
+/// the miniscript fragments that compile to the vault's leaf scripts:
+/// `and_v(v:older(n),pk(K))` for the timelock leaf, `pk(K)` for the
+/// emergency leaf, `sortedmulti_a(k,...)` for the multisig leaf,
+/// `and_v(v:older(n),multi_a(k,...))` for the inheritance leaf and
+/// delayed degrading stages, `multi_a(k,...)` for an immediate
+/// degrading stage, `and_v(v:after(n),pk(K))` for the absolute lock
+/// leaf and `and_v(v:sha256(H),pk(K))` for the hashlock leaf. The
+/// descriptor checksum is appended.
+///
+/// Addresses derived from the descriptor at index `i` equal those of
+/// `taproot::vault_tree_versioned(.., i, .., version)`. `TreeVersion::V1`
+/// timelock leaves have no miniscript form, so `V1` is `InvalidInput`, as
+/// are absolute lock and hashlock leaves before `TreeVersion::V4`.
+/// Templates `VaultTemplate::validate()` refuses are `PolicyViolation`,
+/// as they are for `VaultBuilder`, so every descriptor can be restored.
+pub fn to_core_descriptor(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+) -> Result<String, CoreError> {
+    build_descriptor(template, owner_xpub, recovery_xpub, network, version, &ranged_key)
+}
+
+/// `to_core_descriptor()` with a key origin on every account key
+///
+/// Keys are written as `[fingerprint/path]xpub/0/*` with the origins
+/// given for the owner and recovery xpubs and those of the template's
+/// cosigner keys (see `keys::parse_xpub_with_origin()`), matching the
+/// key origins vault PSBTs carry. A key given without one is its own
+/// origin, `[fingerprint]xpub/0/*`. The NUMS internal key has no holder
+/// and gets no origin. Addresses are the same as `to_core_descriptor()`'s.
+pub fn to_core_descriptor_with_origins(
+    template: &VaultTemplate,
+    owner: (&ExtendedPubKey, &KeySource),
+    recovery: (&ExtendedPubKey, &KeySource),
+    network: Network,
+    version: TreeVersion,
+) -> Result<String, CoreError> {
+    let mut origins = vec![(*owner.0, owner.1.clone()), (*recovery.0, recovery.1.clone())];
+    for key in cosigner_keys(template) {
+        origins.push(keys::parse_xpub_with_origin(key, network)?);
+    }
+    let origin_key = |xpub: &ExtendedPubKey| {
+        let (fingerprint, path) = origins
+            .iter()
+            .find(|(key, _)| key == xpub)
+            .map(|(_, origin)| origin.clone())
+            .unwrap_or_else(|| (xpub.fingerprint(), DerivationPath::master()));
+        format!("[{}{}]{}", fingerprint, &path.to_string()[1..], ranged_key(xpub))
+    };
+
+    build_descriptor(template, owner.0, recovery.0, network, version, &origin_key)
+}
+
+/// Cosigner or heir keys of a template's multisig, inheritance or degrading leaves
+pub(crate) fn cosigner_keys(template: &VaultTemplate) -> &[String] {
+    match template {
+        VaultTemplate::Custom { multisig: Some(multisig), .. } => &multisig.cosigners,
+        VaultTemplate::Inheritance { heirs, .. } => heirs,
+        VaultTemplate::Degrading { cosigners, .. } => cosigners,
+        _ => &[],
+    }
+}
+
+/// BIP388 wallet policy template of an account pair, and its keys
+///
+/// The template is `to_core_descriptor()`'s descriptor without a
+/// checksum, each distinct key replaced by `@i/**` in order of first
+/// appearance, the NUMS internal key included. `@i/**` stands for
+/// `/<0;1>/*`, so receive address `i` of the policy is vault index `i`.
+/// BIP388 forbids repeating a key expression, so trees using a key twice
+/// (the owner key as internal key and in a leaf, for one) are rejected
+/// with `InvalidInput`, as are script-path-only trees before
+/// `TreeVersion::V3`, whose fixed internal key has no placeholder.
+pub fn policy_template(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+) -> Result<(String, Vec<ExtendedPubKey>), CoreError> {
+    if !template.key_path_enabled() && !version.per_index_nums() {
+        return Err(CoreError::InvalidInput(format!(
+            "Wallet policies can't express the fixed NUMS internal key of tree version {}",
+            u8::from(version)
+        )));
+    }
+
+    let keys = RefCell::new(Vec::<ExtendedPubKey>::new());
+    let repeated = Cell::new(false);
+    let placeholder = |xpub: &ExtendedPubKey| {
+        let mut keys = keys.borrow_mut();
+        let index = match keys.iter().position(|key| key == xpub) {
+            Some(index) => {
+                repeated.set(true);
+                index
+            }
+            None => {
+                keys.push(*xpub);
+                keys.len() - 1
+            }
+        };
+        format!("@{}/**", index)
+    };
+
+    let descriptor = descriptor_body(template, owner_xpub, recovery_xpub, network, version, &placeholder, &placeholder)?;
+    if repeated.get() {
+        return Err(CoreError::InvalidInput(
+            "Wallet policies can't use the same key twice in a vault tree".to_string(),
+        ));
+    }
+    Ok((descriptor, keys.into_inner()))
+}
+
+fn build_descriptor(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    let descriptor = descriptor_body(template, owner_xpub, recovery_xpub, network, version, key, &ranged_key)?;
+    let checksum = checksum(&descriptor)?;
+
+    Ok(format!("{}#{}", descriptor, checksum))
+}
+
+/// Descriptor without its checksum, writing account keys with `key` and
+/// the NUMS internal key, which has no holder, with `nums_key`
+fn descriptor_body(
+    template: &VaultTemplate,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    version: TreeVersion,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+    nums_key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    if !version.has_descriptor() {
+        return Err(CoreError::InvalidInput(format!(
+            "Tree version {} timelock leaves (OP_CSV OP_DROP) have no miniscript form; \
+             only vaults created with tree version 2 or later have a descriptor",
+            u8::from(version)
+        )));
+    }
+
+    // Building a tree validates the keys for `network`
+    template.validate()?;
+    let tree = taproot::vault_tree_versioned(template, owner_xpub, recovery_xpub, 0, network, version)?;
+
+    // The internal key comes first so keys are met in descriptor order
+    let internal_key = if template.key_path_enabled() {
+        key(owner_xpub)
+    } else if version.per_index_nums() {
+        nums_key(&keys::nums_xpub(network))
+    } else {
+        keys::unspendable_internal_key().to_string()
+    };
+
+    let fragments = tree
+        .leaves()
+        .iter()
+        .map(|leaf| match leaf.purpose {
+            LeafPurpose::Timelock => Ok(format!(
+                "and_v(v:older({}),pk({}))",
+                template.sequence()?.to_consensus_u32(),
+                key(owner_xpub)
+            )),
+            LeafPurpose::WhitelistTimelock => Ok(format!(
+                "and_v(v:older({}),pk({}))",
+                template.whitelist_delay().unwrap_or_default(),
+                key(owner_xpub)
+            )),
+            LeafPurpose::Emergency => Ok(format!("pk({})", key(recovery_xpub))),
+            LeafPurpose::Multisig => multisig_fragment(template, network, key),
+            LeafPurpose::Inheritance => inheritance_fragment(template, network, key),
+            LeafPurpose::DegradingStage(stage) => {
+                degrading_fragment(template, stage, owner_xpub, recovery_xpub, network, key)
+            }
+            LeafPurpose::AbsoluteLock => absolute_lock_fragment(template, recovery_xpub, version, key),
+            LeafPurpose::Hashlock => hashlock_fragment(template, network, version, key),
+            LeafPurpose::Metadata => Err(CoreError::InvalidInput(
+                "Metadata leaves cannot be expressed in a descriptor".to_string(),
+            )),
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    // Pair the two lightest subtrees until one is left, as the tree's
+    // Huffman builder does. Where the second and third lightest tie, the
+    // builder pairs by hash, which changes with the index, so no single
+    // ranged descriptor fits: three equal leaves before `TreeVersion::V4`.
+    // From V4 the timelock leaf outweighs the rest, giving
+    // `{timelock,{a,b}}`, and degrading stages never tie, so each pairs
+    // with the subtree of all later ones.
+    let stages = tree
+        .leaves()
+        .iter()
+        .filter(|leaf| matches!(leaf.purpose, LeafPurpose::DegradingStage(_)))
+        .count() as u32;
+    let mut subtrees: Vec<(u32, String)> = tree
+        .leaves()
+        .iter()
+        .map(|leaf| taproot::leaf_weight(leaf.purpose, stages, version))
+        .zip(fragments)
+        .collect();
+    while subtrees.len() > 1 {
+        subtrees.sort_by_key(|(weight, _)| *weight);
+        if subtrees.len() > 2 && subtrees[1].0 == subtrees[2].0 {
+            return Err(CoreError::InvalidInput(format!(
+                "Descriptor export can't place the {} leaves of a tree version {} vault: leaves of equal \
+                 weight are paired by hash, which differs between vault indexes",
+                tree.leaves().len(),
+                u8::from(version)
+            )));
+        }
+        let (light_weight, light) = subtrees.remove(0);
+        let (heavy_weight, heavy) = subtrees.remove(0);
+        // The heavier side first; equal leaves keep their order
+        let branch = if heavy_weight > light_weight {
+            format!("{{{},{}}}", heavy, light)
+        } else {
+            format!("{{{},{}}}", light, heavy)
+        };
+        subtrees.push((light_weight + heavy_weight, branch));
+    }
+    let script_tree = subtrees.pop().map(|(_, subtree)| subtree).unwrap_or_default();
+
+    Ok(format!("tr({},{})", internal_key, script_tree))
+}
+
+/// Characters allowed in a descriptor, grouped so that the position of a
+/// character within its group of 32 is its checksum symbol
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+
+/// Characters a checksum is written in
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Length of a descriptor checksum
+pub const CHECKSUM_LEN: usize = 8;
+
+/// Compute the 8-character checksum of a descriptor (without `#checksum`)
+///
+/// Same algorithm as Bitcoin Core's `DescriptorChecksum()`: a BCH code
+/// over the descriptor's characters. Characters outside the descriptor
+/// charset are rejected with `InvalidInput`.
+pub fn checksum(desc: &str) -> Result<String, CoreError> {
+    let mut c = 1u64;
+    let mut class = 0u64;
+    let mut class_count = 0;
+
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET.find(ch).ok_or_else(|| {
+            CoreError::InvalidInput(format!("Invalid character in descriptor: {:?}", ch))
+        })? as u64;
+        // Low 5 bits go in directly, group numbers are packed three at a time
+        c = polymod(c, pos & 31);
+        class = class * 3 + (pos >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..CHECKSUM_LEN {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..CHECKSUM_LEN)
+        .map(|i| CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Check the `#checksum` suffix of a descriptor and return the descriptor without it
+pub fn verify_checksum(desc_with_checksum: &str) -> Result<&str, CoreError> {
+    let (desc, expected) = desc_with_checksum
+        .rsplit_once('#')
+        .ok_or_else(|| CoreError::InvalidInput("Descriptor has no checksum".to_string()))?;
+    if expected.len() != CHECKSUM_LEN {
+        return Err(CoreError::InvalidInput(format!(
+            "Descriptor checksum must be {} characters, got {}",
+            CHECKSUM_LEN,
+            expected.len()
+        )));
+    }
+
+    let actual = checksum(desc)?;
+    if actual != expected {
+        return Err(CoreError::InvalidInput(format!(
+            "Descriptor checksum mismatch: expected {}, computed {}",
+            expected, actual
+        )));
+    }
+    Ok(desc)
+}
+
+/// One step of the checksum's BCH code over GF(32)
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATORS: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (i, generator) in GENERATORS.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Key expression deriving the vault key at every index of an account
+fn ranged_key(xpub: &ExtendedPubKey) -> String {
+    format!("{}/0/*", xpub)
+}
+
+fn multisig_fragment(
+    template: &VaultTemplate,
+    network: Network,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    let multisig = match template {
+        VaultTemplate::Custom { multisig: Some(multisig), .. } => multisig,
+        _ => {
+            return Err(CoreError::PolicyViolation(
+                "MultiSig recovery requires a cosigner set".to_string(),
+            ))
+        }
+    };
+
+    let cosigners = multisig
+        .cosigners
+        .iter()
+        .map(|xpub| Ok(key(&keys::parse_xpub(xpub, network)?)))
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    Ok(format!("sortedmulti_a({},{})", multisig.threshold, cosigners.join(",")))
+}
+
+fn absolute_lock_fragment(
+    template: &VaultTemplate,
+    recovery_xpub: &ExtendedPubKey,
+    version: TreeVersion,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    // `after()` compiles to OP_CLTV OP_VERIFY, not the earlier leaves' OP_DROP
+    if !version.has_lock_descriptors() {
+        return Err(CoreError::InvalidInput(format!(
+            "Tree version {} absolute lock leaves (OP_CLTV OP_DROP) have no miniscript form; \
+             only vaults created with tree version 4 or later have a descriptor",
+            u8::from(version)
+        )));
+    }
+    let lock = template.absolute_lock()?.ok_or_else(|| {
+        CoreError::PolicyViolation("Absolute lock leaf requires an absolute lock".to_string())
+    })?;
+    Ok(format!("and_v(v:after({}),pk({}))", lock.to_consensus_u32(), key(recovery_xpub)))
+}
+
+fn hashlock_fragment(
+    template: &VaultTemplate,
+    network: Network,
+    version: TreeVersion,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    // `sha256()` adds an OP_SIZE 32 check the earlier leaves don't have
+    if !version.has_lock_descriptors() {
+        return Err(CoreError::InvalidInput(format!(
+            "Tree version {} hashlock leaves (without OP_SIZE) have no miniscript form; \
+             only vaults created with tree version 4 or later have a descriptor",
+            u8::from(version)
+        )));
+    }
+    let hashlock = match template {
+        VaultTemplate::Custom { hashlock: Some(hashlock), .. } => hashlock,
+        _ => {
+            return Err(CoreError::PolicyViolation(
+                "Hashlock leaf requires a hashlock recovery".to_string(),
+            ))
+        }
+    };
+    let service = keys::parse_xpub(&hashlock.service_xpub, network)?;
+    Ok(format!("and_v(v:sha256({}),pk({}))", hashlock.hash, key(&service)))
+}
+
+fn inheritance_fragment(
+    template: &VaultTemplate,
+    network: Network,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    let (threshold, heirs) = match template {
+        VaultTemplate::Inheritance { heir_threshold, heirs, .. } => (heir_threshold, heirs),
+        _ => {
+            return Err(CoreError::PolicyViolation(
+                "Inheritance leaf requires an inheritance template".to_string(),
+            ))
+        }
+    };
+
+    let heirs = heirs
+        .iter()
+        .map(|xpub| Ok(key(&keys::parse_xpub(xpub, network)?)))
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    Ok(format!(
+        "and_v(v:older({}),multi_a({},{}))",
+        template.sequence()?.to_consensus_u32(),
+        threshold,
+        heirs.join(",")
+    ))
+}
+
+fn degrading_fragment(
+    template: &VaultTemplate,
+    stage: u8,
+    owner_xpub: &ExtendedPubKey,
+    recovery_xpub: &ExtendedPubKey,
+    network: Network,
+    key: &dyn Fn(&ExtendedPubKey) -> String,
+) -> Result<String, CoreError> {
+    let (delay, threshold) = template
+        .stages()
+        .and_then(|stages| stages.get(stage as usize).copied())
+        .ok_or_else(|| CoreError::PolicyViolation(format!("Template has no degrading stage {}", stage)))?;
+
+    let mut signers = vec![key(owner_xpub), key(recovery_xpub)];
+    for xpub in cosigner_keys(template) {
+        signers.push(key(&keys::parse_xpub(xpub, network)?));
+    }
+    let multi = format!("multi_a({},{})", threshold, signers.join(","));
+
+    if delay == 0 {
+        Ok(multi)
+    } else {
+        Ok(format!("and_v(v:older({}),{})", delay, multi))
+    }
+}
+
+/// Leaf fragment of a vault descriptor, with its keys parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DescriptorLeaf {
+    /// `and_v(v:older(n),pk(K))`
+    Timelock { older: u32, key: ExtendedPubKey },
+    /// `pk(K)`
+    Key(ExtendedPubKey),
+    /// `sortedmulti_a(k,...)`
+    SortedMulti { threshold: u8, keys: Vec<ExtendedPubKey> },
+    /// `multi_a(k,...)`
+    Multi { threshold: u8, keys: Vec<ExtendedPubKey> },
+    /// `and_v(v:older(n),multi_a(k,...))`
+    DelayedMulti { older: u32, threshold: u8, keys: Vec<ExtendedPubKey> },
+    /// `and_v(v:after(n),pk(K))`
+    AbsoluteLock { after: u32, key: ExtendedPubKey },
+    /// `and_v(v:sha256(H),pk(K))`
+    Hashlock { hash: sha256::Hash, key: ExtendedPubKey },
+}
+
+impl DescriptorLeaf {
+    /// The leaf's `older()` value, if it has one
+    pub fn older(&self) -> Option<u32> {
+        match self {
+            DescriptorLeaf::Timelock { older, .. } | DescriptorLeaf::DelayedMulti { older, .. } => Some(*older),
+            DescriptorLeaf::Key(_)
+            | DescriptorLeaf::SortedMulti { .. }
+            | DescriptorLeaf::Multi { .. }
+            | DescriptorLeaf::AbsoluteLock { .. }
+            | DescriptorLeaf::Hashlock { .. } => None,
+        }
+    }
+}
+
+/// A `tr()` descriptor in the form written by `to_core_descriptor()`
+#[derive(Debug, Clone)]
+pub struct ParsedDescriptor {
+    /// Ranged internal key, or `None` for the fixed
+    /// `keys::unspendable_internal_key()`
+    pub internal_key: Option<ExtendedPubKey>,
+    /// Leaves in the order they appear in the descriptor
+    pub leaves: Vec<DescriptorLeaf>,
+}
+
+/// Parse a descriptor written by `to_core_descriptor()`
+///
+/// Only the fragments `to_core_descriptor()` emits are understood, with
+/// every key an account xpub ranged as `xpub/0/*`. The checksum is
+/// required and keys must belong to `network`. This reads the structure
+/// only; whether the leaves form a valid vault is up to the caller.
+pub fn parse_core_descriptor(desc: &str, network: Network) -> Result<ParsedDescriptor, CoreError> {
+    let body = verify_checksum(desc.trim())?;
+    let inner = body
+        .strip_prefix("tr(")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| CoreError::InvalidInput("Descriptor is not a tr() descriptor".to_string()))?;
+
+    let (internal_key, script_tree) = match split_args(inner).as_slice() {
+        [internal_key, script_tree] => (parse_internal_key(internal_key, network)?, *script_tree),
+        _ => {
+            return Err(CoreError::InvalidInput(
+                "tr() descriptor needs an internal key and a script tree".to_string(),
+            ))
+        }
+    };
+
+    let mut fragments = Vec::new();
+    flatten_tree(script_tree, &mut fragments);
+    let leaves = fragments
+        .into_iter()
+        .map(|fragment| parse_leaf(fragment, network))
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    Ok(ParsedDescriptor { internal_key, leaves })
+}
+
+fn parse_internal_key(key: &str, network: Network) -> Result<Option<ExtendedPubKey>, CoreError> {
+    if key == keys::unspendable_internal_key().to_string() {
+        return Ok(None);
+    }
+    parse_ranged_key(key, network).map(Some)
+}
+
+/// Leaf fragments of a script tree, in order, with the `{,}` branches removed
+fn flatten_tree<'a>(script_tree: &'a str, fragments: &mut Vec<&'a str>) {
+    match script_tree.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) {
+        Some(branch) => {
+            for child in split_args(branch) {
+                flatten_tree(child, fragments);
+            }
+        }
+        None => fragments.push(script_tree),
+    }
+}
+
+fn parse_leaf(fragment: &str, network: Network) -> Result<DescriptorLeaf, CoreError> {
+    let unsupported = || CoreError::InvalidInput(format!("Unsupported descriptor fragment: {}", fragment));
+
+    if let Some(key) = call_args(fragment, "pk") {
+        return Ok(DescriptorLeaf::Key(parse_ranged_key(key, network)?));
+    }
+    if let Some(args) = call_args(fragment, "sortedmulti_a") {
+        let (threshold, keys) = parse_multi(args, network)?;
+        return Ok(DescriptorLeaf::SortedMulti { threshold, keys });
+    }
+    if let Some(args) = call_args(fragment, "multi_a") {
+        let (threshold, keys) = parse_multi(args, network)?;
+        return Ok(DescriptorLeaf::Multi { threshold, keys });
+    }
+    let args = call_args(fragment, "and_v").ok_or_else(unsupported)?;
+    let (verify, inner) = match split_args(args).as_slice() {
+        [verify, inner] => (verify.strip_prefix("v:").ok_or_else(unsupported)?, *inner),
+        _ => return Err(unsupported()),
+    };
+    if let Some(after) = call_args(verify, "after") {
+        let key = call_args(inner, "pk").ok_or_else(unsupported)?;
+        return Ok(DescriptorLeaf::AbsoluteLock { after: parse_number(after)?, key: parse_ranged_key(key, network)? });
+    }
+    if let Some(hash) = call_args(verify, "sha256") {
+        let key = call_args(inner, "pk").ok_or_else(unsupported)?;
+        let hash = hash
+            .parse()
+            .map_err(|_| CoreError::InvalidInput(format!("Invalid sha256 hash in descriptor: {}", hash)))?;
+        return Ok(DescriptorLeaf::Hashlock { hash, key: parse_ranged_key(key, network)? });
+    }
+    let older = parse_number(call_args(verify, "older").ok_or_else(unsupported)?)?;
+    if let Some(key) = call_args(inner, "pk") {
+        return Ok(DescriptorLeaf::Timelock { older, key: parse_ranged_key(key, network)? });
+    }
+    let args = call_args(inner, "multi_a").ok_or_else(unsupported)?;
+    let (threshold, keys) = parse_multi(args, network)?;
+    Ok(DescriptorLeaf::DelayedMulti { older, threshold, keys })
+}
+
+fn parse_multi(args: &str, network: Network) -> Result<(u8, Vec<ExtendedPubKey>), CoreError> {
+    let args = split_args(args);
+    let (threshold, keys) = args
+        .split_first()
+        .ok_or_else(|| CoreError::InvalidInput("Empty multisig fragment".to_string()))?;
+    let threshold = u8::try_from(parse_number(threshold)?)
+        .map_err(|_| CoreError::InvalidInput(format!("Multisig threshold {} is too large", threshold)))?;
+    let keys = keys
+        .iter()
+        .map(|key| parse_ranged_key(key, network))
+        .collect::<Result<Vec<_>, CoreError>>()?;
+    Ok((threshold, keys))
+}
+
+/// Account xpub of a key expression, which must be ranged as `xpub/0/*`
+fn parse_ranged_key(key: &str, network: Network) -> Result<ExtendedPubKey, CoreError> {
+    let xpub = key.strip_suffix("/0/*").ok_or_else(|| {
+        CoreError::InvalidInput(format!("Descriptor key {} is not ranged as xpub/0/*", key))
+    })?;
+    keys::parse_xpub(xpub, network)
+}
+
+fn parse_number(value: &str) -> Result<u32, CoreError> {
+    value
+        .parse()
+        .map_err(|_| CoreError::InvalidInput(format!("Invalid number in descriptor: {}", value)))
+}
+
+/// Arguments of `name(...)` if `fragment` is exactly that call
+fn call_args<'a>(fragment: &'a str, name: &str) -> Option<&'a str> {
+    fragment
+        .strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Split on commas outside any parentheses or braces
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, ch) in args.char_indices() {
+        match ch {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&args[start..]);
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, CustomFields, OWNER_TPUB, RECOVERY_TPUB};
+    use crate::vault::{MultisigRecovery, RecoveryType};
+
+    fn xpubs() -> (ExtendedPubKey, ExtendedPubKey) {
+        (
+            keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap(),
+            keys::parse_xpub(RECOVERY_TPUB, Network::Regtest).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_core_descriptor_shape() {
+        let (owner, recovery) = xpubs();
+        let desc = to_core_descriptor(&VaultTemplate::spending(), &owner, &recovery, Network::Regtest, TreeVersion::V3).unwrap();
+
+        let (body, checksum) = desc.split_once('#').unwrap();
+        assert_eq!(checksum.len(), 8);
+        assert_eq!(
+            body,
+            format!(
+                "tr({}/0/*,{{and_v(v:older(144),pk({}/0/*)),pk({}/0/*)}})",
+                keys::nums_xpub(Network::Regtest),
+                OWNER_TPUB,
+                RECOVERY_TPUB
+            )
+        );
+
+        // Before V3 the internal key is H at every index
+        let v2 = to_core_descriptor(&VaultTemplate::spending(), &owner, &recovery, Network::Regtest, TreeVersion::V2).unwrap();
+        assert!(v2.starts_with(&format!("tr({},{{and_v(", keys::unspendable_internal_key())), "{}", v2);
+        assert!(matches!(
+            to_core_descriptor(&VaultTemplate::spending(), &owner, &recovery, Network::Regtest, TreeVersion::V1),
+            Err(CoreError::InvalidInput(msg)) if msg.contains("no miniscript form")
+        ));
+    }
+
+    #[test]
+    fn test_core_descriptor_timelock_only() {
+        let (owner, recovery) = xpubs();
+        let template = custom_template(CustomFields { delay_blocks: 4320, ..Default::default() });
+
+        let desc = to_core_descriptor(&template, &owner, &recovery, Network::Regtest, TreeVersion::V2).unwrap();
+        assert!(desc.contains(&format!(",and_v(v:older(4320),pk({}/0/*)))#", OWNER_TPUB)));
+        assert!(!desc.contains(RECOVERY_TPUB));
+    }
+
+    #[test]
+    fn test_core_descriptor_multisig() {
+        let (owner, recovery) = xpubs();
+        let template = custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery {
+                threshold: 2,
+                cosigners: vec![OWNER_TPUB.to_string(), RECOVERY_TPUB.to_string()],
+            }),
+            ..Default::default()
+        });
+
+        let desc = to_core_descriptor(&template, &owner, &recovery, Network::Regtest, TreeVersion::V2).unwrap();
+        assert!(desc.contains(&format!("sortedmulti_a(2,{}/0/*,{}/0/*)", OWNER_TPUB, RECOVERY_TPUB)));
+    }
+
+    #[test]
+    fn test_parse_core_descriptor() {
+        let (owner, recovery) = xpubs();
+        let desc = to_core_descriptor(&VaultTemplate::spending(), &owner, &recovery, Network::Regtest, TreeVersion::V3).unwrap();
+
+        let parsed = parse_core_descriptor(&desc, Network::Regtest).unwrap();
+        assert_eq!(parsed.internal_key.unwrap().public_key, keys::nums_xpub(Network::Regtest).public_key);
+        let v2 = to_core_descriptor(&VaultTemplate::spending(), &owner, &recovery, Network::Regtest, TreeVersion::V2).unwrap();
+        assert_eq!(parse_core_descriptor(&v2, Network::Regtest).unwrap().internal_key, None);
+        assert_eq!(
+            parsed.leaves,
+            vec![DescriptorLeaf::Timelock { older: 144, key: owner }, DescriptorLeaf::Key(recovery)]
+        );
+
+        let unranged = format!("tr({},pk({}))", owner, recovery);
+        let unranged = format!("{}#{}", unranged, checksum(&unranged).unwrap());
+        assert!(matches!(
+            parse_core_descriptor(&unranged, Network::Regtest),
+            Err(CoreError::InvalidInput(msg)) if msg.contains("not ranged")
+        ));
+
+        let unknown = format!("tr({}/0/*,after(100))", owner);
+        let unknown = format!("{}#{}", unknown, checksum(&unknown).unwrap());
+        assert!(matches!(
+            parse_core_descriptor(&unknown, Network::Regtest),
+            Err(CoreError::InvalidInput(msg)) if msg.contains("Unsupported descriptor fragment")
+        ));
+    }
+
+    #[test]
+    fn test_core_descriptor_rejects_three_leaf_tree() {
+        let (owner, recovery) = xpubs();
+        let template = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        match to_core_descriptor(&template, &owner, &recovery, Network::Regtest, TreeVersion::V2) {
+            Err(CoreError::InvalidInput(msg)) => assert!(msg.contains("paired by hash"), "{}", msg),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_core_descriptor_weighted_three_leaf_tree() {
+        let (owner, recovery) = xpubs();
+        let template = custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery {
+                threshold: 2,
+                cosigners: vec![OWNER_TPUB.to_string(), RECOVERY_TPUB.to_string()],
+            }),
+            absolute_lock: Some(1_000_000),
+            ..Default::default()
+        });
+
+        // The timelock leaf alone at depth 1, the two recovery leaves under it
+        let desc = to_core_descriptor(&template, &owner, &recovery, Network::Regtest, TreeVersion::V4).unwrap();
+        let timelock = format!("and_v(v:older(144),pk({}/0/*))", OWNER_TPUB);
+        let recovery_leaves = format!(
+            "{{sortedmulti_a(2,{owner}/0/*,{recovery}/0/*),and_v(v:after(1000000),pk({recovery}/0/*))}}",
+            owner = OWNER_TPUB,
+            recovery = RECOVERY_TPUB
+        );
+        assert!(desc.contains(&format!(",{{{},{}}})#", timelock, recovery_leaves)), "{}", desc);
+    }
+
+    #[test]
+    fn test_core_descriptor_rejects_wrong_network() {
+        let (owner, recovery) = xpubs();
+        let result = to_core_descriptor(&VaultTemplate::savings(), &owner, &recovery, Network::Mainnet, TreeVersion::V2);
+        assert!(result.is_err());
+    }
+
+    // From Bitcoin Core's descriptor_tests.cpp and BIP 380
+    const CHECKSUM_VECTORS: [(&str, &str); 7] = [
+        ("raw(deadbeef)", "89f8spxm"),
+        ("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)", "02wpgw69"),
+        ("pk(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)", "gn28ywm7"),
+        ("pkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)", "8fhd9pwu"),
+        ("wpkh(02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9)", "8zl0zxma"),
+        ("sh(wpkh(03fff97bd5755eeea420453a14355235d382f6472f8568a18b2f057a1460297556))", "qkrrc7je"),
+        ("combo(0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)", "lq9sf04s"),
+    ];
+
+    #[test]
+    fn test_checksum_vectors() {
+        for (desc, expected) in CHECKSUM_VECTORS {
+            assert_eq!(checksum(desc).unwrap(), expected, "{}", desc);
+            assert_eq!(verify_checksum(&format!("{}#{}", desc, expected)).unwrap(), desc);
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum_rejects_bad_checksums() {
+        let desc = "raw(deadbeef)";
+        for bad in [
+            desc.to_string(),
+            format!("{}#", desc),
+            format!("{}#89f8spx", desc),
+            format!("{}#89f8spxmq", desc),
+            format!("{}#89f8spxn", desc),
+            // Any change to the descriptor breaks the checksum
+            "raw(deadbeee)#89f8spxm".to_string(),
+        ] {
+            assert!(matches!(verify_checksum(&bad), Err(CoreError::InvalidInput(_))), "accepted {}", bad);
+        }
+    }
+
+    #[test]
+    fn test_checksum_rejects_invalid_characters() {
+        for desc in ["raw(deadbeef)\n", "pk(\u{e9})", "raw(\t)"] {
+            assert!(matches!(checksum(desc), Err(CoreError::InvalidInput(_))), "accepted {:?}", desc);
+        }
+    }
+
+    #[test]
+    fn test_exported_descriptor_checksum_matches_miniscript() {
+        let (owner, recovery) = xpubs();
+        let desc = to_core_descriptor(&VaultTemplate::savings(), &owner, &recovery, Network::Regtest, TreeVersion::V2).unwrap();
+        let body = verify_checksum(&desc).unwrap();
+        assert_eq!(
+            desc.rsplit_once('#').unwrap().1,
+            miniscript::descriptor::checksum::desc_checksum(body).unwrap()
+        );
+    }
+}
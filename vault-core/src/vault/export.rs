@@ -0,0 +1,635 @@
+//! Exports for other wallet software: watch-only wallet files, Ledger wallet
+//! policies, BIP329 labels and BIP21 URIs
+
+use bitcoin::bip32::{ExtendedPubKey, KeySource};
+use bitcoin::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::keys;
+use crate::taproot::LeafPurpose;
+
+use super::{descriptor, Vault, VaultTemplate};
+
+/// Wallet file formats `export_wallet()` can produce
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalletFormat {
+    /// Sparrow / Specter watch-only wallet JSON, see `sparrow_wallet_json()`
+    Sparrow = 0,
+    /// Ledger wallet policy JSON, see `ledger_policy()`
+    Ledger = 1,
+    /// Coldcard import file, see `coldcard_file()`
+    Coldcard = 2,
+}
+
+impl TryFrom<i32> for WalletFormat {
+    type Error = CoreError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(WalletFormat::Sparrow),
+            1 => Ok(WalletFormat::Ledger),
+            2 => Ok(WalletFormat::Coldcard),
+            _ => Err(CoreError::InvalidInput(format!("Invalid wallet format: {}", value))),
+        }
+    }
+}
+
+/// Contents of a wallet file for `vault` in `format`
+pub fn export_wallet(vault: &Vault, format: WalletFormat) -> CoreResult<String> {
+    match format {
+        WalletFormat::Sparrow => sparrow_wallet_json(vault),
+        WalletFormat::Ledger => serde_json::to_string_pretty(&ledger_policy(vault)?)
+            .map_err(|e| CoreError::SerializationError(e.to_string())),
+        WalletFormat::Coldcard => coldcard_file(vault),
+    }
+}
+
+/// Watch-only wallet JSON for Sparrow's and Specter's import
+///
+/// `descriptor` covers every index of the vault's accounts, with key
+/// origins (see `descriptor::to_core_descriptor_with_origins()`), so the
+/// importing wallet derives the same addresses and recognizes the keys
+/// in vault PSBTs. `keystores` lists each account key a leaf uses with
+/// its origin's fingerprint and derivation (see `Vault::origin_of()`). `blockheight`
+/// is where a rescan starts; vaults don't record one, so it is 0.
+pub fn sparrow_wallet_json(vault: &Vault) -> CoreResult<String> {
+    if vault.musig_key().is_some() {
+        return Err(CoreError::InvalidInput(
+            "Wallet files can't express a MuSig2 internal key".to_string(),
+        ));
+    }
+    let descriptor = descriptor::to_core_descriptor_with_origins(
+        vault.template(),
+        (vault.owner_xpub(), vault.owner_origin()),
+        (vault.recovery_xpub(), vault.recovery_origin()),
+        vault.network(),
+        vault.tree_version(),
+    )?;
+
+    let mut keystores = vec![Keystore::new("Owner", vault.owner_xpub(), vault.owner_origin())];
+    let has_emergency_leaf = vault
+        .tree()
+        .leaves()
+        .iter()
+        .any(|leaf| matches!(leaf.purpose, LeafPurpose::Emergency | LeafPurpose::DegradingStage(_)));
+    if has_emergency_leaf {
+        keystores.push(Keystore::new("Recovery", vault.recovery_xpub(), vault.recovery_origin()));
+    }
+    let (role, cosigners): (&str, &[String]) = match vault.template() {
+        VaultTemplate::Custom { multisig: Some(multisig), .. } => ("Cosigner", &multisig.cosigners),
+        VaultTemplate::Inheritance { heirs, .. } => ("Heir", heirs),
+        VaultTemplate::Degrading { cosigners, .. } => ("Cosigner", cosigners),
+        _ => ("Cosigner", &[]),
+    };
+    for (i, xpub) in cosigners.iter().enumerate() {
+        let (xpub, origin) = keys::parse_xpub_with_origin(xpub, vault.network())?;
+        keystores.push(Keystore::new(format!("{} {}", role, i + 1), &xpub, &origin));
+    }
+
+    let wallet = SparrowWallet {
+        label: format!("Vault ({})", vault.template().template_id()),
+        blockheight: 0,
+        descriptor,
+        keystores,
+    };
+    serde_json::to_string_pretty(&wallet).map_err(|e| CoreError::SerializationError(e.to_string()))
+}
+
+#[derive(Serialize)]
+struct SparrowWallet {
+    label: String,
+    blockheight: u32,
+    descriptor: String,
+    keystores: Vec<Keystore>,
+}
+
+#[derive(Serialize)]
+struct Keystore {
+    label: String,
+    fingerprint: String,
+    derivation: String,
+    xpub: String,
+}
+
+impl Keystore {
+    fn new(label: impl Into<String>, xpub: &ExtendedPubKey, (fingerprint, path): &KeySource) -> Self {
+        Keystore {
+            label: label.into(),
+            fingerprint: fingerprint.to_string(),
+            derivation: path.to_string(),
+            xpub: xpub.to_string(),
+        }
+    }
+}
+
+/// BIP388 wallet policy to register a vault on a Ledger device
+///
+/// Ledger devices show and sign script-path spends only for registered
+/// policies, and registration returns an HMAC the host keeps and passes
+/// back with every later request. The policy must be rebuilt byte for
+/// byte to match that HMAC, so everything here follows from the vault
+/// config alone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LedgerPolicy {
+    /// Name shown on the device during registration
+    pub name: String,
+    /// Descriptor template with `@i/**` key placeholders, see
+    /// `descriptor::policy_template()`
+    pub descriptor_template: String,
+    /// Key information for each placeholder, `[fingerprint/path]xpub` with
+    /// the key's origin for an account key (see `Vault::origin_of()`) and a
+    /// bare xpub for the NUMS internal key
+    pub keys: Vec<String>,
+}
+
+/// Ledger wallet policy for `vault`
+///
+/// Vaults whose tree uses a key twice, such as the owner key as both
+/// internal key and timelock key, and MuSig2 vaults can't be expressed
+/// as a wallet policy and are rejected with `InvalidInput`.
+pub fn ledger_policy(vault: &Vault) -> CoreResult<LedgerPolicy> {
+    if vault.musig_key().is_some() {
+        return Err(CoreError::InvalidInput(
+            "Wallet policies can't express a MuSig2 internal key".to_string(),
+        ));
+    }
+    let (descriptor_template, policy_keys) = descriptor::policy_template(
+        vault.template(),
+        vault.owner_xpub(),
+        vault.recovery_xpub(),
+        vault.network(),
+        vault.tree_version(),
+    )?;
+
+    let nums = keys::nums_xpub(vault.network());
+    let keys = policy_keys
+        .iter()
+        .map(|xpub| {
+            if *xpub == nums {
+                xpub.to_string()
+            } else {
+                let (fingerprint, path) = vault.origin_of(xpub);
+                format!("[{}{}]{}", fingerprint, &path.to_string()[1..], xpub)
+            }
+        })
+        .collect();
+
+    Ok(LedgerPolicy {
+        name: policy_name(vault.template()).to_string(),
+        descriptor_template,
+        keys,
+    })
+}
+
+/// Device-facing name of a template's policy
+fn policy_name(template: &VaultTemplate) -> &'static str {
+    match template {
+        VaultTemplate::Savings { .. } => "Savings vault",
+        VaultTemplate::Spending { .. } => "Spending vault",
+        VaultTemplate::Custom { .. } => "Custom vault",
+        VaultTemplate::Inheritance { .. } => "Inheritance vault",
+        VaultTemplate::DualDelay { .. } => "Dual delay vault",
+        VaultTemplate::Degrading { .. } => "Degrading vault",
+    }
+}
+
+/// Wallet import file for a Coldcard
+///
+/// The vault's descriptor with key origins on a line of its own, for the
+/// Coldcard's miniscript descriptor import. The legacy multisig setup
+/// file only describes P2SH and P2WSH k-of-n wallets, so multisig
+/// recovery vaults take the descriptor too.
+///
+/// MuSig2 vaults, trees of more than two leaves, and keys whose origin
+/// path doesn't reach the xpub's depth (an xpub below its master given
+/// without an origin) are refused with `PolicyViolation`.
+pub fn coldcard_file(vault: &Vault) -> CoreResult<String> {
+    if vault.musig_key().is_some() {
+        return Err(CoreError::PolicyViolation(
+            "Coldcard files can't express a MuSig2 internal key".to_string(),
+        ));
+    }
+    let leaf_count = vault.tree().leaves().len();
+    if leaf_count > 2 {
+        return Err(CoreError::PolicyViolation(format!(
+            "Coldcard files can't express a tree of {} leaves; at most 2 are supported",
+            leaf_count
+        )));
+    }
+
+    let descriptor = descriptor::to_core_descriptor_with_origins(
+        vault.template(),
+        (vault.owner_xpub(), vault.owner_origin()),
+        (vault.recovery_xpub(), vault.recovery_origin()),
+        vault.network(),
+        vault.tree_version(),
+    )?;
+
+    let mut origins = vec![
+        (*vault.owner_xpub(), vault.owner_origin().clone()),
+        (*vault.recovery_xpub(), vault.recovery_origin().clone()),
+    ];
+    for key in descriptor::cosigner_keys(vault.template()) {
+        origins.push(keys::parse_xpub_with_origin(key, vault.network())?);
+    }
+    for (xpub, (_, path)) in &origins {
+        if path.len() != xpub.depth as usize && descriptor.contains(&xpub.to_string()) {
+            return Err(CoreError::PolicyViolation(format!(
+                "Coldcard needs the key origin of {}, which is {} levels below its master",
+                xpub, xpub.depth
+            )));
+        }
+    }
+    Ok(format!("{}\n", descriptor))
+}
+
+/// One BIP329 label record
+///
+/// `reference` is written as `ref`: an address, a txid, or `txid:vout`
+/// for an output. `origin` optionally names the wallet the label came
+/// from, as an abbreviated descriptor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LabelEntry {
+    Addr {
+        #[serde(rename = "ref")]
+        reference: String,
+        #[serde(default)]
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+    },
+    Tx {
+        #[serde(rename = "ref")]
+        reference: String,
+        #[serde(default)]
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+    },
+    Output {
+        #[serde(rename = "ref")]
+        reference: String,
+        #[serde(default)]
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+        /// Whether the wallet may spend the output; absent means spendable
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        spendable: Option<bool>,
+    },
+}
+
+/// BIP329 JSON Lines export of `entries`, one record per line
+pub fn bip329(entries: &[LabelEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| serde_json::to_string(entry).expect("label records serialize") + "\n")
+        .collect()
+}
+
+/// Parse a BIP329 JSON Lines export
+///
+/// Blank lines and records of types other than `addr`, `tx` and
+/// `output` are skipped, as BIP329 asks of importers. A line that isn't
+/// a JSON object, or an `addr`/`tx`/`output` record missing its `ref`,
+/// fails with `SerializationError` naming its 1-based line number.
+pub fn import_bip329(jsonl: &str) -> CoreResult<Vec<LabelEntry>> {
+    let mut entries = Vec::new();
+    for (number, line) in jsonl.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |e: serde_json::Error| {
+            CoreError::SerializationError(format!("Invalid BIP329 record on line {}: {}", number, e))
+        };
+
+        let record: serde_json::Value = serde_json::from_str(line).map_err(invalid)?;
+        let known = match record.get("type").and_then(|t| t.as_str()) {
+            Some("addr" | "tx" | "output") => true,
+            // `pubkey`, `input`, `xpub` and types from later revisions
+            Some(_) => false,
+            None => {
+                return Err(CoreError::SerializationError(format!(
+                    "Invalid BIP329 record on line {}: missing \"type\"",
+                    number
+                )))
+            }
+        };
+        if known {
+            entries.push(serde_json::from_value(record).map_err(invalid)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// BIP21 payment URI for a deposit to `address`
+///
+/// `amount_sats` is written in BTC as a plain decimal with trailing
+/// zeros trimmed (1 sat is `0.00000001`). `label` and `message` are
+/// percent-encoded as UTF-8, leaving only RFC 3986 unreserved characters
+/// as they are. Missing or empty values omit their parameter.
+pub fn bip21_uri(address: &Address, amount_sats: Option<u64>, label: Option<&str>, message: Option<&str>) -> String {
+    let mut params = Vec::new();
+    if let Some(amount_sats) = amount_sats {
+        params.push(format!("amount={}", btc_amount(amount_sats)));
+    }
+    for (name, value) in [("label", label), ("message", message)] {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            params.push(format!("{}={}", name, percent_encode(value)));
+        }
+    }
+
+    let mut uri = format!("bitcoin:{}", address);
+    if !params.is_empty() {
+        uri.push('?');
+        uri.push_str(&params.join("&"));
+    }
+    uri
+}
+
+/// `sats` in BTC, computed in integers so no precision is lost
+fn btc_amount(sats: u64) -> String {
+    const SATS_PER_BTC: u64 = 100_000_000;
+
+    let whole = sats / SATS_PER_BTC;
+    let fraction = format!("{:08}", sats % SATS_PER_BTC);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, regtest_builder, CustomFields, OWNER_TPUB, RECOVERY_TPUB};
+    use crate::vault::{Network, RecoveryType, VaultBuilder};
+
+    fn latest_vault(template: VaultTemplate) -> Vault {
+        regtest_builder(template).build().unwrap()
+    }
+
+    #[test]
+    fn test_wallet_format_from_i32() {
+        assert_eq!(WalletFormat::try_from(0).unwrap(), WalletFormat::Sparrow);
+        assert_eq!(WalletFormat::try_from(1).unwrap(), WalletFormat::Ledger);
+        assert_eq!(WalletFormat::try_from(2).unwrap(), WalletFormat::Coldcard);
+        assert!(matches!(WalletFormat::try_from(3), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_sparrow_keystores_follow_leaves() {
+        let wallet = |template| -> serde_json::Value {
+            serde_json::from_str(&export_wallet(&latest_vault(template), WalletFormat::Sparrow).unwrap()).unwrap()
+        };
+
+        let spending = wallet(VaultTemplate::spending());
+        let labels: Vec<&str> = spending["keystores"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|keystore| keystore["label"].as_str().unwrap())
+            .collect();
+        assert_eq!(labels, ["Owner", "Recovery"]);
+
+        // A timelock-only vault's recovery key is in no leaf
+        let timelock_only = wallet(VaultTemplate::custom(4320, RecoveryType::TimelockOnly).unwrap());
+        assert_eq!(timelock_only["keystores"].as_array().unwrap().len(), 1);
+        assert!(!timelock_only["descriptor"].as_str().unwrap().contains(RECOVERY_TPUB));
+    }
+
+    #[test]
+    fn test_btc_amount_formatting() {
+        for (sats, expected) in [
+            (0, "0"),
+            (1, "0.00000001"),
+            (10, "0.0000001"),
+            (12_345, "0.00012345"),
+            (50_000_000, "0.5"),
+            (100_000_000, "1"),
+            (150_000_000, "1.5"),
+            (2_099_999_997_690_000, "20999999.9769"),
+            (2_100_000_000_000_000, "21000000"),
+            (2_100_000_000_000_001, "21000000.00000001"),
+            (u64::MAX, "184467440737.09551615"),
+        ] {
+            assert_eq!(btc_amount(sats), expected, "{} sats", sats);
+        }
+    }
+
+    #[test]
+    fn test_bip21_uri() {
+        let address = latest_vault(VaultTemplate::spending()).address();
+        let uri = |amount, label, message| bip21_uri(&address, amount, label, message);
+
+        assert_eq!(uri(None, None, None), format!("bitcoin:{}", address));
+        assert_eq!(uri(Some(1), None, None), format!("bitcoin:{}?amount=0.00000001", address));
+        assert_eq!(uri(None, Some(""), Some("")), format!("bitcoin:{}", address));
+        assert_eq!(
+            uri(Some(250_000_000), Some("Cold storage"), Some("Deposit #1 & more")),
+            format!(
+                "bitcoin:{}?amount=2.5&label=Cold%20storage&message=Deposit%20%231%20%26%20more",
+                address
+            )
+        );
+        assert_eq!(uri(None, Some("Épargne"), None), format!("bitcoin:{}?label=%C3%89pargne", address));
+    }
+
+    #[test]
+    fn test_ledger_policy_placeholders() {
+        let policy = ledger_policy(&latest_vault(VaultTemplate::spending())).unwrap();
+        assert_eq!(policy.name, "Spending vault");
+        assert_eq!(policy.descriptor_template, "tr(@0/**,{and_v(v:older(144),pk(@1/**)),pk(@2/**)})");
+        assert_eq!(
+            policy.keys,
+            [
+                keys::nums_xpub(Network::Regtest).to_string(),
+                format!("[3442193e]{}", OWNER_TPUB),
+                format!("[bd16bee5]{}", RECOVERY_TPUB),
+            ]
+        );
+
+        // Without an emergency leaf the recovery key has no placeholder
+        let timelock_only = ledger_policy(&latest_vault(VaultTemplate::custom(4320, RecoveryType::TimelockOnly).unwrap())).unwrap();
+        assert_eq!(timelock_only.descriptor_template, "tr(@0/**,and_v(v:older(4320),pk(@1/**)))");
+        assert_eq!(timelock_only.keys.len(), 2);
+    }
+
+    /// Accounts 0 and 1 of "abandon ... about" on regtest, with their
+    /// `[73c5da0a/86'/1'/n']` origins
+    fn origin_keys() -> (String, String) {
+        let master = keys::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+            Network::Regtest,
+        )
+        .unwrap();
+        let account = |n| {
+            let xpriv = keys::account_xpriv(&master, keys::TAPROOT_PURPOSE, n, Network::Regtest).unwrap();
+            let xpub = ExtendedPubKey::from_priv(&bitcoin::secp256k1::Secp256k1::new(), &xpriv.xpriv().unwrap());
+            format!("[73c5da0a/86'/1'/{}']{}", n, xpub)
+        };
+        (account(0), account(1))
+    }
+
+    #[test]
+    fn test_exports_use_key_origins() {
+        let (owner, recovery) = origin_keys();
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::spending())
+            .owner_xpub(format!("{}/0/*", owner))
+            .recovery_xpub(recovery.clone())
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+
+        let wallet: serde_json::Value =
+            serde_json::from_str(&export_wallet(&vault, WalletFormat::Sparrow).unwrap()).unwrap();
+        let descriptor = wallet["descriptor"].as_str().unwrap();
+        assert!(descriptor.contains(&format!("pk({}/0/*)", owner)), "{}", descriptor);
+        assert!(descriptor.contains(&format!("pk({}/0/*)", recovery)), "{}", descriptor);
+        assert_eq!(wallet["keystores"][0]["fingerprint"], "73c5da0a");
+        assert_eq!(wallet["keystores"][0]["derivation"], "m/86'/1'/0'");
+        assert_eq!(wallet["keystores"][1]["derivation"], "m/86'/1'/1'");
+        assert_eq!(coldcard_file(&vault).unwrap(), format!("{}\n", descriptor));
+
+        let policy = ledger_policy(&vault).unwrap();
+        assert_eq!(policy.keys[1..], [owner.clone(), recovery.clone()]);
+
+        // Multisig recovery vaults get their descriptor as well
+        let multisig = |cosigners| custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(crate::vault::MultisigRecovery { threshold: 1, cosigners }),
+            ..Default::default()
+        });
+        let file = coldcard_file(&latest_vault(multisig(vec![owner.clone(), recovery.clone()]))).unwrap();
+        assert!(file.contains(&format!("sortedmulti_a(1,{}/0/*,{}/0/*)", owner, recovery)), "{}", file);
+    }
+
+    #[test]
+    fn test_ledger_policy_rejects_repeated_keys() {
+        let key_path = custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            key_path_enabled: true,
+            ..Default::default()
+        });
+        assert!(matches!(ledger_policy(&latest_vault(key_path)), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_coldcard_file_refuses_unrepresentable_templates() {
+        let dual_delay = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        let err = coldcard_file(&latest_vault(dual_delay)).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(ref message) if message.contains("3 leaves")), "{}", err);
+
+        // A cosigner below its master without an origin can't be placed
+        let owner = keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap();
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let bare = owner.ckd_pub(&secp, bitcoin::bip32::ChildNumber::from_normal_idx(1).unwrap()).unwrap();
+        let no_origin = custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(crate::vault::MultisigRecovery { threshold: 1, cosigners: vec![bare.to_string()] }),
+            ..Default::default()
+        });
+        let err = coldcard_file(&latest_vault(no_origin)).unwrap_err();
+        assert!(
+            matches!(err, CoreError::PolicyViolation(ref message) if message.contains(&bare.to_string())),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_bip329_roundtrip() {
+        let mut entries = latest_vault(VaultTemplate::spending()).default_labels(2).unwrap();
+        entries.push(LabelEntry::Tx {
+            reference: "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd".to_string(),
+            label: "Unvault \"rent\"".to_string(),
+            origin: Some("tr([3442193e])".to_string()),
+        });
+        entries.push(LabelEntry::Output {
+            reference: "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1".to_string(),
+            label: String::new(),
+            origin: None,
+            spendable: Some(false),
+        });
+
+        let jsonl = bip329(&entries);
+        assert_eq!(jsonl.lines().count(), entries.len());
+        assert!(jsonl.ends_with('\n'));
+        let first: serde_json::Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(first["type"], "addr");
+        assert_eq!(first["label"], "Vault #0 deposit");
+        assert!(first.get("origin").is_none());
+
+        assert_eq!(import_bip329(&jsonl).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_default_labels_follow_vault_indexes() {
+        let vault = latest_vault(VaultTemplate::savings());
+        let labels = vault.default_labels(4).unwrap();
+        assert_eq!(labels.len(), 5);
+        for (index, entry) in labels.iter().enumerate() {
+            let LabelEntry::Addr { reference, label, .. } = entry else {
+                panic!("expected an addr record, got {:?}", entry);
+            };
+            assert_eq!(*reference, vault.tree_at(index as u32).unwrap().address(Network::Regtest).to_string());
+            assert_eq!(*label, format!("Vault #{} deposit", index));
+        }
+    }
+
+    #[test]
+    fn test_import_bip329_skips_unknown_types() {
+        let jsonl = r#"{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Owner"}
+
+{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Deposit"}
+{"type":"future-type","ref":"?","label":"?"}
+{"type":"input","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Spent"}
+"#;
+        assert_eq!(
+            import_bip329(jsonl).unwrap(),
+            [LabelEntry::Tx {
+                reference: "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd".to_string(),
+                label: "Deposit".to_string(),
+                origin: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_import_bip329_reports_line_numbers() {
+        let valid = r#"{"type":"addr","ref":"bcrt1q","label":"a"}"#;
+        for (bad_line, expected_line) in [
+            ("{not json", 2),
+            (r#"{"ref":"bcrt1q","label":"no type"}"#, 2),
+            (r#"{"type":"addr","label":"no ref"}"#, 2),
+            (r#"["type","addr"]"#, 2),
+        ] {
+            let jsonl = format!("{}\n{}\n{}\n", valid, bad_line, valid);
+            let err = import_bip329(&jsonl).unwrap_err();
+            assert!(
+                matches!(err, CoreError::SerializationError(ref message) if message.contains(&format!("line {}", expected_line))),
+                "{}: {}",
+                bad_line,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,896 @@
+//! Size and fee estimation for vault spend transactions
+
+use std::fmt;
+
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::psbt::Psbt;
+use bitcoin::taproot::{
+    TAPROOT_ANNEX_PREFIX, TAPROOT_CONTROL_BASE_SIZE, TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
+    TAPROOT_LEAF_MASK, TAPROOT_LEAF_TAPSCRIPT,
+};
+use bitcoin::{Script, Transaction, TxOut, VarInt, Witness};
+use serde::{Deserialize, Serialize};
+
+use crate::error::CoreError;
+use crate::taproot::{self, LeafId, VaultLeaf, VaultTree, HASHLOCK_PREIMAGE_LEN};
+
+/// Lowest fee rate, in sat/vB, that nodes relay by default
+pub const MIN_RELAY_FEE_RATE: u64 = 1;
+
+/// Fee rate, in sat/vB, a replacement must add over the transaction it replaces
+pub const INCREMENTAL_RELAY_FEE_RATE: u64 = 1;
+
+/// Size of a BIP340 signature with the default sighash type
+pub const SCHNORR_SIG_SIZE: usize = 64;
+
+/// Depth of a spending leaf in a vault tree with a recovery leaf
+pub const VAULT_LEAF_DEPTH: usize = 1;
+
+/// Length of a P2TR scriptPubKey: OP_1 <32-byte output key>
+pub(crate) const P2TR_SCRIPT_PUBKEY_LEN: usize = 34;
+
+/// Largest transaction weight nodes relay by default
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Largest OP_RETURN payload nodes relay by default
+pub const MAX_OP_RETURN_PAYLOAD: usize = 80;
+
+/// OP_RETURN output script carrying `MAX_OP_RETURN_PAYLOAD` bytes:
+/// OP_RETURN OP_PUSHDATA1 <len> <payload>
+const MAX_OP_RETURN_SCRIPT_LEN: usize = MAX_OP_RETURN_PAYLOAD + 3;
+
+/// Largest scriptSig nodes relay
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1_650;
+
+/// Largest witness stack item, other than a script or control block, nodes relay
+const MAX_STANDARD_STACK_ITEM_SIZE: usize = 80;
+
+/// Largest P2WSH witness script nodes relay
+const MAX_STANDARD_WITNESS_SCRIPT_SIZE: usize = 3_600;
+
+/// Most P2WSH witness stack items, other than the script, nodes relay
+const MAX_STANDARD_WITNESS_STACK_ITEMS: usize = 100;
+
+/// Outpoint (36) + empty scriptSig (1) + sequence (4), at 4 WU per byte
+const TXIN_BASE_WEIGHT: usize = 41 * 4;
+
+/// Largest DER-encoded ECDSA signature, with its sighash type byte
+const ECDSA_SIG_MAX_SIZE: usize = 72;
+
+/// Size of a compressed public key
+const COMPRESSED_KEY_SIZE: usize = 33;
+
+/// Timelock leaf script with the longest delay push:
+/// <delay> (4) OP_CSV OP_VERIFY <key> (33) OP_CHECKSIG
+const MAX_TIMELOCK_SCRIPT_LEN: usize = 4 + 1 + 1 + 33 + 1;
+
+/// Emergency leaf script: <key> (33) OP_CHECKSIG
+const EMERGENCY_SCRIPT_LEN: usize = 33 + 1;
+
+/// Absolute lock leaf script with the longest lock push:
+/// <lock> (6) OP_CLTV OP_VERIFY <key> (33) OP_CHECKSIG
+const MAX_ABSOLUTE_LOCK_SCRIPT_LEN: usize = 6 + 1 + 1 + 33 + 1;
+
+/// Hashlock leaf script with the `TreeVersion::V4` length check:
+/// OP_SIZE <32> (2) OP_EQUALVERIFY OP_SHA256 <hash> (33) OP_EQUALVERIFY <key> (33) OP_CHECKSIG
+const MAX_HASHLOCK_SCRIPT_LEN: usize = 1 + 2 + 1 + 1 + 33 + 1 + 33 + 1;
+
+/// How a vault input is spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpendPath {
+    /// Single signature against the output key
+    KeyPath,
+    /// Owner signature through the timelock leaf
+    TimelockLeaf,
+    /// Recovery signature through the emergency leaf
+    EmergencyLeaf,
+    /// Recovery signature through the absolute lock leaf
+    AbsoluteLockLeaf,
+    /// Service signature and preimage through the hashlock leaf
+    HashlockLeaf,
+    /// `threshold` signatures through a CHECKSIGADD leaf of `total` keys
+    MultisigLeaf { threshold: usize, total: usize },
+}
+
+/// Weight of one input spent through `path`, with its leaf at `control_block_depth`
+///
+/// Leaf scripts are sized for the worst case (the longest delay push for
+/// the timelock leaf), so the estimate never falls short. The depth is
+/// ignored for key-path spends.
+pub fn input_weight(path: SpendPath, control_block_depth: usize) -> usize {
+    let control_block_len = 33 + 32 * control_block_depth;
+    match path {
+        SpendPath::KeyPath => {
+            TXIN_BASE_WEIGHT + VarInt(1).len() + 1 + SCHNORR_SIG_SIZE
+        }
+        SpendPath::TimelockLeaf => {
+            script_path_weight(1, 0, MAX_TIMELOCK_SCRIPT_LEN, control_block_len, false)
+        }
+        SpendPath::EmergencyLeaf => {
+            script_path_weight(1, 0, EMERGENCY_SCRIPT_LEN, control_block_len, false)
+        }
+        SpendPath::AbsoluteLockLeaf => {
+            script_path_weight(1, 0, MAX_ABSOLUTE_LOCK_SCRIPT_LEN, control_block_len, false)
+        }
+        SpendPath::HashlockLeaf => {
+            script_path_weight(1, 0, MAX_HASHLOCK_SCRIPT_LEN, control_block_len, true)
+        }
+        SpendPath::MultisigLeaf { threshold, total } => {
+            // <key> OP_CHECKSIG, then <key> OP_CHECKSIGADD per extra key, <k> OP_NUMEQUAL
+            let threshold_push = Builder::new().push_int(threshold as i64).into_script().len();
+            let script_len = total * 34 + threshold_push + 1;
+            script_path_weight(threshold, total.saturating_sub(threshold), script_len, control_block_len, false)
+        }
+    }
+}
+
+/// Weight of a single-signature wallet input spending `script_pubkey`
+///
+/// P2WPKH inputs are sized for the longest ECDSA signature, P2TR inputs
+/// as key-path spends. Other output types fail with `InvalidInput`.
+pub fn wallet_input_weight(script_pubkey: &Script) -> Result<usize, CoreError> {
+    if script_pubkey.is_v0_p2wpkh() {
+        // <signature> <compressed key>
+        Ok(TXIN_BASE_WEIGHT + VarInt(2).len() + 1 + ECDSA_SIG_MAX_SIZE + 1 + COMPRESSED_KEY_SIZE)
+    } else if script_pubkey.is_v1_p2tr() {
+        Ok(input_weight(SpendPath::KeyPath, 0))
+    } else {
+        Err(CoreError::InvalidInput(format!(
+            "Only P2WPKH and P2TR wallet outputs can be spent, not {}",
+            script_pubkey
+        )))
+    }
+}
+
+/// Exact weight of an input spending `leaf` of `tree`, with signatures in place
+///
+/// `spend_weight()` with as many signatures as the leaf requires.
+pub fn leaf_input_weight(tree: &VaultTree, leaf: LeafId) -> Result<usize, CoreError> {
+    let signers = taproot::leaf_signers(&tree_leaf(tree, leaf)?.script).ok_or_else(unrecognized_leaf)?;
+    spend_weight(tree, leaf, signers.threshold)
+}
+
+/// Exact weight of an input spending `leaf` of `tree` with `n_sigs` signatures
+///
+/// Sized from the leaf script and the control block for the leaf's depth
+/// in this tree, with an empty push for every key that doesn't sign and
+/// the preimage of a hashlock leaf. Fails with `InvalidInput` if the leaf
+/// has fewer than `n_sigs` keys.
+pub fn spend_weight(tree: &VaultTree, leaf: LeafId, n_sigs: usize) -> Result<usize, CoreError> {
+    let script = &tree_leaf(tree, leaf)?.script;
+    let control_block_len = taproot::control_block(tree, leaf)?.size();
+    signed_script_weight(script, control_block_len, n_sigs)
+}
+
+/// Exact weight of an input spending a vault leaf `script`, with signatures in place
+///
+/// Counts a 64-byte signature for each required signer and an empty
+/// push for every other key in the leaf, plus the preimage of a hashlock
+/// leaf.
+pub fn script_input_weight(script: &Script, control_block_len: usize) -> Result<usize, CoreError> {
+    let signers = taproot::leaf_signers(script).ok_or_else(unrecognized_leaf)?;
+    signed_script_weight(script, control_block_len, signers.threshold)
+}
+
+fn tree_leaf(tree: &VaultTree, leaf: LeafId) -> Result<&VaultLeaf, CoreError> {
+    tree.leaf(leaf)
+        .ok_or_else(|| CoreError::PsbtError(format!("Vault tree has no {:?} leaf", leaf)))
+}
+
+fn unrecognized_leaf() -> CoreError {
+    CoreError::PsbtError("Unrecognized vault leaf script".to_string())
+}
+
+/// Weight of an input spending `script` with `n_sigs` of its keys signing
+fn signed_script_weight(script: &Script, control_block_len: usize, n_sigs: usize) -> Result<usize, CoreError> {
+    let signers = taproot::leaf_signers(script).ok_or_else(unrecognized_leaf)?;
+    if n_sigs > signers.keys.len() {
+        return Err(CoreError::InvalidInput(format!(
+            "Leaf has {} keys, cannot carry {} signatures",
+            signers.keys.len(),
+            n_sigs
+        )));
+    }
+
+    Ok(script_path_weight(
+        n_sigs,
+        signers.keys.len() - n_sigs,
+        script.len(),
+        control_block_len,
+        taproot::leaf_hashlock(script).is_some(),
+    ))
+}
+
+/// Weight of a script-path input: signatures, empty pushes, preimage, script, control block
+fn script_path_weight(sigs: usize, empty: usize, script_len: usize, control_block_len: usize, preimage: bool) -> usize {
+    let preimage_items = usize::from(preimage);
+    let witness_items = sigs + empty + preimage_items + 2;
+    let witness_size = VarInt(witness_items as u64).len()
+        + sigs * (1 + SCHNORR_SIG_SIZE)
+        + empty
+        + preimage_items * (1 + HASHLOCK_PREIMAGE_LEN)
+        + VarInt(script_len as u64).len()
+        + script_len
+        + VarInt(control_block_len as u64).len()
+        + control_block_len;
+
+    TXIN_BASE_WEIGHT + witness_size
+}
+
+/// Weight of a segwit transaction with the given input weights and output scriptPubKey lengths
+pub fn tx_weight(input_weights: &[usize], output_script_lens: &[usize]) -> usize {
+    let output_size: usize = output_script_lens
+        .iter()
+        .map(|len| 8 + VarInt(*len as u64).len() + len)
+        .sum();
+    // version + locktime + input/output counts, plus the segwit marker and flag
+    let base_size =
+        8 + VarInt(input_weights.len() as u64).len() + VarInt(output_script_lens.len() as u64).len();
+
+    (base_size + output_size) * 4 + 2 + input_weights.iter().sum::<usize>()
+}
+
+/// Virtual size of `weight`, rounded up
+pub fn weight_to_vsize(weight: usize) -> u64 {
+    (weight as u64).div_ceil(4)
+}
+
+/// Estimated vsize of a transaction spending `n_inputs` vault inputs
+/// through `spend_path` to `n_outputs` P2TR outputs
+///
+/// Script-path leaves are assumed to sit at `VAULT_LEAF_DEPTH`, as in
+/// every template with a recovery leaf.
+pub fn estimate_vsize(n_inputs: usize, spend_path: SpendPath, n_outputs: usize) -> u64 {
+    let input_weights = vec![input_weight(spend_path, VAULT_LEAF_DEPTH); n_inputs];
+    let output_script_lens = vec![P2TR_SCRIPT_PUBKEY_LEN; n_outputs];
+    weight_to_vsize(tx_weight(&input_weights, &output_script_lens))
+}
+
+/// Fee for `vsize` vbytes at `fee_rate_sat_vb`
+///
+/// Rates below `MIN_RELAY_FEE_RATE` are rejected with `PolicyViolation`,
+/// since such transactions would not propagate.
+pub fn estimate_fee(vsize: u64, fee_rate_sat_vb: u64) -> Result<u64, CoreError> {
+    if fee_rate_sat_vb < MIN_RELAY_FEE_RATE {
+        return Err(CoreError::PolicyViolation(format!(
+            "Fee rate of {} sat/vB is below the minimum relay fee rate of {} sat/vB",
+            fee_rate_sat_vb, MIN_RELAY_FEE_RATE
+        )));
+    }
+    vsize
+        .checked_mul(fee_rate_sat_vb)
+        .ok_or_else(|| CoreError::InvalidInput(format!("Fee rate of {} sat/vB is too high", fee_rate_sat_vb)))
+}
+
+/// A transaction for `check_standardness()`
+#[derive(Debug, Clone, Copy)]
+pub enum TxOrPsbt<'a> {
+    /// A transaction with its witnesses in place
+    Tx(&'a Transaction),
+    /// A PSBT, checked as the transaction its signers will produce
+    Psbt(&'a Psbt),
+}
+
+impl<'a> From<&'a Transaction> for TxOrPsbt<'a> {
+    fn from(tx: &'a Transaction) -> Self {
+        TxOrPsbt::Tx(tx)
+    }
+}
+
+impl<'a> From<&'a Psbt> for TxOrPsbt<'a> {
+    fn from(psbt: &'a Psbt) -> Self {
+        TxOrPsbt::Psbt(psbt)
+    }
+}
+
+/// A standardness rule a transaction breaks, so that nodes won't relay it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StandardnessViolation {
+    /// Heavier than `MAX_STANDARD_TX_WEIGHT`
+    TxWeight { weight: usize, max: usize },
+    /// scriptSig of `input` is `size` bytes
+    ScriptSigSize { input: usize, size: usize, max: usize },
+    /// Witness stack item `item` of `input` is `size` bytes
+    WitnessItemSize { input: usize, item: usize, size: usize, max: usize },
+    /// P2WSH witness script of `input` is `size` bytes
+    WitnessScriptSize { input: usize, size: usize, max: usize },
+    /// P2WSH witness of `input` has `count` stack items besides its script
+    WitnessItemCount { input: usize, count: usize, max: usize },
+    /// Taproot witness of `input` carries an annex
+    Annex { input: usize },
+    /// `output` pays less than its `dust_sats` dust limit
+    DustOutput { output: usize, value_sats: u64, dust_sats: u64 },
+    /// OP_RETURN `output` carries `payload_len` bytes
+    OpReturnSize { output: usize, payload_len: usize, max: usize },
+    /// More than one OP_RETURN output
+    MultipleOpReturns { count: usize },
+}
+
+impl fmt::Display for StandardnessViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TxWeight { weight, max } => write!(f, "Transaction weight of {} WU exceeds {} WU", weight, max),
+            Self::ScriptSigSize { input, size, max } => {
+                write!(f, "Input {} has a {}-byte scriptSig, over {} bytes", input, size, max)
+            }
+            Self::WitnessItemSize { input, item, size, max } => write!(
+                f,
+                "Input {} has a {}-byte witness item at position {}, over {} bytes",
+                input, size, item, max
+            ),
+            Self::WitnessScriptSize { input, size, max } => {
+                write!(f, "Input {} has a {}-byte witness script, over {} bytes", input, size, max)
+            }
+            Self::WitnessItemCount { input, count, max } => {
+                write!(f, "Input {} has {} witness items, over {}", input, count, max)
+            }
+            Self::Annex { input } => write!(f, "Input {} has a taproot annex", input),
+            Self::DustOutput { output, value_sats, dust_sats } => write!(
+                f,
+                "Output {} pays {} sats, below its dust limit of {} sats",
+                output, value_sats, dust_sats
+            ),
+            Self::OpReturnSize { output, payload_len, max } => write!(
+                f,
+                "OP_RETURN output {} carries {} bytes, over {} bytes",
+                output, payload_len, max
+            ),
+            Self::MultipleOpReturns { count } => write!(f, "Transaction has {} OP_RETURN outputs", count),
+        }
+    }
+}
+
+/// Smallest value the PSBT builders let an output carry
+///
+/// Nodes won't relay an output below its dust limit, 330 sats for P2TR.
+/// Deployments that refuse small outputs outright can set a higher floor,
+/// which then applies to destinations and change alike. Zero-value
+/// OP_RETURN memo outputs are exempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DustPolicy {
+    /// Each output's relay dust limit
+    #[default]
+    Relay,
+    /// At least this many sats, and never less than the relay dust limit
+    Floor(u64),
+}
+
+impl DustPolicy {
+    /// Policy for a configured `dust_limit_sats`, `Relay` without one
+    pub fn from_limit(dust_limit_sats: Option<u64>) -> Self {
+        dust_limit_sats.map_or(DustPolicy::Relay, DustPolicy::Floor)
+    }
+
+    /// Smallest value an output paying to `script_pubkey` may carry
+    pub fn limit(&self, script_pubkey: &Script) -> u64 {
+        if script_pubkey.is_op_return() {
+            return 0;
+        }
+        let relay = script_pubkey.dust_value().to_sat();
+        match *self {
+            DustPolicy::Relay => relay,
+            DustPolicy::Floor(floor) => relay.max(floor),
+        }
+    }
+
+    /// Fail with `PolicyViolation`, naming `output` and its value, if
+    /// `txout` is below its limit
+    pub fn check_output(&self, output: usize, txout: &TxOut) -> Result<(), CoreError> {
+        let dust_sats = self.limit(&txout.script_pubkey);
+        if txout.value < dust_sats {
+            let violation = StandardnessViolation::DustOutput {
+                output,
+                value_sats: txout.value,
+                dust_sats,
+            };
+            return Err(CoreError::PolicyViolation(violation.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Result of `check_standardness()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StandardnessReport {
+    /// Weight of the checked transaction, in WU
+    pub weight: usize,
+    /// Every rule broken, in input then output order after the weight
+    pub violations: Vec<StandardnessViolation>,
+}
+
+impl StandardnessReport {
+    /// Whether nodes with default policy would relay the transaction
+    pub fn is_standard(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Check a transaction against Bitcoin Core's default relay policy
+///
+/// Covers the transaction weight, scriptSig and witness item sizes,
+/// output dust limits, and the size and count of OP_RETURN outputs,
+/// reporting every violation rather than stopping at the first.
+/// Witnesses are classified by shape: one ending in a taproot control
+/// block is a script-path spend, any other of two or more items is
+/// treated as P2WSH.
+///
+/// A PSBT is checked before it is signed: inputs without a final
+/// witness get one of the size their signers will produce, using the
+/// heaviest of their leaves so the weight never falls short. Inputs
+/// with neither a final witness nor taproot signing data are counted
+/// without a witness.
+pub fn check_standardness<'a>(tx: impl Into<TxOrPsbt<'a>>) -> Result<StandardnessReport, CoreError> {
+    let projected;
+    let tx = match tx.into() {
+        TxOrPsbt::Tx(tx) => tx,
+        TxOrPsbt::Psbt(psbt) => {
+            projected = projected_tx(psbt)?;
+            &projected
+        }
+    };
+
+    let weight = tx.weight().to_wu() as usize;
+    let mut violations = Vec::new();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        violations.push(StandardnessViolation::TxWeight {
+            weight,
+            max: MAX_STANDARD_TX_WEIGHT,
+        });
+    }
+
+    for (input, txin) in tx.input.iter().enumerate() {
+        let size = txin.script_sig.len();
+        if size > MAX_STANDARD_SCRIPTSIG_SIZE {
+            violations.push(StandardnessViolation::ScriptSigSize {
+                input,
+                size,
+                max: MAX_STANDARD_SCRIPTSIG_SIZE,
+            });
+        }
+        check_witness(input, &txin.witness, &mut violations);
+    }
+
+    let mut op_returns = 0;
+    for (output, txout) in tx.output.iter().enumerate() {
+        let script = &txout.script_pubkey;
+        if script.is_op_return() {
+            op_returns += 1;
+            if script.len() > MAX_OP_RETURN_SCRIPT_LEN {
+                violations.push(StandardnessViolation::OpReturnSize {
+                    output,
+                    payload_len: op_return_payload_len(script),
+                    max: MAX_OP_RETURN_PAYLOAD,
+                });
+            }
+            continue;
+        }
+        let dust_sats = script.dust_value().to_sat();
+        if txout.value < dust_sats {
+            violations.push(StandardnessViolation::DustOutput {
+                output,
+                value_sats: txout.value,
+                dust_sats,
+            });
+        }
+    }
+    if op_returns > 1 {
+        violations.push(StandardnessViolation::MultipleOpReturns { count: op_returns });
+    }
+
+    Ok(StandardnessReport { weight, violations })
+}
+
+fn check_witness(input: usize, witness: &Witness, violations: &mut Vec<StandardnessViolation>) {
+    let mut items: Vec<&[u8]> = witness.iter().collect();
+    if items.len() < 2 {
+        return;
+    }
+
+    let has_annex = items.last().is_some_and(|last| last.first() == Some(&TAPROOT_ANNEX_PREFIX));
+    let is_script_path = |items: &[&[u8]]| items.len() >= 2 && items.last().is_some_and(|last| is_control_block(last));
+    if has_annex && is_script_path(&items[..items.len() - 1]) {
+        violations.push(StandardnessViolation::Annex { input });
+        items.pop();
+    }
+
+    // Tapscript only limits the stack items; P2WSH also limits the script
+    // and item count
+    let stack = if is_script_path(&items) {
+        &items[..items.len() - 2]
+    } else {
+        let (script, stack) = items.split_last().expect("at least two items");
+        if script.len() > MAX_STANDARD_WITNESS_SCRIPT_SIZE {
+            violations.push(StandardnessViolation::WitnessScriptSize {
+                input,
+                size: script.len(),
+                max: MAX_STANDARD_WITNESS_SCRIPT_SIZE,
+            });
+        }
+        if stack.len() > MAX_STANDARD_WITNESS_STACK_ITEMS {
+            violations.push(StandardnessViolation::WitnessItemCount {
+                input,
+                count: stack.len(),
+                max: MAX_STANDARD_WITNESS_STACK_ITEMS,
+            });
+        }
+        stack
+    };
+    for (item, bytes) in stack.iter().enumerate() {
+        if bytes.len() > MAX_STANDARD_STACK_ITEM_SIZE {
+            violations.push(StandardnessViolation::WitnessItemSize {
+                input,
+                item,
+                size: bytes.len(),
+                max: MAX_STANDARD_STACK_ITEM_SIZE,
+            });
+        }
+    }
+}
+
+/// Whether `item` is shaped like a BIP341 control block for a tapscript leaf
+fn is_control_block(item: &[u8]) -> bool {
+    let Some(path_len) = item.len().checked_sub(TAPROOT_CONTROL_BASE_SIZE) else {
+        return false;
+    };
+    path_len % TAPROOT_CONTROL_NODE_SIZE == 0
+        && path_len / TAPROOT_CONTROL_NODE_SIZE <= TAPROOT_CONTROL_MAX_NODE_COUNT
+        && item[0] & TAPROOT_LEAF_MASK == TAPROOT_LEAF_TAPSCRIPT
+}
+
+/// Bytes pushed by an OP_RETURN output, or everything after the opcode
+/// if it isn't push-only
+fn op_return_payload_len(script: &Script) -> usize {
+    script
+        .instructions()
+        .skip(1)
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(bytes.len()),
+            _ => None,
+        })
+        .sum::<Option<usize>>()
+        .unwrap_or(script.len() - 1)
+}
+
+/// `psbt`'s transaction with placeholder witnesses the size of the ones
+/// its signers will produce
+fn projected_tx(psbt: &Psbt) -> Result<Transaction, CoreError> {
+    if psbt.inputs.len() != psbt.unsigned_tx.input.len() {
+        return Err(CoreError::PsbtError(format!(
+            "PSBT has {} inputs but its transaction has {}",
+            psbt.inputs.len(),
+            psbt.unsigned_tx.input.len()
+        )));
+    }
+
+    let mut tx = psbt.unsigned_tx.clone();
+    for (txin, input) in tx.input.iter_mut().zip(&psbt.inputs) {
+        if let Some(script_sig) = &input.final_script_sig {
+            txin.script_sig = script_sig.clone();
+        }
+        txin.witness = if let Some(witness) = &input.final_script_witness {
+            witness.clone()
+        } else if let Some(sig) = &input.tap_key_sig {
+            Witness::from_slice(&[sig.to_vec()])
+        } else if let Some(witness) = heaviest_leaf_witness(input) {
+            witness
+        } else if input.tap_internal_key.is_some() {
+            Witness::from_slice(&[[0u8; SCHNORR_SIG_SIZE]])
+        } else {
+            Witness::new()
+        };
+    }
+    Ok(tx)
+}
+
+/// Placeholder for the heaviest script-path witness among `input`'s
+/// leaves: a signature for each required signer, an empty push for every
+/// other key, then the script and control block
+fn heaviest_leaf_witness(input: &bitcoin::psbt::Input) -> Option<Witness> {
+    input
+        .tap_scripts
+        .iter()
+        .map(|(control_block, (script, _))| {
+            let mut stack: Vec<Vec<u8>> = match taproot::leaf_signers(script) {
+                Some(signers) => {
+                    let mut stack = vec![vec![0u8; SCHNORR_SIG_SIZE]; signers.threshold];
+                    stack.resize(signers.keys.len(), vec![]);
+                    stack
+                }
+                None => vec![],
+            };
+            stack.push(script.to_bytes());
+            stack.push(control_block.serialize());
+            Witness::from_slice(&stack)
+        })
+        .max_by_key(|witness| witness.serialized_len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, CustomFields, OWNER_TPUB, RECOVERY_TPUB};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::bip32::{ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::hashes::Hash;
+    use bitcoin::script::PushBytesBuf;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{OutPoint, ScriptBuf, TxIn, TxOut};
+
+    use crate::keys;
+    use crate::taproot::LeafPurpose;
+    use crate::vault::{MultisigRecovery, Network, RecoveryType, VaultTemplate};
+
+    fn tree(template: &VaultTemplate) -> VaultTree {
+        let owner = keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap();
+        let recovery = keys::parse_xpub(RECOVERY_TPUB, Network::Regtest).unwrap();
+        taproot::vault_tree(template, &owner, &recovery, 0, Network::Regtest).unwrap()
+    }
+
+    /// Vault tree with 2-of-3 multisig recovery
+    fn multisig_tree() -> VaultTree {
+        tree(&custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery {
+                threshold: 2,
+                cosigners: (1..=3u8)
+                    .map(|seed| {
+                        let xpriv = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[seed; 32]).unwrap();
+                        ExtendedPubKey::from_priv(&Secp256k1::new(), &xpriv).to_string()
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_generic_weight_bounds_exact_leaf_weight() {
+        let spending = tree(&VaultTemplate::spending());
+        for (path, leaf) in [
+            (SpendPath::TimelockLeaf, LeafPurpose::Timelock),
+            (SpendPath::EmergencyLeaf, LeafPurpose::Emergency),
+        ] {
+            let exact = leaf_input_weight(&spending, leaf).unwrap();
+            let generic = input_weight(path, VAULT_LEAF_DEPTH);
+            assert!(generic >= exact && generic - exact <= 4, "{:?}: {} vs {}", path, generic, exact);
+        }
+
+        let multisig = multisig_tree();
+        assert_eq!(
+            input_weight(SpendPath::MultisigLeaf { threshold: 2, total: 3 }, VAULT_LEAF_DEPTH),
+            leaf_input_weight(&multisig, LeafPurpose::Multisig).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_spend_weight_counts_signatures() {
+        let multisig = multisig_tree();
+        let required = spend_weight(&multisig, LeafPurpose::Multisig, 2).unwrap();
+        assert_eq!(required, leaf_input_weight(&multisig, LeafPurpose::Multisig).unwrap());
+        // A signature replaces an empty push: 64 bytes more
+        assert_eq!(spend_weight(&multisig, LeafPurpose::Multisig, 3).unwrap(), required + SCHNORR_SIG_SIZE);
+        assert_eq!(spend_weight(&multisig, LeafPurpose::Multisig, 1).unwrap(), required - SCHNORR_SIG_SIZE);
+        assert!(matches!(spend_weight(&multisig, LeafPurpose::Multisig, 4), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(spend_weight(&multisig, LeafPurpose::Emergency, 1), Err(CoreError::PsbtError(_))));
+
+        // The control block is sized for the leaf's depth in this tree
+        let depth = taproot::control_block(&multisig, LeafPurpose::Timelock).unwrap().merkle_branch.as_inner().len();
+        assert_eq!(
+            spend_weight(&multisig, LeafPurpose::Timelock, 1).unwrap(),
+            script_input_weight(&multisig.leaf(LeafPurpose::Timelock).unwrap().script, 33 + 32 * depth).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_control_block_depth_adds_32_bytes_per_level() {
+        let shallow = input_weight(SpendPath::EmergencyLeaf, 0);
+        let deep = input_weight(SpendPath::EmergencyLeaf, 3);
+        assert_eq!(deep - shallow, 3 * 32);
+        assert_eq!(input_weight(SpendPath::KeyPath, 0), input_weight(SpendPath::KeyPath, 5));
+    }
+
+    #[test]
+    fn test_wallet_input_weight() {
+        let p2wpkh = ScriptBuf::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        // Well-known size of a 1-in 1-out P2WPKH spend with a 72-byte signature
+        assert_eq!(weight_to_vsize(tx_weight(&[wallet_input_weight(&p2wpkh).unwrap()], &[p2wpkh.len()])), 110);
+
+        let p2tr = ScriptBuf::new_v1_p2tr_tweaked(bitcoin::key::TweakedPublicKey::dangerous_assume_tweaked(
+            crate::keys::unspendable_internal_key(),
+        ));
+        assert_eq!(wallet_input_weight(&p2tr).unwrap(), input_weight(SpendPath::KeyPath, 0));
+
+        let p2pkh = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+        assert!(matches!(wallet_input_weight(&p2pkh), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_key_path_vsize() {
+        // Well-known size of a 1-in 1-out P2TR key-path spend
+        assert_eq!(estimate_vsize(1, SpendPath::KeyPath, 1), 111);
+    }
+
+    #[test]
+    fn test_estimate_vsize_scales_with_inputs() {
+        let one = estimate_vsize(1, SpendPath::EmergencyLeaf, 1);
+        let three = estimate_vsize(3, SpendPath::EmergencyLeaf, 1);
+        let per_input = weight_to_vsize(input_weight(SpendPath::EmergencyLeaf, VAULT_LEAF_DEPTH));
+        assert!(three - one >= 2 * per_input - 1 && three - one <= 2 * per_input);
+    }
+
+    #[test]
+    fn test_estimate_fee_enforces_min_relay_rate() {
+        assert_eq!(estimate_fee(150, 1).unwrap(), 150);
+        assert_eq!(estimate_fee(150, 12).unwrap(), 1_800);
+        assert!(matches!(estimate_fee(150, 0), Err(CoreError::PolicyViolation(_))));
+        assert!(estimate_fee(u64::MAX, 2).is_err());
+    }
+
+    fn p2tr_output(value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: ScriptBuf::from_bytes([[0x51, 0x20].as_slice(), &[7; 32]].concat()),
+        }
+    }
+
+    fn op_return(payload_len: usize) -> TxOut {
+        let payload = PushBytesBuf::try_from(vec![0xaa; payload_len]).unwrap();
+        TxOut {
+            value: 0,
+            script_pubkey: ScriptBuf::new_op_return(&payload),
+        }
+    }
+
+    fn synthetic_tx(witnesses: Vec<Witness>, output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: witnesses
+                .into_iter()
+                .map(|witness| TxIn {
+                    witness,
+                    ..Default::default()
+                })
+                .collect(),
+            output,
+        }
+    }
+
+    /// Script-path witness for a leaf of `script_len` bytes at depth 1
+    fn tapscript_witness(stack: &[Vec<u8>], script_len: usize) -> Witness {
+        let mut control_block = vec![TAPROOT_LEAF_TAPSCRIPT; 65];
+        control_block[1..].fill(2);
+        let mut items = stack.to_vec();
+        items.push(vec![0xac; script_len]);
+        items.push(control_block);
+        Witness::from_slice(&items)
+    }
+
+    #[test]
+    fn test_check_standardness_accepts_vault_spend() {
+        let witness = tapscript_witness(&[vec![1; 64]], 40);
+        let tx = synthetic_tx(vec![witness], vec![p2tr_output(330), op_return(MAX_OP_RETURN_PAYLOAD)]);
+        let report = check_standardness(&tx).unwrap();
+        assert!(report.is_standard(), "{:?}", report);
+        assert_eq!(report.weight, tx.weight().to_wu() as usize);
+    }
+
+    #[test]
+    fn test_check_standardness_weight_limit() {
+        // 15-of-15 recovery spends: ~1,700 WU each, so 240 inputs break the limit
+        let multisig = tapscript_witness(&vec![vec![1; 64]; 15], 15 * 34 + 2);
+        let tx = synthetic_tx(vec![multisig; 240], vec![p2tr_output(100_000)]);
+        let report = check_standardness(&tx).unwrap();
+        assert_eq!(
+            report.violations,
+            vec![StandardnessViolation::TxWeight {
+                weight: report.weight,
+                max: MAX_STANDARD_TX_WEIGHT
+            }]
+        );
+        assert!(report.weight > MAX_STANDARD_TX_WEIGHT);
+    }
+
+    #[test]
+    fn test_check_standardness_reports_every_violation() {
+        let mut annexed = tapscript_witness(&[vec![1; 64]], 40).to_vec();
+        annexed.push(vec![TAPROOT_ANNEX_PREFIX, 0]);
+        let p2wsh_items: Vec<Vec<u8>> = vec![vec![]; 101].into_iter().chain([vec![0x51; 3_601]]).collect();
+        let mut tx = synthetic_tx(
+            vec![
+                tapscript_witness(&[vec![1; 64], vec![2; 81]], 40),
+                Witness::from_slice(&annexed),
+                Witness::from_slice(&p2wsh_items),
+                Witness::new(),
+            ],
+            vec![p2tr_output(329), op_return(81), op_return(4)],
+        );
+        tx.input[3].script_sig = ScriptBuf::from_bytes(vec![0; 1_651]);
+
+        let report = check_standardness(&tx).unwrap();
+        assert_eq!(
+            report.violations,
+            vec![
+                StandardnessViolation::WitnessItemSize { input: 0, item: 1, size: 81, max: 80 },
+                StandardnessViolation::Annex { input: 1 },
+                StandardnessViolation::WitnessScriptSize { input: 2, size: 3_601, max: 3_600 },
+                StandardnessViolation::WitnessItemCount { input: 2, count: 101, max: 100 },
+                StandardnessViolation::ScriptSigSize { input: 3, size: 1_651, max: 1_650 },
+                StandardnessViolation::DustOutput { output: 0, value_sats: 329, dust_sats: 330 },
+                StandardnessViolation::OpReturnSize { output: 1, payload_len: 81, max: 80 },
+                StandardnessViolation::MultipleOpReturns { count: 2 },
+            ]
+        );
+        assert_eq!(
+            report.violations[5].to_string(),
+            "Output 0 pays 329 sats, below its dust limit of 330 sats"
+        );
+
+        let json = serde_json::to_value(&report.violations[1]).unwrap();
+        assert_eq!(json, serde_json::json!({"kind": "annex", "input": 1}));
+    }
+
+    #[test]
+    fn test_check_standardness_projects_psbt_witnesses() {
+        let vault = crate::vault::VaultBuilder::new()
+            .template(VaultTemplate::spending())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+        let utxo = vault.utxo(OutPoint::null(), 100_000);
+        let psbt = crate::vault::psbt::build_unvault(
+            utxo,
+            vault.address(),
+            2,
+            &vault.metadata(),
+            crate::vault::psbt::SpendOptions::default(),
+        )
+        .unwrap();
+
+        // Unsigned, the PSBT is checked at the weight it will have once signed
+        let report = check_standardness(&psbt).unwrap();
+        assert!(report.is_standard(), "{:?}", report);
+        let input = leaf_input_weight(vault.tree(), LeafPurpose::Timelock).unwrap();
+        assert_eq!(report.weight, tx_weight(&[input], &[P2TR_SCRIPT_PUBKEY_LEN]));
+        assert!(report.weight > psbt.unsigned_tx.weight().to_wu() as usize);
+
+        let mut mismatched = psbt.clone();
+        mismatched.inputs.push(Default::default());
+        assert!(matches!(check_standardness(&mismatched), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_dust_policy_limits() {
+        let p2tr = tree(&VaultTemplate::spending()).script_pubkey();
+        let p2wpkh = ScriptBuf::from(hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap());
+        let memo = ScriptBuf::new_op_return(&PushBytesBuf::try_from(vec![1u8; 8]).unwrap());
+
+        assert_eq!(DustPolicy::default(), DustPolicy::Relay);
+        assert_eq!(DustPolicy::from_limit(None), DustPolicy::Relay);
+        assert_eq!(DustPolicy::Relay.limit(&p2tr), 330);
+        assert_eq!(DustPolicy::Relay.limit(&p2wpkh), 294);
+        // A floor stricter than relay policy wins, a laxer one doesn't
+        let strict = DustPolicy::from_limit(Some(1_000));
+        assert_eq!(strict.limit(&p2tr), 1_000);
+        assert_eq!(strict.limit(&p2wpkh), 1_000);
+        assert_eq!(DustPolicy::Floor(100).limit(&p2tr), 330);
+        assert_eq!(strict.limit(&memo), 0);
+
+        let txout = TxOut {
+            value: 999,
+            script_pubkey: p2tr,
+        };
+        DustPolicy::Relay.check_output(0, &txout).unwrap();
+        match strict.check_output(2, &txout) {
+            Err(CoreError::PolicyViolation(message)) => {
+                assert_eq!(message, "Output 2 pays 999 sats, below its dust limit of 1000 sats")
+            }
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+        strict.check_output(0, &TxOut { value: 0, script_pubkey: memo }).unwrap();
+    }
+}
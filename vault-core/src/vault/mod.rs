@@ -1,27 +1,116 @@
+use std::collections::BTreeMap;
+
+use bitcoin::absolute::{self, LockTime};
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{Address, OutPoint, Script, ScriptBuf, Sequence};
 use serde::{Deserialize, Serialize};
 
+use crate::cancel::CancelToken;
+use crate::error::{CoreError, CoreResult};
+use crate::keys;
+use crate::taproot::{self, TreeVersion, VaultTree, MAX_CSV_DELAY_BLOCKS};
+
+pub mod catalog;
+pub mod coins;
+pub mod descriptor;
+pub mod export;
+pub mod fees;
+pub mod policy;
+pub mod proof;
+pub mod psbt;
+pub mod registry;
+pub mod restore;
+pub mod scan;
+pub mod state;
+pub mod status;
+pub mod summary;
+pub mod ur;
+pub mod watch;
+
+pub use restore::{restore, restore_with_commitment};
+pub use state::{VaultEvent, VaultState};
+pub use status::{UnvaultState, UnvaultStatus, VaultUtxo};
+pub use summary::{describe, PolicySummary};
+
 /// Bitcoin network selection
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Network {
     Mainnet = 0,
     Testnet = 1,
     Signet = 2,
     Regtest = 3,
+    Testnet4 = 4,
 }
 
 impl From<Network> for bitcoin::Network {
     fn from(n: Network) -> Self {
         match n {
             Network::Mainnet => bitcoin::Network::Bitcoin,
-            Network::Testnet => bitcoin::Network::Testnet,
+            // The bitcoin crate predates testnet4, whose address HRP, base58
+            // prefixes and xpub versions are testnet3's
+            Network::Testnet | Network::Testnet4 => bitcoin::Network::Testnet,
             Network::Signet => bitcoin::Network::Signet,
             Network::Regtest => bitcoin::Network::Regtest,
         }
     }
 }
 
+impl Network {
+    /// Every network, in `i32` order
+    pub const ALL: [Network; 5] = [
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+        Network::Testnet4,
+    ];
+
+    /// Name of the network as used in vault configs
+    pub fn name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+            Network::Testnet4 => "testnet4",
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses the names `Display` writes, ignoring case
+impl std::str::FromStr for Network {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Network::ALL
+            .into_iter()
+            .find(|network| network.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| invalid_name("network", s, Network::ALL.map(Network::name)))
+    }
+}
+
+/// `InvalidInput` for an unknown `what` name, listing the valid ones
+fn invalid_name(what: &str, name: &str, valid: impl IntoIterator<Item = &'static str>) -> CoreError {
+    CoreError::InvalidInput(format!(
+        "Invalid {} \"{}\": expected one of {}",
+        what,
+        name,
+        valid.into_iter().collect::<Vec<_>>().join(", ")
+    ))
+}
+
 impl TryFrom<i32> for Network {
     type Error = crate::error::CoreError;
 
@@ -31,6 +120,7 @@ impl TryFrom<i32> for Network {
             1 => Ok(Network::Testnet),
             2 => Ok(Network::Signet),
             3 => Ok(Network::Regtest),
+            4 => Ok(Network::Testnet4),
             _ => Err(crate::error::CoreError::InvalidInput(
                 format!("Invalid network value: {}", value)
             )),
@@ -39,8 +129,11 @@ impl TryFrom<i32> for Network {
 }
 
 /// Pre-defined vault security templates
+///
+/// Deserialization runs `validate()`, so a template read from JSON always
+/// has a delay that fits a CSV sequence.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", try_from = "TemplateRepr")]
 pub enum VaultTemplate {
     #[serde(rename = "savings")]
     Savings {
@@ -54,13 +147,303 @@ pub enum VaultTemplate {
         delay_blocks: u32
     },
 
+    #[serde(rename = "custom")]
+    Custom {
+        /// Delay in `delay_unit`s
+        delay_blocks: u32,
+        #[serde(default)]
+        delay_unit: DelayUnit,
+        recovery_type: RecoveryType,
+        /// Cosigner set, required when `recovery_type` is `MultiSig`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        multisig: Option<MultisigRecovery>,
+        /// Use the owner key as internal key instead of a NUMS point,
+        /// allowing immediate key-path spends by the owner
+        #[serde(default)]
+        key_path_enabled: bool,
+        /// Block height (or, with `absolute_lock_unit` seconds, Unix
+        /// timestamp) from which the recovery key can spend through the
+        /// absolute lock leaf, however recently the output moved
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        absolute_lock: Option<u32>,
+        /// Unit of `absolute_lock`
+        #[serde(default, skip_serializing_if = "AbsoluteLockUnit::is_height")]
+        absolute_lock_unit: AbsoluteLockUnit,
+        /// Recovery service that can sweep through the hashlock leaf once
+        /// given the preimage of `hash`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hashlock: Option<HashlockRecovery>,
+    },
+
+    /// Owner spends through the key path at any time; once a vault output
+    /// has sat unspent for `inactivity_blocks`, `heir_threshold` of the
+    /// heirs can spend it through the inheritance leaf
+    #[serde(rename = "inheritance")]
+    Inheritance {
+        heir_threshold: u8,
+        heir_count: u8,
+        /// Relative delay in blocks before the heirs' leaf becomes valid
+        inactivity_blocks: u32,
+        /// Heir account xpubs, `heir_count` of them, derived at the vault
+        /// index like the owner key
+        heirs: Vec<String>,
+    },
+
+    /// Owner spends to approved destinations after `whitelist_delay`,
+    /// anywhere else after `open_delay`
+    ///
+    /// Each delay has its own timelock leaf. Script can't see where a
+    /// spend pays, so the short path is enforced by `policy::check_psbt()`.
+    #[serde(rename = "dual_delay")]
+    DualDelay {
+        whitelist_delay: u32,
+        open_delay: u32,
+    },
+
+    /// The owner, recovery and cosigner keys spend together through one
+    /// k-of-n leaf per stage, each stage needing fewer of them after a
+    /// longer delay
+    ///
+    /// A stage with delay 0 can be spent at once. Earlier stages sit
+    /// higher in the tree, so they have smaller control blocks.
+    #[serde(rename = "degrading")]
+    Degrading {
+        /// Number of signers: owner, recovery, then the cosigners
+        keys: u8,
+        /// `(delay in blocks, threshold)` of each stage, earliest first
+        stages: Vec<(u32, u8)>,
+        /// Cosigner account xpubs, `keys - 2` of them, derived at the
+        /// vault index like the owner key
+        cosigners: Vec<String>,
+    },
+}
+
+/// `VaultTemplate` as read from JSON, before validation
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TemplateRepr {
+    #[serde(rename = "savings")]
+    Savings {
+        #[serde(default = "default_savings_delay")]
+        delay_blocks: u32
+    },
+
+    #[serde(rename = "spending")]
+    Spending {
+        #[serde(default = "default_spending_delay")]
+        delay_blocks: u32
+    },
+
     #[serde(rename = "custom")]
     Custom {
         delay_blocks: u32,
+        #[serde(default)]
+        delay_unit: DelayUnit,
         recovery_type: RecoveryType,
+        #[serde(default)]
+        multisig: Option<MultisigRecovery>,
+        #[serde(default)]
+        key_path_enabled: bool,
+        #[serde(default)]
+        absolute_lock: Option<u32>,
+        #[serde(default)]
+        absolute_lock_unit: AbsoluteLockUnit,
+        #[serde(default)]
+        hashlock: Option<HashlockRecovery>,
+    },
+
+    #[serde(rename = "inheritance")]
+    Inheritance {
+        heir_threshold: u8,
+        heir_count: u8,
+        inactivity_blocks: u32,
+        heirs: Vec<String>,
     },
+
+    #[serde(rename = "dual_delay")]
+    DualDelay {
+        whitelist_delay: u32,
+        open_delay: u32,
+    },
+
+    #[serde(rename = "degrading")]
+    Degrading {
+        keys: u8,
+        stages: Vec<(u32, u8)>,
+        cosigners: Vec<String>,
+    },
+}
+
+impl TryFrom<TemplateRepr> for VaultTemplate {
+    type Error = CoreError;
+
+    fn try_from(repr: TemplateRepr) -> Result<Self, CoreError> {
+        let template = match repr {
+            TemplateRepr::Savings { delay_blocks } => VaultTemplate::Savings { delay_blocks },
+            TemplateRepr::Spending { delay_blocks } => VaultTemplate::Spending { delay_blocks },
+            TemplateRepr::Custom {
+                delay_blocks,
+                delay_unit,
+                recovery_type,
+                multisig,
+                key_path_enabled,
+                absolute_lock,
+                absolute_lock_unit,
+                hashlock,
+            } => VaultTemplate::Custom {
+                delay_blocks,
+                delay_unit,
+                recovery_type,
+                multisig,
+                key_path_enabled,
+                absolute_lock,
+                absolute_lock_unit,
+                hashlock,
+            },
+            TemplateRepr::Inheritance {
+                heir_threshold,
+                heir_count,
+                inactivity_blocks,
+                heirs,
+            } => VaultTemplate::Inheritance {
+                heir_threshold,
+                heir_count,
+                inactivity_blocks,
+                heirs,
+            },
+            TemplateRepr::DualDelay {
+                whitelist_delay,
+                open_delay,
+            } => VaultTemplate::DualDelay {
+                whitelist_delay,
+                open_delay,
+            },
+            TemplateRepr::Degrading { keys, stages, cosigners } => VaultTemplate::Degrading { keys, stages, cosigners },
+        };
+        template.validate()?;
+        Ok(template)
+    }
+}
+
+/// Unit of a relative timelock delay (BIP68)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DelayUnit {
+    #[default]
+    #[serde(rename = "blocks")]
+    Blocks,
+    /// 512-second intervals of median time past
+    #[serde(rename = "time_units_512s")]
+    TimeUnits512s,
+}
+
+impl DelayUnit {
+    /// nSequence encoding a relative lock of `delay` units
+    ///
+    /// Time-based locks have the BIP68 type flag (bit 22) set. Delays
+    /// that don't fit the 16-bit value field are rejected.
+    pub fn sequence(self, delay: u32) -> Result<Sequence, CoreError> {
+        let value = u16::try_from(delay).map_err(|_| {
+            CoreError::PolicyViolation(format!(
+                "Delay of {} {} exceeds the relative lock limit of {}",
+                delay,
+                self.name(),
+                u16::MAX
+            ))
+        })?;
+        Ok(match self {
+            DelayUnit::Blocks => Sequence::from_height(value),
+            DelayUnit::TimeUnits512s => Sequence::from_512_second_intervals(value),
+        })
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DelayUnit::Blocks => "blocks",
+            DelayUnit::TimeUnits512s => "512-second units",
+        }
+    }
+}
+
+/// BIP68 type flag marking a time-based relative lock
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+
+/// Unit of a custom template's `absolute_lock` (BIP65)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbsoluteLockUnit {
+    /// Block height, below 500,000,000
+    #[default]
+    Height,
+    /// Unix timestamp, compared against median time past
+    Seconds,
+}
+
+impl AbsoluteLockUnit {
+    /// nLockTime of a lock at `value`, rejecting values of the other unit
+    ///
+    /// Heights of 500,000,000 and up would be read as timestamps, and
+    /// timestamps below it as heights, so both are a `PolicyViolation`,
+    /// as is 0, which doesn't lock at all.
+    pub fn lock_time(self, value: u32) -> CoreResult<LockTime> {
+        let lock = match self {
+            AbsoluteLockUnit::Height => absolute::Height::from_consensus(value).map(LockTime::Blocks).ok(),
+            AbsoluteLockUnit::Seconds => absolute::Time::from_consensus(value).map(LockTime::Seconds).ok(),
+        };
+        match lock {
+            Some(lock) if value != 0 => Ok(lock),
+            _ => Err(CoreError::PolicyViolation(format!(
+                "Absolute lock {} is not a valid {} (the boundary is {})",
+                value,
+                self.name(),
+                absolute::LOCK_TIME_THRESHOLD
+            ))),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            AbsoluteLockUnit::Height => "block height",
+            AbsoluteLockUnit::Seconds => "Unix timestamp",
+        }
+    }
+
+    fn is_height(&self) -> bool {
+        *self == AbsoluteLockUnit::Height
+    }
+}
+
+/// k-of-n cosigner set for `RecoveryType::MultiSig`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultisigRecovery {
+    /// Number of cosigner signatures required
+    pub threshold: u8,
+    /// Cosigner account xpubs, derived at the vault index like the owner key
+    pub cosigners: Vec<String>,
+}
+
+/// Recovery service for a custom template's hashlock leaf
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashlockRecovery {
+    /// SHA256 of the secret handed to the service to trigger recovery
+    pub hash: sha256::Hash,
+    /// Service account xpub, derived at the vault index like the owner key
+    pub service_xpub: String,
 }
 
+/// Threshold and size of an inheritance template's heir set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeirSet {
+    pub threshold: u8,
+    pub count: u8,
+}
+
+/// Most heirs an inheritance template can name
+pub const MAX_HEIRS: u8 = 15;
+
+/// Most signers a degrading template can have, owner and recovery included
+pub const MAX_DEGRADING_KEYS: u8 = 15;
+
 fn default_savings_delay() -> u32 { 1008 }
 fn default_spending_delay() -> u32 { 144 }
 
@@ -73,25 +456,288 @@ impl VaultTemplate {
         VaultTemplate::Spending { delay_blocks: 144 }
     }
 
+    /// Custom template with a block delay and no cosigners or key path
+    ///
+    /// Fails like `validate()` if `delay_blocks` doesn't fit a CSV sequence.
+    pub fn custom(delay_blocks: u32, recovery_type: RecoveryType) -> CoreResult<Self> {
+        let template = VaultTemplate::Custom {
+            delay_blocks,
+            delay_unit: DelayUnit::Blocks,
+            recovery_type,
+            multisig: None,
+            key_path_enabled: false,
+            absolute_lock: None,
+            absolute_lock_unit: AbsoluteLockUnit::Height,
+            hashlock: None,
+        };
+        template.validate()?;
+        Ok(template)
+    }
+
+    /// Check the delay is within `1..=MAX_CSV_DELAY_BLOCKS`
+    ///
+    /// A zero delay disables the timelock and anything larger can't be
+    /// encoded in the 16-bit CSV value, so both are a `PolicyViolation`.
+    /// Inheritance templates also need `1 <= heir_threshold <= heir_count
+    /// <= MAX_HEIRS`, with exactly `heir_count` heir xpubs. Dual-delay
+    /// templates check both delays and need `whitelist_delay < open_delay`.
+    /// A custom template's `absolute_lock` must fit its unit (see
+    /// `AbsoluteLockUnit::lock_time()`); with `EmergencyKey` recovery it
+    /// is a `PolicyViolation`, since the recovery key could already sweep
+    /// at any time. A hashlock with an all-zero hash is also refused.
+    /// Degrading templates need `3 <= keys <= MAX_DEGRADING_KEYS` with
+    /// `keys - 2` cosigner xpubs, and at least two stages whose delays
+    /// increase and whose thresholds decrease within `1..=keys`; only the
+    /// first stage may have delay 0.
+    pub fn validate(&self) -> CoreResult<()> {
+        if let VaultTemplate::Degrading { keys, stages, cosigners } = self {
+            validate_stages(*keys, stages, cosigners)?;
+        }
+
+        for delay in self.whitelist_delay().into_iter().chain([self.delay_blocks()]) {
+            if delay == 0 || delay > MAX_CSV_DELAY_BLOCKS {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Delay of {} {} is outside the CSV range 1..={}",
+                    delay,
+                    self.delay_unit().name(),
+                    MAX_CSV_DELAY_BLOCKS
+                )));
+            }
+        }
+
+        if self.absolute_lock()?.is_some() && self.recovery_type() == RecoveryType::EmergencyKey {
+            return Err(CoreError::PolicyViolation(
+                "An absolute lock needs timelock_only or multi_sig recovery; \
+                 the emergency key can already spend at any time"
+                    .to_string(),
+            ));
+        }
+
+        if let VaultTemplate::Custom { hashlock: Some(hashlock), .. } = self {
+            if hashlock.hash.to_byte_array() == [0u8; 32] {
+                return Err(CoreError::PolicyViolation(
+                    "Hashlock hash is all zeros".to_string(),
+                ));
+            }
+        }
+
+        if let VaultTemplate::DualDelay { whitelist_delay, open_delay } = self {
+            if whitelist_delay >= open_delay {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Whitelist delay of {} blocks must be shorter than the open delay of {}",
+                    whitelist_delay, open_delay
+                )));
+            }
+        }
+
+        if let VaultTemplate::Inheritance { heir_threshold, heir_count, heirs, .. } = self {
+            if *heir_threshold == 0 || heir_threshold > heir_count || *heir_count > MAX_HEIRS {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Heir threshold {} of {} is invalid (at most {} heirs)",
+                    heir_threshold, heir_count, MAX_HEIRS
+                )));
+            }
+            if heirs.len() != *heir_count as usize {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Inheritance template names {} heirs but heir_count is {}",
+                    heirs.len(),
+                    heir_count
+                )));
+            }
+        }
+        Ok(())
+    }
+
     pub fn delay_blocks(&self) -> u32 {
         match self {
             VaultTemplate::Savings { delay_blocks } => *delay_blocks,
             VaultTemplate::Spending { delay_blocks } => *delay_blocks,
             VaultTemplate::Custom { delay_blocks, .. } => *delay_blocks,
+            VaultTemplate::Inheritance { inactivity_blocks, .. } => *inactivity_blocks,
+            VaultTemplate::DualDelay { open_delay, .. } => *open_delay,
+            // The first delayed stage; only the first stage can be immediate
+            VaultTemplate::Degrading { stages, .. } => {
+                stages.iter().map(|&(delay, _)| delay).find(|&delay| delay > 0).unwrap_or(0)
+            }
+        }
+    }
+
+    /// Shorter delay for spends to approved destinations, in blocks
+    ///
+    /// Only dual-delay templates have one; `delay_blocks()` is then the
+    /// delay for any other destination.
+    pub fn whitelist_delay(&self) -> Option<u32> {
+        match self {
+            VaultTemplate::DualDelay { whitelist_delay, .. } => Some(*whitelist_delay),
+            _ => None,
+        }
+    }
+
+    /// Unit of `delay_blocks()`; always blocks for the other templates
+    pub fn delay_unit(&self) -> DelayUnit {
+        match self {
+            VaultTemplate::Custom { delay_unit, .. } => *delay_unit,
+            _ => DelayUnit::Blocks,
+        }
+    }
+
+    /// nLockTime from which the absolute lock leaf can be spent, for
+    /// custom templates with an `absolute_lock`
+    ///
+    /// Fails like `AbsoluteLockUnit::lock_time()` for a lock outside its unit.
+    pub fn absolute_lock(&self) -> CoreResult<Option<LockTime>> {
+        match self {
+            VaultTemplate::Custom { absolute_lock: Some(value), absolute_lock_unit, .. } => {
+                absolute_lock_unit.lock_time(*value).map(Some)
+            }
+            _ => Ok(None),
         }
     }
 
-    pub fn template_id(&self) -> &str {
+    /// nSequence an unvault input needs to satisfy the timelock leaf
+    pub fn sequence(&self) -> Result<Sequence, CoreError> {
+        self.delay_unit().sequence(self.delay_blocks())
+    }
+
+    pub fn template_id(&self) -> &'static str {
         match self {
             VaultTemplate::Savings { .. } => "savings_v1",
             VaultTemplate::Spending { .. } => "spending_v1",
             VaultTemplate::Custom { .. } => "custom_v1",
+            VaultTemplate::Inheritance { .. } => "inheritance_v1",
+            VaultTemplate::DualDelay { .. } => "dual_delay_v1",
+            VaultTemplate::Degrading { .. } => "degrading_v1",
+        }
+    }
+
+    /// Template with id `template_id` (any case) built from `params`
+    ///
+    /// The inverse of `template_id()`. `params` holds the template's JSON
+    /// fields other than `"type"`, as listed by `catalog()`, or is null
+    /// for a template whose fields all have defaults. Unknown ids fail
+    /// with `InvalidInput` naming the valid ones; parameters are checked
+    /// as when deserializing.
+    pub fn from_template_id(template_id: &str, params: &serde_json::Value) -> CoreResult<Self> {
+        let catalog = Self::catalog();
+        let info = catalog
+            .iter()
+            .find(|info| info.template_id.eq_ignore_ascii_case(template_id))
+            .ok_or_else(|| invalid_name("template id", template_id, catalog.iter().map(|info| info.template_id)))?;
+
+        let mut fields = match params {
+            serde_json::Value::Object(fields) => fields.clone(),
+            serde_json::Value::Null => serde_json::Map::new(),
+            _ => {
+                return Err(CoreError::InvalidInput(
+                    "Template parameters must be a JSON object".to_string(),
+                ))
+            }
+        };
+        fields.insert("type".to_string(), info.type_tag.into());
+        serde_json::from_value(serde_json::Value::Object(fields))
+            .map_err(|e| CoreError::InvalidInput(format!("Invalid {} parameters: {}", info.template_id, e)))
+    }
+
+    /// Whether the tree's internal key is the owner key rather than a NUMS point
+    pub fn key_path_enabled(&self) -> bool {
+        match self {
+            VaultTemplate::Savings { .. }
+            | VaultTemplate::Spending { .. }
+            | VaultTemplate::DualDelay { .. }
+            | VaultTemplate::Degrading { .. } => false,
+            VaultTemplate::Custom { key_path_enabled, .. } => *key_path_enabled,
+            VaultTemplate::Inheritance { .. } => true,
+        }
+    }
+
+    /// Recovery path of the template's tree
+    ///
+    /// Inheritance trees have no immediate recovery path, only the heirs'
+    /// delayed leaf. Degrading trees count as multisig: every path is a
+    /// k-of-n leaf.
+    pub fn recovery_type(&self) -> RecoveryType {
+        match self {
+            VaultTemplate::Savings { .. } | VaultTemplate::Spending { .. } | VaultTemplate::DualDelay { .. } => {
+                RecoveryType::EmergencyKey
+            }
+            VaultTemplate::Custom { recovery_type, .. } => *recovery_type,
+            VaultTemplate::Inheritance { .. } => RecoveryType::TimelockOnly,
+            VaultTemplate::Degrading { .. } => RecoveryType::MultiSig,
+        }
+    }
+
+    /// Threshold and size of the heir set, for inheritance templates
+    pub fn heir_set(&self) -> Option<HeirSet> {
+        match self {
+            VaultTemplate::Inheritance { heir_threshold, heir_count, .. } => Some(HeirSet {
+                threshold: *heir_threshold,
+                count: *heir_count,
+            }),
+            _ => None,
+        }
+    }
+
+    /// `(delay in blocks, threshold)` of each stage, for degrading templates
+    pub fn stages(&self) -> Option<&[(u32, u8)]> {
+        match self {
+            VaultTemplate::Degrading { stages, .. } => Some(stages),
+            _ => None,
+        }
+    }
+}
+
+/// Check a degrading template's signer count, cosigners and stage table
+fn validate_stages(keys: u8, stages: &[(u32, u8)], cosigners: &[String]) -> CoreResult<()> {
+    if !(3..=MAX_DEGRADING_KEYS).contains(&keys) {
+        return Err(CoreError::PolicyViolation(format!(
+            "Degrading template has {} keys, expected 3..={}",
+            keys, MAX_DEGRADING_KEYS
+        )));
+    }
+    if cosigners.len() + 2 != keys as usize {
+        return Err(CoreError::PolicyViolation(format!(
+            "Degrading template names {} cosigners but {} keys need {}",
+            cosigners.len(),
+            keys,
+            keys - 2
+        )));
+    }
+    if stages.len() < 2 {
+        return Err(CoreError::PolicyViolation(
+            "Degrading template needs at least two stages".to_string(),
+        ));
+    }
+    for (i, &(delay, threshold)) in stages.iter().enumerate() {
+        if threshold == 0 || threshold > keys {
+            return Err(CoreError::PolicyViolation(format!(
+                "Stage {} threshold {} is invalid for {} keys",
+                i, threshold, keys
+            )));
+        }
+        if delay > MAX_CSV_DELAY_BLOCKS {
+            return Err(CoreError::PolicyViolation(format!(
+                "Stage {} delay of {} blocks is outside the CSV range 0..={}",
+                i, delay, MAX_CSV_DELAY_BLOCKS
+            )));
+        }
+        if let Some(&(previous_delay, previous_threshold)) = i.checked_sub(1).map(|j| &stages[j]) {
+            if delay <= previous_delay || threshold >= previous_threshold {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Stage {} ({} of {} after {} blocks) must have a longer delay and lower threshold than stage {}",
+                    i,
+                    threshold,
+                    keys,
+                    delay,
+                    i - 1
+                )));
+            }
         }
     }
+    Ok(())
 }
 
 /// Recovery mechanism type
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RecoveryType {
     EmergencyKey,
@@ -99,18 +745,59 @@ pub enum RecoveryType {
     MultiSig,
 }
 
+impl RecoveryType {
+    pub const ALL: [RecoveryType; 3] = [RecoveryType::EmergencyKey, RecoveryType::TimelockOnly, RecoveryType::MultiSig];
+
+    /// Name of the recovery type as used in vault configs
+    pub fn name(self) -> &'static str {
+        match self {
+            RecoveryType::EmergencyKey => "emergency_key",
+            RecoveryType::TimelockOnly => "timelock_only",
+            RecoveryType::MultiSig => "multi_sig",
+        }
+    }
+}
+
+impl std::fmt::Display for RecoveryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses the names `Display` writes, ignoring case
+impl std::str::FromStr for RecoveryType {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RecoveryType::ALL
+            .into_iter()
+            .find(|recovery_type| recovery_type.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| invalid_name("recovery type", s, RecoveryType::ALL.map(RecoveryType::name)))
+    }
+}
+
 /// Metadata encoded in Taproot script leaf for recovery
+///
+/// The JSON form lists fields in declaration order and rejects unknown
+/// fields; see `to_json()` and `from_json()`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct VaultMetadata {
-    /// Schema version for future compatibility
+    /// Encoding version (`METADATA_V1` or `METADATA_V2`), set by `from_bytes()`
     pub version: u8,
 
     /// Template identifier
     pub template_id: String,
 
-    /// Delay in blocks before spend completes
+    /// Delay before spend completes, in `delay_unit`s
     pub delay_blocks: u32,
 
+    /// Unit of `delay_blocks`
+    ///
+    /// Time-based delays are only representable in the version 2 layout.
+    #[serde(default)]
+    pub delay_unit: DelayUnit,
+
     /// Indices into approved destinations list
     pub destination_indices: Vec<u8>,
 
@@ -122,23 +809,260 @@ pub struct VaultMetadata {
 
     /// Derivation index for this vault
     pub vault_index: u32,
+
+    /// Whether the owner key is the internal key, allowing key-path spends
+    ///
+    /// Stored in the `TLV_KEY_PATH_ENABLED` record; a version 1 encoding
+    /// always has the NUMS internal key.
+    #[serde(default)]
+    pub key_path_enabled: bool,
+
+    /// Heir threshold and count of an inheritance vault
+    ///
+    /// Kept in the `TLV_HEIRS` record, so setting it forces version 2.
+    #[serde(default)]
+    pub heirs: Option<HeirSet>,
+
+    /// Delay in blocks for spends to approved destinations, for dual-delay
+    /// vaults; `delay_blocks` then applies to any other destination
+    ///
+    /// Version 1 has a single delay field, so this needs the
+    /// `TLV_WHITELIST_DELAY` record of version 2.
+    #[serde(default)]
+    pub whitelist_delay: Option<u32>,
+
+    /// Version 2 TLV records without a field of their own, by type
+    ///
+    /// Holds the label and cosigner fingerprints (see `label()` and
+    /// `cosigner_fingerprints()`) and any record type this version
+    /// doesn't know, so they survive a decode/encode round trip. Written
+    /// in type order; `set_tlv()` checks the length limits.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tlv_records: BTreeMap<u8, Vec<u8>>,
+}
+
+/// `VaultMetadata` decoded in place, borrowing its variable-length fields
+///
+/// `parse()` does no allocation, for paths that decode metadata from
+/// every script they inspect; `to_owned()` copies it out when it needs
+/// to outlive the input. Fields are as in `VaultMetadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VaultMetadataRef<'a> {
+    pub version: u8,
+    pub template_id: &'a str,
+    pub delay_blocks: u32,
+    pub delay_unit: DelayUnit,
+    pub destination_indices: &'a [u8],
+    pub recovery_type: RecoveryType,
+    pub created_at_block: u32,
+    pub vault_index: u32,
+    pub key_path_enabled: bool,
+    pub heirs: Option<HeirSet>,
+    pub whitelist_delay: Option<u32>,
+    /// The whole TLV section, known records included; empty in version 1
+    pub tlv_section: &'a [u8],
+}
+
+/// Longest template ID accepted when decoding metadata
+pub const MAX_TEMPLATE_ID_LEN: usize = 32;
+
+/// Original metadata layout
+pub const METADATA_V1: u8 = 1;
+
+/// Version 1 layout followed by a TLV section and a CRC32 checksum
+pub const METADATA_V2: u8 = 2;
+
+/// TLV record marking a key-path-enabled vault, with a single 1 byte value
+const TLV_KEY_PATH_ENABLED: u8 = 1;
+
+/// TLV record holding an inheritance vault's heir threshold and count, one byte each
+const TLV_HEIRS: u8 = 2;
+
+/// TLV record holding a dual-delay vault's whitelist delay, 4 bytes little-endian
+const TLV_WHITELIST_DELAY: u8 = 3;
+
+/// TLV record holding a UTF-8 vault label
+///
+/// The label and cosigner fingerprints were the first records meant for
+/// `tlv_records`, but types 1 to 3 already held fields decoded into
+/// `VaultMetadata` itself, so they start at 4.
+pub const TLV_LABEL: u8 = 4;
+
+/// TLV record holding the cosigners' BIP32 fingerprints, 4 bytes each
+pub const TLV_COSIGNER_FINGERPRINTS: u8 = 5;
+
+/// TLV record holding a degrading vault's stage table, 5 bytes per
+/// stage: the delay (4 bytes little-endian), then the threshold
+pub const TLV_DEGRADING_STAGES: u8 = 6;
+
+/// TLV record holding the vault's `TreeVersion` as one byte, absent for
+/// `TreeVersion::V1`
+pub const TLV_TREE_VERSION: u8 = 7;
+
+/// TLV record holding a custom vault's absolute lock as its nLockTime,
+/// 4 bytes little-endian; values from 500,000,000 are timestamps
+pub const TLV_ABSOLUTE_LOCK: u8 = 8;
+
+/// TLV record holding a custom vault's hashlock recovery: the 32-byte
+/// hash, then the service account xpub in its 78-byte BIP32 encoding
+pub const TLV_HASHLOCK: u8 = 9;
+
+/// Size of the `TLV_HASHLOCK` record
+const HASHLOCK_RECORD_LEN: usize = 32 + 78;
+
+/// Size of one stage in the `TLV_DEGRADING_STAGES` record
+const DEGRADING_STAGE_LEN: usize = 5;
+
+/// Longest value a single TLV record can hold
+pub const MAX_TLV_VALUE_LEN: usize = u8::MAX as usize;
+
+/// BIP340 tag of `VaultMetadata::commitment()`
+const METADATA_COMMITMENT_TAG: &[u8] = b"TapVaultMeta";
+
+/// How a vault's tree carries its metadata
+///
+/// A `Full` leaf reveals the whole metadata blob to anyone who sees it
+/// spent; a `Commitment` leaf holds only its 32-byte hash, so a restore
+/// needs the blob from the backup and checks it against the leaf.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataMode {
+    /// `OP_RETURN <metadata bytes>` (see `taproot::metadata_leaf()`)
+    #[default]
+    Full,
+    /// `OP_RETURN <commitment>` (see `taproot::commitment_leaf()`)
+    Commitment,
 }
 
 impl VaultMetadata {
-    /// Encode metadata to bytes for script leaf
+    /// Encode metadata to bytes for script leaf, in the version 1 layout
+    ///
+    /// Falls back to the version 2 layout when `key_path_enabled` is set,
+    /// the delay is time-based, or `heirs`, `whitelist_delay` or any
+    /// `tlv_records` are present, since version 1 can't represent them.
     pub fn to_bytes(&self) -> Vec<u8> {
+        if self.key_path_enabled
+            || self.delay_unit != DelayUnit::Blocks
+            || self.heirs.is_some()
+            || self.whitelist_delay.is_some()
+            || !self.tlv_records.is_empty()
+        {
+            return self.to_bytes_v2();
+        }
+        self.encode_fields(METADATA_V1)
+    }
+
+    /// Encode metadata in the version 2 layout
+    ///
+    /// Time-based delays set the BIP68 type flag (bit 22) in the delay
+    /// field. Appends a TLV section (2-byte little-endian length, then
+    /// the records) and a little-endian CRC32 of everything before it.
+    pub fn to_bytes_v2(&self) -> Vec<u8> {
+        let mut bytes = self.encode_fields(METADATA_V2);
+
+        let mut tlv = Vec::new();
+        if self.key_path_enabled {
+            tlv.extend_from_slice(&[TLV_KEY_PATH_ENABLED, 1, 1]);
+        }
+        if let Some(heirs) = self.heirs {
+            tlv.extend_from_slice(&[TLV_HEIRS, 2, heirs.threshold, heirs.count]);
+        }
+        if let Some(delay) = self.whitelist_delay {
+            tlv.extend_from_slice(&[TLV_WHITELIST_DELAY, 4]);
+            tlv.extend_from_slice(&delay.to_le_bytes());
+        }
+        for (&tlv_type, value) in self.tlv_records.iter().filter(|(&t, _)| !has_own_field(t)) {
+            tlv.extend_from_slice(&[tlv_type, value.len() as u8]);
+            tlv.extend_from_slice(value);
+        }
+        bytes.extend_from_slice(&(tlv.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&tlv);
+
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+
+        bytes
+    }
+
+    /// Encode in the layout `version` names
+    ///
+    /// Unlike `to_bytes()`, never picks a layout itself, so decoded
+    /// metadata re-encodes to the bytes it came from. Fails for unknown
+    /// versions and for version 1 metadata that needs version 2.
+    pub fn to_bytes_as_version(&self) -> CoreResult<Vec<u8>> {
+        match self.version {
+            METADATA_V1 => {
+                let bytes = self.to_bytes();
+                if bytes[0] != METADATA_V1 {
+                    return Err(CoreError::MetadataError(
+                        "Metadata needs the version 2 layout but declares version 1".to_string(),
+                    ));
+                }
+                Ok(bytes)
+            }
+            METADATA_V2 => Ok(self.to_bytes_v2()),
+            v => Err(CoreError::MetadataError(format!("unsupported version: {}", v))),
+        }
+    }
+
+    /// Canonical JSON form, for hosts that store metadata as JSON
+    ///
+    /// Fields appear in declaration order, with `recovery_type` and
+    /// `delay_unit` as snake_case strings and `tlv_records` omitted when
+    /// empty.
+    pub fn to_json(&self) -> CoreResult<String> {
+        serde_json::to_string(self).map_err(|e| CoreError::SerializationError(e.to_string()))
+    }
+
+    /// Parse the JSON form written by `to_json()`
+    ///
+    /// Unknown fields are rejected. The result must survive a trip
+    /// through `to_bytes_as_version()` and `from_bytes()` unchanged, so
+    /// the JSON and binary forms of stored metadata can't drift apart:
+    /// anything the binary form would drop or rewrite is an error.
+    pub fn from_json(json: &str) -> CoreResult<Self> {
+        let metadata: VaultMetadata = serde_json::from_str(json)
+            .map_err(|e| CoreError::SerializationError(format!("Invalid metadata JSON: {}", e)))?;
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes_as_version()?)?;
+        if decoded.to_json()? != metadata.to_json()? {
+            return Err(CoreError::SerializationError(
+                "Metadata JSON does not round-trip through its binary form".to_string(),
+            ));
+        }
+        Ok(metadata)
+    }
+
+    /// Tagged SHA256 (tag `TapVaultMeta`) of `to_bytes()`
+    ///
+    /// Every encoded field feeds the hash, so a backup blob altered in
+    /// any field no longer matches a commitment leaf built from the
+    /// original.
+    pub fn commitment(&self) -> [u8; 32] {
+        let tag = sha256::Hash::hash(METADATA_COMMITMENT_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(tag.as_ref());
+        engine.input(tag.as_ref());
+        engine.input(&self.to_bytes());
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
+    fn encode_fields(&self, version: u8) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(64);
 
         // Version (1 byte)
-        bytes.push(self.version);
+        bytes.push(version);
 
         // Template ID length + bytes
         let template_bytes = self.template_id.as_bytes();
         bytes.push(template_bytes.len() as u8);
         bytes.extend_from_slice(template_bytes);
 
-        // Delay blocks (4 bytes, little-endian)
-        bytes.extend_from_slice(&self.delay_blocks.to_le_bytes());
+        // Delay (4 bytes, little-endian), flagged if time-based in version 2
+        let mut delay = self.delay_blocks;
+        if version == METADATA_V2 && self.delay_unit == DelayUnit::TimeUnits512s {
+            delay |= SEQUENCE_TYPE_FLAG;
+        }
+        bytes.extend_from_slice(&delay.to_le_bytes());
 
         // Destination indices count + bytes
         bytes.push(self.destination_indices.len() as u8);
@@ -161,93 +1085,1180 @@ impl VaultMetadata {
     }
 
     /// Decode metadata from bytes
+    ///
+    /// Accepts versions 1 and 2; `version` is set to the one decoded.
+    /// Version 2 blobs are checksummed: truncated input reports which
+    /// field was cut short, anything else that fails the checksum
+    /// reports corruption. See `VaultMetadataRef::parse()` to decode
+    /// without allocating.
     pub fn from_bytes(data: &[u8]) -> Result<Self, crate::error::CoreError> {
-        if data.is_empty() {
-            return Err(crate::error::CoreError::MetadataError(
-                "Empty metadata bytes".to_string()
-            ));
+        VaultMetadataRef::parse(data).map(|metadata| metadata.to_owned())
+    }
+
+    /// Value of the TLV record of type `tlv_type` in `tlv_records`
+    ///
+    /// Types 1 to 3 have their own fields and are never held there.
+    pub fn get_tlv(&self, tlv_type: u8) -> Option<&[u8]> {
+        self.tlv_records.get(&tlv_type).map(Vec::as_slice)
+    }
+
+    /// Add or replace the TLV record of type `tlv_type`
+    ///
+    /// Rejects types that have their own field, values longer than
+    /// `MAX_TLV_VALUE_LEN`, and values of a defined type that
+    /// `from_bytes()` would refuse, such as a label that isn't UTF-8.
+    /// Even with every type set, the section stays within its 2-byte
+    /// length.
+    pub fn set_tlv(&mut self, tlv_type: u8, value: Vec<u8>) -> CoreResult<()> {
+        if has_own_field(tlv_type) {
+            return Err(CoreError::MetadataError(format!(
+                "TLV record type {} has its own field",
+                tlv_type
+            )));
+        }
+        if value.len() > MAX_TLV_VALUE_LEN {
+            return Err(CoreError::MetadataError(format!(
+                "TLV record type {} too long: {} bytes (max {})",
+                tlv_type,
+                value.len(),
+                MAX_TLV_VALUE_LEN
+            )));
         }
+        check_tlv_record(tlv_type, &value)?;
+        self.tlv_records.insert(tlv_type, value);
+        Ok(())
+    }
 
-        let mut pos = 0;
+    /// Vault label from the `TLV_LABEL` record
+    pub fn label(&self) -> Option<&str> {
+        self.get_tlv(TLV_LABEL).and_then(|value| std::str::from_utf8(value).ok())
+    }
 
-        // Version
-        let version = data[pos];
-        pos += 1;
+    /// Set the `TLV_LABEL` record
+    pub fn set_label(&mut self, label: &str) -> CoreResult<()> {
+        self.set_tlv(TLV_LABEL, label.as_bytes().to_vec())
+    }
 
-        // Template ID
-        if pos >= data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated metadata".to_string()));
-        }
-        let template_id_len = data[pos] as usize;
-        pos += 1;
+    /// Cosigner fingerprints from the `TLV_COSIGNER_FINGERPRINTS` record,
+    /// empty when it is absent
+    pub fn cosigner_fingerprints(&self) -> Vec<Fingerprint> {
+        self.get_tlv(TLV_COSIGNER_FINGERPRINTS)
+            .map(|value| value.chunks_exact(4).map(|chunk| Fingerprint::from([chunk[0], chunk[1], chunk[2], chunk[3]])).collect())
+            .unwrap_or_default()
+    }
 
-        if pos + template_id_len > data.len() {
-            return Err(crate::error::CoreError::MetadataError("Invalid template_id length".to_string()));
+    /// Set the `TLV_COSIGNER_FINGERPRINTS` record, removing it when
+    /// `fingerprints` is empty
+    pub fn set_cosigner_fingerprints(&mut self, fingerprints: &[Fingerprint]) -> CoreResult<()> {
+        if fingerprints.is_empty() {
+            self.tlv_records.remove(&TLV_COSIGNER_FINGERPRINTS);
+            return Ok(());
         }
-        let template_id = String::from_utf8(data[pos..pos + template_id_len].to_vec())
-            .map_err(|e| crate::error::CoreError::MetadataError(format!("Invalid UTF-8: {}", e)))?;
-        pos += template_id_len;
+        let value = fingerprints.iter().flat_map(|fingerprint| fingerprint.to_bytes()).collect();
+        self.set_tlv(TLV_COSIGNER_FINGERPRINTS, value)
+    }
 
-        // Delay blocks
-        if pos + 4 > data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated delay_blocks".to_string()));
-        }
-        let delay_blocks = u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]);
-        pos += 4;
+    /// `(delay, threshold)` stages from the `TLV_DEGRADING_STAGES` record,
+    /// empty when it is absent
+    pub fn degrading_stages(&self) -> Vec<(u32, u8)> {
+        self.get_tlv(TLV_DEGRADING_STAGES)
+            .map(|value| {
+                value
+                    .chunks_exact(DEGRADING_STAGE_LEN)
+                    .map(|chunk| (u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]), chunk[4]))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 
-        // Destination indices
-        if pos >= data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated destination_indices".to_string()));
+    /// Tree layout of the vault, from the `TLV_TREE_VERSION` record
+    ///
+    /// Metadata without the record predates tree versions and describes
+    /// a `TreeVersion::V1` tree. A record that isn't one byte naming a
+    /// known version is a `MetadataError`.
+    pub fn tree_version(&self) -> CoreResult<TreeVersion> {
+        match self.get_tlv(TLV_TREE_VERSION) {
+            None => Ok(TreeVersion::V1),
+            Some(&[version]) => TreeVersion::try_from(version).map_err(|e| CoreError::MetadataError(e.to_string())),
+            Some(value) => Err(CoreError::MetadataError(format!(
+                "Tree version record is {} bytes, expected 1",
+                value.len()
+            ))),
         }
-        let dest_count = data[pos] as usize;
-        pos += 1;
+    }
 
-        if pos + dest_count > data.len() {
-            return Err(crate::error::CoreError::MetadataError("Invalid destination_indices length".to_string()));
+    /// Set the `TLV_TREE_VERSION` record, removing it for `TreeVersion::V1`
+    pub fn set_tree_version(&mut self, version: TreeVersion) {
+        if version == TreeVersion::V1 {
+            self.tlv_records.remove(&TLV_TREE_VERSION);
+        } else {
+            self.tlv_records.insert(TLV_TREE_VERSION, vec![version.into()]);
         }
-        let destination_indices = data[pos..pos + dest_count].to_vec();
-        pos += dest_count;
+    }
 
-        // Recovery type
-        if pos >= data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated recovery_type".to_string()));
-        }
-        let recovery_type = match data[pos] {
-            0 => RecoveryType::EmergencyKey,
-            1 => RecoveryType::TimelockOnly,
-            2 => RecoveryType::MultiSig,
-            v => return Err(crate::error::CoreError::MetadataError(format!("Invalid recovery_type: {}", v))),
-        };
-        pos += 1;
+    /// nLockTime of the absolute lock leaf, from the `TLV_ABSOLUTE_LOCK`
+    /// record
+    pub fn absolute_lock(&self) -> Option<LockTime> {
+        self.get_tlv(TLV_ABSOLUTE_LOCK)
+            .and_then(|value| <[u8; 4]>::try_from(value).ok())
+            .map(|value| LockTime::from_consensus(u32::from_le_bytes(value)))
+    }
 
-        // Created at block
-        if pos + 4 > data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated created_at_block".to_string()));
+    /// Set the `TLV_ABSOLUTE_LOCK` record, removing it for `None`
+    pub fn set_absolute_lock(&mut self, lock: Option<LockTime>) {
+        match lock {
+            Some(lock) => {
+                self.tlv_records.insert(TLV_ABSOLUTE_LOCK, lock.to_consensus_u32().to_le_bytes().to_vec());
+            }
+            None => {
+                self.tlv_records.remove(&TLV_ABSOLUTE_LOCK);
+            }
         }
-        let created_at_block = u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]);
-        pos += 4;
+    }
 
-        // Vault index
-        if pos + 4 > data.len() {
-            return Err(crate::error::CoreError::MetadataError("Truncated vault_index".to_string()));
-        }
-        let vault_index = u32::from_le_bytes([data[pos], data[pos+1], data[pos+2], data[pos+3]]);
+    /// Hashlock recovery from the `TLV_HASHLOCK` record, with the service
+    /// xpub in its canonical `xpub`/`tpub` form
+    pub fn hashlock(&self) -> Option<HashlockRecovery> {
+        let value = self.get_tlv(TLV_HASHLOCK).filter(|value| value.len() == HASHLOCK_RECORD_LEN)?;
+        let (hash, service) = value.split_at(32);
+        Some(HashlockRecovery {
+            hash: sha256::Hash::from_slice(hash).ok()?,
+            service_xpub: ExtendedPubKey::decode(service).ok()?.to_string(),
+        })
+    }
 
-        Ok(VaultMetadata {
-            version,
-            template_id,
+    /// Set the `TLV_HASHLOCK` record, removing it for `None`
+    ///
+    /// The service xpub must be valid for `network`.
+    pub fn set_hashlock(&mut self, hashlock: Option<&HashlockRecovery>, network: Network) -> CoreResult<()> {
+        let hashlock = match hashlock {
+            Some(hashlock) => hashlock,
+            None => {
+                self.tlv_records.remove(&TLV_HASHLOCK);
+                return Ok(());
+            }
+        };
+        let service = keys::parse_xpub(&hashlock.service_xpub, network)?;
+        let mut value = hashlock.hash.to_byte_array().to_vec();
+        value.extend_from_slice(&service.encode());
+        self.set_tlv(TLV_HASHLOCK, value)
+    }
+
+    /// Set the `TLV_DEGRADING_STAGES` record, removing it when `stages`
+    /// is empty
+    pub fn set_degrading_stages(&mut self, stages: &[(u32, u8)]) -> CoreResult<()> {
+        if stages.is_empty() {
+            self.tlv_records.remove(&TLV_DEGRADING_STAGES);
+            return Ok(());
+        }
+        let value = stages
+            .iter()
+            .flat_map(|&(delay, threshold)| delay.to_le_bytes().into_iter().chain([threshold]))
+            .collect();
+        self.set_tlv(TLV_DEGRADING_STAGES, value)
+    }
+}
+
+/// Whether TLV records of `tlv_type` decode into a `VaultMetadata` field
+/// rather than into `tlv_records`
+fn has_own_field(tlv_type: u8) -> bool {
+    matches!(tlv_type, TLV_KEY_PATH_ENABLED | TLV_HEIRS | TLV_WHITELIST_DELAY)
+}
+
+/// Check a record of a type held in `tlv_records`
+///
+/// Types this version defines must hold a value their accessor can
+/// read; unknown types are kept as they are.
+fn check_tlv_record(tlv_type: u8, value: &[u8]) -> CoreResult<()> {
+    let (invalid, name) = match tlv_type {
+        TLV_LABEL => (std::str::from_utf8(value).is_err(), "label"),
+        TLV_COSIGNER_FINGERPRINTS => (value.is_empty() || !value.chunks_exact(4).remainder().is_empty(), "cosigner fingerprints"),
+        TLV_DEGRADING_STAGES => (
+            value.is_empty() || !value.chunks_exact(DEGRADING_STAGE_LEN).remainder().is_empty(),
+            "degrading stages",
+        ),
+        TLV_ABSOLUTE_LOCK => (value.len() != 4 || value == [0; 4], "absolute lock"),
+        TLV_HASHLOCK => (
+            value.len() != HASHLOCK_RECORD_LEN
+                || value[..32] == [0; 32]
+                || ExtendedPubKey::decode(&value[32..]).is_err(),
+            "hashlock",
+        ),
+        // V1 is written as no record, so it has one encoding
+        TLV_TREE_VERSION => (
+            !matches!(*value, [version] if version != u8::from(TreeVersion::V1) && TreeVersion::try_from(version).is_ok()),
+            "tree version",
+        ),
+        _ => return Ok(()),
+    };
+    if invalid {
+        Err(CoreError::MetadataError(format!("Invalid {} record", name)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Records of an already validated TLV section
+fn tlv_records(section: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = section;
+    std::iter::from_fn(move || {
+        let (&[tlv_type, len], tail) = rest.split_first_chunk::<2>()?;
+        let (value, tail) = tail.split_at(len as usize);
+        rest = tail;
+        Some((tlv_type, value))
+    })
+}
+
+impl<'a> VaultMetadataRef<'a> {
+    /// Decode metadata from bytes without copying them
+    ///
+    /// Accepts and rejects exactly what `VaultMetadata::from_bytes()` does.
+    pub fn parse(data: &'a [u8]) -> CoreResult<Self> {
+        if data.is_empty() {
+            return Err(crate::error::CoreError::MetadataError(
+                "Empty metadata bytes".to_string()
+            ));
+        }
+
+        let mut reader = MetadataReader { data, pos: 0, truncated: false };
+        match data[0] {
+            METADATA_V1 => {
+                let metadata = reader.fields()?;
+                reader.finish()?;
+                Ok(metadata)
+            }
+            METADATA_V2 => {
+                let result = reader.fields().and_then(|mut metadata| {
+                    reader.tlv_section(&mut metadata)?;
+                    reader.u32("checksum")?;
+                    reader.finish()?;
+                    Ok(metadata)
+                });
+                if result.is_err() && reader.truncated {
+                    return result;
+                }
+
+                let (body, checksum) = data.split_at(data.len().saturating_sub(4));
+                if checksum.len() != 4 || crc32fast::hash(body).to_le_bytes() != checksum {
+                    return Err(crate::error::CoreError::MetadataError(
+                        "checksum mismatch: metadata is corrupted".to_string(),
+                    ));
+                }
+                result
+            }
+            v => Err(crate::error::CoreError::MetadataError(format!(
+                "unsupported version: {}",
+                v
+            ))),
+        }
+    }
+
+    /// Copy into an owned `VaultMetadata`
+    pub fn to_owned(&self) -> VaultMetadata {
+        VaultMetadata {
+            version: self.version,
+            template_id: self.template_id.to_string(),
+            delay_blocks: self.delay_blocks,
+            delay_unit: self.delay_unit,
+            destination_indices: self.destination_indices.to_vec(),
+            recovery_type: self.recovery_type,
+            created_at_block: self.created_at_block,
+            vault_index: self.vault_index,
+            key_path_enabled: self.key_path_enabled,
+            heirs: self.heirs,
+            whitelist_delay: self.whitelist_delay,
+            tlv_records: tlv_records(self.tlv_section)
+                .filter(|&(tlv_type, _)| !has_own_field(tlv_type))
+                .map(|(tlv_type, value)| (tlv_type, value.to_vec()))
+                .collect(),
+        }
+    }
+
+    /// Value of the TLV record of type `tlv_type`, if it has no field of
+    /// its own (see `VaultMetadata::get_tlv()`)
+    pub fn get_tlv(&self, tlv_type: u8) -> Option<&'a [u8]> {
+        if has_own_field(tlv_type) {
+            return None;
+        }
+        tlv_records(self.tlv_section).find(|&(t, _)| t == tlv_type).map(|(_, value)| value)
+    }
+}
+
+/// Cursor over encoded metadata
+struct MetadataReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Set once a read runs past the end of `data`
+    truncated: bool,
+}
+
+impl<'a> MetadataReader<'a> {
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8], crate::error::CoreError> {
+        if self.data.len() - self.pos < len {
+            self.truncated = true;
+            return Err(crate::error::CoreError::MetadataError(format!("Truncated {}", field)));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self, field: &str) -> Result<u8, crate::error::CoreError> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &str) -> Result<u16, crate::error::CoreError> {
+        let bytes = self.take(2, field)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self, field: &str) -> Result<u32, crate::error::CoreError> {
+        let bytes = self.take(4, field)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Fields shared by every version, starting at the version byte
+    fn fields(&mut self) -> Result<VaultMetadataRef<'a>, crate::error::CoreError> {
+        let version = self.u8("version")?;
+
+        let template_id_len = self.u8("template_id")? as usize;
+        if template_id_len > MAX_TEMPLATE_ID_LEN {
+            return Err(crate::error::CoreError::MetadataError(format!(
+                "template_id too long: {} bytes (max {})",
+                template_id_len, MAX_TEMPLATE_ID_LEN
+            )));
+        }
+        let template_id = std::str::from_utf8(self.take(template_id_len, "template_id")?)
+            .map_err(|e| crate::error::CoreError::MetadataError(format!("Invalid UTF-8: {}", e)))?;
+
+        let mut delay_blocks = self.u32("delay_blocks")?;
+        let mut delay_unit = DelayUnit::Blocks;
+        if version == METADATA_V2 && delay_blocks & SEQUENCE_TYPE_FLAG != 0 {
+            delay_blocks &= !SEQUENCE_TYPE_FLAG;
+            if delay_blocks > u16::MAX as u32 {
+                return Err(crate::error::CoreError::MetadataError(format!(
+                    "Time-based delay of {} units exceeds 16 bits",
+                    delay_blocks
+                )));
+            }
+            delay_unit = DelayUnit::TimeUnits512s;
+        }
+
+        let dest_count = self.u8("destination_indices")? as usize;
+        let destination_indices = self.take(dest_count, "destination_indices")?;
+
+        let recovery_type = match self.u8("recovery_type")? {
+            0 => RecoveryType::EmergencyKey,
+            1 => RecoveryType::TimelockOnly,
+            2 => RecoveryType::MultiSig,
+            v => return Err(crate::error::CoreError::MetadataError(format!("Invalid recovery_type: {}", v))),
+        };
+
+        let created_at_block = self.u32("created_at_block")?;
+        let vault_index = self.u32("vault_index")?;
+
+        Ok(VaultMetadataRef {
+            version,
+            template_id,
             delay_blocks,
+            delay_unit,
             destination_indices,
             recovery_type,
             created_at_block,
             vault_index,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_section: &[],
+        })
+    }
+
+    /// Version 2 TLV section: `type (1) | length (1) | value` records
+    ///
+    /// Records with their own field are applied to `metadata`, the label,
+    /// cosigner fingerprints, degrading stages, absolute lock and tree
+    /// version are checked, and well-formed unknown
+    /// records are kept in `metadata.tlv_section`. Each type may appear
+    /// only once.
+    fn tlv_section(&mut self, metadata: &mut VaultMetadataRef<'a>) -> Result<(), crate::error::CoreError> {
+        let len = self.u16("tlv section")? as usize;
+        metadata.tlv_section = self.take(len, "tlv section")?;
+        let mut section = MetadataReader { data: metadata.tlv_section, pos: 0, truncated: false };
+        let mut seen = [false; 256];
+        while section.pos < section.data.len() {
+            let tlv_type = section.u8("tlv type")?;
+            let value_len = section.u8("tlv length")? as usize;
+            let value = section.take(value_len, "tlv value")?;
+            if std::mem::replace(&mut seen[tlv_type as usize], true) {
+                return Err(crate::error::CoreError::MetadataError(format!(
+                    "Duplicate TLV record type {}",
+                    tlv_type
+                )));
+            }
+            if tlv_type == TLV_KEY_PATH_ENABLED {
+                if value != [1] {
+                    return Err(crate::error::CoreError::MetadataError(
+                        "Invalid key_path_enabled record".to_string(),
+                    ));
+                }
+                metadata.key_path_enabled = true;
+            } else if tlv_type == TLV_HEIRS {
+                match *value {
+                    [threshold, count] if threshold >= 1 && threshold <= count && count <= MAX_HEIRS => {
+                        metadata.heirs = Some(HeirSet { threshold, count });
+                    }
+                    _ => {
+                        return Err(crate::error::CoreError::MetadataError(
+                            "Invalid heirs record".to_string(),
+                        ))
+                    }
+                }
+            } else if tlv_type == TLV_WHITELIST_DELAY {
+                let delay = <[u8; 4]>::try_from(value).map(u32::from_le_bytes);
+                match delay {
+                    Ok(delay) if delay >= 1 && delay < metadata.delay_blocks => {
+                        metadata.whitelist_delay = Some(delay);
+                    }
+                    _ => {
+                        return Err(crate::error::CoreError::MetadataError(
+                            "Invalid whitelist_delay record".to_string(),
+                        ))
+                    }
+                }
+            } else {
+                check_tlv_record(tlv_type, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Require that every byte was consumed, so each value has exactly one encoding
+    fn finish(&self) -> Result<(), crate::error::CoreError> {
+        if self.pos != self.data.len() {
+            return Err(crate::error::CoreError::MetadataError("trailing bytes".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to reconstruct a vault, as supplied by callers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultConfig {
+    /// Bitcoin network
+    pub network: Network,
+    /// Vault template
+    pub template: VaultTemplate,
+    /// Owner account xpub (timelock leaf), optionally with its
+    /// `[fingerprint/path]` key origin
+    pub owner_xpub: String,
+    /// Recovery account xpub (emergency leaf), optionally with its
+    /// `[fingerprint/path]` key origin
+    pub recovery_xpub: String,
+    /// Destinations unvaults may pay to, checked by `policy::check_psbt()`
+    #[serde(default)]
+    pub approved_destinations: Option<policy::ApprovedDestinations>,
+    /// Highest fee `policy::check_psbt()` accepts, in satoshis
+    ///
+    /// `None` uses `policy::DEFAULT_MAX_FEE_SATS`.
+    #[serde(default)]
+    pub max_fee_sats: Option<u64>,
+    /// Require every input to carry its full previous transaction
+    /// (`non_witness_utxo`), see `policy::verify_prevouts()`
+    #[serde(default)]
+    pub require_non_witness_utxo: bool,
+    /// Smallest output the PSBT builders create, in satoshis
+    ///
+    /// `None` uses each output's relay dust limit; see `fees::DustPolicy`.
+    #[serde(default)]
+    pub dust_limit_sats: Option<u64>,
+    /// Record the vault and spend path in the PSBTs built for this vault,
+    /// see `psbt::attach_vault_info()`
+    #[serde(default)]
+    pub psbt_vault_info: bool,
+    /// Most the vault may pay out per window of blocks, checked by
+    /// `policy::check_psbt()` against `spend_history`
+    #[serde(default)]
+    pub velocity_limit: Option<policy::VelocityLimit>,
+    /// Earlier spends out of the vault, see `policy::check_velocity()`
+    #[serde(default)]
+    pub spend_history: Vec<policy::SpendRecord>,
+    /// Chain height the velocity window ends at; required with a
+    /// `velocity_limit`
+    #[serde(default)]
+    pub current_block_height: Option<u32>,
+    /// Layout of the vault's trees, `TreeVersion::LATEST` unless given
+    #[serde(default)]
+    pub tree_version: TreeVersion,
+}
+
+impl VaultConfig {
+    /// Config for a vault of `template` over the two account xpubs, with
+    /// every optional field at its serde default
+    pub fn new(network: Network, template: VaultTemplate, owner_xpub: impl Into<String>, recovery_xpub: impl Into<String>) -> Self {
+        VaultConfig {
+            network,
+            template,
+            owner_xpub: owner_xpub.into(),
+            recovery_xpub: recovery_xpub.into(),
+            approved_destinations: None,
+            max_fee_sats: None,
+            require_non_witness_utxo: false,
+            dust_limit_sats: None,
+            psbt_vault_info: false,
+            velocity_limit: None,
+            spend_history: vec![],
+            current_block_height: None,
+            tree_version: TreeVersion::default(),
+        }
+    }
+}
+
+/// Assembles a `Vault` from its parts, validating them together
+///
+/// Keys are kept as strings until `build()`, which parses every key
+/// against the network, validates the template, rejects keys used twice
+/// and derives the tree at the chosen index, so a built `Vault` can't
+/// mix networks.
+#[derive(Debug, Clone, Default)]
+pub struct VaultBuilder {
+    template: Option<VaultTemplate>,
+    owner_xpub: Option<String>,
+    recovery_xpub: Option<String>,
+    network: Option<Network>,
+    index: Option<u32>,
+    ledger: Option<registry::IndexLedger>,
+    destinations: Option<policy::ApprovedDestinations>,
+    internal_key: Option<keys::musig::AggregatedKey>,
+    created_at_block: u32,
+    dust_policy: fees::DustPolicy,
+    psbt_vault_info: bool,
+    allow_duplicate_keys: bool,
+    tree_version: TreeVersion,
+}
+
+impl VaultBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder pre-filled from `config`, at vault index 0
+    pub fn from_config(config: &VaultConfig) -> Self {
+        VaultBuilder {
+            template: Some(config.template.clone()),
+            owner_xpub: Some(config.owner_xpub.clone()),
+            recovery_xpub: Some(config.recovery_xpub.clone()),
+            network: Some(config.network),
+            index: None,
+            ledger: None,
+            destinations: config.approved_destinations.clone(),
+            internal_key: None,
+            created_at_block: 0,
+            dust_policy: fees::DustPolicy::from_limit(config.dust_limit_sats),
+            psbt_vault_info: config.psbt_vault_info,
+            allow_duplicate_keys: false,
+            tree_version: config.tree_version,
+        }
+    }
+
+    pub fn template(mut self, template: VaultTemplate) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Owner account xpub, optionally as `[fingerprint/path]xpub` so that
+    /// PSBTs list the owner's keys by master fingerprint and full path
+    /// (see `keys::parse_xpub_with_origin()`)
+    pub fn owner_xpub(mut self, xpub: impl Into<String>) -> Self {
+        self.owner_xpub = Some(xpub.into());
+        self
+    }
+
+    /// Recovery account xpub, optionally with its key origin as for
+    /// `owner_xpub()`
+    pub fn recovery_xpub(mut self, xpub: impl Into<String>) -> Self {
+        self.recovery_xpub = Some(xpub.into());
+        self
+    }
+
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Vault index to derive at; unless set, the ledger's next free
+    /// index, or 0 without a ledger
+    pub fn index(mut self, index: u32) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Indices already used for these keys, which `build()` refuses
+    ///
+    /// The built vault's `Vault::ledger()` is a copy with its index added,
+    /// for the host to persist in place of `ledger`.
+    pub fn ledger(mut self, ledger: &registry::IndexLedger) -> Self {
+        self.ledger = Some(ledger.clone());
+        self
+    }
+
+    /// Destinations unvaults may pay to
+    pub fn destinations(mut self, destinations: policy::ApprovedDestinations) -> Self {
+        self.destinations = Some(destinations);
+        self
+    }
+
+    /// MuSig2 key to use as the tree's internal key in place of the NUMS key
+    ///
+    /// The signers behind `key` can then spend through the key path (see
+    /// `keys::musig`). The key is fixed, so the vault has a tree at its
+    /// own index only, and no descriptor.
+    pub fn internal_key(mut self, key: keys::musig::AggregatedKey) -> Self {
+        self.internal_key = Some(key);
+        self
+    }
+
+    /// Block height recorded as `created_at_block` in `Vault::metadata()`, 0 unless set
+    pub fn created_at_block(mut self, height: u32) -> Self {
+        self.created_at_block = height;
+        self
+    }
+
+    /// Smallest output the vault's PSBTs create, `DustPolicy::Relay` unless set
+    pub fn dust_policy(mut self, dust_policy: fees::DustPolicy) -> Self {
+        self.dust_policy = dust_policy;
+        self
+    }
+
+    /// Whether PSBTs built for the vault carry `psbt::attach_vault_info()`
+    /// records, false unless set
+    pub fn psbt_vault_info(mut self, enabled: bool) -> Self {
+        self.psbt_vault_info = enabled;
+        self
+    }
+
+    /// Layout of the vault's trees, `TreeVersion::LATEST` unless set
+    ///
+    /// Restores must use the version the vault was created with, which
+    /// `Vault::metadata()` records; new vaults can take
+    /// `TreeVersion::LATEST`.
+    pub fn tree_version(mut self, version: TreeVersion) -> Self {
+        self.tree_version = version;
+        self
+    }
+
+    /// Skip the check that every role has its own key, false unless set
+    ///
+    /// For regtest test setups that reuse one key in several roles;
+    /// `build()` refuses it on any other network. Scripts still reject a
+    /// key repeated within one multisig leaf.
+    pub fn allow_duplicate_keys(mut self, allow: bool) -> Self {
+        self.allow_duplicate_keys = allow;
+        self
+    }
+
+    /// Validate every field against the others and derive the vault
+    ///
+    /// Fails with `InvalidInput` for a missing field or a hardened
+    /// index, `PolicyViolation` for an index the ledger already holds, a
+    /// full ledger, an invalid template, a key used twice (the same xpub,
+    /// or xpubs deriving the same key at index 0) in any two roles,
+    /// `allow_duplicate_keys()` off regtest or an internal key for a
+    /// template whose owner key already is one, `InvalidXpub`
+    /// for an unparseable key and `NetworkMismatch` for a key or
+    /// destination list from another network.
+    pub fn build(self) -> CoreResult<Vault> {
+        let network = self.network.ok_or_else(|| missing("network"))?;
+        let template = self.template.ok_or_else(|| missing("template"))?;
+        let owner_xpub = self.owner_xpub.ok_or_else(|| missing("owner_xpub"))?;
+        let recovery_xpub = self.recovery_xpub.ok_or_else(|| missing("recovery_xpub"))?;
+        let mut ledger = self.ledger;
+        let index = match (self.index, &ledger) {
+            (Some(index), _) => index,
+            (None, Some(ledger)) => ledger.next_free().ok_or_else(|| {
+                CoreError::PolicyViolation("Every vault index is already used".to_string())
+            })?,
+            (None, None) => 0,
+        };
+        if index >= 0x8000_0000 {
+            return Err(CoreError::InvalidInput(format!(
+                "Vault index {} is hardened; only indices below 2^31 are supported",
+                index
+            )));
+        }
+        if let Some(ledger) = &mut ledger {
+            ledger.claim(index)?;
+        }
+        template.validate()?;
+        if self.allow_duplicate_keys && network != Network::Regtest {
+            return Err(CoreError::PolicyViolation(format!(
+                "Duplicate keys may only be allowed on regtest, not {}",
+                network
+            )));
+        }
+        if self.internal_key.is_some() && template.key_path_enabled() {
+            return Err(CoreError::PolicyViolation(format!(
+                "{} vault already uses the owner key as internal key",
+                template.template_id()
+            )));
+        }
+
+        let (owner_xpub, owner_origin) = keys::parse_xpub_with_origin(&owner_xpub, network)?;
+        let (recovery_xpub, recovery_origin) = keys::parse_xpub_with_origin(&recovery_xpub, network)?;
+        let (cosigner_role, cosigners): (&str, &[String]) = match &template {
+            VaultTemplate::Custom { multisig: Some(multisig), .. } => ("cosigner", &multisig.cosigners),
+            VaultTemplate::Inheritance { heirs, .. } => ("heir", heirs),
+            VaultTemplate::Degrading { cosigners, .. } => ("cosigner", cosigners),
+            _ => ("cosigner", &[]),
+        };
+        let mut roles = vec![("owner xpub".to_string(), owner_xpub), ("recovery xpub".to_string(), recovery_xpub)];
+        for (i, xpub) in cosigners.iter().enumerate() {
+            roles.push((format!("{} {} xpub", cosigner_role, i + 1), keys::parse_xpub(xpub, network)?));
+        }
+        if let VaultTemplate::Custom { hashlock: Some(hashlock), .. } = &template {
+            roles.push(("recovery service xpub".to_string(), keys::parse_xpub(&hashlock.service_xpub, network)?));
+        }
+        if !self.allow_duplicate_keys {
+            check_distinct_keys(&roles, network)?;
+        }
+
+        if let Some(destinations) = &self.destinations {
+            if destinations.network() != network {
+                return Err(CoreError::NetworkMismatch {
+                    expected: network.name().to_string(),
+                    actual: destinations.network().name().to_string(),
+                });
+            }
+        }
+
+        let mut tree = taproot::vault_tree_with_origins(
+            &template,
+            (&owner_xpub, &owner_origin),
+            (&recovery_xpub, &recovery_origin),
+            index,
+            network,
+            self.tree_version,
+        )?;
+        let internal_key = self.internal_key.map(|key| key.x_only_public_key());
+        if let Some(internal_key) = internal_key {
+            tree = taproot::build_tree(tree.leaves().to_vec(), internal_key, self.tree_version)?.with_key_origins(tree.key_origins().clone());
+        }
+        Ok(Vault {
+            network,
+            template,
+            owner_xpub,
+            recovery_xpub,
+            owner_origin,
+            recovery_origin,
+            index,
+            ledger,
+            destinations: self.destinations,
+            internal_key,
+            created_at_block: self.created_at_block,
+            dust_policy: self.dust_policy,
+            psbt_vault_info: self.psbt_vault_info,
+            tree_version: self.tree_version,
+            tree,
         })
     }
 }
 
+/// Fail with `PolicyViolation` naming the first two roles that share an
+/// xpub or whose keys at index 0 coincide
+fn check_distinct_keys(roles: &[(String, ExtendedPubKey)], network: Network) -> CoreResult<()> {
+    let first_keys = roles
+        .iter()
+        .map(|(_, xpub)| Ok(keys::derive_vault_key(xpub, 0, network)?.public_key))
+        .collect::<CoreResult<Vec<_>>>()?;
+    for (i, (role, xpub)) in roles.iter().enumerate() {
+        for (j, (other_role, other)) in roles[..i].iter().enumerate() {
+            if other.public_key == xpub.public_key && other.chain_code == xpub.chain_code {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Duplicate key: {} is the same key as the {}",
+                    role, other_role
+                )));
+            }
+            if first_keys[j] == first_keys[i] {
+                return Err(CoreError::PolicyViolation(format!(
+                    "Duplicate key: {} derives the same key at index 0 as the {}",
+                    role, other_role
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn missing(field: &str) -> CoreError {
+    CoreError::InvalidInput(format!("Vault {} is required", field))
+}
+
+/// A validated vault at one index
+///
+/// Built by `VaultBuilder`, so every key matches the network. Trees for
+/// other indices of the same keys are available through `tree_at()`.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    network: Network,
+    template: VaultTemplate,
+    owner_xpub: ExtendedPubKey,
+    recovery_xpub: ExtendedPubKey,
+    owner_origin: KeySource,
+    recovery_origin: KeySource,
+    index: u32,
+    /// Ledger from `VaultBuilder::ledger()` with `index` added
+    ledger: Option<registry::IndexLedger>,
+    destinations: Option<policy::ApprovedDestinations>,
+    /// MuSig2 internal key from `VaultBuilder::internal_key()`
+    internal_key: Option<XOnlyPublicKey>,
+    created_at_block: u32,
+    dust_policy: fees::DustPolicy,
+    psbt_vault_info: bool,
+    tree_version: TreeVersion,
+    tree: VaultTree,
+}
+
+impl Vault {
+    /// Vault at index 0 of `config`
+    pub fn from_config(config: &VaultConfig) -> Result<Self, CoreError> {
+        VaultBuilder::from_config(config).build()
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn template(&self) -> &VaultTemplate {
+        &self.template
+    }
+
+    pub fn owner_xpub(&self) -> &ExtendedPubKey {
+        &self.owner_xpub
+    }
+
+    pub fn recovery_xpub(&self) -> &ExtendedPubKey {
+        &self.recovery_xpub
+    }
+
+    /// Smallest output to build for this vault, from the config's
+    /// `dust_limit_sats` or `VaultBuilder::dust_policy()`
+    pub fn dust_policy(&self) -> fees::DustPolicy {
+        self.dust_policy
+    }
+
+    /// Whether builders given this vault call `psbt::attach_vault_info()`
+    pub fn psbt_vault_info(&self) -> bool {
+        self.psbt_vault_info
+    }
+
+    /// Layout of the vault's trees, see `VaultBuilder::tree_version()`
+    pub fn tree_version(&self) -> TreeVersion {
+        self.tree_version
+    }
+
+    /// Key origin of the owner xpub: as given to the builder, or the
+    /// xpub's own fingerprint and an empty path
+    pub fn owner_origin(&self) -> &KeySource {
+        &self.owner_origin
+    }
+
+    /// Key origin of the recovery xpub, as for `owner_origin()`
+    pub fn recovery_origin(&self) -> &KeySource {
+        &self.recovery_origin
+    }
+
+    /// Key origin of the owner, recovery or a cosigner xpub of this vault,
+    /// as given with the key; any other key is its own origin (its
+    /// fingerprint and an empty path)
+    pub fn origin_of(&self, xpub: &ExtendedPubKey) -> KeySource {
+        if *xpub == self.owner_xpub {
+            return self.owner_origin.clone();
+        }
+        if *xpub == self.recovery_xpub {
+            return self.recovery_origin.clone();
+        }
+        let cosigners: &[String] = match &self.template {
+            VaultTemplate::Custom { multisig: Some(multisig), .. } => &multisig.cosigners,
+            VaultTemplate::Inheritance { heirs, .. } => heirs,
+            VaultTemplate::Degrading { cosigners, .. } => cosigners,
+            _ => &[],
+        };
+        cosigners
+            .iter()
+            .filter_map(|key| keys::parse_xpub_with_origin(key, self.network).ok())
+            .find(|(key, _)| key == xpub)
+            .map(|(_, origin)| origin)
+            .unwrap_or_else(|| (xpub.fingerprint(), DerivationPath::master()))
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Ledger given to `VaultBuilder::ledger()` with this vault's index
+    /// added, for the host to persist
+    pub fn ledger(&self) -> Option<&registry::IndexLedger> {
+        self.ledger.as_ref()
+    }
+
+    pub fn destinations(&self) -> Option<&policy::ApprovedDestinations> {
+        self.destinations.as_ref()
+    }
+
+    /// MuSig2 internal key set by `VaultBuilder::internal_key()`, if any
+    pub fn musig_key(&self) -> Option<XOnlyPublicKey> {
+        self.internal_key
+    }
+
+    /// Script tree of this vault
+    pub fn tree(&self) -> &VaultTree {
+        &self.tree
+    }
+
+    /// Script tree for the same keys at `vault_index`
+    ///
+    /// Vaults with a MuSig2 internal key only have the tree at their own
+    /// index; others fail with `InvalidInput`.
+    pub fn tree_at(&self, vault_index: u32) -> Result<VaultTree, CoreError> {
+        if vault_index == self.index {
+            return Ok(self.tree.clone());
+        }
+        if self.internal_key.is_some() {
+            return Err(CoreError::InvalidInput(format!(
+                "Vault with a MuSig2 internal key has no tree at index {}, only at {}",
+                vault_index, self.index
+            )));
+        }
+        taproot::vault_tree_with_origins(
+            &self.template,
+            (&self.owner_xpub, &self.owner_origin),
+            (&self.recovery_xpub, &self.recovery_origin),
+            vault_index,
+            self.network,
+            self.tree_version,
+        )
+    }
+
+    /// Every leaf of this vault's tree with its disassembly and control
+    /// block size, in tree order, for audit display
+    pub fn leaf_listing(&self) -> CoreResult<Vec<taproot::LeafListing>> {
+        self.tree
+            .leaves()
+            .iter()
+            .map(|leaf| {
+                let control_block = taproot::control_block(&self.tree, leaf.purpose)?;
+                Ok(taproot::LeafListing {
+                    purpose: leaf.purpose,
+                    leaf_hash: TapLeafHash::from_script(&leaf.script, leaf.version),
+                    depth: control_block.merkle_branch.as_inner().len(),
+                    control_block_size: control_block.size(),
+                    script: leaf.script.clone(),
+                    tokens: taproot::disassemble(&leaf.script),
+                    asm: taproot::disassemble_to_string(&leaf.script),
+                })
+            })
+            .collect()
+    }
+
+    /// Weight of an input spending `leaf` of this vault's tree with
+    /// `n_sigs` signatures, witness included (see `fees::spend_weight()`)
+    pub fn spend_weight(&self, leaf: taproot::LeafId, n_sigs: usize) -> CoreResult<u64> {
+        fees::spend_weight(&self.tree, leaf, n_sigs).map(|weight| weight as u64)
+    }
+
+    /// Fee for spending one input of this vault through `leaf`, with
+    /// `n_sigs` signatures, to `n_outputs` P2TR outputs at `fee_rate` sat/vB
+    pub fn estimate_fee(&self, leaf: taproot::LeafId, n_sigs: usize, n_outputs: usize, fee_rate: u64) -> CoreResult<u64> {
+        let input_weight = fees::spend_weight(&self.tree, leaf, n_sigs)?;
+        let output_lens = vec![fees::P2TR_SCRIPT_PUBKEY_LEN; n_outputs];
+        fees::estimate_fee(fees::weight_to_vsize(fees::tx_weight(&[input_weight], &output_lens)), fee_rate)
+    }
+
+    /// Deposit address
+    pub fn address(&self) -> Address {
+        self.tree.address(self.network)
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        self.tree.script_pubkey()
+    }
+
+    /// BIP329 labels "Vault #N deposit" for the addresses at indexes
+    /// `0..=max_index`, to carry along in a label export
+    pub fn default_labels(&self, max_index: u32) -> CoreResult<Vec<export::LabelEntry>> {
+        (0..=max_index)
+            .map(|index| {
+                Ok(export::LabelEntry::Addr {
+                    reference: self.tree_at(index)?.address(self.network).to_string(),
+                    label: format!("Vault #{} deposit", index),
+                    origin: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Ranged descriptor for `importdescriptors`, see
+    /// `descriptor::to_core_descriptor()`
+    ///
+    /// A MuSig2 internal key can't be written as a ranged key, so vaults
+    /// with one fail with `InvalidInput`, as do `TreeVersion::V1` vaults.
+    pub fn descriptor(&self) -> CoreResult<String> {
+        if self.internal_key.is_some() {
+            return Err(CoreError::InvalidInput(
+                "Descriptors can't express a MuSig2 internal key".to_string(),
+            ));
+        }
+        descriptor::to_core_descriptor(
+            &self.template,
+            &self.owner_xpub,
+            &self.recovery_xpub,
+            self.network,
+            self.tree_version,
+        )
+    }
+
+    /// Metadata describing this vault
+    ///
+    /// `created_at_block` is as set by `VaultBuilder::created_at_block()`.
+    /// Destination indices aren't part of a `Vault` and are left empty.
+    /// Degrading vaults carry their stage table as `TLV_DEGRADING_STAGES`,
+    /// custom vaults with an absolute lock the lock as `TLV_ABSOLUTE_LOCK`
+    /// and with a hashlock the hash and service xpub as `TLV_HASHLOCK`,
+    /// and vaults past `TreeVersion::V1` their version as `TLV_TREE_VERSION`.
+    pub fn metadata(&self) -> VaultMetadata {
+        let mut metadata = VaultMetadata {
+            version: METADATA_V1,
+            template_id: self.template.template_id().to_string(),
+            delay_blocks: self.template.delay_blocks(),
+            delay_unit: self.template.delay_unit(),
+            destination_indices: vec![],
+            recovery_type: self.template.recovery_type(),
+            created_at_block: self.created_at_block,
+            vault_index: self.index,
+            key_path_enabled: self.template.key_path_enabled() || self.internal_key.is_some(),
+            heirs: self.template.heir_set(),
+            whitelist_delay: self.template.whitelist_delay(),
+            tlv_records: BTreeMap::new(),
+        };
+        if let Some(stages) = self.template.stages() {
+            // At most MAX_DEGRADING_KEYS stages, well within one record
+            metadata
+                .set_degrading_stages(stages)
+                .expect("validated stage table fits a TLV record");
+        }
+        metadata.set_absolute_lock(self.template.absolute_lock().expect("validated absolute lock"));
+        if let VaultTemplate::Custom { hashlock: Some(hashlock), .. } = &self.template {
+            metadata
+                .set_hashlock(Some(hashlock), self.network)
+                .expect("validated service xpub fits a TLV record");
+        }
+        metadata.set_tree_version(self.tree_version);
+        metadata
+    }
+
+    /// This vault's tree with `taproot::commitment_leaf()` of its
+    /// `metadata()` added: the tree behind its address when built in
+    /// `MetadataMode::Commitment`
+    pub fn commitment_tree(&self) -> CoreResult<VaultTree> {
+        let tree = taproot::vault_tree_with_metadata(
+            &self.template,
+            &self.owner_xpub,
+            &self.recovery_xpub,
+            self.index,
+            self.network,
+            &self.metadata(),
+            MetadataMode::Commitment,
+        )?;
+        match self.internal_key {
+            Some(internal_key) => taproot::build_tree(tree.leaves().to_vec(), internal_key, self.tree_version),
+            None => Ok(tree),
+        }
+    }
+
+    /// Check that `script_pubkey`, an output of this vault, commits to
+    /// its `metadata()`
+    ///
+    /// `leaf_script` must be a `taproot::commitment_leaf()` of
+    /// `metadata()`, and `commitment_tree()` must pay to `script_pubkey`.
+    /// The leaf alone proves nothing, since anyone can hash any metadata;
+    /// only the tree's output key ties it to the chain. Either mismatch
+    /// fails with `MetadataError`.
+    pub fn verify_commitment(&self, leaf_script: &Script, script_pubkey: &Script) -> CoreResult<()> {
+        let committed = taproot::extract_commitment(leaf_script)?;
+        let expected = self.metadata().commitment();
+        if committed != expected {
+            return Err(CoreError::MetadataError(format!(
+                "Leaf commits to metadata {}, but this vault's metadata hashes to {}",
+                hex::encode(committed),
+                hex::encode(expected)
+            )));
+        }
+        if self.commitment_tree()?.script_pubkey().as_script() != script_pubkey {
+            return Err(CoreError::MetadataError(format!(
+                "Commitment leaf is not in the tree of {}",
+                script_pubkey.to_hex_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// A spendable output of this vault
+    pub fn utxo(&self, outpoint: OutPoint, amount_sats: u64) -> psbt::VaultUtxo {
+        psbt::VaultUtxo::new(outpoint, amount_sats, self.tree.clone())
+    }
+
+    /// Change target paying to this vault's address at `vault_index`
+    ///
+    /// Pass an index the host hasn't handed out yet, and mark it used once
+    /// the PSBT is broadcast (see `psbt::PsbtBundle::change_index()`).
+    /// Change below the vault's `dust_policy()` goes to the fee.
+    pub fn change_target(&self, vault_index: u32) -> CoreResult<psbt::ChangeTarget> {
+        let tree = self.tree_at(vault_index)?;
+        let dust_threshold = self.dust_policy.limit(&tree.script_pubkey());
+        Ok(psbt::ChangeTarget::new(vault_index, tree).with_dust_threshold(dust_threshold))
+    }
+}
+
+/// Vault index in `0..=max_index` at which `vault`'s keys pay to `address`
+///
+/// `None` if no index up to `max_index` matches. An address for another
+/// network can never match and fails with `NetworkMismatch` instead.
+/// See `taproot::find_vault_index()` for the scan's cost and limit.
+pub fn verify_address(vault: &Vault, address: &Address, max_index: u32) -> CoreResult<Option<u32>> {
+    verify_address_cancellable(vault, address, max_index, &CancelToken::new())
+}
+
+/// `verify_address()`, giving up with `CoreError::Cancelled` once
+/// `cancel` is tripped
+pub fn verify_address_cancellable(
+    vault: &Vault,
+    address: &Address,
+    max_index: u32,
+    cancel: &CancelToken,
+) -> CoreResult<Option<u32>> {
+    let unchecked = Address::<NetworkUnchecked>::new(address.network, address.payload.clone());
+    if !unchecked.is_valid_for_network(vault.network().into()) {
+        return Err(CoreError::NetworkMismatch {
+            expected: vault.network().name().to_string(),
+            actual: policy::network_name(address.network).to_string(),
+        });
+    }
+
+    taproot::find_vault_index_cancellable(
+        vault.template(),
+        vault.owner_xpub(),
+        vault.recovery_xpub(),
+        vault.network(),
+        vault.tree_version(),
+        &address.script_pubkey(),
+        max_index,
+        cancel,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_common::{custom_template, CustomFields, OWNER_TPUB, OWNER_XPUB, RECOVERY_TPUB, RECOVERY_XPUB, THIRD_XPUB};
 
     #[test]
     fn test_metadata_roundtrip() {
@@ -255,10 +2266,15 @@ mod tests {
             version: 1,
             template_id: "savings_v1".to_string(),
             delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
             destination_indices: vec![0, 1, 2],
             recovery_type: RecoveryType::EmergencyKey,
             created_at_block: 800000,
             vault_index: 42,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: BTreeMap::new(),
         };
 
         let encoded = metadata.to_bytes();
@@ -273,16 +2289,1429 @@ mod tests {
     }
 
     #[test]
-    fn test_vault_template_delay_blocks() {
-        assert_eq!(VaultTemplate::savings().delay_blocks(), 1008);
-        assert_eq!(VaultTemplate::spending().delay_blocks(), 144);
+    fn test_metadata_rejects_trailing_bytes() {
+        let metadata = VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![0, 1, 2],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 800000,
+            vault_index: 42,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: BTreeMap::new(),
+        };
+
+        let mut encoded = metadata.to_bytes();
+        assert!(VaultMetadata::from_bytes(&encoded).is_ok());
+
+        encoded.push(0);
+        match VaultMetadata::from_bytes(&encoded) {
+            Err(crate::error::CoreError::MetadataError(msg)) => assert_eq!(msg, "trailing bytes"),
+            other => panic!("expected trailing bytes error, got {:?}", other),
+        }
     }
 
     #[test]
-    fn test_network_conversion() {
-        assert_eq!(bitcoin::Network::Bitcoin, Network::Mainnet.into());
-        assert_eq!(bitcoin::Network::Testnet, Network::Testnet.into());
-        assert_eq!(bitcoin::Network::Signet, Network::Signet.into());
-        assert_eq!(bitcoin::Network::Regtest, Network::Regtest.into());
+    fn test_metadata_rejects_long_template_id() {
+        let mut metadata = VaultMetadata {
+            version: 1,
+            template_id: "x".repeat(MAX_TEMPLATE_ID_LEN),
+            delay_blocks: 144,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 0,
+            vault_index: 0,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: BTreeMap::new(),
+        };
+        assert!(VaultMetadata::from_bytes(&metadata.to_bytes()).is_ok());
+
+        metadata.template_id.push('x');
+        assert!(matches!(
+            VaultMetadata::from_bytes(&metadata.to_bytes()),
+            Err(crate::error::CoreError::MetadataError(_))
+        ));
+
+        // A length byte alone is rejected before anything is read or allocated
+        assert!(VaultMetadata::from_bytes(&[1, 0xff]).is_err());
+    }
+
+    fn sample_metadata() -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![0, 1, 2],
+            recovery_type: RecoveryType::MultiSig,
+            created_at_block: 800000,
+            vault_index: 42,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: BTreeMap::new(),
+        }
+    }
+
+    fn assert_metadata_error(result: Result<VaultMetadata, CoreError>, expected: &str) {
+        match result {
+            Err(CoreError::MetadataError(msg)) => assert!(msg.starts_with(expected), "got {:?}", msg),
+            other => panic!("expected {:?}, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn test_metadata_v1_roundtrip() {
+        let metadata = sample_metadata();
+        let encoded = metadata.to_bytes();
+        assert_eq!(encoded[0], METADATA_V1);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.version, METADATA_V1);
+        assert_eq!(decoded.to_bytes(), encoded);
+    }
+
+    #[test]
+    fn test_metadata_v2_roundtrip() {
+        let metadata = sample_metadata();
+        let encoded = metadata.to_bytes_v2();
+        assert_eq!(encoded[0], METADATA_V2);
+        // v1 body, empty TLV section, checksum
+        assert_eq!(encoded.len(), metadata.to_bytes().len() + 2 + 4);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.version, METADATA_V2);
+        assert_eq!(decoded.template_id, metadata.template_id);
+        assert_eq!(decoded.delay_blocks, metadata.delay_blocks);
+        assert_eq!(decoded.destination_indices, metadata.destination_indices);
+        assert_eq!(decoded.recovery_type, metadata.recovery_type);
+        assert_eq!(decoded.created_at_block, metadata.created_at_block);
+        assert_eq!(decoded.vault_index, metadata.vault_index);
+        assert_eq!(decoded.to_bytes_v2(), encoded);
+    }
+
+    #[test]
+    fn test_metadata_v2_detects_corruption() {
+        let encoded = sample_metadata().to_bytes_v2();
+
+        for i in 1..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[i] ^= 0x01;
+            assert!(VaultMetadata::from_bytes(&corrupted).is_err(), "flip at byte {} accepted", i);
+        }
+
+        let mut corrupted = encoded.clone();
+        corrupted[5] ^= 0x01;
+        assert_metadata_error(VaultMetadata::from_bytes(&corrupted), "checksum mismatch");
+    }
+
+    #[test]
+    fn test_metadata_v2_truncation_is_not_corruption() {
+        let encoded = sample_metadata().to_bytes_v2();
+
+        assert_metadata_error(VaultMetadata::from_bytes(&encoded[..encoded.len() - 1]), "Truncated checksum");
+        assert_metadata_error(VaultMetadata::from_bytes(&encoded[..encoded.len() - 5]), "Truncated tlv section");
+        assert_metadata_error(VaultMetadata::from_bytes(&encoded[..14]), "Truncated delay_blocks");
+    }
+
+    /// Version 2 encoding of `sample_metadata()` with `records` as its TLV section
+    fn with_tlv_section(records: &[u8]) -> Vec<u8> {
+        let mut encoded = sample_metadata().to_bytes_v2();
+        encoded.truncate(encoded.len() - 6);
+        encoded.extend_from_slice(&(records.len() as u16).to_le_bytes());
+        encoded.extend_from_slice(records);
+        let checksum = crc32fast::hash(&encoded);
+        encoded.extend_from_slice(&checksum.to_le_bytes());
+        encoded
+    }
+
+    #[test]
+    fn test_metadata_v2_preserves_unknown_tlv_records() {
+        let encoded = with_tlv_section(&[TLV_KEY_PATH_ENABLED, 1, 1, 0x10, 3, 0xaa, 0xbb, 0xcc, 0xfe, 0]);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.vault_index, 42);
+        assert!(decoded.key_path_enabled);
+        assert_eq!(decoded.get_tlv(0x10), Some(&[0xaa, 0xbb, 0xcc][..]));
+        assert_eq!(decoded.get_tlv(0xfe), Some(&[][..]));
+        assert_eq!(decoded.get_tlv(TLV_KEY_PATH_ENABLED), None);
+        assert_eq!(decoded.to_bytes(), encoded);
+
+        let borrowed = VaultMetadataRef::parse(&encoded).unwrap();
+        assert_eq!(borrowed.get_tlv(0x10), Some(&[0xaa, 0xbb, 0xcc][..]));
+        assert_eq!(borrowed.get_tlv(0x11), None);
+
+        // Unknown records alone still need the version 2 layout
+        let mut metadata = sample_metadata();
+        metadata.set_tlv(0x10, vec![1]).unwrap();
+        assert_eq!(metadata.to_bytes()[0], METADATA_V2);
+        assert_eq!(VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap().get_tlv(0x10), Some(&[1][..]));
+    }
+
+    #[test]
+    fn test_metadata_label_and_cosigner_fingerprints() {
+        let fingerprints = [Fingerprint::from([0xde, 0xad, 0xbe, 0xef]), Fingerprint::from([1, 2, 3, 4])];
+        let mut metadata = sample_metadata();
+        assert_eq!(metadata.label(), None);
+        assert!(metadata.cosigner_fingerprints().is_empty());
+        metadata.set_label("family savings").unwrap();
+        metadata.set_cosigner_fingerprints(&fingerprints).unwrap();
+
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded.label(), Some("family savings"));
+        assert_eq!(decoded.cosigner_fingerprints(), fingerprints);
+
+        metadata.set_cosigner_fingerprints(&[]).unwrap();
+        assert_eq!(metadata.get_tlv(TLV_COSIGNER_FINGERPRINTS), None);
+
+        for records in [&[TLV_LABEL, 2, 0xc3, 0x28][..], &[TLV_COSIGNER_FINGERPRINTS, 3, 1, 2, 3], &[TLV_COSIGNER_FINGERPRINTS, 0]] {
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(records)), "Invalid");
+        }
+    }
+
+    #[test]
+    fn test_metadata_degrading_stages_roundtrip() {
+        let stages = [(0, 3), (4032, 2), (26_208, 1)];
+        let mut metadata = sample_metadata();
+        assert!(metadata.degrading_stages().is_empty());
+        metadata.set_degrading_stages(&stages).unwrap();
+        assert_eq!(
+            metadata.get_tlv(TLV_DEGRADING_STAGES),
+            Some(&[0, 0, 0, 0, 3, 0xc0, 0x0f, 0, 0, 2, 0x60, 0x66, 0, 0, 1][..])
+        );
+
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded.version, METADATA_V2);
+        assert_eq!(decoded.degrading_stages(), stages);
+
+        metadata.set_degrading_stages(&[]).unwrap();
+        assert_eq!(metadata.get_tlv(TLV_DEGRADING_STAGES), None);
+
+        for records in [&[TLV_DEGRADING_STAGES, 0][..], &[TLV_DEGRADING_STAGES, 4, 0, 0, 0, 3]] {
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(records)), "Invalid degrading stages record");
+        }
+    }
+
+    #[test]
+    fn test_metadata_tree_version_roundtrip() {
+        let mut metadata = sample_metadata();
+        assert_eq!(metadata.tree_version().unwrap(), TreeVersion::V1);
+        metadata.set_tree_version(TreeVersion::V2);
+        assert_eq!(metadata.get_tlv(TLV_TREE_VERSION), Some(&[2][..]));
+
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded.version, METADATA_V2);
+        assert_eq!(decoded.tree_version().unwrap(), TreeVersion::V2);
+
+        // V1 is the absence of the record, so old metadata keeps its bytes
+        let v1_bytes = sample_metadata().to_bytes();
+        metadata.set_tree_version(TreeVersion::V1);
+        assert_eq!(metadata.get_tlv(TLV_TREE_VERSION), None);
+        assert_eq!(metadata.to_bytes(), v1_bytes);
+
+        for records in [&[TLV_TREE_VERSION, 1, 1][..], &[TLV_TREE_VERSION, 1, 0xff], &[TLV_TREE_VERSION, 2, 2, 0]] {
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(records)), "Invalid tree version record");
+        }
+    }
+
+    #[test]
+    fn test_metadata_absolute_lock_roundtrip() {
+        let mut metadata = sample_metadata();
+        assert_eq!(metadata.absolute_lock(), None);
+        for lock in [LockTime::from_height(1_000_000).unwrap(), LockTime::from_time(1_700_000_000).unwrap()] {
+            metadata.set_absolute_lock(Some(lock));
+            assert_eq!(metadata.get_tlv(TLV_ABSOLUTE_LOCK), Some(&lock.to_consensus_u32().to_le_bytes()[..]));
+            let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+            assert_eq!(decoded.absolute_lock(), Some(lock));
+        }
+        metadata.set_absolute_lock(None);
+        assert_eq!(metadata.get_tlv(TLV_ABSOLUTE_LOCK), None);
+
+        for records in [&[TLV_ABSOLUTE_LOCK, 0][..], &[TLV_ABSOLUTE_LOCK, 3, 1, 2, 3], &[TLV_ABSOLUTE_LOCK, 4, 0, 0, 0, 0]] {
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(records)), "Invalid absolute lock record");
+        }
+    }
+
+    #[test]
+    fn test_metadata_hashlock_roundtrip() {
+        let mut metadata = sample_metadata();
+        assert!(metadata.hashlock().is_none());
+        let hashlock = HashlockRecovery { hash: sha256::Hash::hash(b"secret"), service_xpub: OWNER_TPUB.to_string() };
+        metadata.set_hashlock(Some(&hashlock), Network::Regtest).unwrap();
+        assert_eq!(metadata.get_tlv(TLV_HASHLOCK).map(<[u8]>::len), Some(HASHLOCK_RECORD_LEN));
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap().hashlock().unwrap();
+        assert_eq!(decoded.hash, hashlock.hash);
+        assert_eq!(decoded.service_xpub, OWNER_TPUB);
+
+        assert!(metadata.set_hashlock(Some(&hashlock), Network::Mainnet).is_err());
+        metadata.set_hashlock(None, Network::Regtest).unwrap();
+        assert_eq!(metadata.get_tlv(TLV_HASHLOCK), None);
+
+        let mut zero_hash = vec![TLV_HASHLOCK, HASHLOCK_RECORD_LEN as u8];
+        zero_hash.extend_from_slice(&[0; 32]);
+        zero_hash.extend_from_slice(&keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap().encode());
+        for records in [&[TLV_HASHLOCK, 1, 1][..], &zero_hash] {
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(records)), "Invalid hashlock record");
+        }
+    }
+
+    #[test]
+    fn test_set_tlv_rejects_what_from_bytes_rejects() {
+        let mut metadata = sample_metadata();
+        for (tlv_type, value, message) in [
+            (TLV_LABEL, vec![0xff, 0xfe], "Invalid label record"),
+            (TLV_COSIGNER_FINGERPRINTS, vec![], "Invalid cosigner fingerprints record"),
+            (TLV_COSIGNER_FINGERPRINTS, vec![1; MAX_TLV_VALUE_LEN], "Invalid cosigner fingerprints record"),
+            (TLV_DEGRADING_STAGES, vec![1; 4], "Invalid degrading stages record"),
+            (TLV_TREE_VERSION, vec![u8::from(TreeVersion::V1)], "Invalid tree version record"),
+            (TLV_ABSOLUTE_LOCK, vec![0; 4], "Invalid absolute lock record"),
+            (TLV_HASHLOCK, vec![1; 32], "Invalid hashlock record"),
+        ] {
+            // The bytes from_bytes() refuses are refused before they're stored
+            let mut encoded = vec![tlv_type, value.len() as u8];
+            encoded.extend_from_slice(&value);
+            assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(&encoded)), message);
+            assert_metadata_error(metadata.set_tlv(tlv_type, value).map(|_| sample_metadata()), message);
+            assert_eq!(metadata.get_tlv(tlv_type), None);
+        }
+        metadata.set_tlv(TLV_COSIGNER_FINGERPRINTS, vec![1; 8]).unwrap();
+        assert_eq!(VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap().cosigner_fingerprints().len(), 2);
+    }
+
+    #[test]
+    fn test_metadata_rejects_duplicate_tlv_types() {
+        for records in [
+            &[0x10, 1, 1, 0x10, 1, 2][..],
+            &[TLV_LABEL, 1, b'a', 0x10, 0, TLV_LABEL, 1, b'b'],
+            &[TLV_KEY_PATH_ENABLED, 1, 1, TLV_KEY_PATH_ENABLED, 1, 1],
+        ] {
+            let encoded = with_tlv_section(records);
+            assert_metadata_error(VaultMetadata::from_bytes(&encoded), "Duplicate TLV record type");
+            assert!(VaultMetadataRef::parse(&encoded).is_err());
+        }
+    }
+
+    #[test]
+    fn test_metadata_json_roundtrip_keeps_bytes() {
+        let mut v2 = sample_metadata();
+        v2.version = METADATA_V2;
+        let mut full = v2.clone();
+        full.key_path_enabled = true;
+        full.whitelist_delay = Some(144);
+        full.set_label("cold").unwrap();
+        full.set_tlv(0x10, vec![0xaa]).unwrap();
+
+        for metadata in [sample_metadata(), v2, full] {
+            let bytes = metadata.to_bytes_as_version().unwrap();
+            assert_eq!(bytes[0], metadata.version);
+            let json = VaultMetadata::from_bytes(&bytes).unwrap().to_json().unwrap();
+            assert_eq!(VaultMetadata::from_json(&json).unwrap().to_bytes_as_version().unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_metadata_from_json_rejects_drift() {
+        let json = |metadata: &VaultMetadata| serde_json::to_value(metadata).unwrap();
+        let serialization_error = |value: serde_json::Value, expected: &str| {
+            match VaultMetadata::from_json(&value.to_string()) {
+                Err(CoreError::SerializationError(msg)) => assert!(msg.contains(expected), "got {:?}", msg),
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        };
+
+        let mut value = json(&sample_metadata());
+        value["note"] = serde_json::json!("x");
+        serialization_error(value, "unknown field `note`");
+        let mut value = json(&sample_metadata());
+        value["heirs"] = serde_json::json!({"threshold": 1, "count": 2, "extra": 0});
+        serialization_error(value, "unknown field `extra`");
+
+        // A record the binary form keeps in its own field
+        let mut value = json(&sample_metadata());
+        value["version"] = serde_json::json!(2);
+        value["tlv_records"] = serde_json::json!({"1": [1]});
+        serialization_error(value, "does not round-trip");
+
+        let mut metadata = sample_metadata();
+        metadata.key_path_enabled = true;
+        assert_metadata_error(VaultMetadata::from_json(&json(&metadata).to_string()), "Metadata needs the version 2 layout");
+        metadata.version = 3;
+        assert_metadata_error(VaultMetadata::from_json(&json(&metadata).to_string()), "unsupported version: 3");
+        let mut metadata = sample_metadata();
+        metadata.template_id = "x".repeat(MAX_TEMPLATE_ID_LEN + 1);
+        assert_metadata_error(VaultMetadata::from_json(&json(&metadata).to_string()), "template_id too long");
+    }
+
+    #[test]
+    fn test_metadata_tlv_length_overflow() {
+        // A record running past its section, and a section running past the blob
+        assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(&[0x10, 4, 1, 2])), "Truncated tlv value");
+        assert_metadata_error(VaultMetadata::from_bytes(&with_tlv_section(&[0x10])), "Truncated tlv length");
+        let mut encoded = with_tlv_section(&[0x10, 1, 1]);
+        let section_len = encoded.len() - 9;
+        encoded[section_len..section_len + 2].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_metadata_error(VaultMetadata::from_bytes(&encoded), "Truncated tlv section");
+
+        let mut metadata = sample_metadata();
+        metadata.set_tlv(0x10, vec![0; MAX_TLV_VALUE_LEN]).unwrap();
+        assert_metadata_error(metadata.set_tlv(0x11, vec![0; MAX_TLV_VALUE_LEN + 1]).map(|_| sample_metadata()), "TLV record type 17 too long");
+        assert_metadata_error(metadata.set_label(&"x".repeat(256)).map(|_| sample_metadata()), "TLV record type 4 too long");
+        assert_metadata_error(metadata.set_tlv(TLV_HEIRS, vec![1, 1]).map(|_| sample_metadata()), "TLV record type 2 has its own field");
+
+        // Every type at full length still fits the 2-byte section length
+        metadata.key_path_enabled = true;
+        metadata.heirs = Some(HeirSet { threshold: 1, count: 2 });
+        metadata.whitelist_delay = Some(144);
+        for tlv_type in (0..=u8::MAX).filter(|&t| !has_own_field(t)) {
+            // Cosigner fingerprints come in whole 4-byte units; the tree
+            // version is one byte, the absolute lock four and the hashlock
+            // a hash and an encoded xpub
+            let value = match tlv_type {
+                TLV_COSIGNER_FINGERPRINTS => vec![tlv_type; 252],
+                TLV_TREE_VERSION => vec![TreeVersion::LATEST.into()],
+                TLV_ABSOLUTE_LOCK => vec![tlv_type; 4],
+                TLV_HASHLOCK => [tlv_type; 32]
+                    .into_iter()
+                    .chain(keys::parse_xpub(OWNER_TPUB, Network::Regtest).unwrap().encode())
+                    .collect(),
+                _ => vec![tlv_type; MAX_TLV_VALUE_LEN],
+            };
+            metadata.set_tlv(tlv_type, value).unwrap();
+        }
+        let decoded = VaultMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+        assert_eq!(decoded.tlv_records, metadata.tlv_records);
+    }
+
+    #[test]
+    fn test_metadata_key_path_enabled_roundtrip() {
+        let mut metadata = sample_metadata();
+        assert!(!VaultMetadata::from_bytes(&metadata.to_bytes_v2()).unwrap().key_path_enabled);
+
+        metadata.key_path_enabled = true;
+        // Version 1 can't carry the flag, so to_bytes() switches to version 2
+        let encoded = metadata.to_bytes();
+        assert_eq!(encoded, metadata.to_bytes_v2());
+        assert_eq!(encoded.len(), metadata.encode_fields(METADATA_V2).len() + 2 + 3 + 4);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.version, METADATA_V2);
+        assert!(decoded.key_path_enabled);
+        assert_eq!(decoded.to_bytes(), encoded);
+    }
+
+    #[test]
+    fn test_metadata_rejects_bad_key_path_record() {
+        let mut encoded = sample_metadata().to_bytes_v2();
+        encoded.truncate(encoded.len() - 6);
+        encoded.extend_from_slice(&3u16.to_le_bytes());
+        encoded.extend_from_slice(&[TLV_KEY_PATH_ENABLED, 1, 2]);
+        let checksum = crc32fast::hash(&encoded);
+        encoded.extend_from_slice(&checksum.to_le_bytes());
+
+        assert_metadata_error(VaultMetadata::from_bytes(&encoded), "Invalid key_path_enabled");
+    }
+
+    #[test]
+    fn test_metadata_time_based_delay_roundtrip() {
+        let mut metadata = sample_metadata();
+        metadata.delay_blocks = 144;
+        metadata.delay_unit = DelayUnit::TimeUnits512s;
+
+        // Version 1 can't carry the unit, so to_bytes() switches to version 2
+        let encoded = metadata.to_bytes();
+        assert_eq!(encoded[0], METADATA_V2);
+        // Delay field follows the version byte and the 10-byte template id
+        assert_eq!(encoded[12..16], 0x0040_0090u32.to_le_bytes());
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.delay_blocks, 144);
+        assert_eq!(decoded.delay_unit, DelayUnit::TimeUnits512s);
+        assert!(!decoded.key_path_enabled);
+        assert_eq!(decoded.to_bytes(), encoded);
+
+        // A block delay in v2 leaves the flag clear
+        let decoded = VaultMetadata::from_bytes(&sample_metadata().to_bytes_v2()).unwrap();
+        assert_eq!(decoded.delay_unit, DelayUnit::Blocks);
+    }
+
+    #[test]
+    fn test_metadata_rejects_oversized_time_delay() {
+        let mut metadata = sample_metadata();
+        metadata.delay_unit = DelayUnit::TimeUnits512s;
+        metadata.delay_blocks = 0x1_0000;
+
+        assert_metadata_error(VaultMetadata::from_bytes(&metadata.to_bytes()), "Time-based delay");
+    }
+
+    #[test]
+    fn test_delay_unit_sequence_encoding() {
+        assert_eq!(DelayUnit::Blocks.sequence(144).unwrap().to_consensus_u32(), 0x0000_0090);
+        assert_eq!(DelayUnit::TimeUnits512s.sequence(144).unwrap().to_consensus_u32(), 0x0040_0090);
+        assert_eq!(DelayUnit::TimeUnits512s.sequence(0xffff).unwrap().to_consensus_u32(), 0x0040_ffff);
+
+        for unit in [DelayUnit::Blocks, DelayUnit::TimeUnits512s] {
+            assert!(matches!(unit.sequence(0x1_0000), Err(CoreError::PolicyViolation(_))));
+        }
+
+        let template = custom_template(CustomFields {
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::TimeUnits512s,
+            recovery_type: RecoveryType::EmergencyKey,
+            ..Default::default()
+        });
+        assert_eq!(template.sequence().unwrap(), Sequence::from_512_second_intervals(1008));
+        assert_eq!(VaultTemplate::savings().sequence().unwrap(), Sequence::from_height(1008));
+    }
+
+    #[test]
+    fn test_metadata_unsupported_version() {
+        for version in [0u8, 3, 0xff] {
+            let mut encoded = sample_metadata().to_bytes();
+            encoded[0] = version;
+            assert_metadata_error(VaultMetadata::from_bytes(&encoded), "unsupported version");
+        }
+    }
+
+    #[test]
+    fn test_vault_template_delay_blocks() {
+        assert_eq!(VaultTemplate::savings().delay_blocks(), 1008);
+        assert_eq!(VaultTemplate::spending().delay_blocks(), 144);
+    }
+
+    #[test]
+    fn test_vault_template_delay_bounds() {
+        for delay in [0, MAX_CSV_DELAY_BLOCKS + 1] {
+            match VaultTemplate::custom(delay, RecoveryType::EmergencyKey) {
+                Err(CoreError::PolicyViolation(msg)) => assert!(msg.contains(&delay.to_string()), "got {:?}", msg),
+                other => panic!("delay {} accepted: {:?}", delay, other),
+            }
+        }
+        for delay in [1, MAX_CSV_DELAY_BLOCKS] {
+            let template = VaultTemplate::custom(delay, RecoveryType::TimelockOnly).unwrap();
+            assert_eq!(template.delay_blocks(), delay);
+            assert_eq!(template.delay_unit(), DelayUnit::Blocks);
+        }
+
+        assert!(VaultTemplate::savings().validate().is_ok());
+        assert!(VaultTemplate::Spending { delay_blocks: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_vault_template_deserialize_validates_delay() {
+        let parse = |json: &str| serde_json::from_str::<VaultTemplate>(json);
+
+        let template = parse(r#"{"type":"custom","delay_blocks":65535,"recovery_type":"emergency_key"}"#).unwrap();
+        assert_eq!(template.delay_blocks(), 65_535);
+        assert_eq!(parse(r#"{"type":"savings"}"#).unwrap().delay_blocks(), 1008);
+
+        for json in [
+            r#"{"type":"custom","delay_blocks":0,"recovery_type":"emergency_key"}"#,
+            r#"{"type":"custom","delay_blocks":65536,"recovery_type":"timelock_only"}"#,
+            r#"{"type":"custom","delay_blocks":1000000,"delay_unit":"time_units_512s","recovery_type":"emergency_key"}"#,
+            r#"{"type":"spending","delay_blocks":0}"#,
+        ] {
+            let err = parse(json).unwrap_err();
+            assert!(err.to_string().contains("outside the CSV range"), "{}: {}", json, err);
+        }
+
+        // Serialization is unchanged and round-trips
+        let json = serde_json::to_string(&VaultTemplate::spending()).unwrap();
+        assert_eq!(json, r#"{"type":"spending","delay_blocks":144}"#);
+        assert_eq!(parse(&json).unwrap().delay_blocks(), 144);
+    }
+
+    fn inheritance_template(heir_threshold: u8, heir_count: u8, heirs: usize) -> VaultTemplate {
+        VaultTemplate::Inheritance {
+            heir_threshold,
+            heir_count,
+            inactivity_blocks: 26_000,
+            heirs: vec!["xpub".to_string(); heirs],
+        }
+    }
+
+    #[test]
+    fn test_inheritance_template_validation() {
+        assert!(inheritance_template(2, 3, 3).validate().is_ok());
+        assert!(inheritance_template(1, 1, 1).validate().is_ok());
+        assert!(inheritance_template(MAX_HEIRS, MAX_HEIRS, MAX_HEIRS as usize).validate().is_ok());
+
+        for template in [
+            inheritance_template(0, 3, 3),
+            inheritance_template(4, 3, 3),
+            inheritance_template(2, MAX_HEIRS + 1, MAX_HEIRS as usize + 1),
+            inheritance_template(2, 3, 2),
+            VaultTemplate::Inheritance {
+                heir_threshold: 2,
+                heir_count: 3,
+                inactivity_blocks: 0,
+                heirs: vec!["xpub".to_string(); 3],
+            },
+        ] {
+            assert!(
+                matches!(template.validate(), Err(CoreError::PolicyViolation(_))),
+                "{:?} accepted",
+                template
+            );
+        }
+
+        let template = inheritance_template(2, 3, 3);
+        assert_eq!(template.template_id(), "inheritance_v1");
+        assert_eq!(template.delay_blocks(), 26_000);
+        assert!(template.key_path_enabled());
+        assert_eq!(template.heir_set(), Some(HeirSet { threshold: 2, count: 3 }));
+        assert_eq!(VaultTemplate::savings().heir_set(), None);
+    }
+
+    #[test]
+    fn test_inheritance_template_json() {
+        let json = r#"{"type":"inheritance","heir_threshold":2,"heir_count":3,"inactivity_blocks":26000,"heirs":["a","b","c"]}"#;
+        let template: VaultTemplate = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string(&template).unwrap(), json);
+
+        let bad = r#"{"type":"inheritance","heir_threshold":3,"heir_count":2,"inactivity_blocks":26000,"heirs":["a","b"]}"#;
+        assert!(serde_json::from_str::<VaultTemplate>(bad).is_err());
+    }
+
+    #[test]
+    fn test_metadata_heirs_roundtrip() {
+        let mut metadata = sample_metadata();
+        metadata.template_id = "inheritance_v1".to_string();
+        metadata.heirs = Some(HeirSet { threshold: 2, count: 3 });
+
+        // No version 1 field holds k-of-n, so the heir set needs the TLV layout
+        let encoded = metadata.to_bytes();
+        assert_eq!(encoded[0], METADATA_V2);
+        assert_eq!(encoded.len(), metadata.encode_fields(METADATA_V2).len() + 2 + 4 + 4);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.heirs, Some(HeirSet { threshold: 2, count: 3 }));
+        assert_eq!(decoded.template_id, "inheritance_v1");
+        assert_eq!(decoded.to_bytes(), encoded);
+    }
+
+    #[test]
+    fn test_metadata_rejects_bad_heirs_record() {
+        for record in [&[TLV_HEIRS, 2, 0, 3][..], &[TLV_HEIRS, 2, 3, 2], &[TLV_HEIRS, 2, 1, 16], &[TLV_HEIRS, 1, 1]] {
+            let mut encoded = sample_metadata().to_bytes_v2();
+            encoded.truncate(encoded.len() - 6);
+            encoded.extend_from_slice(&(record.len() as u16).to_le_bytes());
+            encoded.extend_from_slice(record);
+            let checksum = crc32fast::hash(&encoded);
+            encoded.extend_from_slice(&checksum.to_le_bytes());
+
+            assert_metadata_error(VaultMetadata::from_bytes(&encoded), "Invalid heirs");
+        }
+    }
+
+    #[test]
+    fn test_dual_delay_template_validation() {
+        let template = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        template.validate().unwrap();
+        assert_eq!(template.template_id(), "dual_delay_v1");
+        assert_eq!(template.delay_blocks(), 1008);
+        assert_eq!(template.whitelist_delay(), Some(144));
+        assert_eq!(VaultTemplate::spending().whitelist_delay(), None);
+
+        for (whitelist_delay, open_delay) in [(1008, 1008), (1009, 1008), (0, 1008), (144, 65_536)] {
+            let template = VaultTemplate::DualDelay { whitelist_delay, open_delay };
+            assert!(
+                matches!(template.validate(), Err(CoreError::PolicyViolation(_))),
+                "{:?} accepted",
+                template
+            );
+        }
+
+        let json = r#"{"type":"dual_delay","whitelist_delay":144,"open_delay":1008}"#;
+        let template: VaultTemplate = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string(&template).unwrap(), json);
+        assert!(serde_json::from_str::<VaultTemplate>(r#"{"type":"dual_delay","whitelist_delay":1008,"open_delay":144}"#).is_err());
+    }
+
+    #[test]
+    fn test_absolute_lock_template_validation() {
+        let parse = |json: &str| serde_json::from_str::<VaultTemplate>(json);
+
+        let json = r#"{"type":"custom","delay_blocks":144,"delay_unit":"blocks","recovery_type":"timelock_only","key_path_enabled":false,"absolute_lock":1000000}"#;
+        let template = parse(json).unwrap();
+        assert_eq!(template.absolute_lock().unwrap(), Some(LockTime::from_height(1_000_000).unwrap()));
+        assert_eq!(serde_json::to_string(&template).unwrap(), json);
+        assert_eq!(VaultTemplate::savings().absolute_lock().unwrap(), None);
+
+        // Timestamps need the unit spelled out
+        let dated = parse(r#"{"type":"custom","delay_blocks":144,"recovery_type":"timelock_only","absolute_lock":1700000000,"absolute_lock_unit":"seconds"}"#).unwrap();
+        assert_eq!(dated.absolute_lock().unwrap(), Some(LockTime::from_time(1_700_000_000).unwrap()));
+        assert!(serde_json::to_string(&dated).unwrap().contains(r#""absolute_lock_unit":"seconds""#));
+
+        for json in [
+            r#"{"type":"custom","delay_blocks":144,"recovery_type":"timelock_only","absolute_lock":500000000}"#,
+            r#"{"type":"custom","delay_blocks":144,"recovery_type":"timelock_only","absolute_lock":0}"#,
+            r#"{"type":"custom","delay_blocks":144,"recovery_type":"multi_sig","absolute_lock":1000,"absolute_lock_unit":"seconds"}"#,
+        ] {
+            let err = parse(json).unwrap_err();
+            assert!(err.to_string().contains("is not a valid"), "{}: {}", json, err);
+        }
+
+        // The emergency key can already spend at any time
+        let err = parse(r#"{"type":"custom","delay_blocks":144,"recovery_type":"emergency_key","absolute_lock":1000000}"#).unwrap_err();
+        assert!(err.to_string().contains("needs timelock_only or multi_sig recovery"), "{}", err);
+    }
+
+    #[test]
+    fn test_hashlock_template_validation() {
+        let parse = |json: &str| serde_json::from_str::<VaultTemplate>(json);
+        let hash = sha256::Hash::hash(b"recovery secret");
+
+        let json = format!(
+            r#"{{"type":"custom","delay_blocks":144,"delay_unit":"blocks","recovery_type":"emergency_key","key_path_enabled":false,"hashlock":{{"hash":"{}","service_xpub":"xpub-service"}}}}"#,
+            hash
+        );
+        let template = parse(&json).unwrap();
+        match &template {
+            VaultTemplate::Custom { hashlock: Some(hashlock), .. } => assert_eq!(hashlock.hash, hash),
+            other => panic!("unexpected template {:?}", other),
+        }
+        assert_eq!(serde_json::to_string(&template).unwrap(), json);
+        assert!(!serde_json::to_string(&VaultTemplate::custom(144, RecoveryType::EmergencyKey).unwrap()).unwrap().contains("hashlock"));
+
+        let zeros = format!(
+            r#"{{"type":"custom","delay_blocks":144,"recovery_type":"timelock_only","hashlock":{{"hash":"{}","service_xpub":"xpub-service"}}}}"#,
+            "00".repeat(32)
+        );
+        let err = parse(&zeros).unwrap_err();
+        assert!(err.to_string().contains("all zeros"), "{}", err);
+    }
+
+    #[test]
+    fn test_degrading_template_validation() {
+        let parse = |keys: u8, stages: &str, cosigners: usize| {
+            let cosigners = vec!["xpub"; cosigners];
+            serde_json::from_str::<VaultTemplate>(&format!(
+                r#"{{"type":"degrading","keys":{},"stages":{},"cosigners":{:?}}}"#,
+                keys, stages, cosigners
+            ))
+        };
+
+        let template = parse(3, "[[0,3],[4032,2],[26208,1]]", 1).unwrap();
+        assert_eq!(template.template_id(), "degrading_v1");
+        assert_eq!(template.stages(), Some(&[(0, 3), (4032, 2), (26_208, 1)][..]));
+        // The first delayed stage stands in for the template's delay
+        assert_eq!(template.delay_blocks(), 4032);
+        assert_eq!(template.recovery_type(), RecoveryType::MultiSig);
+        assert!(!template.key_path_enabled());
+        assert!(parse(3, "[[144,2],[1008,1]]", 1).is_ok());
+
+        for (keys, stages, cosigners, expected) in [
+            (3, "[[0,3],[4032,2]]", 2, "names 2 cosigners"),
+            (2, "[[0,2],[4032,1]]", 0, "expected 3..=15"),
+            (16, "[[0,2],[4032,1]]", 14, "expected 3..=15"),
+            (3, "[[0,3]]", 1, "at least two stages"),
+            (3, "[[0,4],[4032,2]]", 1, "threshold 4 is invalid"),
+            (3, "[[0,3],[4032,0]]", 1, "threshold 0 is invalid"),
+            (3, "[[0,3],[65536,2]]", 1, "outside the CSV range"),
+            (3, "[[4032,3],[144,2]]", 1, "longer delay"),
+            (3, "[[0,3],[0,2]]", 1, "longer delay"),
+            (3, "[[0,2],[4032,2]]", 1, "lower threshold"),
+            (3, "[[0,1],[4032,2]]", 1, "lower threshold"),
+        ] {
+            let err = parse(keys, stages, cosigners).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", stages, err);
+        }
+    }
+
+    #[test]
+    fn test_metadata_whitelist_delay_roundtrip() {
+        let mut metadata = sample_metadata();
+        metadata.whitelist_delay = Some(144);
+
+        // The whitelist delay goes in its own record, which only version 2 has
+        let encoded = metadata.to_bytes();
+        assert_eq!(encoded[0], METADATA_V2);
+        assert_eq!(encoded.len(), metadata.encode_fields(METADATA_V2).len() + 2 + 6 + 4);
+
+        let decoded = VaultMetadata::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.delay_blocks, 1008);
+        assert_eq!(decoded.whitelist_delay, Some(144));
+        assert_eq!(decoded.to_bytes(), encoded);
+
+        // Zero, too long for the record, or not shorter than delay_blocks
+        for record in [&[TLV_WHITELIST_DELAY, 4, 0, 0, 0, 0][..], &[TLV_WHITELIST_DELAY, 2, 144, 0], &[TLV_WHITELIST_DELAY, 4, 0xf0, 0x03, 0, 0]] {
+            let mut encoded = sample_metadata().to_bytes_v2();
+            encoded.truncate(encoded.len() - 6);
+            encoded.extend_from_slice(&(record.len() as u16).to_le_bytes());
+            encoded.extend_from_slice(record);
+            let checksum = crc32fast::hash(&encoded);
+            encoded.extend_from_slice(&checksum.to_le_bytes());
+
+            assert_metadata_error(VaultMetadata::from_bytes(&encoded), "Invalid whitelist_delay");
+        }
+    }
+
+    #[test]
+    fn test_metadata_ref_borrows_input() {
+        let mut metadata = sample_metadata();
+        metadata.heirs = Some(HeirSet { threshold: 2, count: 3 });
+        let encoded = metadata.to_bytes();
+
+        let parsed = VaultMetadataRef::parse(&encoded).unwrap();
+        assert_eq!(parsed.template_id, "savings_v1");
+        assert_eq!(parsed.destination_indices, [0, 1, 2]);
+        assert_eq!(parsed.heirs, metadata.heirs);
+        let input = encoded.as_ptr_range();
+        assert!(input.contains(&parsed.template_id.as_ptr()));
+        assert!(input.contains(&parsed.destination_indices.as_ptr()));
+        assert_eq!(parsed.to_owned().to_bytes(), encoded);
+    }
+
+    #[test]
+    fn test_metadata_ref_agrees_with_owned_on_damaged_input() {
+        let mut full = sample_metadata();
+        full.delay_unit = DelayUnit::TimeUnits512s;
+        full.key_path_enabled = true;
+        full.heirs = Some(HeirSet { threshold: 1, count: 2 });
+        full.whitelist_delay = Some(144);
+        full.set_label("cold").unwrap();
+        let mut empty = sample_metadata();
+        empty.template_id.clear();
+        empty.destination_indices.clear();
+
+        for encoded in [sample_metadata().to_bytes(), sample_metadata().to_bytes_v2(), full.to_bytes(), empty.to_bytes()] {
+            let mut inputs: Vec<Vec<u8>> = (0..=encoded.len()).map(|len| encoded[..len].to_vec()).collect();
+            for i in 0..encoded.len() {
+                let mut flipped = encoded.clone();
+                flipped[i] ^= 0x80;
+                inputs.push(flipped);
+            }
+
+            for input in &inputs {
+                match (VaultMetadataRef::parse(input), VaultMetadata::from_bytes(input)) {
+                    (Ok(borrowed), Ok(owned)) => {
+                        assert_eq!(borrowed.template_id, owned.template_id);
+                        assert_eq!(borrowed.destination_indices, owned.destination_indices.as_slice());
+                        assert_eq!(borrowed.to_owned().to_bytes(), owned.to_bytes());
+                    }
+                    (Err(borrowed), Err(owned)) => assert_eq!(borrowed.to_string(), owned.to_string()),
+                    (borrowed, owned) => panic!("{:02x?}: parse gave {:?}, from_bytes gave {:?}", input, borrowed, owned),
+                }
+            }
+            // Only the whole encoding decodes
+            assert!(inputs[..encoded.len()].iter().all(|input| VaultMetadataRef::parse(input).is_err()));
+        }
+    }
+
+    #[test]
+    fn test_metadata_commitment_covers_every_field() {
+        let original = sample_metadata().commitment();
+        assert_eq!(sample_metadata().commitment(), original);
+
+        // `version` is omitted: to_bytes() picks the layout from the other fields
+        let flips: [fn(&mut VaultMetadata); 11] = [
+            |m| m.template_id = "spending_v1".to_string(),
+            |m| m.delay_blocks = 1009,
+            |m| m.delay_unit = DelayUnit::TimeUnits512s,
+            |m| m.destination_indices = vec![0, 1],
+            |m| m.destination_indices = vec![0, 1, 3],
+            |m| m.recovery_type = RecoveryType::EmergencyKey,
+            |m| m.created_at_block = 800_001,
+            |m| m.vault_index = 43,
+            |m| m.key_path_enabled = true,
+            |m| m.heirs = Some(HeirSet { threshold: 1, count: 2 }),
+            |m| m.whitelist_delay = Some(144),
+        ];
+        let mut seen = vec![original];
+        for flip in flips {
+            let mut metadata = sample_metadata();
+            flip(&mut metadata);
+            let commitment = metadata.commitment();
+            assert!(!seen.contains(&commitment), "{:?} collides", metadata);
+            seen.push(commitment);
+        }
+
+        // Tagged, so not the plain hash of the bytes
+        assert_ne!(original, sha256::Hash::hash(&sample_metadata().to_bytes()).to_byte_array());
+    }
+
+    #[test]
+    fn test_vault_verify_commitment() {
+        let vault = mainnet_builder().index(5).created_at_block(840_000).build().unwrap();
+        assert_eq!(vault.metadata().created_at_block, 840_000);
+
+        let leaf = taproot::commitment_leaf(&vault.metadata().commitment());
+        let funded = vault.commitment_tree().unwrap().script_pubkey();
+        assert!(vault.commitment_tree().unwrap().leaves().iter().any(|l| l.script == leaf));
+        assert_ne!(funded, vault.address().script_pubkey());
+        vault.verify_commitment(&leaf, &funded).unwrap();
+
+        // Another height, another index, or a full metadata leaf
+        let other_height = mainnet_builder().index(5).build().unwrap();
+        let other_index = mainnet_builder().index(6).created_at_block(840_000).build().unwrap();
+        for other in [other_height, other_index] {
+            assert!(matches!(other.verify_commitment(&leaf, &funded), Err(CoreError::MetadataError(_))));
+        }
+        let full = taproot::metadata_leaf(&vault.metadata());
+        assert!(matches!(vault.verify_commitment(&full, &funded), Err(CoreError::MetadataError(_))));
+
+        // A leaf matching the metadata proves nothing about an output whose
+        // tree doesn't contain it
+        let other_index = mainnet_builder().index(6).created_at_block(840_000).build().unwrap();
+        for unrelated in [vault.address().script_pubkey(), other_index.commitment_tree().unwrap().script_pubkey()] {
+            match vault.verify_commitment(&leaf, &unrelated) {
+                Err(CoreError::MetadataError(msg)) => assert!(msg.starts_with("Commitment leaf is not in the tree"), "{}", msg),
+                other => panic!("expected MetadataError, got {:?}", other),
+            }
+        }
+    }
+
+    fn mainnet_builder() -> VaultBuilder {
+        VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_XPUB)
+            .recovery_xpub(RECOVERY_XPUB)
+            .network(Network::Mainnet)
+    }
+
+    fn heirs_template(heirs: &[&str]) -> VaultTemplate {
+        VaultTemplate::Inheritance {
+            heir_threshold: 1,
+            heir_count: heirs.len() as u8,
+            inactivity_blocks: 26_000,
+            heirs: heirs.iter().map(|heir| heir.to_string()).collect(),
+        }
+    }
+
+    /// The corporate example: 3-of-3 at once, 2-of-3 after four weeks,
+    /// 1-of-3 after six months
+    fn degrading_template() -> VaultTemplate {
+        VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+            cosigners: vec![THIRD_XPUB.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_degrading_vault_tree() {
+        for index in 0..8 {
+            let vault = mainnet_builder().template(degrading_template()).index(index).build().unwrap();
+            let leaves = vault.tree().leaves();
+            assert_eq!(leaves.len(), 3);
+
+            // The earliest stage alone at depth 1, the later two under it
+            let control_block_lens: Vec<usize> = (0..3)
+                .map(|stage| taproot::control_block(vault.tree(), taproot::LeafPurpose::DegradingStage(stage)).unwrap().size())
+                .collect();
+            assert_eq!(control_block_lens, vec![33 + 32, 33 + 64, 33 + 64], "index {}", index);
+        }
+
+        let vault = mainnet_builder().template(degrading_template()).build().unwrap();
+        let metadata = vault.metadata();
+        assert_eq!(metadata.delay_blocks, 4032);
+        assert_eq!(metadata.degrading_stages(), vec![(0, 3), (4032, 2), (26_208, 1)]);
+
+        // Owner, recovery and cosigner keys must all differ
+        let repeated = VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2)],
+            cosigners: vec![OWNER_XPUB.to_string()],
+        };
+        assert!(matches!(mainnet_builder().template(repeated).build(), Err(CoreError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_vault_builder_builds_at_index() {
+        let vault = mainnet_builder().index(3).build().unwrap();
+        let owner = keys::parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+        let recovery = keys::parse_xpub(RECOVERY_XPUB, Network::Mainnet).unwrap();
+        let expected = taproot::vault_tree(&VaultTemplate::savings(), &owner, &recovery, 3, Network::Mainnet).unwrap();
+
+        assert_eq!(vault.index(), 3);
+        assert_eq!(vault.address(), expected.address(Network::Mainnet));
+        assert_eq!(vault.script_pubkey(), expected.script_pubkey());
+        assert_eq!(vault.tree_at(3).unwrap().script_pubkey(), vault.script_pubkey());
+        assert_ne!(vault.tree_at(4).unwrap().script_pubkey(), vault.script_pubkey());
+        assert_eq!(vault.metadata().vault_index, 3);
+        assert_eq!(vault.metadata().template_id, "savings_v1");
+        assert_eq!(vault.tree_version(), TreeVersion::LATEST);
+        assert!(vault.descriptor().unwrap().starts_with("tr("));
+        let v1 = mainnet_builder().tree_version(TreeVersion::V1).build().unwrap();
+        assert!(matches!(v1.descriptor(), Err(CoreError::InvalidInput(_))));
+        assert_ne!(v1.address(), mainnet_builder().build().unwrap().address());
+        assert!(vault.destinations().is_none());
+
+        let heirs = mainnet_builder().template(heirs_template(&[THIRD_XPUB])).build().unwrap();
+        assert_eq!(heirs.metadata().heirs, Some(HeirSet { threshold: 1, count: 1 }));
+    }
+
+    #[test]
+    fn test_default_vaults_take_per_index_nums_key() {
+        for index in [0, 1, 9] {
+            let vault = mainnet_builder().index(index).build().unwrap();
+            assert_eq!(vault.tree().internal_key(), taproot::nums_internal_key(index).unwrap());
+        }
+        let vault = mainnet_builder().build().unwrap();
+        assert_ne!(vault.tree_at(1).unwrap().internal_key(), vault.tree().internal_key());
+
+        // Only vaults asking for an earlier version share the point H
+        let v2 = mainnet_builder().tree_version(TreeVersion::V2).index(1).build().unwrap();
+        assert_eq!(v2.tree().internal_key(), keys::unspendable_internal_key());
+    }
+
+    #[test]
+    fn test_default_vaults_weight_the_timelock_leaf() {
+        let template = custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::EmergencyKey,
+            hashlock: Some(HashlockRecovery {
+                hash: bitcoin::hashes::sha256::Hash::hash(b"recovery secret"),
+                service_xpub: THIRD_XPUB.to_string(),
+            }),
+            ..Default::default()
+        });
+        let timelock_depth = |vault: &Vault| {
+            let control_block = taproot::control_block(vault.tree(), taproot::LeafPurpose::Timelock).unwrap();
+            (control_block.size() - 33) / 32
+        };
+
+        // Three leaves, the timelock alone at depth 1 at every index
+        for index in 0..8 {
+            let vault = mainnet_builder().template(template.clone()).index(index).build().unwrap();
+            assert_eq!(vault.tree().leaves().len(), 3);
+            assert_eq!(timelock_depth(&vault), 1, "index {}", index);
+        }
+
+        // Equal weights leave the pairing to the leaf hashes
+        let v3_depths: Vec<usize> = (0..8)
+            .map(|index| {
+                let vault = mainnet_builder().template(template.clone()).tree_version(TreeVersion::V3).index(index);
+                timelock_depth(&vault.build().unwrap())
+            })
+            .collect();
+        assert!(v3_depths.contains(&2), "{:?}", v3_depths);
+    }
+
+    #[test]
+    fn test_vault_builder_ledger() {
+        let ledger = registry::IndexLedger::from_iter([0, 1, 3]);
+
+        // No index given: the lowest free one is taken and recorded
+        let vault = mainnet_builder().ledger(&ledger).build().unwrap();
+        assert_eq!(vault.index(), 2);
+        assert_eq!(vault.metadata().vault_index, 2);
+        assert_eq!(vault.ledger().unwrap().iter().collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert_eq!(ledger.len(), 3, "the caller's ledger is left untouched");
+
+        let vault = mainnet_builder().ledger(&ledger).index(9).build().unwrap();
+        assert_eq!(vault.index(), 9);
+        assert!(vault.ledger().unwrap().contains(9));
+        assert!(mainnet_builder().index(1).build().unwrap().ledger().is_none());
+
+        match mainnet_builder().ledger(&ledger).index(3).build() {
+            Err(CoreError::PolicyViolation(msg)) => assert_eq!(msg, "Vault index 3 already used"),
+            other => panic!("expected PolicyViolation, got {:?}", other.map(|vault| vault.index())),
+        }
+    }
+
+    #[test]
+    fn test_vault_leaf_listing() {
+        let template = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        let vault = mainnet_builder().template(template.clone()).build().unwrap();
+        let listing = vault.leaf_listing().unwrap();
+        assert_eq!(listing.len(), vault.tree().leaves().len());
+
+        for (entry, leaf) in listing.iter().zip(vault.tree().leaves()) {
+            assert_eq!(entry.purpose, leaf.purpose);
+            assert_eq!(Some(entry.leaf_hash), vault.tree().leaf_hash(leaf.purpose));
+            assert_eq!(entry.script, leaf.script);
+            assert_eq!(entry.asm, taproot::disassemble_to_string(&leaf.script));
+            assert_eq!(entry.control_block_size, 33 + 32 * entry.depth);
+        }
+        // Both timelock leaves outweigh the recovery leaf, which sits at
+        // depth 2
+        let emergency = listing.iter().find(|entry| entry.purpose == taproot::LeafPurpose::Emergency).unwrap();
+        assert_eq!(emergency.depth, 2);
+        let owner_key = taproot::leaf_signers(&listing[0].script).unwrap().keys[0];
+        assert_eq!(listing[0].asm, format!("f003[2] OP_CSV OP_VERIFY {}[32] OP_CHECKSIG", owner_key));
+
+        // Tree version 1 weighs the three leaves equally: one at depth 1,
+        // two at depth 2
+        let v1 = mainnet_builder().template(template).tree_version(TreeVersion::V1).build().unwrap();
+        let v1_listing = v1.leaf_listing().unwrap();
+        let mut depths: Vec<_> = v1_listing.iter().map(|entry| entry.depth).collect();
+        depths.sort();
+        assert_eq!(depths, [1, 2, 2]);
+        assert_eq!(v1_listing[0].asm, format!("f003[2] OP_CSV OP_DROP {}[32] OP_CHECKSIG", owner_key));
+    }
+
+    #[test]
+    fn test_vault_builder_musig_internal_key() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let cosigners: Vec<_> = [[0x11; 32], [0x22; 32]]
+            .iter()
+            .map(|bytes| bitcoin::secp256k1::SecretKey::from_slice(bytes).unwrap().public_key(&secp))
+            .collect();
+        let agg_key = keys::musig::aggregate_keys(&cosigners).unwrap();
+
+        let vault = mainnet_builder().index(2).internal_key(agg_key.clone()).build().unwrap();
+        let plain = mainnet_builder().index(2).build().unwrap();
+        assert_eq!(vault.tree().internal_key(), agg_key.x_only_public_key());
+        assert_eq!(vault.musig_key(), Some(agg_key.x_only_public_key()));
+        // Same leaves, different internal key
+        assert_eq!(vault.tree().merkle_root(), plain.tree().merkle_root());
+        assert_ne!(vault.script_pubkey(), plain.script_pubkey());
+        assert!(vault.metadata().key_path_enabled);
+
+        assert_eq!(vault.tree_at(2).unwrap().script_pubkey(), vault.script_pubkey());
+        assert!(matches!(vault.tree_at(3), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(vault.descriptor(), Err(CoreError::InvalidInput(_))));
+
+        let key_path = custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            key_path_enabled: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            mainnet_builder().template(key_path).internal_key(agg_key).build(),
+            Err(CoreError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_vault_builder_accepts_key_expressions() {
+        // BIP86 test vector: account 0 of "abandon ... about"
+        let account = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+        let expression: keys::KeyExpression = format!("[73c5da0a/86'/0'/0']{}/<0;1>/*", account).parse().unwrap();
+
+        let vault = mainnet_builder().owner_xpub(expression.clone()).index(4).build().unwrap();
+        let bare = mainnet_builder().owner_xpub(account).index(4).build().unwrap();
+        assert_eq!(vault.address(), bare.address());
+        assert_eq!(vault.owner_origin(), &expression.key_source());
+        assert_eq!(vault.origin_of(vault.owner_xpub()).1.to_string(), "m/86'/0'/0'");
+        assert_eq!(bare.owner_origin(), &(bare.owner_xpub().fingerprint(), DerivationPath::master()));
+
+        // Cosigner keys keep their origins too
+        let heir = format!("[73c5da0a/86'/0'/0']{}/0/*", account);
+        let heirs = mainnet_builder().template(heirs_template(&[&heir])).build().unwrap();
+        let heir_xpub = keys::parse_xpub(account, Network::Mainnet).unwrap();
+        assert_eq!(heirs.origin_of(&heir_xpub), expression.key_source());
+
+        // Only expressions for the vault's `0/index` keys describe an account
+        for bad in [format!("{}/1/*", account), format!("{}/0h/*", account), format!("{}/0/5", account)] {
+            assert!(
+                matches!(mainnet_builder().owner_xpub(bad.clone()).build(), Err(CoreError::InvalidXpub(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_vault_builder_rejects_duplicate_keys() {
+        fn assert_duplicate(builder: VaultBuilder, expected: &str) {
+            match builder.build() {
+                Err(CoreError::PolicyViolation(msg)) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("expected PolicyViolation({}), got {:?}", expected, other.map(|vault| vault.index())),
+            }
+        }
+
+        // The same key pasted twice, once with its origin
+        let account = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+        assert_duplicate(
+            mainnet_builder().owner_xpub(account).recovery_xpub(format!("[73c5da0a/86'/0'/0']{}", account)),
+            "recovery xpub is the same key as the owner xpub",
+        );
+
+        let multisig = |cosigners: &[&str]| custom_template(CustomFields {
+            delay_blocks: 1008,
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery {
+                threshold: 1,
+                cosigners: cosigners.iter().map(|xpub| xpub.to_string()).collect(),
+            }),
+            ..Default::default()
+        });
+        assert!(mainnet_builder().template(multisig(&[THIRD_XPUB])).build().is_ok());
+        assert_duplicate(
+            mainnet_builder().template(multisig(&[THIRD_XPUB, THIRD_XPUB])),
+            "cosigner 2 xpub is the same key as the cosigner 1 xpub",
+        );
+        assert_duplicate(
+            mainnet_builder().template(multisig(&[THIRD_XPUB, OWNER_XPUB])),
+            "cosigner 2 xpub is the same key as the owner xpub",
+        );
+    }
+
+    #[test]
+    fn test_check_distinct_keys_rejects_reserialized_xpub() {
+        // The owner key exported again as if from another depth and
+        // parent: a different xpub string, the same key at index 0
+        let owner = keys::parse_xpub(OWNER_XPUB, Network::Mainnet).unwrap();
+        let mut reserialized = owner;
+        reserialized.depth = 3;
+        reserialized.parent_fingerprint = Fingerprint::from([0x73, 0xc5, 0xda, 0x0a]);
+        reserialized.child_number = bitcoin::bip32::ChildNumber::from_hardened_idx(0).unwrap();
+        assert_ne!(reserialized.to_string(), OWNER_XPUB);
+        assert_eq!(
+            keys::derive_vault_key(&reserialized, 0, Network::Mainnet).unwrap().public_key,
+            keys::derive_vault_key(&owner, 0, Network::Mainnet).unwrap().public_key
+        );
+
+        let roles = [("owner xpub".to_string(), owner), ("recovery xpub".to_string(), reserialized)];
+        match check_distinct_keys(&roles, Network::Mainnet) {
+            Err(CoreError::PolicyViolation(msg)) => {
+                assert_eq!(msg, "Duplicate key: recovery xpub is the same key as the owner xpub")
+            }
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+        assert!(matches!(
+            mainnet_builder().recovery_xpub(reserialized.to_string()).build(),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        assert!(check_distinct_keys(&roles[..1], Network::Mainnet).is_ok());
+    }
+
+    #[test]
+    fn test_vault_builder_allow_duplicate_keys_on_regtest_only() {
+        let regtest = || {
+            VaultBuilder::new()
+                .template(VaultTemplate::savings())
+                .owner_xpub(OWNER_TPUB)
+                .recovery_xpub(OWNER_TPUB)
+                .network(Network::Regtest)
+        };
+        assert!(matches!(regtest().build(), Err(CoreError::PolicyViolation(_))));
+
+        let vault = regtest().allow_duplicate_keys(true).build().unwrap();
+        assert_eq!(vault.owner_xpub(), vault.recovery_xpub());
+        assert_eq!(vault.tree().leaves().len(), 2);
+
+        for network in [Network::Mainnet, Network::Testnet, Network::Signet] {
+            match regtest().network(network).allow_duplicate_keys(true).build() {
+                Err(CoreError::PolicyViolation(msg)) => {
+                    assert_eq!(msg, format!("Duplicate keys may only be allowed on regtest, not {}", network))
+                }
+                other => panic!("expected PolicyViolation, got {:?}", other.map(|vault| vault.index())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_vault_builder_errors() {
+        fn build_err(builder: VaultBuilder) -> CoreError {
+            builder.build().expect_err("builder accepted an invalid vault")
+        }
+        fn assert_input(error: CoreError, expected: &str) {
+            match error {
+                CoreError::InvalidInput(msg) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("expected InvalidInput({}), got {:?}", expected, other),
+            }
+        }
+        fn assert_policy(error: CoreError, expected: &str) {
+            match error {
+                CoreError::PolicyViolation(msg) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("expected PolicyViolation({}), got {:?}", expected, other),
+            }
+        }
+        fn assert_mismatch(error: CoreError, expected_network: &str, actual_network: &str) {
+            match error {
+                CoreError::NetworkMismatch { expected, actual } => {
+                    assert!(expected.contains(expected_network), "{}", expected);
+                    assert!(actual.contains(actual_network), "{}", actual);
+                }
+                other => panic!("expected NetworkMismatch, got {:?}", other),
+            }
+        }
+
+        let without = |field: &str| {
+            let mut builder = mainnet_builder();
+            match field {
+                "network" => builder.network = None,
+                "template" => builder.template = None,
+                "owner_xpub" => builder.owner_xpub = None,
+                _ => builder.recovery_xpub = None,
+            }
+            builder
+        };
+        assert_input(build_err(without("network")), "Vault network is required");
+        assert_input(build_err(without("template")), "Vault template is required");
+        assert_input(build_err(without("owner_xpub")), "Vault owner_xpub is required");
+        assert_input(build_err(without("recovery_xpub")), "Vault recovery_xpub is required");
+        assert_input(build_err(mainnet_builder().index(0x8000_0000)), "is hardened");
+
+        let inverted = VaultTemplate::DualDelay { whitelist_delay: 1008, open_delay: 144 };
+        assert_policy(build_err(mainnet_builder().template(inverted)), "must be shorter than the open delay");
+
+        assert!(matches!(
+            build_err(mainnet_builder().owner_xpub("xpubnotakey")),
+            CoreError::InvalidXpub(_)
+        ));
+        assert_mismatch(build_err(mainnet_builder().owner_xpub(OWNER_TPUB)), "mainnet", "testnet");
+        assert_mismatch(
+            build_err(mainnet_builder().template(heirs_template(&[THIRD_XPUB, OWNER_TPUB]))),
+            "mainnet",
+            "testnet",
+        );
+
+        assert_policy(
+            build_err(mainnet_builder().recovery_xpub(OWNER_XPUB)),
+            "recovery xpub is the same key as the owner xpub",
+        );
+        assert_policy(
+            build_err(mainnet_builder().template(heirs_template(&[THIRD_XPUB, RECOVERY_XPUB]))),
+            "heir 2 xpub is the same key as the recovery xpub",
+        );
+
+        let regtest_list = policy::ApprovedDestinations::new(Network::Regtest);
+        assert_mismatch(build_err(mainnet_builder().destinations(regtest_list)), "mainnet", "regtest");
+    }
+
+    #[test]
+    fn test_verify_address_scans_ten_thousand_indices() {
+        let vault = mainnet_builder().build().unwrap();
+        let last = mainnet_builder().index(9_999).build().unwrap().address();
+
+        let started = std::time::Instant::now();
+        assert_eq!(verify_address(&vault, &last, 9_999).unwrap(), Some(9_999));
+        assert!(started.elapsed() < std::time::Duration::from_secs(10), "{:?}", started.elapsed());
+
+        assert_eq!(verify_address(&vault, &vault.address(), 9_999).unwrap(), Some(0));
+        assert_eq!(verify_address(&vault, &last, 9_998).unwrap(), None);
+        assert!(matches!(verify_address(&vault, &last, 10_000), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_verify_address_rejects_other_network() {
+        let vault = mainnet_builder().build().unwrap();
+        let testnet: Address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+            .parse::<Address<NetworkUnchecked>>()
+            .unwrap()
+            .assume_checked();
+        match verify_address(&vault, &testnet, 10) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "mainnet");
+                assert_eq!(actual, "testnet");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_names_agree_with_serde() {
+        for network in Network::ALL {
+            let serde_name = serde_json::to_value(network).unwrap();
+            assert_eq!(serde_name, network.to_string());
+            assert_eq!(network.to_string().parse::<Network>().unwrap(), network);
+            assert_eq!(network.to_string().to_uppercase().parse::<Network>().unwrap(), network);
+        }
+        for recovery_type in RecoveryType::ALL {
+            let serde_name = serde_json::to_value(recovery_type).unwrap();
+            assert_eq!(serde_name, recovery_type.to_string());
+            assert_eq!(recovery_type.to_string().parse::<RecoveryType>().unwrap(), recovery_type);
+            assert_eq!("Emergency_Key".parse::<RecoveryType>().unwrap(), RecoveryType::EmergencyKey);
+        }
+
+        match "testnet3".parse::<Network>() {
+            Err(CoreError::InvalidInput(message)) => {
+                assert!(message.contains("mainnet, testnet, signet, regtest, testnet4"), "{}", message)
+            }
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+        match "multisig".parse::<RecoveryType>() {
+            Err(CoreError::InvalidInput(message)) => {
+                assert!(message.contains("emergency_key, timelock_only, multi_sig"), "{}", message)
+            }
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_template_id_inverts_template_id() {
+        let templates = [
+            VaultTemplate::savings(),
+            VaultTemplate::Spending { delay_blocks: 10 },
+            VaultTemplate::custom(4320, RecoveryType::TimelockOnly).unwrap(),
+            VaultTemplate::Inheritance {
+                heir_threshold: 1,
+                heir_count: 1,
+                inactivity_blocks: 52_560,
+                heirs: vec![RECOVERY_TPUB.to_string()],
+            },
+            VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 },
+        ];
+        for template in templates {
+            let mut params = serde_json::to_value(&template).unwrap();
+            params.as_object_mut().unwrap().remove("type");
+            let parsed = VaultTemplate::from_template_id(template.template_id(), &params).unwrap();
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(&template).unwrap());
+        }
+
+        // Defaults apply, ids ignore case
+        let savings = VaultTemplate::from_template_id("SAVINGS_V1", &serde_json::Value::Null).unwrap();
+        assert_eq!(savings.delay_blocks(), VaultTemplate::savings().delay_blocks());
+
+        match VaultTemplate::from_template_id("savings", &serde_json::Value::Null) {
+            Err(CoreError::InvalidInput(message)) => assert!(message.contains("savings_v1, spending_v1"), "{}", message),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+        // Parameters are validated as in JSON configs
+        assert!(VaultTemplate::from_template_id("custom_v1", &serde_json::json!({"delay_blocks": 144})).is_err());
+        assert!(VaultTemplate::from_template_id("savings_v1", &serde_json::json!({"delay_blocks": 0})).is_err());
+        assert!(VaultTemplate::from_template_id("savings_v1", &serde_json::json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_network_conversion() {
+        assert_eq!(bitcoin::Network::Bitcoin, Network::Mainnet.into());
+        assert_eq!(bitcoin::Network::Testnet, Network::Testnet.into());
+        assert_eq!(bitcoin::Network::Signet, Network::Signet.into());
+        assert_eq!(bitcoin::Network::Regtest, Network::Regtest.into());
+        assert_eq!(bitcoin::Network::Testnet, Network::Testnet4.into());
+
+        assert_eq!(Network::try_from(4).unwrap(), Network::Testnet4);
+        assert!(Network::try_from(5).is_err());
+        assert_eq!(serde_json::to_string(&Network::Testnet4).unwrap(), "\"testnet4\"");
+        assert_eq!(serde_json::from_str::<Network>("\"testnet4\"").unwrap(), Network::Testnet4);
+    }
+
+    #[test]
+    fn test_testnet4_vault_uses_testnet_prefixes() {
+        let build = |network| {
+            VaultBuilder::new()
+                .template(VaultTemplate::savings())
+                .owner_xpub(OWNER_TPUB)
+                .recovery_xpub(RECOVERY_TPUB)
+                .network(network)
+                .tree_version(TreeVersion::V2)
+                .build()
+        };
+        let vault = build(Network::Testnet4).unwrap();
+        let address = vault.address().to_string();
+        assert!(address.starts_with("tb1p"), "{}", address);
+        assert_eq!(address, build(Network::Testnet).unwrap().address().to_string());
+        assert!(vault.descriptor().unwrap().contains(&format!("{}/0/*", OWNER_TPUB)));
+
+        assert!(policy::validate_address(&address, Network::Testnet4).is_ok());
+        let regtest_address = build(Network::Regtest).unwrap().address().to_string();
+        match policy::validate_address(&regtest_address, Network::Testnet4) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "testnet4");
+                assert_eq!(actual, "regtest");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+
+        // Mainnet keys are refused like on testnet
+        let mainnet_keys = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_XPUB)
+            .recovery_xpub(RECOVERY_XPUB)
+            .network(Network::Testnet4)
+            .build();
+        assert!(matches!(mainnet_keys, Err(CoreError::NetworkMismatch { .. })));
     }
 }
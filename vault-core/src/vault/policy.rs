@@ -0,0 +1,1356 @@
+//! Spending policy: approved destinations for unvaults, and checks of
+//! PSBTs against the vault's rules before signing
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{ChildNumber, KeySource};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::psbt::{Input as PsbtInput, Psbt};
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{Address, Script, ScriptBuf, Sequence, Txid};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CoreError;
+use crate::taproot::{self, LeafPurpose, VaultKeys, VaultLeaf, VaultTree};
+use crate::vault::psbt::describe_lock;
+use crate::vault::{Network, Vault, VaultConfig, VaultMetadata};
+
+/// Version byte leading the commitment serialization
+const COMMITMENT_VERSION: u8 = 1;
+
+/// Most destinations a list can hold, as metadata refers to them by `u8` index
+pub const MAX_APPROVED_DESTINATIONS: usize = u8::MAX as usize + 1;
+
+/// Ordered whitelist of labelled destination addresses
+///
+/// `VaultMetadata::destination_indices` refers to entries by position,
+/// so entries are only ever appended. Every address must belong to the
+/// list's network; this is enforced on construction and deserialization.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "DestinationsRepr", into = "DestinationsRepr")]
+pub struct ApprovedDestinations {
+    network: Network,
+    destinations: Vec<(String, Address)>,
+}
+
+impl ApprovedDestinations {
+    pub fn new(network: Network) -> Self {
+        ApprovedDestinations {
+            network,
+            destinations: Vec::new(),
+        }
+    }
+
+    /// Append `address` under `label`, returning its index
+    pub fn push(&mut self, label: impl Into<String>, address: Address) -> Result<u8, CoreError> {
+        if self.destinations.len() == MAX_APPROVED_DESTINATIONS {
+            return Err(CoreError::InvalidInput(format!(
+                "Approved destinations list is full ({} entries)",
+                MAX_APPROVED_DESTINATIONS
+            )));
+        }
+        let unchecked = Address::<NetworkUnchecked>::new(address.network, address.payload.clone());
+        if !unchecked.is_valid_for_network(self.network.into()) {
+            return Err(CoreError::InvalidInput(format!(
+                "Address {} is not valid for {:?}",
+                address, self.network
+            )));
+        }
+        let label = label.into();
+        if label.len() > u16::MAX as usize {
+            return Err(CoreError::InvalidInput("Destination label too long".to_string()));
+        }
+
+        self.destinations.push((label, address));
+        Ok((self.destinations.len() - 1) as u8)
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn len(&self) -> usize {
+        self.destinations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.destinations.is_empty()
+    }
+
+    /// `(label, address)` pairs in index order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Address)> {
+        self.destinations
+            .iter()
+            .map(|(label, address)| (label.as_str(), address))
+    }
+
+    /// Index of the first entry paying to `address`'s scriptPubKey
+    pub fn index_of(&self, address: &Address) -> Option<u8> {
+        let script_pubkey = address.script_pubkey();
+        self.destinations
+            .iter()
+            .position(|(_, approved)| approved.script_pubkey() == script_pubkey)
+            .map(|index| index as u8)
+    }
+
+    /// Address at `index`
+    pub fn resolve(&self, index: u8) -> Result<&Address, CoreError> {
+        self.destinations
+            .get(index as usize)
+            .map(|(_, address)| address)
+            .ok_or_else(|| {
+                CoreError::InvalidInput(format!(
+                    "No approved destination at index {} ({} entries)",
+                    index,
+                    self.destinations.len()
+                ))
+            })
+    }
+
+    /// SHA256 of the canonical serialization, for committing the list into metadata
+    ///
+    /// Serialization: version (1), network (1), entry count (u16 LE), then
+    /// per entry the label and the scriptPubKey, each prefixed with a
+    /// u16 LE length.
+    pub fn commitment(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&[COMMITMENT_VERSION, self.network as u8]);
+        engine.input(&(self.destinations.len() as u16).to_le_bytes());
+        for (label, address) in &self.destinations {
+            let script_pubkey = address.script_pubkey();
+            engine.input(&(label.len() as u16).to_le_bytes());
+            engine.input(label.as_bytes());
+            engine.input(&(script_pubkey.len() as u16).to_le_bytes());
+            engine.input(script_pubkey.as_bytes());
+        }
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+/// Parse `addr` and require it to belong to `network`
+///
+/// Unparseable strings fail with `InvalidAddress`; addresses for another
+/// network fail with `NetworkMismatch` naming both networks. Testnet
+/// and signet share address prefixes, so either accepts the other's
+/// addresses, and legacy testnet addresses are also valid on regtest.
+pub fn validate_address(addr: &str, network: Network) -> Result<Address, CoreError> {
+    let address = Address::<NetworkUnchecked>::from_str(addr)
+        .map_err(|e| CoreError::InvalidAddress(format!("Failed to parse {}: {}", addr, e)))?;
+    let actual = address.network;
+
+    address
+        .require_network(network.into())
+        .map_err(|_| CoreError::NetworkMismatch {
+            expected: network.name().to_string(),
+            actual: network_name(actual).to_string(),
+        })
+}
+
+/// Name of an address's network as used in vault configs; testnet4
+/// addresses are indistinguishable from testnet ones
+pub(crate) fn network_name(network: bitcoin::Network) -> &'static str {
+    match network {
+        bitcoin::Network::Bitcoin => "mainnet",
+        bitcoin::Network::Testnet => "testnet",
+        bitcoin::Network::Signet => "signet",
+        bitcoin::Network::Regtest => "regtest",
+        _ => "unknown",
+    }
+}
+
+/// Serde form of `ApprovedDestinations`, with addresses as strings
+#[derive(Serialize, Deserialize)]
+struct DestinationsRepr {
+    network: Network,
+    destinations: Vec<DestinationRepr>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DestinationRepr {
+    label: String,
+    address: String,
+}
+
+impl TryFrom<DestinationsRepr> for ApprovedDestinations {
+    type Error = CoreError;
+
+    fn try_from(repr: DestinationsRepr) -> Result<Self, CoreError> {
+        let mut approved = ApprovedDestinations::new(repr.network);
+        for entry in repr.destinations {
+            let address = validate_address(&entry.address, repr.network)?;
+            approved.push(entry.label, address)?;
+        }
+        Ok(approved)
+    }
+}
+
+impl From<ApprovedDestinations> for DestinationsRepr {
+    fn from(approved: ApprovedDestinations) -> Self {
+        DestinationsRepr {
+            network: approved.network,
+            destinations: approved
+                .destinations
+                .into_iter()
+                .map(|(label, address)| DestinationRepr {
+                    label,
+                    address: address.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Check that `destination` is allowed by `metadata`
+///
+/// Metadata without `destination_indices` allows any destination.
+/// Otherwise `destination` must appear in `approved` at one of those
+/// indices, or the spend is rejected with `PolicyViolation`.
+pub fn check_destination(
+    metadata: &VaultMetadata,
+    approved: Option<&ApprovedDestinations>,
+    destination: &Address,
+) -> Result<(), CoreError> {
+    if metadata.destination_indices.is_empty() {
+        return Ok(());
+    }
+    let approved = approved.ok_or_else(|| {
+        CoreError::PolicyViolation(
+            "Vault restricts destinations but no approved destinations were given".to_string(),
+        )
+    })?;
+
+    let script_pubkey = destination.script_pubkey();
+    let allowed = metadata.destination_indices.iter().any(|index| {
+        approved
+            .resolve(*index)
+            .is_ok_and(|address| address.script_pubkey() == script_pubkey)
+    });
+    if !allowed {
+        return Err(CoreError::PolicyViolation(format!(
+            "Destination {} is not an approved destination",
+            destination
+        )));
+    }
+
+    Ok(())
+}
+
+/// Fee ceiling `check_psbt()` applies when the config sets none
+pub const DEFAULT_MAX_FEE_SATS: u64 = 1_000_000;
+
+/// A rule `check_psbt()` applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    /// The input spends a script derived from the vault's keys
+    VaultInput,
+    /// A delayed-leaf input's nSequence covers that leaf's delay
+    UnvaultDelay,
+    /// The output pays an approved destination or back to the vault, and
+    /// only those if any input takes the whitelist delay
+    Destination,
+    /// The fee implied by inputs and outputs is within the ceiling
+    FeeCeiling,
+    /// The value leaving the vault keeps the window within the velocity limit
+    Velocity,
+}
+
+/// Outcome of one rule for one input or output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleOutcome {
+    pub rule: PolicyRule,
+    /// Input or output index; `None` for rules on the whole transaction
+    pub index: Option<usize>,
+    pub passed: bool,
+    /// What was found, in a form fit to show the user
+    pub detail: String,
+}
+
+/// Every check `check_psbt()` ran, with the overall verdict
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyReport {
+    pub checks: Vec<RuleOutcome>,
+    /// Whether every check passed
+    pub passed: bool,
+}
+
+impl PolicyReport {
+    fn new(checks: Vec<RuleOutcome>) -> Self {
+        let passed = checks.iter().all(|check| check.passed);
+        PolicyReport { checks, passed }
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &RuleOutcome> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+
+    /// The report if every check passed, else `PolicyViolation` listing the failures
+    pub fn into_result(self) -> Result<Self, CoreError> {
+        if self.passed {
+            return Ok(self);
+        }
+        let failures: Vec<&str> = self.failures().map(|check| check.detail.as_str()).collect();
+        Err(CoreError::PolicyViolation(failures.join("; ")))
+    }
+}
+
+/// Most value allowed to leave the vault in any window of blocks
+///
+/// Enforced by the signer, not by the vault's scripts: a PSBT that would
+/// exceed the limit is still spendable, `check_psbt()` only refuses to
+/// pass it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VelocityLimit {
+    pub max_sats: u64,
+    pub window_blocks: u32,
+}
+
+/// An earlier spend out of the vault, for `check_velocity()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendRecord {
+    pub txid: Txid,
+    /// Height the spend confirmed at
+    pub height: u32,
+    /// Value that left the vault, fee included
+    pub amount_sats: u64,
+}
+
+/// Check that spending `candidate` keeps the value leaving `vault`
+/// within `limit`, returning the candidate's outflow
+///
+/// The window is the `window_blocks` blocks ending at `current_height`;
+/// a record at `current_height - window_blocks` or before has aged out.
+/// The candidate's outflow is its input total less the outputs returning
+/// to the vault: those paying a script one of its inputs spends, and
+/// change to a fresh index named by the output's `tap_key_origins`
+/// (re-derived, as in `check_psbt()`).
+///
+/// Errors with `PolicyViolation` naming the amount over the limit, and
+/// with `PsbtError` for an input without a `witness_utxo`.
+pub fn check_velocity(
+    limit: &VelocityLimit,
+    history: &[SpendRecord],
+    candidate: &Psbt,
+    vault: &Vault,
+    current_height: u32,
+) -> Result<u64, CoreError> {
+    let prevouts = candidate
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input
+                .witness_utxo
+                .as_ref()
+                .ok_or_else(|| CoreError::PsbtError(format!("Input {} is missing witness_utxo", i)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let input_total = prevouts.iter().fold(0u64, |total, prevout| total.saturating_add(prevout.value));
+    let returned = candidate
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(candidate.outputs.iter())
+        .filter(|(output, psbt_output)| {
+            prevouts.iter().any(|prevout| prevout.script_pubkey == output.script_pubkey)
+                || origin_tree(vault, &psbt_output.tap_key_origins, &output.script_pubkey).is_some()
+        })
+        .fold(0u64, |total, (output, _)| total.saturating_add(output.value));
+
+    let outflow = input_total.saturating_sub(returned);
+    let total = window_total(limit, history, outflow, current_height);
+    if total > limit.max_sats {
+        return Err(CoreError::PolicyViolation(over_limit(limit, outflow, total)));
+    }
+    Ok(outflow)
+}
+
+/// Value leaving the vault in the window ending at `current_height` once
+/// `outflow_sats` is spent
+fn window_total(limit: &VelocityLimit, history: &[SpendRecord], outflow_sats: u64, current_height: u32) -> u64 {
+    history
+        .iter()
+        .filter(|record| record.height.saturating_add(limit.window_blocks) > current_height)
+        .fold(outflow_sats, |total, record| total.saturating_add(record.amount_sats))
+}
+
+fn over_limit(limit: &VelocityLimit, outflow_sats: u64, total: u64) -> String {
+    format!(
+        "Spending {} sats would bring the last {} blocks to {} sats, {} over the limit of {} sats",
+        outflow_sats,
+        limit.window_blocks,
+        total,
+        total - limit.max_sats,
+        limit.max_sats
+    )
+}
+
+/// Check a PSBT from an untrusted coordinator against `vault`'s rules
+///
+/// * Every input's `witness_utxo` must pay a vault script. The vault
+///   index is read from the input's `tap_key_origins` and the script
+///   re-derived, so a forged origin only produces a mismatch.
+/// * Inputs listing a delayed leaf (timelock, whitelist timelock or
+///   inheritance) in `tap_scripts` must have an nSequence covering that
+///   leaf's CSV delay. Inputs with no leaf script fail, as their spend
+///   path can't be established.
+/// * Outputs must pay back to the vault, either to a spent vault script
+///   or to an index named by the output's `tap_key_origins` (change to a
+///   fresh address, re-derived like inputs), or, when
+///   `approved_destinations` is configured, to one of its entries.
+///   Without a list any destination passes, unless an input spends the
+///   whitelist timelock leaf: the short delay is only for approved
+///   destinations, so every other output fails. Zero-value OP_RETURN
+///   memo outputs always pass.
+/// * The fee must not exceed `max_fee_sats`, by default
+///   `DEFAULT_MAX_FEE_SATS`.
+/// * With a `velocity_limit`, `check_velocity()` must pass for the
+///   `spend_history`: the value leaving the vault (inputs less the
+///   outputs returning to it) plus the history in the window must be
+///   within the limit.
+///
+/// Rule failures are reported, not returned as errors; call
+/// `PolicyReport::into_result()` for a hard `PolicyViolation`. Errors
+/// are returned for an invalid `vault` config, as `InvalidInput` for a
+/// `velocity_limit` without `current_block_height`, and as `PsbtError` for
+/// an input whose `non_witness_utxo` contradicts it or is missing while
+/// `require_non_witness_utxo` is set (see `verify_prevouts()`).
+pub fn check_psbt(psbt: &Psbt, vault: &VaultConfig) -> Result<PolicyReport, CoreError> {
+    let keys = Vault::from_config(vault)?;
+    check_prevout_data(psbt, vault.require_non_witness_utxo)?;
+    let velocity = match (&vault.velocity_limit, vault.current_block_height) {
+        (Some(limit), Some(height)) => Some((limit, height)),
+        (Some(_), None) => {
+            return Err(CoreError::InvalidInput(
+                "A velocity limit needs the current block height".to_string(),
+            ))
+        }
+        (None, _) => None,
+    };
+    let approved = keys.destinations();
+    let max_fee_sats = vault.max_fee_sats.unwrap_or(DEFAULT_MAX_FEE_SATS);
+
+    let mut checks = Vec::new();
+    let mut vault_scripts = Vec::new();
+    let mut whitelist_path = false;
+    let mut input_total = Some(0u64);
+    for (i, (input, txin)) in psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate() {
+        let prevout = input.witness_utxo.as_ref();
+        input_total = input_total
+            .zip(prevout)
+            .and_then(|(total, prevout)| total.checked_add(prevout.value));
+
+        let tree = prevout.and_then(|prevout| {
+            origin_tree(&keys, &input.tap_key_origins, &prevout.script_pubkey)
+        });
+        let (passed, detail) = match (prevout, &tree) {
+            (None, _) => (false, format!("Input {} is missing witness_utxo", i)),
+            (Some(prevout), None) => (
+                false,
+                format!(
+                    "Input {} spends {}, which is not a vault script",
+                    i,
+                    describe_script(&prevout.script_pubkey, vault.network)
+                ),
+            ),
+            (Some(_), Some((index, _))) => (true, format!("Input {} spends vault index {}", i, index)),
+        };
+        checks.push(outcome(PolicyRule::VaultInput, Some(i), passed, detail));
+
+        let (passed, detail) = match &tree {
+            Some((_, tree)) => {
+                whitelist_path |= spent_leaf(input, tree, LeafPurpose::WhitelistTimelock).is_some();
+                check_sequence(i, input, txin.sequence, tree)
+            }
+            None => (false, format!("Input {} is not a vault input; its delay can't be checked", i)),
+        };
+        checks.push(outcome(PolicyRule::UnvaultDelay, Some(i), passed, detail));
+
+        if let Some((_, tree)) = tree {
+            vault_scripts.push(tree.script_pubkey());
+        }
+    }
+
+    let mut output_total = 0u64;
+    for (i, output) in psbt.unsigned_tx.output.iter().enumerate() {
+        output_total = output_total.saturating_add(output.value);
+        let script_pubkey = &output.script_pubkey;
+        let approved_label = approved.and_then(|approved| {
+            approved
+                .iter()
+                .find(|(_, address)| address.script_pubkey() == *script_pubkey)
+                .map(|(label, _)| label)
+        });
+        let change_index = psbt
+            .outputs
+            .get(i)
+            .and_then(|psbt_output| origin_tree(&keys, &psbt_output.tap_key_origins, script_pubkey))
+            .map(|(index, _)| index);
+        let (passed, detail) = if vault_scripts.contains(script_pubkey) {
+            (true, format!("Output {} returns {} sats to the vault", i, output.value))
+        } else if let Some(index) = change_index {
+            (true, format!("Output {} returns {} sats to vault index {}", i, output.value, index))
+        } else if let Some(label) = approved_label {
+            (true, format!("Output {} pays approved destination \"{}\"", i, label))
+        } else if script_pubkey.is_op_return() && output.value == 0 {
+            (true, format!("Output {} is an OP_RETURN memo carrying no value", i))
+        } else if whitelist_path {
+            (
+                false,
+                format!(
+                    "Output {} pays {}, but the whitelist delay only allows approved destinations",
+                    i,
+                    describe_script(script_pubkey, vault.network)
+                ),
+            )
+        } else if approved.is_none() {
+            (
+                true,
+                format!(
+                    "Output {} pays {}; no approved destinations are configured",
+                    i,
+                    describe_script(script_pubkey, vault.network)
+                ),
+            )
+        } else {
+            (
+                false,
+                format!(
+                    "Output {} pays {}, which is not an approved destination",
+                    i,
+                    describe_script(script_pubkey, vault.network)
+                ),
+            )
+        };
+        checks.push(outcome(PolicyRule::Destination, Some(i), passed, detail));
+    }
+
+    let (passed, detail) = match input_total.map(|total| total.checked_sub(output_total)) {
+        None => (false, "Fee can't be computed without every input's witness_utxo".to_string()),
+        Some(None) => (false, "Outputs exceed inputs".to_string()),
+        Some(Some(fee)) if fee > max_fee_sats => (
+            false,
+            format!("Fee of {} sats exceeds the ceiling of {} sats", fee, max_fee_sats),
+        ),
+        Some(Some(fee)) => (true, format!("Fee of {} sats", fee)),
+    };
+    checks.push(outcome(PolicyRule::FeeCeiling, None, passed, detail));
+
+    if let Some((limit, height)) = velocity {
+        let (passed, detail) = match check_velocity(limit, &vault.spend_history, psbt, &keys, height) {
+            Ok(outflow) => (
+                true,
+                format!(
+                    "{} sats leave the vault, {} of {} sats in the last {} blocks",
+                    outflow,
+                    window_total(limit, &vault.spend_history, outflow, height),
+                    limit.max_sats,
+                    limit.window_blocks
+                ),
+            ),
+            Err(CoreError::PolicyViolation(detail)) => (false, detail),
+            Err(_) => (false, "Outflow can't be computed without every input's witness_utxo".to_string()),
+        };
+        checks.push(outcome(PolicyRule::Velocity, None, passed, detail));
+    }
+
+    Ok(PolicyReport::new(checks))
+}
+
+/// Check that every input spends a vault output, as described, before
+/// signing it
+///
+/// Taproot signatures commit to every input's amount and script, so
+/// signing over forged `witness_utxo` data only yields an invalid
+/// transaction; but the fee and amounts the user approved would be made
+/// up, and the key could be used on scripts that aren't the vault's.
+/// Each input must therefore:
+/// * have a `witness_utxo` paying a vault script, at the index its
+///   `tap_key_origins` name or, failing that, an index up to
+///   `max_index`;
+/// * if it has a `non_witness_utxo`, have that be the transaction the
+///   input spends, with the output at its vout equal to `witness_utxo`.
+///   With `require_non_witness_utxo` set in `vault`, it must have one.
+///
+/// Returns the vault index each input spends. Errors with `PsbtError`
+/// naming the input and the field that disagrees, and with
+/// `InvalidInput` for a `max_index` of `taproot::MAX_ADDRESS_RANGE` or
+/// more.
+pub fn verify_prevouts(psbt: &Psbt, vault: &VaultConfig, max_index: u32) -> Result<Vec<u32>, CoreError> {
+    if max_index >= taproot::MAX_ADDRESS_RANGE {
+        return Err(CoreError::InvalidInput(format!(
+            "Cannot scan to index {} (at most {} indices)",
+            max_index,
+            taproot::MAX_ADDRESS_RANGE
+        )));
+    }
+    let keys = Vault::from_config(vault)?;
+    check_prevout_data(psbt, vault.require_non_witness_utxo)?;
+
+    let mut scanned: Option<HashMap<ScriptBuf, u32>> = None;
+    let mut indices = Vec::with_capacity(psbt.inputs.len());
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let prevout = input
+            .witness_utxo
+            .as_ref()
+            .ok_or_else(|| CoreError::PsbtError(format!("Input {} is missing witness_utxo", i)))?;
+        let index = match origin_tree(&keys, &input.tap_key_origins, &prevout.script_pubkey) {
+            Some((index, _)) => Some(index),
+            None => {
+                let scanned = match &mut scanned {
+                    Some(scanned) => scanned,
+                    None => scanned.insert(vault_scripts(&keys, max_index)?),
+                };
+                scanned.get(&prevout.script_pubkey).copied()
+            }
+        };
+        let index = index.ok_or_else(|| {
+            CoreError::PsbtError(format!(
+                "Input {}: witness_utxo pays {}, which is not a vault script at indices 0 to {}",
+                i,
+                describe_script(&prevout.script_pubkey, vault.network),
+                max_index
+            ))
+        })?;
+        indices.push(index);
+    }
+    Ok(indices)
+}
+
+/// Check each input's `non_witness_utxo` against its outpoint and
+/// `witness_utxo`, and that it is present if `required`
+fn check_prevout_data(psbt: &Psbt, required: bool) -> Result<(), CoreError> {
+    for (i, (input, txin)) in psbt.inputs.iter().zip(&psbt.unsigned_tx.input).enumerate() {
+        let Some(prev_tx) = &input.non_witness_utxo else {
+            if required {
+                return Err(CoreError::PsbtError(format!(
+                    "Input {} is missing non_witness_utxo, which the vault config requires",
+                    i
+                )));
+            }
+            continue;
+        };
+        let outpoint = txin.previous_output;
+        if prev_tx.txid() != outpoint.txid {
+            return Err(CoreError::PsbtError(format!(
+                "Input {}: non_witness_utxo is transaction {}, but the input spends {}",
+                i,
+                prev_tx.txid(),
+                outpoint
+            )));
+        }
+        let output = prev_tx.output.get(outpoint.vout as usize).ok_or_else(|| {
+            CoreError::PsbtError(format!("Input {}: non_witness_utxo has no output {}", i, outpoint.vout))
+        })?;
+        let Some(witness_utxo) = &input.witness_utxo else { continue };
+        if witness_utxo.value != output.value {
+            return Err(CoreError::PsbtError(format!(
+                "Input {}: witness_utxo value of {} sats disagrees with non_witness_utxo, which has {} sats",
+                i, witness_utxo.value, output.value
+            )));
+        }
+        if witness_utxo.script_pubkey != output.script_pubkey {
+            return Err(CoreError::PsbtError(format!(
+                "Input {}: witness_utxo scriptPubKey disagrees with non_witness_utxo",
+                i
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Vault index of each of `vault`'s scripts at indices `0..=max_index`
+///
+/// A vault with a MuSig2 internal key has only its own script.
+fn vault_scripts(vault: &Vault, max_index: u32) -> Result<HashMap<ScriptBuf, u32>, CoreError> {
+    if vault.musig_key().is_some() {
+        return Ok(HashMap::from([(vault.script_pubkey(), vault.index())]));
+    }
+    let secp = Secp256k1::verification_only();
+    let vault_keys = VaultKeys::new(&secp, vault.template(), vault.owner_xpub(), vault.recovery_xpub(), vault.network(), vault.tree_version())?;
+    (0..=max_index)
+        .map(|index| Ok((vault_keys.tree(&secp, index, None)?.script_pubkey(), index)))
+        .collect()
+}
+
+fn outcome(rule: PolicyRule, index: Option<usize>, passed: bool, detail: String) -> RuleOutcome {
+    RuleOutcome {
+        rule,
+        index,
+        passed,
+        detail,
+    }
+}
+
+/// Vault index and tree named by an input's or output's key origins,
+/// if the tree at that index pays `script_pubkey`
+pub(crate) fn origin_tree(
+    vault: &Vault,
+    origins: &BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    script_pubkey: &Script,
+) -> Option<(u32, VaultTree)> {
+    let indices: BTreeSet<u32> = origins
+        .values()
+        .filter_map(|(_, (_, path))| match path.as_ref().last() {
+            Some(ChildNumber::Normal { index }) => Some(*index),
+            _ => None,
+        })
+        .collect();
+
+    indices.into_iter().find_map(|index| {
+        vault
+            .tree_at(index)
+            .ok()
+            .filter(|tree| tree.script_pubkey().as_script() == script_pubkey)
+            .map(|tree| (index, tree))
+    })
+}
+
+/// `purpose`'s leaf of `tree`, if the input lists it in `tap_scripts`
+fn spent_leaf<'a>(input: &PsbtInput, tree: &'a VaultTree, purpose: LeafPurpose) -> Option<&'a VaultLeaf> {
+    tree.leaf(purpose).filter(|leaf| {
+        input
+            .tap_scripts
+            .values()
+            .any(|(script, _)| *script == leaf.script)
+    })
+}
+
+/// Check input `i`'s nSequence if it spends one of the delayed leaves
+///
+/// The required lock is read from the spent leaf of the re-derived tree,
+/// so each delayed leaf is held to its own CSV delay.
+fn check_sequence(i: usize, input: &PsbtInput, sequence: Sequence, tree: &VaultTree) -> (bool, String) {
+    if input.tap_scripts.is_empty() {
+        return (false, format!("Input {} names no leaf script", i));
+    }
+    let delayed = [LeafPurpose::Timelock, LeafPurpose::WhitelistTimelock, LeafPurpose::Inheritance]
+        .into_iter()
+        .find_map(|purpose| spent_leaf(input, tree, purpose));
+    let Some(leaf) = delayed else {
+        return (true, format!("Input {} does not spend the timelock leaf", i));
+    };
+    let Some(required) = taproot::leaf_csv_delay(&leaf.script)
+        .and_then(|delay| Sequence::from_consensus(delay).to_relative_lock_time())
+    else {
+        return (false, format!("Input {}'s {:?} leaf has no relative lock", i, leaf.purpose));
+    };
+
+    match sequence.to_relative_lock_time() {
+        Some(lock) if required.is_implied_by(lock) => {
+            (true, format!("Input {} waits {}", i, describe_lock(lock)))
+        }
+        _ => (
+            false,
+            format!(
+                "Input {} has nSequence {:#x}, short of the {} unvault delay",
+                i,
+                sequence.to_consensus_u32(),
+                describe_lock(required)
+            ),
+        ),
+    }
+}
+
+/// `script_pubkey` as an address on `network`, or as hex if it has none
+fn describe_script(script_pubkey: &Script, network: Network) -> String {
+    Address::from_script(script_pubkey, network.into())
+        .map(|address| address.to_string())
+        .unwrap_or_else(|_| hex::encode(script_pubkey.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{regtest_config, regtest_vault};
+    use crate::vault::psbt::SpendOptions;
+    use crate::vault::psbt;
+    use crate::vault::{DelayUnit, RecoveryType, VaultBuilder, VaultTemplate};
+    use bitcoin::OutPoint;
+
+    const MAINNET_P2WPKH: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+    const MAINNET_P2TR: &str = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+    const TESTNET_P2WPKH: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+    const REGTEST_P2WPKH: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn address(s: &str, network: Network) -> Address {
+        validate_address(s, network).unwrap()
+    }
+
+    fn mainnet_list() -> ApprovedDestinations {
+        let mut approved = ApprovedDestinations::new(Network::Mainnet);
+        approved.push("exchange", address(MAINNET_P2WPKH, Network::Mainnet)).unwrap();
+        approved.push("cold storage", address(MAINNET_P2TR, Network::Mainnet)).unwrap();
+        approved
+    }
+
+    fn metadata(destination_indices: Vec<u8>) -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: 1008,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices,
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 0,
+            vault_index: 0,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_index_resolution() {
+        let approved = mainnet_list();
+        assert_eq!(approved.len(), 2);
+        assert_eq!(approved.index_of(&address(MAINNET_P2TR, Network::Mainnet)), Some(1));
+        assert_eq!(approved.resolve(0).unwrap().to_string(), MAINNET_P2WPKH);
+        assert!(approved.resolve(2).is_err());
+
+        let labels: Vec<&str> = approved.iter().map(|(label, _)| label).collect();
+        assert_eq!(labels, vec!["exchange", "cold storage"]);
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let approved = mainnet_list();
+        let json = serde_json::to_string(&approved).unwrap();
+        assert!(json.contains(MAINNET_P2TR));
+        assert!(json.contains("\"label\":\"exchange\""));
+
+        let decoded: ApprovedDestinations = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, approved);
+        assert_eq!(decoded.commitment(), approved.commitment());
+    }
+
+    #[test]
+    fn test_network_mismatch_rejected() {
+        let mut approved = ApprovedDestinations::new(Network::Mainnet);
+        let testnet = address(TESTNET_P2WPKH, Network::Testnet);
+        assert!(matches!(approved.push("faucet", testnet), Err(CoreError::InvalidInput(_))));
+        assert!(approved.is_empty());
+
+        let json = format!(
+            r#"{{"network":"mainnet","destinations":[{{"label":"faucet","address":"{}"}}]}}"#,
+            TESTNET_P2WPKH
+        );
+        assert!(serde_json::from_str::<ApprovedDestinations>(&json).is_err());
+    }
+
+    #[test]
+    fn test_commitment_covers_order_and_labels() {
+        let approved = mainnet_list();
+
+        let mut reordered = ApprovedDestinations::new(Network::Mainnet);
+        reordered.push("cold storage", address(MAINNET_P2TR, Network::Mainnet)).unwrap();
+        reordered.push("exchange", address(MAINNET_P2WPKH, Network::Mainnet)).unwrap();
+        assert_ne!(approved.commitment(), reordered.commitment());
+
+        let mut relabelled = ApprovedDestinations::new(Network::Mainnet);
+        relabelled.push("exchange 2", address(MAINNET_P2WPKH, Network::Mainnet)).unwrap();
+        relabelled.push("cold storage", address(MAINNET_P2TR, Network::Mainnet)).unwrap();
+        assert_ne!(approved.commitment(), relabelled.commitment());
+    }
+
+    #[test]
+    fn test_validate_address_formats() {
+        // bech32 (P2WPKH), bech32m (P2TR), legacy P2PKH and P2SH
+        for (addr, network) in [
+            (MAINNET_P2WPKH, Network::Mainnet),
+            (MAINNET_P2TR, Network::Mainnet),
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", Network::Mainnet),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", Network::Mainnet),
+            (TESTNET_P2WPKH, Network::Testnet),
+            ("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c", Network::Signet),
+            ("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Testnet),
+            ("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", Network::Regtest),
+        ] {
+            let address = validate_address(addr, network).unwrap();
+            assert_eq!(address.to_string(), addr);
+        }
+        // Legacy testnet addresses are shared with regtest
+        validate_address("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", Network::Regtest).unwrap();
+    }
+
+    #[test]
+    fn test_validate_address_network_mismatch() {
+        match validate_address(TESTNET_P2WPKH, Network::Mainnet) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "mainnet");
+                assert_eq!(actual, "testnet");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+        match validate_address("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", Network::Testnet) {
+            Err(CoreError::NetworkMismatch { expected, actual }) => {
+                assert_eq!(expected, "testnet");
+                assert_eq!(actual, "regtest");
+            }
+            other => panic!("expected NetworkMismatch, got {:?}", other),
+        }
+        assert!(matches!(
+            validate_address(MAINNET_P2TR, Network::Regtest),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+        assert!(matches!(
+            validate_address("bc1qnotanaddress", Network::Mainnet),
+            Err(CoreError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_destination() {
+        let approved = mainnet_list();
+        let exchange = address(MAINNET_P2WPKH, Network::Mainnet);
+        let cold = address(MAINNET_P2TR, Network::Mainnet);
+
+        // No restriction
+        check_destination(&metadata(vec![]), None, &exchange).unwrap();
+
+        check_destination(&metadata(vec![1]), Some(&approved), &cold).unwrap();
+        let err = check_destination(&metadata(vec![1]), Some(&approved), &exchange).unwrap_err();
+        match err {
+            CoreError::PolicyViolation(message) => assert!(message.contains(MAINNET_P2WPKH)),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+
+        assert!(matches!(
+            check_destination(&metadata(vec![1]), None, &cold),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        // Indices past the end of the list approve nothing
+        assert!(check_destination(&metadata(vec![7]), Some(&approved), &cold).is_err());
+    }
+
+    /// Unvault PSBT for vault index 2, optionally sending only `amount_sats`
+    /// with change to index 3
+    fn unvault_psbt(config: &VaultConfig, amount_sats: Option<u64>) -> Psbt {
+        let vault = VaultBuilder::from_config(config).index(2).build().unwrap();
+        let utxo = vault.utxo(OutPoint::default(), 100_000);
+        let destination = address(REGTEST_P2WPKH, Network::Regtest);
+        let metadata = vault.metadata();
+        match amount_sats {
+            Some(amount) => {
+                let change = vault.change_target(3).unwrap();
+                psbt::build_partial_unvault(utxo, destination, amount, &change, 2, &metadata, SpendOptions::default())
+                    .map(|bundle| bundle.psbt)
+            }
+            None => psbt::build_unvault(utxo, destination, 2, &metadata, SpendOptions::default()),
+        }
+        .unwrap()
+    }
+
+    fn failed_rules(report: &PolicyReport) -> Vec<(PolicyRule, Option<usize>)> {
+        report.failures().map(|check| (check.rule, check.index)).collect()
+    }
+
+    #[test]
+    fn test_check_psbt_accepts_vault_unvault() {
+        let config = regtest_config();
+        let report = check_psbt(&unvault_psbt(&config, None), &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+        assert_eq!(report.checks.len(), 4);
+        assert!(report.checks[0].detail.contains("vault index 2"));
+
+        // Change back to the vault passes alongside a whitelisted destination
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved.push("exchange", address(REGTEST_P2WPKH, Network::Regtest)).unwrap();
+        let config = VaultConfig {
+            approved_destinations: Some(approved),
+            ..regtest_config()
+        };
+        let mut psbt = unvault_psbt(&config, Some(40_000));
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+        assert!(report.checks.iter().any(|check| check.detail.contains("to vault index 3")));
+        report.into_result().unwrap();
+
+        // Without its key origins, change at a fresh index can't be told
+        // apart from an unapproved destination
+        psbt.outputs[1].tap_key_origins.clear();
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::Destination, Some(1))]);
+    }
+
+    #[test]
+    fn test_check_psbt_rejects_unapproved_destination() {
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved
+            .push("other", address("bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk", Network::Regtest))
+            .unwrap();
+        let config = VaultConfig {
+            approved_destinations: Some(approved),
+            ..regtest_config()
+        };
+
+        let report = check_psbt(&unvault_psbt(&config, None), &config).unwrap();
+        assert!(!report.passed);
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::Destination, Some(0))]);
+        match report.into_result() {
+            Err(CoreError::PolicyViolation(message)) => assert!(message.contains(REGTEST_P2WPKH)),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_psbt_accepts_memo_output() {
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved.push("exchange", address(REGTEST_P2WPKH, Network::Regtest)).unwrap();
+        let config = VaultConfig {
+            approved_destinations: Some(approved),
+            ..regtest_config()
+        };
+        let vault = VaultBuilder::from_config(&config).index(2).build().unwrap();
+        let utxo = vault.utxo(OutPoint::default(), 100_000);
+        let destination = address(REGTEST_P2WPKH, Network::Regtest);
+        let memo = Some(b"ref 42".to_vec());
+        let psbt = psbt::build_unvault(
+            utxo,
+            destination,
+            2,
+            &vault.metadata(),
+            SpendOptions { memo, ..Default::default() },
+        )
+        .unwrap();
+
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+        assert!(report.checks[3].detail.contains("OP_RETURN memo"));
+    }
+
+    #[test]
+    fn test_check_psbt_rejects_short_sequence() {
+        let config = regtest_config();
+        let mut psbt = unvault_psbt(&config, None);
+        psbt.unsigned_tx.input[0].sequence = Sequence::from_height(143);
+
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::UnvaultDelay, Some(0))]);
+
+        psbt.unsigned_tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::UnvaultDelay, Some(0))]);
+    }
+
+    #[test]
+    fn test_check_psbt_whitelist_path() {
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved.push("exchange", address(REGTEST_P2WPKH, Network::Regtest)).unwrap();
+        let config = VaultConfig {
+            template: VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 },
+            approved_destinations: Some(approved.clone()),
+            ..regtest_config()
+        };
+        let vault = VaultBuilder::from_config(&config).index(2).build().unwrap();
+        let utxo = vault.utxo(OutPoint::default(), 100_000);
+        let psbt = psbt::build_unvault(
+            utxo,
+            address(REGTEST_P2WPKH, Network::Regtest),
+            2,
+            &vault.metadata(),
+            SpendOptions { approved: Some(&approved), ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence::from_height(144));
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+
+        // The short path paying elsewhere fails, even with no list configured
+        let mut redirected = psbt.clone();
+        redirected.unsigned_tx.output[0].script_pubkey =
+            address("bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk", Network::Regtest).script_pubkey();
+        for config in [config.clone(), VaultConfig { approved_destinations: None, ..config.clone() }] {
+            let report = check_psbt(&redirected, &config).unwrap();
+            assert_eq!(failed_rules(&report), vec![(PolicyRule::Destination, Some(0))]);
+            assert!(report.checks[2].detail.contains("whitelist delay"), "{}", report.checks[2].detail);
+        }
+
+        // The whitelist leaf is held to its own delay
+        let mut early = psbt;
+        early.unsigned_tx.input[0].sequence = Sequence::from_height(143);
+        let report = check_psbt(&early, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::UnvaultDelay, Some(0))]);
+    }
+
+    #[test]
+    fn test_check_psbt_rejects_foreign_input() {
+        let config = regtest_config();
+        let mut psbt = unvault_psbt(&config, None);
+        // Same origins, different script: the re-derived script won't match
+        let witness_utxo = psbt.inputs[0].witness_utxo.as_mut().unwrap();
+        witness_utxo.script_pubkey = address(REGTEST_P2WPKH, Network::Regtest).script_pubkey();
+
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(
+            failed_rules(&report),
+            vec![(PolicyRule::VaultInput, Some(0)), (PolicyRule::UnvaultDelay, Some(0))]
+        );
+
+        psbt.inputs[0].witness_utxo = None;
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(failed_rules(&report).contains(&(PolicyRule::FeeCeiling, None)));
+    }
+
+    /// Unvault PSBT for vault index 2 spending output 1 of a funding
+    /// transaction, which is attached as `non_witness_utxo`
+    fn funded_unvault_psbt(config: &VaultConfig) -> Psbt {
+        let vault = VaultBuilder::from_config(config).index(2).build().unwrap();
+        let funding = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                bitcoin::TxOut {
+                    value: 5_000,
+                    script_pubkey: address(REGTEST_P2WPKH, Network::Regtest).script_pubkey(),
+                },
+                vault.utxo(OutPoint::default(), 100_000).txout(),
+            ],
+        };
+        let utxo = vault.utxo(OutPoint::new(funding.txid(), 1), 100_000);
+        let destination = address(REGTEST_P2WPKH, Network::Regtest);
+        let mut psbt = psbt::build_unvault(utxo, destination, 2, &vault.metadata(), SpendOptions::default()).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(funding);
+        psbt
+    }
+
+    fn psbt_error(result: Result<impl std::fmt::Debug, CoreError>) -> String {
+        match result {
+            Err(CoreError::PsbtError(message)) => message,
+            other => panic!("expected PsbtError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verify_prevouts_accepts_vault_inputs() {
+        let config = regtest_config();
+        let mut psbt = funded_unvault_psbt(&config);
+        assert_eq!(verify_prevouts(&psbt, &config, 0).unwrap(), vec![2]);
+
+        // Without origins, the index is found by scanning up to max_index
+        psbt.inputs[0].tap_key_origins.clear();
+        assert_eq!(verify_prevouts(&psbt, &config, 5).unwrap(), vec![2]);
+        assert!(psbt_error(verify_prevouts(&psbt, &config, 1)).contains("indices 0 to 1"));
+
+        assert!(matches!(
+            verify_prevouts(&psbt, &config, taproot::MAX_ADDRESS_RANGE),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_prevouts_rejects_forged_value() {
+        let config = regtest_config();
+        let mut psbt = funded_unvault_psbt(&config);
+        // Claiming the input is worth less hides part of the fee
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = 90_000;
+
+        let message = psbt_error(verify_prevouts(&psbt, &config, 10));
+        assert!(message.starts_with("Input 0: witness_utxo value"), "{}", message);
+        assert!(message.contains("100000 sats"));
+        psbt_error(check_psbt(&psbt, &config));
+
+        // Nothing is signed
+        let mut owner: bitcoin::bip32::ExtendedPrivKey = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi".parse().unwrap();
+        owner.network = bitcoin::Network::Regtest;
+        psbt_error(psbt::sign_verified(&mut psbt, &owner.into(), &config, 10));
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = 100_000;
+        assert_eq!(psbt::sign_verified(&mut psbt, &owner.into(), &config, 10).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_verify_prevouts_rejects_foreign_script() {
+        let config = regtest_config();
+        let mut psbt = funded_unvault_psbt(&config);
+        let foreign = address(REGTEST_P2WPKH, Network::Regtest).script_pubkey();
+
+        // Consistent with the funding transaction, but not the vault's
+        psbt.inputs[0].non_witness_utxo = None;
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey = foreign.clone();
+        let message = psbt_error(verify_prevouts(&psbt, &config, 10));
+        assert!(message.contains("not a vault script"), "{}", message);
+
+        let mut psbt = funded_unvault_psbt(&config);
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey = foreign;
+        assert!(psbt_error(verify_prevouts(&psbt, &config, 10)).contains("witness_utxo scriptPubKey disagrees"));
+    }
+
+    #[test]
+    fn test_verify_prevouts_checks_non_witness_utxo() {
+        let config = regtest_config();
+        let mut psbt = funded_unvault_psbt(&config);
+
+        // A different transaction than the one spent
+        let mut other = psbt.inputs[0].non_witness_utxo.clone().unwrap();
+        other.lock_time = bitcoin::absolute::LockTime::from_consensus(1);
+        psbt.inputs[0].non_witness_utxo = Some(other);
+        assert!(psbt_error(verify_prevouts(&psbt, &config, 10)).contains("non_witness_utxo is transaction"));
+
+        // Missing, which only fails when the config requires it
+        psbt.inputs[0].non_witness_utxo = None;
+        assert_eq!(verify_prevouts(&psbt, &config, 10).unwrap(), vec![2]);
+        assert!(check_psbt(&psbt, &config).unwrap().passed);
+        let strict = VaultConfig {
+            require_non_witness_utxo: true,
+            ..regtest_config()
+        };
+        assert!(psbt_error(verify_prevouts(&psbt, &strict, 10)).contains("missing non_witness_utxo"));
+        psbt_error(check_psbt(&psbt, &strict));
+        assert!(check_psbt(&funded_unvault_psbt(&strict), &strict).unwrap().passed);
+    }
+
+    #[test]
+    fn test_check_psbt_fee_ceiling() {
+        let config = VaultConfig {
+            max_fee_sats: Some(100),
+            ..regtest_config()
+        };
+        let report = check_psbt(&unvault_psbt(&config, None), &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::FeeCeiling, None)]);
+        assert!(report.checks[3].detail.contains("ceiling of 100 sats"));
+    }
+
+    fn spend(height: u32, amount_sats: u64) -> SpendRecord {
+        SpendRecord { txid: Txid::all_zeros(), height, amount_sats }
+    }
+
+    /// Spends 100,000 sats from a vault script, paying 20,000 out and
+    /// 79,000 back: 21,000 sats leave with the fee
+    fn velocity_psbt() -> Psbt {
+        let vault_script = Vault::from_config(&regtest_config()).unwrap().tree().script_pubkey();
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![
+                bitcoin::TxOut { value: 20_000, script_pubkey: address(REGTEST_P2WPKH, Network::Regtest).script_pubkey() },
+                bitcoin::TxOut { value: 79_000, script_pubkey: vault_script.clone() },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut { value: 100_000, script_pubkey: vault_script });
+        psbt
+    }
+
+    #[test]
+    fn test_check_velocity_window_edge() {
+        let limit = VelocityLimit { max_sats: 50_000, window_blocks: 1008 };
+        let history = [spend(1000, 30_000)];
+
+        // Still in the window on its last block: 51,000 sats
+        match check_velocity(&limit, &history, &velocity_psbt(), &regtest_vault(VaultTemplate::spending(), 0), 2007).unwrap_err() {
+            CoreError::PolicyViolation(msg) => assert!(msg.contains("51000 sats, 1000 over"), "{}", msg),
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+        // A block later it has aged out
+        check_velocity(&limit, &history, &velocity_psbt(), &regtest_vault(VaultTemplate::spending(), 0), 2008).unwrap();
+
+        let mut unfunded = velocity_psbt();
+        unfunded.inputs[0].witness_utxo = None;
+        assert!(matches!(check_velocity(&limit, &[], &unfunded, &regtest_vault(VaultTemplate::spending(), 0), 2008), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_check_velocity_sums_partial_spends() {
+        let history = [spend(1500, 10_000), spend(1900, 15_000), spend(2000, 5_000), spend(400, 40_000)];
+
+        // 30,000 in the window plus 21,000 is over 50,000, and exactly at 51,000
+        let limit = VelocityLimit { max_sats: 50_000, window_blocks: 1008 };
+        assert!(matches!(
+            check_velocity(&limit, &history, &velocity_psbt(), &regtest_vault(VaultTemplate::spending(), 0), 2000),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        let limit = VelocityLimit { max_sats: 51_000, window_blocks: 1008 };
+        check_velocity(&limit, &history, &velocity_psbt(), &regtest_vault(VaultTemplate::spending(), 0), 2000).unwrap();
+        check_velocity(&limit, &[], &velocity_psbt(), &regtest_vault(VaultTemplate::spending(), 0), 2000).unwrap();
+    }
+
+    #[test]
+    fn test_check_psbt_velocity_limit() {
+        let config = VaultConfig {
+            velocity_limit: Some(VelocityLimit { max_sats: 50_000, window_blocks: 1008 }),
+            current_block_height: Some(2000),
+            ..regtest_config()
+        };
+        // Change to a fresh vault index doesn't count as leaving
+        let psbt = unvault_psbt(&config, Some(40_000));
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+        let velocity = report.checks.last().unwrap();
+        assert_eq!(velocity.rule, PolicyRule::Velocity);
+        assert!(velocity.detail.contains("of 50000 sats in the last 1008 blocks"), "{}", velocity.detail);
+
+        let config = VaultConfig {
+            spend_history: vec![spend(1500, 5_000), spend(1900, 5_000)],
+            ..config
+        };
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::Velocity, None)]);
+        assert!(matches!(report.into_result(), Err(CoreError::PolicyViolation(_))));
+
+        let config = VaultConfig { current_block_height: None, ..config };
+        assert!(matches!(check_psbt(&psbt, &config), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_check_velocity_agrees_with_check_psbt() {
+        let config = VaultConfig {
+            velocity_limit: Some(VelocityLimit { max_sats: 50_000, window_blocks: 1008 }),
+            current_block_height: Some(2000),
+            ..regtest_config()
+        };
+        let limit = config.velocity_limit.unwrap();
+        let vault = Vault::from_config(&config).unwrap();
+
+        // The change goes to fresh index 3, so only the 40,000 sats paid
+        // out and the fee leave
+        let psbt = unvault_psbt(&config, Some(40_000));
+        let outflow = check_velocity(&limit, &[], &psbt, &vault, 2000).unwrap();
+        let change = psbt.unsigned_tx.output.iter().find(|output| output.value != 40_000).unwrap().value;
+        assert_eq!(outflow, 100_000 - change);
+        let report = check_psbt(&psbt, &config).unwrap();
+        let velocity = report.checks.last().unwrap();
+        assert!(velocity.detail.starts_with(&format!("{} sats leave the vault", outflow)), "{}", velocity.detail);
+
+        // Both refuse it once the history brings the window over
+        let config = VaultConfig { spend_history: vec![spend(1900, 50_000 - outflow + 1)], ..config };
+        let refused = match check_velocity(&limit, &config.spend_history, &psbt, &vault, 2000) {
+            Err(CoreError::PolicyViolation(msg)) => msg,
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        };
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert_eq!(failed_rules(&report), vec![(PolicyRule::Velocity, None)]);
+        assert_eq!(report.checks.last().unwrap().detail, refused);
+    }
+
+    #[test]
+    fn test_check_psbt_recovery_skips_delay() {
+        let config = regtest_config();
+        let vault = Vault::from_config(&config).unwrap();
+        let utxo = vault.utxo(OutPoint::default(), 100_000);
+        let cold = address(REGTEST_P2WPKH, Network::Regtest);
+        let psbt = psbt::build_recovery(&[utxo], None, cold, 2, SpendOptions::default()).unwrap();
+
+        let report = check_psbt(&psbt, &config).unwrap();
+        assert!(report.passed, "{:?}", report);
+        assert!(report.checks[1].detail.contains("does not spend the timelock leaf"));
+    }
+
+    #[test]
+    fn test_check_psbt_rejects_mismatched_whitelist_network() {
+        let config = VaultConfig {
+            approved_destinations: Some(mainnet_list()),
+            ..regtest_config()
+        };
+        let psbt = unvault_psbt(&regtest_config(), None);
+        assert!(matches!(
+            check_psbt(&psbt, &config),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+    }
+}
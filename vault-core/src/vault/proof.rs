@@ -0,0 +1,449 @@
+//! BIP322 message signing, for proving control of vault addresses
+//!
+//! A proof is the witness of a virtual transaction, `to_sign`, spending
+//! the only output of another, `to_spend`, which pays to the address and
+//! commits to the message. Neither is ever broadcast. Vault proofs spend
+//! through the key path when the signer holds the internal key, and
+//! otherwise through the emergency leaf, the only leaf without a
+//! timelock, so `to_sign` keeps BIP322's default version, lock time and
+//! sequence and the proof can use the simple encoding: the base64
+//! witness.
+//!
+//! Verification covers taproot addresses, for proofs in either the
+//! simple or the full encoding (the whole `to_sign`). Key-path spends are
+//! checked against the output key; script-path spends are checked for
+//! the leaf forms `taproot::leaf_signers()` recognizes.
+
+use base64::Engine;
+use bitcoin::absolute::LockTime;
+use bitcoin::address::Address;
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Annex, Prevouts, SighashCache};
+use bitcoin::taproot::{self as bip341, ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::{consensus, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+use crate::error::{CoreError, CoreResult};
+use crate::keys;
+use crate::taproot::{self, LeafPurpose, VaultTree};
+
+use super::{psbt, Vault};
+
+/// BIP340 tag of the message hash
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Tagged hash of a message, as committed to by `to_spend`
+pub fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// The virtual transaction paying to `script_pubkey` that a proof for
+/// `message` spends
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), 0xFFFF_FFFF),
+            script_sig: Builder::new()
+                .push_int(0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    }
+}
+
+/// The virtual transaction spending `to_spend` with `witness`, as
+/// encoded by a full proof
+pub fn to_sign(to_spend: &Transaction, witness: Witness) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Prove control of the vault address at `index` by signing `message`
+///
+/// Signs the key path if `xpriv` is the account key behind the internal
+/// key (vaults whose template enables key-path spends), and otherwise the
+/// emergency leaf, which needs the recovery account key. Returns the
+/// simple-encoded proof.
+///
+/// Errors with `SigningError` if `xpriv` holds neither key, and with
+/// `NetworkMismatch` for a key of the wrong network.
+pub fn sign_message(vault: &Vault, index: u32, xpriv: &keys::SecretMaterial, message: &str) -> CoreResult<String> {
+    let tree = vault.tree_at(index)?;
+    let to_spend = to_spend(&tree.script_pubkey(), message);
+    let utxo = psbt::VaultUtxo::new(OutPoint::new(to_spend.txid(), 0), 0, tree);
+
+    let mut psbt = Psbt::from_unsigned_tx(to_sign(&to_spend, Witness::new()))
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    let fingerprint = xpriv.fingerprint();
+    if holds_internal_key(&utxo.tree, fingerprint) {
+        psbt.inputs[0] = psbt::key_path_input(&utxo);
+        psbt::sign_key_path(&mut psbt, xpriv)?;
+    } else if utxo.tree.leaf(LeafPurpose::Emergency).is_some() {
+        psbt.inputs[0] = psbt::script_path_input(&utxo, LeafPurpose::Emergency)?;
+        keys::sign_psbt(&mut psbt, xpriv, vault.network())?;
+    } else {
+        return Err(CoreError::SigningError {
+            input_index: 0,
+            reason: format!(
+                "Key {} does not hold the internal key, and vault index {} has no emergency leaf",
+                fingerprint, index
+            ),
+        });
+    }
+
+    let tx = psbt::finalize(&mut psbt)?;
+    log::debug!("Signed BIP322 proof for vault index {}", index);
+    Ok(base64::engine::general_purpose::STANDARD.encode(consensus::serialize(&tx.input[0].witness)))
+}
+
+/// Whether the account key with `fingerprint` is behind the tree's internal key
+fn holds_internal_key(tree: &VaultTree, fingerprint: bitcoin::bip32::Fingerprint) -> bool {
+    tree.key_origins()
+        .get(&tree.internal_key())
+        .is_some_and(|(key_fingerprint, _)| *key_fingerprint == fingerprint)
+}
+
+/// Check a BIP322 proof that `message` was signed for `address`
+///
+/// `proof` is base64, in the simple or full encoding. Returns `Ok(false)`
+/// for a proof that doesn't satisfy the address's script, or a full
+/// proof that isn't a `to_sign` for this address and message.
+///
+/// Errors with `InvalidInput` for a proof that doesn't decode, for a
+/// non-taproot address, for a proof of funds (a `to_sign` with more than
+/// one input) and for leaf scripts other than single-key and multisig
+/// leaves, which can't be checked here.
+pub fn verify_message(address: &Address, message: &str, proof: &str) -> CoreResult<bool> {
+    let script_pubkey = address.script_pubkey();
+    if !script_pubkey.is_v1_p2tr() {
+        return Err(CoreError::InvalidInput(format!(
+            "BIP322 proofs can only be checked for taproot addresses, not {}",
+            address
+        )));
+    }
+    let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid taproot output key: {}", e)))?;
+
+    let to_spend = to_spend(&script_pubkey, message);
+    let proof_tx = decode_proof(&to_spend, proof)?;
+    if proof_tx.input.len() != 1 {
+        return Err(CoreError::InvalidInput(
+            "BIP322 proofs of funds (extra to_sign inputs) are not supported".to_string(),
+        ));
+    }
+    let expected = to_sign(&to_spend, Witness::new());
+    if proof_tx.input[0].previous_output != expected.input[0].previous_output || proof_tx.output != expected.output {
+        return Ok(false);
+    }
+
+    verify_taproot_spend(&proof_tx, &to_spend.output[0], &output_key)
+}
+
+/// Decode a base64 proof to the `to_sign` it stands for
+///
+/// A simple proof is a witness, placed in the default `to_sign`; a full
+/// proof is the transaction itself.
+fn decode_proof(to_spend: &Transaction, proof: &str) -> CoreResult<Transaction> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(proof.trim())
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid proof base64: {}", e)))?;
+
+    if let Ok(witness) = consensus::deserialize::<Witness>(&bytes) {
+        return Ok(to_sign(to_spend, witness));
+    }
+    consensus::deserialize::<Transaction>(&bytes).map_err(|_| {
+        CoreError::InvalidInput("Proof is neither a BIP322 witness nor a to_sign transaction".to_string())
+    })
+}
+
+/// Whether the witness of `to_sign`'s input satisfies the P2TR `prevout`
+/// with output key `output_key`, per BIP341 and BIP342
+fn verify_taproot_spend(to_sign: &Transaction, prevout: &TxOut, output_key: &XOnlyPublicKey) -> CoreResult<bool> {
+    let mut elements: Vec<&[u8]> = to_sign.input[0].witness.iter().collect();
+    let annex = match elements.last() {
+        Some(last) if elements.len() >= 2 && last.first() == Some(&0x50) => {
+            let annex = Annex::new(last).expect("annex starts with 0x50");
+            elements.pop();
+            Some(annex)
+        }
+        _ => None,
+    };
+
+    let secp = Secp256k1::verification_only();
+    let prevouts = [prevout];
+    let prevouts = Prevouts::All(&prevouts);
+    let mut cache = SighashCache::new(to_sign);
+    let mut check_sig = |sig: &[u8], key: &XOnlyPublicKey, leaf_hash: Option<TapLeafHash>| {
+        // An explicit SIGHASH_DEFAULT byte is invalid (BIP341)
+        let Some(sig) = bip341::Signature::from_slice(sig).ok().filter(|_| sig.len() != 65 || sig[64] != 0) else {
+            return false;
+        };
+        let leaf = leaf_hash.map(|hash| (hash, 0xFFFF_FFFF));
+        let Ok(sighash) = cache.taproot_signature_hash(0, &prevouts, annex.clone(), leaf, sig.hash_ty) else {
+            return false;
+        };
+        let msg = Message::from_slice(sighash.as_ref()).expect("sighashes are 32 bytes");
+        secp.verify_schnorr(&sig.sig, &msg, key).is_ok()
+    };
+
+    match elements[..] {
+        [] => Ok(false),
+        [sig] => Ok(check_sig(sig, output_key, None)),
+        [ref stack @ .., script, control_block] => {
+            let Ok(control_block) = ControlBlock::decode(control_block) else {
+                return Ok(false);
+            };
+            let script = Script::from_bytes(script);
+            if !taproot::verify_control_block(&control_block, script, output_key) {
+                return Ok(false);
+            }
+            if control_block.leaf_version != LeafVersion::TapScript {
+                return Err(CoreError::InvalidInput(format!(
+                    "Proof spends a leaf of unknown version {}",
+                    control_block.leaf_version
+                )));
+            }
+            let signers = taproot::leaf_signers(script).ok_or_else(|| {
+                CoreError::InvalidInput("Proof spends a leaf script that can't be checked here".to_string())
+            })?;
+            if let Some(delay) = taproot::leaf_csv_delay(script) {
+                if !csv_satisfied(to_sign, delay) {
+                    return Ok(false);
+                }
+            }
+            // One stack element per key, the first key's on top
+            if stack.len() != signers.keys.len() {
+                return Ok(false);
+            }
+            let leaf_hash = TapLeafHash::from_script(script, control_block.leaf_version);
+            let mut valid = 0;
+            for (key, sig) in signers.keys.iter().zip(stack.iter().rev()) {
+                if sig.is_empty() {
+                    continue;
+                }
+                if !check_sig(sig, key, Some(leaf_hash)) {
+                    return Ok(false);
+                }
+                valid += 1;
+            }
+            Ok(valid == signers.threshold)
+        }
+    }
+}
+
+/// Whether `to_sign`'s input satisfies `<delay> OP_CSV`, per BIP112
+fn csv_satisfied(to_sign: &Transaction, delay: u32) -> bool {
+    let required = Sequence::from_consensus(delay);
+    if !required.is_relative_lock_time() {
+        return true;
+    }
+    match (required.to_relative_lock_time(), to_sign.input[0].sequence.to_relative_lock_time()) {
+        (Some(required), Some(actual)) if to_sign.version >= 2 => required.is_implied_by(actual),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use bitcoin::bip32::{ExtendedPrivKey, ExtendedPubKey};
+
+    use crate::test_common::{custom_template, CustomFields};
+    use crate::vault::{Network, RecoveryType, VaultBuilder, VaultTemplate};
+
+    // BIP322 test vector: key-path P2TR address of L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k
+    const VECTOR_ADDRESS: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+    const VECTOR_PROOF: &str = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+
+    fn account(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[seed; 32]).unwrap()
+    }
+
+    fn regtest_vault(template: VaultTemplate) -> Vault {
+        let secp = Secp256k1::new();
+        VaultBuilder::new()
+            .template(template)
+            .owner_xpub(ExtendedPubKey::from_priv(&secp, &account(1)).to_string())
+            .recovery_xpub(ExtendedPubKey::from_priv(&secp, &account(2)).to_string())
+            .network(Network::Regtest)
+            .build()
+            .unwrap()
+    }
+
+    fn address(s: &str) -> Address {
+        Address::from_str(s).unwrap().assume_checked()
+    }
+
+    #[test]
+    fn test_message_hash_vectors() {
+        assert_eq!(
+            message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_virtual_transaction_vectors() {
+        // BIP322 test vectors for bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l
+        let script_pubkey = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").script_pubkey();
+        for (message, to_spend_txid, to_sign_txid) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ] {
+            let to_spend = to_spend(&script_pubkey, message);
+            assert_eq!(to_spend.txid().to_string(), to_spend_txid);
+            assert_eq!(to_sign(&to_spend, Witness::new()).txid().to_string(), to_sign_txid);
+        }
+    }
+
+    #[test]
+    fn test_verify_key_path_vector() {
+        let address = address(VECTOR_ADDRESS);
+        assert!(verify_message(&address, "Hello World", VECTOR_PROOF).unwrap());
+        assert!(!verify_message(&address, "Hello World!", VECTOR_PROOF).unwrap());
+        assert!(!verify_message(&address, "", VECTOR_PROOF).unwrap());
+
+        // The same proof in the full encoding
+        let to_spend = to_spend(&address.script_pubkey(), "Hello World");
+        let witness: Witness = consensus::deserialize(
+            &base64::engine::general_purpose::STANDARD.decode(VECTOR_PROOF).unwrap(),
+        )
+        .unwrap();
+        let full = base64::engine::general_purpose::STANDARD.encode(consensus::serialize(&to_sign(&to_spend, witness)));
+        assert!(verify_message(&address, "Hello World", &full).unwrap());
+        assert!(!verify_message(&address, "Hello World!", &full).unwrap());
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_proofs() {
+        let taproot = address(VECTOR_ADDRESS);
+        for proof in ["not base64!", "AAAA"] {
+            assert!(matches!(
+                verify_message(&taproot, "Hello World", proof),
+                Err(CoreError::InvalidInput(_))
+            ));
+        }
+        let segwit_v0 = address("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l");
+        assert!(matches!(
+            verify_message(&segwit_v0, "Hello World", VECTOR_PROOF),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_emergency_leaf_proof_roundtrip() {
+        let vault = regtest_vault(VaultTemplate::savings());
+        let proof = sign_message(&vault, 4, &account(2).into(), "vault audit 2026").unwrap();
+        let witness: Witness = consensus::deserialize(
+            &base64::engine::general_purpose::STANDARD.decode(&proof).unwrap(),
+        )
+        .unwrap();
+        // Signature, emergency leaf script and control block
+        assert_eq!(witness.len(), 3);
+
+        let address = vault.tree_at(4).unwrap().address(Network::Regtest);
+        assert!(verify_message(&address, "vault audit 2026", &proof).unwrap());
+        assert!(!verify_message(&address, "vault audit 2027", &proof).unwrap());
+        assert!(!verify_message(&vault.address(), "vault audit 2026", &proof).unwrap());
+    }
+
+    #[test]
+    fn test_key_path_proof_roundtrip() {
+        let vault = regtest_vault(custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            key_path_enabled: true,
+            ..Default::default()
+        }));
+
+        // The owner holds the internal key; the recovery key still signs its leaf
+        let owner_proof = sign_message(&vault, 0, &account(1).into(), "hello").unwrap();
+        let recovery_proof = sign_message(&vault, 0, &account(2).into(), "hello").unwrap();
+        assert!(owner_proof.len() < recovery_proof.len());
+        for proof in [owner_proof, recovery_proof] {
+            assert!(verify_message(&vault.address(), "hello", &proof).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sign_message_needs_a_signing_key() {
+        // The owner key only signs the timelock leaf of a script-path vault
+        let vault = regtest_vault(VaultTemplate::savings());
+        assert!(matches!(
+            sign_message(&vault, 0, &account(1).into(), "hello"),
+            Err(CoreError::SigningError { .. })
+        ));
+
+        let timelock_only = regtest_vault(custom_template(CustomFields::default()));
+        assert!(matches!(
+            sign_message(&timelock_only, 0, &account(2).into(), "hello"),
+            Err(CoreError::SigningError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_checks_timelock_leaf_sequence() {
+        // A full proof through the timelock leaf must set the CSV sequence
+        let vault = regtest_vault(VaultTemplate::savings());
+        let tree = vault.tree();
+        let to_spend = to_spend(&tree.script_pubkey(), "hello");
+        let utxo = psbt::VaultUtxo::new(OutPoint::new(to_spend.txid(), 0), 0, tree.clone());
+
+        let full_proof = |version: i32, sequence: Sequence| {
+            let mut tx = to_sign(&to_spend, Witness::new());
+            tx.version = version;
+            tx.input[0].sequence = sequence;
+            let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+            psbt.inputs[0] = psbt::script_path_input(&utxo, LeafPurpose::Timelock).unwrap();
+            keys::sign_psbt(&mut psbt, &account(1).into(), Network::Regtest).unwrap();
+            let tx = psbt::finalize(&mut psbt).unwrap();
+            base64::engine::general_purpose::STANDARD.encode(consensus::serialize(&tx))
+        };
+
+        let delay = vault.template().sequence().unwrap();
+        assert!(verify_message(&vault.address(), "hello", &full_proof(2, delay)).unwrap());
+        assert!(!verify_message(&vault.address(), "hello", &full_proof(2, Sequence::ZERO)).unwrap());
+        assert!(!verify_message(&vault.address(), "hello", &full_proof(0, delay)).unwrap());
+    }
+}
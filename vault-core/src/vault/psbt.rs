@@ -0,0 +1,4610 @@
+use std::collections::BTreeMap;
+
+use base64::Engine;
+use bitcoin::absolute::LockTime;
+use bitcoin::relative;
+use bitcoin::address::Address;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::raw::ProprietaryKey;
+use bitcoin::psbt::{Input as PsbtInput, Output as PsbtOutput, Psbt};
+use bitcoin::script::{Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::taproot::TapLeafHash;
+use bitcoin::{OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+use serde::Serialize;
+
+use crate::error::CoreError;
+use crate::keys;
+use crate::taproot::{self, LeafId, LeafPurpose, VaultTree, MAX_CSV_DELAY_BLOCKS};
+use crate::vault::coins::Selection;
+use crate::vault::fees::{self, DustPolicy};
+use crate::vault::policy::{self, ApprovedDestinations};
+use crate::vault::{DelayUnit, Vault, VaultConfig, VaultMetadata};
+
+/// A vault output to be spent, together with the tree it pays to
+///
+/// Each UTXO carries its own tree, so UTXOs from different vault indices
+/// can be spent in one transaction.
+#[derive(Debug, Clone)]
+pub struct VaultUtxo {
+    /// Outpoint of the vault output
+    pub outpoint: OutPoint,
+    /// Value of the output in satoshis
+    pub amount_sats: u64,
+    /// Script tree the output pays to
+    pub tree: VaultTree,
+}
+
+impl VaultUtxo {
+    pub fn new(outpoint: OutPoint, amount_sats: u64, tree: VaultTree) -> Self {
+        VaultUtxo {
+            outpoint,
+            amount_sats,
+            tree,
+        }
+    }
+
+    /// The output being spent, as committed to by taproot sighashes
+    pub fn txout(&self) -> TxOut {
+        TxOut {
+            value: self.amount_sats,
+            script_pubkey: self.tree.script_pubkey(),
+        }
+    }
+}
+
+/// Vault address an unvault returns its change to
+///
+/// Change goes to a fresh vault index rather than back to the spent
+/// script, so deposits aren't linked by address reuse.
+#[derive(Debug, Clone)]
+pub struct ChangeTarget {
+    /// Vault index `tree` is derived at
+    pub vault_index: u32,
+    pub tree: VaultTree,
+    /// Smallest change kept as an output, in sats; less goes to the fee
+    pub dust_threshold: u64,
+}
+
+impl ChangeTarget {
+    /// Change to `tree`, the vault's tree at `vault_index`, kept if it
+    /// clears the output's dust limit (330 sats for P2TR)
+    pub fn new(vault_index: u32, tree: VaultTree) -> Self {
+        let dust_threshold = tree.script_pubkey().dust_value().to_sat();
+        ChangeTarget {
+            vault_index,
+            tree,
+            dust_threshold,
+        }
+    }
+
+    /// Raise the threshold below which change goes to the fee
+    ///
+    /// Thresholds under the output's dust limit are ignored, as such
+    /// outputs wouldn't relay.
+    pub fn with_dust_threshold(mut self, sats: u64) -> Self {
+        self.dust_threshold = self.dust_threshold.max(sats);
+        self
+    }
+
+    /// PSBT output data marking the change as the vault's: its internal
+    /// key, and the origins of its leaf keys so signers and
+    /// `policy::check_psbt()` can re-derive it
+    fn psbt_output(&self) -> PsbtOutput {
+        let mut output = PsbtOutput {
+            tap_internal_key: Some(self.tree.internal_key()),
+            ..Default::default()
+        };
+        for leaf in self.tree.leaves() {
+            let leaf_hash = TapLeafHash::from_script(&leaf.script, leaf.version);
+            for key in script_keys(&leaf.script) {
+                if let Some(origin) = self.tree.key_origins().get(&key) {
+                    output
+                        .tap_key_origins
+                        .entry(key)
+                        .or_insert_with(|| (vec![], origin.clone()))
+                        .0
+                        .push(leaf_hash);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// What became of an unvault's change, as reported in `PsbtBundle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChangeOutcome {
+    /// The inputs paid the destination and fee exactly
+    None,
+    /// Change paid to the vault at `vault_index`, in output `vout`
+    Output { vault_index: u32, vout: u32, amount_sats: u64 },
+    /// Change below `dust_limit_sats`, the larger of the change target's
+    /// threshold and the dust policy's limit, added to the fee
+    AddedToFee { amount_sats: u64, dust_limit_sats: u64 },
+}
+
+/// An unvault PSBT with the change decision the host must persist
+#[derive(Debug, Clone)]
+pub struct PsbtBundle {
+    pub psbt: Psbt,
+    pub change: ChangeOutcome,
+}
+
+impl PsbtBundle {
+    /// Vault index that now receives funds, to be marked used by the host
+    pub fn change_index(&self) -> Option<u32> {
+        match self.change {
+            ChangeOutcome::Output { vault_index, .. } => Some(vault_index),
+            _ => None,
+        }
+    }
+}
+
+/// Optional parts of a spend, shared by the PSBT builders
+///
+/// The default sets nLockTime to 0, adds no memo or anchor output and
+/// applies each output's relay dust limit.
+#[derive(Debug, Clone, Default)]
+pub struct SpendOptions<'a> {
+    /// Approved destinations of a dual-delay vault; unvaults paying one
+    /// spend the whitelist timelock leaf. Sweeps ignore it.
+    pub approved: Option<&'a ApprovedDestinations>,
+    /// Tip height, from which nLockTime discourages fee sniping (see
+    /// `anti_fee_sniping_lock_time()`)
+    pub current_block_height: Option<u32>,
+    /// Payload of a last, zero-value OP_RETURN output (see `memo_script()`)
+    pub memo: Option<Vec<u8>>,
+    pub dust: DustPolicy,
+    /// Key of an `ANCHOR_VALUE_SATS` output for CPFP (see `anchor_script()`),
+    /// added by recovery and stage spends. Unvaults ignore it.
+    pub anchor: Option<XOnlyPublicKey>,
+}
+
+/// Build the unvault PSBT: sweep a vault UTXO to `destination` through
+/// the timelock leaf
+///
+/// The input's nSequence encodes `metadata.delay_blocks` in
+/// `metadata.delay_unit`, so the transaction is only valid once the
+/// UTXO is that old. Dual-delay vaults paying a destination in
+/// `options.approved` spend the whitelist timelock leaf instead, waiting
+/// `metadata.whitelist_delay`.
+/// The whole UTXO value minus fee goes to `destination`. A memo is paid
+/// for out of the destination's share. If the fee leaves the destination
+/// less than the dust policy allows, fails with `InsufficientFunds`.
+pub fn build_unvault(
+    utxo: VaultUtxo,
+    destination: Address,
+    fee_rate: u64,
+    metadata: &VaultMetadata,
+    options: SpendOptions,
+) -> Result<Psbt, CoreError> {
+    unvault_psbt(utxo, destination, None, fee_rate, metadata, options).map(|bundle| bundle.psbt)
+}
+
+/// Build an unvault PSBT sending `amount_sats` to `destination`
+///
+/// Like `build_unvault`, but the remainder is returned to the vault at
+/// `change`. Remainders below `change.dust_threshold` or the dust
+/// policy's limit are added to the fee instead; the bundle reports which
+/// happened. An `amount_sats` below the dust limit fails with
+/// `PolicyViolation`. A memo output follows the change output and is
+/// paid for out of the change.
+pub fn build_partial_unvault(
+    utxo: VaultUtxo,
+    destination: Address,
+    amount_sats: u64,
+    change: &ChangeTarget,
+    fee_rate: u64,
+    metadata: &VaultMetadata,
+    options: SpendOptions,
+) -> Result<PsbtBundle, CoreError> {
+    unvault_psbt(utxo, destination, Some((amount_sats, change)), fee_rate, metadata, options)
+}
+
+fn unvault_psbt(
+    utxo: VaultUtxo,
+    destination: Address,
+    payment: Option<(u64, &ChangeTarget)>,
+    fee_rate: u64,
+    metadata: &VaultMetadata,
+    options: SpendOptions,
+) -> Result<PsbtBundle, CoreError> {
+    let SpendOptions { approved, current_block_height, memo, dust, .. } = options;
+    let (leaf, sequence) = unvault_leaf(&utxo.tree, metadata, approved, &destination)?;
+    policy::check_destination(metadata, approved, &destination)?;
+    let memo = memo.as_deref().map(memo_script).transpose()?;
+
+    let input = script_path_input(&utxo, leaf)?;
+    let input_weight = fees::leaf_input_weight(&utxo.tree, leaf)?;
+    let dest_spk = destination.script_pubkey();
+    let available = utxo.amount_sats;
+    let output_lens = |spks: &[&ScriptBuf]| -> Vec<usize> {
+        spks.iter().copied().chain(&memo).map(|spk| spk.len()).collect()
+    };
+
+    let sweep_fee = fee_for_weight(fees::tx_weight(&[input_weight], &output_lens(&[&dest_spk])), fee_rate)?;
+    let mut outputs = Vec::with_capacity(2);
+    let mut change_outcome = ChangeOutcome::None;
+    match payment {
+        None => {
+            let needed = sweep_fee + dust.limit(&dest_spk);
+            if available < needed {
+                return Err(CoreError::InsufficientFunds { needed, available });
+            }
+            outputs.push(TxOut {
+                value: available - sweep_fee,
+                script_pubkey: dest_spk,
+            });
+        }
+        Some((amount, change)) => {
+            let dest_out = TxOut {
+                value: amount,
+                script_pubkey: dest_spk.clone(),
+            };
+            dust.check_output(0, &dest_out)?;
+            let needed = amount + sweep_fee;
+            if available < needed {
+                return Err(CoreError::InsufficientFunds { needed, available });
+            }
+            outputs.push(dest_out);
+
+            // Only add change if it still clears dust after paying for itself
+            let change_spk = change.tree.script_pubkey();
+            let change_fee = fee_for_weight(
+                fees::tx_weight(&[input_weight], &output_lens(&[&dest_spk, &change_spk])),
+                fee_rate,
+            )?;
+            let change_sats = available.saturating_sub(amount + change_fee);
+            let change_dust = change.dust_threshold.max(dust.limit(&change_spk));
+            change_outcome = if change_sats >= change_dust {
+                outputs.push(TxOut {
+                    value: change_sats,
+                    script_pubkey: change_spk,
+                });
+                ChangeOutcome::Output {
+                    vault_index: change.vault_index,
+                    vout: 1,
+                    amount_sats: change_sats,
+                }
+            } else {
+                leftover(available - amount - sweep_fee, change_dust)
+            };
+        }
+    }
+    push_memo(&mut outputs, memo)?;
+
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: anti_fee_sniping_lock_time(current_block_height)?,
+        input: vec![TxIn {
+            previous_output: utxo.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::default(),
+        }],
+        output: outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs[0] = input;
+    if let (ChangeOutcome::Output { .. }, Some((_, change))) = (change_outcome, payment) {
+        psbt.outputs[1] = change.psbt_output();
+    }
+    log::debug!(
+        "Built unvault {} through the {:?} leaf, change {:?}",
+        psbt.unsigned_tx.txid(),
+        leaf,
+        change_outcome
+    );
+
+    Ok(PsbtBundle {
+        psbt,
+        change: change_outcome,
+    })
+}
+
+/// Outcome for change that didn't make an output: none at all, or
+/// `sats` below `dust_limit_sats` given up to the fee
+fn leftover(sats: u64, dust_limit_sats: u64) -> ChangeOutcome {
+    match sats {
+        0 => ChangeOutcome::None,
+        amount_sats => ChangeOutcome::AddedToFee {
+            amount_sats,
+            dust_limit_sats,
+        },
+    }
+}
+
+/// OP_RETURN output script carrying `memo`, e.g. a withdrawal reference
+///
+/// Fails with `PolicyViolation` for a memo over
+/// `fees::MAX_OP_RETURN_PAYLOAD` bytes, which nodes wouldn't relay, and
+/// with `InvalidInput` for an empty one.
+fn memo_script(memo: &[u8]) -> Result<ScriptBuf, CoreError> {
+    if memo.is_empty() {
+        return Err(CoreError::InvalidInput("Memo is empty; leave it out instead".to_string()));
+    }
+    if memo.len() > fees::MAX_OP_RETURN_PAYLOAD {
+        return Err(CoreError::PolicyViolation(format!(
+            "Memo of {} bytes is over the {}-byte OP_RETURN standardness limit",
+            memo.len(),
+            fees::MAX_OP_RETURN_PAYLOAD
+        )));
+    }
+    let push = PushBytesBuf::try_from(memo.to_vec())
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid memo: {}", e)))?;
+    Ok(ScriptBuf::new_op_return(&push))
+}
+
+/// Append a zero-value output carrying the `memo` script, if any
+///
+/// A memo only tags a payment, so `outputs` must already move value.
+fn push_memo(outputs: &mut Vec<TxOut>, memo: Option<ScriptBuf>) -> Result<(), CoreError> {
+    let Some(script_pubkey) = memo else { return Ok(()) };
+    if outputs.iter().all(|output| output.value == 0) {
+        return Err(CoreError::PolicyViolation(
+            "A memo output needs an output carrying value alongside it".to_string(),
+        ));
+    }
+    outputs.push(TxOut { value: 0, script_pubkey });
+    Ok(())
+}
+
+/// Build an unvault PSBT spending the UTXOs chosen by `coins::select`
+///
+/// Every input spends the leaf `build_unvault` would pick, with nSequence
+/// encoding that leaf's delay. `selection.target_sats` goes to `destination`
+/// and the selection's change to `change`, unless it is below
+/// `change.dust_threshold` or the `dust` limit and goes to the fee; the
+/// fee is whatever else the selection left over. A target below the
+/// `dust` limit fails with `PolicyViolation`. nLockTime is set as in
+/// `build_unvault`.
+pub fn build_unvault_from_selection(
+    selection: &Selection,
+    destination: Address,
+    change: &ChangeTarget,
+    metadata: &VaultMetadata,
+    approved: Option<&ApprovedDestinations>,
+    current_block_height: Option<u32>,
+    dust: DustPolicy,
+) -> Result<PsbtBundle, CoreError> {
+    policy::check_destination(metadata, approved, &destination)?;
+    if selection.utxos.is_empty() {
+        return Err(CoreError::InvalidInput("Selection contains no vault UTXOs".to_string()));
+    }
+    let total: u64 = selection.utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    if total != selection.total_input_sats
+        || selection.target_sats + selection.fee_sats + selection.change_sats != total
+    {
+        return Err(CoreError::InvalidInput(
+            "Selection amounts don't add up to its inputs".to_string(),
+        ));
+    }
+
+    let payment = TxOut {
+        value: selection.target_sats,
+        script_pubkey: destination.script_pubkey(),
+    };
+    dust.check_output(0, &payment)?;
+
+    let mut inputs = Vec::with_capacity(selection.utxos.len());
+    let mut txins = Vec::with_capacity(selection.utxos.len());
+    for utxo in &selection.utxos {
+        let (leaf, sequence) = unvault_leaf(&utxo.tree, metadata, approved, &destination)?;
+        inputs.push(script_path_input(utxo, leaf)?);
+        txins.push(TxIn {
+            previous_output: utxo.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::default(),
+        });
+    }
+
+    let mut outputs = vec![payment];
+    let change_spk = change.tree.script_pubkey();
+    let change_dust = change.dust_threshold.max(dust.limit(&change_spk));
+    let change_outcome = if selection.change_sats >= change_dust {
+        outputs.push(TxOut {
+            value: selection.change_sats,
+            script_pubkey: change_spk,
+        });
+        ChangeOutcome::Output {
+            vault_index: change.vault_index,
+            vout: 1,
+            amount_sats: selection.change_sats,
+        }
+    } else {
+        leftover(selection.change_sats, change_dust)
+    };
+
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: anti_fee_sniping_lock_time(current_block_height)?,
+        input: txins,
+        output: outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs = inputs;
+    if let ChangeOutcome::Output { .. } = change_outcome {
+        psbt.outputs[1] = change.psbt_output();
+    }
+    log::debug!(
+        "Built unvault {} spending {} vault UTXOs, change {:?}",
+        psbt.unsigned_tx.txid(),
+        selection.utxos.len(),
+        change_outcome
+    );
+
+    Ok(PsbtBundle {
+        psbt,
+        change: change_outcome,
+    })
+}
+
+/// nLockTime for a transaction built with the tip at `current_block_height`
+///
+/// Discourages fee sniping the way Bitcoin Core does: the locktime is the
+/// current height, so the transaction can't be mined in a reorg of
+/// earlier blocks. With the `rand` feature it is, one time in ten, up to
+/// 99 blocks lower, so transactions that were signed long before their
+/// broadcast don't stand out. Every builder's sequences are below
+/// `Sequence::MAX`, so the locktime is enforced. Without a height the
+/// locktime is 0.
+fn anti_fee_sniping_lock_time(current_block_height: Option<u32>) -> Result<LockTime, CoreError> {
+    let Some(height) = current_block_height else {
+        return Ok(LockTime::ZERO);
+    };
+    let lock_time = LockTime::from_height(height)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid block height {}: {}", height, e)))?;
+    #[cfg(feature = "rand")]
+    {
+        use bitcoin::secp256k1::rand::{thread_rng, Rng};
+        let mut rng = thread_rng();
+        if rng.gen_ratio(1, 10) {
+            let earlier = height.saturating_sub(rng.gen_range(0..100));
+            return Ok(LockTime::from_height(earlier).unwrap_or(lock_time));
+        }
+    }
+    Ok(lock_time)
+}
+
+/// Delayed leaf an unvault to `destination` spends, with its nSequence
+///
+/// The whitelist timelock leaf is used when the tree has one, `metadata`
+/// carries its delay and `destination` is in `approved`; every other
+/// unvault waits the full delay through the timelock leaf.
+fn unvault_leaf(
+    tree: &VaultTree,
+    metadata: &VaultMetadata,
+    approved: Option<&ApprovedDestinations>,
+    destination: &Address,
+) -> Result<(LeafPurpose, Sequence), CoreError> {
+    let whitelisted = approved.is_some_and(|approved| approved.index_of(destination).is_some());
+    match metadata.whitelist_delay {
+        Some(delay) if whitelisted && tree.leaf(LeafPurpose::WhitelistTimelock).is_some() => {
+            let leaf = LeafPurpose::WhitelistTimelock;
+            Ok((leaf, unvault_sequence(tree, leaf, delay, DelayUnit::Blocks)?))
+        }
+        _ => {
+            let leaf = LeafPurpose::Timelock;
+            Ok((leaf, unvault_sequence(tree, leaf, metadata.delay_blocks, metadata.delay_unit)?))
+        }
+    }
+}
+
+/// nSequence for an unvault input spending `leaf` after `delay` `unit`s
+///
+/// Rejects delays outside the CSV range, and sequences that don't
+/// satisfy the leaf's own CSV delay: shorter, or in the other unit.
+fn unvault_sequence(tree: &VaultTree, leaf: LeafPurpose, delay: u32, unit: DelayUnit) -> Result<Sequence, CoreError> {
+    if delay == 0 || delay > MAX_CSV_DELAY_BLOCKS {
+        return Err(CoreError::PolicyViolation(format!(
+            "Unvault delay of {} {} is outside 1..={}",
+            delay,
+            unit.name(),
+            MAX_CSV_DELAY_BLOCKS
+        )));
+    }
+    let sequence = unit.sequence(delay)?;
+
+    let leaf = tree
+        .leaf(leaf)
+        .ok_or_else(|| CoreError::PsbtError(format!("Vault tree has no {:?} leaf", leaf)))?;
+    let leaf_lock = taproot::leaf_csv_delay(&leaf.script)
+        .and_then(|leaf_delay| Sequence::from_consensus(leaf_delay).to_relative_lock_time());
+    if let Some(leaf_lock) = leaf_lock {
+        let satisfied = sequence
+            .to_relative_lock_time()
+            .is_some_and(|lock| leaf_lock.is_implied_by(lock));
+        if !satisfied {
+            return Err(CoreError::PolicyViolation(format!(
+                "Unvault delay of {} {} doesn't satisfy the leaf's CSV delay of {}",
+                delay,
+                unit.name(),
+                describe_lock(leaf_lock)
+            )));
+        }
+    }
+
+    Ok(sequence)
+}
+
+/// A relative lock in words, e.g. "144 blocks"
+pub(crate) fn describe_lock(lock: relative::LockTime) -> String {
+    match lock {
+        relative::LockTime::Blocks(height) => format!("{} {}", height.value(), DelayUnit::Blocks.name()),
+        relative::LockTime::Time(time) => format!("{} {}", time.value(), DelayUnit::TimeUnits512s.name()),
+    }
+}
+
+/// Leaves `build_recovery()` can spend, in the order it picks them
+pub const RECOVERY_LEAVES: [LeafPurpose; 3] = [LeafPurpose::Emergency, LeafPurpose::Hashlock, LeafPurpose::AbsoluteLock];
+
+/// Build the recovery PSBT: sweep vault UTXOs to `cold_address` through
+/// the emergency leaf, or another of `RECOVERY_LEAVES`
+///
+/// With `leaf` set, every UTXO is spent through that leaf, failing with
+/// `PolicyViolation` for a UTXO whose tree has none. Without it, each
+/// UTXO takes the first of `RECOVERY_LEAVES` its tree has, so a tree
+/// with both an emergency and a hashlock leaf is swept through the
+/// emergency leaf unless `leaf` asks for the hashlock. The emergency and
+/// hashlock leaves have no timelock, so the transaction is valid
+/// immediately, though the hashlock leaf still needs the preimage (see
+/// `attach_preimage()`); the absolute lock leaf raises nLockTime to the
+/// lock (see `recovery_lock_time()`). Every UTXO is spent in one
+/// transaction with its own leaf data, so UTXOs from different vault
+/// indices can be mixed. The entire value minus fee goes to
+/// `cold_address`; there is no change. nLockTime, the memo and the dust
+/// limit are as in `build_unvault`.
+///
+/// With an `options.anchor` key, the anchor output follows the cold
+/// output, so a recovery signed long ago at a stale fee rate can still be
+/// bumped with `build_cpfp()`.
+pub fn build_recovery(
+    utxos: &[VaultUtxo],
+    leaf: Option<LeafPurpose>,
+    cold_address: Address,
+    fee_rate: u64,
+    options: SpendOptions,
+) -> Result<Psbt, CoreError> {
+    if utxos.is_empty() {
+        return Err(CoreError::InvalidInput(
+            "Recovery needs at least one vault UTXO".to_string(),
+        ));
+    }
+    if let Some(purpose) = leaf.filter(|purpose| !RECOVERY_LEAVES.contains(purpose)) {
+        return Err(CoreError::InvalidInput(format!("{:?} is not a recovery leaf", purpose)));
+    }
+    let candidates = match &leaf {
+        Some(purpose) => std::slice::from_ref(purpose),
+        None => &RECOVERY_LEAVES[..],
+    };
+
+    let mut leaves = Vec::with_capacity(utxos.len());
+    let mut absolute_locks = Vec::new();
+    for (i, utxo) in utxos.iter().enumerate() {
+        let leaf = candidates
+            .iter()
+            .find_map(|&purpose| utxo.tree.leaf(purpose))
+            .ok_or_else(|| match leaf {
+                Some(purpose) => CoreError::PolicyViolation(format!(
+                    "Input {} ({}) has no {:?} leaf",
+                    i, utxo.outpoint, purpose
+                )),
+                None => CoreError::PolicyViolation(format!(
+                    "Input {} ({}) has no emergency recovery leaf",
+                    i, utxo.outpoint
+                )),
+            })?;
+        if leaf.purpose == LeafPurpose::AbsoluteLock {
+            let lock = taproot::leaf_cltv_lock(&leaf.script).ok_or_else(|| {
+                CoreError::ScriptError(format!("Input {}'s absolute lock leaf has no CLTV lock", i))
+            })?;
+            absolute_locks.push(lock);
+        }
+        leaves.push((leaf.purpose, Sequence::ENABLE_RBF_NO_LOCKTIME));
+    }
+    let lock_time = recovery_lock_time(&absolute_locks, options.current_block_height)?;
+
+    let psbt = sweep(utxos, &leaves, lock_time, cold_address, fee_rate, options)?;
+    let available: u64 = utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    log::debug!("Built recovery {} sweeping {} sats", psbt.unsigned_tx.txid(), available);
+    Ok(psbt)
+}
+
+/// Build a PSBT spending vault UTXOs through one stage of a degrading tree
+///
+/// Every input spends the `DegradingStage(stage)` leaf of its tree, with
+/// nSequence set to the stage's CSV delay, or no relative lock for a
+/// stage with delay 0. As in `build_recovery`, the entire value minus fee
+/// goes to `destination`, UTXOs from different vault indices can be
+/// mixed, and `options` add the same outputs. nLockTime is set as in
+/// `build_unvault`.
+///
+/// A UTXO whose tree has no such stage fails with `PolicyViolation`.
+pub fn build_stage_spend(
+    utxos: &[VaultUtxo],
+    stage: usize,
+    destination: Address,
+    fee_rate: u64,
+    options: SpendOptions,
+) -> Result<Psbt, CoreError> {
+    if utxos.is_empty() {
+        return Err(CoreError::InvalidInput(
+            "Stage spend needs at least one vault UTXO".to_string(),
+        ));
+    }
+    let purpose = u8::try_from(stage)
+        .map(LeafPurpose::DegradingStage)
+        .map_err(|_| CoreError::InvalidInput(format!("Invalid degrading stage {}", stage)))?;
+
+    let mut leaves = Vec::with_capacity(utxos.len());
+    for (i, utxo) in utxos.iter().enumerate() {
+        let leaf = utxo.tree.leaf(purpose).ok_or_else(|| {
+            CoreError::PolicyViolation(format!(
+                "Input {} ({}) has no degrading stage {} leaf",
+                i, utxo.outpoint, stage
+            ))
+        })?;
+        let sequence = taproot::leaf_csv_delay(&leaf.script)
+            .map_or(Sequence::ENABLE_RBF_NO_LOCKTIME, Sequence::from_consensus);
+        leaves.push((purpose, sequence));
+    }
+    let lock_time = anti_fee_sniping_lock_time(options.current_block_height)?;
+
+    let psbt = sweep(utxos, &leaves, lock_time, destination, fee_rate, options)?;
+    let available: u64 = utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    log::debug!("Built stage {} spend {} sweeping {} sats", stage, psbt.unsigned_tx.txid(), available);
+    Ok(psbt)
+}
+
+/// Sweep `utxos` to `destination`, input `i` spending leaf `leaves[i].0`
+/// with nSequence `leaves[i].1`; `options.current_block_height` is
+/// already in `lock_time`
+fn sweep(
+    utxos: &[VaultUtxo],
+    leaves: &[(LeafPurpose, Sequence)],
+    lock_time: LockTime,
+    destination: Address,
+    fee_rate: u64,
+    options: SpendOptions,
+) -> Result<Psbt, CoreError> {
+    let SpendOptions { memo, dust, anchor, .. } = options;
+    let memo = memo.as_deref().map(memo_script).transpose()?;
+
+    let mut inputs = Vec::with_capacity(utxos.len());
+    let mut input_weights = Vec::with_capacity(utxos.len());
+    for (utxo, &(leaf, _)) in utxos.iter().zip(leaves) {
+        inputs.push(script_path_input(utxo, leaf)?);
+        input_weights.push(fees::leaf_input_weight(&utxo.tree, leaf)?);
+    }
+
+    let destination_spk = destination.script_pubkey();
+    let anchor = anchor.map(|key| TxOut {
+        value: ANCHOR_VALUE_SATS,
+        script_pubkey: anchor_script(key),
+    });
+    let anchor_sats = anchor.as_ref().map_or(0, |txout| txout.value);
+    let available: u64 = utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    let output_lens: Vec<usize> = std::iter::once(&destination_spk)
+        .chain(anchor.as_ref().map(|txout| &txout.script_pubkey))
+        .chain(&memo)
+        .map(|spk| spk.len())
+        .collect();
+    let fee = fee_for_weight(fees::tx_weight(&input_weights, &output_lens), fee_rate)?;
+    let needed = fee + anchor_sats + dust.limit(&destination_spk);
+    if available < needed {
+        return Err(CoreError::InsufficientFunds { needed, available });
+    }
+    let mut outputs = vec![TxOut {
+        value: available - fee - anchor_sats,
+        script_pubkey: destination_spk,
+    }];
+    outputs.extend(anchor);
+    push_memo(&mut outputs, memo)?;
+
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time,
+        input: utxos
+            .iter()
+            .zip(leaves)
+            .map(|(utxo, &(_, sequence))| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::default(),
+            })
+            .collect(),
+        output: outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs = inputs;
+    Ok(psbt)
+}
+
+/// nLockTime for a recovery spending absolute lock leaves with `locks`
+///
+/// Without locks this is `anti_fee_sniping_lock_time()`. A block height
+/// lock raises it to the latest lock, and fails with `TimelockNotExpired`
+/// while `current_block_height` is below that. Timestamp locks can't be
+/// checked against a height: nLockTime is the latest timestamp, and
+/// nodes refuse the transaction until median time past passes it. A
+/// transaction has one nLockTime, so mixing the two fails with
+/// `PolicyViolation`.
+fn recovery_lock_time(locks: &[LockTime], current_block_height: Option<u32>) -> Result<LockTime, CoreError> {
+    let anti_fee_sniping = anti_fee_sniping_lock_time(current_block_height)?;
+    let Some(&latest) = locks.iter().max_by_key(|lock| lock.to_consensus_u32()) else {
+        return Ok(anti_fee_sniping);
+    };
+    if locks.iter().any(|lock| !lock.is_same_unit(latest)) {
+        return Err(CoreError::PolicyViolation(
+            "Recovery inputs mix block height and timestamp absolute locks".to_string(),
+        ));
+    }
+
+    match latest {
+        LockTime::Blocks(height) => {
+            let required = height.to_consensus_u32();
+            match current_block_height {
+                Some(current) if current < required => Err(CoreError::TimelockNotExpired { required, current }),
+                // Anti-fee-sniping may pick a height below the lock
+                _ => Ok(LockTime::from_consensus(required.max(anti_fee_sniping.to_consensus_u32()))),
+            }
+        }
+        LockTime::Seconds(_) => Ok(latest),
+    }
+}
+
+/// Value of the anchor output `build_recovery()` adds, the P2TR dust limit
+pub const ANCHOR_VALUE_SATS: u64 = 330;
+
+/// ScriptPubKey of an anchor output to `anchor_key`: a P2TR output with
+/// the key as internal key and no script tree
+pub fn anchor_script(anchor_key: XOnlyPublicKey) -> ScriptBuf {
+    ScriptBuf::new_v1_p2tr(&Secp256k1::verification_only(), anchor_key, None)
+}
+
+/// A non-vault P2TR output that can add funds to a CPFP child
+#[derive(Debug, Clone)]
+pub struct WalletUtxo {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+}
+
+/// Build a child transaction bumping `parent` through its anchor output
+///
+/// The child spends output `anchor_index` of `parent`, and any
+/// `extra_inputs`, to one output back to `anchor_script()` of
+/// `anchor_key`. `parent` must be the finalized transaction, as
+/// broadcast: the package is sized with its witnesses. Its fee brings
+/// the package of `parent` (which pays `parent_fee_sats`) and the child up to
+/// `target_fee_rate` sat/vB, and never leaves the child alone below that
+/// rate. Extra inputs are costed as key-path spends.
+///
+/// The anchor input is signed with `anchor_key` as a key-path spend;
+/// extra inputs are left for the wallet that holds them to sign.
+///
+/// Errors with `InvalidInput` if an input of `parent` has no witness, the
+/// anchor output doesn't exist or doesn't pay `anchor_key`, or an extra
+/// input isn't P2TR, and with `InsufficientFunds` if the inputs can't
+/// cover the fee and a change output above the dust limit.
+pub fn build_cpfp(
+    parent: &Transaction,
+    parent_fee_sats: u64,
+    anchor_index: u32,
+    anchor_key: &keys::SecretMaterial,
+    extra_inputs: &[WalletUtxo],
+    target_fee_rate: u64,
+) -> Result<Psbt, CoreError> {
+    if let Some(i) = parent.input.iter().position(|txin| txin.witness.is_empty()) {
+        return Err(CoreError::InvalidInput(format!(
+            "Parent input {} has no witness; pass the finalized parent to size the package",
+            i
+        )));
+    }
+    let anchor_pubkey = anchor_key.keypair().x_only_public_key().0;
+    let anchor_spk = anchor_script(anchor_pubkey);
+    let anchor_txout = parent
+        .output
+        .get(anchor_index as usize)
+        .ok_or_else(|| {
+            CoreError::InvalidInput(format!(
+                "Parent has {} outputs, no anchor at index {}",
+                parent.output.len(),
+                anchor_index
+            ))
+        })?
+        .clone();
+    if anchor_txout.script_pubkey != anchor_spk {
+        return Err(CoreError::InvalidInput(format!(
+            "Output {} of the parent does not pay the anchor key",
+            anchor_index
+        )));
+    }
+    if let Some(utxo) = extra_inputs.iter().find(|utxo| !utxo.txout.script_pubkey.is_v1_p2tr()) {
+        return Err(CoreError::InvalidInput(format!(
+            "Extra input {} is not a P2TR output",
+            utxo.outpoint
+        )));
+    }
+
+    let input_weights = vec![fees::input_weight(fees::SpendPath::KeyPath, 0); 1 + extra_inputs.len()];
+    let child_vsize = fees::weight_to_vsize(fees::tx_weight(&input_weights, &[anchor_spk.len()]));
+    let package_vsize = parent.vsize() as u64 + child_vsize;
+    let package_fee = fee_for_weight(package_vsize as usize * 4, target_fee_rate)?;
+    let fee = package_fee
+        .saturating_sub(parent_fee_sats)
+        .max(child_vsize * target_fee_rate);
+
+    let available = anchor_txout.value + extra_inputs.iter().map(|utxo| utxo.txout.value).sum::<u64>();
+    let needed = fee + anchor_spk.dust_value().to_sat();
+    if available < needed {
+        return Err(CoreError::InsufficientFunds { needed, available });
+    }
+
+    let anchor_outpoint = OutPoint::new(parent.txid(), anchor_index);
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: std::iter::once(anchor_outpoint)
+            .chain(extra_inputs.iter().map(|utxo| utxo.outpoint))
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: available - fee,
+            script_pubkey: anchor_spk,
+        }],
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs[0] = PsbtInput {
+        witness_utxo: Some(anchor_txout),
+        tap_internal_key: Some(anchor_pubkey),
+        tap_key_origins: [(anchor_pubkey, (vec![], (anchor_key.fingerprint(), DerivationPath::master())))].into(),
+        ..Default::default()
+    };
+    for (input, utxo) in psbt.inputs[1..].iter_mut().zip(extra_inputs) {
+        input.witness_utxo = Some(utxo.txout.clone());
+    }
+    sign_key_path(&mut psbt, anchor_key)?;
+    log::debug!(
+        "Built CPFP child {} paying {} sats for {} vB package",
+        psbt.unsigned_tx.txid(),
+        fee,
+        package_vsize
+    );
+
+    Ok(psbt)
+}
+
+/// A P2WPKH or P2TR output of the user's wallet funding a deposit
+#[derive(Debug, Clone)]
+pub struct ExternalUtxo {
+    pub outpoint: OutPoint,
+    pub amount_sats: u64,
+    pub script_pubkey: ScriptBuf,
+    /// Key the output pays to, with its BIP32 origin in the wallet:
+    /// the P2WPKH key, or the P2TR internal key. Lets the wallet find
+    /// the key to sign with.
+    pub key_origin: Option<(PublicKey, KeySource)>,
+}
+
+impl ExternalUtxo {
+    /// PSBT input data for signing this output: its witness UTXO, and
+    /// the key origin as BIP32 derivation (P2WPKH) or tap key origin
+    /// (P2TR)
+    fn psbt_input(&self) -> Result<PsbtInput, CoreError> {
+        let mut input = PsbtInput {
+            witness_utxo: Some(TxOut {
+                value: self.amount_sats,
+                script_pubkey: self.script_pubkey.clone(),
+            }),
+            ..Default::default()
+        };
+        let Some((pubkey, origin)) = &self.key_origin else {
+            return Ok(input);
+        };
+
+        let pays_key = if self.script_pubkey.is_v0_p2wpkh() {
+            input.bip32_derivation.insert(*pubkey, origin.clone());
+            bitcoin::PublicKey::new(*pubkey)
+                .wpubkey_hash()
+                .is_some_and(|hash| ScriptBuf::new_v0_p2wpkh(&hash) == self.script_pubkey)
+        } else {
+            let internal_key = pubkey.x_only_public_key().0;
+            input.tap_internal_key = Some(internal_key);
+            input.tap_key_origins.insert(internal_key, (vec![], origin.clone()));
+            ScriptBuf::new_v1_p2tr(&Secp256k1::verification_only(), internal_key, None) == self.script_pubkey
+        };
+        if !pays_key {
+            return Err(CoreError::InvalidInput(format!(
+                "Wallet output {} does not pay key {}",
+                self.outpoint, pubkey
+            )));
+        }
+        Ok(input)
+    }
+}
+
+/// Build a PSBT funding the vault from the user's wallet
+///
+/// Spends every one of `inputs`, paying `amount_sats` to
+/// `vault_address` and the rest, less the fee, to `change_address`.
+/// Change below its output's dust limit is added to the fee instead.
+/// Inputs are costed by type (see `fees::wallet_input_weight()`), and
+/// carry their witness UTXO and key origin, so the PSBT can be imported
+/// into the wallet (Sparrow, Bitcoin Core) that signs it.
+///
+/// Errors with `InvalidInput` for an empty input list, an input that
+/// isn't P2WPKH or P2TR, or a key origin that doesn't match its output,
+/// with `PolicyViolation` if `amount_sats` is below the vault output's
+/// dust limit, and with `InsufficientFunds` if the inputs can't cover
+/// the amount and fee.
+pub fn build_deposit(
+    inputs: &[ExternalUtxo],
+    vault_address: Address,
+    amount_sats: u64,
+    change_address: Address,
+    fee_rate: u64,
+) -> Result<Psbt, CoreError> {
+    if inputs.is_empty() {
+        return Err(CoreError::InvalidInput("No wallet UTXOs to deposit".to_string()));
+    }
+    let input_weights = inputs
+        .iter()
+        .map(|utxo| fees::wallet_input_weight(&utxo.script_pubkey))
+        .collect::<Result<Vec<_>, _>>()?;
+    let psbt_inputs = inputs.iter().map(ExternalUtxo::psbt_input).collect::<Result<Vec<_>, _>>()?;
+
+    let vault_out = TxOut {
+        value: amount_sats,
+        script_pubkey: vault_address.script_pubkey(),
+    };
+    DustPolicy::Relay.check_output(0, &vault_out)?;
+    let change_spk = change_address.script_pubkey();
+
+    let available: u64 = inputs.iter().map(|utxo| utxo.amount_sats).sum();
+    let fee = fee_for_weight(fees::tx_weight(&input_weights, &[vault_out.script_pubkey.len()]), fee_rate)?;
+    let needed = amount_sats + fee;
+    if available < needed {
+        return Err(CoreError::InsufficientFunds { needed, available });
+    }
+    let change_fee = fee_for_weight(
+        fees::tx_weight(&input_weights, &[vault_out.script_pubkey.len(), change_spk.len()]),
+        fee_rate,
+    )?;
+    let change_sats = available.saturating_sub(amount_sats + change_fee);
+
+    let mut outputs = vec![vault_out];
+    if change_sats >= change_spk.dust_value().to_sat() {
+        outputs.push(TxOut {
+            value: change_sats,
+            script_pubkey: change_spk,
+        });
+    }
+
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::default(),
+            })
+            .collect(),
+        output: outputs,
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs = psbt_inputs;
+    log::debug!(
+        "Built deposit {} of {} sats from {} wallet inputs",
+        psbt.unsigned_tx.txid(),
+        amount_sats,
+        inputs.len()
+    );
+
+    Ok(psbt)
+}
+
+/// Share of the consolidated value, in percent, above which
+/// `build_consolidation` warns about the fee
+pub const DEFAULT_CONSOLIDATION_FEE_WARNING_PERCENT: u64 = 5;
+
+/// A consolidation PSBT with its fee, for the host to confirm
+#[derive(Debug, Clone)]
+pub struct Consolidation {
+    pub psbt: Psbt,
+    /// Vault index the single output pays to, to be marked used by the host
+    pub vault_index: u32,
+    /// Sum of the spent UTXOs
+    pub total_input_sats: u64,
+    pub fee_sats: u64,
+    /// Set when the fee is more than the warning share of
+    /// `total_input_sats`: consolidating now may cost more than it saves
+    pub fee_warning: Option<String>,
+}
+
+/// Build a consolidation PSBT: sweep every UTXO in `utxos` into one output
+/// at `vault`'s address at `target_index`
+///
+/// Each input takes the cheapest spend the vault allows its owner: the key
+/// path when the template enables it or the vault has a MuSig2 internal
+/// key, and otherwise the timelock leaf, with nSequence encoding the
+/// template's delay. Pass an index the host hasn't handed out yet; one
+/// that receives any of the spent UTXOs is rejected. nLockTime is 0.
+/// With `vault.psbt_vault_info()`, the PSBT carries `attach_vault_info()`
+/// records.
+///
+/// The fee is reported, with a warning when it exceeds
+/// `DEFAULT_CONSOLIDATION_FEE_WARNING_PERCENT` of the swept value; see
+/// `build_consolidation_with_fee_warning` to choose the share. Errors with
+/// `InvalidInput` for no UTXOs and `InsufficientFunds` when the fee
+/// leaves less than `vault`'s dust limit.
+pub fn build_consolidation(
+    utxos: &[VaultUtxo],
+    target_index: u32,
+    fee_rate: u64,
+    vault: &Vault,
+) -> Result<Consolidation, CoreError> {
+    build_consolidation_with_fee_warning(
+        utxos,
+        target_index,
+        fee_rate,
+        vault,
+        DEFAULT_CONSOLIDATION_FEE_WARNING_PERCENT,
+    )
+}
+
+/// Build a consolidation PSBT as `build_consolidation` does, warning when
+/// the fee exceeds `warning_percent` of the swept value
+pub fn build_consolidation_with_fee_warning(
+    utxos: &[VaultUtxo],
+    target_index: u32,
+    fee_rate: u64,
+    vault: &Vault,
+    warning_percent: u64,
+) -> Result<Consolidation, CoreError> {
+    if utxos.is_empty() {
+        return Err(CoreError::InvalidInput(
+            "Consolidation needs at least one vault UTXO".to_string(),
+        ));
+    }
+    let target = vault.change_target(target_index)?;
+    let target_spk = target.tree.script_pubkey();
+    if let Some(i) = utxos.iter().position(|utxo| utxo.tree.script_pubkey() == target_spk) {
+        return Err(CoreError::InvalidInput(format!(
+            "Input {} ({}) already pays to vault index {}; consolidate to a fresh index",
+            i, utxos[i].outpoint, target_index
+        )));
+    }
+
+    let key_path = vault.metadata().key_path_enabled;
+    let template = vault.template();
+    let mut inputs = Vec::with_capacity(utxos.len());
+    let mut txins = Vec::with_capacity(utxos.len());
+    let mut input_weights = Vec::with_capacity(utxos.len());
+    for utxo in utxos {
+        let sequence = if key_path {
+            inputs.push(key_path_input(utxo));
+            input_weights.push(fees::input_weight(fees::SpendPath::KeyPath, 0));
+            Sequence::ENABLE_RBF_NO_LOCKTIME
+        } else {
+            let leaf = LeafPurpose::Timelock;
+            inputs.push(script_path_input(utxo, leaf)?);
+            input_weights.push(fees::leaf_input_weight(&utxo.tree, leaf)?);
+            unvault_sequence(&utxo.tree, leaf, template.delay_blocks(), template.delay_unit())?
+        };
+        txins.push(TxIn {
+            previous_output: utxo.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::default(),
+        });
+    }
+
+    let weight = fees::tx_weight(&input_weights, &[target_spk.len()]);
+    if weight > fees::MAX_STANDARD_TX_WEIGHT {
+        return Err(CoreError::PolicyViolation(format!(
+            "Consolidating {} UTXOs weighs {} WU, above the standard limit of {}; split them up",
+            utxos.len(),
+            weight,
+            fees::MAX_STANDARD_TX_WEIGHT
+        )));
+    }
+    let total_input_sats: u64 = utxos.iter().map(|utxo| utxo.amount_sats).sum();
+    let fee_sats = fee_for_weight(weight, fee_rate)?;
+    let needed = fee_sats + vault.dust_policy().limit(&target_spk);
+    if total_input_sats < needed {
+        return Err(CoreError::InsufficientFunds {
+            needed,
+            available: total_input_sats,
+        });
+    }
+
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: txins,
+        output: vec![TxOut {
+            value: total_input_sats - fee_sats,
+            script_pubkey: target_spk,
+        }],
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs = inputs;
+    psbt.outputs[0] = target.psbt_output();
+    if vault.psbt_vault_info() {
+        let spend_path = built_spend_path(&psbt)?;
+        attach_vault_info(&mut psbt, &vault.metadata(), spend_path)?;
+    }
+
+    let fee_warning = (u128::from(fee_sats) * 100 > u128::from(total_input_sats) * u128::from(warning_percent))
+        .then(|| {
+            format!(
+                "Fee of {} sats is more than {}% of the {} sats consolidated",
+                fee_sats, warning_percent, total_input_sats
+            )
+        });
+    log::debug!(
+        "Built consolidation {} of {} vault UTXOs into index {}",
+        psbt.unsigned_tx.txid(),
+        utxos.len(),
+        target_index
+    );
+
+    Ok(Consolidation {
+        psbt,
+        vault_index: target_index,
+        total_input_sats,
+        fee_sats,
+        fee_warning,
+    })
+}
+
+/// Rebuild a vault PSBT at a higher fee rate to replace it via RBF
+///
+/// Inputs and sequences are kept. The extra fee comes out of the change
+/// output (an output paying back to one of the spent vault scripts, or
+/// marked with a `tap_internal_key` as `ChangeTarget` outputs are); if
+/// that would leave less than the `dust` limit, the change is dropped to
+/// fees. Without change, the destination output pays, failing with
+/// `InsufficientFunds` rather than go below the limit. Memo outputs and
+/// `build_recovery()` anchor outputs are left untouched. All signatures
+/// are stripped, so the result must be signed again; global proprietary
+/// keys, such as `attach_vault_info()` records, are kept.
+///
+/// The new fee must exceed the original by at least
+/// `fees::INCREMENTAL_RELAY_FEE_RATE` per vbyte of the replacement.
+pub fn bump_fee(original: &Psbt, new_fee_rate: u64, dust: DustPolicy) -> Result<Psbt, CoreError> {
+    let mut input_weights = Vec::with_capacity(original.inputs.len());
+    let mut spent_scripts = Vec::with_capacity(original.inputs.len());
+    let mut available = 0u64;
+    for (i, (input, txin)) in original.inputs.iter().zip(&original.unsigned_tx.input).enumerate() {
+        if !txin.sequence.is_rbf() {
+            return Err(CoreError::PolicyViolation(format!(
+                "Input {} does not signal replaceability",
+                i
+            )));
+        }
+        let prevout = input
+            .witness_utxo
+            .as_ref()
+            .ok_or_else(|| CoreError::PsbtError(format!("Input {} is missing witness_utxo", i)))?;
+        let (control_block, (script, _)) = input.tap_scripts.iter().next().ok_or_else(|| {
+            CoreError::PsbtError(format!("Input {} has no leaf script to size the replacement", i))
+        })?;
+
+        input_weights.push(fees::script_input_weight(script, control_block.size())?);
+        spent_scripts.push(&prevout.script_pubkey);
+        available += prevout.value;
+    }
+
+    let outputs = &original.unsigned_tx.output;
+    let original_fee = available
+        .checked_sub(outputs.iter().map(|out| out.value).sum())
+        .ok_or_else(|| CoreError::PsbtError("Outputs exceed inputs".to_string()))?;
+
+    // Change pays back to a spent script, or to a fresh vault index the
+    // builder marked with its internal key
+    let is_change = |i: usize| {
+        spent_scripts.contains(&&outputs[i].script_pubkey)
+            || original.outputs.get(i).is_some_and(|output| output.tap_internal_key.is_some())
+    };
+    // Memo outputs carry no value, and anchor outputs only the P2TR dust
+    // limit; both are kept as they are
+    let is_anchor = |i: usize| outputs[i].value == ANCHOR_VALUE_SATS && outputs[i].script_pubkey.is_v1_p2tr();
+    let destinations: Vec<usize> = (0..outputs.len())
+        .filter(|&i| !is_change(i) && !outputs[i].script_pubkey.is_op_return() && !is_anchor(i))
+        .collect();
+    let change = (0..outputs.len()).find(|&i| is_change(i));
+    let destination = match destinations.as_slice() {
+        [destination] => *destination,
+        _ => {
+            return Err(CoreError::InvalidInput(format!(
+                "Fee bumping needs exactly one destination output, found {}",
+                destinations.len()
+            )))
+        }
+    };
+
+    // Size the replacement with and without change before picking one
+    let output_lens = |with_change: bool| -> Vec<usize> {
+        (0..outputs.len())
+            .filter(|&i| with_change || Some(i) != change)
+            .map(|i| outputs[i].script_pubkey.len())
+            .collect()
+    };
+    let required_fee = |weight: usize| -> Result<u64, CoreError> {
+        let vsize = fees::weight_to_vsize(weight);
+        let fee = fees::estimate_fee(vsize, new_fee_rate)?;
+        let min_fee = original_fee + vsize * fees::INCREMENTAL_RELAY_FEE_RATE;
+        if fee < min_fee {
+            return Err(CoreError::PolicyViolation(format!(
+                "New fee of {} sats must be at least {} sats to replace the original fee of {} sats",
+                fee, min_fee, original_fee
+            )));
+        }
+        Ok(fee)
+    };
+
+    let mut new_outputs = outputs.clone();
+    let mut psbt_outputs = original.outputs.clone();
+    let mut shortfall = required_fee(fees::tx_weight(&input_weights, &output_lens(true)))? - original_fee;
+    let mut drop_change = false;
+    if let Some(change) = change {
+        let change_out = &mut new_outputs[change];
+        let remaining = change_out.value.saturating_sub(shortfall);
+        if remaining >= dust.limit(&change_out.script_pubkey) {
+            change_out.value = remaining;
+            shortfall = 0;
+        } else {
+            // Dust change goes to fees; the smaller transaction may need less
+            let fee = required_fee(fees::tx_weight(&input_weights, &output_lens(false)))?;
+            shortfall = fee.saturating_sub(original_fee + change_out.value);
+            drop_change = true;
+        }
+    }
+
+    let dest_out = &mut new_outputs[destination];
+    let dest_dust = dust.limit(&dest_out.script_pubkey);
+    if dest_out.value < shortfall + dest_dust {
+        return Err(CoreError::InsufficientFunds {
+            needed: available - dest_out.value + shortfall + dest_dust,
+            available,
+        });
+    }
+    dest_out.value -= shortfall;
+
+    if let (Some(change), true) = (change, drop_change) {
+        new_outputs.remove(change);
+        psbt_outputs.remove(change);
+    }
+
+    let unsigned_tx = Transaction {
+        output: new_outputs,
+        ..original.unsigned_tx.clone()
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to create PSBT: {}", e)))?;
+    psbt.inputs = original
+        .inputs
+        .iter()
+        .map(|input| PsbtInput {
+            tap_key_sig: None,
+            tap_script_sigs: Default::default(),
+            partial_sigs: Default::default(),
+            final_script_sig: None,
+            final_script_witness: None,
+            ..input.clone()
+        })
+        .collect();
+    psbt.outputs = psbt_outputs;
+    psbt.proprietary = original.proprietary.clone();
+    log::debug!(
+        "Bumped {} to {} at {} sat/vB",
+        original.unsigned_tx.txid(),
+        psbt.unsigned_tx.txid(),
+        new_fee_rate
+    );
+
+    Ok(psbt)
+}
+
+/// Sign a vault PSBT's script-path inputs with `xpriv` once its inputs
+/// check out
+///
+/// Runs `policy::verify_prevouts()`, scanning indices up to `max_index`,
+/// before `keys::sign_psbt()`; nothing is signed if any input fails.
+/// Returns the number of signatures added.
+pub fn sign_verified(
+    psbt: &mut Psbt,
+    xpriv: &keys::SecretMaterial,
+    vault: &VaultConfig,
+    max_index: u32,
+) -> Result<usize, CoreError> {
+    policy::verify_prevouts(psbt, vault, max_index)?;
+    keys::sign_psbt(psbt, xpriv, vault.network)
+}
+
+/// Sign the key-path spend of every input whose internal key `xpriv` holds
+///
+/// The internal key's origin is read from `tap_key_origins`: vaults with
+/// key-path spends use the owner key as internal key, and their leaves
+/// list it too. Inputs whose internal key has an origin with `xpriv`'s
+/// fingerprint get the key derived along that path, tweaked by
+/// `tap_merkle_root` (see `keys::tweaked_keypair()`), and a BIP341
+/// key-spend signature in `tap_key_sig`. Other inputs are skipped.
+///
+/// Returns the number of inputs signed. Errors with `SigningError` for
+/// an input whose internal key is the vault's NUMS key, which nobody can
+/// sign for, for a tweaked key that doesn't match the spent output, and
+/// if `xpriv` signs no input.
+pub fn sign_key_path(psbt: &mut Psbt, xpriv: &keys::SecretMaterial) -> Result<usize, CoreError> {
+    let secp = Secp256k1::new();
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                CoreError::PsbtError(format!("Input {} is missing its witness UTXO", i))
+            })
+        })
+        .collect::<Result<Vec<TxOut>, CoreError>>()?;
+    let fingerprint = xpriv.fingerprint();
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut signed = 0;
+
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        let Some(internal_key) = input.tap_internal_key else { continue };
+        if is_nums_internal_key(input, &internal_key) {
+            return Err(CoreError::SigningError {
+                input_index: i,
+                reason: "Internal key is the vault's unspendable NUMS key; the vault is script-path only"
+                    .to_string(),
+            });
+        }
+        let Some((_, (key_fingerprint, path))) = input.tap_key_origins.get(&internal_key) else { continue };
+        if *key_fingerprint != fingerprint {
+            continue;
+        }
+
+        let keypair = keys::tweaked_keypair(xpriv, path, input.tap_merkle_root)?.keypair();
+        if p2tr_output_key(&prevouts[i]) != Some(keypair.x_only_public_key().0) {
+            return Err(CoreError::SigningError {
+                input_index: i,
+                reason: "Tweaked internal key does not match the spent output key".to_string(),
+            });
+        }
+
+        let hash_ty = input
+            .taproot_hash_ty()
+            .map_err(|e| CoreError::PsbtError(format!("Input {} has an invalid sighash type: {}", i, e)))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(i, &Prevouts::All(&prevouts), hash_ty)
+            .map_err(|e| CoreError::SigningError {
+                input_index: i,
+                reason: format!("Sighash failed: {}", e),
+            })?;
+        let msg = Message::from_slice(sighash.as_ref()).map_err(|e| CoreError::SigningError {
+            input_index: i,
+            reason: format!("Invalid sighash: {}", e),
+        })?;
+        input.tap_key_sig = Some(bitcoin::taproot::Signature {
+            sig: secp.sign_schnorr(&msg, &keypair),
+            hash_ty,
+        });
+        signed += 1;
+    }
+
+    if signed == 0 {
+        return Err(CoreError::SigningError {
+            input_index: 0,
+            reason: format!("Key {} does not hold the internal key of any input", fingerprint),
+        });
+    }
+    Ok(signed)
+}
+
+/// Whether `internal_key` is `keys::unspendable_internal_key()`, or
+/// `taproot::nums_internal_key()` at the vault index of the input's leaf
+/// keys (the last step of their origin paths)
+fn is_nums_internal_key(input: &PsbtInput, internal_key: &XOnlyPublicKey) -> bool {
+    if *internal_key == keys::unspendable_internal_key() {
+        return true;
+    }
+    input.tap_key_origins.values().any(|(_, (_, path))| match path.as_ref().last() {
+        Some(ChildNumber::Normal { index }) => taproot::nums_internal_key(*index).ok() == Some(*internal_key),
+        _ => false,
+    })
+}
+
+/// A digest an external signer must sign for one input of a PSBT
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SighashInfo {
+    pub input_index: usize,
+    /// BIP341 signature message, hex
+    pub message: String,
+    /// Leaf being spent; `None` for the key path
+    pub leaf_hash: Option<TapLeafHash>,
+    /// Sighash type byte, 0x00 for SIGHASH_DEFAULT
+    pub sighash_type: u8,
+    /// Key expected to sign: a leaf key, or the tweaked output key for the key path
+    pub pubkey: XOnlyPublicKey,
+}
+
+/// BIP341 sighashes for signing `psbt` through `spend_path`, for signers
+/// that sign raw digests
+///
+/// Key-path spends yield one entry per input, for the output key. For
+/// script paths, each leaf in an input's `tap_scripts` of the kind
+/// `spend_path` names yields one entry per key in the leaf: the
+/// timelock paths match single-key leaves behind OP_CSV, the emergency
+/// path single-key leaves without, and `MultisigLeaf` CHECKSIGADD leaves
+/// of that threshold and size. Inputs without such a leaf are skipped.
+///
+/// Errors with `PsbtError` if no input can be spent through `spend_path`.
+pub fn sighashes(psbt: &Psbt, spend_path: fees::SpendPath) -> Result<Vec<SighashInfo>, CoreError> {
+    let prevouts = witness_utxos(psbt)?;
+    let prevouts = Prevouts::All(&prevouts);
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let mut infos = Vec::new();
+
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        let hash_ty = input
+            .taproot_hash_ty()
+            .map_err(|e| CoreError::PsbtError(format!("Input {} has an invalid sighash type: {}", i, e)))?;
+
+        if spend_path == fees::SpendPath::KeyPath {
+            let Some(output_key) = input.witness_utxo.as_ref().and_then(p2tr_output_key) else { continue };
+            let sighash = cache
+                .taproot_key_spend_signature_hash(i, &prevouts, hash_ty)
+                .map_err(|e| CoreError::PsbtError(format!("Sighash for input {} failed: {}", i, e)))?;
+            infos.push(SighashInfo {
+                input_index: i,
+                message: hex::encode(sighash.as_ref() as &[u8]),
+                leaf_hash: None,
+                sighash_type: hash_ty as u8,
+                pubkey: output_key,
+            });
+            continue;
+        }
+
+        for (script, version) in input.tap_scripts.values() {
+            let Some(signers) = taproot::leaf_signers(script) else { continue };
+            if !leaf_matches(script, &signers, spend_path) {
+                continue;
+            }
+            let leaf_hash = TapLeafHash::from_script(script, *version);
+            let sighash = cache
+                .taproot_script_spend_signature_hash(i, &prevouts, leaf_hash, hash_ty)
+                .map_err(|e| CoreError::PsbtError(format!("Sighash for input {} failed: {}", i, e)))?;
+            for key in signers.keys {
+                infos.push(SighashInfo {
+                    input_index: i,
+                    message: hex::encode(sighash.as_ref() as &[u8]),
+                    leaf_hash: Some(leaf_hash),
+                    sighash_type: hash_ty as u8,
+                    pubkey: key,
+                });
+            }
+        }
+    }
+
+    if infos.is_empty() {
+        return Err(CoreError::PsbtError(format!("No input can be spent through {:?}", spend_path)));
+    }
+    Ok(infos)
+}
+
+/// Whether a leaf with `signers` is of the kind `spend_path` spends
+fn leaf_matches(script: &Script, signers: &taproot::LeafSigners, spend_path: fees::SpendPath) -> bool {
+    let single_key = signers.keys.len() == 1;
+    match spend_path {
+        fees::SpendPath::KeyPath => false,
+        fees::SpendPath::TimelockLeaf => single_key && taproot::leaf_csv_delay(script).is_some(),
+        fees::SpendPath::EmergencyLeaf => {
+            single_key
+                && taproot::leaf_csv_delay(script).is_none()
+                && taproot::leaf_cltv_lock(script).is_none()
+                && taproot::leaf_hashlock(script).is_none()
+        }
+        fees::SpendPath::AbsoluteLockLeaf => single_key && taproot::leaf_cltv_lock(script).is_some(),
+        fees::SpendPath::HashlockLeaf => single_key && taproot::leaf_hashlock(script).is_some(),
+        fees::SpendPath::MultisigLeaf { threshold, total } => {
+            !single_key && signers.threshold == threshold && signers.keys.len() == total
+        }
+    }
+}
+
+/// Add a signature made by an external signer over one of `sighashes()`
+///
+/// A signature by the spent output key becomes the input's `tap_key_sig`.
+/// Otherwise it is checked against every leaf of the input that `pubkey`
+/// signs for, and stored in `tap_script_sigs` under the leaf it is valid
+/// for. The signature's sighash type must be the input's.
+///
+/// Errors with `SigningError` if `pubkey` doesn't sign for the input or
+/// the signature doesn't verify, and `InvalidInput` for an input index
+/// out of range.
+pub fn apply_signature(
+    psbt: &mut Psbt,
+    input_index: usize,
+    pubkey: XOnlyPublicKey,
+    signature: bitcoin::taproot::Signature,
+) -> Result<(), CoreError> {
+    let signing_error = |reason: String| CoreError::SigningError { input_index, reason };
+    let prevouts = witness_utxos(psbt)?;
+    let prevouts = Prevouts::All(&prevouts);
+    let input = psbt.inputs.get(input_index).ok_or_else(|| {
+        CoreError::InvalidInput(format!("PSBT has no input {}", input_index))
+    })?;
+    let hash_ty = input
+        .taproot_hash_ty()
+        .map_err(|e| CoreError::PsbtError(format!("Input {} has an invalid sighash type: {}", input_index, e)))?;
+    if signature.hash_ty != hash_ty {
+        return Err(signing_error(format!(
+            "Signature has sighash type {}, but the input uses {}",
+            signature.hash_ty, hash_ty
+        )));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let verify = |sighash: &[u8]| {
+        let msg = Message::from_slice(sighash).map_err(|e| signing_error(format!("Invalid sighash: {}", e)))?;
+        Ok::<bool, CoreError>(secp.verify_schnorr(&signature.sig, &msg, &pubkey).is_ok())
+    };
+
+    if input.witness_utxo.as_ref().and_then(p2tr_output_key) == Some(pubkey) {
+        let sighash = cache
+            .taproot_key_spend_signature_hash(input_index, &prevouts, hash_ty)
+            .map_err(|e| signing_error(format!("Sighash failed: {}", e)))?;
+        if !verify(sighash.as_ref())? {
+            return Err(signing_error("Key-path signature does not verify".to_string()));
+        }
+        psbt.inputs[input_index].tap_key_sig = Some(signature);
+        return Ok(());
+    }
+
+    let leaves: Vec<TapLeafHash> = input
+        .tap_scripts
+        .values()
+        .filter(|(script, _)| taproot::leaf_signers(script).is_some_and(|signers| signers.keys.contains(&pubkey)))
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+        .collect();
+    if leaves.is_empty() {
+        return Err(signing_error(format!("Key {} does not sign for any leaf of the input", pubkey)));
+    }
+    for leaf_hash in leaves {
+        let sighash = cache
+            .taproot_script_spend_signature_hash(input_index, &prevouts, leaf_hash, hash_ty)
+            .map_err(|e| signing_error(format!("Sighash failed: {}", e)))?;
+        if verify(sighash.as_ref())? {
+            psbt.inputs[input_index].tap_script_sigs.insert((pubkey, leaf_hash), signature);
+            return Ok(());
+        }
+    }
+    Err(signing_error(format!("Signature by {} does not verify for any leaf of the input", pubkey)))
+}
+
+/// Every input's `witness_utxo`, as taproot sighashes commit to all of them
+fn witness_utxos(psbt: &Psbt) -> Result<Vec<TxOut>, CoreError> {
+    psbt.inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                CoreError::PsbtError(format!("Input {} is missing its witness UTXO", i))
+            })
+        })
+        .collect()
+}
+
+/// Finalize a signed vault PSBT into a broadcastable transaction
+///
+/// For each input, a leaf from `tap_scripts` whose signature threshold is
+/// met is turned into the BIP342 witness stack: one item per key in
+/// reverse script order (see `taproot::assemble_multisig_witness()`),
+/// then the leaf script and control block. Each placed
+/// signature is checked against the key at its stack position before
+/// the per-input PSBT fields are cleared. An input with a `tap_key_sig`
+/// (see `sign_key_path()`) is finalized as a key-path spend instead, its
+/// signature checked against the spent output key. Inputs that are
+/// already final are left alone.
+///
+/// A hashlock leaf also needs the preimage `attach_preimage()` recorded;
+/// it goes on top of the signatures, where OP_SHA256 reads it.
+///
+/// Errors with `PsbtError` naming the input and how many signatures are
+/// missing, or that the preimage is, if no leaf of an input can be
+/// satisfied.
+pub fn finalize(psbt: &mut Psbt) -> Result<Transaction, CoreError> {
+    let secp = Secp256k1::verification_only();
+    let prevouts = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            input.witness_utxo.clone().ok_or_else(|| {
+                CoreError::PsbtError(format!("Input {} is missing its witness UTXO", i))
+            })
+        })
+        .collect::<Result<Vec<TxOut>, CoreError>>()?;
+    let prevouts = Prevouts::All(&prevouts);
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        if input.final_script_witness.is_some() {
+            continue;
+        }
+        if let Some(sig) = input.tap_key_sig {
+            let output_key = input
+                .witness_utxo
+                .as_ref()
+                .and_then(p2tr_output_key)
+                .ok_or_else(|| CoreError::PsbtError(format!("Input {} does not spend a P2TR output", i)))?;
+            let sighash = cache
+                .taproot_key_spend_signature_hash(i, &prevouts, sig.hash_ty)
+                .map_err(|e| CoreError::PsbtError(format!("Sighash for input {} failed: {}", i, e)))?;
+            let msg = Message::from_slice(sighash.as_ref())
+                .map_err(|e| CoreError::PsbtError(format!("Invalid sighash: {}", e)))?;
+            secp.verify_schnorr(&sig.sig, &msg, &output_key).map_err(|_| {
+                CoreError::PsbtError(format!("Input {} has an invalid key-path signature", i))
+            })?;
+
+            let mut witness = Witness::new();
+            witness.push(sig.to_vec());
+            finalize_input(input, witness);
+            continue;
+        }
+        if input.tap_scripts.is_empty() {
+            return Err(CoreError::PsbtError(format!(
+                "Input {} has no tapscript to finalize",
+                i
+            )));
+        }
+
+        let mut fewest_missing = usize::MAX;
+        let mut missing_preimage = false;
+        let mut witness = None;
+        for (control_block, (script, version)) in &input.tap_scripts {
+            let signers = taproot::leaf_signers(script).ok_or_else(|| {
+                CoreError::PsbtError(format!("Input {} has an unrecognized leaf script", i))
+            })?;
+            let leaf_hash = TapLeafHash::from_script(script, *version);
+
+            // Use exactly `threshold` signatures, taking them in key order
+            let chosen: BTreeMap<_, _> = signers
+                .keys
+                .iter()
+                .filter_map(|key| Some((*key, *input.tap_script_sigs.get(&(*key, leaf_hash))?)))
+                .take(signers.threshold)
+                .collect();
+            if chosen.len() < signers.threshold {
+                fewest_missing = fewest_missing.min(signers.threshold - chosen.len());
+                continue;
+            }
+            let preimage = match taproot::leaf_hashlock(script) {
+                Some(hash) => match input_preimage(input, hash) {
+                    Some(preimage) => Some(preimage),
+                    None => {
+                        missing_preimage = true;
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            for (key, sig) in &chosen {
+                let sighash = cache
+                    .taproot_script_spend_signature_hash(i, &prevouts, leaf_hash, sig.hash_ty)
+                    .map_err(|e| CoreError::PsbtError(format!("Sighash for input {} failed: {}", i, e)))?;
+                let msg = Message::from_slice(sighash.as_ref())
+                    .map_err(|e| CoreError::PsbtError(format!("Invalid sighash: {}", e)))?;
+                secp.verify_schnorr(&sig.sig, &msg, key).map_err(|_| {
+                    CoreError::PsbtError(format!("Input {} has an invalid signature for key {}", i, key))
+                })?;
+            }
+
+            let threshold = u8::try_from(signers.threshold).map_err(|_| {
+                CoreError::PsbtError(format!("Input {} has a leaf threshold above 255", i))
+            })?;
+            let mut stack = Witness::from_slice(&taproot::assemble_multisig_witness(&signers.keys, &chosen, threshold)?);
+            if let Some(preimage) = preimage {
+                stack.push(preimage);
+            }
+            stack.push(script.as_bytes());
+            stack.push(control_block.serialize());
+            witness = Some(stack);
+            break;
+        }
+
+        if witness.is_none() && missing_preimage {
+            return Err(CoreError::PsbtError(format!(
+                "Input {} is missing the preimage for its hashlock leaf",
+                i
+            )));
+        }
+        let witness = witness.ok_or_else(|| {
+            log::warn!("Input {} is missing {} signature(s) for every leaf", i, fewest_missing);
+            CoreError::PsbtError(format!(
+                "Input {} is missing {} signature(s)",
+                i, fewest_missing
+            ))
+        })?;
+
+        finalize_input(input, witness);
+    }
+
+    let tx = psbt.clone().extract_tx();
+    log::debug!("Finalized {} with {} inputs", tx.txid(), tx.input.len());
+    Ok(tx)
+}
+
+/// Set the final witness of `input`, clearing the fields only signers use
+fn finalize_input(input: &mut PsbtInput, witness: Witness) {
+    *input = PsbtInput {
+        witness_utxo: input.witness_utxo.take(),
+        non_witness_utxo: input.non_witness_utxo.take(),
+        final_script_witness: Some(witness),
+        unknown: std::mem::take(&mut input.unknown),
+        proprietary: std::mem::take(&mut input.proprietary),
+        ..Default::default()
+    };
+}
+
+/// Output key of a P2TR output
+fn p2tr_output_key(txout: &TxOut) -> Option<XOnlyPublicKey> {
+    let script = &txout.script_pubkey;
+    if !script.is_v1_p2tr() {
+        return None;
+    }
+    XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).ok()
+}
+
+/// Merge cosigners' copies of one PSBT into a single PSBT
+///
+/// Every PSBT must describe the same unsigned transaction; otherwise this
+/// errors with `PsbtError` naming the first field that differs. Signatures,
+/// key origins and the other per-input and per-output maps are merged,
+/// identical entries appearing once. Where two copies disagree on an
+/// entry, the earlier PSBT's is kept.
+pub fn combine(psbts: &[Psbt]) -> Result<Psbt, CoreError> {
+    let (first, rest) = psbts
+        .split_first()
+        .ok_or_else(|| CoreError::PsbtError("No PSBTs to combine".to_string()))?;
+
+    let mut combined = first.clone();
+    for (i, psbt) in rest.iter().enumerate() {
+        if let Some(field) = unsigned_tx_mismatch(&first.unsigned_tx, &psbt.unsigned_tx) {
+            return Err(CoreError::PsbtError(format!(
+                "PSBT {} has a different {} than PSBT 0",
+                i + 1,
+                field
+            )));
+        }
+        // `Psbt::combine()` lets the argument win; merge into a copy of
+        // `psbt` so earlier entries take precedence
+        let mut merged = psbt.clone();
+        merged
+            .combine(combined)
+            .map_err(|e| CoreError::PsbtError(format!("Cannot combine PSBT {}: {}", i + 1, e)))?;
+        combined = merged;
+    }
+    Ok(combined)
+}
+
+/// First field in which two unsigned transactions differ
+fn unsigned_tx_mismatch(a: &Transaction, b: &Transaction) -> Option<String> {
+    if a.version != b.version {
+        return Some("version".to_string());
+    }
+    if a.lock_time != b.lock_time {
+        return Some("lock time".to_string());
+    }
+    if a.input.len() != b.input.len() {
+        return Some("input count".to_string());
+    }
+    for (i, (x, y)) in a.input.iter().zip(&b.input).enumerate() {
+        if x.previous_output != y.previous_output {
+            return Some(format!("input {} outpoint", i));
+        }
+        if x.sequence != y.sequence {
+            return Some(format!("input {} sequence", i));
+        }
+    }
+    if a.output.len() != b.output.len() {
+        return Some("output count".to_string());
+    }
+    for (i, (x, y)) in a.output.iter().zip(&b.output).enumerate() {
+        if x.value != y.value {
+            return Some(format!("output {} value", i));
+        }
+        if x.script_pubkey != y.script_pubkey {
+            return Some(format!("output {} script", i));
+        }
+    }
+    None
+}
+
+/// Signatures collected for one input, as reported by `signature_status()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct InputSignatures {
+    pub input_index: usize,
+    pub present: usize,
+    pub required: usize,
+}
+
+impl InputSignatures {
+    pub fn is_complete(&self) -> bool {
+        self.present >= self.required
+    }
+}
+
+/// Signing progress of a PSBT, from `signature_status()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SignatureStatus {
+    pub inputs: Vec<InputSignatures>,
+    /// Whether every input has its required signatures
+    pub complete: bool,
+}
+
+/// How many of `threshold` signatures each input of `psbt` has
+///
+/// Counts the distinct keys with a `tap_script_sigs` entry for the
+/// input's best-signed leaf. Inputs that are already final or carry a
+/// key-path signature count as fully signed.
+pub fn signature_status(psbt: &Psbt, threshold: usize) -> SignatureStatus {
+    let inputs: Vec<InputSignatures> = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(input_index, input)| {
+            let present = if input.final_script_witness.is_some() || input.tap_key_sig.is_some() {
+                threshold
+            } else {
+                input
+                    .tap_scripts
+                    .values()
+                    .map(|(script, version)| {
+                        let leaf_hash = TapLeafHash::from_script(script, *version);
+                        input.tap_script_sigs.keys().filter(|(_, hash)| *hash == leaf_hash).count()
+                    })
+                    .max()
+                    .unwrap_or(0)
+            };
+            InputSignatures {
+                input_index,
+                present,
+                required: threshold,
+            }
+        })
+        .collect();
+    let complete = inputs.iter().all(InputSignatures::is_complete);
+    SignatureStatus { inputs, complete }
+}
+
+/// A key expected to sign an input, as reported by `status()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SignerStatus {
+    pub pubkey: XOnlyPublicKey,
+    /// Master fingerprint from the input's `tap_key_origins`, if listed
+    pub fingerprint: Option<Fingerprint>,
+    pub signed: bool,
+}
+
+/// Signing progress of one input, as reported by `status()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputStatus {
+    pub input_index: usize,
+    /// Vault index the input's key origins name, if they match its script
+    pub vault_index: Option<u32>,
+    /// Spend path implied by the input's leaf data, `None` if unrecognized
+    pub spend_path: Option<fees::SpendPath>,
+    /// Keys of the spend path, in the order its script checks them
+    pub signers: Vec<SignerStatus>,
+    /// Signatures the spend path needs
+    pub required: usize,
+    /// Signatures present, out of `required`
+    pub signed: usize,
+    /// Whether the input already has a final witness
+    pub finalized: bool,
+    /// Whether `finalize()` can complete the input
+    pub finalizable: bool,
+}
+
+/// Signing progress of a PSBT against a vault, from `status()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PsbtStatus {
+    pub inputs: Vec<InputStatus>,
+    /// Whether every input is final or finalizable
+    pub ready: bool,
+    /// Vsize of the finalized transaction, estimated as in
+    /// `fees::check_standardness()`
+    pub estimated_vsize: u64,
+    pub fee_sats: u64,
+}
+
+/// Who is expected to sign each input of `psbt`, and who has
+///
+/// An input's spend path comes from its leaf data: a `tap_key_sig`, or
+/// an internal key without `tap_scripts`, is a key-path spend by the
+/// output key. Otherwise the leaf of `tap_scripts` closest to its
+/// threshold is reported, the one `finalize()` would pick. Inputs that
+/// are already final are read back from their witness. Signatures are
+/// counted, not verified; `finalize()` checks them.
+///
+/// Errors with `PsbtError` if an input lacks its `witness_utxo` or the
+/// outputs spend more than the inputs.
+pub fn status(psbt: &Psbt, vault: &VaultConfig) -> Result<PsbtStatus, CoreError> {
+    let vault = Vault::from_config(vault)?;
+    let prevouts = witness_utxos(psbt)?;
+
+    let inputs: Vec<InputStatus> = psbt
+        .inputs
+        .iter()
+        .zip(&prevouts)
+        .enumerate()
+        .map(|(input_index, (input, prevout))| {
+            let vault_index = policy::origin_tree(&vault, &input.tap_key_origins, &prevout.script_pubkey)
+                .map(|(index, _)| index);
+            let mut status = match &input.final_script_witness {
+                Some(witness) => final_input_status(witness, prevout),
+                None => pending_input_status(input, prevout),
+            };
+            status.input_index = input_index;
+            status.vault_index = vault_index;
+            status
+        })
+        .collect();
+
+    let spent: u64 = prevouts.iter().map(|txout| txout.value).sum();
+    let sent: u64 = psbt.unsigned_tx.output.iter().map(|txout| txout.value).sum();
+    let fee_sats = spent
+        .checked_sub(sent)
+        .ok_or_else(|| CoreError::PsbtError(format!("Outputs ({} sats) exceed inputs ({} sats)", sent, spent)))?;
+    let estimated_vsize = fees::weight_to_vsize(fees::check_standardness(psbt)?.weight);
+    let ready = !inputs.is_empty() && inputs.iter().all(|input| input.finalizable);
+
+    Ok(PsbtStatus {
+        inputs,
+        ready,
+        estimated_vsize,
+        fee_sats,
+    })
+}
+
+/// Status of an input that has not been finalized
+fn pending_input_status(input: &PsbtInput, prevout: &TxOut) -> InputStatus {
+    let fingerprint = |key: &XOnlyPublicKey| input.tap_key_origins.get(key).map(|(_, (fingerprint, _))| *fingerprint);
+
+    if input.tap_key_sig.is_some() || (input.tap_scripts.is_empty() && input.tap_internal_key.is_some()) {
+        let signed = input.tap_key_sig.is_some();
+        let signers = p2tr_output_key(prevout)
+            .map(|pubkey| SignerStatus {
+                pubkey,
+                fingerprint: input.tap_internal_key.as_ref().and_then(fingerprint),
+                signed,
+            })
+            .into_iter()
+            .collect();
+        return input_status(Some(fees::SpendPath::KeyPath), signers, 1, false);
+    }
+
+    // The leaf closest to its threshold, first of equals as in `finalize()`
+    let best = input
+        .tap_scripts
+        .values()
+        .filter_map(|(script, version)| {
+            let leaf_signers = taproot::leaf_signers(script)?;
+            let leaf_hash = TapLeafHash::from_script(script, *version);
+            let signers: Vec<SignerStatus> = leaf_signers
+                .keys
+                .iter()
+                .map(|key| SignerStatus {
+                    pubkey: *key,
+                    fingerprint: fingerprint(key),
+                    signed: input.tap_script_sigs.contains_key(&(*key, leaf_hash)),
+                })
+                .collect();
+            Some((script, leaf_signers.threshold, signers))
+        })
+        .min_by_key(|(_, threshold, signers)| {
+            threshold.saturating_sub(signers.iter().filter(|signer| signer.signed).count())
+        });
+
+    match best {
+        Some((script, threshold, signers)) => input_status(Some(leaf_spend_path(script, threshold, signers.len())), signers, threshold, false),
+        None => input_status(None, Vec::new(), 1, false),
+    }
+}
+
+/// Status of an input with a final witness, read back from the witness
+fn final_input_status(witness: &Witness, prevout: &TxOut) -> InputStatus {
+    let Some((script, _)) = super::watch::script_path(witness) else {
+        let signers = p2tr_output_key(prevout)
+            .map(|pubkey| SignerStatus {
+                pubkey,
+                fingerprint: None,
+                signed: true,
+            })
+            .into_iter()
+            .collect();
+        return input_status(Some(fees::SpendPath::KeyPath), signers, 1, true);
+    };
+    let Some(leaf_signers) = taproot::leaf_signers(script) else {
+        return input_status(None, Vec::new(), 1, true);
+    };
+
+    // Witness items run in reverse script order, an empty one for each
+    // key that did not sign
+    let items: Vec<&[u8]> = witness.iter().collect();
+    let total = leaf_signers.keys.len();
+    let signers = leaf_signers
+        .keys
+        .iter()
+        .enumerate()
+        .map(|(n, key)| SignerStatus {
+            pubkey: *key,
+            fingerprint: None,
+            signed: items.get(total - 1 - n).is_some_and(|item| !item.is_empty()),
+        })
+        .collect();
+    let spend_path = leaf_spend_path(script, leaf_signers.threshold, total);
+    input_status(Some(spend_path), signers, leaf_signers.threshold, true)
+}
+
+fn input_status(spend_path: Option<fees::SpendPath>, signers: Vec<SignerStatus>, required: usize, finalized: bool) -> InputStatus {
+    let signed = signers.iter().filter(|signer| signer.signed).count();
+    InputStatus {
+        input_index: 0,
+        vault_index: None,
+        finalizable: finalized || (spend_path.is_some() && signed >= required),
+        spend_path,
+        signers,
+        required,
+        signed,
+        finalized,
+    }
+}
+
+/// Spend path of a leaf with `total` keys and `threshold`, the inverse of
+/// `leaf_matches()`
+fn leaf_spend_path(script: &Script, threshold: usize, total: usize) -> fees::SpendPath {
+    match total {
+        1 if taproot::leaf_csv_delay(script).is_some() => fees::SpendPath::TimelockLeaf,
+        1 if taproot::leaf_cltv_lock(script).is_some() => fees::SpendPath::AbsoluteLockLeaf,
+        1 if taproot::leaf_hashlock(script).is_some() => fees::SpendPath::HashlockLeaf,
+        1 => fees::SpendPath::EmergencyLeaf,
+        _ => fees::SpendPath::MultisigLeaf { threshold, total },
+    }
+}
+
+/// Path the first input of a PSBT built here is spent through
+///
+/// Read from the input rather than assumed by the caller: the key path
+/// when the input carries no leaf, otherwise the kind of leaf the builder
+/// put in `tap_scripts`. Pass the result to `attach_vault_info()`.
+/// Errors with `PsbtError` for a PSBT without inputs or whose first
+/// input's leaf has no signers this library knows.
+pub fn built_spend_path(psbt: &Psbt) -> Result<fees::SpendPath, CoreError> {
+    let input = psbt
+        .inputs
+        .first()
+        .ok_or_else(|| CoreError::PsbtError("PSBT has no inputs".to_string()))?;
+    let Some((script, _)) = input.tap_scripts.values().next() else {
+        return Ok(fees::SpendPath::KeyPath);
+    };
+    let signers = taproot::leaf_signers(script)
+        .ok_or_else(|| CoreError::PsbtError("Input 0 spends through a leaf of unknown form".to_string()))?;
+    Ok(leaf_spend_path(script, signers.threshold, signers.keys.len()))
+}
+
+/// Prefix of the proprietary global keys `attach_vault_info()` writes,
+/// and of the input keys `attach_preimage()` writes
+pub const VAULT_INFO_PREFIX: &[u8] = b"vaultmgr";
+
+/// Proprietary subtype holding `VaultMetadata::to_bytes()`
+const VAULT_INFO_METADATA: u8 = 0x00;
+
+/// Proprietary subtype holding the spend path, as JSON
+const VAULT_INFO_SPEND_PATH: u8 = 0x01;
+
+/// Proprietary subtype holding the version of the library that built the PSBT
+const VAULT_INFO_LIBRARY_VERSION: u8 = 0x02;
+
+/// Proprietary input subtype holding a hashlock preimage, keyed by its SHA256
+const VAULT_INPUT_PREIMAGE: u8 = 0x00;
+
+/// Vault records read back by `read_vault_info()`
+#[derive(Debug, Clone, Serialize)]
+pub struct VaultPsbtInfo {
+    /// Metadata of the vault the PSBT spends from
+    pub metadata: VaultMetadata,
+    /// Path the PSBT's inputs are spent through
+    pub spend_path: fees::SpendPath,
+    /// Version of this library that attached the records
+    pub library_version: String,
+}
+
+/// Record in `psbt` which vault it spends from and through which path
+///
+/// Writes BIP174 proprietary global keys under `VAULT_INFO_PREFIX`:
+/// `metadata` in its byte layout, `spend_path` as JSON and this
+/// library's version, so a coordinator can route the PSBT without
+/// out-of-band context (see `read_vault_info()`). Signers that don't know
+/// the keys pass them through. Earlier vault records are replaced; other
+/// proprietary keys are left as they are.
+pub fn attach_vault_info(psbt: &mut Psbt, metadata: &VaultMetadata, spend_path: fees::SpendPath) -> Result<(), CoreError> {
+    let spend_path = serde_json::to_vec(&spend_path)
+        .map_err(|e| CoreError::PsbtError(format!("Failed to encode spend path: {}", e)))?;
+    for (subtype, value) in [
+        (VAULT_INFO_METADATA, metadata.to_bytes()),
+        (VAULT_INFO_SPEND_PATH, spend_path),
+        (VAULT_INFO_LIBRARY_VERSION, env!("CARGO_PKG_VERSION").as_bytes().to_vec()),
+    ] {
+        let key = ProprietaryKey {
+            prefix: VAULT_INFO_PREFIX.to_vec(),
+            subtype,
+            key: vec![],
+        };
+        psbt.proprietary.insert(key, value);
+    }
+    Ok(())
+}
+
+/// Vault records `attach_vault_info()` wrote into `psbt`
+///
+/// `None` when the PSBT carries none, as for PSBTs from other software.
+/// Subtypes under `VAULT_INFO_PREFIX` this version doesn't know are
+/// skipped, so records from newer versions still read. Fails with
+/// `PsbtError` for an incomplete or undecodable record, and with
+/// `MetadataError` for invalid metadata bytes.
+pub fn read_vault_info(psbt: &Psbt) -> Result<Option<VaultPsbtInfo>, CoreError> {
+    let record = |subtype: u8| {
+        psbt.proprietary
+            .iter()
+            .find(|(key, _)| key.prefix == VAULT_INFO_PREFIX && key.subtype == subtype && key.key.is_empty())
+            .map(|(_, value)| value.as_slice())
+    };
+    let (metadata, spend_path, library_version) = match (
+        record(VAULT_INFO_METADATA),
+        record(VAULT_INFO_SPEND_PATH),
+        record(VAULT_INFO_LIBRARY_VERSION),
+    ) {
+        (None, None, None) => return Ok(None),
+        (Some(metadata), Some(spend_path), Some(library_version)) => (metadata, spend_path, library_version),
+        _ => {
+            return Err(CoreError::PsbtError(
+                "Vault info is incomplete: metadata, spend path and library version are all required".to_string(),
+            ))
+        }
+    };
+
+    Ok(Some(VaultPsbtInfo {
+        metadata: VaultMetadata::from_bytes(metadata)?,
+        spend_path: serde_json::from_slice(spend_path)
+            .map_err(|e| CoreError::PsbtError(format!("Invalid vault info spend path: {}", e)))?,
+        library_version: String::from_utf8(library_version.to_vec())
+            .map_err(|e| CoreError::PsbtError(format!("Invalid vault info library version: {}", e)))?,
+    }))
+}
+
+/// Record in input `index` of `psbt` the preimage that opens its hashlock leaf
+///
+/// Writes a BIP174 proprietary input key under `VAULT_INFO_PREFIX`, keyed
+/// by the preimage's SHA256, so a coordinator can add the secret to a
+/// PSBT the recovery service signed; `finalize()` puts it in the witness.
+/// Fails with `InvalidInput` for an input index out of range or a
+/// preimage that isn't `HASHLOCK_PREIMAGE_LEN` bytes, and with
+/// `PolicyViolation` if no hashlock leaf of the input matches its hash.
+pub fn attach_preimage(psbt: &mut Psbt, index: usize, preimage: &[u8]) -> Result<(), CoreError> {
+    if preimage.len() != taproot::HASHLOCK_PREIMAGE_LEN {
+        return Err(CoreError::InvalidInput(format!(
+            "Preimage is {} bytes, expected {}",
+            preimage.len(),
+            taproot::HASHLOCK_PREIMAGE_LEN
+        )));
+    }
+    let input = psbt.inputs.get_mut(index).ok_or_else(|| {
+        CoreError::InvalidInput(format!("PSBT has no input {}", index))
+    })?;
+    let hash = sha256::Hash::hash(preimage).to_byte_array();
+    if !input.tap_scripts.values().any(|(script, _)| taproot::leaf_hashlock(script) == Some(hash)) {
+        return Err(CoreError::PolicyViolation(format!(
+            "Input {} has no hashlock leaf for preimage hash {}",
+            index,
+            sha256::Hash::from_byte_array(hash)
+        )));
+    }
+
+    let key = ProprietaryKey {
+        prefix: VAULT_INFO_PREFIX.to_vec(),
+        subtype: VAULT_INPUT_PREIMAGE,
+        key: hash.to_vec(),
+    };
+    input.proprietary.insert(key, preimage.to_vec());
+    Ok(())
+}
+
+/// Preimage of `hash` recorded in `input` by `attach_preimage()`, if it still matches
+fn input_preimage(input: &PsbtInput, hash: [u8; 32]) -> Option<&[u8]> {
+    let key = ProprietaryKey {
+        prefix: VAULT_INFO_PREFIX.to_vec(),
+        subtype: VAULT_INPUT_PREIMAGE,
+        key: hash.to_vec(),
+    };
+    input
+        .proprietary
+        .get(&key)
+        .map(Vec::as_slice)
+        .filter(|preimage| {
+            preimage.len() == taproot::HASHLOCK_PREIMAGE_LEN
+                && sha256::Hash::hash(preimage).to_byte_array() == hash
+        })
+}
+
+/// BIP174 magic bytes every serialized PSBT starts with
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// Decode a base64 PSBT
+pub fn from_base64(psbt_base64: &str) -> Result<Psbt, CoreError> {
+    let psbt_bytes = base64::engine::general_purpose::STANDARD
+        .decode(psbt_base64.trim())
+        .map_err(|e| CoreError::PsbtError(format!("Invalid base64: {}", e)))?;
+
+    deserialize(&psbt_bytes)
+}
+
+/// Encode a PSBT as base64
+pub fn to_base64(psbt: &Psbt) -> String {
+    base64::engine::general_purpose::STANDARD.encode(psbt.serialize())
+}
+
+/// Decode a hex PSBT
+pub fn from_hex(psbt_hex: &str) -> Result<Psbt, CoreError> {
+    let psbt_bytes =
+        hex::decode(psbt_hex.trim()).map_err(|e| CoreError::PsbtError(format!("Invalid hex: {}", e)))?;
+
+    deserialize(&psbt_bytes)
+}
+
+/// Encode a PSBT as lowercase hex
+pub fn to_hex(psbt: &Psbt) -> String {
+    hex::encode(psbt.serialize())
+}
+
+/// Decode a PSBT given as base64 or hex text
+///
+/// Hex is recognized by the encoded magic (`70736274ff`), anything else
+/// is read as base64.
+pub fn parse_any(input: &str) -> Result<Psbt, CoreError> {
+    parse_any_bytes(input.as_bytes())
+}
+
+/// Decode a PSBT given as raw bytes, or as base64 or hex text
+///
+/// Raw PSBTs are recognized by their `psbt\xff` magic; other input must
+/// be UTF-8 text for `parse_any()`.
+pub fn parse_any_bytes(input: &[u8]) -> Result<Psbt, CoreError> {
+    if input.starts_with(PSBT_MAGIC) {
+        return deserialize(input);
+    }
+    let text = std::str::from_utf8(input).map_err(|e| {
+        CoreError::PsbtError(format!(
+            "PSBT is neither binary nor text: invalid UTF-8 at byte {}",
+            e.valid_up_to()
+        ))
+    })?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(CoreError::PsbtError("Empty PSBT".to_string()));
+    }
+    let hex_magic = hex::encode(PSBT_MAGIC);
+    if text.get(..hex_magic.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(&hex_magic)) {
+        from_hex(text)
+    } else {
+        from_base64(text)
+    }
+}
+
+/// Deserialize a binary PSBT
+fn deserialize(psbt_bytes: &[u8]) -> Result<Psbt, CoreError> {
+    if !psbt_bytes.starts_with(PSBT_MAGIC) {
+        return Err(CoreError::PsbtError("Invalid PSBT: missing psbt magic bytes at offset 0".to_string()));
+    }
+    Psbt::deserialize(psbt_bytes).map_err(|e| CoreError::PsbtError(format!("Invalid PSBT: {}", e)))
+}
+
+/// PSBT input data for spending `utxo` through `leaf`
+///
+/// Fills the witness UTXO, internal key, merkle root, the leaf script
+/// keyed by its control block, and the origins of the leaf's keys
+/// tagged with the leaf hash. When the internal key is a vault key
+/// rather than the NUMS key, its origin is added too, for key-path
+/// signing.
+pub(crate) fn script_path_input(utxo: &VaultUtxo, leaf: LeafId) -> Result<PsbtInput, CoreError> {
+    let tree = &utxo.tree;
+    let vault_leaf = tree
+        .leaf(leaf)
+        .ok_or_else(|| CoreError::PsbtError(format!("Vault tree has no {:?} leaf", leaf)))?;
+    let leaf_hash = TapLeafHash::from_script(&vault_leaf.script, vault_leaf.version);
+    let control_block = taproot::control_block(tree, leaf)?;
+
+    let mut input = PsbtInput {
+        witness_utxo: Some(utxo.txout()),
+        tap_internal_key: Some(tree.internal_key()),
+        tap_merkle_root: tree.merkle_root(),
+        ..Default::default()
+    };
+    input
+        .tap_scripts
+        .insert(control_block, (vault_leaf.script.clone(), vault_leaf.version));
+
+    for key in script_keys(&vault_leaf.script) {
+        if let Some(origin) = tree.key_origins().get(&key) {
+            input
+                .tap_key_origins
+                .insert(key, (vec![leaf_hash], origin.clone()));
+        }
+    }
+    // A signable internal key is listed with no leaf hashes (BIP371)
+    let internal_key = tree.internal_key();
+    if let Some(origin) = tree.key_origins().get(&internal_key) {
+        input
+            .tap_key_origins
+            .entry(internal_key)
+            .or_insert_with(|| (vec![], origin.clone()));
+    }
+
+    Ok(input)
+}
+
+/// PSBT input data for a key-path spend of `utxo`, see `sign_key_path()`
+pub(crate) fn key_path_input(utxo: &VaultUtxo) -> PsbtInput {
+    let mut input = PsbtInput {
+        witness_utxo: Some(utxo.txout()),
+        tap_internal_key: Some(utxo.tree.internal_key()),
+        tap_merkle_root: utxo.tree.merkle_root(),
+        ..Default::default()
+    };
+    if let Some(origin) = utxo.tree.key_origins().get(&utxo.tree.internal_key()) {
+        input
+            .tap_key_origins
+            .insert(utxo.tree.internal_key(), (vec![], origin.clone()));
+    }
+    input
+}
+
+/// X-only keys pushed by a tapscript
+fn script_keys(script: &Script) -> Vec<XOnlyPublicKey> {
+    script
+        .instructions()
+        .filter_map(|ins| match ins {
+            Ok(Instruction::PushBytes(bytes)) => XOnlyPublicKey::from_slice(bytes.as_bytes()).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fee for a transaction of `weight` at `fee_rate` sat/vB, rounding vbytes up
+fn fee_for_weight(weight: usize, fee_rate: u64) -> Result<u64, CoreError> {
+    fees::estimate_fee(fees::weight_to_vsize(weight), fee_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, regtest_config, regtest_vault, CustomFields, OWNER_TPUB, RECOVERY_TPUB};
+    use crate::keys;
+    use crate::taproot::vault_tree;
+    use crate::vault::{AbsoluteLockUnit, Network, RecoveryType, VaultTemplate};
+    use crate::vault::fees::SCHNORR_SIG_SIZE;
+    use crate::vault::{HashlockRecovery, MultisigRecovery};
+    use bitcoin::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    const DESTINATION: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn utxo(amount_sats: u64, vault_index: u32) -> VaultUtxo {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let tree = vault_tree(&VaultTemplate::spending(), &owner, &recovery, vault_index, Network::Regtest).unwrap();
+        let outpoint = OutPoint::new(Txid::from_str(&"ab".repeat(32)).unwrap(), 1);
+        VaultUtxo::new(outpoint, amount_sats, tree)
+    }
+
+    fn psbt_tree() -> VaultTree {
+        utxo(0, 0).tree
+    }
+
+    fn change_to(vault_index: u32) -> ChangeTarget {
+        ChangeTarget::new(vault_index, utxo(0, vault_index).tree)
+    }
+
+    fn destination() -> Address {
+        DESTINATION
+            .parse::<Address<bitcoin::address::NetworkUnchecked>>()
+            .unwrap()
+            .require_network(bitcoin::Network::Regtest)
+            .unwrap()
+    }
+
+    fn metadata(delay_blocks: u32) -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "spending_v1".to_string(),
+            delay_blocks,
+            delay_unit: DelayUnit::Blocks,
+            destination_indices: vec![],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 0,
+            vault_index: 0,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_build_unvault_sweep() {
+        let psbt = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].sequence, Sequence::from_height(144));
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, destination().script_pubkey());
+
+        let fee = 100_000 - tx.output[0].value;
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        assert_eq!(fee, fee_for_weight(fees::tx_weight(&[weight], &[tx.output[0].script_pubkey.len()]), 2).unwrap());
+    }
+
+    #[test]
+    fn test_build_unvault_from_selection() {
+        use crate::vault::coins::{self, SelectionStrategy};
+
+        let utxos = vec![utxo(40_000, 0), utxo(70_000, 1), utxo(30_000, 2)];
+        let selection = coins::select(&utxos, 100_000, 2, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        let bundle = build_unvault_from_selection(&selection, destination(), &change_to(7), &metadata(144), None, None, DustPolicy::Relay)
+            .unwrap();
+        let psbt = &bundle.psbt;
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.iter().map(|i| i.previous_output).collect::<Vec<_>>(), selection.outpoints());
+        assert!(tx.input.iter().all(|i| i.sequence == Sequence::from_height(144)));
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(psbt.inputs[1].witness_utxo, Some(selection.utxos[1].txout()));
+
+        assert_eq!(tx.output[0].value, 100_000);
+        assert_eq!(tx.output[1].value, selection.change_sats);
+        assert_eq!(tx.output[1].script_pubkey, change_to(7).tree.script_pubkey());
+        assert_eq!(psbt.outputs[1].tap_internal_key, Some(taproot::nums_internal_key(7).unwrap()));
+        assert_eq!(
+            bundle.change,
+            ChangeOutcome::Output {
+                vault_index: 7,
+                vout: 1,
+                amount_sats: selection.change_sats
+            }
+        );
+        let output_total: u64 = tx.output.iter().map(|o| o.value).sum();
+        assert_eq!(selection.total_input_sats - output_total, selection.fee_sats);
+
+        let mut tampered = selection.clone();
+        tampered.fee_sats += 1;
+        assert!(build_unvault_from_selection(&tampered, destination(), &change_to(7), &metadata(144), None, None, DustPolicy::Relay).is_err());
+        assert!(matches!(
+            build_unvault_from_selection(&selection, destination(), &change_to(7), &metadata(10), None, None, DustPolicy::Relay),
+            Err(CoreError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_unvault_key_origins_from_master() {
+        use crate::vault::coins::{self, SelectionStrategy};
+        use crate::vault::VaultBuilder;
+        use std::collections::BTreeMap;
+
+        let secp = Secp256k1::new();
+        let master = keys::from_mnemonic(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "",
+            Network::Regtest,
+        )
+        .unwrap();
+        let account_xpub = |account| {
+            let xpriv = keys::account_xpriv(&master, keys::TAPROOT_PURPOSE, account, Network::Regtest).unwrap();
+            ExtendedPubKey::from_priv(&secp, &xpriv.xpriv().unwrap())
+        };
+        let (owner, recovery) = (account_xpub(0), account_xpub(1));
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::spending())
+            .owner_xpub(format!("[73c5da0a/86h/1h/0h]{}", owner))
+            .recovery_xpub(format!("[73c5da0a/86'/1'/1']{}", recovery))
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+        let master_fingerprint = master.fingerprint();
+        assert_eq!(master_fingerprint.to_string(), "73c5da0a");
+
+        let outpoint = |vout| OutPoint::new(Txid::from_str(&"cd".repeat(32)).unwrap(), vout);
+        let utxos = vec![
+            VaultUtxo::new(outpoint(0), 60_000, vault.tree_at(2).unwrap()),
+            VaultUtxo::new(outpoint(1), 50_000, vault.tree_at(9).unwrap()),
+        ];
+        let selection = coins::select(&utxos, 100_000, 2, SelectionStrategy::LargestFirst, DustPolicy::Relay).unwrap();
+        let change = vault.change_target(10).unwrap();
+        let bundle =
+            build_unvault_from_selection(&selection, destination(), &change, &vault.metadata(), None, None, DustPolicy::Relay).unwrap();
+        let mut psbt = bundle.psbt;
+        assert_eq!(psbt.inputs.len(), 2);
+
+        for (input, utxo) in psbt.inputs.iter().zip(&selection.utxos) {
+            let index = if utxo.outpoint == outpoint(0) { 2 } else { 9 };
+            let owner_key = keys::derive_vault_key(&owner, index, Network::Regtest).unwrap().public_key;
+            let path = DerivationPath::from_str(&format!("m/86'/1'/0'/0/{}", index)).unwrap();
+            let expected = BTreeMap::from([(
+                owner_key,
+                (vec![utxo.tree.leaf_hash(LeafPurpose::Timelock).unwrap()], (master_fingerprint, path)),
+            )]);
+            assert_eq!(input.tap_key_origins, expected);
+        }
+
+        // The change output names its keys the same way
+        let change_output = &psbt.outputs[1];
+        let recovery_key = keys::derive_vault_key(&recovery, 10, Network::Regtest).unwrap().public_key;
+        let (_, (fingerprint, path)) = &change_output.tap_key_origins[&recovery_key];
+        assert_eq!(*fingerprint, master_fingerprint);
+        assert_eq!(path.to_string(), "m/86'/1'/1'/0/10");
+
+        // So the master key signs both inputs directly
+        assert_eq!(keys::sign_psbt(&mut psbt, &master, Network::Regtest).unwrap(), 2);
+
+        // A recovery spends through the emergency leaf, listing the recovery key
+        let recovery_psbt = build_recovery(&utxos[1..], None, destination(), 2, SpendOptions::default()).unwrap();
+        let (leaf_hashes, (fingerprint, path)) = &recovery_psbt.inputs[0].tap_key_origins
+            [&keys::derive_vault_key(&recovery, 9, Network::Regtest).unwrap().public_key];
+        assert_eq!(leaf_hashes, &vec![utxos[1].tree.leaf_hash(LeafPurpose::Emergency).unwrap()]);
+        assert_eq!(*fingerprint, master_fingerprint);
+        assert_eq!(path.to_string(), "m/86'/1'/1'/0/9");
+    }
+
+    #[test]
+    fn test_build_unvault_enforces_approved_destinations() {
+        let mut restricted = metadata(144);
+        restricted.destination_indices = vec![1];
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved.push("other", psbt_tree().address(Network::Regtest)).unwrap();
+        approved.push("destination", destination()).unwrap();
+
+        assert!(build_unvault(
+            utxo(100_000, 0),
+            destination(),
+            2,
+            &restricted,
+            SpendOptions { approved: Some(&approved), ..Default::default() },
+        )
+        .is_ok());
+
+        restricted.destination_indices = vec![0];
+        let err = build_partial_unvault(
+            utxo(100_000, 0),
+            destination(),
+            40_000,
+            &change_to(1),
+            2,
+            &restricted,
+            SpendOptions { approved: Some(&approved), ..Default::default() },
+        )
+        .unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(ref message) if message.contains(DESTINATION)));
+        assert!(matches!(
+            build_unvault(utxo(100_000, 0), destination(), 2, &restricted, SpendOptions::default()),
+            Err(CoreError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_unvault_input_fields() {
+        let utxo = utxo(100_000, 3);
+        let tree = utxo.tree.clone();
+        let psbt = build_unvault(utxo, destination(), 1, &metadata(144), SpendOptions::default()).unwrap();
+        let input = &psbt.inputs[0];
+
+        assert_eq!(input.witness_utxo.as_ref().unwrap().script_pubkey, tree.script_pubkey());
+        assert_eq!(input.tap_internal_key, Some(taproot::nums_internal_key(3).unwrap()));
+        assert_eq!(input.tap_merkle_root, tree.merkle_root());
+
+        let leaf = tree.leaf(LeafPurpose::Timelock).unwrap();
+        let (cb, (script, version)) = input.tap_scripts.iter().next().unwrap();
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert_eq!(script, &leaf.script);
+        assert_eq!(*version, leaf.version);
+        assert!(taproot::verify_control_block(cb, script, &tree.output_key().to_inner()));
+
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let owner_key = keys::derive_vault_key(&owner, 3, Network::Regtest).unwrap().public_key;
+        let (leaf_hashes, (fingerprint, path)) = input.tap_key_origins.get(&owner_key).unwrap();
+        assert_eq!(input.tap_key_origins.len(), 1);
+        assert_eq!(leaf_hashes, &vec![tree.leaf_hash(LeafPurpose::Timelock).unwrap()]);
+        assert_eq!(*fingerprint, owner.fingerprint());
+        assert_eq!(path.to_string(), "m/0/3");
+    }
+
+    #[test]
+    fn test_build_partial_unvault_returns_change_to_fresh_index() {
+        let utxo = utxo(100_000, 0);
+        let spent_spk = utxo.tree.script_pubkey();
+        let change = change_to(5);
+        let bundle = build_partial_unvault(
+            utxo,
+            destination(),
+            40_000,
+            &change,
+            2,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+        .unwrap();
+        let psbt = &bundle.psbt;
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 40_000);
+        assert_eq!(tx.output[1].script_pubkey, change.tree.script_pubkey());
+        assert_ne!(tx.output[1].script_pubkey, spent_spk);
+        assert_eq!(
+            bundle.change,
+            ChangeOutcome::Output {
+                vault_index: 5,
+                vout: 1,
+                amount_sats: tx.output[1].value
+            }
+        );
+        assert_eq!(bundle.change_index(), Some(5));
+
+        // Signers can re-derive the change from its key origins
+        let output = &psbt.outputs[1];
+        assert_eq!(output.tap_internal_key, Some(taproot::nums_internal_key(5).unwrap()));
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let owner_key = keys::derive_vault_key(&owner, 5, Network::Regtest).unwrap().public_key;
+        let (leaf_hashes, (_, path)) = output.tap_key_origins.get(&owner_key).unwrap();
+        assert!(leaf_hashes.contains(&change.tree.leaf_hash(LeafPurpose::Timelock).unwrap()));
+        assert_eq!(path.to_string(), "m/0/5");
+
+        let fee = 100_000 - tx.output.iter().map(|o| o.value).sum::<u64>();
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        let outputs: Vec<usize> = tx.output.iter().map(|o| o.script_pubkey.len()).collect();
+        assert_eq!(fee, fee_for_weight(fees::tx_weight(&[weight], &outputs), 2).unwrap());
+    }
+
+    #[test]
+    fn test_build_partial_unvault_dust_change_goes_to_fee() {
+        let bundle =
+            build_partial_unvault(
+                utxo(40_400, 0),
+                destination(),
+                40_000,
+                &change_to(1),
+                1,
+                &metadata(144),
+                SpendOptions::default(),
+            )
+                .unwrap();
+        let tx = &bundle.psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value, 40_000);
+        let sweep_fee = 40_400 - 40_000 - match bundle.change {
+            ChangeOutcome::AddedToFee { amount_sats, dust_limit_sats } => {
+                assert_eq!(dust_limit_sats, 330);
+                amount_sats
+            }
+            other => panic!("unexpected change outcome: {:?}", other),
+        };
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        assert_eq!(sweep_fee, fee_for_weight(fees::tx_weight(&[weight], &[tx.output[0].script_pubkey.len()]), 1).unwrap());
+        assert_eq!(bundle.change_index(), None);
+    }
+
+    #[test]
+    fn test_build_partial_unvault_custom_dust_threshold() {
+        let change = change_to(1);
+        assert_eq!(change.dust_threshold, 330);
+        // Thresholds below the output's dust limit don't apply
+        assert_eq!(change.clone().with_dust_threshold(100).dust_threshold, 330);
+
+        let build = |change: &ChangeTarget| {
+            build_partial_unvault(
+                utxo(100_000, 0),
+                destination(),
+                40_000,
+                change,
+                2,
+                &metadata(144),
+                SpendOptions::default(),
+            )
+                .unwrap()
+        };
+        let kept = build(&change);
+        let change_sats = match kept.change {
+            ChangeOutcome::Output { amount_sats, .. } => amount_sats,
+            other => panic!("unexpected change outcome: {:?}", other),
+        };
+
+        let folded = build(&change.clone().with_dust_threshold(change_sats + 1));
+        assert_eq!(folded.psbt.unsigned_tx.output.len(), 1);
+        assert!(matches!(folded.change, ChangeOutcome::AddedToFee { amount_sats, .. } if amount_sats > change_sats));
+        assert_eq!(build(&change.with_dust_threshold(change_sats)).change, kept.change);
+    }
+
+    #[test]
+    fn test_dust_floor_applies_to_every_output() {
+        let strict = DustPolicy::Floor(1_000);
+        let build = |utxo_sats: u64, amount: u64, dust: DustPolicy| {
+            let change = change_to(1);
+            build_partial_unvault(
+                utxo(utxo_sats, 0),
+                destination(),
+                amount,
+                &change,
+                2,
+                &metadata(144),
+                SpendOptions { dust, ..Default::default() },
+            )
+        };
+
+        // A destination below the limit is refused, not rounded up or dropped
+        match build(100_000, 999, strict) {
+            Err(CoreError::PolicyViolation(message)) => {
+                assert_eq!(message, "Output 0 pays 999 sats, below its dust limit of 1000 sats")
+            }
+            other => panic!("Expected PolicyViolation, got {:?}", other),
+        }
+        build(100_000, 999, DustPolicy::Relay).unwrap();
+        build(100_000, 1_000, strict).unwrap();
+        assert!(matches!(build(100_000, 100, DustPolicy::Relay), Err(CoreError::PolicyViolation(_))));
+
+        // 800 sats of change clears the relay limit but not the floor
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        let change_fee = fee_for_weight(fees::tx_weight(&[weight], &[destination().script_pubkey().len(), 34]), 2).unwrap();
+        let amount = 50_000 - change_fee - 800;
+        let kept = build(50_000, amount, DustPolicy::Relay).unwrap();
+        assert!(matches!(kept.change, ChangeOutcome::Output { amount_sats: 800, .. }));
+        let folded = build(50_000, amount, strict).unwrap();
+        assert_eq!(folded.psbt.unsigned_tx.output.len(), 1);
+        assert!(matches!(
+            folded.change,
+            ChangeOutcome::AddedToFee { amount_sats, dust_limit_sats: 1_000 } if amount_sats > 800
+        ));
+        assert_eq!(folded.change_index(), None);
+
+        let selection = crate::vault::coins::Selection {
+            utxos: vec![utxo(50_000, 0)],
+            total_input_sats: 50_000,
+            target_sats: 999,
+            fee_sats: 1_000,
+            change_sats: 48_001,
+        };
+        let from_selection =
+            |dust| build_unvault_from_selection(&selection, destination(), &change_to(1), &metadata(144), None, None, dust);
+        assert!(matches!(from_selection(strict), Err(CoreError::PolicyViolation(_))));
+        from_selection(DustPolicy::Relay).unwrap();
+
+        // Sweeps that would leave less than the floor can't be built at all
+        let sweep_fee = fee_for_weight(fees::tx_weight(&[weight], &[destination().script_pubkey().len()]), 2).unwrap();
+        let small = utxo(sweep_fee + 900, 0);
+        build_unvault(small.clone(), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        match build_unvault(
+            small.clone(),
+            destination(),
+            2,
+            &metadata(144),
+            SpendOptions { dust: strict, ..Default::default() },
+        ) {
+            Err(CoreError::InsufficientFunds { needed, .. }) => assert_eq!(needed, sweep_fee + 1_000),
+            other => panic!("Expected InsufficientFunds, got {:?}", other),
+        }
+        let small = [utxo(5_000, 0)];
+        build_recovery(&small, None, destination(), 2, SpendOptions::default()).unwrap();
+        assert!(matches!(
+            build_recovery(
+                &small,
+                None,
+                destination(),
+                2,
+                SpendOptions { dust: DustPolicy::Floor(5_000), ..Default::default() },
+            ),
+            Err(CoreError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_build_exact_amount_has_no_change() {
+        // Exactly the destination plus the fee for a change-less spend
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        let fee = fee_for_weight(fees::tx_weight(&[weight], &[destination().script_pubkey().len()]), 2).unwrap();
+        let bundle =
+            build_partial_unvault(
+                utxo(40_000 + fee, 0),
+                destination(),
+                40_000,
+                &change_to(1),
+                2,
+                &metadata(144),
+                SpendOptions::default(),
+            )
+                .unwrap();
+        assert_eq!(bundle.change, ChangeOutcome::None);
+        assert_eq!(bundle.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt_fee(&bundle.psbt), fee);
+
+        let selection = crate::vault::coins::Selection {
+            utxos: vec![utxo(40_000 + fee, 0)],
+            total_input_sats: 40_000 + fee,
+            target_sats: 40_000,
+            fee_sats: fee,
+            change_sats: 0,
+        };
+        let bundle = build_unvault_from_selection(&selection, destination(), &change_to(1), &metadata(144), None, None, DustPolicy::Relay)
+            .unwrap();
+        assert_eq!(bundle.change, ChangeOutcome::None);
+        assert_eq!(bundle.psbt.unsigned_tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_change_outcome_json() {
+        let json = |change: ChangeOutcome| serde_json::to_value(change).unwrap();
+        assert_eq!(json(ChangeOutcome::None), serde_json::json!({"kind": "none"}));
+        assert_eq!(
+            json(ChangeOutcome::Output {
+                vault_index: 4,
+                vout: 1,
+                amount_sats: 5_000
+            }),
+            serde_json::json!({"kind": "output", "vault_index": 4, "vout": 1, "amount_sats": 5_000})
+        );
+        assert_eq!(
+            json(ChangeOutcome::AddedToFee {
+                amount_sats: 200,
+                dust_limit_sats: 330
+            }),
+            serde_json::json!({"kind": "added_to_fee", "amount_sats": 200, "dust_limit_sats": 330})
+        );
+    }
+
+    #[test]
+    fn test_build_unvault_insufficient_funds() {
+        let err = build_partial_unvault(
+            utxo(10_000, 0),
+            destination(),
+            10_000,
+            &change_to(1),
+            1,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+            .unwrap_err();
+        match err {
+            CoreError::InsufficientFunds { needed, available } => {
+                assert!(needed > 10_000);
+                assert_eq!(available, 10_000);
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = build_unvault(utxo(300, 0), destination(), 1, &metadata(144), SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::InsufficientFunds { available: 300, .. }));
+    }
+
+    #[test]
+    fn test_builders_reject_fee_rate_below_min_relay() {
+        let err = build_unvault(utxo(100_000, 0), destination(), 0, &metadata(144), SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+
+        let err = build_recovery(&[utxo(100_000, 0)], None, destination(), 0, SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+    }
+
+    #[test]
+    fn test_build_unvault_rejects_short_delay() {
+        let err = build_unvault(utxo(100_000, 0), destination(), 1, &metadata(143), SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+
+        let err = build_unvault(utxo(100_000, 0), destination(), 1, &metadata(0), SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+    }
+
+    #[test]
+    fn test_build_unvault_time_based_delay() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let template = custom_template(CustomFields {
+            delay_unit: DelayUnit::TimeUnits512s,
+            recovery_type: RecoveryType::EmergencyKey,
+            ..Default::default()
+        });
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Regtest).unwrap();
+        let time_utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&"ef".repeat(32)).unwrap(), 0), 100_000, tree);
+        let time_metadata = VaultMetadata {
+            delay_unit: DelayUnit::TimeUnits512s,
+            ..metadata(144)
+        };
+
+        let mut psbt = build_unvault(time_utxo.clone(), destination(), 2, &time_metadata, SpendOptions::default()).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence.to_consensus_u32(), 0x0040_0090);
+
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        keys::sign_psbt(&mut psbt, &owner_xpriv().into(), Network::Regtest).unwrap();
+        verify_consensus(&[prevout], &finalize(&mut psbt).unwrap());
+
+        // A block count never satisfies a time lock, and vice versa
+        let err = build_unvault(time_utxo, destination(), 2, &metadata(144), SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+        let err = build_unvault(utxo(100_000, 0), destination(), 2, &time_metadata, SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+    }
+
+    #[test]
+    fn test_build_unvault_dual_delay_picks_leaf() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let template = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        let tree = vault_tree(&template, &owner, &recovery, 1, Network::Regtest).unwrap();
+        let dual_utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&"ab".repeat(32)).unwrap(), 1), 100_000, tree);
+        let dual_metadata = VaultMetadata {
+            delay_blocks: 1008,
+            whitelist_delay: Some(144),
+            ..metadata(1008)
+        };
+        let mut approved = ApprovedDestinations::new(Network::Regtest);
+        approved.push("exchange", destination()).unwrap();
+
+        let mut psbt = build_unvault(
+            dual_utxo.clone(),
+            destination(),
+            2,
+            &dual_metadata,
+            SpendOptions { approved: Some(&approved), ..Default::default() },
+        )
+        .unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence::from_height(144));
+        let scripts: Vec<_> = psbt.inputs[0].tap_scripts.values().map(|(script, _)| script.clone()).collect();
+        assert_eq!(scripts, vec![dual_utxo.tree.leaf(LeafPurpose::WhitelistTimelock).unwrap().script.clone()]);
+
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        keys::sign_psbt(&mut psbt, &owner_xpriv().into(), Network::Regtest).unwrap();
+        verify_consensus(&[prevout], &finalize(&mut psbt).unwrap());
+
+        // Without the destination in the list, the unvault waits the open delay
+        for approved in [None, Some(&ApprovedDestinations::new(Network::Regtest))] {
+            let psbt = build_unvault(
+                dual_utxo.clone(),
+                destination(),
+                2,
+                &dual_metadata,
+                SpendOptions { approved, ..Default::default() },
+            )
+            .unwrap();
+            assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence::from_height(1008));
+            let (script, _) = psbt.inputs[0].tap_scripts.values().next().unwrap();
+            assert_eq!(*script, dual_utxo.tree.leaf(LeafPurpose::Timelock).unwrap().script);
+        }
+    }
+
+    #[test]
+    fn test_input_weight_matches_signed_witness() {
+        let tree = psbt_tree();
+        let leaf = tree.leaf(LeafPurpose::Timelock).unwrap();
+        let cb = taproot::control_block(&tree, LeafPurpose::Timelock).unwrap();
+
+        let mut witness = Witness::new();
+        witness.push([0u8; SCHNORR_SIG_SIZE]);
+        witness.push(leaf.script.as_bytes());
+        witness.push(cb.serialize());
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::from_height(144),
+                witness,
+            }],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: destination().script_pubkey(),
+            }],
+        };
+
+        let weight = fees::leaf_input_weight(&tree, LeafPurpose::Timelock).unwrap();
+        let estimate = fees::tx_weight(&[weight], &[tx.output[0].script_pubkey.len()]);
+        assert_eq!(estimate, tx.weight().to_wu() as usize);
+    }
+
+    fn dummy_signature() -> bitcoin::taproot::Signature {
+        bitcoin::taproot::Signature::from_slice(&[1u8; SCHNORR_SIG_SIZE]).unwrap()
+    }
+
+    fn psbt_fee(psbt: &Psbt) -> u64 {
+        let inputs: u64 = psbt.inputs.iter().map(|i| i.witness_utxo.as_ref().unwrap().value).sum();
+        inputs - psbt.unsigned_tx.output.iter().map(|o| o.value).sum::<u64>()
+    }
+
+    #[test]
+    fn test_bump_fee_sweep_reduces_destination() {
+        let mut original = build_unvault(
+            utxo(100_000, 0),
+            destination(),
+            2,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+        .unwrap();
+        let key = *original.inputs[0].tap_key_origins.keys().next().unwrap();
+        let leaf_hash = psbt_tree().leaf_hash(LeafPurpose::Timelock).unwrap();
+        original.inputs[0].tap_script_sigs.insert((key, leaf_hash), dummy_signature());
+
+        let bumped = bump_fee(&original, 5, DustPolicy::Relay).unwrap();
+        let weight = fees::leaf_input_weight(&psbt_tree(), LeafPurpose::Timelock).unwrap();
+        let expected_fee = fee_for_weight(fees::tx_weight(&[weight], &[destination().script_pubkey().len()]), 5).unwrap();
+        assert_eq!(psbt_fee(&bumped), expected_fee);
+        assert_eq!(bumped.unsigned_tx.output[0].value, 100_000 - expected_fee);
+
+        // Same inputs and sequence, signatures gone, leaf data intact
+        assert_eq!(bumped.unsigned_tx.input, original.unsigned_tx.input);
+        assert!(bumped.unsigned_tx.input[0].sequence.is_rbf());
+        assert!(bumped.inputs[0].tap_script_sigs.is_empty());
+        assert_eq!(bumped.inputs[0].tap_scripts, original.inputs[0].tap_scripts);
+        assert_ne!(bumped.unsigned_tx.txid(), original.unsigned_tx.txid());
+    }
+
+    #[test]
+    fn test_bump_fee_takes_from_change() {
+        // Change at a fresh index is recognized by its internal key
+        let original =
+            build_partial_unvault(
+                utxo(100_000, 0),
+                destination(),
+                40_000,
+                &change_to(6),
+                2,
+                &metadata(144),
+                SpendOptions::default(),
+            )
+                .unwrap()
+                .psbt;
+        let bumped = bump_fee(&original, 10, DustPolicy::Relay).unwrap();
+
+        let tx = &bumped.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, 40_000);
+        assert_eq!(
+            original.unsigned_tx.output[1].value - tx.output[1].value,
+            psbt_fee(&bumped) - psbt_fee(&original)
+        );
+        assert_eq!(bumped.outputs[1], original.outputs[1]);
+    }
+
+    #[test]
+    fn test_bump_fee_drops_dust_change() {
+        let original =
+            build_partial_unvault(
+                utxo(40_700, 0),
+                destination(),
+                40_000,
+                &change_to(1),
+                1,
+                &metadata(144),
+                SpendOptions::default(),
+            )
+                .unwrap()
+                .psbt;
+        assert_eq!(original.unsigned_tx.output.len(), 2);
+
+        let bumped = bump_fee(&original, 3, DustPolicy::Relay).unwrap();
+        assert_eq!(bumped.unsigned_tx.output.len(), 1);
+        assert_eq!(bumped.outputs.len(), 1);
+        assert_eq!(bumped.unsigned_tx.output[0].value, 40_000);
+        assert_eq!(psbt_fee(&bumped), 700);
+    }
+
+    #[test]
+    fn test_bump_fee_requires_incremental_relay_fee() {
+        let original = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        for rate in [0, 1, 2] {
+            let err = bump_fee(&original, rate, DustPolicy::Relay).unwrap_err();
+            assert!(matches!(err, CoreError::PolicyViolation(_)), "rate {}: {:?}", rate, err);
+        }
+        assert!(bump_fee(&original, 3, DustPolicy::Relay).is_ok());
+    }
+
+    #[test]
+    fn test_bump_fee_rejects_non_replaceable_and_underfunded() {
+        let mut original = build_unvault(
+            utxo(100_000, 0),
+            destination(),
+            2,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+        .unwrap();
+        original.unsigned_tx.input[0].sequence = Sequence::MAX;
+        assert!(matches!(bump_fee(&original, 5, DustPolicy::Relay), Err(CoreError::PolicyViolation(_))));
+
+        let original = build_unvault(utxo(1_000, 0), destination(), 1, &metadata(144), SpendOptions::default()).unwrap();
+        let err = bump_fee(&original, 50, DustPolicy::Relay).unwrap_err();
+        assert!(matches!(err, CoreError::InsufficientFunds { available: 1_000, .. }));
+    }
+
+    #[test]
+    fn test_bump_fee_recovery() {
+        let utxos = vec![utxo(50_000, 0), utxo(70_000, 5)];
+        let original = build_recovery(&utxos, None, destination(), 3, SpendOptions::default()).unwrap();
+        let bumped = bump_fee(&original, 20, DustPolicy::Relay).unwrap();
+
+        let weights: Vec<usize> = utxos
+            .iter()
+            .map(|u| fees::leaf_input_weight(&u.tree, LeafPurpose::Emergency).unwrap())
+            .collect();
+        let expected_fee =
+            fee_for_weight(fees::tx_weight(&weights, &[destination().script_pubkey().len()]), 20).unwrap();
+        assert_eq!(psbt_fee(&bumped), expected_fee);
+    }
+
+    #[test]
+    fn test_build_recovery_mixed_indices() {
+        let utxos = vec![utxo(50_000, 0), utxo(70_000, 5), utxo(30_000, 12)];
+        let psbt = build_recovery(&utxos, None, destination(), 3, SpendOptions::default()).unwrap();
+        let tx = &psbt.unsigned_tx;
+
+        assert_eq!(tx.input.len(), 3);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, destination().script_pubkey());
+        for txin in &tx.input {
+            assert_eq!(txin.sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        }
+
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        for (utxo, input) in utxos.iter().zip(&psbt.inputs) {
+            let leaf = utxo.tree.leaf(LeafPurpose::Emergency).unwrap();
+            let (cb, (script, _)) = input.tap_scripts.iter().next().unwrap();
+            assert_eq!(script, &leaf.script);
+            assert!(taproot::verify_control_block(cb, script, &utxo.tree.output_key().to_inner()));
+            assert_eq!(input.witness_utxo.as_ref(), Some(&utxo.txout()));
+
+            let (_, (fingerprint, _)) = input.tap_key_origins.values().next().unwrap();
+            assert_eq!(*fingerprint, recovery.fingerprint());
+        }
+
+        let weights: Vec<usize> = utxos
+            .iter()
+            .map(|u| fees::leaf_input_weight(&u.tree, LeafPurpose::Emergency).unwrap())
+            .collect();
+        let fee = 150_000 - tx.output[0].value;
+        assert_eq!(fee, fee_for_weight(fees::tx_weight(&weights, &[tx.output[0].script_pubkey.len()]), 3).unwrap());
+    }
+
+    #[test]
+    fn test_build_recovery_empty_utxos() {
+        let err = build_recovery(&[], None, destination(), 1, SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::InvalidInput(_)));
+    }
+
+    #[test]
+    fn test_build_recovery_requires_emergency_leaf() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let template = custom_template(CustomFields::default());
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Regtest).unwrap();
+        let utxo = VaultUtxo::new(OutPoint::null(), 100_000, tree);
+
+        let err = build_recovery(&[utxo], None, destination(), 1, SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::PolicyViolation(_)));
+    }
+
+    fn absolute_lock_utxo(lock: u32, unit: AbsoluteLockUnit) -> VaultUtxo {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let template = custom_template(CustomFields {
+            absolute_lock: Some(lock),
+            absolute_lock_unit: unit,
+            ..Default::default()
+        });
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Regtest).unwrap();
+        VaultUtxo::new(OutPoint::null(), 100_000, tree)
+    }
+
+    #[test]
+    fn test_build_recovery_absolute_lock_height() {
+        let recover = |utxos: &[VaultUtxo], height| {
+            build_recovery(
+                utxos,
+                None,
+                destination(),
+                1,
+                SpendOptions { current_block_height: height, ..Default::default() },
+            )
+        };
+        let locked = absolute_lock_utxo(1_000_000, AbsoluteLockUnit::Height);
+
+        match recover(std::slice::from_ref(&locked), Some(999_999)) {
+            Err(CoreError::TimelockNotExpired { required, current }) => assert_eq!((required, current), (1_000_000, 999_999)),
+            other => panic!("expected TimelockNotExpired, got {:?}", other),
+        }
+
+        for height in [None, Some(1_000_000)] {
+            let psbt = recover(std::slice::from_ref(&locked), height).unwrap();
+            assert_eq!(psbt.unsigned_tx.lock_time, LockTime::from_height(1_000_000).unwrap());
+            assert!(psbt.unsigned_tx.is_lock_time_enabled());
+            let leaf = locked.tree.leaf(LeafPurpose::AbsoluteLock).unwrap();
+            assert!(psbt.inputs[0].tap_scripts.values().any(|(script, _)| *script == leaf.script));
+        }
+        // Past the lock, anti-fee-sniping stays within 99 blocks of the tip
+        let psbt = recover(std::slice::from_ref(&locked), Some(1_000_500)).unwrap();
+        let lock_height = psbt.unsigned_tx.lock_time.to_consensus_u32();
+        assert!((1_000_401..=1_000_500).contains(&lock_height), "{}", lock_height);
+
+        // The latest lock of several inputs wins; emergency inputs add none
+        let later = absolute_lock_utxo(1_000_100, AbsoluteLockUnit::Height);
+        let psbt = recover(&[locked.clone(), later.clone(), utxo(50_000, 0)], None).unwrap();
+        assert_eq!(psbt.unsigned_tx.lock_time, LockTime::from_height(1_000_100).unwrap());
+        assert!(matches!(
+            recover(&[locked, later], Some(1_000_050)),
+            Err(CoreError::TimelockNotExpired { required: 1_000_100, current: 1_000_050 })
+        ));
+    }
+
+    #[test]
+    fn test_build_recovery_absolute_lock_timestamp() {
+        let locked = absolute_lock_utxo(1_700_000_000, AbsoluteLockUnit::Seconds);
+        // A height says nothing about median time past, so only nLockTime enforces it
+        for height in [None, Some(800_000)] {
+            let psbt = build_recovery(
+                std::slice::from_ref(&locked),
+                None,
+                destination(),
+                1,
+                SpendOptions { current_block_height: height, ..Default::default() },
+            )
+            .unwrap();
+            assert_eq!(psbt.unsigned_tx.lock_time, LockTime::from_time(1_700_000_000).unwrap());
+            assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        }
+
+        let by_height = absolute_lock_utxo(1_000_000, AbsoluteLockUnit::Height);
+        match build_recovery(&[locked, by_height], None, destination(), 1, SpendOptions::default()) {
+            Err(CoreError::PolicyViolation(msg)) => assert!(msg.contains("mix block height and timestamp"), "{}", msg),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    const MEMO: &[u8] = b"WD-2026-0042";
+
+    #[test]
+    fn test_build_unvault_memo_output() {
+        let plain = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        let psbt =
+            build_unvault(
+                utxo(100_000, 0),
+                destination(),
+                2,
+                &metadata(144),
+                SpendOptions { memo: Some(MEMO.to_vec()), ..Default::default() },
+            )
+            .unwrap();
+
+        let outputs = &psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1].value, 0);
+        // OP_RETURN, push of 12 bytes, the memo
+        assert_eq!(hex::encode(outputs[1].script_pubkey.as_bytes()), format!("6a0c{}", hex::encode(MEMO)));
+
+        // The 23-byte output (value, length, 14-byte script) costs 23 vB
+        assert_eq!(psbt_fee(&psbt) - psbt_fee(&plain), 23 * 2);
+        assert_eq!(outputs[0].value, plain.unsigned_tx.output[0].value - 46);
+        assert!(fees::check_standardness(&psbt).unwrap().is_standard());
+
+        // Bumping keeps the memo and takes the fee from the destination
+        let bumped = bump_fee(&psbt, 4, DustPolicy::Relay).unwrap();
+        assert_eq!(bumped.unsigned_tx.output[1], outputs[1]);
+        assert!(bumped.unsigned_tx.output[0].value < outputs[0].value);
+    }
+
+    #[test]
+    fn test_build_partial_unvault_memo_follows_change() {
+        let bundle = build_partial_unvault(
+            utxo(100_000, 0),
+            destination(),
+            40_000,
+            &change_to(3),
+            2,
+            &metadata(144),
+            SpendOptions { memo: Some(MEMO.to_vec()), ..Default::default() },
+        )
+        .unwrap();
+        let outputs = &bundle.psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 3);
+        assert!(matches!(bundle.change, ChangeOutcome::Output { vout: 1, .. }));
+        assert!(outputs[2].script_pubkey.is_op_return());
+        assert_eq!(outputs[2].value, 0);
+    }
+
+    #[test]
+    fn test_build_recovery_memo_output() {
+        let utxos = [utxo(60_000, 0), utxo(40_000, 1)];
+        let plain = build_recovery(&utxos, None, destination(), 3, SpendOptions::default()).unwrap();
+        let psbt = build_recovery(
+            &utxos,
+            None,
+            destination(),
+            3,
+            SpendOptions { memo: Some(MEMO.to_vec()), ..Default::default() },
+        )
+        .unwrap();
+
+        assert_eq!(psbt.unsigned_tx.output[1].script_pubkey.as_bytes()[2..], *MEMO);
+        assert_eq!(psbt_fee(&psbt) - psbt_fee(&plain), 23 * 3);
+    }
+
+    #[test]
+    fn test_memo_limits() {
+        let max = vec![0xaa; fees::MAX_OP_RETURN_PAYLOAD];
+        let psbt = build_recovery(
+            &[utxo(100_000, 0)],
+            None,
+            destination(),
+            2,
+            SpendOptions { memo: Some(max), ..Default::default() },
+        )
+        .unwrap();
+        assert!(fees::check_standardness(&psbt).unwrap().is_standard());
+
+        let over = vec![0xaa; fees::MAX_OP_RETURN_PAYLOAD + 1];
+        assert!(matches!(
+            build_recovery(
+                &[utxo(100_000, 0)],
+                None,
+                destination(),
+                2,
+                SpendOptions { memo: Some(over.clone()), ..Default::default() },
+            ),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        assert!(matches!(
+            build_unvault(
+                utxo(100_000, 0),
+                destination(),
+                2,
+                &metadata(144),
+                SpendOptions { memo: Some(over), ..Default::default() },
+            ),
+            Err(CoreError::PolicyViolation(_))
+        ));
+        assert!(matches!(
+            build_recovery(
+                &[utxo(100_000, 0)],
+                None,
+                destination(),
+                2,
+                SpendOptions { memo: Some(vec![]), ..Default::default() },
+            ),
+            Err(CoreError::InvalidInput(_))
+        ));
+
+        // A memo alone moves no value
+        let memo = memo_script(MEMO).unwrap();
+        assert!(matches!(push_memo(&mut vec![], Some(memo.clone())), Err(CoreError::PolicyViolation(_))));
+        let mut zero = vec![TxOut { value: 0, script_pubkey: memo.clone() }];
+        assert!(matches!(push_memo(&mut zero, Some(memo)), Err(CoreError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_build_recovery_insufficient_funds() {
+        let err = build_recovery(&[utxo(400, 0)], None, destination(), 5, SpendOptions::default()).unwrap_err();
+        assert!(matches!(err, CoreError::InsufficientFunds { available: 400, .. }));
+    }
+
+    fn vault_utxos(vault: &Vault, amount_sats: u64, count: u32) -> Vec<VaultUtxo> {
+        (0..count)
+            .map(|index| {
+                let outpoint = OutPoint::new(Txid::from_str(&"ef".repeat(32)).unwrap(), index);
+                VaultUtxo::new(outpoint, amount_sats, vault.tree_at(index).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_consolidation_fee_estimate_50_inputs() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let utxos = vault_utxos(&vault, 20_000, 50);
+        let consolidation = build_consolidation(&utxos, 50, 3, &vault).unwrap();
+        assert_eq!(consolidation.vault_index, 50);
+        assert_eq!(consolidation.total_input_sats, 1_000_000);
+        assert!(consolidation.fee_warning.is_none());
+
+        let mut psbt = consolidation.psbt;
+        assert!(psbt.unsigned_tx.input.iter().all(|i| i.sequence == Sequence::from_height(144)));
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.unsigned_tx.output[0].script_pubkey, vault.tree_at(50).unwrap().script_pubkey());
+        assert_eq!(psbt.outputs[0].tap_internal_key, Some(taproot::nums_internal_key(50).unwrap()));
+        assert_eq!(psbt_fee(&psbt), consolidation.fee_sats);
+
+        let prevouts: Vec<TxOut> = utxos.iter().map(VaultUtxo::txout).collect();
+        assert_eq!(keys::sign_psbt(&mut psbt, &owner_xpriv().into(), Network::Regtest).unwrap(), 50);
+        let tx = finalize(&mut psbt).unwrap();
+        verify_consensus(&prevouts, &tx);
+
+        // Leaf weights are exact, so the fee pays for the signed size precisely
+        assert_eq!(consolidation.fee_sats, fee_for_weight(tx.weight().to_wu() as usize, 3).unwrap());
+        assert_eq!(consolidation.fee_sats, tx.vsize() as u64 * 3);
+    }
+
+    #[test]
+    fn test_build_consolidation_uses_key_path_when_enabled() {
+        let vault = regtest_vault(custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            key_path_enabled: true,
+            ..Default::default()
+        }), 0);
+        let utxos = vault_utxos(&vault, 30_000, 3);
+        let consolidation = build_consolidation(&utxos, 7, 2, &vault).unwrap();
+
+        let mut psbt = consolidation.psbt;
+        assert!(psbt.unsigned_tx.input.iter().all(|i| i.sequence == Sequence::ENABLE_RBF_NO_LOCKTIME));
+        assert!(psbt.inputs.iter().all(|input| input.tap_scripts.is_empty()));
+
+        let prevouts: Vec<TxOut> = utxos.iter().map(VaultUtxo::txout).collect();
+        assert_eq!(sign_key_path(&mut psbt, &owner_xpriv().into()).unwrap(), 3);
+        let tx = finalize(&mut psbt).unwrap();
+        verify_consensus(&prevouts, &tx);
+        assert_eq!(consolidation.fee_sats, fee_for_weight(tx.weight().to_wu() as usize, 2).unwrap());
+    }
+
+    #[test]
+    fn test_build_consolidation_fee_warning() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let utxos = vault_utxos(&vault, 2_000, 10);
+
+        let consolidation = build_consolidation(&utxos, 10, 5, &vault).unwrap();
+        assert!(consolidation.fee_sats * 100 > 20_000 * DEFAULT_CONSOLIDATION_FEE_WARNING_PERCENT);
+        assert!(consolidation.fee_warning.unwrap().contains("more than 5%"));
+
+        let lenient = build_consolidation_with_fee_warning(&utxos, 10, 5, &vault, 50).unwrap();
+        assert_eq!(lenient.fee_sats, consolidation.fee_sats);
+        assert!(lenient.fee_warning.is_none());
+    }
+
+    #[test]
+    fn test_build_consolidation_errors() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let utxos = vault_utxos(&vault, 20_000, 3);
+
+        assert!(matches!(build_consolidation(&[], 3, 2, &vault), Err(CoreError::InvalidInput(_))));
+        // Consolidating into an index being spent would reuse its address
+        assert!(matches!(build_consolidation(&utxos, 1, 2, &vault), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(
+            build_consolidation(&vault_utxos(&vault, 300, 3), 3, 2, &vault),
+            Err(CoreError::InsufficientFunds { available: 900, .. })
+        ));
+    }
+
+    fn owner_xpriv() -> ExtendedPrivKey {
+        // BIP32 test vector 1 master, the private half of OWNER_TPUB
+        let mut xpriv = ExtendedPrivKey::from_str("xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi").unwrap();
+        xpriv.network = bitcoin::Network::Regtest;
+        xpriv
+    }
+
+    fn cosigner(seed: u8) -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[seed; 32]).unwrap()
+    }
+
+    fn cosigner_xpubs() -> Vec<String> {
+        let secp = Secp256k1::new();
+        (1..=3)
+            .map(|seed| ExtendedPubKey::from_priv(&secp, &cosigner(seed)).to_string())
+            .collect()
+    }
+
+    /// Vault with 2-of-3 multisig recovery by the `cosigner()` keys
+    fn multisig_template() -> VaultTemplate {
+        custom_template(CustomFields {
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery { threshold: 2, cosigners: cosigner_xpubs() }),
+            ..Default::default()
+        })
+    }
+
+    /// Unsigned PSBT spending a 2-of-3 multisig vault through its multisig leaf
+    fn multisig_psbt() -> Psbt {
+        leaf_psbt(&multisig_template(), LeafPurpose::Multisig, Sequence::ENABLE_RBF_NO_LOCKTIME)
+    }
+
+    /// Unsigned PSBT spending a vault of `template` through `leaf`
+    fn leaf_psbt(template: &VaultTemplate, leaf: LeafPurpose, sequence: Sequence) -> Psbt {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let tree = vault_tree(template, &owner, &recovery, 2, Network::Regtest).unwrap();
+        let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&"cd".repeat(32)).unwrap(), 0), 80_000, tree);
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::default(),
+            }],
+            output: vec![TxOut {
+                value: 79_000,
+                script_pubkey: destination().script_pubkey(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0] = script_path_input(&utxo, leaf).unwrap();
+        psbt
+    }
+
+    fn verify_consensus(prevouts: &[TxOut], tx: &Transaction) {
+        let spent_outputs: Vec<bitcoinconsensus::Utxo> = prevouts
+            .iter()
+            .map(|txout| bitcoinconsensus::Utxo {
+                script_pubkey: txout.script_pubkey.as_bytes().as_ptr(),
+                script_pubkey_len: txout.script_pubkey.len() as u32,
+                value: txout.value as i64,
+            })
+            .collect();
+        let tx_bytes = bitcoin::consensus::serialize(tx);
+        for (i, prevout) in prevouts.iter().enumerate() {
+            bitcoinconsensus::verify_with_flags(
+                prevout.script_pubkey.as_bytes(),
+                prevout.value,
+                &tx_bytes,
+                Some(&spent_outputs),
+                i,
+                bitcoinconsensus::VERIFY_ALL_PRE_TAPROOT | bitcoinconsensus::VERIFY_TAPROOT,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_finalize_timelock_leaf() {
+        let mut psbt = build_unvault(utxo(100_000, 1), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let leaf_script = psbt.inputs[0].tap_scripts.values().next().unwrap().0.clone();
+        keys::sign_psbt(&mut psbt, &owner_xpriv().into(), Network::Regtest).unwrap();
+
+        let tx = finalize(&mut psbt).unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness.nth(0).unwrap().len(), SCHNORR_SIG_SIZE);
+        assert_eq!(witness.nth(1).unwrap(), leaf_script.as_bytes());
+        verify_consensus(&[prevout], &tx);
+
+        // Per-input signing data is cleared, the UTXO is kept
+        let input = &psbt.inputs[0];
+        assert!(input.tap_scripts.is_empty());
+        assert!(input.tap_script_sigs.is_empty());
+        assert!(input.tap_key_origins.is_empty());
+        assert!(input.tap_internal_key.is_none());
+        assert!(input.witness_utxo.is_some());
+        assert_eq!(input.final_script_witness.as_ref(), Some(witness));
+    }
+
+    #[test]
+    fn test_finalize_multisig_leaf_stack_order() {
+        let mut psbt = multisig_psbt();
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let script = psbt.inputs[0].tap_scripts.values().next().unwrap().0.clone();
+        let signers = taproot::leaf_signers(&script).unwrap();
+
+        keys::sign_psbt(&mut psbt, &cosigner(1).into(), Network::Regtest).unwrap();
+        keys::sign_psbt(&mut psbt, &cosigner(3).into(), Network::Regtest).unwrap();
+        let signed: Vec<bool> = signers
+            .keys
+            .iter()
+            .map(|key| psbt.inputs[0].tap_script_sigs.keys().any(|(k, _)| k == key))
+            .collect();
+
+        let tx = finalize(&mut psbt).unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 5);
+        // Items are in reverse key order: the last key's item comes first
+        for (j, has_sig) in signed.iter().rev().enumerate() {
+            let expected_len = if *has_sig { SCHNORR_SIG_SIZE } else { 0 };
+            assert_eq!(witness.nth(j).unwrap().len(), expected_len);
+        }
+        verify_consensus(&[prevout], &tx);
+    }
+
+    const PREIMAGE: [u8; 32] = [7; 32];
+
+    /// Timelock-only vault whose recovery service, `cosigner(9)`, sweeps
+    /// with `PREIMAGE`
+    fn hashlock_template() -> VaultTemplate {
+        custom_template(CustomFields {
+            hashlock: Some(HashlockRecovery {
+                hash: sha256::Hash::hash(&PREIMAGE),
+                service_xpub: ExtendedPubKey::from_priv(&Secp256k1::new(), &cosigner(9)).to_string(),
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_attach_preimage_validation() {
+        let mut psbt = leaf_psbt(&hashlock_template(), LeafPurpose::Hashlock, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        assert!(matches!(attach_preimage(&mut psbt, 0, &PREIMAGE[1..]), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(attach_preimage(&mut psbt, 0, &[8; 32]), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(attach_preimage(&mut psbt, 1, &PREIMAGE), Err(CoreError::InvalidInput(_))));
+        assert!(psbt.inputs[0].proprietary.is_empty());
+
+        // Emergency leaves have no hash to match
+        let mut emergency = multisig_psbt();
+        assert!(matches!(attach_preimage(&mut emergency, 0, &PREIMAGE), Err(CoreError::PolicyViolation(_))));
+
+        attach_preimage(&mut psbt, 0, &PREIMAGE).unwrap();
+        let hash = sha256::Hash::hash(&PREIMAGE).to_byte_array();
+        assert_eq!(input_preimage(&psbt.inputs[0], hash), Some(&PREIMAGE[..]));
+        // A tampered record no longer counts
+        psbt.inputs[0].proprietary.values_mut().for_each(|value| value[0] ^= 1);
+        assert_eq!(input_preimage(&psbt.inputs[0], hash), None);
+    }
+
+    #[test]
+    fn test_finalize_hashlock_leaf() {
+        let template = hashlock_template();
+        let mut psbt = leaf_psbt(&template, LeafPurpose::Hashlock, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let script = psbt.inputs[0].tap_scripts.values().next().unwrap().0.clone();
+        keys::sign_psbt(&mut psbt, &cosigner(9).into(), Network::Regtest).unwrap();
+
+        match finalize(&mut psbt.clone()) {
+            Err(CoreError::PsbtError(msg)) => assert!(msg.contains("missing the preimage"), "{}", msg),
+            other => panic!("expected a missing preimage error, got {:?}", other),
+        }
+
+        attach_preimage(&mut psbt, 0, &PREIMAGE).unwrap();
+        let tx = finalize(&mut psbt).unwrap();
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 4);
+        // The preimage is on top of the signature, where OP_SHA256 reads it
+        assert_eq!(witness.nth(0).unwrap().len(), SCHNORR_SIG_SIZE);
+        assert_eq!(witness.nth(1).unwrap(), PREIMAGE);
+        assert_eq!(witness.nth(2).unwrap(), script.as_bytes());
+        verify_consensus(&[prevout], &tx);
+
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let tree = vault_tree(&template, &owner, &recovery, 2, Network::Regtest).unwrap();
+        let weight = fees::leaf_input_weight(&tree, LeafPurpose::Hashlock).unwrap();
+        let estimate = fees::tx_weight(&[weight], &[tx.output[0].script_pubkey.len()]);
+        assert_eq!(estimate, tx.weight().to_wu() as usize);
+    }
+
+    #[test]
+    fn test_build_recovery_hashlock_leaf() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let tree = vault_tree(&hashlock_template(), &owner, &recovery, 0, Network::Regtest).unwrap();
+        let leaf = tree.leaf(LeafPurpose::Hashlock).unwrap().script.clone();
+        let utxo = VaultUtxo::new(OutPoint::null(), 100_000, tree);
+
+        let psbt = build_recovery(&[utxo], None, destination(), 1, SpendOptions::default()).unwrap();
+        let scripts: Vec<_> = psbt.inputs[0].tap_scripts.values().map(|(script, _)| script.clone()).collect();
+        assert_eq!(scripts, vec![leaf]);
+    }
+
+    #[test]
+    fn test_build_recovery_leaf_selection() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let mut template = hashlock_template();
+        if let VaultTemplate::Custom { recovery_type, .. } = &mut template {
+            *recovery_type = RecoveryType::EmergencyKey;
+        }
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Regtest).unwrap();
+        let utxo = VaultUtxo::new(OutPoint::null(), 100_000, tree.clone());
+        let build = |leaf| build_recovery(std::slice::from_ref(&utxo), leaf, destination(), 1, SpendOptions::default());
+
+        // The emergency leaf comes first unless the hashlock leaf is asked for
+        for (leaf, expected) in [
+            (None, LeafPurpose::Emergency),
+            (Some(LeafPurpose::Emergency), LeafPurpose::Emergency),
+            (Some(LeafPurpose::Hashlock), LeafPurpose::Hashlock),
+        ] {
+            let psbt = build(leaf).unwrap();
+            let scripts: Vec<_> = psbt.inputs[0].tap_scripts.values().map(|(script, _)| script.clone()).collect();
+            assert_eq!(scripts, vec![tree.leaf(expected).unwrap().script.clone()]);
+        }
+
+        assert!(matches!(build(Some(LeafPurpose::AbsoluteLock)), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(build(Some(LeafPurpose::Timelock)), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_build_stage_spend() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let template = VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+            cosigners: vec![cosigner_xpubs().remove(0)],
+        };
+        let tree = vault_tree(&template, &owner, &recovery, 0, Network::Regtest).unwrap();
+        let stage_utxo = VaultUtxo::new(OutPoint::null(), 100_000, tree.clone());
+
+        for (stage, sequence) in [
+            (0, Sequence::ENABLE_RBF_NO_LOCKTIME),
+            (1, Sequence::from_height(4032)),
+            (2, Sequence::from_height(26_208)),
+        ] {
+            let psbt =
+                build_stage_spend(std::slice::from_ref(&stage_utxo), stage, destination(), 1, SpendOptions::default())
+                    .unwrap();
+            assert_eq!(psbt.unsigned_tx.input[0].sequence, sequence);
+            let scripts: Vec<_> = psbt.inputs[0].tap_scripts.values().map(|(script, _)| script.clone()).collect();
+            assert_eq!(scripts, vec![tree.leaf(LeafPurpose::DegradingStage(stage as u8)).unwrap().script.clone()]);
+        }
+
+        let build = |utxo: &VaultUtxo, stage| build_stage_spend(
+            std::slice::from_ref(utxo),
+            stage,
+            destination(),
+            1,
+            SpendOptions::default(),
+        );
+        assert!(matches!(build(&stage_utxo, 3), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(build(&stage_utxo, 256), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(build(&utxo(100_000, 0), 0), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(
+            build_stage_spend(&[], 0, destination(), 1, SpendOptions::default()),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_estimated_fee_matches_finalized_tx() {
+        let degrading = VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+            cosigners: vec![cosigner_xpubs().remove(0)],
+        };
+        for (template, leaf, signers) in [
+            (VaultTemplate::Savings { delay_blocks: 144 }, LeafPurpose::Timelock, vec![owner_xpriv()]),
+            (multisig_template(), LeafPurpose::Timelock, vec![owner_xpriv()]),
+            (degrading, LeafPurpose::DegradingStage(1), vec![owner_xpriv(), cosigner(1)]),
+        ] {
+            let vault = regtest_vault(template, 0);
+            let utxo = vault.utxo(OutPoint::new(Txid::from_str(&"ab".repeat(32)).unwrap(), 0), 100_000);
+            let destination = vault.address();
+            let mut psbt = match leaf {
+                LeafPurpose::DegradingStage(stage) => {
+                    build_stage_spend(&[utxo], stage as usize, destination, 3, SpendOptions::default())
+                }
+                _ => build_unvault(utxo, destination, 3, &vault.metadata(), SpendOptions::default()),
+            }
+            .unwrap();
+            let estimate = vault.estimate_fee(leaf, signers.len(), 1, 3).unwrap();
+            assert_eq!(psbt_fee(&psbt), estimate);
+
+            for signer in signers.iter().copied() {
+                keys::sign_psbt(&mut psbt, &signer.into(), Network::Regtest).unwrap();
+            }
+            let tx = finalize(&mut psbt).unwrap();
+            assert_eq!(vault.spend_weight(leaf, signers.len()).unwrap(), tx.input[0].segwit_weight() as u64);
+            let actual = tx.vsize() as u64 * 3;
+            assert!(estimate.abs_diff(actual) <= 3, "{:?}: estimated {} vs {}", leaf, estimate, actual);
+        }
+    }
+
+    #[test]
+    fn test_status_multisig_recovery_stages() {
+        let config = VaultConfig {
+            template: multisig_template(),
+            ..regtest_config()
+        };
+        let mut psbt = multisig_psbt();
+        let secp = Secp256k1::new();
+        let fingerprints: Vec<Fingerprint> = (1..=3).map(|seed| cosigner(seed).fingerprint(&secp)).collect();
+        // Which cosigners have signed, in seed order
+        let signed_by = |status: &PsbtStatus| -> Vec<bool> {
+            fingerprints
+                .iter()
+                .map(|fingerprint| {
+                    status.inputs[0]
+                        .signers
+                        .iter()
+                        .any(|s| s.signed && s.fingerprint.as_ref() == Some(fingerprint))
+                })
+                .collect()
+        };
+
+        let unsigned = status(&psbt, &config).unwrap();
+        let input = &unsigned.inputs[0];
+        assert_eq!(input.vault_index, Some(2));
+        assert_eq!(input.spend_path, Some(fees::SpendPath::MultisigLeaf { threshold: 2, total: 3 }));
+        assert_eq!((input.required, input.signed), (2, 0));
+        assert!(!input.finalizable && !input.finalized && !unsigned.ready);
+        assert_eq!(unsigned.fee_sats, 1_000);
+        let mut listed: Vec<Fingerprint> = input.signers.iter().filter_map(|s| s.fingerprint).collect();
+        listed.sort();
+        let mut expected = fingerprints.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        keys::sign_psbt(&mut psbt, &cosigner(1).into(), Network::Regtest).unwrap();
+        let one = status(&psbt, &config).unwrap();
+        assert_eq!(one.inputs[0].signed, 1);
+        assert_eq!(signed_by(&one), [true, false, false]);
+        assert!(!one.inputs[0].finalizable && !one.ready);
+
+        keys::sign_psbt(&mut psbt, &cosigner(3).into(), Network::Regtest).unwrap();
+        let two = status(&psbt, &config).unwrap();
+        assert_eq!(two.inputs[0].signed, 2);
+        assert_eq!(signed_by(&two), [true, false, true]);
+        assert!(two.inputs[0].finalizable && !two.inputs[0].finalized && two.ready);
+
+        let tx = finalize(&mut psbt).unwrap();
+        let finalized = status(&psbt, &config).unwrap();
+        let input = &finalized.inputs[0];
+        assert!(input.finalized && input.finalizable && finalized.ready);
+        assert_eq!(input.spend_path, two.inputs[0].spend_path);
+        // Origins are cleared on finalizing; the witness still shows who signed
+        assert_eq!(input.signed, 2);
+        let signed_keys = |input: &InputStatus| -> Vec<(XOnlyPublicKey, bool)> {
+            input.signers.iter().map(|s| (s.pubkey, s.signed)).collect()
+        };
+        assert_eq!(signed_keys(input), signed_keys(&two.inputs[0]));
+        // The pre-signing estimate holds for the final transaction
+        assert_eq!(finalized.estimated_vsize, tx.vsize() as u64);
+        assert!(two.estimated_vsize >= finalized.estimated_vsize);
+        assert_eq!(finalized.fee_sats, 1_000);
+    }
+
+    #[test]
+    fn test_status_key_path_and_missing_utxo() {
+        let config = VaultConfig {
+            template: multisig_template(),
+            ..regtest_config()
+        };
+        let mut psbt = multisig_psbt();
+        psbt.inputs[0].tap_key_sig = Some(dummy_signature());
+        let report = status(&psbt, &config).unwrap();
+        let input = &report.inputs[0];
+        assert_eq!(input.spend_path, Some(fees::SpendPath::KeyPath));
+        assert_eq!((input.required, input.signed), (1, 1));
+        assert_eq!(Some(input.signers[0].pubkey), p2tr_output_key(psbt.inputs[0].witness_utxo.as_ref().unwrap()));
+
+        psbt.inputs[0].witness_utxo = None;
+        assert!(matches!(status(&psbt, &config), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_recovery_anchor_output() {
+        let secp = Secp256k1::new();
+        let anchor_key = cosigner(7).to_keypair(&secp).x_only_public_key().0;
+        let utxos = [utxo(100_000, 0)];
+        let plain = build_recovery(&utxos, None, destination(), 2, SpendOptions::default()).unwrap();
+        let psbt = build_recovery(
+            &utxos,
+            None,
+            destination(),
+            2,
+            SpendOptions { anchor: Some(anchor_key), ..Default::default() },
+        )
+        .unwrap();
+
+        let outputs = &psbt.unsigned_tx.output;
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1].value, ANCHOR_VALUE_SATS);
+        assert_eq!(outputs[1].script_pubkey, anchor_script(anchor_key));
+        // The anchor is key-path only, to the key itself tweaked with no scripts
+        let tweaked = bitcoin::key::TapTweak::tap_tweak(anchor_key, &secp, None).0.to_inner();
+        assert_eq!(p2tr_output_key(&outputs[1]), Some(tweaked));
+        // Its 43 vB are paid for at the recovery's rate
+        assert_eq!(psbt_fee(&psbt), psbt_fee(&plain) + 43 * 2);
+        assert_eq!(outputs[0].value, 100_000 - psbt_fee(&psbt) - ANCHOR_VALUE_SATS);
+    }
+
+    /// Recovery of a 100,000 sat UTXO at 2 sat/vB with an anchor to
+    /// `anchor_key`, signed by a known recovery key and finalized, with
+    /// its fee and spent output
+    fn signed_recovery(anchor_key: XOnlyPublicKey) -> (Transaction, u64, TxOut) {
+        let secp = Secp256k1::new();
+        let recovery_key = cosigner(9);
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_priv(&secp, &recovery_key);
+        let tree = vault_tree(&VaultTemplate::spending(), &owner, &recovery, 0, Network::Regtest).unwrap();
+        let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&"ab".repeat(32)).unwrap(), 1), 100_000, tree);
+
+        let mut psbt = build_recovery(
+            std::slice::from_ref(&utxo),
+            None,
+            destination(),
+            2,
+            SpendOptions { anchor: Some(anchor_key), ..Default::default() },
+        )
+        .unwrap();
+        let fee = psbt_fee(&psbt);
+        assert_eq!(keys::sign_psbt(&mut psbt, &recovery_key.into(), Network::Regtest).unwrap(), 1);
+        let tx = finalize(&mut psbt).unwrap();
+        verify_consensus(&[utxo.txout()], &tx);
+        (tx, fee, utxo.txout())
+    }
+
+    #[test]
+    fn test_cpfp_package_fee_rate() {
+        let secp = Secp256k1::new();
+        let operator = cosigner(7);
+        let anchor_key = operator.to_keypair(&secp).x_only_public_key().0;
+        let (parent, parent_fee, _) = signed_recovery(anchor_key);
+        // Sized with its witness, the parent is well above its unsigned size
+        let mut unsigned = parent.clone();
+        unsigned.input.iter_mut().for_each(|txin| txin.witness.clear());
+        assert!(parent.vsize() > unsigned.vsize() + 30);
+
+        // The anchor alone can't pay for the package at 20 sat/vB
+        assert!(matches!(
+            build_cpfp(&parent, parent_fee, 1, &operator.into(), &[], 20),
+            Err(CoreError::InsufficientFunds { .. })
+        ));
+
+        let wallet = cosigner(8);
+        let wallet_key = wallet.to_keypair(&secp).x_only_public_key().0;
+        let extras = [WalletUtxo {
+            outpoint: OutPoint::new(Txid::from_str(&"ef".repeat(32)).unwrap(), 3),
+            txout: TxOut {
+                value: 50_000,
+                script_pubkey: anchor_script(wallet_key),
+            },
+        }];
+        let mut child = build_cpfp(&parent, parent_fee, 1, &operator.into(), &extras, 20).unwrap();
+        assert_eq!(child.unsigned_tx.input[0].previous_output, OutPoint::new(parent.txid(), 1));
+        assert!(child.inputs[0].tap_key_sig.is_some());
+        assert!(child.inputs[1].tap_key_sig.is_none());
+        assert_eq!(child.unsigned_tx.output[0].script_pubkey, anchor_script(anchor_key));
+
+        // The wallet signs its own input, then the package pays the target
+        child.inputs[1].tap_internal_key = Some(wallet_key);
+        child.inputs[1].tap_key_origins = [(wallet_key, (vec![], (wallet.fingerprint(&secp), DerivationPath::master())))].into();
+        sign_key_path(&mut child, &wallet.into()).unwrap();
+        let child_fee = psbt_fee(&child);
+        let tx = finalize(&mut child).unwrap();
+        verify_consensus(&[parent.output[1].clone(), extras[0].txout.clone()], &tx);
+        let package_vsize = (parent.vsize() + tx.vsize()) as u64;
+        assert!(parent_fee + child_fee >= 20 * package_vsize);
+        assert!(parent_fee + child_fee < 21 * package_vsize);
+
+        // A parent already above the target leaves the child paying its own way
+        let child = build_cpfp(&parent, parent_fee, 1, &operator.into(), &extras, 2).unwrap();
+        assert_eq!(psbt_fee(&child), 2 * tx.vsize() as u64);
+    }
+
+    #[test]
+    fn test_cpfp_rejects_wrong_anchor() {
+        let secp = Secp256k1::new();
+        let anchor_key = cosigner(7).to_keypair(&secp).x_only_public_key().0;
+        let (parent, _, _) = signed_recovery(anchor_key);
+        for (index, key) in [(0, cosigner(7)), (1, cosigner(8)), (2, cosigner(7))] {
+            assert!(matches!(
+                build_cpfp(&parent, 1_000, index, &key.into(), &[], 1),
+                Err(CoreError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_cpfp_rejects_unsigned_parent() {
+        let secp = Secp256k1::new();
+        let anchor_key = cosigner(7).to_keypair(&secp).x_only_public_key().0;
+        let recovery = build_recovery(
+            &[utxo(100_000, 0)],
+            None,
+            destination(),
+            2,
+            SpendOptions { anchor: Some(anchor_key), ..Default::default() },
+        )
+        .unwrap();
+        match build_cpfp(&recovery.unsigned_tx, psbt_fee(&recovery), 1, &cosigner(7).into(), &[], 1) {
+            Err(CoreError::InvalidInput(msg)) => assert!(msg.contains("has no witness"), "{}", msg),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_bump_fee_anchored_recovery() {
+        let secp = Secp256k1::new();
+        let anchor_key = cosigner(7).to_keypair(&secp).x_only_public_key().0;
+        let original = build_recovery(
+            &[utxo(100_000, 0)],
+            None,
+            destination(),
+            2,
+            SpendOptions { anchor: Some(anchor_key), ..Default::default() },
+        )
+        .unwrap();
+        let bumped = bump_fee(&original, 10, DustPolicy::Relay).unwrap();
+
+        // The anchor is kept as it is; the destination pays the extra fee
+        let outputs = &bumped.unsigned_tx.output;
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[1], original.unsigned_tx.output[1]);
+        assert_eq!(outputs[0].script_pubkey, destination().script_pubkey());
+        assert!(psbt_fee(&bumped) > psbt_fee(&original));
+        assert_eq!(outputs[0].value, 100_000 - psbt_fee(&bumped) - ANCHOR_VALUE_SATS);
+    }
+
+    fn wallet_utxo(amount_sats: u64, vout: u32, taproot: bool) -> ExternalUtxo {
+        let secp = Secp256k1::new();
+        let wallet = cosigner(9);
+        let pubkey = wallet.private_key.public_key(&secp);
+        let script_pubkey = if taproot {
+            ScriptBuf::new_v1_p2tr(&secp, pubkey.x_only_public_key().0, None)
+        } else {
+            ScriptBuf::new_v0_p2wpkh(&bitcoin::PublicKey::new(pubkey).wpubkey_hash().unwrap())
+        };
+        ExternalUtxo {
+            outpoint: OutPoint::new(Txid::from_str(&"cd".repeat(32)).unwrap(), vout),
+            amount_sats,
+            script_pubkey,
+            key_origin: Some((pubkey, (wallet.fingerprint(&secp), DerivationPath::master()))),
+        }
+    }
+
+    #[test]
+    fn test_build_deposit() {
+        let inputs = [wallet_utxo(60_000, 0, false), wallet_utxo(50_000, 1, true)];
+        let vault_address = psbt_tree().address(Network::Regtest);
+        let psbt = build_deposit(&inputs, vault_address.clone(), 100_000, destination(), 2).unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, LockTime::ZERO);
+        assert!(tx.input.iter().all(|txin| txin.sequence == Sequence::ENABLE_RBF_NO_LOCKTIME));
+        assert_eq!(tx.output[0].value, 100_000);
+        assert_eq!(tx.output[0].script_pubkey, vault_address.script_pubkey());
+        assert_eq!(tx.output[1].script_pubkey, destination().script_pubkey());
+
+        let weight = fees::tx_weight(
+            &[fees::wallet_input_weight(&inputs[0].script_pubkey).unwrap(), fees::input_weight(fees::SpendPath::KeyPath, 0)],
+            &[tx.output[0].script_pubkey.len(), tx.output[1].script_pubkey.len()],
+        );
+        assert_eq!(psbt_fee(&psbt), fee_for_weight(weight, 2).unwrap());
+
+        // Each input carries what the wallet needs to sign it
+        assert_eq!(psbt.inputs[0].witness_utxo.as_ref().unwrap().value, 60_000);
+        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
+        assert!(psbt.inputs[0].tap_internal_key.is_none());
+        let internal_key = psbt.inputs[1].tap_internal_key.unwrap();
+        assert!(psbt.inputs[1].tap_key_origins.contains_key(&internal_key));
+        assert!(psbt.inputs[1].bip32_derivation.is_empty());
+
+        // Change below dust goes to the fee
+        let input_weights: Vec<usize> = inputs.iter().map(|utxo| fees::wallet_input_weight(&utxo.script_pubkey).unwrap()).collect();
+        let no_change_fee = fee_for_weight(fees::tx_weight(&input_weights, &[tx.output[0].script_pubkey.len()]), 2).unwrap();
+        let amount_sats = 110_000 - no_change_fee - 100;
+        let psbt = build_deposit(&inputs, vault_address, amount_sats, destination(), 2).unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt_fee(&psbt), no_change_fee + 100);
+    }
+
+    #[test]
+    fn test_build_deposit_errors() {
+        let vault_address = psbt_tree().address(Network::Regtest);
+        let deposit = |inputs: &[ExternalUtxo], amount_sats| {
+            build_deposit(inputs, vault_address.clone(), amount_sats, destination(), 2)
+        };
+
+        assert!(matches!(deposit(&[], 10_000), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(deposit(&[wallet_utxo(50_000, 0, true)], 100), Err(CoreError::PolicyViolation(_))));
+        assert!(matches!(
+            deposit(&[wallet_utxo(50_000, 0, true)], 50_000),
+            Err(CoreError::InsufficientFunds { available: 50_000, .. })
+        ));
+
+        let mut p2pkh = wallet_utxo(50_000, 0, false);
+        p2pkh.script_pubkey = ScriptBuf::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+        assert!(matches!(deposit(&[p2pkh], 10_000), Err(CoreError::InvalidInput(_))));
+
+        // A key origin for a key the output doesn't pay
+        let mut mismatched = wallet_utxo(50_000, 0, true);
+        mismatched.key_origin.as_mut().unwrap().0 = cosigner(10).private_key.public_key(&Secp256k1::new());
+        assert!(matches!(deposit(&[mismatched], 10_000), Err(CoreError::InvalidInput(_))));
+
+        // Without a key origin, only the witness UTXO is set
+        let mut bare = wallet_utxo(50_000, 0, false);
+        bare.key_origin = None;
+        let psbt = deposit(&[bare], 10_000).unwrap();
+        assert!(psbt.inputs[0].witness_utxo.is_some());
+        assert!(psbt.inputs[0].bip32_derivation.is_empty());
+    }
+
+    #[test]
+    fn test_finalize_inheritance_leaf() {
+        let template = VaultTemplate::Inheritance {
+            heir_threshold: 2,
+            heir_count: 3,
+            inactivity_blocks: 26_000,
+            heirs: cosigner_xpubs(),
+        };
+        let mut psbt = leaf_psbt(&template, LeafPurpose::Inheritance, Sequence::from_height(26_000));
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+
+        keys::sign_psbt(&mut psbt, &cosigner(2).into(), Network::Regtest).unwrap();
+        keys::sign_psbt(&mut psbt, &cosigner(3).into(), Network::Regtest).unwrap();
+
+        let tx = finalize(&mut psbt).unwrap();
+        // Three key items, the script and the control block
+        assert_eq!(tx.input[0].witness.len(), 5);
+        verify_consensus(&[prevout], &tx);
+    }
+
+    #[test]
+    fn test_finalize_extra_signatures_use_threshold() {
+        let mut psbt = multisig_psbt();
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        for seed in 1..=3 {
+            keys::sign_psbt(&mut psbt, &cosigner(seed).into(), Network::Regtest).unwrap();
+        }
+
+        let tx = finalize(&mut psbt).unwrap();
+        let sigs = tx.input[0].witness.iter().take(3).filter(|item| !item.is_empty()).count();
+        assert_eq!(sigs, 2);
+        verify_consensus(&[prevout], &tx);
+    }
+
+    #[test]
+    fn test_finalize_reports_missing_signatures() {
+        let mut psbt = multisig_psbt();
+        keys::sign_psbt(&mut psbt, &cosigner(2).into(), Network::Regtest).unwrap();
+
+        match finalize(&mut psbt).unwrap_err() {
+            CoreError::PsbtError(msg) => {
+                assert!(msg.contains("Input 0"), "{}", msg);
+                assert!(msg.contains("missing 1 signature"), "{}", msg);
+            }
+            other => panic!("Expected PsbtError, got {:?}", other),
+        }
+
+        let mut unsigned = build_unvault(
+            utxo(100_000, 0),
+            destination(),
+            2,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+        .unwrap();
+        match finalize(&mut unsigned).unwrap_err() {
+            CoreError::PsbtError(msg) => assert!(msg.contains("missing 1 signature"), "{}", msg),
+            other => panic!("Expected PsbtError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_finalize_rejects_misplaced_signature() {
+        let mut psbt = build_unvault(utxo(100_000, 1), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        keys::sign_psbt(&mut psbt, &owner_xpriv().into(), Network::Regtest).unwrap();
+
+        // Corrupt the signature so it no longer verifies for the leaf key
+        let sig = psbt.inputs[0].tap_script_sigs.values_mut().next().unwrap();
+        let mut bytes = sig.sig.as_ref().to_vec();
+        bytes[0] ^= 1;
+        sig.sig = bitcoin::secp256k1::schnorr::Signature::from_slice(&bytes).unwrap();
+
+        assert!(matches!(finalize(&mut psbt), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_sighashes_by_spend_path() {
+        let utxo = utxo(100_000, 1);
+        let tree = utxo.tree.clone();
+        let psbt = build_unvault(utxo, destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+
+        let infos = sighashes(&psbt, fees::SpendPath::TimelockLeaf).unwrap();
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let owner_key = keys::derive_vault_key(&owner, 1, Network::Regtest).unwrap().public_key;
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].input_index, 0);
+        assert_eq!(infos[0].pubkey, owner_key);
+        assert_eq!(infos[0].leaf_hash, tree.leaf_hash(LeafPurpose::Timelock));
+        assert_eq!(infos[0].sighash_type, 0);
+        assert_eq!(infos[0].message.len(), 64);
+
+        // The key path signs a different message, with the output key
+        let key_path = sighashes(&psbt, fees::SpendPath::KeyPath).unwrap();
+        assert_eq!(key_path[0].pubkey, tree.output_key().to_inner());
+        assert_eq!(key_path[0].leaf_hash, None);
+        assert_ne!(key_path[0].message, infos[0].message);
+
+        // Only the timelock leaf is in the PSBT
+        assert!(matches!(sighashes(&psbt, fees::SpendPath::EmergencyLeaf), Err(CoreError::PsbtError(_))));
+
+        let json = serde_json::to_value(&infos[0]).unwrap();
+        assert_eq!(json["leaf_hash"], tree.leaf_hash(LeafPurpose::Timelock).unwrap().to_string());
+        assert_eq!(json["pubkey"], owner_key.to_string());
+    }
+
+    #[test]
+    fn test_sighashes_for_multisig_cosigners() {
+        let psbt = multisig_psbt();
+        let script = &psbt.inputs[0].tap_scripts.values().next().unwrap().0;
+        let signers = taproot::leaf_signers(script).unwrap();
+
+        let infos = sighashes(&psbt, fees::SpendPath::MultisigLeaf { threshold: 2, total: 3 }).unwrap();
+        assert_eq!(infos.iter().map(|info| info.pubkey).collect::<Vec<_>>(), signers.keys);
+        assert!(infos.iter().all(|info| info.message == infos[0].message));
+
+        for spend_path in [
+            fees::SpendPath::MultisigLeaf { threshold: 3, total: 3 },
+            fees::SpendPath::TimelockLeaf,
+        ] {
+            assert!(sighashes(&psbt, spend_path).is_err(), "{:?}", spend_path);
+        }
+    }
+
+    /// Schnorr signature over `message` (hex) by the child of `xpriv` at
+    /// the origin of `pubkey` in input 0
+    fn external_signature(psbt: &Psbt, xpriv: &ExtendedPrivKey, pubkey: &XOnlyPublicKey, message: &str) -> bitcoin::taproot::Signature {
+        let secp = Secp256k1::new();
+        let (_, (_, path)) = &psbt.inputs[0].tap_key_origins[pubkey];
+        let keypair = xpriv.derive_priv(&secp, path).unwrap().private_key.keypair(&secp);
+        let msg = Message::from_slice(&hex::decode(message).unwrap()).unwrap();
+        bitcoin::taproot::Signature {
+            sig: secp.sign_schnorr(&msg, &keypair),
+            hash_ty: bitcoin::sighash::TapSighashType::Default,
+        }
+    }
+
+    #[test]
+    fn test_apply_signature_verifies_against_sighash() {
+        let mut psbt = multisig_psbt();
+        let prevout = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let infos = sighashes(&psbt, fees::SpendPath::MultisigLeaf { threshold: 2, total: 3 }).unwrap();
+        // Keys are in script order, cosigners in xpub order
+        let cosigners: Vec<_> = infos
+            .iter()
+            .map(|info| {
+                let fingerprint = psbt.inputs[0].tap_key_origins[&info.pubkey].1 .0;
+                (1..=3).map(cosigner).find(|xpriv| xpriv.fingerprint(&Secp256k1::new()) == fingerprint).unwrap()
+            })
+            .collect();
+
+        let sig = external_signature(&psbt, &cosigners[0], &infos[0].pubkey, &infos[0].message);
+        // Signed by the right key, but reported for another
+        match apply_signature(&mut psbt, 0, infos[1].pubkey, sig).unwrap_err() {
+            CoreError::SigningError { input_index: 0, reason } => assert!(reason.contains("does not verify"), "{}", reason),
+            other => panic!("expected SigningError, got {:?}", other),
+        }
+        // A signature over some other digest
+        let wrong = external_signature(&psbt, &cosigners[0], &infos[0].pubkey, &"11".repeat(32));
+        assert!(matches!(
+            apply_signature(&mut psbt, 0, infos[0].pubkey, wrong),
+            Err(CoreError::SigningError { .. })
+        ));
+        let all = bitcoin::taproot::Signature {
+            hash_ty: bitcoin::sighash::TapSighashType::All,
+            ..sig
+        };
+        assert!(matches!(apply_signature(&mut psbt, 0, infos[0].pubkey, all), Err(CoreError::SigningError { .. })));
+        let owner_key = keys::derive_vault_key(&ExtendedPubKey::from_str(OWNER_TPUB).unwrap(), 2, Network::Regtest)
+            .unwrap()
+            .public_key;
+        assert!(matches!(apply_signature(&mut psbt, 0, owner_key, sig), Err(CoreError::SigningError { .. })));
+        assert!(matches!(apply_signature(&mut psbt, 1, infos[0].pubkey, sig), Err(CoreError::InvalidInput(_))));
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+
+        for j in [0, 2] {
+            let sig = external_signature(&psbt, &cosigners[j], &infos[j].pubkey, &infos[j].message);
+            apply_signature(&mut psbt, 0, infos[j].pubkey, sig).unwrap();
+        }
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 2);
+        verify_consensus(&[prevout], &finalize(&mut psbt).unwrap());
+    }
+
+    #[test]
+    fn test_base64_roundtrip() {
+        let psbt = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        assert_eq!(from_base64(&to_base64(&psbt)).unwrap(), psbt);
+        assert!(matches!(from_base64("not base64!"), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_combine_merges_cosigner_signatures() {
+        let unsigned = multisig_psbt();
+        let mut copies = vec![unsigned.clone(), unsigned.clone(), unsigned.clone()];
+        keys::sign_psbt(&mut copies[0], &cosigner(1).into(), Network::Regtest).unwrap();
+        keys::sign_psbt(&mut copies[1], &cosigner(3).into(), Network::Regtest).unwrap();
+        // A cosigner who sent the same signature twice
+        copies[2] = copies[1].clone();
+
+        let status = signature_status(&copies[0], 2);
+        assert_eq!(status.inputs, vec![InputSignatures { input_index: 0, present: 1, required: 2 }]);
+        assert!(!status.complete);
+
+        let prevout = unsigned.inputs[0].witness_utxo.clone().unwrap();
+        let mut combined = combine(&copies).unwrap();
+        assert_eq!(combined.inputs[0].tap_script_sigs.len(), 2);
+        assert_eq!(combined.inputs[0].tap_key_origins, unsigned.inputs[0].tap_key_origins);
+        assert!(signature_status(&combined, 2).complete);
+
+        let tx = finalize(&mut combined).unwrap();
+        verify_consensus(&[prevout], &tx);
+        assert!(signature_status(&combined, 2).complete);
+    }
+
+    #[test]
+    fn test_combine_rejects_different_transactions() {
+        let psbt = multisig_psbt();
+        let combine_err = |other: Psbt| match combine(&[psbt.clone(), other]).unwrap_err() {
+            CoreError::PsbtError(msg) => msg,
+            other => panic!("Expected PsbtError, got {:?}", other),
+        };
+
+        let mut other = psbt.clone();
+        other.unsigned_tx.output[0].value -= 1;
+        assert_eq!(combine_err(other), "PSBT 1 has a different output 0 value than PSBT 0");
+
+        let mut other = psbt.clone();
+        other.unsigned_tx.input[0].sequence = Sequence::from_height(144);
+        assert_eq!(combine_err(other), "PSBT 1 has a different input 0 sequence than PSBT 0");
+
+        let other = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        assert!(combine_err(other).contains("input 0 outpoint"));
+
+        assert!(matches!(combine(&[]), Err(CoreError::PsbtError(_))));
+        assert_eq!(combine(std::slice::from_ref(&psbt)).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_parse_any_detects_encoding() {
+        let mut psbt = multisig_psbt();
+        // Bytes that aren't text-safe: NUL, high bytes, invalid UTF-8
+        psbt.inputs[0].unknown.insert(
+            bitcoin::psbt::raw::Key { type_value: 0xf0, key: vec![0x00, 0xff, 0x80] },
+            (0..=255).collect(),
+        );
+        let binary = psbt.serialize();
+        assert!(std::str::from_utf8(&binary).is_err());
+
+        assert_eq!(parse_any(&to_base64(&psbt)).unwrap(), psbt);
+        assert_eq!(parse_any(&to_hex(&psbt)).unwrap(), psbt);
+        assert_eq!(parse_any(&to_hex(&psbt).to_uppercase()).unwrap(), psbt);
+        assert_eq!(parse_any(&format!("  {}\n", to_base64(&psbt))).unwrap(), psbt);
+        assert_eq!(parse_any_bytes(&binary).unwrap(), psbt);
+        assert_eq!(parse_any_bytes(to_base64(&psbt).as_bytes()).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_parse_any_rejects_malformed_input() {
+        let psbt_err = |result: Result<Psbt, CoreError>| match result.unwrap_err() {
+            CoreError::PsbtError(msg) => msg,
+            other => panic!("Expected PsbtError, got {:?}", other),
+        };
+        let hex = to_hex(&multisig_psbt());
+
+        assert!(psbt_err(parse_any(&hex[..hex.len() - 1])).contains("Odd number of digits"));
+        let mut bad_char = hex.clone();
+        bad_char.replace_range(20..21, "g");
+        assert!(psbt_err(parse_any(&bad_char)).contains("position 20"));
+        assert!(psbt_err(parse_any("cHNidP8B!")).contains("offset 8"));
+        assert!(psbt_err(parse_any_bytes(&[0xff, 0xfe])).contains("byte 0"));
+        assert!(psbt_err(from_hex("00ff")).contains("magic"));
+        assert_eq!(psbt_err(parse_any("  ")), "Empty PSBT");
+        // Magic followed by garbage
+        assert!(psbt_err(parse_any_bytes(b"psbt\xff\x01")).starts_with("Invalid PSBT"));
+    }
+
+    #[test]
+    fn test_builders_set_anti_fee_sniping_lock_time() {
+        let height = 800_000;
+        for _ in 0..50 {
+            let unvault = build_unvault(
+                utxo(100_000, 0),
+                destination(),
+                2,
+                &metadata(144),
+                SpendOptions { current_block_height: Some(height), ..Default::default() },
+            )
+            .unwrap();
+            let recovery = build_recovery(
+                &[utxo(100_000, 0)],
+                None,
+                destination(),
+                2,
+                SpendOptions { current_block_height: Some(height), ..Default::default() },
+            )
+            .unwrap();
+            for tx in [&unvault.unsigned_tx, &recovery.unsigned_tx] {
+                let LockTime::Blocks(lock_height) = tx.lock_time else {
+                    panic!("expected a height locktime, got {:?}", tx.lock_time);
+                };
+                assert!((height - 99..=height).contains(&lock_height.to_consensus_u32()));
+                assert!(tx.is_lock_time_enabled());
+            }
+            // The relative lock is untouched
+            assert_eq!(unvault.unsigned_tx.input[0].sequence, Sequence::from_height(144));
+            assert_eq!(recovery.unsigned_tx.input[0].sequence, Sequence::ENABLE_RBF_NO_LOCKTIME);
+        }
+
+        let unvault = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        assert_eq!(unvault.unsigned_tx.lock_time, LockTime::ZERO);
+
+        // Timestamps aren't heights
+        assert!(matches!(
+            build_recovery(
+                &[utxo(100_000, 0)],
+                None,
+                destination(),
+                2,
+                SpendOptions { current_block_height: Some(500_000_000), ..Default::default() },
+            ),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_bump_fee_keeps_lock_time() {
+        let original = build_unvault(
+            utxo(100_000, 0),
+            destination(),
+            2,
+            &metadata(144),
+            SpendOptions { current_block_height: Some(800_000), ..Default::default() },
+        )
+        .unwrap();
+        let bumped = bump_fee(&original, 10, DustPolicy::Relay).unwrap();
+        assert_eq!(bumped.unsigned_tx.lock_time, original.unsigned_tx.lock_time);
+    }
+
+    fn proprietary_key(prefix: &[u8], subtype: u8) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: prefix.to_vec(),
+            subtype,
+            key: vec![],
+        }
+    }
+
+    #[test]
+    fn test_vault_info_round_trip() {
+        let mut psbt = build_unvault(utxo(100_000, 3), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        assert!(read_vault_info(&psbt).unwrap().is_none());
+
+        let mut metadata = metadata(144);
+        metadata.vault_index = 3;
+        attach_vault_info(&mut psbt, &metadata, fees::SpendPath::TimelockLeaf).unwrap();
+        let decoded = from_base64(&to_base64(&psbt)).unwrap();
+        let info = read_vault_info(&decoded).unwrap().unwrap();
+        assert_eq!(info.metadata.to_bytes(), metadata.to_bytes());
+        assert_eq!(info.metadata.vault_index, 3);
+        assert_eq!(info.spend_path, fees::SpendPath::TimelockLeaf);
+        assert_eq!(info.library_version, env!("CARGO_PKG_VERSION"));
+
+        // Attaching again replaces the records rather than adding to them
+        let multisig = fees::SpendPath::MultisigLeaf { threshold: 2, total: 3 };
+        let mut decoded = decoded;
+        attach_vault_info(&mut decoded, &metadata, multisig).unwrap();
+        assert_eq!(decoded.proprietary.len(), 3);
+        let decoded = from_base64(&to_base64(&decoded)).unwrap();
+        assert_eq!(read_vault_info(&decoded).unwrap().unwrap().spend_path, multisig);
+
+        let mut incomplete = decoded;
+        incomplete.proprietary.remove(&proprietary_key(VAULT_INFO_PREFIX, VAULT_INFO_LIBRARY_VERSION));
+        assert!(matches!(read_vault_info(&incomplete), Err(CoreError::PsbtError(_))));
+    }
+
+    #[test]
+    fn test_vault_info_preserves_foreign_keys() {
+        let mut psbt = build_unvault(utxo(100_000, 0), destination(), 2, &metadata(144), SpendOptions::default()).unwrap();
+        let foreign = [
+            (proprietary_key(b"othertool", VAULT_INFO_METADATA), vec![0xde, 0xad]),
+            (proprietary_key(b"othertool", 0x42), vec![]),
+            // A subtype from a later version of this library
+            (proprietary_key(VAULT_INFO_PREFIX, 0x7f), b"future".to_vec()),
+        ];
+        psbt.proprietary.extend(foreign.iter().cloned());
+        // Only unknown subtypes: nothing this version can read
+        assert!(read_vault_info(&psbt).unwrap().is_none());
+
+        attach_vault_info(&mut psbt, &metadata(144), fees::SpendPath::KeyPath).unwrap();
+        let decoded = from_base64(&to_base64(&psbt)).unwrap();
+        assert_eq!(decoded.proprietary.len(), foreign.len() + 3);
+        for (key, value) in &foreign {
+            assert_eq!(decoded.proprietary.get(key), Some(value));
+        }
+        assert_eq!(read_vault_info(&decoded).unwrap().unwrap().spend_path, fees::SpendPath::KeyPath);
+    }
+
+    #[test]
+    fn test_builders_attach_vault_info_when_configured() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let utxos = vault_utxos(&vault, 30_000, 3);
+        let plain = build_consolidation(&utxos, 7, 2, &vault).unwrap();
+        assert!(read_vault_info(&plain.psbt).unwrap().is_none());
+
+        let vault = crate::vault::VaultBuilder::new()
+            .template(VaultTemplate::spending())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .psbt_vault_info(true)
+            .build()
+            .unwrap();
+        let consolidation = build_consolidation(&utxos, 7, 2, &vault).unwrap();
+        let info = read_vault_info(&consolidation.psbt).unwrap().unwrap();
+        assert_eq!(info.metadata.to_bytes(), vault.metadata().to_bytes());
+        assert_eq!(info.spend_path, fees::SpendPath::TimelockLeaf);
+
+        // A fee bump keeps the records
+        let mut unvault =
+            build_unvault(utxos[0].clone(), destination(), 2, &vault.metadata(), SpendOptions::default()).unwrap();
+        attach_vault_info(&mut unvault, &vault.metadata(), fees::SpendPath::TimelockLeaf).unwrap();
+        let bumped = bump_fee(&unvault, 5, DustPolicy::Relay).unwrap();
+        assert_eq!(bumped.proprietary, unvault.proprietary);
+    }
+
+    #[test]
+    fn test_built_spend_path_follows_the_leaf() {
+        let owner = ExtendedPubKey::from_str(OWNER_TPUB).unwrap();
+        let recovery = ExtendedPubKey::from_str(RECOVERY_TPUB).unwrap();
+        let tree = vault_tree(&hashlock_template(), &owner, &recovery, 0, Network::Regtest).unwrap();
+        let hashlock_utxo = VaultUtxo::new(OutPoint::null(), 100_000, tree);
+        let recover = |utxo: VaultUtxo, leaf| {
+            build_recovery(&[utxo], leaf, destination(), 1, SpendOptions::default()).unwrap()
+        };
+
+        for (psbt, expected) in [
+            (leaf_psbt(&VaultTemplate::spending(), LeafPurpose::Timelock, Sequence::from_height(144)), fees::SpendPath::TimelockLeaf),
+            (recover(utxo(100_000, 0), None), fees::SpendPath::EmergencyLeaf),
+            (recover(hashlock_utxo.clone(), Some(LeafPurpose::Hashlock)), fees::SpendPath::HashlockLeaf),
+            (recover(absolute_lock_utxo(1_000_000, AbsoluteLockUnit::Height), None), fees::SpendPath::AbsoluteLockLeaf),
+            (multisig_psbt(), fees::SpendPath::MultisigLeaf { threshold: 2, total: 3 }),
+        ] {
+            assert_eq!(built_spend_path(&psbt).unwrap(), expected);
+        }
+
+        let mut key_path = multisig_psbt();
+        key_path.inputs[0].tap_scripts.clear();
+        assert_eq!(built_spend_path(&key_path).unwrap(), fees::SpendPath::KeyPath);
+
+        // The estimate for each new path covers the leaf it names
+        let tree = &hashlock_utxo.tree;
+        assert!(fees::input_weight(fees::SpendPath::HashlockLeaf, 1) >= fees::leaf_input_weight(tree, LeafPurpose::Hashlock).unwrap());
+        let tree = &absolute_lock_utxo(1_700_000_000, AbsoluteLockUnit::Seconds).tree;
+        assert!(
+            fees::input_weight(fees::SpendPath::AbsoluteLockLeaf, 1)
+                >= fees::leaf_input_weight(tree, LeafPurpose::AbsoluteLock).unwrap()
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Vault indices already in use for one set of keys
+//!
+//! Two vaults built at the same index from the same xpubs have the same
+//! address, so their coins can't be told apart. The host keeps an
+//! `IndexLedger` per owner/recovery xpub pair and hands it to
+//! `VaultBuilder::ledger()`, which refuses used indices and picks the
+//! next free one when no index is given.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+/// First hardened index; vault indices stay below it
+const HARDENED: u32 = 0x8000_0000;
+
+/// Set of used vault indices, serialized as a sorted JSON array
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IndexLedger {
+    used: BTreeSet<u32>,
+}
+
+impl IndexLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, index: u32) -> bool {
+        self.used.contains(&index)
+    }
+
+    /// Record `index` as used; fails with `PolicyViolation` if it already is
+    pub fn claim(&mut self, index: u32) -> CoreResult<()> {
+        if !self.used.insert(index) {
+            return Err(CoreError::PolicyViolation(format!("Vault index {} already used", index)));
+        }
+        Ok(())
+    }
+
+    /// Lowest unused non-hardened index, or `None` once all 2^31 are used
+    ///
+    /// Walks the used indices from 0 up to the first gap, so a ledger
+    /// costs time in the length of its dense prefix, however large or
+    /// sparse it is beyond that.
+    pub fn next_free(&self) -> Option<u32> {
+        let mut next = 0;
+        for &index in self.used.range(..HARDENED) {
+            if index != next {
+                break;
+            }
+            next += 1;
+        }
+        (next < HARDENED).then_some(next)
+    }
+
+    /// Used indices in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.used.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.used.is_empty()
+    }
+}
+
+impl FromIterator<u32> for IndexLedger {
+    fn from_iter<I: IntoIterator<Item = u32>>(indices: I) -> Self {
+        IndexLedger { used: indices.into_iter().collect() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_free_fills_gaps() {
+        assert_eq!(IndexLedger::new().next_free(), Some(0));
+        assert_eq!(IndexLedger::from_iter([1, 2]).next_free(), Some(0));
+        assert_eq!(IndexLedger::from_iter([0, 1, 3]).next_free(), Some(2));
+        assert_eq!(IndexLedger::from_iter([0, 1, 2]).next_free(), Some(3));
+        // Hardened entries never count as free space below them
+        assert_eq!(IndexLedger::from_iter([0, HARDENED]).next_free(), Some(1));
+    }
+
+    #[test]
+    fn test_claim_rejects_used_index() {
+        let mut ledger = IndexLedger::from_iter([4]);
+        ledger.claim(5).unwrap();
+        match ledger.claim(4) {
+            Err(CoreError::PolicyViolation(msg)) => assert_eq!(msg, "Vault index 4 already used"),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+        assert_eq!(ledger.iter().collect::<Vec<_>>(), [4, 5]);
+    }
+
+    #[test]
+    fn test_large_sparse_ledger() {
+        // A dense prefix followed by a long sparse tail
+        let dense = 0..100_000;
+        let sparse = (0..100_000u32).map(|i| 200_000 + i * 7919);
+        let mut ledger: IndexLedger = dense.chain(sparse).collect();
+        assert_eq!(ledger.len(), 200_000);
+        assert_eq!(ledger.next_free(), Some(100_000));
+
+        ledger.claim(100_000).unwrap();
+        assert_eq!(ledger.next_free(), Some(100_001));
+        assert!(ledger.contains(200_000 + 99_999 * 7919));
+
+        let json = serde_json::to_string(&ledger).unwrap();
+        assert!(json.starts_with("[0,1,2,"));
+        assert_eq!(serde_json::from_str::<IndexLedger>(&json).unwrap(), ledger);
+    }
+
+    #[test]
+    fn test_ledger_json_is_sorted_set() {
+        let ledger: IndexLedger = serde_json::from_str("[2,0,1,1]").unwrap();
+        assert_eq!(serde_json::to_string(&ledger).unwrap(), "[0,1,2]");
+        assert_eq!(ledger.next_free(), Some(3));
+        assert!(serde_json::from_str::<IndexLedger>("[-1]").is_err());
+    }
+}
@@ -0,0 +1,545 @@
+//! Watch-only restore of a vault from its backup
+//!
+//! A backup holds the vault's descriptor and its metadata blob. The
+//! descriptor carries the keys, the metadata the template and index;
+//! each is checked against the other before a `Vault` is returned.
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::ExtendedPubKey;
+use bitcoin::Script;
+
+use crate::error::{CoreError, CoreResult};
+use crate::keys;
+
+use super::descriptor::{self, DescriptorLeaf, ParsedDescriptor};
+use super::{policy, AbsoluteLockUnit, MultisigRecovery, Network, Vault, VaultBuilder, VaultMetadata, VaultTemplate};
+
+/// Rebuild a vault from `to_core_descriptor()` output and `VaultMetadata` bytes
+///
+/// The delay of the descriptor's CSV leaf must equal the metadata's
+/// delay, or the restore fails with `MetadataError`; so must any other
+/// difference between the descriptor and the vault rebuilt from both.
+/// Templates without a recovery key (timelock-only and inheritance) get
+/// `keys::nums_xpub()` in its place, which no leaf uses. Degrading
+/// vaults take their signers from the first stage's keys and their
+/// stage table from the metadata's `TLV_DEGRADING_STAGES` record; custom
+/// vaults take their absolute lock from `TLV_ABSOLUTE_LOCK`, their
+/// hashlock from `TLV_HASHLOCK`, and their recovery key from the lock's
+/// leaf when there is no emergency leaf.
+///
+/// The tree version comes from the metadata's `TLV_TREE_VERSION` record;
+/// only `TreeVersion::V2` and later trees have a descriptor to restore
+/// from.
+///
+/// The result is watch-only: it derives addresses and trees for
+/// building unsigned PSBTs, and holds no private keys.
+pub fn restore(descriptor: &str, metadata_hex: &str, network: Network) -> CoreResult<Vault> {
+    let parsed = descriptor::parse_core_descriptor(descriptor, network)?;
+    let bytes = hex::decode(metadata_hex.trim())
+        .map_err(|e| CoreError::MetadataError(format!("Invalid metadata hex: {}", e)))?;
+    let metadata = VaultMetadata::from_bytes(&bytes)?;
+
+    let older = parsed
+        .leaves
+        .iter()
+        .find_map(DescriptorLeaf::older)
+        .ok_or_else(|| CoreError::InvalidInput("Descriptor has no CSV leaf".to_string()))?;
+    let expected = metadata.delay_unit.sequence(metadata.delay_blocks)?.to_consensus_u32();
+    if older != expected {
+        return Err(CoreError::MetadataError(format!(
+            "Metadata delay of {} {} does not match the descriptor's older({})",
+            metadata.delay_blocks,
+            metadata.delay_unit.name(),
+            older
+        )));
+    }
+
+    let (owner_xpub, recovery_xpub) = if metadata.template_id == "degrading_v1" {
+        match degrading_signers(&parsed)? {
+            [owner, recovery, ..] => (*owner, *recovery),
+            _ => return Err(CoreError::InvalidInput("Degrading stage has fewer than 2 keys".to_string())),
+        }
+    } else {
+        let owner_xpub = if metadata.key_path_enabled {
+            parsed
+                .internal_key
+                .ok_or_else(|| CoreError::InvalidInput("Descriptor has no owner internal key".to_string()))?
+        } else {
+            timelock_key(&parsed)?
+        };
+        let recovery_xpub = parsed
+            .leaves
+            .iter()
+            .find_map(|leaf| match leaf {
+                DescriptorLeaf::Key(key) | DescriptorLeaf::AbsoluteLock { key, .. } => Some(*key),
+                _ => None,
+            })
+            .unwrap_or_else(|| keys::nums_xpub(network));
+        (owner_xpub, recovery_xpub)
+    };
+
+    let vault = VaultBuilder::new()
+        .template(template(&metadata, &parsed)?)
+        .owner_xpub(owner_xpub.to_string())
+        .recovery_xpub(recovery_xpub.to_string())
+        .network(network)
+        .index(metadata.vault_index)
+        .created_at_block(metadata.created_at_block)
+        .tree_version(metadata.tree_version()?)
+        .build()?;
+
+    if vault.descriptor()? != descriptor.trim() {
+        return Err(CoreError::MetadataError(format!(
+            "Descriptor does not describe a {} vault with this metadata",
+            metadata.template_id
+        )));
+    }
+    Ok(vault)
+}
+
+/// `restore()`, then check the metadata against its on-chain commitment
+///
+/// For vaults built in `MetadataMode::Commitment`: the chain reveals
+/// only `commitment_leaf_hex` and the funded `address`, so the backup's
+/// metadata is trusted only once it hashes to the commitment and the
+/// restored vault's `commitment_tree()` pays to `address` (see
+/// `Vault::verify_commitment()`). A tampered or mismatched backup fails
+/// with `MetadataError`.
+pub fn restore_with_commitment(
+    descriptor: &str,
+    metadata_hex: &str,
+    commitment_leaf_hex: &str,
+    address: &str,
+    network: Network,
+) -> CoreResult<Vault> {
+    let leaf = hex::decode(commitment_leaf_hex.trim())
+        .map_err(|e| CoreError::MetadataError(format!("Invalid commitment leaf hex: {}", e)))?;
+    let address = policy::validate_address(address.trim(), network)?;
+    let vault = restore(descriptor, metadata_hex, network)?;
+    vault.verify_commitment(Script::from_bytes(&leaf), &address.script_pubkey())?;
+    Ok(vault)
+}
+
+fn timelock_key(parsed: &ParsedDescriptor) -> CoreResult<ExtendedPubKey> {
+    parsed
+        .leaves
+        .iter()
+        .find_map(|leaf| match leaf {
+            DescriptorLeaf::Timelock { key, .. } => Some(*key),
+            _ => None,
+        })
+        .ok_or_else(|| CoreError::InvalidInput("Descriptor has no owner timelock leaf".to_string()))
+}
+
+/// Keys of a degrading vault's first stage: owner, recovery, then cosigners
+fn degrading_signers(parsed: &ParsedDescriptor) -> CoreResult<&[ExtendedPubKey]> {
+    parsed
+        .leaves
+        .iter()
+        .find_map(|leaf| match leaf {
+            DescriptorLeaf::Multi { keys, .. } | DescriptorLeaf::DelayedMulti { keys, .. } => Some(keys.as_slice()),
+            _ => None,
+        })
+        .ok_or_else(|| CoreError::InvalidInput("Descriptor has no degrading stage".to_string()))
+}
+
+/// Template named by `metadata`, with cosigner or heir keys from the descriptor
+fn template(metadata: &VaultMetadata, parsed: &ParsedDescriptor) -> CoreResult<VaultTemplate> {
+    let delay_blocks = metadata.delay_blocks;
+    let template = match metadata.template_id.as_str() {
+        "savings_v1" => VaultTemplate::Savings { delay_blocks },
+        "spending_v1" => VaultTemplate::Spending { delay_blocks },
+        "custom_v1" => VaultTemplate::Custom {
+            delay_blocks,
+            delay_unit: metadata.delay_unit,
+            recovery_type: metadata.recovery_type,
+            multisig: parsed.leaves.iter().find_map(|leaf| match leaf {
+                DescriptorLeaf::SortedMulti { threshold, keys } => Some(MultisigRecovery {
+                    threshold: *threshold,
+                    cosigners: keys.iter().map(ToString::to_string).collect(),
+                }),
+                _ => None,
+            }),
+            key_path_enabled: metadata.key_path_enabled,
+            absolute_lock: metadata.absolute_lock().map(|lock| lock.to_consensus_u32()),
+            absolute_lock_unit: match metadata.absolute_lock() {
+                Some(LockTime::Seconds(_)) => AbsoluteLockUnit::Seconds,
+                _ => AbsoluteLockUnit::Height,
+            },
+            hashlock: metadata.hashlock(),
+        },
+        "inheritance_v1" => {
+            let heirs = parsed
+                .leaves
+                .iter()
+                .find_map(|leaf| match leaf {
+                    DescriptorLeaf::DelayedMulti { keys, .. } => Some(keys),
+                    _ => None,
+                })
+                .ok_or_else(|| CoreError::InvalidInput("Descriptor has no inheritance leaf".to_string()))?;
+            let heir_set = metadata
+                .heirs
+                .ok_or_else(|| CoreError::MetadataError("Inheritance metadata has no heirs record".to_string()))?;
+            VaultTemplate::Inheritance {
+                heir_threshold: heir_set.threshold,
+                heir_count: heir_set.count,
+                inactivity_blocks: delay_blocks,
+                heirs: heirs.iter().map(ToString::to_string).collect(),
+            }
+        }
+        "dual_delay_v1" => VaultTemplate::DualDelay {
+            whitelist_delay: metadata.whitelist_delay.ok_or_else(|| {
+                CoreError::MetadataError("Dual-delay metadata has no whitelist_delay record".to_string())
+            })?,
+            open_delay: delay_blocks,
+        },
+        "degrading_v1" => {
+            let signers = degrading_signers(parsed)?;
+            let stages = metadata.degrading_stages();
+            if stages.is_empty() {
+                return Err(CoreError::MetadataError("Degrading metadata has no stages record".to_string()));
+            }
+            VaultTemplate::Degrading {
+                keys: u8::try_from(signers.len())
+                    .map_err(|_| CoreError::InvalidInput(format!("Degrading stage has {} keys", signers.len())))?,
+                stages,
+                cosigners: signers.iter().skip(2).map(ToString::to_string).collect(),
+            }
+        }
+        other => {
+            return Err(CoreError::MetadataError(format!("Unknown template id: {}", other)));
+        }
+    };
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, regtest_builder, CustomFields, OWNER_TPUB, RECOVERY_TPUB};
+    use bitcoin::bip32::ExtendedPrivKey;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1::Secp256k1;
+
+    use crate::taproot::{self, TreeVersion};
+    use crate::vault::{DelayUnit, HashlockRecovery, RecoveryType};
+
+    fn backup(template: VaultTemplate, index: u32) -> (Vault, String, String) {
+        let vault = regtest_builder(template).index(index).build().unwrap();
+        let descriptor = vault.descriptor().unwrap();
+        let metadata_hex = hex::encode(vault.metadata().to_bytes());
+        (vault, descriptor, metadata_hex)
+    }
+
+    #[test]
+    fn test_restore_matches_original() {
+        let time_based = custom_template(CustomFields {
+            delay_blocks: 675,
+            delay_unit: DelayUnit::TimeUnits512s,
+            recovery_type: RecoveryType::EmergencyKey,
+            ..Default::default()
+        });
+        for template in [VaultTemplate::savings(), VaultTemplate::spending(), time_based] {
+            let (original, descriptor, metadata_hex) = backup(template, 7);
+            let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+
+            assert_eq!(restored.index(), 7);
+            assert_eq!(restored.address(), original.address());
+            assert_eq!(
+                restored.tree_at(8).unwrap().script_pubkey(),
+                original.tree_at(8).unwrap().script_pubkey()
+            );
+            assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_restore_degrading_stages() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let xprv = bitcoin::bip32::ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[3; 32]).unwrap();
+        let cosigner = ExtendedPubKey::from_priv(&secp, &xprv).to_string();
+        let template = VaultTemplate::Degrading {
+            keys: 3,
+            stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+            cosigners: vec![cosigner.clone()],
+        };
+        let (original, descriptor, metadata_hex) = backup(template.clone(), 5);
+        assert!(descriptor.starts_with(&format!(
+            "tr({}/0/*,{{multi_a(3,{}/0/*,{}/0/*,{}/0/*),{{and_v(v:older(4032),multi_a(2,",
+            keys::nums_xpub(Network::Regtest),
+            OWNER_TPUB,
+            RECOVERY_TPUB,
+            cosigner
+        )));
+
+        let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+        assert_eq!(serde_json::to_value(restored.template()).unwrap(), serde_json::to_value(&template).unwrap());
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+
+        // Without the stage table the tree can't be rebuilt
+        let mut metadata = original.metadata();
+        metadata.set_degrading_stages(&[]).unwrap();
+        match restore(&descriptor, &hex::encode(metadata.to_bytes()), Network::Regtest) {
+            Err(CoreError::MetadataError(msg)) => assert!(msg.contains("no stages record"), "{}", msg),
+            other => panic!("expected MetadataError, got {:?}", other),
+        }
+        // A different table gives a different descriptor
+        metadata.set_degrading_stages(&[(0, 3), (4032, 2), (20_000, 1)]).unwrap();
+        assert!(matches!(
+            restore(&descriptor, &hex::encode(metadata.to_bytes()), Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_timelock_only_has_no_recovery_key() {
+        let template = VaultTemplate::custom(4320, RecoveryType::TimelockOnly).unwrap();
+        let (original, descriptor, metadata_hex) = backup(template, 0);
+        let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.recovery_xpub().public_key, keys::nums_xpub(Network::Regtest).public_key);
+    }
+
+    #[test]
+    fn test_restore_absolute_lock() {
+        for (absolute_lock, absolute_lock_unit) in [(1_000_000, AbsoluteLockUnit::Height), (1_700_000_000, AbsoluteLockUnit::Seconds)] {
+            let template = custom_template(CustomFields {
+                delay_blocks: 4320,
+                absolute_lock: Some(absolute_lock),
+                absolute_lock_unit,
+                ..Default::default()
+            });
+            let (original, descriptor, metadata_hex) = backup(template.clone(), 4);
+            assert!(descriptor.contains(&format!("and_v(v:after({}),pk({}/0/*))", absolute_lock, RECOVERY_TPUB)), "{}", descriptor);
+
+            let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+            assert_eq!(serde_json::to_value(restored.template()).unwrap(), serde_json::to_value(&template).unwrap());
+            assert_eq!(restored.address(), original.address());
+            assert_eq!(restored.recovery_xpub().to_string(), RECOVERY_TPUB);
+            assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+
+            // Without the lock the metadata describes a vault with no such leaf
+            let mut metadata = original.metadata();
+            metadata.set_absolute_lock(None);
+            assert!(matches!(
+                restore(&descriptor, &hex::encode(metadata.to_bytes()), Network::Regtest),
+                Err(CoreError::MetadataError(_))
+            ));
+        }
+
+        // Earlier trees' OP_DROP leaf has no descriptor to restore from
+        let template = custom_template(CustomFields {
+            delay_blocks: 4320,
+            absolute_lock: Some(1_000_000),
+            ..Default::default()
+        });
+        let vault = VaultBuilder::new()
+            .template(template)
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .tree_version(TreeVersion::V3)
+            .build()
+            .unwrap();
+        match vault.descriptor() {
+            Err(CoreError::InvalidInput(msg)) => assert!(msg.contains("tree version 4 or later"), "{}", msg),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_restore_multisig_with_absolute_lock() {
+        let secp = Secp256k1::new();
+        let cosigners: Vec<String> = [[5; 32], [6; 32]]
+            .iter()
+            .map(|seed| {
+                let xprv = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, seed).unwrap();
+                ExtendedPubKey::from_priv(&secp, &xprv).to_string()
+            })
+            .collect();
+        let template = custom_template(CustomFields {
+            delay_blocks: 4320,
+            recovery_type: RecoveryType::MultiSig,
+            multisig: Some(MultisigRecovery { threshold: 2, cosigners }),
+            absolute_lock: Some(1_000_000),
+            ..Default::default()
+        });
+        // Three leaves: the timelock alone at depth 1, the recovery paths under it
+        let (original, descriptor, metadata_hex) = backup(template.clone(), 3);
+        assert!(descriptor.contains(&format!(",{{and_v(v:older(4320),pk({}/0/*)),{{sortedmulti_a(2,", OWNER_TPUB)), "{}", descriptor);
+
+        let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+        assert_eq!(serde_json::to_value(restored.template()).unwrap(), serde_json::to_value(&template).unwrap());
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.tree_at(4).unwrap().script_pubkey(), original.tree_at(4).unwrap().script_pubkey());
+        assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+
+        // The emergency key can spend at any time, so it takes no lock,
+        // and export refuses it as the builder does
+        let emergency = custom_template(CustomFields {
+            delay_blocks: 4320,
+            recovery_type: RecoveryType::EmergencyKey,
+            absolute_lock: Some(1_000_000),
+            ..Default::default()
+        });
+        assert!(matches!(regtest_builder(emergency.clone()).build(), Err(CoreError::PolicyViolation(_))));
+        let (owner, recovery) = (original.owner_xpub(), original.recovery_xpub());
+        assert!(matches!(
+            descriptor::to_core_descriptor(&emergency, owner, recovery, Network::Regtest, TreeVersion::LATEST),
+            Err(CoreError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_hashlock() {
+        let service = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[9; 32]).unwrap();
+        let service_xpub = ExtendedPubKey::from_priv(&Secp256k1::new(), &service).to_string();
+        let hash = sha256::Hash::hash(b"recovery secret");
+        let template = custom_template(CustomFields {
+            delay_blocks: 4320,
+            hashlock: Some(HashlockRecovery { hash, service_xpub: service_xpub.clone() }),
+            ..Default::default()
+        });
+        let (original, descriptor, metadata_hex) = backup(template.clone(), 2);
+        assert!(descriptor.contains(&format!("and_v(v:sha256({}),pk({}/0/*))", hash, service_xpub)), "{}", descriptor);
+
+        let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+        assert_eq!(serde_json::to_value(restored.template()).unwrap(), serde_json::to_value(&template).unwrap());
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+
+        // Without the record the metadata describes a vault with no hashlock leaf
+        let mut metadata = original.metadata();
+        metadata.set_hashlock(None, Network::Regtest).unwrap();
+        assert!(matches!(
+            restore(&descriptor, &hex::encode(metadata.to_bytes()), Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_emergency_key_with_hashlock() {
+        let service = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[9; 32]).unwrap();
+        let service_xpub = ExtendedPubKey::from_priv(&Secp256k1::new(), &service).to_string();
+        let hash = sha256::Hash::hash(b"recovery secret");
+        let template = custom_template(CustomFields {
+            delay_blocks: 4320,
+            recovery_type: RecoveryType::EmergencyKey,
+            hashlock: Some(HashlockRecovery { hash, service_xpub: service_xpub.clone() }),
+            ..Default::default()
+        });
+        // Three leaves: the timelock alone at depth 1, the recovery paths under it
+        let (original, descriptor, metadata_hex) = backup(template.clone(), 6);
+        let recovery_leaves =
+            format!("{{pk({}/0/*),and_v(v:sha256({}),pk({}/0/*))}}", RECOVERY_TPUB, hash, service_xpub);
+        assert!(descriptor.contains(&format!("pk({}/0/*)),{}}})#", OWNER_TPUB, recovery_leaves)), "{}", descriptor);
+
+        let restored = restore(&descriptor, &metadata_hex, Network::Regtest).unwrap();
+        assert_eq!(serde_json::to_value(restored.template()).unwrap(), serde_json::to_value(&template).unwrap());
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.recovery_xpub().to_string(), RECOVERY_TPUB);
+        assert_eq!(restored.tree_at(7).unwrap().script_pubkey(), original.tree_at(7).unwrap().script_pubkey());
+        assert_eq!(restored.metadata().to_bytes(), original.metadata().to_bytes());
+    }
+
+    #[test]
+    fn test_restore_rejects_tampered_delay() {
+        let (original, descriptor, _) = backup(VaultTemplate::savings(), 3);
+        let mut metadata = original.metadata();
+        metadata.delay_blocks = 144;
+        let tampered = hex::encode(metadata.to_bytes());
+
+        match restore(&descriptor, &tampered, Network::Regtest) {
+            Err(CoreError::MetadataError(msg)) => {
+                assert!(msg.contains("144 blocks does not match the descriptor's older(1008)"), "{}", msg)
+            }
+            other => panic!("expected MetadataError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_restore_rejects_mismatched_template() {
+        // Same delay, different template: only the rebuilt descriptor differs
+        let (_, descriptor, _) = backup(VaultTemplate::spending(), 0);
+        let (_, _, timelock_only) = backup(VaultTemplate::custom(144, RecoveryType::TimelockOnly).unwrap(), 0);
+
+        assert!(matches!(
+            restore(&descriptor, &timelock_only, Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_inputs() {
+        let (_, descriptor, metadata_hex) = backup(VaultTemplate::savings(), 0);
+
+        assert!(matches!(
+            restore(&descriptor, "zz", Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+        let (body, _) = descriptor.split_once('#').unwrap();
+        assert!(matches!(
+            restore(&format!("{}#qqqqqqqq", body), &metadata_hex, Network::Regtest),
+            Err(CoreError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            restore(&descriptor, &metadata_hex, Network::Mainnet),
+            Err(CoreError::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_restore_with_commitment() {
+        let original = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .index(2)
+            .created_at_block(120)
+            .build()
+            .unwrap();
+        let descriptor = original.descriptor().unwrap();
+        let metadata = original.metadata();
+        let leaf_hex = hex::encode(taproot::commitment_leaf(&metadata.commitment()).as_bytes());
+        let funded = original.commitment_tree().unwrap().address(Network::Regtest).to_string();
+        let metadata_hex = hex::encode(metadata.to_bytes());
+
+        let restored =
+            restore_with_commitment(&descriptor, &metadata_hex, &leaf_hex, &funded, Network::Regtest).unwrap();
+        assert_eq!(restored.address(), original.address());
+        assert_eq!(restored.metadata().created_at_block, 120);
+
+        // A backup whose creation height was altered still restores, but
+        // no longer matches the commitment
+        let mut tampered = metadata.clone();
+        tampered.created_at_block = 121;
+        let tampered_hex = hex::encode(tampered.to_bytes());
+        assert!(restore(&descriptor, &tampered_hex, Network::Regtest).is_ok());
+        match restore_with_commitment(&descriptor, &tampered_hex, &leaf_hex, &funded, Network::Regtest) {
+            Err(CoreError::MetadataError(msg)) => assert!(msg.starts_with("Leaf commits to metadata"), "{}", msg),
+            other => panic!("expected MetadataError, got {:?}", other),
+        }
+
+        // A leaf recomputed from the tampered backup matches it, but isn't
+        // in the funded output's tree
+        let forged_leaf_hex = hex::encode(taproot::commitment_leaf(&tampered.commitment()).as_bytes());
+        match restore_with_commitment(&descriptor, &tampered_hex, &forged_leaf_hex, &funded, Network::Regtest) {
+            Err(CoreError::MetadataError(msg)) => assert!(msg.starts_with("Commitment leaf is not in the tree"), "{}", msg),
+            other => panic!("expected MetadataError, got {:?}", other),
+        }
+        let plain = original.address().to_string();
+        assert!(matches!(
+            restore_with_commitment(&descriptor, &metadata_hex, &leaf_hex, &plain, Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+
+        let full_leaf_hex = hex::encode(taproot::metadata_leaf(&metadata).as_bytes());
+        assert!(matches!(
+            restore_with_commitment(&descriptor, &metadata_hex, &full_leaf_hex, &funded, Network::Regtest),
+            Err(CoreError::MetadataError(_))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Discovering which vault indices are in use, for restores
+//!
+//! A restored wallet knows its keys but not how many addresses it handed
+//! out. Given the scriptPubKeys a node or Electrum server reports as
+//! funded, `discover_indices()` walks the vault's indices forward the way
+//! BIP44 wallets do, until a run of unused indices says there are no more.
+
+use std::collections::HashSet;
+
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::ScriptBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::{CoreError, CoreResult};
+use crate::taproot::{VaultKeys, MAX_ADDRESS_RANGE};
+
+use super::Vault;
+
+/// Indices derived at a time, across threads with the `parallel` feature
+///
+/// Serially each index is derived only once the scan knows it needs it;
+/// in parallel, up to a batch past the end of the scan may be derived.
+const SCAN_BATCH: u32 = if cfg!(feature = "parallel") { 256 } else { 1 };
+
+/// Vault indices found in use by `discover_indices()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanResult {
+    /// Indices whose scriptPubKey is funded, in increasing order
+    pub used_indices: Vec<u32>,
+    /// Last of `used_indices`, `None` if no index is used
+    pub highest_used: Option<u32>,
+    /// First index after every used one, where the next deposit goes
+    pub next_index: u32,
+}
+
+/// Indices of `vault`'s keys whose scriptPubKey is in `funded_spks`
+///
+/// Indices are derived from 0 upward and the scan stops after
+/// `gap_limit` consecutive unused ones, so a used index is found as long
+/// as fewer than `gap_limit` unused indices precede it. ScriptPubKeys
+/// that belong to no index, such as the wallet's other outputs, are
+/// ignored. Receive branches are derived once, so each index costs one
+/// child derivation per key. With the `parallel` feature indices are
+/// derived in batches across threads; the result is the same.
+///
+/// Fails with `InvalidInput` for a `gap_limit` of 0 or above
+/// `taproot::MAX_ADDRESS_RANGE`, and for a vault with a MuSig2 internal
+/// key, which has an address at its own index only.
+pub fn discover_indices(vault: &Vault, funded_spks: &HashSet<ScriptBuf>, gap_limit: u32) -> CoreResult<ScanResult> {
+    discover_indices_cancellable(vault, funded_spks, gap_limit, &CancelToken::new())
+}
+
+/// `discover_indices()`, giving up with `CoreError::Cancelled` once
+/// `cancel` is tripped
+pub fn discover_indices_cancellable(
+    vault: &Vault,
+    funded_spks: &HashSet<ScriptBuf>,
+    gap_limit: u32,
+    cancel: &CancelToken,
+) -> CoreResult<ScanResult> {
+    if gap_limit == 0 || gap_limit > MAX_ADDRESS_RANGE {
+        return Err(CoreError::InvalidInput(format!(
+            "Gap limit must be between 1 and {}, got {}",
+            MAX_ADDRESS_RANGE, gap_limit
+        )));
+    }
+    if vault.musig_key().is_some() {
+        return Err(CoreError::InvalidInput(format!(
+            "Vault with a MuSig2 internal key has one address, at index {}; there are no indices to scan",
+            vault.index()
+        )));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let vault_keys = VaultKeys::new(&secp, vault.template(), vault.owner_xpub(), vault.recovery_xpub(), vault.network(), vault.tree_version())?;
+
+    let mut used_indices = Vec::new();
+    let mut misses = 0;
+    let mut index = 0u32;
+    // Unhardened indices end at 2^31 - 1
+    'scan: while misses < gap_limit && index < 1 << 31 {
+        let last = (index + SCAN_BATCH - 1).min((1 << 31) - 1);
+        cancel.check()?;
+        let script_pubkeys = crate::parallel::map_indices(index..=last, |index| {
+            vault_keys.tree(&secp, index, None).map(|tree| tree.script_pubkey())
+        });
+        for script_pubkey in script_pubkeys {
+            if funded_spks.contains(&script_pubkey?) {
+                used_indices.push(index);
+                misses = 0;
+            } else {
+                misses += 1;
+            }
+            index += 1;
+            if misses == gap_limit {
+                break 'scan;
+            }
+        }
+    }
+
+    let highest_used = used_indices.last().copied();
+    Ok(ScanResult {
+        used_indices,
+        highest_used,
+        next_index: highest_used.map_or(0, |index| index + 1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{regtest_vault, OWNER_TPUB, RECOVERY_TPUB};
+    use crate::vault::{Network, VaultBuilder, VaultTemplate};
+
+    fn funded(vault: &Vault, indices: &[u32]) -> HashSet<ScriptBuf> {
+        indices.iter().map(|index| vault.tree_at(*index).unwrap().script_pubkey()).collect()
+    }
+
+    #[test]
+    fn test_empty_set() {
+        let result = discover_indices(&regtest_vault(VaultTemplate::spending(), 0), &HashSet::new(), 20).unwrap();
+        assert_eq!(
+            result,
+            ScanResult {
+                used_indices: vec![],
+                highest_used: None,
+                next_index: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_sparse_set_within_gap_limit() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let result = discover_indices(&vault, &funded(&vault, &[0, 3, 19, 38]), 20).unwrap();
+        assert_eq!(result.used_indices, [0, 3, 19, 38]);
+        assert_eq!(result.highest_used, Some(38));
+        assert_eq!(result.next_index, 39);
+
+        // 20 unused indices (39..=58) end the scan before index 59
+        let result = discover_indices(&vault, &funded(&vault, &[5, 59]), 20).unwrap();
+        assert_eq!(result.used_indices, [5]);
+        assert_eq!(result.next_index, 6);
+        // ...while a wider gap limit reaches it
+        assert_eq!(discover_indices(&vault, &funded(&vault, &[5, 59]), 54).unwrap().next_index, 60);
+    }
+
+    #[test]
+    fn test_foreign_script_pubkeys_ignored() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let mut spks = funded(&vault, &[1]);
+        let other = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+        spks.insert(other.tree_at(2).unwrap().script_pubkey());
+        spks.insert(ScriptBuf::from(hex::decode("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()));
+
+        let result = discover_indices(&vault, &spks, 10).unwrap();
+        assert_eq!(result.used_indices, [1]);
+        assert_eq!(result.next_index, 2);
+    }
+
+    #[test]
+    fn test_scans_many_indices() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let spks = funded(&vault, &[9_000]);
+        let started = std::time::Instant::now();
+        let result = discover_indices(&vault, &spks, MAX_ADDRESS_RANGE).unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(result.next_index, 9_001);
+        // Generous bound so unoptimized test builds on slow CI still pass
+        assert!(elapsed < std::time::Duration::from_secs(30), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_cancelled_scan() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let cancel = CancelToken::new();
+        let spks = funded(&vault, &[2]);
+        assert_eq!(discover_indices_cancellable(&vault, &spks, 5, &cancel).unwrap().next_index, 3);
+
+        cancel.cancel();
+        assert!(matches!(
+            discover_indices_cancellable(&vault, &spks, MAX_ADDRESS_RANGE, &cancel),
+            Err(CoreError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_gap_limit_bounds() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        for gap_limit in [0, MAX_ADDRESS_RANGE + 1] {
+            assert!(matches!(
+                discover_indices(&vault, &HashSet::new(), gap_limit),
+                Err(CoreError::InvalidInput(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Vault lifecycle, from creation to the coins leaving it
+//!
+//! ```text
+//! Created --deposit--> Funded --unvault broadcast--> Unvaulting
+//!     Unvaulting --unvault confirmed--> Unvaulting (delay running)
+//!     Unvaulting --delay elapsed--> Spendable --spend--> Spent
+//! Funded | Unvaulting | Spendable --recovery--> Recovered
+//! ```
+//!
+//! Hosts persist the `VaultState` JSON and feed chain events to
+//! `VaultState::transition()`, which refuses moves the vault's scripts
+//! don't allow, such as completing a spend before the delay has run.
+
+use std::fmt;
+
+use bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+use super::{DelayUnit, VaultMetadata};
+
+/// Where a vault is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum VaultState {
+    /// Address handed out, nothing deposited yet
+    Created,
+    /// A deposit to the vault has confirmed
+    Funded,
+    /// The unvault (trigger) transaction is out; the delay starts once it
+    /// confirms
+    Unvaulting {
+        trigger_txid: Txid,
+        /// Height of the block that confirmed the trigger, if any
+        #[serde(default)]
+        confirmed_height: Option<u32>,
+    },
+    /// The delay has run; the completing spend can be broadcast
+    Spendable,
+    /// The completing spend has confirmed
+    Spent,
+    /// The coins were swept along the recovery path
+    Recovered,
+}
+
+/// A chain event moving a vault between states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultEvent {
+    DepositConfirmed,
+    /// Unvault transaction broadcast, with its txid
+    UnvaultBroadcast(Txid),
+    /// Unvault transaction confirmed at this height
+    UnvaultConfirmed(u32),
+    /// The host believes the delay is over with the tip at this height
+    DelayElapsed(u32),
+    SpendConfirmed,
+    RecoveryConfirmed,
+}
+
+impl VaultState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VaultState::Created => "created",
+            VaultState::Funded => "funded",
+            VaultState::Unvaulting { .. } => "unvaulting",
+            VaultState::Spendable => "spendable",
+            VaultState::Spent => "spent",
+            VaultState::Recovered => "recovered",
+        }
+    }
+
+    /// Spent and Recovered vaults hold no coins and accept no events
+    pub fn is_final(&self) -> bool {
+        matches!(self, VaultState::Spent | VaultState::Recovered)
+    }
+
+    /// The state after `event`, for a vault described by `metadata`
+    ///
+    /// Fails with `PolicyViolation` for a move the lifecycle doesn't
+    /// allow, or for `DelayElapsed` before the trigger's confirmation
+    /// height plus the metadata's delay. Time-based delays can't be
+    /// checked against a block height, so `DelayElapsed` fails with
+    /// `InvalidInput` for them.
+    pub fn transition(&self, event: VaultEvent, metadata: &VaultMetadata) -> CoreResult<VaultState> {
+        match (*self, event) {
+            (VaultState::Created, VaultEvent::DepositConfirmed) => Ok(VaultState::Funded),
+            (VaultState::Funded, VaultEvent::UnvaultBroadcast(trigger_txid)) => Ok(VaultState::Unvaulting {
+                trigger_txid,
+                confirmed_height: None,
+            }),
+            (VaultState::Unvaulting { trigger_txid, confirmed_height: None }, VaultEvent::UnvaultConfirmed(height)) => {
+                Ok(VaultState::Unvaulting { trigger_txid, confirmed_height: Some(height) })
+            }
+            (VaultState::Unvaulting { confirmed_height: Some(height), .. }, VaultEvent::DelayElapsed(current_height)) => {
+                if metadata.delay_unit != DelayUnit::Blocks {
+                    return Err(CoreError::InvalidInput(format!(
+                        "Vault delay is {} {}; it can't be checked against a block height",
+                        metadata.delay_blocks,
+                        metadata.delay_unit.name()
+                    )));
+                }
+                let spendable_at = height.saturating_add(metadata.delay_blocks);
+                if current_height < spendable_at {
+                    return Err(CoreError::PolicyViolation(format!(
+                        "Vault delay has not elapsed: unvault confirmed at height {} with a {}-block delay, \
+                         spendable from height {}, tip is {}",
+                        height, metadata.delay_blocks, spendable_at, current_height
+                    )));
+                }
+                Ok(VaultState::Spendable)
+            }
+            (VaultState::Spendable, VaultEvent::SpendConfirmed) => Ok(VaultState::Spent),
+            (VaultState::Funded | VaultState::Unvaulting { .. } | VaultState::Spendable, VaultEvent::RecoveryConfirmed) => {
+                Ok(VaultState::Recovered)
+            }
+            (state, event) => Err(CoreError::PolicyViolation(format!(
+                "Illegal vault transition: {} while {}",
+                event,
+                state.name()
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for VaultState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for VaultEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VaultEvent::DepositConfirmed => f.write_str("deposit confirmed"),
+            VaultEvent::UnvaultBroadcast(txid) => write!(f, "unvault {} broadcast", txid),
+            VaultEvent::UnvaultConfirmed(height) => write!(f, "unvault confirmed at height {}", height),
+            VaultEvent::DelayElapsed(height) => write!(f, "delay elapsed at height {}", height),
+            VaultEvent::SpendConfirmed => f.write_str("spend confirmed"),
+            VaultEvent::RecoveryConfirmed => f.write_str("recovery confirmed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    use crate::vault::RecoveryType;
+
+    const DELAY: u32 = 144;
+
+    fn metadata(delay_unit: DelayUnit) -> VaultMetadata {
+        VaultMetadata {
+            version: 1,
+            template_id: "savings_v1".to_string(),
+            delay_blocks: DELAY,
+            delay_unit,
+            destination_indices: vec![],
+            recovery_type: RecoveryType::EmergencyKey,
+            created_at_block: 0,
+            vault_index: 0,
+            key_path_enabled: false,
+            heirs: None,
+            whitelist_delay: None,
+            tlv_records: Default::default(),
+        }
+    }
+
+    fn txid() -> Txid {
+        Txid::from_str(&"ab".repeat(32)).unwrap()
+    }
+
+    fn unvaulting(confirmed_height: Option<u32>) -> VaultState {
+        VaultState::Unvaulting { trigger_txid: txid(), confirmed_height }
+    }
+
+    #[test]
+    fn test_transition_matrix() {
+        use VaultState::*;
+
+        let states = [Created, Funded, unvaulting(None), unvaulting(Some(100)), Spendable, Spent, Recovered];
+        let events = [
+            VaultEvent::DepositConfirmed,
+            VaultEvent::UnvaultBroadcast(txid()),
+            VaultEvent::UnvaultConfirmed(100),
+            VaultEvent::DelayElapsed(100 + DELAY),
+            VaultEvent::SpendConfirmed,
+            VaultEvent::RecoveryConfirmed,
+        ];
+        // One row per state, one column per event; None is an illegal move
+        let expected: [[Option<VaultState>; 6]; 7] = [
+            [Some(Funded), None, None, None, None, None],
+            [None, Some(unvaulting(None)), None, None, None, Some(Recovered)],
+            [None, None, Some(unvaulting(Some(100))), None, None, Some(Recovered)],
+            [None, None, None, Some(Spendable), None, Some(Recovered)],
+            [None, None, None, None, Some(Spent), Some(Recovered)],
+            [None; 6],
+            [None; 6],
+        ];
+
+        let metadata = metadata(DelayUnit::Blocks);
+        for (state, row) in states.iter().zip(expected) {
+            for (event, expected) in events.iter().zip(row) {
+                match (state.transition(*event, &metadata), expected) {
+                    (Ok(next), Some(expected)) => assert_eq!(next, expected, "{} on {}", event, state),
+                    (Err(CoreError::PolicyViolation(msg)), None) => {
+                        assert!(msg.starts_with("Illegal vault transition"), "{}", msg)
+                    }
+                    (result, expected) => panic!("{} on {}: got {:?}, expected {:?}", event, state, result, expected),
+                }
+            }
+            assert_eq!(state.is_final(), row.iter().all(Option::is_none));
+        }
+    }
+
+    #[test]
+    fn test_delay_elapsed_checks_height() {
+        let blocks = metadata(DelayUnit::Blocks);
+        let state = unvaulting(Some(100));
+
+        match state.transition(VaultEvent::DelayElapsed(100 + DELAY - 1), &blocks) {
+            Err(CoreError::PolicyViolation(msg)) => {
+                assert!(msg.contains("spendable from height 244, tip is 243"), "{}", msg)
+            }
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+        assert_eq!(state.transition(VaultEvent::DelayElapsed(10_000), &blocks).unwrap(), VaultState::Spendable);
+
+        // A 512-second delay says nothing about block heights
+        let time_based = metadata(DelayUnit::TimeUnits512s);
+        assert!(matches!(
+            state.transition(VaultEvent::DelayElapsed(10_000), &time_based),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_state_json() {
+        let json = |state: VaultState| serde_json::to_value(state).unwrap();
+        assert_eq!(json(VaultState::Created), serde_json::json!({"state": "created"}));
+        assert_eq!(
+            json(unvaulting(Some(100))),
+            serde_json::json!({"state": "unvaulting", "trigger_txid": "ab".repeat(32), "confirmed_height": 100})
+        );
+
+        let decoded: VaultState = serde_json::from_value(serde_json::json!({
+            "state": "unvaulting",
+            "trigger_txid": "ab".repeat(32),
+        }))
+        .unwrap();
+        assert_eq!(decoded, unvaulting(None));
+        for state in [VaultState::Funded, VaultState::Spendable, VaultState::Spent, VaultState::Recovered] {
+            assert_eq!(json(state)["state"], state.name());
+            assert_eq!(serde_json::from_value::<VaultState>(json(state)).unwrap(), state);
+        }
+    }
+}
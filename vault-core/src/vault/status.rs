@@ -0,0 +1,200 @@
+//! Tracking vault outputs and unvaults as the chain advances
+//!
+//! Relative locks follow BIP68: an input whose output confirmed at height
+//! `h` with a lock of `n` blocks is valid in blocks at height `h + n` and
+//! later. A transaction is accepted to the mempool once it would be valid
+//! in the next block, i.e. when the tip is at `h + n - 1`.
+
+use bitcoin::{OutPoint, ScriptBuf, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+
+use super::{psbt, Vault};
+
+/// A vault output as tracked by a host wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultUtxo {
+    pub outpoint: OutPoint,
+    /// Value in satoshis
+    pub value: u64,
+    /// Vault index the output pays to
+    pub vault_index: u32,
+    pub script_pubkey: ScriptBuf,
+    /// Height of the block that confirmed the output, if any
+    #[serde(default)]
+    pub confirmation_height: Option<u32>,
+}
+
+impl VaultUtxo {
+    /// Number of confirmations with the tip at `current_height`; 0 while unconfirmed
+    pub fn confirmations(&self, current_height: u32) -> u32 {
+        self.confirmation_height
+            .map_or(0, |height| current_height.saturating_add(1).saturating_sub(height))
+    }
+
+    /// The output with `vault`'s tree at its index, ready for PSBT building
+    ///
+    /// Fails with `InvalidInput` if the tree doesn't pay to `script_pubkey`.
+    pub fn resolve(&self, vault: &Vault) -> CoreResult<psbt::VaultUtxo> {
+        let tree = vault.tree_at(self.vault_index)?;
+        if tree.script_pubkey() != self.script_pubkey {
+            return Err(CoreError::InvalidInput(format!(
+                "Output {} does not pay to vault index {}",
+                self.outpoint, self.vault_index
+            )));
+        }
+        Ok(psbt::VaultUtxo::new(self.outpoint, self.value, tree))
+    }
+}
+
+/// An unvault (trigger) transaction waiting out its delay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnvaultState {
+    pub trigger_txid: Txid,
+    /// Tip height when the trigger was broadcast
+    pub broadcast_height: u32,
+    /// Height of the block that confirmed the trigger, if any
+    #[serde(default)]
+    pub confirmation_height: Option<u32>,
+}
+
+/// Where an unvault stands, as reported by `UnvaultState::status()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UnvaultStatus {
+    /// The trigger is unconfirmed, so the delay hasn't started
+    Pending,
+    /// `blocks_left` more blocks must be mined before the spend is valid
+    Waiting { blocks_left: u32 },
+    /// The completing spend is valid in the next block
+    Spendable,
+}
+
+impl UnvaultState {
+    /// First block height the completing spend can be mined in
+    ///
+    /// The trigger's confirmation height plus `delay_blocks`, or `None`
+    /// while the trigger is unconfirmed.
+    pub fn spendable_at(&self, delay_blocks: u32) -> Option<u32> {
+        self.confirmation_height
+            .map(|height| height.saturating_add(delay_blocks))
+    }
+
+    /// Blocks to be mined on top of `current_height` before the spend is
+    /// valid in the next block
+    ///
+    /// 0 once the spend can be broadcast; `None` while the trigger is
+    /// unconfirmed.
+    pub fn blocks_remaining(&self, delay_blocks: u32, current_height: u32) -> Option<u32> {
+        self.spendable_at(delay_blocks)
+            .map(|height| height.saturating_sub(current_height.saturating_add(1)))
+    }
+
+    pub fn status(&self, delay_blocks: u32, current_height: u32) -> UnvaultStatus {
+        match self.blocks_remaining(delay_blocks, current_height) {
+            None => UnvaultStatus::Pending,
+            Some(0) => UnvaultStatus::Spendable,
+            Some(blocks_left) => UnvaultStatus::Waiting { blocks_left },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{OWNER_TPUB, RECOVERY_TPUB};
+    use std::str::FromStr;
+
+    use crate::vault::{Network, VaultBuilder, VaultTemplate};
+
+    fn state(confirmation_height: Option<u32>) -> UnvaultState {
+        UnvaultState {
+            trigger_txid: Txid::from_str(&"ab".repeat(32)).unwrap(),
+            broadcast_height: 99,
+            confirmation_height,
+        }
+    }
+
+    #[test]
+    fn test_spendable_at_boundary() {
+        // Confirmed at 100 with a 144-block delay: valid in block 244,
+        // broadcastable once the tip is 243
+        let state = state(Some(100));
+        assert_eq!(state.spendable_at(144), Some(244));
+
+        assert_eq!(state.blocks_remaining(144, 100), Some(143));
+        assert_eq!(state.blocks_remaining(144, 242), Some(1));
+        assert_eq!(state.blocks_remaining(144, 243), Some(0));
+        assert_eq!(state.blocks_remaining(144, 500), Some(0));
+
+        assert_eq!(state.status(144, 242), UnvaultStatus::Waiting { blocks_left: 1 });
+        assert_eq!(state.status(144, 243), UnvaultStatus::Spendable);
+    }
+
+    #[test]
+    fn test_one_block_delay_is_spendable_on_confirmation() {
+        // A 1-block lock is met by the block after the trigger's
+        assert_eq!(state(Some(100)).spendable_at(1), Some(101));
+        assert_eq!(state(Some(100)).status(1, 100), UnvaultStatus::Spendable);
+        assert_eq!(state(Some(100)).status(2, 100), UnvaultStatus::Waiting { blocks_left: 1 });
+    }
+
+    #[test]
+    fn test_unconfirmed_trigger_is_pending() {
+        let state = state(None);
+        assert_eq!(state.spendable_at(144), None);
+        assert_eq!(state.blocks_remaining(144, 1_000), None);
+        assert_eq!(state.status(144, 1_000), UnvaultStatus::Pending);
+    }
+
+    #[test]
+    fn test_status_json() {
+        let json = |status: UnvaultStatus| serde_json::to_value(status).unwrap();
+        assert_eq!(json(UnvaultStatus::Pending), serde_json::json!({"status": "pending"}));
+        assert_eq!(
+            json(UnvaultStatus::Waiting { blocks_left: 3 }),
+            serde_json::json!({"status": "waiting", "blocks_left": 3})
+        );
+        assert_eq!(json(UnvaultStatus::Spendable), serde_json::json!({"status": "spendable"}));
+
+        let decoded: UnvaultState = serde_json::from_value(serde_json::json!({
+            "trigger_txid": "ab".repeat(32),
+            "broadcast_height": 99,
+        }))
+        .unwrap();
+        assert_eq!(decoded, state(None));
+    }
+
+    #[test]
+    fn test_utxo_resolve_checks_script() {
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::spending())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .index(4)
+            .build()
+            .unwrap();
+        let mut utxo = VaultUtxo {
+            outpoint: OutPoint::new(Txid::from_str(&"cd".repeat(32)).unwrap(), 1),
+            value: 50_000,
+            vault_index: 4,
+            script_pubkey: vault.script_pubkey(),
+            confirmation_height: Some(200),
+        };
+        assert_eq!(utxo.confirmations(200), 1);
+        assert_eq!(utxo.confirmations(209), 10);
+
+        let resolved = utxo.resolve(&vault).unwrap();
+        assert_eq!(resolved.txout().script_pubkey, vault.script_pubkey());
+        assert_eq!(resolved.amount_sats, 50_000);
+
+        let json = serde_json::to_value(&utxo).unwrap();
+        assert_eq!(json["outpoint"], format!("{}:1", "cd".repeat(32)));
+        assert_eq!(serde_json::from_value::<VaultUtxo>(json).unwrap(), utxo);
+
+        utxo.vault_index = 5;
+        assert!(matches!(utxo.resolve(&vault), Err(CoreError::InvalidInput(_))));
+    }
+}
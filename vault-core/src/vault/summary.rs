@@ -0,0 +1,517 @@
+//! Plain-language summary of a vault's spending rules
+//!
+//! `describe()` lists every way a vault's coins can move, for the user
+//! to confirm on a second screen before funding it. Each piece keeps the
+//! facts behind its English text next to it, so hosts can localize.
+
+use bitcoin::absolute::LockTime;
+use serde::Serialize;
+
+use crate::keys;
+use crate::taproot::LeafPurpose;
+
+use super::{DelayUnit, Vault, VaultTemplate};
+
+/// Minutes per block assumed by `delay_to_duration()`
+const MINUTES_PER_BLOCK: u64 = 10;
+
+/// A vault's rules, from `describe()`
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicySummary {
+    /// Template the vault was built from, as in its metadata
+    pub template_id: &'static str,
+    /// Every way the vault's coins can move, key path first
+    pub spend_paths: Vec<SpendPathSummary>,
+    /// How funds are saved if the owner key is lost or stolen
+    pub recovery: String,
+    /// Number of approved destinations, if the vault has a list
+    pub whitelist_size: Option<usize>,
+    /// Properties of the policy the user should confirm deliberately
+    pub warnings: Vec<PolicyWarning>,
+}
+
+/// One way a vault's coins can move
+#[derive(Debug, Clone, Serialize)]
+pub struct SpendPathSummary {
+    /// Leaf the path spends through; `None` for the key path
+    pub leaf: Option<LeafPurpose>,
+    /// Fingerprints of the keys that can sign, or the x-only key itself
+    /// for a MuSig2 aggregate key, which has no BIP32 origin
+    pub signers: Vec<String>,
+    /// Signatures required out of `signers`
+    pub threshold: usize,
+    /// Relative delay before the path can be used, in `delay_unit`s
+    pub delay: Option<u32>,
+    pub delay_unit: DelayUnit,
+    /// `delay` as a rough duration, e.g. "~7 days"
+    pub duration: Option<String>,
+    /// The rule in English, e.g. "funds can be spent by owner key
+    /// 3442193e after 1008 blocks (~7 days)"
+    pub description: String,
+}
+
+/// Something about a vault's policy worth a second look before funding it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PolicyWarning {
+    /// Nothing but the owner key can ever move the funds
+    NoRecoveryPath,
+    /// The key path spends at once, skipping the delay meant to give the
+    /// recovery key time to react
+    KeyPathEnabled,
+    /// A dual-delay vault has no approved destinations, so only its
+    /// longer delay can be used
+    EmptyWhitelist,
+}
+
+/// Rough duration of `blocks` at ten minutes a block, e.g. "~7 days"
+pub fn delay_to_duration(blocks: u32) -> String {
+    approx_duration(blocks as u64 * MINUTES_PER_BLOCK)
+}
+
+fn approx_duration(minutes: u64) -> String {
+    if minutes < 60 {
+        format!("~{} minutes", minutes)
+    } else if minutes < 1440 {
+        let hours = minutes / 60;
+        format!("~{} hour{}", hours, if hours == 1 { "" } else { "s" })
+    } else {
+        let days = minutes / 1440;
+        format!("~{} day{}", days, if days == 1 { "" } else { "s" })
+    }
+}
+
+/// Summarize the rules of `vault`'s tree
+///
+/// Paths are read off the tree actually built, so a MuSig2 internal key
+/// set through `VaultBuilder::internal_key()` shows up as a key path.
+pub fn describe(vault: &Vault) -> PolicySummary {
+    let template = vault.template();
+    let owner = Signers::single("owner key", vault.owner_origin().0.to_string());
+    let whitelist_size = vault.destinations().map(|destinations| destinations.len());
+
+    let mut spend_paths = Vec::new();
+    let mut warnings = Vec::new();
+
+    if let Some(musig_key) = vault.musig_key() {
+        let signers = Signers::single("MuSig2 aggregate key", musig_key.to_string());
+        spend_paths.push(signers.path(None, None, DelayUnit::Blocks, "spent", "at any time"));
+    } else if template.key_path_enabled() {
+        spend_paths.push(owner.path(None, None, DelayUnit::Blocks, "spent", "at any time"));
+    }
+    if !spend_paths.is_empty() && !matches!(template, VaultTemplate::Inheritance { .. }) {
+        warnings.push(PolicyWarning::KeyPathEnabled);
+    }
+
+    let mut recovery = None;
+    for leaf in vault.tree().leaves() {
+        let delay = template.delay_blocks();
+        let unit = template.delay_unit();
+        let path = match leaf.purpose {
+            LeafPurpose::Timelock => {
+                let to = if template.whitelist_delay().is_some() { "to any destination " } else { "" };
+                owner.path(Some(leaf.purpose), Some(delay), unit, "spent", &format!("{}after", to))
+            }
+            LeafPurpose::WhitelistTimelock => {
+                let count = whitelist_size.unwrap_or(0);
+                let to = format!("to one of {} approved destination{} after", count, if count == 1 { "" } else { "s" });
+                let whitelist_delay = template.whitelist_delay().unwrap_or(delay);
+                owner.path(Some(leaf.purpose), Some(whitelist_delay), DelayUnit::Blocks, "spent", &to)
+            }
+            LeafPurpose::Emergency => {
+                let signers = Signers::single("recovery key", vault.recovery_origin().0.to_string());
+                recovery = Some(format!("{} can sweep funds at any time, even during a pending spend", signers.name()));
+                signers.path(Some(leaf.purpose), None, unit, "swept", "at any time")
+            }
+            LeafPurpose::Multisig => {
+                let signers = cosigners(vault, "cosigner");
+                recovery = Some(format!("{} can sweep funds at any time, even during a pending spend", signers.name()));
+                signers.path(Some(leaf.purpose), None, unit, "swept", "at any time")
+            }
+            LeafPurpose::Inheritance => {
+                let signers = cosigners(vault, "heir");
+                recovery = Some(format!(
+                    "{} can claim funds left unmoved for {}",
+                    signers.name(),
+                    describe_delay(delay, unit)
+                ));
+                signers.path(Some(leaf.purpose), Some(delay), unit, "spent", "once unmoved for")
+            }
+            LeafPurpose::AbsoluteLock => {
+                let signers = Signers::single("recovery key", vault.recovery_origin().0.to_string());
+                let from = match template.absolute_lock().ok().flatten() {
+                    Some(LockTime::Blocks(height)) => format!("from block {}", height),
+                    Some(LockTime::Seconds(time)) => format!("from Unix time {}", time),
+                    None => continue,
+                };
+                recovery.get_or_insert_with(|| format!("{} can claim funds {}", signers.name(), from));
+                signers.path(Some(leaf.purpose), None, unit, "spent", &from)
+            }
+            LeafPurpose::Hashlock => {
+                let signers = service(vault);
+                recovery.get_or_insert_with(|| {
+                    format!("{} can sweep funds at any time once given the secret", signers.name())
+                });
+                signers.path(Some(leaf.purpose), None, unit, "swept", "at any time with the secret")
+            }
+            LeafPurpose::DegradingStage(stage) => {
+                let (delay, threshold) = match template.stages().and_then(|stages| stages.get(stage as usize)) {
+                    Some(&stage) => stage,
+                    None => continue,
+                };
+                let signers = degrading_signers(vault, threshold);
+                if delay == 0 {
+                    signers.path(Some(leaf.purpose), None, unit, "spent", "at any time")
+                } else {
+                    recovery.get_or_insert_with(|| {
+                        format!("{} can move funds after {}", signers.name(), describe_delay(delay, unit))
+                    });
+                    signers.path(Some(leaf.purpose), Some(delay), unit, "spent", "after")
+                }
+            }
+            LeafPurpose::Metadata => continue,
+        };
+        spend_paths.push(path);
+    }
+
+    let recovery = recovery.unwrap_or_else(|| {
+        warnings.push(PolicyWarning::NoRecoveryPath);
+        format!("no recovery path: only {} can move funds", owner.name())
+    });
+    if template.whitelist_delay().is_some() && whitelist_size.unwrap_or(0) == 0 {
+        warnings.push(PolicyWarning::EmptyWhitelist);
+    }
+
+    PolicySummary {
+        template_id: template.template_id(),
+        spend_paths,
+        recovery,
+        whitelist_size,
+        warnings,
+    }
+}
+
+/// "1008 blocks (~7 days)"
+fn describe_delay(delay: u32, unit: DelayUnit) -> String {
+    format!("{} {} ({})", delay, unit.name(), duration(delay, unit))
+}
+
+fn duration(delay: u32, unit: DelayUnit) -> String {
+    match unit {
+        DelayUnit::Blocks => delay_to_duration(delay),
+        DelayUnit::TimeUnits512s => approx_duration(delay as u64 * 512 / 60),
+    }
+}
+
+/// Keys that can sign one path, for wording its description
+struct Signers {
+    role: &'static str,
+    keys: Vec<String>,
+    threshold: usize,
+}
+
+impl Signers {
+    fn single(role: &'static str, key: String) -> Self {
+        Signers { role, keys: vec![key], threshold: 1 }
+    }
+
+    /// "owner key 3442193e", "2 of 3 cosigner keys (…, …, …)"
+    fn name(&self) -> String {
+        match self.keys.as_slice() {
+            [key] => format!("{} {}", self.role, key),
+            keys => format!("{} of {} {} keys ({})", self.threshold, keys.len(), self.role, keys.join(", ")),
+        }
+    }
+
+    /// "funds can be `verb` by `name()` `when` [`delay`]"
+    fn path(&self, leaf: Option<LeafPurpose>, delay: Option<u32>, unit: DelayUnit, verb: &str, when: &str) -> SpendPathSummary {
+        let mut description = format!("funds can be {} by {} {}", verb, self.name(), when);
+        if let Some(delay) = delay {
+            description = format!("{} {}", description, describe_delay(delay, unit));
+        }
+        SpendPathSummary {
+            leaf,
+            signers: self.keys.clone(),
+            threshold: self.threshold,
+            delay,
+            delay_unit: unit,
+            duration: delay.map(|delay| duration(delay, unit)),
+            description,
+        }
+    }
+}
+
+/// Multisig cosigners or heirs of `vault`'s template
+fn cosigners(vault: &Vault, role: &'static str) -> Signers {
+    let (threshold, keys) = match vault.template() {
+        VaultTemplate::Custom { multisig: Some(multisig), .. } => (multisig.threshold, multisig.cosigners.as_slice()),
+        VaultTemplate::Inheritance { heir_threshold, heirs, .. } => (*heir_threshold, heirs.as_slice()),
+        VaultTemplate::Degrading { cosigners, .. } => (0, cosigners.as_slice()),
+        _ => (0, &[][..]),
+    };
+    let keys = keys
+        .iter()
+        .filter_map(|key| keys::parse_xpub_with_origin(key, vault.network()).ok())
+        .map(|(_, (fingerprint, _))| fingerprint.to_string())
+        .collect();
+    Signers { role, keys, threshold: threshold as usize }
+}
+
+/// Owner, recovery and cosigner keys of a degrading template, `threshold` of them signing
+fn degrading_signers(vault: &Vault, threshold: u8) -> Signers {
+    let mut keys = vec![vault.owner_origin().0.to_string(), vault.recovery_origin().0.to_string()];
+    keys.extend(cosigners(vault, "signer").keys);
+    Signers { role: "signer", keys, threshold: threshold as usize }
+}
+
+/// The recovery service of a custom template's hashlock leaf
+fn service(vault: &Vault) -> Signers {
+    let fingerprint = match vault.template() {
+        VaultTemplate::Custom { hashlock: Some(hashlock), .. } => {
+            keys::parse_xpub_with_origin(&hashlock.service_xpub, vault.network())
+                .map(|(_, (fingerprint, _))| fingerprint.to_string())
+                .unwrap_or_default()
+        }
+        _ => String::new(),
+    };
+    Signers::single("recovery service key", fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::{custom_template, CustomFields, OWNER_XPUB, RECOVERY_XPUB};
+    use crate::vault::policy::ApprovedDestinations;
+    use crate::vault::{AbsoluteLockUnit, HashlockRecovery, MultisigRecovery, Network, RecoveryType, VaultBuilder};
+    use bitcoin::bip32::{ExtendedPrivKey, ExtendedPubKey};
+    use std::str::FromStr;
+
+    fn builder(template: VaultTemplate) -> VaultBuilder {
+        VaultBuilder::new()
+            .template(template)
+            .owner_xpub(OWNER_XPUB)
+            .recovery_xpub(RECOVERY_XPUB)
+            .network(Network::Mainnet)
+    }
+
+    fn summary(template: VaultTemplate) -> PolicySummary {
+        describe(&builder(template).build().unwrap())
+    }
+
+    /// Mainnet master xpubs of `count` seeds unrelated to the owner and recovery keys
+    fn cosigner_xpubs(count: u8) -> Vec<String> {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        (1..=count)
+            .map(|seed| {
+                let xprv = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[seed; 32]).unwrap();
+                ExtendedPubKey::from_priv(&secp, &xprv).to_string()
+            })
+            .collect()
+    }
+
+    fn fingerprint(xpub: &str) -> String {
+        ExtendedPubKey::from_str(xpub).unwrap().fingerprint().to_string()
+    }
+
+    fn descriptions(summary: &PolicySummary) -> Vec<&str> {
+        summary.spend_paths.iter().map(|path| path.description.as_str()).collect()
+    }
+
+    fn custom(recovery_type: RecoveryType, multisig: Option<MultisigRecovery>, key_path_enabled: bool) -> VaultTemplate {
+        custom_template(CustomFields {
+            delay_blocks: 675,
+            delay_unit: DelayUnit::TimeUnits512s,
+            recovery_type,
+            multisig,
+            key_path_enabled,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_delay_to_duration() {
+        assert_eq!(delay_to_duration(0), "~0 minutes");
+        assert_eq!(delay_to_duration(5), "~50 minutes");
+        assert_eq!(delay_to_duration(6), "~1 hour");
+        assert_eq!(delay_to_duration(144), "~1 day");
+        assert_eq!(delay_to_duration(1008), "~7 days");
+        assert_eq!(delay_to_duration(u32::MAX), "~29826161 days");
+    }
+
+    #[test]
+    fn test_describe_savings_and_spending() {
+        let (owner, recovery) = (fingerprint(OWNER_XPUB), fingerprint(RECOVERY_XPUB));
+
+        let savings = summary(VaultTemplate::savings());
+        assert_eq!(savings.template_id, "savings_v1");
+        assert_eq!(
+            descriptions(&savings),
+            [
+                format!("funds can be spent by owner key {} after 1008 blocks (~7 days)", owner),
+                format!("funds can be swept by recovery key {} at any time", recovery),
+            ]
+        );
+        assert_eq!(savings.recovery, format!("recovery key {} can sweep funds at any time, even during a pending spend", recovery));
+        assert_eq!(savings.spend_paths[0].leaf, Some(LeafPurpose::Timelock));
+        assert_eq!(savings.spend_paths[0].duration.as_deref(), Some("~7 days"));
+        assert_eq!(savings.spend_paths[1].delay, None);
+        assert_eq!(savings.whitelist_size, None);
+        assert!(savings.warnings.is_empty());
+
+        let spending = summary(VaultTemplate::spending());
+        assert_eq!(spending.template_id, "spending_v1");
+        assert_eq!(spending.spend_paths[0].description, format!("funds can be spent by owner key {} after 144 blocks (~1 day)", owner));
+        assert!(spending.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_describe_custom_variants() {
+        let owner = fingerprint(OWNER_XPUB);
+
+        let timelock_only = summary(custom(RecoveryType::TimelockOnly, None, false));
+        assert_eq!(
+            descriptions(&timelock_only),
+            [format!("funds can be spent by owner key {} after 675 512-second units (~4 days)", owner)]
+        );
+        assert_eq!(timelock_only.spend_paths[0].delay_unit, DelayUnit::TimeUnits512s);
+        assert_eq!(timelock_only.recovery, format!("no recovery path: only owner key {} can move funds", owner));
+        assert_eq!(timelock_only.warnings, [PolicyWarning::NoRecoveryPath]);
+
+        let cosigners = cosigner_xpubs(3);
+        let multisig = MultisigRecovery { threshold: 2, cosigners: cosigners.clone() };
+        let multisig = summary(custom(RecoveryType::MultiSig, Some(multisig), false));
+        let fingerprints: Vec<_> = cosigners.iter().map(|key| fingerprint(key)).collect();
+        let path = &multisig.spend_paths[1];
+        assert_eq!(path.leaf, Some(LeafPurpose::Multisig));
+        assert_eq!((path.threshold, &path.signers), (2, &fingerprints));
+        assert_eq!(
+            path.description,
+            format!("funds can be swept by 2 of 3 cosigner keys ({}) at any time", fingerprints.join(", "))
+        );
+        assert!(multisig.recovery.starts_with("2 of 3 cosigner keys"));
+        assert!(multisig.warnings.is_empty());
+
+        let key_path = summary(custom(RecoveryType::EmergencyKey, None, true));
+        assert_eq!(key_path.spend_paths[0].leaf, None);
+        assert_eq!(key_path.spend_paths[0].description, format!("funds can be spent by owner key {} at any time", owner));
+        assert_eq!(key_path.spend_paths.len(), 3);
+        assert_eq!(key_path.warnings, [PolicyWarning::KeyPathEnabled]);
+    }
+
+    #[test]
+    fn test_describe_absolute_lock() {
+        let recovery = fingerprint(RECOVERY_XPUB);
+        let dated = |lock, unit| {
+            let mut template = custom(RecoveryType::TimelockOnly, None, false);
+            if let VaultTemplate::Custom { absolute_lock, absolute_lock_unit, .. } = &mut template {
+                (*absolute_lock, *absolute_lock_unit) = (Some(lock), unit);
+            }
+            template
+        };
+
+        let by_height = summary(dated(1_000_000, AbsoluteLockUnit::Height));
+        let path = &by_height.spend_paths[1];
+        assert_eq!(path.leaf, Some(LeafPurpose::AbsoluteLock));
+        assert_eq!(path.description, format!("funds can be spent by recovery key {} from block 1000000", recovery));
+        assert_eq!(by_height.recovery, format!("recovery key {} can claim funds from block 1000000", recovery));
+        assert!(by_height.warnings.is_empty());
+
+        let by_time = summary(dated(1_700_000_000, AbsoluteLockUnit::Seconds));
+        assert!(by_time.spend_paths[1].description.ends_with("from Unix time 1700000000"));
+    }
+
+    #[test]
+    fn test_describe_hashlock() {
+        let service_xpub = cosigner_xpubs(1).remove(0);
+        let service = fingerprint(&service_xpub);
+        let mut template = custom(RecoveryType::TimelockOnly, None, false);
+        if let VaultTemplate::Custom { hashlock, .. } = &mut template {
+            *hashlock = Some(HashlockRecovery {
+                hash: bitcoin::hashes::Hash::hash(b"recovery secret"),
+                service_xpub,
+            });
+        }
+
+        let summary = summary(template);
+        let path = &summary.spend_paths[1];
+        assert_eq!(path.leaf, Some(LeafPurpose::Hashlock));
+        assert_eq!(
+            path.description,
+            format!("funds can be swept by recovery service key {} at any time with the secret", service)
+        );
+        assert_eq!(
+            summary.recovery,
+            format!("recovery service key {} can sweep funds at any time once given the secret", service)
+        );
+        assert!(summary.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_describe_inheritance() {
+        let heirs = cosigner_xpubs(2);
+        let summary = summary(VaultTemplate::Inheritance {
+            heir_threshold: 1,
+            heir_count: 2,
+            inactivity_blocks: 52_560,
+            heirs: heirs.clone(),
+        });
+        let heirs = format!("1 of 2 heir keys ({}, {})", fingerprint(&heirs[0]), fingerprint(&heirs[1]));
+
+        assert_eq!(summary.template_id, "inheritance_v1");
+        assert_eq!(
+            descriptions(&summary),
+            [
+                format!("funds can be spent by owner key {} at any time", fingerprint(OWNER_XPUB)),
+                format!("funds can be spent by {} once unmoved for 52560 blocks (~365 days)", heirs),
+            ]
+        );
+        assert_eq!(summary.recovery, format!("{} can claim funds left unmoved for 52560 blocks (~365 days)", heirs));
+        // The key path is how the owner spends, not a bypass
+        assert!(summary.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_describe_dual_delay() {
+        let template = VaultTemplate::DualDelay { whitelist_delay: 144, open_delay: 1008 };
+        let owner = fingerprint(OWNER_XPUB);
+
+        let empty = summary(template.clone());
+        assert_eq!(empty.warnings, [PolicyWarning::EmptyWhitelist]);
+        assert_eq!(empty.whitelist_size, None);
+
+        let mut destinations = ApprovedDestinations::new(Network::Mainnet);
+        destinations.push("exchange", builder(VaultTemplate::savings()).index(1).build().unwrap().address()).unwrap();
+        destinations.push("cold", builder(VaultTemplate::savings()).index(2).build().unwrap().address()).unwrap();
+        let summary = describe(&builder(template).destinations(destinations).build().unwrap());
+        assert_eq!(summary.template_id, "dual_delay_v1");
+        assert_eq!(summary.whitelist_size, Some(2));
+        let paths: Vec<_> = summary.spend_paths.iter().map(|path| (path.leaf.unwrap(), path.description.clone())).collect();
+        assert!(paths.contains(&(
+            LeafPurpose::Timelock,
+            format!("funds can be spent by owner key {} to any destination after 1008 blocks (~7 days)", owner)
+        )));
+        assert!(paths.contains(&(
+            LeafPurpose::WhitelistTimelock,
+            format!("funds can be spent by owner key {} to one of 2 approved destinations after 144 blocks (~1 day)", owner)
+        )));
+        assert!(summary.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_describe_musig_key_path_on_savings() {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let cosigners: Vec<_> = [[0x11; 32], [0x22; 32]]
+            .iter()
+            .map(|bytes| bitcoin::secp256k1::SecretKey::from_slice(bytes).unwrap().public_key(&secp))
+            .collect();
+        let agg_key = keys::musig::aggregate_keys(&cosigners).unwrap();
+        let vault = builder(VaultTemplate::savings()).internal_key(agg_key.clone()).build().unwrap();
+
+        let summary = describe(&vault);
+        let key_path = &summary.spend_paths[0];
+        assert_eq!(key_path.leaf, None);
+        assert_eq!(key_path.signers, [agg_key.x_only_public_key().to_string()]);
+        assert!(key_path.description.starts_with("funds can be spent by MuSig2 aggregate key"));
+        assert_eq!(summary.warnings, [PolicyWarning::KeyPathEnabled]);
+    }
+}
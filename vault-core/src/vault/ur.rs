@@ -0,0 +1,1049 @@
+//! Uniform Resources (BCR-2020-005) for animated QR transfer to air-gapped signers
+//!
+//! A UR is `ur:<type>/<bytewords>`, its CBOR body written in minimal
+//! bytewords (two letters per byte, followed by a CRC32). Bodies too long
+//! for one QR code are split into fragments and sent as multi-part URs,
+//! `ur:<type>/<seq>-<count>/<bytewords>`. The first `count` parts carry
+//! one fragment each; later parts XOR a pseudo-random subset of the
+//! fragments together (fountain coding), so a scanner that misses some
+//! frames of a looping animation still completes.
+//!
+//! PSBTs are sent as `crypto-psbt`. `crypto-output` (BCR-2020-010) has
+//! no way to express script trees, so vault descriptors are sent as its
+//! successor `output-descriptor` (BCR-2023-010), which carries the
+//! descriptor text.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::Psbt;
+
+use crate::cancel::CancelToken;
+use crate::error::{CoreError, CoreResult};
+
+use super::psbt;
+
+/// UR type of a PSBT (BCR-2020-006)
+pub const UR_TYPE_PSBT: &str = "crypto-psbt";
+
+/// UR type of an output descriptor (BCR-2023-010)
+pub const UR_TYPE_OUTPUT_DESCRIPTOR: &str = "output-descriptor";
+
+/// Shortest fragment `UrEncoder` produces, unless the whole message is
+/// shorter; smaller limits are raised to it and `UrDecoder` rejects
+/// shorter fragments
+pub const MIN_FRAGMENT_LEN: usize = 10;
+
+/// Largest message `UrDecoder` accepts, beyond any transaction's size
+const MAX_MESSAGE_LEN: usize = 4_000_000;
+
+/// Most fragments a message is split into, bounding the work of mixing
+/// and reducing each fountain part
+const MAX_FRAGMENT_COUNT: usize = 4096;
+
+/// CBOR map key of the descriptor text in an `output-descriptor`
+const DESCRIPTOR_SOURCE_KEY: u64 = 1;
+
+/// Minimal bytewords: the first and last letters of each byte's word
+const BYTEWORDS: [&str; 256] = [
+    "ae", "ad", "ao", "ax", "aa", "ah", "am", "at", "ay", "as", "bk", "bd", "bn", "bt", "ba", "bs",
+    "be", "by", "bg", "bw", "bb", "bz", "cm", "ch", "cs", "cf", "cy", "cw", "ce", "ca", "ck", "ct",
+    "cx", "cl", "cp", "cn", "dk", "da", "ds", "di", "de", "dt", "dr", "dn", "dw", "dp", "dm", "dl",
+    "dy", "eh", "ey", "eo", "ee", "ec", "en", "em", "et", "es", "ft", "fr", "fn", "fs", "fm", "fh",
+    "fz", "fp", "fw", "fx", "fy", "fe", "fg", "fl", "fd", "ga", "ge", "gr", "gs", "gt", "gl", "gw",
+    "gd", "gy", "gm", "gu", "gh", "go", "hf", "hg", "hd", "hk", "ht", "hp", "hh", "hl", "hy", "he",
+    "hn", "hs", "id", "ia", "ie", "ih", "iy", "io", "is", "in", "im", "je", "jz", "jn", "jt", "jl",
+    "jo", "js", "jp", "jk", "jy", "kp", "ko", "kt", "ks", "kk", "kn", "kg", "ke", "ki", "kb", "lb",
+    "la", "ly", "lf", "ls", "lr", "lp", "ln", "lt", "lo", "ld", "le", "lu", "lk", "lg", "mn", "my",
+    "mh", "me", "mo", "mu", "mw", "md", "mt", "ms", "mk", "nl", "ny", "nd", "ns", "nt", "nn", "ne",
+    "nb", "oy", "oe", "ot", "ox", "on", "ol", "os", "pd", "pt", "pk", "py", "ps", "pm", "pl", "pe",
+    "pf", "pa", "pr", "qd", "qz", "re", "rp", "rl", "ro", "rh", "rd", "rk", "rf", "ry", "rn", "rs",
+    "rt", "se", "sa", "sr", "ss", "sk", "sw", "st", "sp", "so", "sg", "sb", "sf", "sn", "to", "tk",
+    "ti", "tt", "td", "te", "ty", "tl", "tb", "ts", "tp", "ta", "tn", "uy", "uo", "ut", "ue", "ur",
+    "vt", "vy", "vo", "vl", "ve", "vw", "va", "vd", "vs", "wl", "wd", "wm", "wp", "we", "wy", "ws",
+    "wt", "wn", "wz", "wf", "wk", "yk", "yn", "yl", "ya", "yt", "zs", "zo", "zt", "zc", "ze", "zm",
+];
+
+/// What a completed UR holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum UrPayload {
+    /// A `crypto-psbt`
+    Psbt(Psbt),
+    /// The descriptor text of an `output-descriptor`
+    Descriptor(String),
+}
+
+/// Split a PSBT into `crypto-psbt` parts of at most `max_fragment_len`
+/// body bytes each
+///
+/// Returns a single-part UR when the PSBT fits in one fragment, and
+/// otherwise one part per fragment, for a host to loop through. Use
+/// `UrEncoder` directly for the fountain-coded parts that follow.
+pub fn encode_psbt(psbt: &Psbt, max_fragment_len: usize) -> Vec<String> {
+    UrEncoder::new(UR_TYPE_PSBT, cbor_bytes(&psbt.serialize()), max_fragment_len).parts()
+}
+
+/// Split a descriptor into `output-descriptor` parts, as `encode_psbt()` does
+pub fn encode_descriptor(descriptor: &str, max_fragment_len: usize) -> Vec<String> {
+    let mut body = Vec::new();
+    cbor_head(&mut body, MAJOR_MAP, 1);
+    cbor_head(&mut body, MAJOR_UINT, DESCRIPTOR_SOURCE_KEY);
+    cbor_head(&mut body, MAJOR_TEXT, descriptor.len() as u64);
+    body.extend_from_slice(descriptor.as_bytes());
+    UrEncoder::new(UR_TYPE_OUTPUT_DESCRIPTOR, body, max_fragment_len).parts()
+}
+
+/// Fountain encoder producing the parts of one UR
+#[derive(Debug, Clone)]
+pub struct UrEncoder {
+    ur_type: String,
+    message: Vec<u8>,
+    fragment_len: usize,
+    checksum: u32,
+    seq_num: u32,
+}
+
+impl UrEncoder {
+    /// Encoder for the CBOR `message` of type `ur_type`
+    ///
+    /// Fragments are as even as possible and at most
+    /// `max_fragment_len` bytes, or `MIN_FRAGMENT_LEN` if that is larger.
+    /// They grow past that only to keep at least `MIN_FRAGMENT_LEN`
+    /// bytes each, or to fit a huge message in `MAX_FRAGMENT_COUNT`.
+    pub fn new(ur_type: &str, message: Vec<u8>, max_fragment_len: usize) -> Self {
+        let max_fragment_len = max_fragment_len.max(MIN_FRAGMENT_LEN);
+        let fragment_count = message
+            .len()
+            .div_ceil(max_fragment_len)
+            .min(message.len() / MIN_FRAGMENT_LEN)
+            .clamp(1, MAX_FRAGMENT_COUNT);
+        UrEncoder {
+            ur_type: ur_type.to_string(),
+            fragment_len: message.len().div_ceil(fragment_count).max(1),
+            checksum: crc32fast::hash(&message),
+            message,
+            seq_num: 0,
+        }
+    }
+
+    /// Number of fragments, the `<count>` of multi-part URs
+    pub fn part_count(&self) -> usize {
+        self.message.len().div_ceil(self.fragment_len).max(1)
+    }
+
+    /// Whether the message fits in a single-part UR
+    pub fn is_single_part(&self) -> bool {
+        self.part_count() == 1
+    }
+
+    /// The next part: the single-part UR every time, or the next
+    /// multi-part UR, mixing fragments once each has been sent alone
+    pub fn next_part(&mut self) -> String {
+        if self.is_single_part() {
+            return format!("ur:{}/{}", self.ur_type, bytewords_encode(&self.message));
+        }
+
+        self.seq_num += 1;
+        let seq_len = self.part_count();
+        let mut data = vec![0; self.fragment_len];
+        for index in choose_fragments(self.seq_num, seq_len, self.checksum) {
+            xor_into(&mut data, &fragment(&self.message, self.fragment_len, index));
+        }
+
+        let mut body = Vec::new();
+        cbor_head(&mut body, MAJOR_ARRAY, 5);
+        cbor_head(&mut body, MAJOR_UINT, self.seq_num as u64);
+        cbor_head(&mut body, MAJOR_UINT, seq_len as u64);
+        cbor_head(&mut body, MAJOR_UINT, self.message.len() as u64);
+        cbor_head(&mut body, MAJOR_UINT, self.checksum as u64);
+        cbor_head(&mut body, MAJOR_BYTES, data.len() as u64);
+        body.extend_from_slice(&data);
+        format!("ur:{}/{}-{}/{}", self.ur_type, self.seq_num, seq_len, bytewords_encode(&body))
+    }
+
+    /// One part per fragment, from a fresh encoder
+    fn parts(mut self) -> Vec<String> {
+        (0..self.part_count()).map(|_| self.next_part()).collect()
+    }
+}
+
+/// Fragment `index` of `message`, zero-padded to `fragment_len`
+fn fragment(message: &[u8], fragment_len: usize, index: usize) -> Vec<u8> {
+    let start = (index * fragment_len).min(message.len());
+    let end = (start + fragment_len).min(message.len());
+    let mut fragment = message[start..end].to_vec();
+    fragment.resize(fragment_len, 0);
+    fragment
+}
+
+fn xor_into(target: &mut [u8], other: &[u8]) {
+    for (a, b) in target.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+/// Accumulates scanned parts until a UR is complete
+///
+/// Parts may arrive in any order and more than once; fountain-coded
+/// parts are reduced against the fragments already known. All parts
+/// must belong to the same UR.
+#[derive(Debug, Clone, Default)]
+pub struct UrDecoder {
+    ur_type: Option<String>,
+    /// Sequence length, message length, checksum and fragment length of
+    /// the multi-part UR being decoded
+    params: Option<(usize, usize, u32, usize)>,
+    fragments: BTreeMap<usize, Vec<u8>>,
+    mixed: HashMap<BTreeSet<usize>, Vec<u8>>,
+    /// The mixed parts each unknown fragment appears in, so a newly
+    /// known fragment reduces only those
+    mixed_by_fragment: HashMap<usize, Vec<BTreeSet<usize>>>,
+    message: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a scanned part; parts arriving after completion are ignored
+    ///
+    /// Errors with `SerializationError` for a malformed part and with
+    /// `InvalidInput` for a part of another UR. Either leaves the
+    /// decoder as it was, except that fragments joining into a message
+    /// that fails its checksum are discarded so scanning can start over.
+    pub fn receive_part(&mut self, part: &str) -> CoreResult<()> {
+        self.receive(part, &CancelToken::new())
+    }
+
+    /// `receive_part()`, giving up with `CoreError::Cancelled` once
+    /// `cancel` is tripped
+    ///
+    /// Reducing a part against many mixed parts is the slow step; a
+    /// cancelled part leaves the decoder as it was before the call.
+    pub fn receive_part_cancellable(&mut self, part: &str, cancel: &CancelToken) -> CoreResult<()> {
+        cancel.check()?;
+        let saved = self.clone();
+        let result = self.receive(part, cancel);
+        if matches!(result, Err(CoreError::Cancelled)) {
+            *self = saved;
+        }
+        result
+    }
+
+    fn receive(&mut self, part: &str, cancel: &CancelToken) -> CoreResult<()> {
+        if self.message.is_some() {
+            return Ok(());
+        }
+        let part = part.trim().to_ascii_lowercase();
+        let rest = part
+            .strip_prefix("ur:")
+            .ok_or_else(|| CoreError::SerializationError("UR part must start with \"ur:\"".to_string()))?;
+        let components: Vec<&str> = rest.split('/').collect();
+        let ur_type = components[0];
+        if ur_type.is_empty() || !ur_type.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-') {
+            return Err(CoreError::SerializationError(format!("Invalid UR type \"{}\"", ur_type)));
+        }
+        if self.ur_type.as_deref().is_some_and(|expected| expected != ur_type) {
+            return Err(CoreError::InvalidInput(format!(
+                "Part of a {} UR while decoding a {} UR",
+                ur_type,
+                self.ur_type.as_deref().unwrap_or_default()
+            )));
+        }
+
+        match components[1..] {
+            [body] => {
+                let message = bytewords_decode(body)?;
+                if self.params.is_some() {
+                    return Err(CoreError::InvalidInput(
+                        "Single-part UR while decoding a multi-part UR".to_string(),
+                    ));
+                }
+                self.ur_type = Some(ur_type.to_string());
+                self.message = Some(message);
+                Ok(())
+            }
+            [sequence, body] => {
+                let (seq_num, seq_len) = parse_sequence(sequence)?;
+                let part = FountainPart::decode(&bytewords_decode(body)?)?;
+                if (part.seq_num, part.seq_len) != (seq_num, seq_len) {
+                    return Err(CoreError::SerializationError(format!(
+                        "UR part is labeled {} but its body says {}-{}",
+                        sequence, part.seq_num, part.seq_len
+                    )));
+                }
+                let params = (part.seq_len, part.message_len, part.checksum, part.data.len());
+                if self.params.is_some_and(|expected| expected != params) {
+                    return Err(CoreError::InvalidInput("UR part belongs to another message".to_string()));
+                }
+
+                self.ur_type = Some(ur_type.to_string());
+                self.params = Some(params);
+                let indexes = choose_fragments(part.seq_num, part.seq_len, part.checksum);
+                self.add_part(indexes, part.data, cancel)?;
+                self.try_complete()
+            }
+            _ => Err(CoreError::SerializationError(format!(
+                "UR part has {} path components, expected 2 or 3",
+                components.len()
+            ))),
+        }
+    }
+
+    /// Reduce a part by the known fragments and, once it is a single
+    /// fragment, every mixed part by it
+    fn add_part(&mut self, indexes: BTreeSet<usize>, data: Vec<u8>, cancel: &CancelToken) -> CoreResult<()> {
+        let mut queue = vec![(indexes, data)];
+        while let Some((mut indexes, mut data)) = queue.pop() {
+            cancel.check()?;
+            for index in indexes.clone() {
+                if let Some(known) = self.fragments.get(&index) {
+                    xor_into(&mut data, known);
+                    indexes.remove(&index);
+                }
+            }
+
+            match indexes.len() {
+                0 => {}
+                1 => {
+                    let index = *indexes.first().expect("one index");
+                    self.fragments.insert(index, data);
+                    // A mixed part may already have been reduced through
+                    // another of its fragments
+                    for mixed in self.mixed_by_fragment.remove(&index).unwrap_or_default() {
+                        if let Some(data) = self.mixed.remove(&mixed) {
+                            queue.push((mixed, data));
+                        }
+                    }
+                }
+                _ => {
+                    if !self.mixed.contains_key(&indexes) {
+                        for index in &indexes {
+                            self.mixed_by_fragment.entry(*index).or_default().push(indexes.clone());
+                        }
+                        self.mixed.insert(indexes, data);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Join the fragments once all are known, checking the message's CRC32
+    fn try_complete(&mut self) -> CoreResult<()> {
+        let Some((seq_len, message_len, checksum, _)) = self.params else {
+            return Ok(());
+        };
+        if self.fragments.len() < seq_len {
+            return Ok(());
+        }
+
+        let mut message: Vec<u8> = self.fragments.values().flatten().copied().collect();
+        message.truncate(message_len);
+        if crc32fast::hash(&message) != checksum {
+            *self = UrDecoder::new();
+            return Err(CoreError::SerializationError(
+                "UR message checksum mismatch; decoding restarted".to_string(),
+            ));
+        }
+        self.message = Some(message);
+        Ok(())
+    }
+
+    /// UR type of the parts received so far
+    pub fn ur_type(&self) -> Option<&str> {
+        self.ur_type.as_deref()
+    }
+
+    /// Fragments known so far and the total, `(0, 0)` before the first
+    /// part and `(1, 1)` for a single-part UR
+    pub fn fragments_received(&self) -> (usize, usize) {
+        match (self.params, &self.message) {
+            (Some((seq_len, ..)), _) => (self.fragments.len(), seq_len),
+            (None, Some(_)) => (1, 1),
+            (None, None) => (0, 0),
+        }
+    }
+
+    /// Fraction of the fragments known, from 0.0 to 1.0
+    pub fn progress(&self) -> f64 {
+        if self.is_complete() {
+            return 1.0;
+        }
+        match self.fragments_received() {
+            (_, 0) => 0.0,
+            (received, total) => received as f64 / total as f64,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.message.is_some()
+    }
+
+    /// The decoded UR
+    ///
+    /// Errors with `InvalidInput` while parts are missing or for UR types
+    /// other than `crypto-psbt` (or `psbt`) and `output-descriptor`, and
+    /// with `SerializationError` or `PsbtError` for a malformed body.
+    pub fn result(&self) -> CoreResult<UrPayload> {
+        let message = self.message.as_ref().ok_or_else(|| {
+            let (received, total) = self.fragments_received();
+            CoreError::InvalidInput(format!("UR is incomplete: {} of {} fragments received", received, total))
+        })?;
+        let mut reader = CborReader::new(message);
+
+        let payload = match self.ur_type.as_deref() {
+            Some(UR_TYPE_PSBT) | Some("psbt") => {
+                let bytes = reader.bytes()?;
+                UrPayload::Psbt(psbt::parse_any_bytes(bytes)?)
+            }
+            Some(UR_TYPE_OUTPUT_DESCRIPTOR) => {
+                let mut source = None;
+                for _ in 0..reader.head_of(MAJOR_MAP)? {
+                    let key = reader.head_of(MAJOR_UINT)?;
+                    if key == DESCRIPTOR_SOURCE_KEY {
+                        source = Some(reader.text()?.to_string());
+                    } else {
+                        reader.skip()?;
+                    }
+                }
+                UrPayload::Descriptor(source.ok_or_else(|| {
+                    CoreError::SerializationError("output-descriptor has no descriptor text".to_string())
+                })?)
+            }
+            other => {
+                return Err(CoreError::InvalidInput(format!(
+                    "Unsupported UR type \"{}\"",
+                    other.unwrap_or_default()
+                )))
+            }
+        };
+        reader.finish()?;
+        Ok(payload)
+    }
+}
+
+fn parse_sequence(sequence: &str) -> CoreResult<(u32, usize)> {
+    let invalid = || CoreError::SerializationError(format!("Invalid UR sequence \"{}\"", sequence));
+    let (seq_num, seq_len) = sequence.split_once('-').ok_or_else(invalid)?;
+    Ok((seq_num.parse().map_err(|_| invalid())?, seq_len.parse().map_err(|_| invalid())?))
+}
+
+/// Body of a multi-part UR: `[seqNum, seqLen, messageLen, checksum, data]`
+struct FountainPart {
+    seq_num: u32,
+    seq_len: usize,
+    message_len: usize,
+    checksum: u32,
+    data: Vec<u8>,
+}
+
+impl FountainPart {
+    fn decode(body: &[u8]) -> CoreResult<Self> {
+        let invalid = |what: &str| CoreError::SerializationError(format!("Invalid UR part: {}", what));
+        let mut reader = CborReader::new(body);
+        if reader.head_of(MAJOR_ARRAY)? != 5 {
+            return Err(invalid("expected a 5-element array"));
+        }
+        let seq_num = u32::try_from(reader.head_of(MAJOR_UINT)?).map_err(|_| invalid("sequence number"))?;
+        let seq_len = reader.head_of(MAJOR_UINT)? as usize;
+        let message_len = reader.head_of(MAJOR_UINT)? as usize;
+        let checksum = u32::try_from(reader.head_of(MAJOR_UINT)?).map_err(|_| invalid("checksum"))?;
+        let data = reader.bytes()?.to_vec();
+        reader.finish()?;
+
+        if seq_num == 0 || seq_len == 0 || data.is_empty() {
+            return Err(invalid("zero sequence number, count or fragment length"));
+        }
+        if message_len > MAX_MESSAGE_LEN {
+            return Err(invalid("message too long"));
+        }
+        if seq_len > MAX_FRAGMENT_COUNT {
+            return Err(invalid("too many fragments"));
+        }
+        if data.len() < MIN_FRAGMENT_LEN.min(message_len) {
+            return Err(invalid("fragment too short"));
+        }
+        // Every fragment must hold some of the message, and together
+        // they must hold all of it
+        let capacity = seq_len.checked_mul(data.len()).ok_or_else(|| invalid("sequence count too large"))?;
+        if message_len > capacity || message_len <= capacity - data.len() {
+            return Err(invalid("fragment length doesn't fit the message length"));
+        }
+        Ok(FountainPart { seq_num, seq_len, message_len, checksum, data })
+    }
+}
+
+/// Indexes of the fragments XORed into part `seq_num`
+///
+/// Parts up to `seq_len` carry their own fragment. Later ones take a
+/// degree from the 1/n-weighted distribution and that many fragments
+/// from a shuffle, both driven by Xoshiro256** seeded from the sequence
+/// number and message checksum, so encoder and decoder agree.
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return BTreeSet::from([seq_num as usize - 1]);
+    }
+
+    let mut seed = [0u8; 8];
+    seed[..4].copy_from_slice(&seq_num.to_be_bytes());
+    seed[4..].copy_from_slice(&checksum.to_be_bytes());
+    let mut rng = Xoshiro256::from_seed(&seed);
+
+    let weights: Vec<f64> = (1..=seq_len).map(|i| 1.0 / i as f64).collect();
+    let degree = AliasSampler::new(&weights).sample(&mut rng) + 1;
+
+    // The first `degree` picks of the reference's full shuffle
+    let mut remaining: Vec<usize> = (0..seq_len).collect();
+    let mut chosen = BTreeSet::new();
+    while chosen.len() < degree {
+        let index = rng.next_int(0, remaining.len() as u64 - 1) as usize;
+        chosen.insert(remaining.remove(index));
+    }
+    chosen
+}
+
+/// The xoshiro256** generator, seeded as UR encoders do
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    /// State from the SHA-256 of `seed`, read as four big-endian words
+    fn from_seed(seed: &[u8]) -> Self {
+        let hash = sha256::Hash::hash(seed).to_byte_array();
+        let mut s = [0u64; 4];
+        for (word, chunk) in s.iter_mut().zip(hash.chunks_exact(8)) {
+            *word = u64::from_be_bytes(chunk.try_into().expect("8-byte chunk"));
+        }
+        Xoshiro256 { s }
+    }
+
+    fn next(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    /// Integer in `low..=high`
+    fn next_int(&mut self, low: u64, high: u64) -> u64 {
+        (self.next_double() * (high - low + 1) as f64) as u64 + low
+    }
+}
+
+/// Vose's alias method over the given weights, as in the UR reference
+struct AliasSampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl AliasSampler {
+    fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let mut scaled: Vec<f64> = weights.iter().map(|w| w * n as f64 / sum).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).rev().partition(|&i| scaled[i] < 1.0);
+
+        let mut probs = vec![0.0; n];
+        let mut aliases = vec![0; n];
+        while let (Some(&a), Some(&g)) = (small.last(), large.last()) {
+            small.pop();
+            large.pop();
+            probs[a] = scaled[a];
+            aliases[a] = g;
+            scaled[g] += scaled[a] - 1.0;
+            if scaled[g] < 1.0 {
+                small.push(g);
+            } else {
+                large.push(g);
+            }
+        }
+        for i in large.into_iter().chain(small) {
+            probs[i] = 1.0;
+        }
+        AliasSampler { probs, aliases }
+    }
+
+    fn sample(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let i = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+/// Minimal bytewords of `data` followed by its CRC32
+fn bytewords_encode(data: &[u8]) -> String {
+    data.iter()
+        .chain(&crc32fast::hash(data).to_be_bytes())
+        .map(|&b| BYTEWORDS[b as usize])
+        .collect()
+}
+
+/// Decode minimal bytewords, checking and removing the CRC32
+fn bytewords_decode(text: &str) -> CoreResult<Vec<u8>> {
+    let invalid = |what: String| CoreError::SerializationError(format!("Invalid bytewords: {}", what));
+    if !text.is_ascii() || text.len() % 2 != 0 {
+        return Err(invalid("expected pairs of letters".to_string()));
+    }
+    let mut bytes = text
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).expect("ASCII");
+            BYTEWORDS
+                .iter()
+                .position(|word| *word == pair)
+                .map(|b| b as u8)
+                .ok_or_else(|| invalid(format!("unknown word \"{}\"", pair)))
+        })
+        .collect::<CoreResult<Vec<u8>>>()?;
+
+    let body_len = bytes
+        .len()
+        .checked_sub(4)
+        .ok_or_else(|| invalid("shorter than its checksum".to_string()))?;
+    let checksum = bytes.split_off(body_len);
+    if crc32fast::hash(&bytes).to_be_bytes()[..] != checksum[..] {
+        return Err(invalid("checksum mismatch".to_string()));
+    }
+    Ok(bytes)
+}
+
+const MAJOR_UINT: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+
+/// Append a CBOR head in its shortest form
+fn cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+/// A CBOR byte string holding `data`
+fn cbor_bytes(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 9);
+    cbor_head(&mut out, MAJOR_BYTES, data.len() as u64);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Reads the definite-length CBOR items UR bodies use
+struct CborReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        CborReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> CoreResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| CoreError::SerializationError("Truncated CBOR".to_string()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Major type and argument of the next item
+    fn head(&mut self) -> CoreResult<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let value = match initial & 0x1f {
+            n @ 0..=23 => n as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().expect("2 bytes")) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().expect("4 bytes")) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")),
+            _ => return Err(CoreError::SerializationError("Unsupported CBOR item".to_string())),
+        };
+        Ok((initial >> 5, value))
+    }
+
+    fn head_of(&mut self, major: u8) -> CoreResult<u64> {
+        match self.head()? {
+            (m, value) if m == major => Ok(value),
+            (m, _) => Err(CoreError::SerializationError(format!(
+                "Expected CBOR major type {}, found {}",
+                major, m
+            ))),
+        }
+    }
+
+    fn bytes(&mut self) -> CoreResult<&'a [u8]> {
+        let len = self.head_of(MAJOR_BYTES)?;
+        self.take(usize::try_from(len).unwrap_or(usize::MAX))
+    }
+
+    fn text(&mut self) -> CoreResult<&'a str> {
+        let len = self.head_of(MAJOR_TEXT)?;
+        let bytes = self.take(usize::try_from(len).unwrap_or(usize::MAX))?;
+        std::str::from_utf8(bytes).map_err(|e| CoreError::SerializationError(format!("Invalid CBOR text: {}", e)))
+    }
+
+    /// Skip one item, including any nested items
+    fn skip(&mut self) -> CoreResult<()> {
+        let mut pending: u64 = 1;
+        while pending > 0 {
+            pending -= 1;
+            let (major, value) = self.head()?;
+            match major {
+                MAJOR_BYTES | MAJOR_TEXT => {
+                    self.take(usize::try_from(value).unwrap_or(usize::MAX))?;
+                }
+                MAJOR_ARRAY => pending = pending.saturating_add(value),
+                MAJOR_MAP => pending = pending.saturating_add(value.saturating_mul(2)),
+                // Tags are followed by their item
+                6 => pending += 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> CoreResult<()> {
+        if self.pos != self.data.len() {
+            return Err(CoreError::SerializationError("Trailing bytes after CBOR item".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::{OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+
+    /// Unsigned PSBT of a few kilobytes
+    fn large_psbt() -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: (0..40)
+                .map(|i| TxIn {
+                    previous_output: OutPoint::new(Txid::from_byte_array(sha256::Hash::hash(&[i]).to_byte_array()), i as u32),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: (0..40)
+                .map(|i| TxOut {
+                    value: 1_000 + i,
+                    script_pubkey: ScriptBuf::from_bytes(vec![0x51, 0x20].into_iter().chain([i as u8; 32]).collect()),
+                })
+                .collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (i, input) in psbt.inputs.iter_mut().enumerate() {
+            input.witness_utxo = Some(TxOut {
+                value: 10_000 + i as u64,
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51, 0x20].into_iter().chain([0xaa; 32]).collect()),
+            });
+        }
+        psbt
+    }
+
+    /// `makeMessage()` of the UR reference tests: bytes from Xoshiro256**
+    /// seeded with `seed`, wrapped as a CBOR byte string
+    fn reference_message(len: usize, seed: &[u8]) -> Vec<u8> {
+        let mut rng = Xoshiro256::from_seed(seed);
+        let bytes: Vec<u8> = (0..len).map(|_| rng.next_int(0, 255) as u8).collect();
+        cbor_bytes(&bytes)
+    }
+
+    fn decode_all<'a>(parts: impl IntoIterator<Item = &'a String>) -> UrDecoder {
+        let mut decoder = UrDecoder::new();
+        for part in parts {
+            decoder.receive_part(part).unwrap();
+        }
+        decoder
+    }
+
+    #[test]
+    fn test_bytewords_vectors() {
+        // The PSBT magic, as it begins every crypto-psbt body
+        let encoded = bytewords_encode(b"psbt\xff");
+        assert!(encoded.starts_with("jojkidjyzm"));
+        assert_eq!(bytewords_decode(&encoded).unwrap(), b"psbt\xff");
+        assert_eq!(BYTEWORDS[0], "ae");
+        assert_eq!(BYTEWORDS[255], "zm");
+        assert_eq!(BYTEWORDS.iter().collect::<BTreeSet<_>>().len(), 256);
+
+        let mut corrupted = encoded.clone();
+        corrupted.replace_range(0..2, "ae");
+        assert!(matches!(bytewords_decode(&corrupted), Err(CoreError::SerializationError(_))));
+        assert!(bytewords_decode("jojk").is_err());
+        assert!(bytewords_decode("xxjkidjyzm").is_err());
+    }
+
+    #[test]
+    fn test_xoshiro_reference_sequence() {
+        // From the UR reference implementation's tests
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        let numbers: Vec<u64> = (0..16).map(|_| rng.next() % 100).collect();
+        assert_eq!(numbers, [42, 81, 85, 8, 82, 84, 76, 73, 70, 88, 2, 74, 40, 48, 77, 54]);
+    }
+
+    #[test]
+    fn test_bytewords_reference_vector() {
+        // From the UR reference implementation's tests
+        assert_eq!(bytewords_encode(&[0, 1, 2, 128, 255]), "aeadaolazmjendeoti");
+        assert_eq!(bytewords_decode("aeadaolazmjendeoti").unwrap(), [0, 1, 2, 128, 255]);
+    }
+
+    #[test]
+    fn test_single_part_reference_vector() {
+        // From the UR reference implementation's tests
+        let expected = "ur:bytes/hdeymejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtgwdpfnsboxgwlbaawzuefywkdplrsrjynbvygabwjldapfcsdwkbrkch";
+        let message = reference_message(50, b"Wolf");
+        let mut encoder = UrEncoder::new("bytes", message.clone(), 100);
+        assert_eq!(encoder.next_part(), expected);
+
+        let decoder = decode_all([&expected.to_string()]);
+        assert_eq!(decoder.ur_type(), Some("bytes"));
+        assert_eq!(decoder.message.as_deref(), Some(&message[..]));
+    }
+
+    #[test]
+    fn test_multi_part_reference_vector() {
+        // The first parts of the UR reference implementation's encoder
+        // test: 256 bytes in fragments of at most 30
+        let expected = [
+            "ur:bytes/1-9/lpadascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtdkgslpgh",
+            "ur:bytes/2-9/lpaoascfadaxcywenbpljkhdcagwdpfnsboxgwlbaawzuefywkdplrsrjynbvygabwjldapfcsgmghhkhstlrdcxaefz",
+        ];
+        let message = reference_message(256, b"Wolf");
+        let mut encoder = UrEncoder::new("bytes", message.clone(), 30);
+        assert_eq!(encoder.part_count(), 9);
+        for part in expected {
+            assert_eq!(encoder.next_part(), part);
+        }
+
+        let mut parts: Vec<String> = expected.iter().map(|part| part.to_string()).collect();
+        parts.extend((2..9).map(|_| encoder.next_part()));
+        let decoder = decode_all(&parts);
+        assert_eq!(decoder.message.as_deref(), Some(&message[..]));
+    }
+
+    #[test]
+    fn test_single_part_roundtrip() {
+        let psbt = large_psbt();
+        let parts = encode_psbt(&psbt, 100_000);
+        assert_eq!(parts.len(), 1);
+        // A two-byte CBOR length, then the PSBT magic
+        assert_eq!(&parts[0]["ur:crypto-psbt/".len() + 6..][..10], "jojkidjyzm");
+        assert!(!parts[0]["ur:crypto-psbt/".len()..].contains('/'));
+
+        // QR alphanumeric mode upper-cases parts
+        let decoder = decode_all([&parts[0].to_uppercase()]);
+        assert_eq!(decoder.progress(), 1.0);
+        assert_eq!(decoder.result().unwrap(), UrPayload::Psbt(psbt));
+    }
+
+    #[test]
+    fn test_multi_part_roundtrip_out_of_order_with_duplicates() {
+        let psbt = large_psbt();
+        assert!(psbt.serialize().len() > 4_000);
+        let parts = encode_psbt(&psbt, 200);
+        let count = parts.len();
+        assert!(count > 20);
+        assert!(parts[0].starts_with(&format!("ur:crypto-psbt/1-{}/", count)));
+
+        let mut decoder = UrDecoder::new();
+        assert_eq!(decoder.progress(), 0.0);
+        for part in parts.iter().rev().chain(parts.iter().step_by(3)) {
+            decoder.receive_part(part).unwrap();
+            if decoder.fragments_received().0 < count {
+                assert!(decoder.progress() < 1.0);
+                assert!(matches!(decoder.result(), Err(CoreError::InvalidInput(_))));
+            }
+        }
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.ur_type(), Some(UR_TYPE_PSBT));
+        assert_eq!(decoder.result().unwrap(), UrPayload::Psbt(psbt));
+    }
+
+    #[test]
+    fn test_fountain_parts_replace_missed_fragments() {
+        let psbt = large_psbt();
+        let mut encoder = UrEncoder::new(UR_TYPE_PSBT, cbor_bytes(&psbt.serialize()), 200);
+        let count = encoder.part_count();
+        let parts: Vec<String> = (0..count * 4).map(|_| encoder.next_part()).collect();
+
+        // Miss every other pure part; mixed parts fill the gaps
+        let mut decoder = UrDecoder::new();
+        let mut scanned = 0;
+        for part in parts.iter().step_by(2).chain(&parts[count..]) {
+            decoder.receive_part(part).unwrap();
+            scanned += 1;
+            if decoder.is_complete() {
+                break;
+            }
+        }
+        assert!(decoder.is_complete());
+        assert!(scanned < parts.len());
+        assert_eq!(decoder.result().unwrap(), UrPayload::Psbt(psbt));
+    }
+
+    #[test]
+    fn test_descriptor_roundtrip() {
+        let descriptor = format!("tr({}/0/*,pk({}/0/*))#abcdefgh", "a".repeat(111), "b".repeat(111));
+        let parts = encode_descriptor(&descriptor, 40);
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.starts_with("ur:output-descriptor/")));
+
+        let decoder = decode_all(parts.iter().rev());
+        assert_eq!(decoder.result().unwrap(), UrPayload::Descriptor(descriptor));
+    }
+
+    #[test]
+    fn test_decoder_rejects_foreign_and_malformed_parts() {
+        let psbt_parts = encode_psbt(&large_psbt(), 200);
+        let other_parts = encode_descriptor(&"c".repeat(500), 200);
+
+        let mut decoder = UrDecoder::new();
+        decoder.receive_part(&psbt_parts[0]).unwrap();
+        assert!(matches!(decoder.receive_part(&other_parts[0]), Err(CoreError::InvalidInput(_))));
+
+        // Same type, different message
+        let mut shorter = large_psbt();
+        shorter.inputs.pop();
+        shorter.unsigned_tx.input.pop();
+        let shorter_parts = encode_psbt(&shorter, 200);
+        assert!(matches!(decoder.receive_part(&shorter_parts[1]), Err(CoreError::InvalidInput(_))));
+
+        for malformed in [
+            "crypto-psbt/1-2/aeae",
+            "ur:crypto-psbt",
+            "ur:crypto psbt/aeaeaeae",
+            "ur:crypto-psbt/x-2/aeaeaeaeae",
+            "ur:crypto-psbt/1-2/3/aeae",
+        ] {
+            assert!(
+                matches!(decoder.receive_part(malformed), Err(CoreError::SerializationError(_))),
+                "{}",
+                malformed
+            );
+        }
+        // A part whose label disagrees with its body
+        let relabeled = psbt_parts[1].replacen("/2-", "/3-", 1);
+        assert!(decoder.receive_part(&relabeled).is_err());
+        assert_eq!(decoder.fragments_received(), (1, psbt_parts.len()));
+    }
+
+    /// Multi-part UR `1-<seq_len>` claiming `message_len` bytes, with
+    /// `data` as its fragment
+    fn crafted_part(seq_len: u64, message_len: u64, data: &[u8]) -> String {
+        let mut body = Vec::new();
+        cbor_head(&mut body, MAJOR_ARRAY, 5);
+        cbor_head(&mut body, MAJOR_UINT, 1);
+        cbor_head(&mut body, MAJOR_UINT, seq_len);
+        cbor_head(&mut body, MAJOR_UINT, message_len);
+        cbor_head(&mut body, MAJOR_UINT, 0);
+        cbor_head(&mut body, MAJOR_BYTES, data.len() as u64);
+        body.extend_from_slice(data);
+        format!("ur:bytes/1-{}/{}", seq_len, bytewords_encode(&body))
+    }
+
+    #[test]
+    fn test_decoder_rejects_overflowing_sequence_count() {
+        let mut decoder = UrDecoder::new();
+        let part = crafted_part(u64::MAX, 100, &[0; 20]);
+        assert!(matches!(decoder.receive_part(&part), Err(CoreError::SerializationError(_))));
+        assert_eq!(decoder.fragments_received(), (0, 0));
+    }
+
+    #[test]
+    fn test_decoder_rejects_oversized_sequences_and_tiny_fragments() {
+        for part in [
+            // Four million one-byte fragments
+            crafted_part(4_000_000, 4_000_000, &[0]),
+            crafted_part(MAX_FRAGMENT_COUNT as u64 + 1, (MAX_FRAGMENT_COUNT as u64 + 1) * 20, &[0; 20]),
+            crafted_part(100, 500, &[0; 5]),
+        ] {
+            let mut decoder = UrDecoder::new();
+            assert!(matches!(decoder.receive_part(&part), Err(CoreError::SerializationError(_))), "{}", part);
+            assert_eq!(decoder.fragments_received(), (0, 0));
+        }
+
+        // The encoder stays within both bounds
+        let short = UrEncoder::new("bytes", vec![7; 15], 1);
+        assert_eq!(short.part_count(), 1);
+        let long = UrEncoder::new("bytes", cbor_bytes(&[7; 100_000]), 10);
+        assert!(long.part_count() <= MAX_FRAGMENT_COUNT);
+        let parts = long.parts();
+        let decoder = decode_all(&parts);
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn test_cancelled_part_leaves_decoder_unchanged() {
+        let parts = encode_psbt(&large_psbt(), 200);
+        let mut decoder = decode_all(&parts[..2]);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(decoder.receive_part_cancellable(&parts[2], &cancel), Err(CoreError::Cancelled)));
+        assert_eq!(decoder.fragments_received(), (2, parts.len()));
+
+        for part in &parts[2..] {
+            decoder.receive_part_cancellable(part, &CancelToken::new()).unwrap();
+        }
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn test_empty_message_is_one_part() {
+        let mut encoder = UrEncoder::new("bytes", vec![], 100);
+        assert_eq!(encoder.part_count(), 1);
+        assert!(encoder.is_single_part());
+        let decoder = decode_all([&encoder.next_part()]);
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn test_unsupported_type() {
+        let mut encoder = UrEncoder::new("crypto-seed", cbor_bytes(&[1, 2, 3]), 100);
+        let decoder = decode_all([&encoder.next_part()]);
+        assert!(decoder.is_complete());
+        assert!(matches!(decoder.result(), Err(CoreError::InvalidInput(_))));
+    }
+}
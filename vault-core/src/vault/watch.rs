@@ -0,0 +1,547 @@
+//! Recognizing spends of vault outputs in raw transactions, for watchtowers
+//!
+//! A raw transaction doesn't carry the outputs it spends, so everything
+//! here is inferred from the witness. A script-path witness reveals the
+//! leaf script and control block, which together commit to the output
+//! key and so to the vault index. A key-path witness reveals only a
+//! signature.
+//!
+//! Once an unexpected unvault is seen, `build_clawback()` sweeps whatever
+//! of it is still held by a vault script to a cold address.
+//! `electrum_script_hashes()` lists the vault's addresses in the form an
+//! Electrum server subscribes to.
+
+use std::ops::Range;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::key::TapTweak;
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash};
+use bitcoin::{Address, OutPoint, Script, ScriptBuf, Transaction, Witness};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, CoreResult};
+use crate::taproot::{self, LeafPurpose};
+
+use super::{psbt, Vault};
+
+/// Highest vault index `classify_spend()` and `build_clawback()` scan
+/// when matching an output to the vault
+pub const MAX_WATCH_INDEX: u32 = 999;
+
+/// How a vault output is being spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendPath {
+    /// Owner key after the full delay: an unvault
+    Timelock,
+    /// Owner key after the shorter delay, to an approved destination
+    WhitelistTimelock,
+    /// Immediate sweep by the recovery key
+    Emergency,
+    /// Immediate sweep by k-of-n cosigners
+    Multisig,
+    /// Sweep by the heirs after the inactivity delay
+    Inheritance,
+    /// Spend by the recovery key after the absolute lock
+    AbsoluteLock,
+    /// Sweep by the recovery service, revealing the hashlock preimage
+    Hashlock,
+    /// Spend by k-of-n signers through the given stage of a degrading tree
+    DegradingStage(u8),
+    /// Key-path spend by the internal key
+    KeyPath,
+    /// Script-path spend of a leaf this vault's trees don't have
+    Unknown,
+}
+
+impl From<LeafPurpose> for SpendPath {
+    fn from(purpose: LeafPurpose) -> Self {
+        match purpose {
+            LeafPurpose::Timelock => SpendPath::Timelock,
+            LeafPurpose::WhitelistTimelock => SpendPath::WhitelistTimelock,
+            LeafPurpose::Emergency => SpendPath::Emergency,
+            LeafPurpose::Multisig => SpendPath::Multisig,
+            LeafPurpose::Inheritance => SpendPath::Inheritance,
+            LeafPurpose::AbsoluteLock => SpendPath::AbsoluteLock,
+            LeafPurpose::Hashlock => SpendPath::Hashlock,
+            LeafPurpose::DegradingStage(stage) => SpendPath::DegradingStage(stage),
+            // OP_RETURN leaves can't be satisfied
+            LeafPurpose::Metadata => SpendPath::Unknown,
+        }
+    }
+}
+
+/// An output of the spending transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendDestination {
+    /// Address of the output, or `None` for non-standard scripts and OP_RETURN
+    pub address: Option<String>,
+    pub script_pubkey: ScriptBuf,
+    pub amount_sats: u64,
+}
+
+/// A watched outpoint spent by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendEvent {
+    /// Index of the spending input
+    pub input_index: usize,
+    /// The watched outpoint it spends
+    pub outpoint: OutPoint,
+    /// Vault index of the spent output, if the witness identifies it
+    pub vault_index: Option<u32>,
+    pub path: SpendPath,
+    /// Every output of the transaction
+    pub destinations: Vec<SpendDestination>,
+}
+
+/// Classify each input of `tx_hex` spending one of `watched_outpoints`
+///
+/// Script-path spends are matched to the tree of `vault`'s keys at the
+/// vault's own index first, then at indices up to `MAX_WATCH_INDEX`;
+/// the revealed leaf names the path. Spends of a leaf none of those
+/// trees commit to are `SpendPath::Unknown`, with no vault index.
+/// Key-path spends are `SpendPath::KeyPath` and, since the witness
+/// doesn't reveal the output key, have no vault index either. Inputs
+/// spending other outpoints are skipped. Only malformed transaction
+/// hex is an error.
+pub fn classify_spend(tx_hex: &str, vault: &Vault, watched_outpoints: &[OutPoint]) -> CoreResult<Vec<SpendEvent>> {
+    let bytes = hex::decode(tx_hex.trim())
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid transaction hex: {}", e)))?;
+    let tx: Transaction = bitcoin::consensus::deserialize(&bytes)
+        .map_err(|e| CoreError::InvalidInput(format!("Invalid transaction: {}", e)))?;
+
+    let destinations: Vec<SpendDestination> = tx
+        .output
+        .iter()
+        .map(|output| SpendDestination {
+            address: Address::from_script(&output.script_pubkey, vault.network().into())
+                .ok()
+                .map(|address| address.to_string()),
+            script_pubkey: output.script_pubkey.clone(),
+            amount_sats: output.value,
+        })
+        .collect();
+
+    let mut events = Vec::new();
+    for (input_index, input) in tx.input.iter().enumerate() {
+        if !watched_outpoints.contains(&input.previous_output) {
+            continue;
+        }
+        let (vault_index, path) = match script_path(&input.witness) {
+            Some((leaf_script, control_block)) => classify_leaf(vault, leaf_script, &control_block)?,
+            None => (None, SpendPath::KeyPath),
+        };
+        events.push(SpendEvent {
+            input_index,
+            outpoint: input.previous_output,
+            vault_index,
+            path,
+            destinations: destinations.clone(),
+        });
+    }
+    Ok(events)
+}
+
+/// Recovery PSBT sweeping the outputs of `unvault_tx` still held by the vault
+///
+/// An unvault pays its destination directly once the delay has passed;
+/// only outputs returning to a vault tree (the change of a partial
+/// unvault) remain reachable, through that tree's emergency leaf.
+/// Those outputs are matched like `classify_spend()` matches spends, up
+/// to `MAX_WATCH_INDEX`, and swept to `cold_address` with
+/// `psbt::build_recovery()`, so the PSBT carries no relative lock and
+/// can be broadcast as soon as it is signed. With
+/// `vault.psbt_vault_info()`, the PSBT carries `psbt::attach_vault_info()`
+/// records.
+///
+/// Fails with `PolicyViolation` if the vault has no emergency leaf or
+/// no output of `unvault_tx` pays back to the vault.
+pub fn build_clawback(
+    unvault_tx: &Transaction,
+    vault: &Vault,
+    cold_address: Address,
+    fee_rate: u64,
+    current_block_height: Option<u32>,
+) -> CoreResult<Psbt> {
+    if vault.tree().leaf(LeafPurpose::Emergency).is_none() {
+        return Err(CoreError::PolicyViolation(format!(
+            "{} vault has no emergency leaf, so unvaults can't be clawed back",
+            vault.template().template_id()
+        )));
+    }
+
+    let txid = unvault_tx.txid();
+    let mut utxos = Vec::new();
+    for (vout, output) in unvault_tx.output.iter().enumerate() {
+        let vault_index = if output.script_pubkey == vault.script_pubkey() {
+            Some(vault.index())
+        } else {
+            taproot::find_vault_index(
+                vault.template(),
+                vault.owner_xpub(),
+                vault.recovery_xpub(),
+                vault.network(),
+                vault.tree_version(),
+                &output.script_pubkey,
+                MAX_WATCH_INDEX,
+            )?
+        };
+        if let Some(vault_index) = vault_index {
+            let outpoint = OutPoint::new(txid, vout as u32);
+            utxos.push(psbt::VaultUtxo::new(outpoint, output.value, vault.tree_at(vault_index)?));
+        }
+    }
+
+    if utxos.is_empty() {
+        return Err(CoreError::PolicyViolation(format!(
+            "Unvault {} pays nothing back to the vault; its outputs are out of the emergency leaf's reach",
+            txid
+        )));
+    }
+    let mut psbt = psbt::build_recovery(
+        &utxos,
+        Some(LeafPurpose::Emergency),
+        cold_address,
+        fee_rate,
+        psbt::SpendOptions {
+            current_block_height,
+            dust: vault.dust_policy(),
+            ..Default::default()
+        },
+    )?;
+    if vault.psbt_vault_info() {
+        let spend_path = psbt::built_spend_path(&psbt)?;
+        psbt::attach_vault_info(&mut psbt, &vault.metadata(), spend_path)?;
+    }
+    Ok(psbt)
+}
+
+/// Electrum-protocol script hash of `script_pubkey`: its SHA256, byte
+/// reversed, in hex, as `blockchain.scripthash.subscribe` takes it
+pub fn electrum_script_hash(script_pubkey: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script_pubkey.as_bytes()).to_byte_array();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Vault index and Electrum script hash of the vault address at each
+/// index in `range`, for a watchtower subscribing through an Electrum
+/// server
+///
+/// Unvaults pay their destination directly, and change goes to a vault
+/// address, so no template has an intermediate unvault script to add.
+/// Fails with `InvalidInput` for a range of more than
+/// `taproot::MAX_ADDRESS_RANGE` indices, and as `Vault::tree_at()` does.
+pub fn electrum_script_hashes(vault: &Vault, range: Range<u32>) -> CoreResult<Vec<(u32, String)>> {
+    if range.len() > taproot::MAX_ADDRESS_RANGE as usize {
+        return Err(CoreError::InvalidInput(format!(
+            "Cannot derive {} script hashes at once (maximum {})",
+            range.len(),
+            taproot::MAX_ADDRESS_RANGE
+        )));
+    }
+    range
+        .map(|index| Ok((index, electrum_script_hash(&vault.tree_at(index)?.script_pubkey()))))
+        .collect()
+}
+
+/// Leaf script and control block of a script-path witness, per BIP341
+///
+/// `None` for a key-path witness: a single element once any annex is
+/// removed, or anything whose last element isn't a control block.
+pub(crate) fn script_path(witness: &Witness) -> Option<(&Script, ControlBlock)> {
+    let mut elements: Vec<&[u8]> = witness.iter().collect();
+    if elements.len() >= 2 && elements.last()?.first() == Some(&0x50) {
+        elements.pop();
+    }
+    if elements.len() < 2 {
+        return None;
+    }
+    let control_block = ControlBlock::decode(elements.pop()?).ok()?;
+    Some((Script::from_bytes(elements.pop()?), control_block))
+}
+
+/// Vault index and path of a script-path spend of `leaf_script`
+fn classify_leaf(
+    vault: &Vault,
+    leaf_script: &Script,
+    control_block: &ControlBlock,
+) -> CoreResult<(Option<u32>, SpendPath)> {
+    let unknown = (None, SpendPath::Unknown);
+    if control_block.leaf_version != LeafVersion::TapScript {
+        return Ok(unknown);
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut node = TapNodeHash::from(TapLeafHash::from_script(leaf_script, control_block.leaf_version));
+    for sibling in control_block.merkle_branch.as_inner() {
+        node = TapNodeHash::from_node_hashes(node, *sibling);
+    }
+    let (output_key, _) = control_block.internal_key.tap_tweak(&secp, Some(node));
+    let script_pubkey = ScriptBuf::new_v1_p2tr_tweaked(output_key);
+
+    let vault_index = if script_pubkey == vault.script_pubkey() {
+        Some(vault.index())
+    } else {
+        taproot::find_vault_index(
+            vault.template(),
+            vault.owner_xpub(),
+            vault.recovery_xpub(),
+            vault.network(),
+            vault.tree_version(),
+            &script_pubkey,
+            MAX_WATCH_INDEX,
+        )?
+    };
+    let Some(vault_index) = vault_index else {
+        return Ok(unknown);
+    };
+
+    let tree = vault.tree_at(vault_index)?;
+    Ok(tree
+        .leaves()
+        .iter()
+        .find(|leaf| leaf.script.as_script() == leaf_script)
+        .map_or(unknown, |leaf| (Some(vault_index), leaf.purpose.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common::regtest_vault;
+    use std::str::FromStr;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::{Sequence, TxIn, TxOut, Txid};
+
+    use crate::taproot::{LeafId, VaultTree};
+    use crate::vault::psbt::SpendOptions;
+    use crate::vault::{Network, VaultTemplate};
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::from_str(&"ab".repeat(32)).unwrap(), vout)
+    }
+
+    /// Witness spending `leaf` of `tree` with a dummy 64-byte signature
+    fn leaf_witness(tree: &VaultTree, leaf: LeafId) -> Witness {
+        let control_block = taproot::control_block(tree, leaf).unwrap();
+        let mut witness = Witness::new();
+        witness.push([1u8; 64]);
+        witness.push(tree.leaf(leaf).unwrap().script.as_bytes());
+        witness.push(control_block.serialize());
+        witness
+    }
+
+    fn tx_hex(inputs: Vec<(OutPoint, Witness)>, outputs: Vec<TxOut>) -> String {
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .into_iter()
+                .map(|(previous_output, witness)| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness,
+                })
+                .collect(),
+            output: outputs,
+        };
+        hex::encode(bitcoin::consensus::serialize(&tx))
+    }
+
+    #[test]
+    fn test_classify_timelock_and_emergency_leaves() {
+        let vault = regtest_vault(VaultTemplate::spending(), 3);
+        let destination = regtest_vault(VaultTemplate::savings(), 0);
+        let outputs = vec![TxOut { value: 90_000, script_pubkey: destination.script_pubkey() }];
+        let tx = tx_hex(
+            vec![
+                (outpoint(0), leaf_witness(vault.tree(), LeafPurpose::Timelock)),
+                (outpoint(1), leaf_witness(vault.tree(), LeafPurpose::Emergency)),
+            ],
+            outputs,
+        );
+
+        let events = classify_spend(&tx, &vault, &[outpoint(0), outpoint(1)]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].input_index, events[0].outpoint), (0, outpoint(0)));
+        assert_eq!((events[0].vault_index, events[0].path), (Some(3), SpendPath::Timelock));
+        assert_eq!((events[1].vault_index, events[1].path), (Some(3), SpendPath::Emergency));
+        assert_eq!(
+            events[0].destinations,
+            vec![SpendDestination {
+                address: Some(destination.address().to_string()),
+                script_pubkey: destination.script_pubkey(),
+                amount_sats: 90_000,
+            }]
+        );
+
+        // Only watched outpoints are reported
+        let events = classify_spend(&tx, &vault, &[outpoint(1)]).unwrap();
+        assert_eq!((events.len(), events[0].input_index), (1, 1));
+    }
+
+    #[test]
+    fn test_classify_finds_other_vault_index() {
+        let vault = regtest_vault(VaultTemplate::savings(), 0);
+        let tree = vault.tree_at(17).unwrap();
+        let tx = tx_hex(vec![(outpoint(0), leaf_witness(&tree, LeafPurpose::Emergency))], vec![]);
+
+        let events = classify_spend(&tx, &vault, &[outpoint(0)]).unwrap();
+        assert_eq!((events[0].vault_index, events[0].path), (Some(17), SpendPath::Emergency));
+        assert!(events[0].destinations.is_empty());
+    }
+
+    #[test]
+    fn test_classify_key_path_and_unknown_leaves() {
+        let vault = regtest_vault(VaultTemplate::savings(), 0);
+        let mut key_path = Witness::new();
+        key_path.push([1u8; 64]);
+        // The same signature with an annex is still a key-path spend
+        let mut annexed = key_path.clone();
+        annexed.push([0x50, 0x00]);
+        // A leaf of another vault's tree
+        let foreign = regtest_vault(VaultTemplate::spending(), 0);
+        let foreign_leaf = leaf_witness(foreign.tree(), LeafPurpose::Timelock);
+
+        let tx = tx_hex(
+            vec![(outpoint(0), key_path), (outpoint(1), annexed), (outpoint(2), foreign_leaf)],
+            vec![],
+        );
+        let events = classify_spend(&tx, &vault, &[outpoint(0), outpoint(1), outpoint(2)]).unwrap();
+        let paths: Vec<_> = events.iter().map(|event| (event.vault_index, event.path)).collect();
+        assert_eq!(
+            paths,
+            [(None, SpendPath::KeyPath), (None, SpendPath::KeyPath), (None, SpendPath::Unknown)]
+        );
+    }
+
+    /// Unsigned unvault of a 100k-sat UTXO of `vault`, sending `amount`
+    /// (or everything, if `None`) to an address outside the vault, with
+    /// change to the next vault index
+    fn unvault_tx(vault: &Vault, amount: Option<u64>) -> Transaction {
+        let utxo = vault.utxo(outpoint(0), 100_000);
+        let destination = crate::vault::policy::validate_address(
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            Network::Regtest,
+        )
+        .unwrap();
+        let psbt = match amount {
+            Some(amount) => {
+                let change = vault.change_target(vault.index() + 1).unwrap();
+                psbt::build_partial_unvault(
+                    utxo,
+                    destination,
+                    amount,
+                    &change,
+                    2,
+                    &vault.metadata(),
+                    SpendOptions::default(),
+                )
+                    .map(|bundle| bundle.psbt)
+            }
+            None => psbt::build_unvault(utxo, destination, 2, &vault.metadata(), SpendOptions::default()),
+        }
+        .unwrap();
+        psbt.unsigned_tx
+    }
+
+    #[test]
+    fn test_clawback_sweeps_change_through_emergency_leaf() {
+        let vault = regtest_vault(VaultTemplate::spending(), 5);
+        let unvault = unvault_tx(&vault, Some(30_000));
+        let change_tree = vault.tree_at(6).unwrap();
+        assert_eq!(unvault.output[1].script_pubkey, change_tree.script_pubkey());
+
+        let cold = regtest_vault(VaultTemplate::savings(), 41).address();
+        let psbt = build_clawback(&unvault, &vault, cold.clone(), 2, None).unwrap();
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, OutPoint::new(unvault.txid(), 1));
+        // No relative lock: valid as soon as the unvault confirms
+        assert!(!tx.input[0].sequence.is_relative_lock_time());
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].script_pubkey, cold.script_pubkey());
+        assert!(tx.output[0].value < unvault.output[1].value);
+
+        let emergency = change_tree.leaf(LeafPurpose::Emergency).unwrap();
+        let (script, _) = psbt.inputs[0].tap_scripts.values().next().unwrap();
+        assert_eq!(script, &emergency.script);
+    }
+
+    #[test]
+    fn test_clawback_follows_change_to_other_index() {
+        // The vault handed to the watchtower is at index 0; the unvault is at 8
+        // and its change at 9
+        let unvaulted = regtest_vault(VaultTemplate::savings(), 8);
+        let unvault = unvault_tx(&unvaulted, Some(30_000));
+        let vault = regtest_vault(VaultTemplate::savings(), 0);
+
+        let psbt = build_clawback(&unvault, &vault, vault.address(), 2, None).unwrap();
+        let input = &psbt.inputs[0];
+        let change_tree = unvaulted.tree_at(9).unwrap();
+        assert_eq!(input.witness_utxo.as_ref().unwrap().script_pubkey, change_tree.script_pubkey());
+        assert_eq!(input.tap_merkle_root, change_tree.merkle_root());
+    }
+
+    #[test]
+    fn test_clawback_impossible_for_direct_unvaults() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let unvault = unvault_tx(&vault, None);
+        match build_clawback(&unvault, &vault, vault.address(), 2, None) {
+            Err(CoreError::PolicyViolation(msg)) => assert!(msg.contains("pays nothing back"), "{}", msg),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+
+        // Change returns to the vault, but there is no leaf to sweep it with
+        let timelock_only = regtest_vault(
+            VaultTemplate::custom(144, crate::vault::RecoveryType::TimelockOnly).unwrap(),
+            0,
+        );
+        let unvault = unvault_tx(&timelock_only, Some(30_000));
+        match build_clawback(&unvault, &timelock_only, vault.address(), 2, None) {
+            Err(CoreError::PolicyViolation(msg)) => assert!(msg.contains("no emergency leaf"), "{}", msg),
+            other => panic!("expected PolicyViolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_classify_rejects_malformed_hex() {
+        let vault = regtest_vault(VaultTemplate::savings(), 0);
+        assert!(matches!(classify_spend("zz", &vault, &[]), Err(CoreError::InvalidInput(_))));
+        assert!(matches!(classify_spend("0200", &vault, &[]), Err(CoreError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_electrum_script_hash_vector() {
+        // The Electrum protocol docs' example: P2PKH of 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let script = ScriptBuf::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+        assert_eq!(
+            electrum_script_hash(&script),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    #[test]
+    fn test_electrum_script_hashes() {
+        let vault = regtest_vault(VaultTemplate::spending(), 0);
+        let hashes = electrum_script_hashes(&vault, 3..6).unwrap();
+        assert_eq!(hashes.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![3, 4, 5]);
+        for (index, hash) in &hashes {
+            let script_pubkey = vault.tree_at(*index).unwrap().script_pubkey();
+            assert_eq!(*hash, electrum_script_hash(&script_pubkey));
+        }
+        // sha256(5120bd13...d155), reversed, computed outside the crate
+        assert_eq!(hashes[0].1, "257c60a6b1a6c1bc519ffb1e13a5299bd89e3341cb2ad4507d1978970e7c0bf3");
+
+        assert!(electrum_script_hashes(&vault, 0..0).unwrap().is_empty());
+        assert!(matches!(
+            electrum_script_hashes(&vault, 0..taproot::MAX_ADDRESS_RANGE + 1),
+            Err(CoreError::InvalidInput(_))
+        ));
+    }
+}
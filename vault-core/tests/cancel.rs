@@ -0,0 +1,108 @@
+//! Cancelling long-running FFI calls from another thread
+//!
+//! A host's UI thread trips the token while a worker thread runs the
+//! scan. Tripping it midway would race the scan, so these tests trip it
+//! from another thread before the worker starts; the scan must still
+//! return 4005 rather than run its 10,000 indices.
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+use serde_json::Value;
+
+use vault_core::ffi::CancelTokenHandle;
+use vault_core::vault::VaultBuilder;
+use vault_core::{
+    free_rust_string, vault_cancel, vault_cancel_token_free, vault_cancel_token_new, vault_derive_addresses_cancellable,
+    vault_find_address_index_cancellable, vault_last_error_code, vault_scan_indices_cancellable, Network, VaultTemplate,
+};
+
+use common::{payload, OWNER_TPUB, RECOVERY_TPUB};
+
+fn config() -> CString {
+    let config = serde_json::json!({
+        "network": "regtest",
+        "template": {"type": "spending"},
+        "owner_xpub": OWNER_TPUB,
+        "recovery_xpub": RECOVERY_TPUB,
+    });
+    CString::new(config.to_string()).unwrap()
+}
+
+fn take(result_ptr: *mut c_char) -> Value {
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    payload(&result)
+}
+
+/// Trip a token from one thread, then run `call` with it on another,
+/// returning its response
+fn run_cancelled(call: fn(*const CancelTokenHandle) -> Value) -> Value {
+    let token = vault_cancel_token_new();
+    assert!(!token.is_null());
+    // Raw pointers aren't Send; the token itself is shared safely
+    let token_addr = token as usize;
+    std::thread::spawn(move || assert_eq!(vault_cancel(token_addr as *const CancelTokenHandle), 0))
+        .join()
+        .unwrap();
+    let response = std::thread::spawn(move || call(token_addr as *const CancelTokenHandle)).join().unwrap();
+
+    assert_eq!(vault_cancel_token_free(token), 0);
+    response
+}
+
+fn assert_cancelled(response: &Value) {
+    assert_eq!(response["error"], true, "{}", response);
+    assert_eq!(response["code"], 4005, "{}", response);
+}
+
+#[test]
+fn test_cancel_scan_indices() {
+    let response = run_cancelled(|token| {
+        let spks = CString::new("[]").unwrap();
+        take(vault_scan_indices_cancellable(config().as_ptr(), spks.as_ptr(), 10_000, token))
+    });
+    assert_cancelled(&response);
+}
+
+#[test]
+fn test_cancel_derive_addresses() {
+    let response = run_cancelled(|token| take(vault_derive_addresses_cancellable(config().as_ptr(), 0, 10_000, token)));
+    assert_cancelled(&response);
+}
+
+#[test]
+fn test_cancel_find_address_index() {
+    let response = run_cancelled(|token| {
+        // An address of another template's vault matches no index, so the
+        // scan would run to the end
+        let other = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_TPUB)
+            .recovery_xpub(RECOVERY_TPUB)
+            .network(Network::Regtest)
+            .build()
+            .unwrap();
+        let address = CString::new(other.address().to_string()).unwrap();
+        take(vault_find_address_index_cancellable(config().as_ptr(), address.as_ptr(), 10_000, token))
+    });
+    assert_cancelled(&response);
+}
+
+#[test]
+fn test_untripped_token_and_bad_tokens() {
+    let token = vault_cancel_token_new();
+    let spks = CString::new("[]").unwrap();
+    let response = take(vault_scan_indices_cancellable(config().as_ptr(), spks.as_ptr(), 5, token));
+    assert_eq!(response["next_index"], 0, "{}", response);
+    assert_eq!(vault_cancel_token_free(token), 0);
+
+    let response = take(vault_scan_indices_cancellable(config().as_ptr(), spks.as_ptr(), 5, std::ptr::null()));
+    assert_eq!(response["code"], 4002, "{}", response);
+    assert_eq!(vault_cancel(std::ptr::null()), -1);
+    assert_eq!(vault_last_error_code(), 4002);
+    assert_eq!(vault_cancel_token_free(std::ptr::null_mut()), -1);
+}
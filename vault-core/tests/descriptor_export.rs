@@ -0,0 +1,164 @@
+//! Derive addresses from exported descriptors with rust-miniscript and
+//! compare them to the vault's own derivation
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::str::FromStr;
+
+use bitcoin::bip32::{ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::Secp256k1;
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+
+use vault_core::keys;
+use vault_core::taproot::{self, TreeVersion};
+use vault_core::vault::descriptor::to_core_descriptor;
+use vault_core::{AbsoluteLockUnit, DelayUnit, HashlockRecovery, Network, RecoveryType, VaultTemplate};
+
+use common::{custom_template, CustomFields, OWNER_TPUB, OWNER_XPUB, RECOVERY_TPUB, RECOVERY_XPUB, THIRD_XPUB};
+
+fn assert_descriptor_matches(template: &VaultTemplate, owner: &str, recovery: &str, network: Network) {
+    assert_versioned_descriptor_matches(template, owner, recovery, network, TreeVersion::V2);
+}
+
+fn assert_versioned_descriptor_matches(
+    template: &VaultTemplate,
+    owner: &str,
+    recovery: &str,
+    network: Network,
+    version: TreeVersion,
+) {
+    let owner = keys::parse_xpub(owner, network).unwrap();
+    let recovery = keys::parse_xpub(recovery, network).unwrap();
+
+    let exported = to_core_descriptor(template, &owner, &recovery, network, version).unwrap();
+    // Parsing with the checksum attached also validates it
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&exported).unwrap();
+    assert!(descriptor.has_wildcard());
+
+    for index in 0..5 {
+        let from_descriptor = descriptor
+            .at_derivation_index(index)
+            .unwrap()
+            .address(network.into())
+            .unwrap();
+        let from_vault = taproot::vault_tree_versioned(template, &owner, &recovery, index, network, version)
+            .unwrap()
+            .address(network);
+        assert_eq!(from_descriptor, from_vault, "index {}", index);
+    }
+}
+
+#[test]
+fn test_savings_descriptor_addresses_mainnet() {
+    assert_descriptor_matches(&VaultTemplate::savings(), OWNER_XPUB, RECOVERY_XPUB, Network::Mainnet);
+}
+
+#[test]
+fn test_spending_descriptor_addresses_regtest() {
+    assert_descriptor_matches(&VaultTemplate::spending(), OWNER_TPUB, RECOVERY_TPUB, Network::Regtest);
+}
+
+#[test]
+fn test_timelock_only_descriptor_addresses() {
+    let template = custom_template(CustomFields { delay_blocks: 52_560, ..Default::default() });
+    assert_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Signet);
+}
+
+#[test]
+fn test_key_path_enabled_descriptor_addresses() {
+    let template = custom_template(CustomFields {
+        delay_blocks: 1_008,
+        recovery_type: RecoveryType::EmergencyKey,
+        key_path_enabled: true,
+        ..Default::default()
+    });
+    assert_descriptor_matches(&template, OWNER_XPUB, RECOVERY_XPUB, Network::Mainnet);
+}
+
+#[test]
+fn test_time_based_descriptor_addresses() {
+    let template = custom_template(CustomFields {
+        delay_blocks: 1_024,
+        delay_unit: DelayUnit::TimeUnits512s,
+        recovery_type: RecoveryType::EmergencyKey,
+        ..Default::default()
+    });
+    assert_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Regtest);
+}
+
+#[test]
+fn test_inheritance_descriptor_addresses() {
+    let third = THIRD_XPUB;
+    let template = VaultTemplate::Inheritance {
+        heir_threshold: 2,
+        heir_count: 3,
+        inactivity_blocks: 26_000,
+        heirs: vec![RECOVERY_XPUB.to_string(), third.to_string(), OWNER_XPUB.to_string()],
+    };
+    assert_descriptor_matches(&template, OWNER_XPUB, RECOVERY_XPUB, Network::Mainnet);
+}
+
+#[test]
+fn test_absolute_lock_descriptor_addresses() {
+    for (absolute_lock, absolute_lock_unit) in [(1_000_000, AbsoluteLockUnit::Height), (1_700_000_000, AbsoluteLockUnit::Seconds)] {
+        let template = custom_template(CustomFields {
+            delay_blocks: 4_320,
+            absolute_lock: Some(absolute_lock),
+            absolute_lock_unit,
+            ..Default::default()
+        });
+        assert_versioned_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Regtest, TreeVersion::V4);
+    }
+}
+
+#[test]
+fn test_three_leaf_absolute_lock_descriptor_addresses() {
+    // Weighted into {timelock,{lock,hashlock}}; rust-miniscript 10 has
+    // no sortedmulti_a, so the hashlock stands in for a multisig leaf
+    let secp = Secp256k1::new();
+    let service = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[9; 32]).unwrap();
+    let template = custom_template(CustomFields {
+        delay_blocks: 4_320,
+        absolute_lock: Some(1_000_000),
+        hashlock: Some(HashlockRecovery {
+            hash: sha256::Hash::hash(b"recovery secret"),
+            service_xpub: ExtendedPubKey::from_priv(&secp, &service).to_string(),
+        }),
+        ..Default::default()
+    });
+    assert_versioned_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Regtest, TreeVersion::V4);
+}
+
+#[test]
+fn test_hashlock_descriptor_addresses() {
+    // The recovery key has no leaf in a timelock-only vault, so it can
+    // stand in for the service
+    let template = custom_template(CustomFields {
+        delay_blocks: 4_320,
+        hashlock: Some(HashlockRecovery {
+            hash: sha256::Hash::hash(b"recovery secret"),
+            service_xpub: RECOVERY_TPUB.to_string(),
+        }),
+        ..Default::default()
+    });
+    assert_versioned_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Regtest, TreeVersion::V4);
+}
+
+#[test]
+fn test_emergency_key_with_hashlock_descriptor_addresses() {
+    // Weighted into {timelock,{emergency,hashlock}}
+    let secp = Secp256k1::new();
+    let service = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[9; 32]).unwrap();
+    let template = custom_template(CustomFields {
+        delay_blocks: 4_320,
+        recovery_type: RecoveryType::EmergencyKey,
+        hashlock: Some(HashlockRecovery {
+            hash: sha256::Hash::hash(b"recovery secret"),
+            service_xpub: ExtendedPubKey::from_priv(&secp, &service).to_string(),
+        }),
+        ..Default::default()
+    });
+    assert_versioned_descriptor_matches(&template, OWNER_TPUB, RECOVERY_TPUB, Network::Regtest, TreeVersion::V4);
+}
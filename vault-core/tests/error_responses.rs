@@ -0,0 +1,123 @@
+//! Golden-file snapshot of the FFI error response for every `CoreError`
+//! variant
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite `tests/golden/error_responses.json`
+//! after an intended change to the response.
+
+use std::ffi::CStr;
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+use vault_core::{ffi, free_rust_string, CoreError, MnemonicError};
+
+/// One error per variant, keyed by variant name
+fn samples() -> Vec<(&'static str, CoreError)> {
+    let samples = vec![
+        ("InvalidXpub", CoreError::InvalidXpub("Failed to parse: base58 error".to_string())),
+        ("InvalidAddress", CoreError::InvalidAddress("Failed to parse bc1qnotanaddress".to_string())),
+        (
+            "NetworkMismatch",
+            CoreError::NetworkMismatch {
+                expected: "mainnet".to_string(),
+                actual: "testnet".to_string(),
+            },
+        ),
+        ("InvalidMnemonic", CoreError::InvalidMnemonic(MnemonicError::UnknownWord { word_index: 4 })),
+        ("PsbtError", CoreError::PsbtError("Invalid base64".to_string())),
+        ("DerivationError", CoreError::DerivationError("Hardened index".to_string())),
+        ("MetadataError", CoreError::MetadataError("Invalid hex".to_string())),
+        ("ScriptError", CoreError::ScriptError("Failed to finalize Taproot tree".to_string())),
+        (
+            "InsufficientFunds",
+            CoreError::InsufficientFunds {
+                needed: 12345,
+                available: 678,
+            },
+        ),
+        (
+            "SigningError",
+            CoreError::SigningError {
+                input_index: 1,
+                reason: "Invalid sighash".to_string(),
+            },
+        ),
+        (
+            "TimelockNotExpired",
+            CoreError::TimelockNotExpired {
+                required: 144,
+                current: 12,
+            },
+        ),
+        ("PolicyViolation", CoreError::PolicyViolation("Destination is not approved".to_string())),
+        ("SerializationError", CoreError::SerializationError("expected value".to_string())),
+        ("InvalidInput", CoreError::InvalidInput("null pointer".to_string())),
+        ("InvalidUtf8", CoreError::InvalidUtf8 { offset: 17 }),
+        ("NotInitialized", CoreError::NotInitialized),
+        ("Cancelled", CoreError::Cancelled),
+        ("Internal", CoreError::Internal("panic".to_string())),
+    ];
+
+    // Fails to compile when a variant is added, so it gets a sample above
+    for (_, error) in &samples {
+        match error {
+            CoreError::InvalidXpub(_)
+            | CoreError::InvalidAddress(_)
+            | CoreError::NetworkMismatch { .. }
+            | CoreError::InvalidMnemonic(_)
+            | CoreError::PsbtError(_)
+            | CoreError::DerivationError(_)
+            | CoreError::MetadataError(_)
+            | CoreError::ScriptError(_)
+            | CoreError::InsufficientFunds { .. }
+            | CoreError::SigningError { .. }
+            | CoreError::TimelockNotExpired { .. }
+            | CoreError::PolicyViolation(_)
+            | CoreError::SerializationError(_)
+            | CoreError::InvalidInput(_)
+            | CoreError::InvalidUtf8 { .. }
+            | CoreError::NotInitialized
+            | CoreError::Cancelled
+            | CoreError::Internal(_) => {}
+        }
+    }
+    samples
+}
+
+fn response(error: CoreError) -> Value {
+    let result_ptr = ffi::error_response(error);
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    serde_json::from_str(&result).unwrap()
+}
+
+#[test]
+fn test_error_responses_match_golden() {
+    let actual: Map<String, Value> = samples()
+        .into_iter()
+        .map(|(name, error)| (name.to_string(), response(error)))
+        .collect();
+    let actual = Value::Object(actual);
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/error_responses.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(actual, expected, "responses differ from {}", path.display());
+}
+
+#[test]
+fn test_error_responses_keep_legacy_keys() {
+    for (name, error) in samples() {
+        let code = error.code();
+        let message = error.to_string();
+        let response = response(error);
+
+        assert_eq!(response["error"], true, "{}", name);
+        assert_eq!(response["code"], code, "{}", name);
+        assert_eq!(response["message"], message, "{}", name);
+        assert!(response["details"].is_object(), "{}", name);
+    }
+}
@@ -0,0 +1,114 @@
+//! Golden-file tests pinning the canonical metadata JSON of
+//! `vault_metadata_to_json`, byte for byte including field order
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the files in `tests/golden/`
+//! after an intended change to the JSON form.
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use vault_core::{
+    free_rust_string, vault_metadata_from_json, vault_metadata_to_json, DelayUnit, HeirSet, RecoveryType, VaultMetadata,
+};
+
+use common::payload;
+
+fn call(f: extern "C" fn(*const c_char) -> *mut c_char, arg: &str) -> String {
+    let arg = CString::new(arg).unwrap();
+    let result_ptr = f(arg.as_ptr());
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    result
+}
+
+fn assert_golden(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.json", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, format!("{}\n", actual)).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(actual, expected.trim_end(), "JSON differs from {}", path.display());
+}
+
+/// Convert `metadata` to JSON through the FFI, check it against the
+/// golden file, and check the golden JSON converts back to the same bytes
+fn assert_roundtrip(name: &str, metadata: &VaultMetadata) {
+    let metadata_hex = hex::encode(metadata.to_bytes_as_version().unwrap());
+
+    let response = payload(&call(vault_metadata_to_json, &metadata_hex));
+    let json = response["metadata_json"].as_str().unwrap_or_else(|| panic!("{}", response));
+    assert_golden(name, json);
+
+    let response = payload(&call(vault_metadata_from_json, json));
+    assert_eq!(response["metadata_hex"], metadata_hex.as_str(), "{}", response);
+}
+
+fn savings() -> VaultMetadata {
+    VaultMetadata {
+        version: 1,
+        template_id: "savings_v1".to_string(),
+        delay_blocks: 1008,
+        delay_unit: DelayUnit::Blocks,
+        destination_indices: vec![0, 2],
+        recovery_type: RecoveryType::EmergencyKey,
+        created_at_block: 840_000,
+        vault_index: 7,
+        key_path_enabled: false,
+        heirs: None,
+        whitelist_delay: None,
+        tlv_records: Default::default(),
+    }
+}
+
+fn error(response: &str) -> (i64, String) {
+    let response: Value = serde_json::from_str(response).unwrap();
+    assert_eq!(response["error"], true, "{}", response);
+    (response["code"].as_i64().unwrap(), response["message"].as_str().unwrap().to_string())
+}
+
+#[test]
+fn test_metadata_json_savings_v1() {
+    assert_roundtrip("metadata_json_savings_v1", &savings());
+}
+
+#[test]
+fn test_metadata_json_inheritance_v2() {
+    let mut metadata = savings();
+    metadata.version = 2;
+    metadata.template_id = "inheritance_v1".to_string();
+    metadata.delay_blocks = 4320;
+    metadata.delay_unit = DelayUnit::TimeUnits512s;
+    metadata.recovery_type = RecoveryType::MultiSig;
+    metadata.key_path_enabled = true;
+    metadata.heirs = Some(HeirSet { threshold: 2, count: 3 });
+    metadata.whitelist_delay = Some(144);
+    metadata.set_label("family").unwrap();
+    metadata.set_tlv(0x40, vec![0xde, 0xad]).unwrap();
+    assert_roundtrip("metadata_json_inheritance_v2", &metadata);
+}
+
+#[test]
+fn test_metadata_json_errors() {
+    let mut json: Value = serde_json::to_value(savings()).unwrap();
+    json["owner"] = Value::from("alice");
+    let (code, message) = error(&call(vault_metadata_from_json, &json.to_string()));
+    assert_eq!(code, 4001);
+    assert!(message.contains("unknown field `owner`"), "{}", message);
+
+    let (code, _) = error(&call(vault_metadata_from_json, "{\"version\":1}"));
+    assert_eq!(code, 4001);
+
+    for bytes_hex in ["zz", "", "0100"] {
+        let (code, _) = error(&call(vault_metadata_to_json, bytes_hex));
+        assert_eq!(code, 3002, "{}", bytes_hex);
+    }
+}
@@ -0,0 +1,81 @@
+//! The network selected by `vault_init()`, seen from a fresh process
+//!
+//! Everything runs in one test: the selection is process-wide and can't
+//! be undone, so parallel tests would race on it.
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::sync::Barrier;
+
+use serde_json::Value;
+
+use vault_core::{
+    free_rust_string, vault_create, vault_get_address, vault_get_network, vault_init, vault_last_error_code,
+};
+
+use common::{payload, OWNER_TPUB, RECOVERY_TPUB};
+
+fn read(result_ptr: *mut std::os::raw::c_char) -> Value {
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    payload(&result)
+}
+
+#[test]
+fn test_network_context() {
+    // No "network" in the config
+    let mut vault = serde_json::json!({
+        "template": {"type": "spending"},
+        "owner_xpub": OWNER_TPUB,
+        "recovery_xpub": RECOVERY_TPUB,
+    });
+    let config = CString::new(vault.to_string()).unwrap();
+    vault["vault_index"] = serde_json::json!(0);
+    let create_config = CString::new(vault.to_string()).unwrap();
+
+    assert_eq!(vault_get_network(), -1);
+    assert_eq!(read(vault_create(create_config.as_ptr()))["code"], 4003);
+    assert_eq!(read(vault_get_address(config.as_ptr(), 0, -1))["code"], 4003);
+
+    // Threads race to select different networks; exactly one network wins
+    let barrier = Barrier::new(8);
+    let results: Vec<(i32, i32)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let barrier = &barrier;
+                let network = if i % 2 == 0 { 3 } else { 2 };
+                scope.spawn(move || {
+                    barrier.wait();
+                    let status = vault_init(network);
+                    if status != 0 {
+                        assert_eq!(vault_last_error_code(), 1003);
+                    }
+                    (network, status)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    let winner = vault_get_network();
+    assert!(winner == 2 || winner == 3, "{}", winner);
+    for (network, status) in results {
+        assert_eq!(status == 0, network == winner, "network {}", network);
+    }
+
+    // Calls without a network now use the winner
+    let created = read(vault_create(create_config.as_ptr()));
+    let expected_network = if winner == 3 { "regtest" } else { "signet" };
+    assert_eq!(created["network"], expected_network, "{}", created);
+    let prefix = if winner == 3 { "bcrt1p" } else { "tb1p" };
+    assert!(created["address"].as_str().unwrap().starts_with(prefix));
+
+    let address = read(vault_get_address(config.as_ptr(), 0, -1));
+    assert_eq!(address["address"], created["address"]);
+
+    // An explicit network still takes precedence
+    let explicit = read(vault_get_address(config.as_ptr(), 0, 5 - winner));
+    assert!(explicit.get("error").is_none(), "{}", explicit);
+    assert_ne!(explicit["address"], created["address"]);
+}
@@ -0,0 +1,105 @@
+//! Parallel address derivation and index scans against a single thread
+//!
+//! Runs with `--features parallel`. The same calls are made inside a
+//! one-thread rayon pool, which takes the serial order, and in the
+//! default pool; results must be identical. The timed comparison is
+//! ignored by default:
+//!
+//! `cargo test --release --features parallel --test parallel_scan -- --ignored --nocapture`
+
+#![cfg(feature = "parallel")]
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use bitcoin::ScriptBuf;
+
+use vault_core::taproot::{derive_address_range, AddressInfo, MAX_ADDRESS_RANGE};
+use vault_core::vault::{scan, verify_address};
+use vault_core::{Network, VaultTemplate};
+
+use common::{regtest_config, regtest_vault};
+
+/// Run `f` on a rayon pool of `threads` threads
+fn on_threads<T: Send>(threads: usize, f: impl FnOnce() -> T + Send) -> T {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap()
+        .install(f)
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let started = Instant::now();
+    let result = f();
+    (result, started.elapsed())
+}
+
+fn same_addresses(a: &[AddressInfo], b: &[AddressInfo]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            (a.index, &a.address, &a.script_pubkey) == (b.index, &b.address, &b.script_pubkey)
+        })
+}
+
+#[test]
+fn test_address_range_matches_serial() {
+    let config = regtest_config();
+    let serial = on_threads(1, || derive_address_range(&config, 7, 500).unwrap());
+    let parallel = on_threads(4, || derive_address_range(&config, 7, 500).unwrap());
+    assert!(same_addresses(&serial, &parallel));
+    assert_eq!(parallel.iter().map(|info| info.index).collect::<Vec<_>>(), (7..507).collect::<Vec<_>>());
+    assert!(on_threads(4, || derive_address_range(&config, 3, 0)).unwrap().is_empty());
+}
+
+#[test]
+fn test_discover_indices_matches_serial() {
+    let vault = regtest_vault(VaultTemplate::spending(), 0);
+    let spks: HashSet<ScriptBuf> = [0, 3, 19, 38, 300, 301, 700]
+        .iter()
+        .map(|index| vault.tree_at(*index).unwrap().script_pubkey())
+        .collect();
+    for gap_limit in [1, 20, 300, 1_000] {
+        let serial = on_threads(1, || scan::discover_indices(&vault, &spks, gap_limit).unwrap());
+        let parallel = on_threads(4, || scan::discover_indices(&vault, &spks, gap_limit).unwrap());
+        assert_eq!(serial, parallel, "gap limit {}", gap_limit);
+    }
+    // The scan stops at the gap even when a batch reaches past it
+    let result = on_threads(4, || scan::discover_indices(&vault, &spks, 20).unwrap());
+    assert_eq!(result.used_indices, [0, 3, 19, 38]);
+}
+
+#[test]
+fn test_verify_address_matches_serial() {
+    let vault = regtest_vault(VaultTemplate::spending(), 0);
+    let address = |index| vault.tree_at(index).unwrap().address(Network::Regtest);
+    for (index, max_index) in [(0, 10), (1_234, 2_000), (1_999, 1_999), (2_000, 1_999)] {
+        let address = address(index);
+        let serial = on_threads(1, || verify_address(&vault, &address, max_index).unwrap());
+        let parallel = on_threads(4, || verify_address(&vault, &address, max_index).unwrap());
+        assert_eq!(serial, parallel);
+        assert_eq!(parallel, (index <= max_index).then_some(index));
+    }
+}
+
+#[test]
+#[ignore = "timing benchmark; run with --release"]
+fn bench_parallel_speedup() {
+    let config = regtest_config();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let (serial, serial_time) = timed(|| on_threads(1, || derive_address_range(&config, 0, MAX_ADDRESS_RANGE).unwrap()));
+    let (parallel, parallel_time) = timed(|| on_threads(threads, || derive_address_range(&config, 0, MAX_ADDRESS_RANGE).unwrap()));
+    assert!(same_addresses(&serial, &parallel));
+
+    let speedup = serial_time.as_secs_f64() / parallel_time.as_secs_f64();
+    println!(
+        "{} addresses: 1 thread {:?}, {} threads {:?} ({:.1}x)",
+        MAX_ADDRESS_RANGE, serial_time, threads, parallel_time, speedup
+    );
+    if threads >= 4 {
+        assert!(speedup > 1.5, "expected a speedup on {} threads, got {:.2}x", threads, speedup);
+    }
+}
@@ -0,0 +1,641 @@
+//! Sign vault PSBTs and check the resulting spends against libbitcoinconsensus
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::str::FromStr;
+
+use bitcoin::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+use bitcoin::key::TapTweak;
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::{Address, OutPoint, ScriptBuf, Transaction, TxOut, Txid, Witness};
+use miniscript::psbt::PsbtExt;
+
+use vault_core::keys::{self, SecretMaterial};
+use vault_core::keys::musig::{aggregate_keys, aggregate_partial_sigs, generate_nonce, partial_sign};
+use vault_core::taproot;
+use vault_core::vault::fees::{estimate_vsize, DustPolicy, SpendPath};
+use vault_core::vault::psbt::{
+    apply_signature, attach_preimage, build_partial_unvault, build_recovery, build_deposit, build_stage_spend, build_unvault,
+    bump_fee, finalize, sighashes, sign_key_path, ChangeTarget, ExternalUtxo, SpendOptions, VaultUtxo,
+};
+use vault_core::vault::{proof, VaultBuilder};
+use vault_core::{CoreError, HashlockRecovery, DelayUnit, Network, RecoveryType, VaultMetadata, VaultTemplate};
+
+use common::{custom_template, CustomFields};
+
+const DESTINATION: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+fn account(seed: u8) -> (SecretMaterial, ExtendedPubKey) {
+    let secp = Secp256k1::new();
+    let xpriv = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[seed; 32]).unwrap();
+    (xpriv.into(), ExtendedPubKey::from_priv(&secp, &xpriv))
+}
+
+fn vault_utxo(amount_sats: u64, vault_index: u32) -> VaultUtxo {
+    let (_, owner) = account(1);
+    let (_, recovery) = account(2);
+    let tree =
+        taproot::vault_tree(&VaultTemplate::spending(), &owner, &recovery, vault_index, Network::Regtest).unwrap();
+    let txid = Txid::from_str(&format!("{:064x}", vault_index + 1)).unwrap();
+    VaultUtxo::new(OutPoint::new(txid, 0), amount_sats, tree)
+}
+
+fn destination() -> Address {
+    taproot::parse_address(DESTINATION, Network::Regtest).unwrap()
+}
+
+/// A P2TR destination, matching what `estimate_vsize` assumes for outputs
+fn taproot_destination() -> Address {
+    vault_utxo(0, 99).tree.address(Network::Regtest)
+}
+
+fn metadata(delay_blocks: u32) -> VaultMetadata {
+    VaultMetadata {
+        version: 1,
+        template_id: "spending_v1".to_string(),
+        delay_blocks,
+        delay_unit: DelayUnit::Blocks,
+        destination_indices: vec![],
+        recovery_type: RecoveryType::EmergencyKey,
+        created_at_block: 0,
+        vault_index: 0,
+        key_path_enabled: false,
+        heirs: None,
+        whitelist_delay: None,
+        tlv_records: Default::default(),
+    }
+}
+
+fn verify_spend(psbt: &Psbt, tx: &Transaction) -> Result<(), bitcoinconsensus::Error> {
+    let prevouts: Vec<_> = psbt.inputs.iter().map(|i| i.witness_utxo.clone().unwrap()).collect();
+    let spent_outputs: Vec<bitcoinconsensus::Utxo> = prevouts
+        .iter()
+        .map(|txout| bitcoinconsensus::Utxo {
+            script_pubkey: txout.script_pubkey.as_bytes().as_ptr(),
+            script_pubkey_len: txout.script_pubkey.len() as u32,
+            value: txout.value as i64,
+        })
+        .collect();
+    let tx_bytes = bitcoin::consensus::serialize(tx);
+
+    for (i, prevout) in prevouts.iter().enumerate() {
+        bitcoinconsensus::verify_with_flags(
+            prevout.script_pubkey.as_bytes(),
+            prevout.value,
+            &tx_bytes,
+            Some(&spent_outputs),
+            i,
+            bitcoinconsensus::VERIFY_ALL_PRE_TAPROOT | bitcoinconsensus::VERIFY_TAPROOT,
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_signed_unvault_passes_consensus() {
+    let (owner_xpriv, _) = account(1);
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 4),
+        destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+
+    let signed = keys::sign_psbt(&mut psbt, &owner_xpriv, Network::Regtest).unwrap();
+    assert_eq!(signed, 1);
+
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+}
+
+#[test]
+fn test_unvault_with_short_sequence_fails_consensus() {
+    let (owner_xpriv, _) = account(1);
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 4),
+        destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+    psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::from_height(143);
+
+    keys::sign_psbt(&mut psbt, &owner_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut psbt).unwrap();
+    assert!(verify_spend(&psbt, &tx).is_err());
+}
+
+#[test]
+fn test_signed_recovery_passes_consensus() {
+    let (recovery_xpriv, _) = account(2);
+    let utxos = [vault_utxo(50_000, 0), vault_utxo(20_000, 7)];
+    let mut psbt = build_recovery(&utxos, None, destination(), 3, SpendOptions::default()).unwrap();
+
+    let signed = keys::sign_psbt(&mut psbt, &recovery_xpriv, Network::Regtest).unwrap();
+    assert_eq!(signed, 2);
+
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+}
+
+#[test]
+fn test_signed_absolute_lock_recovery_passes_consensus() {
+    let (recovery_xpriv, recovery) = account(2);
+    let (_, owner) = account(1);
+    let template = custom_template(CustomFields { absolute_lock: Some(1_000_000), ..Default::default() });
+    let tree = taproot::vault_tree(&template, &owner, &recovery, 3, Network::Regtest).unwrap();
+    let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&format!("{:064x}", 42)).unwrap(), 0), 100_000, tree);
+    let recovery_psbt = build_recovery(
+        &[utxo],
+        None,
+        destination(),
+        2,
+        SpendOptions { current_block_height: Some(1_000_000), ..Default::default() },
+    )
+    .unwrap();
+
+    let mut psbt = recovery_psbt.clone();
+    assert_eq!(keys::sign_psbt(&mut psbt, &recovery_xpriv, Network::Regtest).unwrap(), 1);
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    // Signed over an nLockTime below the lock, OP_CLTV fails
+    let mut early = recovery_psbt;
+    early.unsigned_tx.lock_time = bitcoin::absolute::LockTime::from_height(999_999).unwrap();
+    keys::sign_psbt(&mut early, &recovery_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut early).unwrap();
+    assert!(verify_spend(&early, &tx).is_err());
+}
+
+#[test]
+fn test_signed_hashlock_recovery_passes_consensus() {
+    let (service_xpriv, service) = account(5);
+    let (_, owner) = account(1);
+    let (_, recovery) = account(2);
+    let preimage = [0x5a; 32];
+    let template = custom_template(CustomFields {
+        hashlock: Some(HashlockRecovery {
+            hash: bitcoin::hashes::sha256::Hash::hash(&preimage),
+            service_xpub: service.to_string(),
+        }),
+        ..Default::default()
+    });
+    let tree = taproot::vault_tree(&template, &owner, &recovery, 3, Network::Regtest).unwrap();
+    let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&format!("{:064x}", 43)).unwrap(), 0), 100_000, tree);
+    let mut psbt = build_recovery(
+        &[utxo],
+        None,
+        destination(),
+        2,
+        SpendOptions { current_block_height: Some(800_000), ..Default::default() },
+    )
+    .unwrap();
+
+    // The service signs without knowing the secret; the coordinator adds it
+    assert_eq!(keys::sign_psbt(&mut psbt, &service_xpriv, Network::Regtest).unwrap(), 1);
+    assert!(matches!(finalize(&mut psbt.clone()), Err(CoreError::PsbtError(_))));
+    attach_preimage(&mut psbt, 0, &preimage).unwrap();
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    // Any other 32 bytes fail OP_EQUALVERIFY
+    let mut items: Vec<Vec<u8>> = tx.input[0].witness.iter().map(<[u8]>::to_vec).collect();
+    items[1] = vec![0x5b; 32];
+    let mut forged = tx;
+    forged.input[0].witness = Witness::from_slice(&items);
+    assert!(verify_spend(&psbt, &forged).is_err());
+}
+
+#[test]
+fn test_signed_degrading_stage_passes_consensus() {
+    let (owner_xpriv, owner) = account(1);
+    let (_, recovery) = account(2);
+    let (cosigner_xpriv, cosigner) = account(3);
+    let template = VaultTemplate::Degrading {
+        keys: 3,
+        stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+        cosigners: vec![cosigner.to_string()],
+    };
+    let tree = taproot::vault_tree(&template, &owner, &recovery, 3, Network::Regtest).unwrap();
+    let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&format!("{:064x}", 44)).unwrap(), 0), 100_000, tree);
+    let stage_psbt = build_stage_spend(
+        &[utxo],
+        1,
+        destination(),
+        2,
+        SpendOptions { current_block_height: Some(800_000), ..Default::default() },
+    )
+    .unwrap();
+    assert_eq!(stage_psbt.unsigned_tx.input[0].sequence, bitcoin::Sequence::from_height(4032));
+
+    // Owner and cosigner: 2 of 3, the recovery key's slot left empty
+    let mut psbt = stage_psbt.clone();
+    assert_eq!(keys::sign_psbt(&mut psbt, &owner_xpriv, Network::Regtest).unwrap(), 1);
+    assert_eq!(keys::sign_psbt(&mut psbt, &cosigner_xpriv, Network::Regtest).unwrap(), 1);
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    // Signed before the stage's delay, OP_CSV fails
+    let mut early = stage_psbt;
+    early.unsigned_tx.input[0].sequence = bitcoin::Sequence::from_height(4031);
+    keys::sign_psbt(&mut early, &owner_xpriv, Network::Regtest).unwrap();
+    keys::sign_psbt(&mut early, &cosigner_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut early).unwrap();
+    assert!(verify_spend(&early, &tx).is_err());
+}
+
+#[test]
+fn test_every_signer_pair_passes_consensus() {
+    let (owner_xpriv, owner) = account(1);
+    let (recovery_xpriv, recovery) = account(2);
+    let (cosigner_xpriv, cosigner) = account(3);
+    let template = VaultTemplate::Degrading {
+        keys: 3,
+        stages: vec![(0, 3), (4032, 2), (26_208, 1)],
+        cosigners: vec![cosigner.to_string()],
+    };
+    let tree = taproot::vault_tree(&template, &owner, &recovery, 3, Network::Regtest).unwrap();
+    let utxo = VaultUtxo::new(OutPoint::new(Txid::from_str(&format!("{:064x}", 45)).unwrap(), 0), 100_000, tree);
+    let stage_psbt = build_stage_spend(&[utxo], 1, destination(), 2, SpendOptions::default()).unwrap();
+
+    // 2 of 3 through stage 1, each pair leaving a different slot empty
+    let xprivs = [&owner_xpriv, &recovery_xpriv, &cosigner_xpriv];
+    for (a, b) in [(0, 1), (0, 2), (1, 2)] {
+        let mut psbt = stage_psbt.clone();
+        keys::sign_psbt(&mut psbt, xprivs[a], Network::Regtest).unwrap();
+        keys::sign_psbt(&mut psbt, xprivs[b], Network::Regtest).unwrap();
+        let tx = finalize(&mut psbt).unwrap();
+        let empty = tx.input[0].witness.iter().take(3).filter(|element| element.is_empty()).count();
+        assert_eq!(empty, 1, "signers {} and {}", a, b);
+        verify_spend(&psbt, &tx).unwrap_or_else(|e| panic!("signers {} and {}: {:?}", a, b, e));
+    }
+}
+
+#[test]
+fn test_sign_with_unrelated_key() {
+    let (stranger, _) = account(9);
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 0),
+        destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+
+    let err = keys::sign_psbt(&mut psbt, &stranger, Network::Regtest).unwrap_err();
+    assert!(matches!(err, CoreError::SigningError { input_index: 0, .. }));
+    assert_eq!(err.code(), 2004);
+
+    // The recovery key has no place in the timelock leaf either
+    let (recovery_xpriv, _) = account(2);
+    assert!(keys::sign_psbt(&mut psbt, &recovery_xpriv, Network::Regtest).is_err());
+    assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+}
+
+#[test]
+fn test_sign_rejects_wrong_network_key() {
+    let (owner_xpriv, _) = account(1);
+    let mut mainnet_xpriv = owner_xpriv.xpriv().unwrap();
+    mainnet_xpriv.network = bitcoin::Network::Bitcoin;
+    let owner_xpriv = SecretMaterial::from(mainnet_xpriv);
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 0),
+        destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+
+    let err = keys::sign_psbt(&mut psbt, &owner_xpriv, Network::Regtest).unwrap_err();
+    assert!(matches!(err, CoreError::NetworkMismatch { .. }));
+}
+
+#[test]
+fn test_estimated_vsize_matches_signed_unvault() {
+    let (owner_xpriv, _) = account(1);
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 2),
+        taproot_destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+    keys::sign_psbt(&mut psbt, &owner_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    let estimate = estimate_vsize(1, SpendPath::TimelockLeaf, 1);
+    assert!(estimate.abs_diff(tx.vsize() as u64) <= 2, "estimated {}, signed {}", estimate, tx.vsize());
+}
+
+#[test]
+fn test_estimated_vsize_matches_signed_recovery() {
+    let (recovery_xpriv, _) = account(2);
+    let utxos = [vault_utxo(50_000, 0), vault_utxo(20_000, 7), vault_utxo(30_000, 8)];
+    let mut psbt = build_recovery(&utxos, None, taproot_destination(), 3, SpendOptions::default()).unwrap();
+    keys::sign_psbt(&mut psbt, &recovery_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    let estimate = estimate_vsize(3, SpendPath::EmergencyLeaf, 1);
+    assert!(estimate.abs_diff(tx.vsize() as u64) <= 2, "estimated {}, signed {}", estimate, tx.vsize());
+}
+
+#[test]
+fn test_bumped_unvault_resigned_passes_consensus() {
+    let (owner_xpriv, _) = account(1);
+    let change = ChangeTarget::new(4, vault_utxo(0, 4).tree);
+    let mut original =
+        build_partial_unvault(
+            vault_utxo(100_000, 3),
+            destination(),
+            30_000,
+            &change,
+            2,
+            &metadata(144),
+            SpendOptions::default(),
+        )
+            .unwrap()
+            .psbt;
+    keys::sign_psbt(&mut original, &owner_xpriv, Network::Regtest).unwrap();
+
+    let mut bumped = bump_fee(&original, 25, DustPolicy::Relay).unwrap();
+    assert!(bumped.inputs[0].tap_script_sigs.is_empty());
+    // The fee came out of the change at the fresh index
+    assert_eq!(bumped.unsigned_tx.output[0].value, 30_000);
+    assert_eq!(bumped.unsigned_tx.output[1].script_pubkey, change.tree.script_pubkey());
+    assert!(finalize(&mut bumped.clone()).is_err());
+
+    keys::sign_psbt(&mut bumped, &owner_xpriv, Network::Regtest).unwrap();
+    let tx = finalize(&mut bumped).unwrap();
+    verify_spend(&bumped, &tx).unwrap();
+
+    let fee = 100_000 - tx.output.iter().map(|o| o.value).sum::<u64>();
+    assert!(fee >= 25 * tx.vsize() as u64);
+}
+
+/// UTXO of a custom vault whose owner key is also the internal key
+fn key_path_utxo(amount_sats: u64, vault_index: u32) -> VaultUtxo {
+    let (_, owner) = account(1);
+    let (_, recovery) = account(2);
+    let template = custom_template(CustomFields {
+        recovery_type: RecoveryType::EmergencyKey,
+        key_path_enabled: true,
+        ..Default::default()
+    });
+    let tree = taproot::vault_tree(&template, &owner, &recovery, vault_index, Network::Regtest).unwrap();
+    let txid = Txid::from_str(&format!("{:064x}", vault_index + 100)).unwrap();
+    VaultUtxo::new(OutPoint::new(txid, 0), amount_sats, tree)
+}
+
+#[test]
+fn test_key_path_spend_passes_consensus() {
+    let (owner_xpriv, _) = account(1);
+    let utxos = [key_path_utxo(50_000, 0), key_path_utxo(20_000, 5)];
+    let mut psbt = build_recovery(&utxos, None, destination(), 2, SpendOptions::default()).unwrap();
+
+    assert_eq!(sign_key_path(&mut psbt, &owner_xpriv).unwrap(), 2);
+    let tx = finalize(&mut psbt).unwrap();
+    // Key-path witnesses are the signature alone
+    assert!(tx.input.iter().all(|input| input.witness.len() == 1));
+    verify_spend(&psbt, &tx).unwrap();
+
+    // The recovery key is in a leaf, not the internal key
+    let (recovery_xpriv, _) = account(2);
+    let mut psbt = build_recovery(&utxos, None, destination(), 2, SpendOptions::default()).unwrap();
+    assert!(matches!(
+        sign_key_path(&mut psbt, &recovery_xpriv),
+        Err(CoreError::SigningError { input_index: 0, .. })
+    ));
+}
+
+/// Sign each digest from `sighashes()` as an HSM would, with the key at
+/// the signing key's origin, tweaked for key-path entries
+fn sign_externally(psbt: &mut Psbt, xpriv: &SecretMaterial, spend_path: SpendPath) {
+    let secp = Secp256k1::new();
+    for info in sighashes(psbt, spend_path).unwrap() {
+        let input = &psbt.inputs[info.input_index];
+        let keypair = match info.leaf_hash {
+            None => {
+                let internal_key = input.tap_internal_key.unwrap();
+                let (_, (_, path)) = &input.tap_key_origins[&internal_key];
+                keys::tweaked_keypair(xpriv, path, input.tap_merkle_root).unwrap().keypair()
+            }
+            Some(_) => {
+                let (_, (_, path)) = &input.tap_key_origins[&info.pubkey];
+                let child = xpriv.xpriv().unwrap().derive_priv(&secp, path).unwrap();
+                child.private_key.keypair(&secp)
+            }
+        };
+        assert_eq!(keypair.x_only_public_key().0, info.pubkey);
+
+        let digest: [u8; 32] = hex::decode(&info.message).unwrap().try_into().unwrap();
+        let sig = secp.sign_schnorr(&Message::from_slice(&digest).unwrap(), &keypair);
+        let signature = bitcoin::taproot::Signature { sig, hash_ty: TapSighashType::Default };
+        apply_signature(psbt, info.input_index, info.pubkey, signature).unwrap();
+    }
+}
+
+#[test]
+fn test_external_signatures_pass_consensus() {
+    let (owner_xpriv, _) = account(1);
+
+    let utxos = [key_path_utxo(50_000, 0), key_path_utxo(20_000, 5)];
+    let mut psbt = build_recovery(&utxos, None, destination(), 2, SpendOptions::default()).unwrap();
+    sign_externally(&mut psbt, &owner_xpriv, SpendPath::KeyPath);
+    assert!(psbt.inputs.iter().all(|input| input.tap_key_sig.is_some()));
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+
+    let mut psbt = build_unvault(
+        vault_utxo(100_000, 6),
+        destination(),
+        2,
+        &metadata(144),
+        SpendOptions::default(),
+    )
+    .unwrap();
+    sign_externally(&mut psbt, &owner_xpriv, SpendPath::TimelockLeaf);
+    assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+    let tx = finalize(&mut psbt).unwrap();
+    verify_spend(&psbt, &tx).unwrap();
+}
+
+#[test]
+fn test_key_path_sign_refuses_nums_internal_key() {
+    let (owner_xpriv, _) = account(1);
+    let mut psbt = build_recovery(
+        &[vault_utxo(50_000, 3)],
+        None,
+        destination(),
+        2,
+        SpendOptions::default(),
+    )
+    .unwrap();
+
+    match sign_key_path(&mut psbt, &owner_xpriv).unwrap_err() {
+        CoreError::SigningError { input_index: 0, reason } => assert!(reason.contains("NUMS"), "{}", reason),
+        other => panic!("expected SigningError, got {:?}", other),
+    }
+    assert!(psbt.inputs[0].tap_key_sig.is_none());
+}
+
+#[test]
+fn test_musig_internal_key_spend_passes_consensus() {
+    let secp = Secp256k1::new();
+    let (_, owner) = account(1);
+    let (_, recovery) = account(2);
+    let alice = SecretKey::from_slice(&[0x11; 32]).unwrap();
+    let bob = SecretKey::from_slice(&[0x22; 32]).unwrap();
+    let agg_key = aggregate_keys(&[alice.public_key(&secp), bob.public_key(&secp)]).unwrap();
+
+    let vault = VaultBuilder::new()
+        .template(VaultTemplate::spending())
+        .owner_xpub(owner.to_string())
+        .recovery_xpub(recovery.to_string())
+        .network(Network::Regtest)
+        .internal_key(agg_key.clone())
+        .build()
+        .unwrap();
+    assert_eq!(vault.tree().internal_key(), agg_key.x_only_public_key());
+
+    let txid = Txid::from_str(&format!("{:064x}", 500)).unwrap();
+    let mut psbt = build_recovery(
+        &[vault.utxo(OutPoint::new(txid, 0), 50_000)],
+        None,
+        destination(),
+        2,
+        SpendOptions::default(),
+    )
+    .unwrap();
+    let prevouts: Vec<TxOut> = psbt.inputs.iter().map(|i| i.witness_utxo.clone().unwrap()).collect();
+    let sighash = SighashCache::new(&psbt.unsigned_tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), TapSighashType::Default)
+        .unwrap();
+    let msg = sighash.to_byte_array();
+
+    // Both signers sign for the output key, so the aggregate takes the tap tweak
+    let tweaked = agg_key.with_taproot_tweak(vault.tree().merkle_root()).unwrap();
+    assert_eq!(tweaked.x_only_public_key(), vault.tree().output_key().to_inner());
+
+    let (alice_secnonce, alice_pubnonce) = generate_nonce(&alice, &tweaked, &msg).unwrap();
+    let (bob_secnonce, bob_pubnonce) = generate_nonce(&bob, &tweaked, &msg).unwrap();
+    let pub_nonces = [alice_pubnonce, bob_pubnonce];
+    let partial_sigs = [
+        partial_sign(alice_secnonce, &alice, &tweaked, &pub_nonces, &msg).unwrap(),
+        partial_sign(bob_secnonce, &bob, &tweaked, &pub_nonces, &msg).unwrap(),
+    ];
+    let sig = aggregate_partial_sigs(&tweaked, &pub_nonces, &msg, &partial_sigs).unwrap();
+
+    psbt.inputs[0].tap_key_sig = Some(bitcoin::taproot::Signature { sig, hash_ty: TapSighashType::Default });
+    let tx = finalize(&mut psbt).unwrap();
+    assert_eq!(tx.input[0].witness.len(), 1);
+    verify_spend(&psbt, &tx).unwrap();
+}
+
+#[test]
+fn test_bip322_proofs_pass_consensus() {
+    use base64::Engine;
+
+    let (owner_xpriv, owner) = account(1);
+    let (recovery_xpriv, recovery) = account(2);
+    let vault = VaultBuilder::new()
+        .template(custom_template(CustomFields {
+            recovery_type: RecoveryType::EmergencyKey,
+            key_path_enabled: true,
+            ..Default::default()
+        }))
+        .owner_xpub(owner.to_string())
+        .recovery_xpub(recovery.to_string())
+        .network(Network::Regtest)
+        .build()
+        .unwrap();
+
+    // Key path with the owner key, emergency leaf with the recovery key
+    for xpriv in [owner_xpriv, recovery_xpriv] {
+        let proof = proof::sign_message(&vault, 7, &xpriv, "proof of reserves").unwrap();
+        let witness = bitcoin::consensus::deserialize(
+            &base64::engine::general_purpose::STANDARD.decode(&proof).unwrap(),
+        )
+        .unwrap();
+        let to_spend = proof::to_spend(&vault.tree_at(7).unwrap().script_pubkey(), "proof of reserves");
+        let to_sign = proof::to_sign(&to_spend, witness);
+
+        let mut psbt = Psbt::from_unsigned_tx(proof::to_sign(&to_spend, Default::default())).unwrap();
+        psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+        verify_spend(&psbt, &to_sign).unwrap();
+    }
+}
+
+/// Sign a deposit as the funding wallet would, from the PSBT's key
+/// origins alone, and finalize it with rust-miniscript
+///
+/// `Psbt::sign` signs the P2WPKH input from its `bip32_derivation`.
+/// rust-bitcoin 0.30 skips taproot inputs there, so the P2TR input's key
+/// is found from `tap_key_origins` and signed over rust-miniscript's
+/// sighash. No round-trip through Bitcoin Core's wallet runs here.
+fn sign_as_wallet(psbt: &mut Psbt, xpriv: &ExtendedPrivKey) {
+    let secp = Secp256k1::new();
+    let signed = psbt.sign(xpriv, &secp).unwrap();
+    assert_eq!(signed.values().map(Vec::len).sum::<usize>(), 1, "one ECDSA input");
+
+    let tx = psbt.unsigned_tx.clone();
+    let mut cache = SighashCache::new(&tx);
+    for i in 0..psbt.inputs.len() {
+        let Some(internal_key) = psbt.inputs[i].tap_internal_key else { continue };
+        let (_, (fingerprint, path)) = &psbt.inputs[i].tap_key_origins[&internal_key];
+        assert_eq!(*fingerprint, xpriv.fingerprint(&secp));
+        let keypair = xpriv.derive_priv(&secp, path).unwrap().private_key.keypair(&secp);
+        let tweaked = keypair.tap_tweak(&secp, psbt.inputs[i].tap_merkle_root).to_inner();
+        let message = psbt.sighash_msg(i, &mut cache, None).unwrap().to_secp_msg();
+        psbt.inputs[i].tap_key_sig = Some(bitcoin::taproot::Signature {
+            sig: secp.sign_schnorr(&message, &tweaked),
+            hash_ty: TapSighashType::Default,
+        });
+    }
+    psbt.finalize_mut(&secp).unwrap();
+}
+
+#[test]
+fn test_wallet_signed_deposit_passes_consensus() {
+    let secp = Secp256k1::new();
+    let wallet = ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[3; 32]).unwrap();
+    let fingerprint = wallet.fingerprint(&secp);
+    let wallet_utxo = |path: &str, vout, amount_sats, taproot| {
+        let path = DerivationPath::from_str(path).unwrap();
+        let pubkey = wallet.derive_priv(&secp, &path).unwrap().private_key.public_key(&secp);
+        let script_pubkey = if taproot {
+            ScriptBuf::new_v1_p2tr(&secp, pubkey.x_only_public_key().0, None)
+        } else {
+            ScriptBuf::new_v0_p2wpkh(&bitcoin::PublicKey::new(pubkey).wpubkey_hash().unwrap())
+        };
+        ExternalUtxo {
+            outpoint: OutPoint::new(Txid::from_str(&"ee".repeat(32)).unwrap(), vout),
+            amount_sats,
+            script_pubkey,
+            key_origin: Some((pubkey, (fingerprint, path))),
+        }
+    };
+    let inputs = [wallet_utxo("m/84h/1h/0h/0/0", 0, 70_000, false), wallet_utxo("m/86h/1h/0h/0/0", 1, 40_000, true)];
+    let vault_address = vault_utxo(0, 0).tree.address(Network::Regtest);
+
+    let mut psbt = build_deposit(&inputs, vault_address.clone(), 100_000, destination(), 2).unwrap();
+    sign_as_wallet(&mut psbt, &wallet);
+    let tx = psbt.clone().extract_tx();
+    verify_spend(&psbt, &tx).unwrap();
+
+    assert_eq!(tx.output[0].script_pubkey, vault_address.script_pubkey());
+    let fee = 110_000 - tx.output.iter().map(|o| o.value).sum::<u64>();
+    assert!(fee >= 2 * tx.vsize() as u64);
+    assert!(fee <= 2 * (tx.vsize() as u64 + 1));
+}
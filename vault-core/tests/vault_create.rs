@@ -0,0 +1,208 @@
+//! Golden-file tests pinning the `vault_create` response shape
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the files in `tests/golden/`
+//! after an intended change to the response.
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use vault_core::{free_rust_string, vault_create, vault_init, vault_restore};
+
+use common::{payload, OWNER_TPUB, OWNER_XPUB, RECOVERY_TPUB, RECOVERY_XPUB};
+
+fn create(config: &str) -> Value {
+    let config = CString::new(config).unwrap();
+    let result_ptr = vault_create(config.as_ptr());
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    payload(&result)
+}
+
+fn restore(descriptor: &str, metadata_hex: &str) -> Value {
+    let descriptor = CString::new(descriptor).unwrap();
+    let metadata_hex = CString::new(metadata_hex).unwrap();
+    let result_ptr = vault_restore(descriptor.as_ptr(), metadata_hex.as_ptr());
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    payload(&result)
+}
+
+fn assert_golden(name: &str, actual: &Value) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.json", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(actual).unwrap() + "\n").unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(actual, &expected, "response differs from {}", path.display());
+}
+
+fn config(network: &str, template: Value, owner: &str, recovery: &str, vault_index: u32) -> String {
+    serde_json::json!({
+        "network": network,
+        "template": template,
+        "owner_xpub": owner,
+        "recovery_xpub": recovery,
+        "vault_index": vault_index,
+        "tree_version": 3,
+    })
+    .to_string()
+}
+
+fn error_code(response: &Value) -> i64 {
+    assert_eq!(response["error"], true, "{}", response);
+    response["code"].as_i64().unwrap()
+}
+
+#[test]
+fn test_vault_create_savings_mainnet() {
+    let response = create(&config(
+        "mainnet",
+        serde_json::json!({"type": "savings"}),
+        OWNER_XPUB,
+        RECOVERY_XPUB,
+        0,
+    ));
+    assert_golden("vault_create_savings_mainnet", &response);
+}
+
+#[test]
+fn test_vault_create_spending_regtest() {
+    let response = create(&config(
+        "regtest",
+        serde_json::json!({"type": "spending", "delay_blocks": 288}),
+        OWNER_TPUB,
+        RECOVERY_TPUB,
+        5,
+    ));
+    assert_golden("vault_create_spending_regtest", &response);
+}
+
+#[test]
+fn test_vault_create_custom_timelock_only_signet() {
+    let response = create(&config(
+        "signet",
+        serde_json::json!({"type": "custom", "delay_blocks": 52560, "recovery_type": "timelock_only"}),
+        OWNER_TPUB,
+        RECOVERY_TPUB,
+        1,
+    ));
+    assert_golden("vault_create_custom_timelock_only_signet", &response);
+}
+
+#[test]
+fn test_vault_create_default_tree_version() {
+    let mut request: Value =
+        serde_json::from_str(&config("mainnet", serde_json::json!({"type": "savings"}), OWNER_XPUB, RECOVERY_XPUB, 0))
+            .unwrap();
+    request.as_object_mut().unwrap().remove("tree_version");
+    let response = create(&request.to_string());
+
+    // New vaults take the latest layout, which has a descriptor
+    assert!(response["descriptor"].is_string(), "{}", response);
+    assert_golden("vault_create_default_mainnet", &response);
+}
+
+#[test]
+fn test_vault_create_tree_version_1() {
+    let mut request: Value =
+        serde_json::from_str(&config("mainnet", serde_json::json!({"type": "savings"}), OWNER_XPUB, RECOVERY_XPUB, 0))
+            .unwrap();
+    request["tree_version"] = Value::from(1);
+    let response = create(&request.to_string());
+
+    // Only made on request: its OP_DROP timelock leaf has no descriptor
+    assert_eq!(response["descriptor"], Value::Null, "{}", response);
+    assert_eq!(response["address"], "bc1pxss4uus4xg2slncafuja8efxa9z7n3shypgmsj5k2nw6evaaqr3qjp936n");
+    assert_eq!(response["metadata_hex"], "010a736176696e67735f7631f003000000000000000000000000");
+
+    request["tree_version"] = Value::from(5);
+    assert_eq!(error_code(&create(&request.to_string())), 4001);
+}
+
+#[test]
+fn test_vault_create_errors() {
+    assert_eq!(error_code(&create("{not json")), 4001);
+    assert_eq!(error_code(&create(r#"{"network":"mainnet"}"#)), 4001);
+
+    let bad_key = config("mainnet", serde_json::json!({"type": "savings"}), "xpubnotakey", RECOVERY_XPUB, 0);
+    assert_eq!(error_code(&create(&bad_key)), 1001);
+
+    let mismatch = config("mainnet", serde_json::json!({"type": "savings"}), OWNER_TPUB, RECOVERY_TPUB, 0);
+    assert_eq!(error_code(&create(&mismatch)), 1003);
+
+    let duplicate = config("mainnet", serde_json::json!({"type": "savings"}), OWNER_XPUB, OWNER_XPUB, 0);
+    assert_eq!(error_code(&create(&duplicate)), 2003);
+
+    let hardened = config("mainnet", serde_json::json!({"type": "savings"}), OWNER_XPUB, RECOVERY_XPUB, 1 << 31);
+    assert_eq!(error_code(&create(&hardened)), 4002);
+}
+
+#[test]
+fn test_vault_create_used_indices() {
+    let with_ledger = |vault_index: Option<u32>, used: Value| {
+        let mut config: Value =
+            serde_json::from_str(&config("regtest", serde_json::json!({"type": "savings"}), OWNER_TPUB, RECOVERY_TPUB, 0))
+                .unwrap();
+        match vault_index {
+            Some(index) => config["vault_index"] = Value::from(index),
+            None => drop(config.as_object_mut().unwrap().remove("vault_index")),
+        }
+        config["used_indices"] = used;
+        create(&config.to_string())
+    };
+
+    let assigned = with_ledger(None, serde_json::json!([0, 1, 4]));
+    assert_eq!(assigned["vault_index"], 2, "{}", assigned);
+    assert_eq!(assigned["used_indices"], serde_json::json!([0, 1, 2, 4]));
+
+    let explicit = with_ledger(Some(7), serde_json::json!([]));
+    assert_eq!(explicit["vault_index"], 7, "{}", explicit);
+    assert_eq!(explicit["used_indices"], serde_json::json!([7]));
+
+    let reused = with_ledger(Some(4), serde_json::json!([0, 1, 4]));
+    assert_eq!(error_code(&reused), 2003);
+    assert!(reused["message"].as_str().unwrap().contains("Vault index 4 already used"), "{}", reused);
+
+    // Without a ledger the response keeps its old shape
+    let plain = create(&config("regtest", serde_json::json!({"type": "savings"}), OWNER_TPUB, RECOVERY_TPUB, 2));
+    assert!(plain.get("used_indices").is_none());
+    assert_eq!(plain["address"], assigned["address"]);
+}
+
+#[test]
+fn test_vault_restore_from_create_output() {
+    assert_eq!(vault_init(3), 0);
+    let spending = |delay_blocks: u32| {
+        create(&config(
+            "regtest",
+            serde_json::json!({"type": "spending", "delay_blocks": delay_blocks}),
+            OWNER_TPUB,
+            RECOVERY_TPUB,
+            5,
+        ))
+    };
+    let created = spending(288);
+
+    let restored = restore(
+        created["descriptor"].as_str().unwrap(),
+        created["metadata_hex"].as_str().unwrap(),
+    );
+    assert_eq!(restored["address"], created["address"], "{}", restored);
+    assert_eq!(restored["vault_index"], 5);
+    assert_eq!(restored["template"], serde_json::json!({"type": "spending", "delay_blocks": 288}));
+
+    // Metadata of a vault with another delay contradicts the descriptor
+    let tampered = restore(
+        created["descriptor"].as_str().unwrap(),
+        spending(144)["metadata_hex"].as_str().unwrap(),
+    );
+    assert_eq!(error_code(&tampered), 3002);
+}
@@ -0,0 +1,248 @@
+//! Golden-file tests pinning the Sparrow, Ledger and Coldcard wallet exports
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the files in `tests/golden/`
+//! after an intended change to an export. A changed Ledger policy
+//! invalidates every registration made with the old one, so its fixture
+//! is written by hand and never rewritten.
+
+#[path = "../src/test_common.rs"]
+mod common;
+
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use serde_json::Value;
+
+use vault_core::taproot::TreeVersion;
+use vault_core::vault::VaultBuilder;
+use vault_core::{free_rust_string, vault_export_wallet, Network, VaultTemplate};
+
+use common::{OWNER_XPUB, RECOVERY_XPUB, THIRD_XPUB};
+
+const COSIGNER_XPUBS: [&str; 2] = [
+    THIRD_XPUB,
+    "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+];
+
+fn export(config: &Value, format: i32) -> String {
+    let config = CString::new(config.to_string()).unwrap();
+    let result_ptr = vault_export_wallet(config.as_ptr(), format);
+    let result = unsafe { CStr::from_ptr(result_ptr) }.to_str().unwrap().to_string();
+    free_rust_string(result_ptr);
+    result
+}
+
+fn savings_config() -> Value {
+    serde_json::json!({
+        "network": "mainnet",
+        "template": {"type": "savings"},
+        "owner_xpub": OWNER_XPUB,
+        "recovery_xpub": RECOVERY_XPUB,
+        "tree_version": 3,
+    })
+}
+
+#[test]
+fn test_sparrow_export_matches_fixture() {
+    let actual: Value = serde_json::from_str(&export(&savings_config(), 0)).unwrap();
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/wallet_export_sparrow_mainnet.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return;
+    }
+    let expected: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(actual, expected, "export differs from {}", path.display());
+}
+
+#[test]
+fn test_sparrow_export_addresses_match_vault() {
+    let wallet: Value = serde_json::from_str(&export(&savings_config(), 0)).unwrap();
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(wallet["descriptor"].as_str().unwrap()).unwrap();
+
+    for index in 0..5 {
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_XPUB)
+            .recovery_xpub(RECOVERY_XPUB)
+            .network(Network::Mainnet)
+            .index(index)
+            .tree_version(TreeVersion::V3)
+            .build()
+            .unwrap();
+        let from_descriptor = descriptor
+            .at_derivation_index(index)
+            .unwrap()
+            .address(bitcoin::Network::Bitcoin)
+            .unwrap();
+        assert_eq!(from_descriptor, vault.address(), "index {}", index);
+    }
+
+    // Every keystore's fingerprint appears as a key origin
+    for keystore in wallet["keystores"].as_array().unwrap() {
+        let origin = format!("[{}]{}", keystore["fingerprint"].as_str().unwrap(), keystore["xpub"].as_str().unwrap());
+        assert!(wallet["descriptor"].as_str().unwrap().contains(&origin), "{}", origin);
+    }
+}
+
+/// The hand-written Ledger policy fixture, without its `_provenance` note
+fn ledger_policy_fixture() -> Value {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/ledger_policy_savings_mainnet.json");
+    let mut fixture: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert!(fixture.as_object_mut().unwrap().remove("_provenance").is_some());
+    fixture
+}
+
+#[test]
+fn test_ledger_policy_matches_fixture() {
+    let exported = export(&savings_config(), 1);
+    assert_eq!(exported, export(&savings_config(), 1), "policy must be byte-identical across exports");
+    let actual: Value = serde_json::from_str(&exported).unwrap();
+    assert_eq!(actual, ledger_policy_fixture());
+}
+
+/// Rebuilds the Ledger fixture from the BIPs alone, without vault-core
+#[test]
+fn test_ledger_policy_fixture_is_independent() {
+    use bitcoin::bip32::{ChainCode, ChildNumber, ExtendedPubKey, Fingerprint};
+    use bitcoin::hashes::{sha256, Hash};
+
+    // BIP341's suggested NUMS point H, even y
+    let h = "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+    let public_key = bitcoin::secp256k1::PublicKey::from_str(h).unwrap();
+    let nums = ExtendedPubKey {
+        network: bitcoin::Network::Bitcoin,
+        depth: 0,
+        parent_fingerprint: Fingerprint::default(),
+        child_number: ChildNumber::Normal { index: 0 },
+        public_key,
+        chain_code: ChainCode::from(sha256::Hash::hash(&public_key.serialize()[1..]).to_byte_array()),
+    };
+
+    // Master key IDs listed in BIP32's test vectors 1 and 2
+    let expected = serde_json::json!({
+        "descriptor_template": "tr(@0/**,{and_v(v:older(1008),pk(@1/**)),pk(@2/**)})",
+        "keys": [
+            nums.to_string(),
+            format!("[3442193e]{}", OWNER_XPUB),
+            format!("[bd16bee5]{}", RECOVERY_XPUB),
+        ],
+        "name": "Savings vault",
+    });
+    assert_eq!(ledger_policy_fixture(), expected);
+}
+
+#[test]
+fn test_ledger_policy_addresses_match_vault() {
+    let policy: Value = serde_json::from_str(&export(&savings_config(), 1)).unwrap();
+
+    // Receive addresses of the policy: `@i/**` is key i's `/0/*` branch
+    let mut receive = policy["descriptor_template"].as_str().unwrap().to_string();
+    for (i, key) in policy["keys"].as_array().unwrap().iter().enumerate().rev() {
+        receive = receive.replace(&format!("@{}/**", i), &format!("{}/0/*", key.as_str().unwrap()));
+    }
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&receive).unwrap();
+
+    for index in 0..5 {
+        let vault = VaultBuilder::new()
+            .template(VaultTemplate::savings())
+            .owner_xpub(OWNER_XPUB)
+            .recovery_xpub(RECOVERY_XPUB)
+            .network(Network::Mainnet)
+            .index(index)
+            .tree_version(TreeVersion::V3)
+            .build()
+            .unwrap();
+        let from_policy = descriptor
+            .at_derivation_index(index)
+            .unwrap()
+            .address(bitcoin::Network::Bitcoin)
+            .unwrap();
+        assert_eq!(from_policy, vault.address(), "index {}", index);
+    }
+}
+
+/// Compare `actual` with a text file in `tests/golden/`, or rewrite it
+/// under `UPDATE_GOLDEN`
+fn assert_golden_text(actual: &str, name: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    assert_eq!(actual, std::fs::read_to_string(&path).unwrap(), "export differs from {}", path.display());
+}
+
+fn coldcard_multisig_config() -> Value {
+    serde_json::json!({
+        "network": "mainnet",
+        "template": {
+            "type": "custom",
+            "delay_blocks": 4320,
+            "recovery_type": "multi_sig",
+            // The second cosigner is BIP32 test vector 1's m/0'/1/2'
+            "multisig": {"threshold": 2, "cosigners": [COSIGNER_XPUBS[0], format!("[3442193e/0'/1/2']{}", COSIGNER_XPUBS[1])]},
+        },
+        "owner_xpub": OWNER_XPUB,
+        "recovery_xpub": RECOVERY_XPUB,
+        "tree_version": 3,
+    })
+}
+
+/// `descriptor` at `index`, with its `sortedmulti_a()` fragment, which
+/// rust-miniscript 10 can't parse, written as the `multi_a()` of the
+/// derived keys in sorted order
+fn at_index_without_sortedmulti(descriptor: &str, index: u32) -> Descriptor<DescriptorPublicKey> {
+    let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+    let descriptor = descriptor.split_once('#').unwrap().0;
+    let (head, rest) = descriptor.split_once("sortedmulti_a(").unwrap();
+    let (args, tail) = rest.split_once(')').unwrap();
+    let mut args = args.split(',');
+    let threshold = args.next().unwrap();
+    let mut keys: Vec<String> = args
+        .map(|key| {
+            let key = DescriptorPublicKey::from_str(key).unwrap().at_derivation_index(index).unwrap();
+            key.derive_public_key(&secp).unwrap().inner.x_only_public_key().0.to_string()
+        })
+        .collect();
+    keys.sort();
+    let rewritten = format!("{}multi_a({},{}){}", head, threshold, keys.join(","), tail);
+    Descriptor::<DescriptorPublicKey>::from_str(&rewritten).unwrap()
+}
+
+/// The fixture is the vault's `tr()` descriptor for the Coldcard's
+/// descriptor import. No device import runs here: the addresses are
+/// checked with rust-miniscript's parser instead.
+#[test]
+fn test_coldcard_multisig_export_matches_fixture() {
+    let file = export(&coldcard_multisig_config(), 2);
+
+    let config: vault_core::vault::VaultConfig = serde_json::from_value(coldcard_multisig_config()).unwrap();
+    for index in 0..5 {
+        let vault = VaultBuilder::from_config(&config).index(index).build().unwrap();
+        let from_descriptor = at_index_without_sortedmulti(file.trim_end(), index)
+            .at_derivation_index(index)
+            .unwrap()
+            .address(bitcoin::Network::Bitcoin)
+            .unwrap();
+        assert_eq!(from_descriptor, vault.address(), "index {}", index);
+    }
+    assert_golden_text(&file, "wallet_export_coldcard_multisig_mainnet.txt");
+}
+
+#[test]
+fn test_coldcard_descriptor_export_matches_fixture() {
+    let file = export(&savings_config(), 2);
+    let sparrow: Value = serde_json::from_str(&export(&savings_config(), 0)).unwrap();
+    assert_eq!(file.trim_end(), sparrow["descriptor"].as_str().unwrap());
+    assert_golden_text(&file, "wallet_export_coldcard_savings_mainnet.txt");
+}
+
+#[test]
+fn test_export_rejects_unknown_format() {
+    let response: Value = serde_json::from_str(&export(&savings_config(), 7)).unwrap();
+    assert_eq!(response["error"], true);
+    assert_eq!(response["code"], 4002);
+}